@@ -5,9 +5,13 @@
 //! The middle number of the three in each result is your actual measurement:
 //!   fibonacci/fib(10)    time: [4.8 µs  >>4.9 µs<<  5.1 µs]
 
-use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion};
+use std::collections::HashMap;
+
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
 use rune::{
+    image::MemoryImage,
     ir::{BlockType, Function, Op},
+    linker::Linker,
     module::Module,
     runtime::Runtime,
     types::{FuncType, Val, ValType},
@@ -63,16 +67,20 @@ fn add_module() -> Module {
     m
 }
 
-fn host_call_module() -> Module {
+fn unary() -> FuncType {
+    FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    }
+}
+
+fn host_call_module() -> (Module, Linker) {
     let mut m = Module::new();
-    m.register_host(
-        "noop",
-        FuncType {
-            params: vec![ValType::I32],
-            results: vec![ValType::I32],
-        },
-        |args| Ok(Some(args[0])),
-    );
+    m.import("env", "noop", unary());
+    let mut linker = Linker::new();
+    linker
+        .func("env", "noop", unary(), |args| Ok(Some(args[0])))
+        .unwrap();
     m.functions.push(Function::new(
         "call_host",
         FuncType {
@@ -83,7 +91,31 @@ fn host_call_module() -> Module {
         vec![Op::LocalGet(0), Op::CallHost(0), Op::Return],
     ));
     m.exports.push(("call_host".into(), 0));
-    m
+    // Count down from the argument, passing the count through the host.
+    m.functions.push(Function::new(
+        "call_host_loop",
+        unary(),
+        vec![],
+        vec![
+            Op::Block(BlockType::Empty),
+            Op::Loop(BlockType::Empty),
+            Op::LocalGet(0),
+            Op::I32Eqz,
+            Op::BrIf(1),
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32Sub,
+            Op::CallHost(0),
+            Op::LocalSet(0),
+            Op::Br(0),
+            Op::End,
+            Op::End,
+            Op::LocalGet(0),
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("call_host_loop".into(), 1));
+    (m, linker)
 }
 
 // ── Benchmarks ────────────────────────────────────────────────────────────────
@@ -116,14 +148,39 @@ fn bench_simple_call(c: &mut Criterion) {
 }
 
 fn bench_host_call(c: &mut Criterion) {
-    let module = host_call_module();
+    let (module, linker) = host_call_module();
     let rt = Runtime::new();
-    let mut inst = rt.instantiate(&module).unwrap();
+    let mut inst = linker.instantiate(&rt, &module).unwrap();
     c.bench_function("host_call/round_trip", |b| {
         b.iter(|| black_box(inst.call("call_host", &[Val::I32(black_box(42))]).unwrap()))
     });
 }
 
+/// Calls from guest code into the host, per call, under each strategy.
+fn bench_host_call_from_guest(c: &mut Criterion) {
+    use rune::config::{RuntimeConfig, Strategy};
+
+    const CALLS: i32 = 1000;
+    let (module, linker) = host_call_module();
+    let mut group = c.benchmark_group("host_call/from_guest");
+    group.throughput(Throughput::Elements(CALLS as u64));
+    let mut strategies = vec![Strategy::Interpreter];
+    if cfg!(feature = "cranelift") {
+        strategies.extend([Strategy::Baseline, Strategy::Cranelift]);
+    }
+    for strategy in strategies {
+        let mut config = RuntimeConfig::new();
+        config.set_strategy(strategy);
+        let rt = Runtime::with_config(config);
+        let mut inst = linker.instantiate(&rt, &module).unwrap();
+        let name = format!("{strategy:?}").to_lowercase();
+        group.bench_function(name, |b| {
+            b.iter(|| black_box(inst.call("call_host_loop", &[Val::I32(CALLS)]).unwrap()))
+        });
+    }
+    group.finish();
+}
+
 fn bench_cold_start(c: &mut Criterion) {
     let rt = Runtime::new();
     let mut group = c.benchmark_group("cold_start");
@@ -160,6 +217,30 @@ fn bench_cold_start(c: &mut Criterion) {
         })
     });
 
+    // 16 pages with a data segment — plain copy vs. copy-on-write image
+    let mut data_module = fib_module();
+    data_module.initial_memory_pages = 16;
+    data_module.data_segments.push((0, vec![0xAB; 256 * 1024]));
+    let image = MemoryImage::new(&data_module, &HashMap::new()).unwrap();
+    group.bench_function("data_module", |b| {
+        b.iter(|| {
+            let inst = rt.instantiate(&data_module).unwrap();
+            black_box(inst)
+        })
+    });
+    group.bench_function("data_module_from_image", |b| {
+        b.iter(|| {
+            let inst = rt.instantiate_from_image(&data_module, &image).unwrap();
+            black_box(inst)
+        })
+    });
+
+    // Reusing one instance between requests instead of instantiating again
+    let mut inst = rt.instantiate(&data_module).unwrap();
+    group.bench_function("data_module_reset", |b| {
+        b.iter(|| inst.reset_memory().unwrap())
+    });
+
     group.finish();
 }
 
@@ -197,6 +278,7 @@ criterion_group!(
     bench_fibonacci,
     bench_simple_call,
     bench_host_call,
+    bench_host_call_from_guest,
     bench_cold_start,
     bench_memory,
 );
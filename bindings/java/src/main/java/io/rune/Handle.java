@@ -0,0 +1,37 @@
+package io.rune;
+
+/**
+ * Base of every Rune object: owns one handle from the native registry and
+ * releases it on {@link #close()}. Objects a handle keeps alive natively,
+ * such as an instance's module, may be closed in any order.
+ */
+public abstract class Handle implements AutoCloseable {
+    static {
+        System.loadLibrary("rune");
+    }
+
+    private long handle;
+
+    Handle(long handle) {
+        this.handle = handle;
+    }
+
+    /** The native handle; throws if this object is closed. */
+    synchronized long handle() {
+        if (handle == 0) {
+            throw new IllegalStateException(getClass().getSimpleName() + " is closed");
+        }
+        return handle;
+    }
+
+    @Override
+    public synchronized void close() {
+        if (handle != 0) {
+            long h = handle;
+            handle = 0;
+            nativeRelease(h);
+        }
+    }
+
+    private static native void nativeRelease(long handle);
+}
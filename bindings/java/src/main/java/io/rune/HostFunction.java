@@ -0,0 +1,14 @@
+package io.rune;
+
+/**
+ * A host function guests import. Arguments arrive boxed ({@code Integer},
+ * {@code Long}, {@code Float}, {@code Double}) as declared in its
+ * signature; the return value must be a {@code Number}, or is ignored for a
+ * {@link ValType#VOID} result. Throwing traps the calling guest.
+ *
+ * <p>Called on whichever thread runs the guest.
+ */
+@FunctionalInterface
+public interface HostFunction {
+    Object call(Object[] args) throws Exception;
+}
@@ -0,0 +1,42 @@
+package io.rune;
+
+/**
+ * A module instance with its own memory. Calls from several threads take
+ * turns.
+ */
+public final class Instance extends Handle {
+    Instance(long handle) {
+        super(handle);
+    }
+
+    /** Instantiate a module that imports nothing. */
+    public Instance(Runtime runtime, Module module) {
+        this(nativeNew(runtime.handle(), module.handle()));
+    }
+
+    /**
+     * Call an export. Arguments are converted to its parameter types; the
+     * result is boxed, or {@code null} if it returns nothing.
+     */
+    public Object call(String name, Object... args) {
+        return nativeCall(handle(), name, args);
+    }
+
+    /** Copy {@code len} bytes of linear memory at {@code offset}. */
+    public byte[] read(long offset, int len) {
+        return nativeRead(handle(), offset, len);
+    }
+
+    /** Copy {@code data} into linear memory at {@code offset}. */
+    public void write(long offset, byte[] data) {
+        nativeWrite(handle(), offset, data);
+    }
+
+    private static native long nativeNew(long runtime, long module);
+
+    private static native Object nativeCall(long instance, String name, Object[] args);
+
+    private static native byte[] nativeRead(long instance, long offset, int len);
+
+    private static native void nativeWrite(long instance, long offset, byte[] data);
+}
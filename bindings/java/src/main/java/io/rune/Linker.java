@@ -0,0 +1,38 @@
+package io.rune;
+
+/** Host functions shared by the modules instantiated against it. */
+public final class Linker extends Handle {
+    public Linker() {
+        super(nativeNew());
+    }
+
+    /**
+     * Define {@code module.name} with parameter and result types from
+     * {@link ValType}, implemented by {@code function}.
+     */
+    public void define(String module, String name, int[] params, int result, HostFunction function) {
+        nativeDefine(handle(), module, name, params, result, function);
+    }
+
+    /**
+     * Define every export of {@code instance} as {@code module.<export>}, so
+     * modules instantiated against this linker can import from it.
+     */
+    public void defineInstance(String module, Instance instance) {
+        nativeDefineInstance(handle(), module, instance.handle());
+    }
+
+    /** Instantiate {@code module} with its imports bound to this linker's definitions. */
+    public Instance instantiate(Runtime runtime, Module module) {
+        return new Instance(nativeInstantiate(handle(), runtime.handle(), module.handle()));
+    }
+
+    private static native long nativeNew();
+
+    private static native void nativeDefine(
+            long linker, String module, String name, int[] params, int result, HostFunction function);
+
+    private static native void nativeDefineInstance(long linker, String module, long instance);
+
+    private static native long nativeInstantiate(long linker, long runtime, long module);
+}
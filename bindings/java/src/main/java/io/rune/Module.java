@@ -0,0 +1,15 @@
+package io.rune;
+
+/** A loaded module, ready to be instantiated any number of times. */
+public final class Module extends Handle {
+    private Module(long handle) {
+        super(handle);
+    }
+
+    /** Load a module from the binary .rune format. */
+    public static Module load(Runtime runtime, byte[] bytes) {
+        return new Module(nativeLoad(runtime.handle(), bytes));
+    }
+
+    private static native long nativeLoad(long runtime, byte[] bytes);
+}
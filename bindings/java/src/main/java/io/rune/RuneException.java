@@ -0,0 +1,15 @@
+package io.rune;
+
+/** A failed Rune operation, with its {@code RuneError} code from rune.h. */
+public class RuneException extends RuntimeException {
+    private final int code;
+
+    public RuneException(int code, String message) {
+        super(message);
+        this.code = code;
+    }
+
+    public int code() {
+        return code;
+    }
+}
@@ -0,0 +1,10 @@
+package io.rune;
+
+/** Execution context that modules are instantiated in. */
+public final class Runtime extends Handle {
+    public Runtime() {
+        super(nativeNew());
+    }
+
+    private static native long nativeNew();
+}
@@ -0,0 +1,13 @@
+package io.rune;
+
+/** Value type codes for host function signatures, as in rune.h. */
+public final class ValType {
+    /** No value: the result of a function returning nothing. */
+    public static final int VOID = 0x00;
+    public static final int I32 = 0x7F;
+    public static final int I64 = 0x7E;
+    public static final int F32 = 0x7D;
+    public static final int F64 = 0x7C;
+
+    private ValType() {}
+}
@@ -0,0 +1,185 @@
+//! Rune in the browser: a wasm-bindgen wrapper exposing modules and
+//! instances to JavaScript, so a page can inspect and run `.rune` plugins
+//! with the same engine native hosts embed.
+//!
+//! ```text
+//! wasm-pack build bindings/web --target web
+//! ```
+//!
+//! ```js
+//! import init, { Module, Instance } from "./pkg/rune_web.js";
+//!
+//! await init();
+//! const module = new Module(new Uint8Array(await file.arrayBuffer()));
+//! const instance = new Instance(module);
+//! console.log(instance.call("add", [3, 4]), instance.fuel());
+//! ```
+//!
+//! Instances are sandboxed: they run under
+//! [`SandboxProfile::Strict`](rune::sandbox::SandboxProfile::Strict) and
+//! get no host functions, so a module with imports fails to instantiate.
+//! `i64` values cross as `BigInt`, the other types as `number`.
+
+use std::sync::Arc;
+
+use rune::sandbox::SandboxProfile;
+use rune::{OwnedInstance, Runtime, RuntimeConfig, Val, ValType};
+use wasm_bindgen::prelude::*;
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod time {
+    use std::time::Duration;
+
+    use wasm_bindgen::prelude::*;
+
+    #[wasm_bindgen]
+    extern "C" {
+        #[wasm_bindgen(js_namespace = performance, js_name = now)]
+        fn performance_now() -> f64;
+
+        #[wasm_bindgen(thread_local_v2, js_namespace = performance, js_name = timeOrigin)]
+        static TIME_ORIGIN: f64;
+    }
+
+    fn now() -> Duration {
+        let millis = TIME_ORIGIN.with(|origin| *origin) + performance_now();
+        Duration::from_secs_f64(millis.max(0.0) / 1000.0)
+    }
+
+    /// The runtime has no clock of its own here; give it the page's.
+    #[wasm_bindgen(start)]
+    fn start() {
+        rune::clock::set_time_source(now);
+    }
+}
+
+/// A loaded module.
+#[wasm_bindgen]
+pub struct Module(Arc<rune::Module>);
+
+#[wasm_bindgen]
+impl Module {
+    /// Load a module from the binary format, the contents of a `.rune`
+    /// file.
+    #[wasm_bindgen(constructor)]
+    pub fn new(bytes: &[u8]) -> Result<Module, JsError> {
+        let module = rune::Module::from_bytes(bytes).map_err(error)?;
+        Ok(Module(Arc::new(module)))
+    }
+
+    /// Names of the exported functions.
+    pub fn exports(&self) -> Vec<String> {
+        self.0
+            .exports
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Imported host functions, as `module.name`.
+    pub fn imports(&self) -> Vec<String> {
+        self.0
+            .imports
+            .iter()
+            .map(|import| import.to_string())
+            .collect()
+    }
+
+    /// The module in the JSON form of [`rune::json`], for previews.
+    #[wasm_bindgen(js_name = toJson)]
+    pub fn to_json(&self) -> String {
+        self.0.to_json()
+    }
+}
+
+/// An instance of a module, with its own memory and fuel.
+#[wasm_bindgen]
+pub struct Instance {
+    module: Arc<rune::Module>,
+    inner: OwnedInstance,
+}
+
+#[wasm_bindgen]
+impl Instance {
+    /// Instantiate `module` in a strict sandbox, starting with `fuel` if
+    /// given instead of the profile's budget.
+    #[wasm_bindgen(constructor)]
+    pub fn new(module: &Module, fuel: Option<u64>) -> Result<Instance, JsError> {
+        let mut config = RuntimeConfig::with_profile(SandboxProfile::Strict);
+        if let Some(fuel) = fuel {
+            config.set_fuel(fuel);
+        }
+        let runtime = Runtime::with_config(config);
+        let inner = runtime.instantiate_owned(module.0.clone()).map_err(error)?;
+        Ok(Instance {
+            module: module.0.clone(),
+            inner,
+        })
+    }
+
+    /// Call export `name` with `args`, converted to its parameter types.
+    /// Returns `undefined` for a function without a result.
+    pub fn call(&mut self, name: &str, args: Vec<JsValue>) -> Result<JsValue, JsError> {
+        let Some(index) = self.module.find_export(name) else {
+            return Err(JsError::new(&format!("no export named {name}")));
+        };
+        let params = &self.module.functions[index as usize].ty.params;
+        if args.len() != params.len() {
+            return Err(JsError::new(&format!(
+                "{name} takes {} arguments, got {}",
+                params.len(),
+                args.len()
+            )));
+        }
+        let args = params
+            .iter()
+            .zip(args)
+            .enumerate()
+            .map(|(i, (&ty, arg))| {
+                from_js(ty, arg).ok_or_else(|| {
+                    JsError::new(&format!("{name}: argument {i} is not a valid {ty:?}"))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let result = self.inner.call(name, &args).map_err(error)?;
+        Ok(result.map_or(JsValue::UNDEFINED, to_js))
+    }
+
+    /// Fuel left, or `undefined` if the instance is not metered.
+    pub fn fuel(&self) -> Option<u64> {
+        self.inner.fuel()
+    }
+}
+
+fn error(trap: rune::Trap) -> JsError {
+    JsError::new(&trap.to_string())
+}
+
+fn from_js(ty: ValType, value: JsValue) -> Option<Val> {
+    match ty {
+        ValType::I32 => {
+            let n = value.as_f64()?;
+            (n.fract() == 0.0 && n >= i32::MIN as f64 && n <= i32::MAX as f64)
+                .then_some(Val::I32(n as i32))
+        }
+        // Plain numbers are accepted too, where they are exact integers.
+        ValType::I64 => match value.as_f64() {
+            Some(n) if n.fract() == 0.0 && n.abs() <= (1u64 << 53) as f64 => {
+                Some(Val::I64(n as i64))
+            }
+            Some(_) => None,
+            None => i64::try_from(value).ok().map(Val::I64),
+        },
+        ValType::F32 => value.as_f64().map(|n| Val::F32(n as f32)),
+        ValType::F64 => value.as_f64().map(Val::F64),
+    }
+}
+
+fn to_js(value: Val) -> JsValue {
+    match value {
+        Val::I32(n) => n.into(),
+        Val::I64(n) => n.into(),
+        Val::F32(n) => n.into(),
+        Val::F64(n) => n.into(),
+    }
+}
@@ -2,6 +2,7 @@
 
 use rune::{
     ir::{BlockType, Function, Op},
+    linker::Linker,
     module::Module,
     runtime::Runtime,
     types::{FuncType, Val, ValType},
@@ -11,18 +12,12 @@ fn main() {
     // ── Build module ──────────────────────────────────────────────────────────
     let mut module = Module::new();
 
-    // Register host function: print_i32(x: i32)
-    module.register_host(
-        "print_i32",
-        FuncType {
-            params: vec![ValType::I32],
-            results: vec![],
-        },
-        |args| {
-            println!("Guest says: {}", args[0].as_i32().unwrap());
-            Ok(None)
-        },
-    );
+    // Import host function: env.print_i32(x: i32)
+    let print_ty = FuncType {
+        params: vec![ValType::I32],
+        results: vec![],
+    };
+    let print_i32 = module.import("env", "print_i32", print_ty.clone());
 
     // Define guest function: run()  — calls print_i32(42)
     module.functions.push(Function {
@@ -32,12 +27,23 @@ fn main() {
             results: vec![],
         },
         locals: vec![],
-        body: vec![Op::I32Const(42), Op::CallHost(0), Op::Return].into(), // Add .into() here
+        body: vec![Op::I32Const(42), Op::CallHost(print_i32), Op::Return].into(),
     });
     module.exports.push(("run".into(), 0));
 
+    // ── Define host functions ─────────────────────────────────────────────────
+    let mut linker = Linker::new();
+    linker
+        .func("env", "print_i32", print_ty, |args| {
+            println!("Guest says: {}", args[0].as_i32().unwrap());
+            Ok(None)
+        })
+        .expect("duplicate definition");
+
     // ── Instantiate and run ───────────────────────────────────────────────────
     let rt = Runtime::new();
-    let mut inst = rt.instantiate(&module).expect("instantiation failed");
+    let mut inst = linker
+        .instantiate(&rt, &module)
+        .expect("instantiation failed");
     inst.call("run", &[]).expect("call failed");
 }
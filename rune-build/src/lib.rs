@@ -0,0 +1,150 @@
+//! Embed Rune modules in a host binary from its build script, for
+//! deployments where shipping plugin files beside the binary is not an
+//! option.
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     rune_build::embed("plugins/filter.rune").unwrap();
+//! }
+//!
+//! // main.rs
+//! static FILTER: rune::embed::EmbeddedModule = rune::include_rune!("filter.rune");
+//! ```
+//!
+//! Each module is decoded and verified, so an invalid one fails the build
+//! rather than the deployment, then copied into `$OUT_DIR/rune/` with the
+//! Rust file `include_rune!` includes. Cargo reruns the build script when
+//! the module changes.
+//!
+//! With the `cranelift` feature, [`Embed::set_precompile`] also compiles
+//! the module ahead of time, so the binary runs it without compiling at
+//! run time. The code only loads on hosts of the build host's target and
+//! CPU features, under the same Rune version and compiled-in settings, so
+//! precompile only when the build host matches the deployment.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rune::{verify::verify_module, Module, Result, RuntimeConfig, Trap};
+
+/// Embed the module at `path` under its file name, as
+/// [`Embed::run`] does.
+pub fn embed(path: impl AsRef<Path>) -> Result<PathBuf> {
+    Embed::new(path).run()
+}
+
+/// A module to embed, and how.
+pub struct Embed {
+    path: PathBuf,
+    name: Option<String>,
+    config: RuntimeConfig,
+    #[cfg_attr(not(feature = "cranelift"), allow(dead_code))]
+    precompile: bool,
+}
+
+impl Embed {
+    /// Embed the module at `path`, relative to the package being built.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Embed {
+            path: path.as_ref().to_path_buf(),
+            name: None,
+            config: RuntimeConfig::new(),
+            precompile: false,
+        }
+    }
+
+    /// Name to pass `include_rune!`. Defaults to the module's file name.
+    pub fn set_name(&mut self, name: impl Into<String>) {
+        self.name = Some(name.into());
+    }
+
+    /// Verify, and compile, with extension ops typed and settings as in
+    /// `config`: the configuration of the runtime that will load the
+    /// module. Defaults to `RuntimeConfig::new()`.
+    pub fn set_config(&mut self, config: RuntimeConfig) {
+        self.config = config;
+    }
+
+    /// Also compile the module ahead of time, with the backend and
+    /// compiler options `config` names. Off by default.
+    #[cfg(feature = "cranelift")]
+    pub fn set_precompile(&mut self, precompile: bool) {
+        self.precompile = precompile;
+    }
+
+    /// Check the module and embed it in `$OUT_DIR/rune/`, telling Cargo to
+    /// rerun the build script if it changes. Returns the path of the file
+    /// `include_rune!` includes. Fails with `Trap::HostError` outside a
+    /// build script or if a file cannot be read or written, and with
+    /// `Trap::InvalidModule` if the module does not decode or verify.
+    pub fn run(&self) -> Result<PathBuf> {
+        let out = std::env::var_os("OUT_DIR")
+            .ok_or_else(|| Trap::HostError("OUT_DIR is not set; run from build.rs".into()))?;
+        println!("cargo:rerun-if-changed={}", self.path.display());
+        self.run_in(&Path::new(&out).join("rune"))
+    }
+
+    /// [`run`](Self::run), writing into `dir` instead, and telling Cargo
+    /// nothing.
+    pub fn run_in(&self, dir: &Path) -> Result<PathBuf> {
+        let source = self.path.display();
+        let bytes = fs::read(&self.path)
+            .map_err(|e| Trap::HostError(format!("cannot read {source}: {e}")))?;
+        let module = Module::from_bytes(&bytes)
+            .map_err(|e| Trap::InvalidModule(format!("{source}: {e}")))?;
+        verify_module(&module, &self.config)
+            .map_err(|e| Trap::InvalidModule(format!("{source}: {e}")))?;
+        let name = match &self.name {
+            Some(name) => name.clone(),
+            None => self
+                .path
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+                .ok_or_else(|| Trap::HostError(format!("{source} names no file")))?,
+        };
+        if name.is_empty() || name.contains(['/', '\\']) {
+            return Err(Trap::HostError(format!("invalid embedded name {name:?}")));
+        }
+
+        let write = |path: &Path, contents: &[u8]| {
+            fs::write(path, contents)
+                .map_err(|e| Trap::HostError(format!("cannot write {}: {e}", path.display())))
+        };
+        fs::create_dir_all(dir)
+            .map_err(|e| Trap::HostError(format!("cannot create {}: {e}", dir.display())))?;
+        let module_path = dir.join(&name);
+        write(&module_path, &bytes)?;
+        let code = match self.code(&module)? {
+            Some(code) => {
+                let code_path = dir.join(format!("{name}.code"));
+                write(&code_path, &code)?;
+                format!(
+                    "Some(include_bytes!({:?}))",
+                    code_path.display().to_string()
+                )
+            }
+            None => "None".to_string(),
+        };
+        let rs_path = dir.join(format!("{name}.rs"));
+        let rs = format!(
+            "::rune::embed::EmbeddedModule::new(include_bytes!({:?}), {code})\n",
+            module_path.display().to_string()
+        );
+        write(&rs_path, rs.as_bytes())?;
+        Ok(rs_path)
+    }
+
+    #[cfg(feature = "cranelift")]
+    fn code(&self, module: &Module) -> Result<Option<Vec<u8>>> {
+        if !self.precompile {
+            return Ok(None);
+        }
+        rune::compiler::embedded::build(module, &self.config).map(Some)
+    }
+
+    #[cfg(not(feature = "cranelift"))]
+    fn code(&self, _module: &Module) -> Result<Option<Vec<u8>>> {
+        Ok(None)
+    }
+}
@@ -0,0 +1,53 @@
+use rune::ir::{Function, Op};
+use rune::{FuncType, Module, ValType};
+use rune_build::Embed;
+
+fn module(body: Vec<Op>) -> Module {
+    let mut m = Module::new();
+    m.functions.push(Function::new(
+        "answer",
+        FuncType {
+            params: vec![],
+            results: vec![ValType::I32],
+        },
+        vec![],
+        body,
+    ));
+    m.exports.push(("answer".into(), 0));
+    m
+}
+
+#[test]
+fn test_embed_checks_and_writes_modules() {
+    let dir = std::env::temp_dir().join(format!("rune-build-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let out = dir.join("out");
+    let good = module(vec![Op::I32Const(42)]);
+    std::fs::write(dir.join("answer.rune"), good.to_bytes()).unwrap();
+
+    let rs = Embed::new(dir.join("answer.rune")).run_in(&out).unwrap();
+    assert_eq!(rs, out.join("answer.rune.rs"));
+    assert_eq!(
+        std::fs::read(out.join("answer.rune")).unwrap(),
+        good.to_bytes()
+    );
+    let included = std::fs::read_to_string(&rs).unwrap();
+    assert!(included.starts_with("::rune::embed::EmbeddedModule::new(include_bytes!("));
+    assert!(included.contains(", None)"));
+
+    let mut renamed = Embed::new(dir.join("answer.rune"));
+    renamed.set_name("plugin.rune");
+    assert_eq!(renamed.run_in(&out).unwrap(), out.join("plugin.rune.rs"));
+    renamed.set_name("../plugin.rune");
+    assert!(renamed.run_in(&out).is_err());
+
+    // Modules that do not decode or verify fail the build.
+    std::fs::write(dir.join("junk.rune"), b"not a module").unwrap();
+    assert!(Embed::new(dir.join("junk.rune")).run_in(&out).is_err());
+    let bad = module(vec![Op::I32Add]);
+    std::fs::write(dir.join("bad.rune"), bad.to_bytes()).unwrap();
+    assert!(Embed::new(dir.join("bad.rune")).run_in(&out).is_err());
+    assert!(!out.join("bad.rune.rs").exists());
+    assert!(Embed::new(dir.join("missing.rune")).run_in(&out).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -3,6 +3,17 @@
  *
  * Opaque-handle API for embedding Rune in any language with a C FFI.
  * See src/ffi.rs for the Rust implementation (Phase 2).
+ *
+ * No function unwinds into the caller: an internal panic fails the call
+ * with RUNE_HOST_ERROR and a "panic: ..." last error message.
+ *
+ * Ownership: each handle is released by the _free function of its type
+ * (rune_runtime_free(), rune_module_free(), rune_instance_free(),
+ * rune_linker_free(), rune_hostlib_free()), and each integer handle by
+ * rune_handle_release(). A byte buffer the library
+ * allocates for the caller, from rune_module_to_bytes(), is released with
+ * rune_buffer_free(). Strings the library returns stay owned by it and are
+ * never freed by the caller.
  */
 
 #ifndef RUNE_H
@@ -16,31 +27,66 @@
 extern "C" {
 #endif
 
+/* ── ABI version ───────────────────────────────────────────────────────────── */
+
+/*
+ * The C ABI this header describes. The minor version is bumped when entry
+ * points are added, the major version when an existing one changes or is
+ * removed. A library is compatible with this header if rune_abi_version()
+ * has the same major version and at least this minor version.
+ */
+#define RUNE_ABI_VERSION_MAJOR 1
+#define RUNE_ABI_VERSION_MINOR 10
+#define RUNE_ABI_VERSION ((RUNE_ABI_VERSION_MAJOR << 16) | RUNE_ABI_VERSION_MINOR)
+
+/* Marks entry points kept only for existing embedders. */
+#if defined(__GNUC__) || defined(__clang__)
+#define RUNE_DEPRECATED(msg) __attribute__((deprecated(msg)))
+#elif defined(_MSC_VER)
+#define RUNE_DEPRECATED(msg) __declspec(deprecated(msg))
+#else
+#define RUNE_DEPRECATED(msg)
+#endif
+
 /* ── Opaque handles ────────────────────────────────────────────────────────── */
 
 typedef struct RuneRuntime  RuneRuntime;
 typedef struct RuneModule   RuneModule;
 typedef struct RuneInstance RuneInstance;
+typedef struct RuneLinker   RuneLinker;
+typedef struct RuneCaller   RuneCaller;
+typedef struct RuneHostLib  RuneHostLib;
 
 /* ── Error codes ───────────────────────────────────────────────────────────── */
 
 typedef enum {
-    RUNE_OK                  = 0,
-    RUNE_INVALID_MODULE      = 1,
-    RUNE_OUT_OF_MEMORY       = 2,
-    RUNE_TRAP_OUT_OF_BOUNDS  = 3,
-    RUNE_TRAP_DIV_ZERO       = 4,
-    RUNE_TRAP_UNREACHABLE    = 5,
-    RUNE_TRAP_STACK_OVERFLOW = 6,
-    RUNE_TRAP_TYPE_MISMATCH  = 7,
-    RUNE_UNDEFINED_EXPORT    = 8,
-    RUNE_UNDEFINED_IMPORT    = 9,
-    RUNE_HOST_ERROR          = 10,
+    RUNE_OK                    = 0,
+    RUNE_INVALID_MODULE        = 1,
+    RUNE_OUT_OF_MEMORY         = 2,
+    RUNE_TRAP_OUT_OF_BOUNDS    = 3,
+    RUNE_TRAP_DIV_ZERO         = 4,
+    RUNE_TRAP_UNREACHABLE      = 5,
+    RUNE_TRAP_STACK_OVERFLOW   = 6,
+    RUNE_TRAP_TYPE_MISMATCH    = 7,
+    RUNE_UNDEFINED_EXPORT      = 8,
+    RUNE_UNDEFINED_IMPORT      = 9,
+    RUNE_HOST_ERROR            = 10,
+    RUNE_UNSUPPORTED_FEATURE   = 11,
+    RUNE_TRAP_ACCESS_VIOLATION = 12,
+    RUNE_INVALID_UTF8          = 13,
+    RUNE_TRAP_UNALIGNED        = 14,
+    RUNE_TRAP_UNINITIALIZED    = 15,
+    RUNE_PERMISSION_DENIED     = 16,
+    RUNE_INTERRUPTED           = 17,
+    RUNE_OUT_OF_FUEL           = 18,
+    RUNE_CPU_TIME_EXCEEDED     = 19,
+    RUNE_INVALID_ARGUMENT      = 20,
 } RuneError;
 
 /* ── Value types ───────────────────────────────────────────────────────────── */
 
 typedef enum {
+    RUNE_VOID = 0x00,  /* no value: the result of a function returning nothing */
     RUNE_I32 = 0x7F,
     RUNE_I64 = 0x7E,
     RUNE_F32 = 0x7D,
@@ -54,26 +100,132 @@ typedef union {
     double   f64;
 } RuneVal;
 
+/**
+ * A value tagged with its type, as passed to and returned from calls and
+ * host functions. Build them with rune_val_i32() and friends and read them
+ * with rune_val_as_i32() and friends, which check the tag.
+ */
+typedef struct {
+    RuneValType type;
+    RuneVal     val;
+} RuneTypedVal;
+
 /* ── Host function callback ────────────────────────────────────────────────── */
 
 /**
- * A host function registered via rune_module_register_host().
+ * A host function defined via rune_linker_define_typed().
  *
- * @param instance  The calling instance (for memory access).
- * @param args      Argument values array.
+ * @param caller    The calling instance, valid only during the callback;
+ *                  reach its memory with rune_caller_memory_read() and
+ *                  friends.
+ * @param args      Argument values, tagged with the signature's types.
  * @param n_args    Number of arguments.
- * @param result    Write return value here (if any).
- * @param user_data Opaque pointer passed at registration time.
- * @return RUNE_OK on success, or an error code.
+ * @param result    Tagged with the signature's result type (RUNE_VOID for
+ *                  none) on entry; write the return value here with the
+ *                  same tag, or the guest traps.
+ * @param user_data Opaque pointer passed at definition time.
+ * @return RUNE_OK on success, or an error code, which traps the guest.
+ *
+ * A callback that unwinds, such as a C++ exception escaping it, traps the
+ * guest with RUNE_HOST_ERROR instead of unwinding through the runtime.
+ */
+typedef RuneError (*RuneTypedHostFn)(
+    RuneCaller         *caller,
+    const RuneTypedVal *args,
+    size_t              n_args,
+    RuneTypedVal       *result,
+    void               *user_data
+);
+
+/**
+ * A host function defined via the deprecated rune_linker_define(), taking
+ * untagged values.
+ *
+ * @param caller    The calling instance, valid only during the callback;
+ *                  reach its memory with rune_caller_memory_read() and
+ *                  friends.
+ * @param args      Argument values, typed by the function's signature.
+ * @param n_args    Number of arguments.
+ * @param result    Write the return value here (if the signature has one).
+ * @param user_data Opaque pointer passed at definition time.
+ * @return RUNE_OK on success, or an error code, which traps the guest.
+ *
+ * A callback that unwinds, such as a C++ exception escaping it, traps the
+ * guest with RUNE_HOST_ERROR instead of unwinding through the runtime.
  */
 typedef RuneError (*RuneHostFn)(
-    RuneInstance      *instance,
+    RuneCaller        *caller,
     const RuneVal     *args,
     size_t             n_args,
     RuneVal           *result,
     void              *user_data
 );
 
+/** A host function signature. */
+typedef struct {
+    const RuneValType *params;
+    size_t             n_params;
+    RuneValType        result;  /* RUNE_VOID for none */
+} RuneSignature;
+
+/* ── Caller memory (ABI 1.10, feature "caller-memory") ────────────────────── */
+
+/*
+ * The calling instance's default memory, for use inside a host callback.
+ * The rune_memory_*() functions must not be called on that instance from
+ * its own callback: the call holds the instance, so they would deadlock.
+ */
+
+/** Return the size of the caller's linear memory in bytes; 0 for NULL. */
+size_t    rune_caller_memory_size(RuneCaller *caller);
+
+/** rune_memory_read() on the caller's memory. */
+RuneError rune_caller_memory_read(RuneCaller *caller, size_t offset, void *dst, size_t len);
+
+/** rune_memory_write() on the caller's memory. */
+RuneError rune_caller_memory_write(RuneCaller *caller, size_t offset, const void *src, size_t len);
+
+/* ── Values (ABI 1.8, feature "typed-values") ─────────────────────────────── */
+
+/** Tagged value constructors. */
+RuneTypedVal rune_val_i32(int32_t x);
+RuneTypedVal rune_val_i64(int64_t x);
+RuneTypedVal rune_val_f32(float x);
+RuneTypedVal rune_val_f64(double x);
+RuneTypedVal rune_val_void(void);
+
+/**
+ * Write v's value to out and return true if v is tagged with that type;
+ * otherwise leave out alone and return false.
+ */
+bool rune_val_as_i32(const RuneTypedVal *v, int32_t *out);
+bool rune_val_as_i64(const RuneTypedVal *v, int64_t *out);
+bool rune_val_as_f32(const RuneTypedVal *v, float *out);
+bool rune_val_as_f64(const RuneTypedVal *v, double *out);
+
+/** Return whether v's tag is a RuneValType, including RUNE_VOID. */
+bool rune_val_is_valid(const RuneTypedVal *v);
+
+/* ── Compatibility probing ─────────────────────────────────────────────────── */
+
+/**
+ * Return the ABI version the loaded library implements, as
+ * (major << 16) | minor. Safe to call before anything else, including
+ * through dlsym() before binding other entry points.
+ */
+uint32_t rune_abi_version(void);
+
+/**
+ * Return whether the library provides a feature: an entry-point group
+ * ("instance", "linker", "memory-access", "module-builder", "last-error",
+ * "fuel", "deadline", "exports", "trap-callback", "handles", "handle-linker",
+ * "linker-instances", "typed-values", "caller-memory", and "hostlib" and
+ * "jni" when built with the host library and JNI bindings) or an op family
+ * it can execute ("simd", "atomics", ...). Names this library does not know
+ * return false.
+ */
+bool     rune_has_feature(const char *name);
+
 /* ── Runtime lifecycle ─────────────────────────────────────────────────────── */
 
 /** Create a new runtime. Must be freed with rune_runtime_free(). */
@@ -93,34 +245,391 @@ RuneModule *rune_module_load_bytes(RuneRuntime *rt, const uint8_t *data, size_t
 /** Free a module. */
 void        rune_module_free(RuneModule *mod);
 
-/* ── Host function registration ────────────────────────────────────────────── */
+/* ── Module exports (ABI 1.2, feature "exports") ───────────────────────────── */
+
+/** Return the number of functions a module exports. */
+size_t      rune_module_export_count(const RuneModule *mod);
+
+/**
+ * Return the name of export index, or NULL if index is out of range. The
+ * string belongs to the module and stays valid until the module is changed
+ * with the builder functions or freed.
+ */
+const char *rune_module_export_name(const RuneModule *mod, size_t index);
+
+/**
+ * Describe the signature of export index.
+ *
+ * @param mod          The module.
+ * @param index        Export index, below rune_module_export_count().
+ * @param out_params   Receives up to max_params parameter types (may be
+ *                     NULL if max_params is 0).
+ * @param max_params   Capacity of out_params.
+ * @param out_n_params Receives the full parameter count (may be NULL).
+ * @param out_result   Receives the result type, RUNE_VOID for none (may be
+ *                     NULL).
+ * @return RUNE_OK, or RUNE_UNDEFINED_EXPORT if index is out of range.
+ */
+RuneError rune_module_export_signature(
+    const RuneModule *mod,
+    size_t            index,
+    RuneValType      *out_params,
+    size_t            max_params,
+    size_t           *out_n_params,
+    RuneValType      *out_result
+);
+
+/* ── Module building ───────────────────────────────────────────────────────── */
 
 /**
- * Register a host function that guest code can call via CallHost.
- * Must be called before rune_instance_new().
+ * Opcodes for RuneOp, numbered as in the binary module format. Ops up to
+ * RUNE_OP_MEMORY_DISCARD take no immediates; 0xE0-0xFF are embedder
+ * extension ops with a 32-bit imm. Block types are 0x40 (empty) or a
+ * RuneValType.
+ */
+typedef enum {
+    RUNE_OP_NOP = 0x00,
+    RUNE_OP_DROP = 0x01,
+    RUNE_OP_SELECT = 0x02,
+    RUNE_OP_RETURN = 0x03,
+    RUNE_OP_ELSE = 0x04,
+    RUNE_OP_END = 0x05,
+    RUNE_OP_UNREACHABLE = 0x06,
+    RUNE_OP_MEMORY_SIZE = 0x07,
+    RUNE_OP_MEMORY_GROW = 0x08,
+    RUNE_OP_I32_ADD = 0x09,
+    RUNE_OP_I32_SUB = 0x0A,
+    RUNE_OP_I32_MUL = 0x0B,
+    RUNE_OP_I32_DIV_S = 0x0C,
+    RUNE_OP_I32_DIV_U = 0x0D,
+    RUNE_OP_I32_REM_S = 0x0E,
+    RUNE_OP_I32_REM_U = 0x0F,
+    RUNE_OP_I32_AND = 0x10,
+    RUNE_OP_I32_OR = 0x11,
+    RUNE_OP_I32_XOR = 0x12,
+    RUNE_OP_I32_SHL = 0x13,
+    RUNE_OP_I32_SHR_S = 0x14,
+    RUNE_OP_I32_SHR_U = 0x15,
+    RUNE_OP_I32_CLZ = 0x16,
+    RUNE_OP_I32_CTZ = 0x17,
+    RUNE_OP_I32_POPCNT = 0x18,
+    RUNE_OP_I32_EQZ = 0x19,
+    RUNE_OP_I64_ADD = 0x1A,
+    RUNE_OP_I64_SUB = 0x1B,
+    RUNE_OP_I64_MUL = 0x1C,
+    RUNE_OP_I64_DIV_S = 0x1D,
+    RUNE_OP_I64_DIV_U = 0x1E,
+    RUNE_OP_I64_REM_S = 0x1F,
+    RUNE_OP_I64_REM_U = 0x20,
+    RUNE_OP_I64_AND = 0x21,
+    RUNE_OP_I64_OR = 0x22,
+    RUNE_OP_I64_XOR = 0x23,
+    RUNE_OP_I64_SHL = 0x24,
+    RUNE_OP_I64_SHR_S = 0x25,
+    RUNE_OP_I64_SHR_U = 0x26,
+    RUNE_OP_I64_EQZ = 0x27,
+    RUNE_OP_F32_ADD = 0x28,
+    RUNE_OP_F32_SUB = 0x29,
+    RUNE_OP_F32_MUL = 0x2A,
+    RUNE_OP_F32_DIV = 0x2B,
+    RUNE_OP_F32_SQRT = 0x2C,
+    RUNE_OP_F32_MIN = 0x2D,
+    RUNE_OP_F32_MAX = 0x2E,
+    RUNE_OP_F32_ABS = 0x2F,
+    RUNE_OP_F32_NEG = 0x30,
+    RUNE_OP_F32_CEIL = 0x31,
+    RUNE_OP_F32_FLOOR = 0x32,
+    RUNE_OP_F64_ADD = 0x33,
+    RUNE_OP_F64_SUB = 0x34,
+    RUNE_OP_F64_MUL = 0x35,
+    RUNE_OP_F64_DIV = 0x36,
+    RUNE_OP_F64_SQRT = 0x37,
+    RUNE_OP_F64_MIN = 0x38,
+    RUNE_OP_F64_MAX = 0x39,
+    RUNE_OP_F64_ABS = 0x3A,
+    RUNE_OP_F64_NEG = 0x3B,
+    RUNE_OP_F64_CEIL = 0x3C,
+    RUNE_OP_F64_FLOOR = 0x3D,
+    RUNE_OP_I32_EQ = 0x3E,
+    RUNE_OP_I32_NE = 0x3F,
+    RUNE_OP_I32_LT_S = 0x40,
+    RUNE_OP_I32_LT_U = 0x41,
+    RUNE_OP_I32_GT_S = 0x42,
+    RUNE_OP_I32_GT_U = 0x43,
+    RUNE_OP_I32_LE_S = 0x44,
+    RUNE_OP_I32_LE_U = 0x45,
+    RUNE_OP_I32_GE_S = 0x46,
+    RUNE_OP_I32_GE_U = 0x47,
+    RUNE_OP_I64_EQ = 0x48,
+    RUNE_OP_I64_NE = 0x49,
+    RUNE_OP_I64_LT_S = 0x4A,
+    RUNE_OP_I64_LT_U = 0x4B,
+    RUNE_OP_I64_GT_S = 0x4C,
+    RUNE_OP_I64_GT_U = 0x4D,
+    RUNE_OP_I64_LE_S = 0x4E,
+    RUNE_OP_I64_LE_U = 0x4F,
+    RUNE_OP_I64_GE_S = 0x50,
+    RUNE_OP_I64_GE_U = 0x51,
+    RUNE_OP_F32_EQ = 0x52,
+    RUNE_OP_F32_NE = 0x53,
+    RUNE_OP_F32_LT = 0x54,
+    RUNE_OP_F32_GT = 0x55,
+    RUNE_OP_F32_LE = 0x56,
+    RUNE_OP_F32_GE = 0x57,
+    RUNE_OP_F64_EQ = 0x58,
+    RUNE_OP_F64_NE = 0x59,
+    RUNE_OP_F64_LT = 0x5A,
+    RUNE_OP_F64_GT = 0x5B,
+    RUNE_OP_F64_LE = 0x5C,
+    RUNE_OP_F64_GE = 0x5D,
+    RUNE_OP_I32_WRAP_I64 = 0x5E,
+    RUNE_OP_I64_EXTEND_I32_S = 0x5F,
+    RUNE_OP_I64_EXTEND_I32_U = 0x60,
+    RUNE_OP_F32_CONVERT_I32_S = 0x61,
+    RUNE_OP_F32_CONVERT_I32_U = 0x62,
+    RUNE_OP_F64_CONVERT_I32_S = 0x63,
+    RUNE_OP_F64_CONVERT_I32_U = 0x64,
+    RUNE_OP_F64_CONVERT_I64_S = 0x65,
+    RUNE_OP_F64_CONVERT_I64_U = 0x66,
+    RUNE_OP_I32_TRUNC_F32_S = 0x67,
+    RUNE_OP_I32_TRUNC_F32_U = 0x68,
+    RUNE_OP_I32_TRUNC_F64_S = 0x69,
+    RUNE_OP_I32_TRUNC_F64_U = 0x6A,
+    RUNE_OP_F32_DEMOTE_F64 = 0x6B,
+    RUNE_OP_F64_PROMOTE_F32 = 0x6C,
+    RUNE_OP_I32_REINTERPRET_F32 = 0x6D,
+    RUNE_OP_F32_REINTERPRET_I32 = 0x6E,
+    RUNE_OP_I64_REINTERPRET_F64 = 0x6F,
+    RUNE_OP_F64_REINTERPRET_I64 = 0x70,
+    RUNE_OP_MEMORY_DISCARD = 0x71,
+    RUNE_OP_I32_CONST = 0x80,       /* imm: i32 bits */
+    RUNE_OP_I64_CONST = 0x81,       /* imm: i64 bits */
+    RUNE_OP_F32_CONST = 0x82,       /* imm: f32 bits */
+    RUNE_OP_F64_CONST = 0x83,       /* imm: f64 bits */
+    RUNE_OP_LOCAL_GET = 0x84,       /* imm: local index */
+    RUNE_OP_LOCAL_SET = 0x85,       /* imm: local index */
+    RUNE_OP_LOCAL_TEE = 0x86,       /* imm: local index */
+    RUNE_OP_CALL = 0x87,            /* imm: function index */
+    RUNE_OP_CALL_HOST = 0x88,       /* imm: import index */
+    RUNE_OP_BR = 0x89,              /* imm: depth */
+    RUNE_OP_BR_IF = 0x8A,           /* imm: depth */
+    RUNE_OP_BLOCK = 0x8B,           /* imm: block type */
+    RUNE_OP_LOOP = 0x8C,            /* imm: block type */
+    RUNE_OP_IF = 0x8D,              /* imm: block type */
+    RUNE_OP_I32_LOAD = 0x8E,        /* imm: align; offset, memory */
+    RUNE_OP_I32_STORE = 0x8F,
+    RUNE_OP_I64_LOAD = 0x90,
+    RUNE_OP_I64_STORE = 0x91,
+    RUNE_OP_F32_LOAD = 0x92,
+    RUNE_OP_F32_STORE = 0x93,
+    RUNE_OP_F64_LOAD = 0x94,
+    RUNE_OP_F64_STORE = 0x95,
+    RUNE_OP_GLOBAL_GET = 0x98,      /* imm: global import index */
+    RUNE_OP_GLOBAL_SET = 0x99,      /* imm: global import index */
+} RuneOpcode;
+
+/** One op of a function body, with its immediates unpacked. */
+typedef struct {
+    uint8_t  opcode;  /* a RuneOpcode or extension opcode */
+    uint64_t imm;     /* constant bits, index, depth, block type or align */
+    uint32_t offset;  /* load/store offset */
+    uint32_t memory;  /* load/store memory index; 0 is the default memory */
+} RuneOp;
+
+/** Create an empty module with one page of memory. Free with rune_module_free(). */
+RuneModule *rune_module_new(void);
+
+/**
+ * Append a function. Instances already created from the module keep it as
+ * it was; ops are validated when the module is instantiated.
  *
- * @param mod          The module to register the function on.
- * @param name         Name used to resolve imports.
- * @param param_types  Array of parameter types.
- * @param n_params     Number of parameters.
- * @param result_type  Return type (0 = void).
- * @param func         The host callback.
- * @param user_data    Opaque pointer forwarded to the callback.
- */
-RuneError rune_module_register_host(
-    RuneModule    *mod,
-    const char    *name,
-    RuneValType   *param_types,
-    size_t         n_params,
-    RuneValType    result_type,
-    RuneHostFn     func,
-    void          *user_data
+ * @param mod       The module.
+ * @param name      Function name.
+ * @param sig       Parameter and result types.
+ * @param locals    Types of extra locals beyond the parameters.
+ * @param n_locals  Number of extra locals.
+ * @param ops       The body.
+ * @param n_ops     Number of ops.
+ * @param out_index Written with the new function's index (may be NULL).
+ * @return RUNE_OK, or RUNE_INVALID_MODULE for an unknown opcode or an
+ *         immediate out of range.
+ */
+RuneError rune_module_add_function(
+    RuneModule          *mod,
+    const char          *name,
+    const RuneSignature *sig,
+    const RuneValType   *locals,
+    size_t               n_locals,
+    const RuneOp        *ops,
+    size_t               n_ops,
+    uint32_t            *out_index
 );
 
+/**
+ * Export function func_index as name. Returns RUNE_UNDEFINED_EXPORT for an
+ * index past the last function, RUNE_INVALID_MODULE if name is taken.
+ */
+RuneError rune_module_add_export(RuneModule *mod, const char *name, uint32_t func_index);
+
+/**
+ * Serialize a module to the binary .rune format, loadable with
+ * rune_module_load_bytes(). Free the buffer with rune_buffer_free().
+ * Works on loaded modules too, so tooling can load a module, patch it
+ * with the functions above and write it back out; instances created
+ * before the patch keep the module as it was.
+ */
+RuneError rune_module_to_bytes(const RuneModule *mod, uint8_t **out_data, size_t *out_len);
+
+/**
+ * Free a buffer returned by rune_module_to_bytes(), passing the length it
+ * reported. NULL is ignored. Never pass it memory from malloc() or any
+ * other allocator, nor a string returned by the library. (ABI 1.9)
+ */
+void      rune_buffer_free(uint8_t *data, size_t len);
+
+/** Same as rune_buffer_free(); its name before ABI 1.9. */
+void      rune_bytes_free(uint8_t *data, size_t len);
+
+/* ── Linking ───────────────────────────────────────────────────────────────── */
+
+/** Create an empty linker. Must be freed with rune_linker_free(). */
+RuneLinker *rune_linker_new(void);
+
+/** Free a linker. Instances created through it stay valid. */
+void        rune_linker_free(RuneLinker *linker);
+
+/**
+ * Define host function module.name, which guests import by that name.
+ *
+ * @param linker    The linker to define it on.
+ * @param module    Import module name, e.g. "env".
+ * @param name      Import name.
+ * @param sig       Parameter and result types.
+ * @param func      The host callback.
+ * @param user_data Opaque pointer forwarded to every call of the callback,
+ *                  on whichever thread the calling instance runs.
+ * @return RUNE_OK, or RUNE_INVALID_MODULE if already defined. (ABI 1.8)
+ */
+RuneError rune_linker_define_typed(
+    RuneLinker          *linker,
+    const char          *module,
+    const char          *name,
+    const RuneSignature *sig,
+    RuneTypedHostFn      func,
+    void                *user_data
+);
+
+/** As rune_linker_define_typed(), with a callback taking untagged values. */
+RUNE_DEPRECATED("use rune_linker_define_typed")
+RuneError rune_linker_define(
+    RuneLinker          *linker,
+    const char          *module,
+    const char          *name,
+    const RuneSignature *sig,
+    RuneHostFn           func,
+    void                *user_data
+);
+
+/**
+ * Define every export of an instance as module.<export>, so modules
+ * instantiated through the linker can import from it. Calls into the
+ * instance from other instances take turns with calls made on it directly.
+ * The instance may be freed afterwards. (ABI 1.6, feature "linker-instances")
+ *
+ * @return RUNE_OK, or RUNE_INVALID_MODULE if any of the names is already
+ *         defined, in which case none are.
+ */
+RuneError rune_linker_define_instance(RuneLinker *linker, const char *module,
+                                      const RuneInstance *inst);
+
+/** Return how many of a module's imports the linker does not define. (ABI 1.6) */
+size_t    rune_linker_unresolved_count(const RuneLinker *linker, const RuneModule *mod);
+
+/**
+ * Copy the name of unresolved import index, as "module.name", into buf,
+ * truncated to buf_len - 1 bytes and NUL-terminated. Returns the full
+ * length of the name, or 0 if index is out of range, like snprintf(). (ABI 1.6)
+ */
+size_t    rune_linker_unresolved_name(const RuneLinker *linker, const RuneModule *mod,
+                                      size_t index, char *buf, size_t buf_len);
+
+/**
+ * Instantiate a module with its imports bound to the linker's definitions.
+ * Returns NULL on error, including imports the linker does not define,
+ * which fail with RUNE_UNDEFINED_IMPORT; rune_linker_unresolved_name()
+ * lists them.
+ */
+RuneInstance *rune_linker_instantiate(RuneLinker *linker, RuneRuntime *rt, RuneModule *mod);
+
+/* ── Host library (ABI 1.7, feature "hostlib") ─────────────────────────────── */
+
+typedef enum {
+    RUNE_LOG_ERROR = 0,
+    RUNE_LOG_WARN  = 1,
+    RUNE_LOG_INFO  = 2,
+    RUNE_LOG_DEBUG = 3,
+    RUNE_LOG_TRACE = 4,
+} RuneLogLevel;
+
+/**
+ * Receives len bytes of guest output. Return false if they could not be
+ * written; the guest's write then returns -1.
+ */
+typedef bool (*RuneWriteFn)(const uint8_t *data, size_t len, void *user_data);
+
+/**
+ * Receives a message the guest logged with rune_std.log. message is
+ * NUL-terminated; len excludes the NUL and counts any interior NULs.
+ */
+typedef void (*RuneLogFn)(RuneLogLevel level, const char *message, size_t len,
+                          void *user_data);
+
+/**
+ * Create a host library (the rune_std imports) with no arguments, an empty
+ * environment, and output to the process's stdout and stderr. Must be
+ * freed with rune_hostlib_free().
+ */
+RuneHostLib *rune_hostlib_new(void);
+
+/** Free a host library. Linkers it was added to keep working. */
+void         rune_hostlib_free(RuneHostLib *hostlib);
+
+/** Append an argument. */
+RuneError rune_hostlib_add_arg(RuneHostLib *hostlib, const char *arg);
+
+/** Set an environment variable, replacing any earlier value. */
+RuneError rune_hostlib_set_env(RuneHostLib *hostlib, const char *key, const char *value);
+
+/**
+ * Send guest stdout to func instead of the process's stdout; NULL discards
+ * it. The callback runs on whichever thread the guest runs, with user_data.
+ */
+RuneError rune_hostlib_set_stdout(RuneHostLib *hostlib, RuneWriteFn func, void *user_data);
+
+/** As rune_hostlib_set_stdout(), for stderr. */
+RuneError rune_hostlib_set_stderr(RuneHostLib *hostlib, RuneWriteFn func, void *user_data);
+
+/**
+ * Send guest log messages to func instead of writing "[level] message"
+ * lines to stderr; NULL discards them.
+ */
+RuneError rune_hostlib_set_log(RuneHostLib *hostlib, RuneLogFn func, void *user_data);
+
+/**
+ * Define every rune_std function on a linker. Instances linked through it
+ * use the sinks set so far; later changes apply only to linkers the library
+ * is added to afterwards.
+ */
+RuneError rune_hostlib_add_to_linker(const RuneHostLib *hostlib, RuneLinker *linker);
+
 /* ── Instantiation ─────────────────────────────────────────────────────────── */
 
-/** Create a new instance of a module. Returns NULL on error. */
-RuneInstance *rune_instance_new(RuneModule *mod);
+/**
+ * Create a new instance of a module in a runtime. Returns NULL on error.
+ * The instance keeps its own reference to the module, so the module may be
+ * freed while instances of it are alive.
+ */
+RuneInstance *rune_instance_new(RuneRuntime *rt, RuneModule *mod);
 
 /** Free an instance. */
 void          rune_instance_free(RuneInstance *inst);
@@ -130,24 +639,103 @@ void          rune_instance_free(RuneInstance *inst);
 /**
  * Call an exported function by name.
  *
- * @param inst      The instance.
- * @param func_name Exported function name.
- * @param args      Argument values.
- * @param n_args    Number of arguments.
- * @param result    Written with the return value (may be NULL for void).
+ * @param inst       The instance.
+ * @param func_name  Exported function name.
+ * @param args       Argument values; their number and types must match the
+ *                   export's parameters, or RUNE_TRAP_TYPE_MISMATCH is
+ *                   returned without calling.
+ * @param n_args     Number of arguments.
+ * @param out_result Written with the typed return value, tagged RUNE_VOID
+ *                   if the export returns nothing (may be NULL).
  * @return RUNE_OK, or a trap/error code.
  */
-RuneError rune_call(
-    RuneInstance  *inst,
-    const char    *func_name,
-    const RuneVal *args,
-    size_t         n_args,
-    RuneVal       *result
+RuneError rune_instance_call(
+    RuneInstance       *inst,
+    const char         *func_name,
+    const RuneTypedVal *args,
+    size_t              n_args,
+    RuneTypedVal       *out_result
+);
+
+/**
+ * Like rune_instance_call(), but returns RUNE_INTERRUPTED if the call has
+ * not finished after deadline_ms milliseconds. The call runs on a worker
+ * thread and is stopped at its next loop iteration or function call; it
+ * has stopped by the time this returns. A host function that is already
+ * running is not interrupted. (ABI 1.1, feature "deadline")
+ */
+RuneError rune_call_with_deadline_ms(
+    RuneInstance       *inst,
+    const char         *func_name,
+    const RuneTypedVal *args,
+    size_t              n_args,
+    RuneTypedVal       *out_result,
+    uint64_t            deadline_ms
 );
 
+/* ── Fuel (ABI 1.1, feature "fuel") ─────────────────────────────────────────── */
+
+/**
+ * Meter execution with fuel units, replacing whatever was left. Each op
+ * costs one; running out fails the call with RUNE_OUT_OF_FUEL, after which
+ * the instance can be refilled and called again.
+ */
+RuneError rune_instance_set_fuel(RuneInstance *inst, uint64_t fuel);
+
+/** Stop metering execution. */
+void      rune_instance_clear_fuel(RuneInstance *inst);
+
+/**
+ * Write the fuel left to *out_fuel and return true, or return false if
+ * execution is not metered.
+ */
+bool      rune_instance_remaining_fuel(const RuneInstance *inst, uint64_t *out_fuel);
+
+/* ── Trap reporting (ABI 1.3, feature "trap-callback") ───────────────────── */
+
+/** One guest frame of a trap's backtrace. */
+typedef struct {
+    uint32_t    func_index;
+    const char *func_name;  /* NULL if the function has no name */
+    size_t      op_index;   /* op executing in that function */
+    const char *file;       /* NULL without source map info */
+    uint32_t    line;       /* 0 without source map info */
+    uint32_t    column;
+} RuneFrame;
+
+/**
+ * Called with each trap a call ends in, before the call returns.
+ *
+ * @param code      The error code the call returns.
+ * @param message   Full trap message, as rune_last_error_message().
+ * @param frames    Guest backtrace, innermost frame first.
+ * @param n_frames  Number of frames; 0 if none were recorded.
+ * @param user_data Opaque pointer passed at registration.
+ *
+ * All pointers are valid only during the callback. If the callback
+ * unwinds, the unwind is stopped and the call returns code as usual.
+ */
+typedef void (*RuneTrapFn)(
+    RuneError        code,
+    const char      *message,
+    const RuneFrame *frames,
+    size_t           n_frames,
+    void            *user_data
+);
+
+/**
+ * Register callback for traps in calls on any instance of rt, including
+ * instances already created; NULL removes it. It runs on the thread that
+ * made the call, which may be any thread calling into the runtime.
+ */
+RuneError rune_runtime_set_trap_callback(RuneRuntime *rt, RuneTrapFn callback, void *user_data);
+
 /* ── Memory access ─────────────────────────────────────────────────────────── */
 
-/** Return a pointer to the instance's linear memory base (zero-copy). */
+/**
+ * Return a pointer to the instance's linear memory base (zero-copy), or
+ * NULL. Invalidated when memory grows or the instance is freed.
+ */
 uint8_t *rune_memory_base(RuneInstance *inst);
 
 /** Return the current size of linear memory in bytes. */
@@ -156,17 +744,103 @@ size_t   rune_memory_size(RuneInstance *inst);
 /** Grow linear memory by delta_pages 64KB pages. Returns RUNE_OUT_OF_MEMORY on failure. */
 RuneError rune_memory_grow(RuneInstance *inst, size_t delta_pages);
 
-/** Bounds-checked read from linear memory. */
+/**
+ * Bounds-checked read from linear memory. Returns RUNE_TRAP_OUT_OF_BOUNDS,
+ * copying nothing, unless [offset, offset + len) is inside memory.
+ */
 RuneError rune_memory_read(RuneInstance *inst, size_t offset, void *dst, size_t len);
 
-/** Bounds-checked write to linear memory. */
+/** Bounds-checked write to linear memory; all-or-nothing like rune_memory_read(). */
 RuneError rune_memory_write(RuneInstance *inst, size_t offset, const void *src, size_t len);
 
+/* ── Handle-based API (ABI 1.4, feature "handles") ────────────────────────── */
+
+/*
+ * The same objects as above, named by integer handles kept in a
+ * process-wide registry instead of pointers, for hosts with a garbage
+ * collector. Handles can be copied and stored freely; release each with
+ * rune_handle_release() in any order. A released or mistyped handle makes
+ * calls fail with RUNE_INVALID_ARGUMENT rather than touch another object,
+ * and calls on one instance from several threads take turns.
+ */
+
+typedef uint64_t RuneHandle;
+
+#define RUNE_NULL_HANDLE ((RuneHandle)0)
+
+/** Create a runtime. */
+RuneHandle rune_h_runtime_new(void);
+
+/** Load a module from bytes. Returns RUNE_NULL_HANDLE on error. */
+RuneHandle rune_h_module_load_bytes(RuneHandle rt, const uint8_t *data, size_t len);
+
+/** Instantiate a module. Returns RUNE_NULL_HANDLE on error. */
+RuneHandle rune_h_instance_new(RuneHandle rt, RuneHandle mod);
+
+/** rune_instance_call() on an instance handle. */
+RuneError  rune_h_instance_call(
+    RuneHandle          inst,
+    const char         *func_name,
+    const RuneTypedVal *args,
+    size_t              n_args,
+    RuneTypedVal       *out_result
+);
+
+/** rune_memory_read() on an instance handle. */
+RuneError  rune_h_memory_read(RuneHandle inst, size_t offset, void *dst, size_t len);
+
+/** rune_memory_write() on an instance handle. */
+RuneError  rune_h_memory_write(RuneHandle inst, size_t offset, const void *src, size_t len);
+
+/** Create an empty linker. (ABI 1.5, feature "handle-linker") */
+RuneHandle rune_h_linker_new(void);
+
+/** rune_linker_define_typed() on a linker handle. (ABI 1.8) */
+RuneError  rune_h_linker_define_typed(
+    RuneHandle           linker,
+    const char          *module,
+    const char          *name,
+    const RuneSignature *sig,
+    RuneTypedHostFn      func,
+    void                *user_data
+);
+
+/** rune_linker_define() on a linker handle. (ABI 1.5) */
+RUNE_DEPRECATED("use rune_h_linker_define_typed")
+RuneError  rune_h_linker_define(
+    RuneHandle           linker,
+    const char          *module,
+    const char          *name,
+    const RuneSignature *sig,
+    RuneHostFn           func,
+    void                *user_data
+);
+
+/** rune_linker_define_instance() on linker and instance handles. (ABI 1.6) */
+RuneError  rune_h_linker_define_instance(RuneHandle linker, const char *module, RuneHandle inst);
+
+/** Instantiate a module against a linker. Returns RUNE_NULL_HANDLE on error. (ABI 1.5) */
+RuneHandle rune_h_linker_instantiate(RuneHandle linker, RuneHandle rt, RuneHandle mod);
+
+/**
+ * Release a handle. The object is freed once no call is using it;
+ * instances keep their module and runtime alive.
+ */
+RuneError  rune_handle_release(RuneHandle handle);
+
 /* ── Diagnostics ───────────────────────────────────────────────────────────── */
 
 /** Return a human-readable string for an error code. */
 const char *rune_error_string(RuneError err);
 
+/**
+ * Return the full message for the last failed call on the calling thread,
+ * e.g. "invalid module: invalid binary ops at offset 0x2a" or
+ * "undefined export: main", or NULL if no call has failed on it. The
+ * string stays valid until the next failing call on the same thread.
+ */
+const char *rune_last_error_message(void);
+
 #ifdef __cplusplus
 }
 #endif
@@ -1,24 +1,63 @@
-//! `runec` — Rune compiler and runner CLI (Phase 3 Week 10 stub).
+//! `runec` — Rune compiler and runner CLI.
 //!
 //! Usage:
-//!   runec compile <input.c> -o <output.rune>
-//!   runec run <module.rune> <func> [args...]
-//!   runec inspect <module.rune>
+//!   runec compile <module.runet> [-o <module.rune>]
+//!   runec compile <module.rune> [-o <module.so>] [compiler options]
+//!                                                 (`cranelift` feature)
+//!   runec run <module.rune> <func> [[i32:|i64:|f32:|f64:]args...]
+//!             [--dump-memory <start>..<end>]
+//!             [--strategy <interpreter|baseline|cranelift|tiered>]
+//!             [--record-profile <run.profile>] [--profile <run.profile>]
+//!             [compiler options]
+//!   runec inspect <module.rune | module.so>
+//!   runec disasm <module.rune> [func] [--text]
+//!   runec verify <module.rune>
+//!   runec opt <module.rune> [-o <out.rune>] [--passes <pass,...>]
+//!             [--profile <run.profile>]
+//!
+//! `compile` assembles a module from its text form (see `rune::text`), or
+//! builds a module into a shared library; `disasm --text` writes the text
+//! form back.
+//!
+//! `run` types each argument by its prefix, else by the export's signature,
+//! and prints the result with its type, as in `i64:-3`.
+//!
+//! `run --record-profile` runs in the interpreter and adds what the run did
+//! to the profile file; `opt --profile` optimizes by it, and
+//! `run --profile` has the tiered strategy compile its hot functions first.
+//!
+//! Compiler options:
+//!   --opt-level <none|speed|speed-and-size>
+//!   --target <triple>
+//!   --cpu-features <feature,...>
+//!   --debug-info
+//!   --bounds-checks <inline|helper|guard>
 
-use rune::{Module, Runtime};
+use rune::{
+    config::{BoundsChecks, CompilerConfig, OptLevel, Strategy},
+    opt::Pass,
+    profile::Profile,
+    sourcemap::SourceLoc,
+    types::{Val, ValType},
+    Module, Runtime, RuntimeConfig,
+};
 use std::env;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
     if args.len() < 2 {
         eprintln!("Usage: runec <command> [args...]");
-        eprintln!("Commands: run, inspect");
+        eprintln!("Commands: run, inspect, disasm, verify, compile, opt");
         std::process::exit(1);
     }
 
     match args[1].as_str() {
         "run" => cmd_run(&args[2..]),
         "inspect" => cmd_inspect(&args[2..]),
+        "disasm" => cmd_disasm(&args[2..]),
+        "verify" => cmd_verify(&args[2..]),
+        "compile" => cmd_compile(&args[2..]),
+        "opt" => cmd_opt(&args[2..]),
         other => {
             eprintln!("Unknown command: {other}");
             std::process::exit(1);
@@ -27,8 +66,33 @@ fn main() {
 }
 
 fn cmd_run(args: &[String]) {
+    let mut args = args.to_vec();
+    let strategy = take_value(&mut args, "--strategy").map(|s| match s.as_str() {
+        "interpreter" => Strategy::Interpreter,
+        "baseline" => Strategy::Baseline,
+        "cranelift" => Strategy::Cranelift,
+        "tiered" => Strategy::Tiered,
+        _ => usage_error("--strategy expects interpreter, baseline, cranelift or tiered"),
+    });
+    let compiler = take_compiler_options(&mut args);
+    let record = take_value(&mut args, "--record-profile");
+    let tier_profile = take_value(&mut args, "--profile").map(|p| load_profile(&p));
+    let dump = args.iter().position(|a| a == "--dump-memory").map(|i| {
+        let range = args
+            .get(i + 1)
+            .and_then(|r| parse_range(r))
+            .unwrap_or_else(|| {
+                eprintln!("--dump-memory expects <start>..<end>");
+                std::process::exit(1);
+            });
+        args.drain(i..i + 2);
+        range
+    });
     if args.len() < 2 {
-        eprintln!("Usage: runec run <module.rune> <func> [i32 args...]");
+        eprintln!(
+            "Usage: runec run <module.rune> <func> [[i32:|i64:|f32:|f64:]args...] \
+             [--dump-memory <start>..<end>]"
+        );
         std::process::exit(1);
     }
     let path = &args[0];
@@ -44,27 +108,112 @@ fn cmd_run(args: &[String]) {
         std::process::exit(1);
     });
 
-    let rt = Runtime::new();
+    let mut config = RuntimeConfig::new();
+    config.set_strategy(strategy.unwrap_or_default());
+    config.set_compiler(compiler);
+    config.set_profiling(record.is_some());
+    if let Some(profile) = tier_profile {
+        config.set_tier_profile(profile);
+    }
+    let rt = Runtime::with_config(config);
     let mut inst = rt.instantiate(&module).unwrap_or_else(|e| {
         eprintln!("Instantiation failed: {e}");
         std::process::exit(1);
     });
 
-    let val_args: Vec<rune::Val> = args[2..]
+    // Untyped arguments take their type from the export's signature.
+    let params = module
+        .find_export(func)
+        .and_then(|i| module.functions.get(i as usize))
+        .map(|f| f.ty.params.clone());
+    if let Some(params) = &params {
+        if params.len() != args.len() - 2 {
+            usage_error(&format!(
+                "{func} takes {} arguments, got {}",
+                params.len(),
+                args.len() - 2
+            ));
+        }
+    }
+    let val_args: Vec<Val> = args[2..]
         .iter()
-        .map(|s| {
-            rune::Val::I32(s.parse::<i32>().unwrap_or_else(|_| {
-                eprintln!("Cannot parse arg {s:?} as i32");
-                std::process::exit(1);
-            }))
+        .enumerate()
+        .map(|(i, s)| {
+            let param = params.as_ref().map(|p| p[i]);
+            let val = parse_arg(s, param).unwrap_or_else(|| {
+                let ty = param.map_or("a number", type_name);
+                usage_error(&format!("Cannot parse arg {s:?} as {ty}"))
+            });
+            match param {
+                Some(ty) if val.ty() != ty => usage_error(&format!(
+                    "Arg {s:?} is {}, but {func} takes {} there",
+                    type_name(val.ty()),
+                    type_name(ty)
+                )),
+                _ => val,
+            }
         })
         .collect();
 
-    match inst.call(func, &val_args) {
-        Ok(Some(v)) => println!("{v:?}"),
+    let result = inst.call(func, &val_args);
+    // Dump before reporting, so the state is visible after a trap too.
+    if let Some(range) = dump {
+        print!("{}", inst.memory().hexdump(range));
+    }
+    if let (Some(out), Some(profile)) = (record, inst.profile()) {
+        // Runs add up: keep what earlier runs of the module recorded.
+        let mut profile = profile.clone();
+        let earlier = std::fs::read_to_string(&out)
+            .ok()
+            .and_then(|text| text.parse::<Profile>().ok());
+        if let Some(earlier) = earlier.filter(|p| p.is_of(&module)) {
+            let _ = profile.merge(&earlier);
+        }
+        std::fs::write(&out, profile.to_string()).unwrap_or_else(|e| {
+            eprintln!("Cannot write {out}: {e}");
+            std::process::exit(1);
+        });
+    }
+    match result {
+        Ok(Some(v)) => println!("{}", format_val(v)),
         Ok(None) => println!("(no return value)"),
         Err(e) => {
             eprintln!("Trap: {e}");
+            for frame in inst.trap_backtrace() {
+                let name = module
+                    .functions
+                    .get(frame.func_index as usize)
+                    .map_or("?", |f| f.name.as_str());
+                eprintln!(
+                    "  at func[{}] {name} op {}{}",
+                    frame.func_index,
+                    frame.op_index,
+                    describe_loc(&module, frame.loc)
+                );
+            }
+            std::process::exit(1);
+        }
+    }
+}
+
+fn cmd_verify(args: &[String]) {
+    if args.is_empty() {
+        eprintln!("Usage: runec verify <module.rune>");
+        std::process::exit(1);
+    }
+    let path = &args[0];
+    let bytes = std::fs::read(path).unwrap_or_else(|e| {
+        eprintln!("Cannot read {path}: {e}");
+        std::process::exit(1);
+    });
+    let module = Module::from_bytes(&bytes).unwrap_or_else(|e| {
+        eprintln!("Invalid module: {e}");
+        std::process::exit(1);
+    });
+    match rune::verify::verify_module(&module, &RuntimeConfig::new()) {
+        Ok(()) => println!("{path}: {} functions verified", module.functions.len()),
+        Err(e) => {
+            eprintln!("{path}: {e}");
             std::process::exit(1);
         }
     }
@@ -72,7 +221,7 @@ fn cmd_run(args: &[String]) {
 
 fn cmd_inspect(args: &[String]) {
     if args.is_empty() {
-        eprintln!("Usage: runec inspect <module.rune>");
+        eprintln!("Usage: runec inspect <module.rune | module.so>");
         std::process::exit(1);
     }
     let path = &args[0];
@@ -80,16 +229,40 @@ fn cmd_inspect(args: &[String]) {
         eprintln!("Cannot read {path}: {e}");
         std::process::exit(1);
     });
+    #[cfg(feature = "cranelift")]
+    if bytes.starts_with(b"\x7FELF") {
+        let (triple, features) = rune::compiler::library::target_of(&bytes).unwrap_or_else(|e| {
+            eprintln!("Invalid library: {e}");
+            std::process::exit(1);
+        });
+        println!("=== Rune Library: {path} ===");
+        println!("Target: {triple}");
+        if !features.is_empty() {
+            println!("CPU features: {}", features.join(", "));
+        }
+        return;
+    }
     let module = Module::from_bytes(&bytes).unwrap_or_else(|e| {
         eprintln!("Invalid module: {e}");
         std::process::exit(1);
     });
 
     println!("=== Rune Module: {path} ===");
+    println!("Digest: {}", rune::hash::to_hex(&module.digest()));
     println!(
         "Memory: {} initial pages, max: {:?}",
         module.initial_memory_pages, module.max_memory_pages
     );
+    if !module.required_features.is_empty() {
+        println!("Requires: {}", module.required_features);
+    }
+    let host = &module.host_requirements;
+    if let Some(version) = host.api_version {
+        println!("Host API: {version}");
+    }
+    if !host.capabilities.is_empty() {
+        println!("Host capabilities: {}", host.capabilities.join(", "));
+    }
     println!("Functions: {}", module.functions.len());
     for (i, f) in module.functions.iter().enumerate() {
         println!("  [{i}] {} ({} ops)", f.name, f.body.len());
@@ -98,5 +271,365 @@ fn cmd_inspect(args: &[String]) {
     for (name, idx) in &module.exports {
         println!("  {name} -> func[{idx}]");
     }
+    if !module.imports.is_empty() {
+        println!("Imports:");
+        for (i, import) in module.imports.iter().enumerate() {
+            println!(
+                "  [{i}] {import} {:?} -> {:?}",
+                import.ty.params, import.ty.results
+            );
+        }
+    }
+    if !module.global_imports.is_empty() {
+        println!("Globals:");
+        for (i, global) in module.global_imports.iter().enumerate() {
+            let access = if global.mutable { "mut" } else { "const" };
+            println!("  [{i}] {global} {:?} {access}", global.ty);
+        }
+    }
     println!("Data segments: {}", module.data_segments.len());
+    for seg in &module.external_segments {
+        println!(
+            "  external @{:#x} ({} bytes) sha256:{}",
+            seg.offset,
+            seg.len,
+            rune::hash::to_hex(&seg.hash)
+        );
+    }
+    if let Some(sm) = &module.source_map {
+        println!("Source map: {} files", sm.files.len());
+    }
+}
+
+fn cmd_disasm(args: &[String]) {
+    let mut args = args.to_vec();
+    let text = take_flag(&mut args, "--text");
+    if args.is_empty() || (text && args.len() > 1) {
+        eprintln!("Usage: runec disasm <module.rune> [func] [--text]");
+        std::process::exit(1);
+    }
+    let module = load_module(&args[0]);
+    if text {
+        print!("{}", module.to_text());
+        return;
+    }
+    let only = args.get(1);
+
+    for (i, f) in module.functions.iter().enumerate() {
+        if only.is_some_and(|name| *name != f.name && *name != i.to_string()) {
+            continue;
+        }
+        println!(
+            "func[{i}] {} {:?} -> {:?}",
+            f.name, f.ty.params, f.ty.results
+        );
+        let mut last_loc = None;
+        for (pc, op) in f.body.iter().enumerate() {
+            let loc = module
+                .source_map
+                .as_ref()
+                .and_then(|sm| sm.lookup(i as u32, pc));
+            // Only annotate ops where the source location changes.
+            let note = if loc != last_loc {
+                describe_loc(&module, loc)
+            } else {
+                String::new()
+            };
+            last_loc = loc;
+            println!("  {pc:04}  {op:?}{note}");
+        }
+    }
+}
+
+/// Rewrite a module through the optimization passes, into a module that
+/// behaves the same in fewer ops.
+fn cmd_opt(args: &[String]) {
+    let mut args = args.to_vec();
+    let out = take_value(&mut args, "-o");
+    let passes: Vec<Pass> = match take_value(&mut args, "--passes") {
+        Some(names) => names
+            .split(',')
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                Pass::from_name(name).unwrap_or_else(|| {
+                    let known: Vec<_> = Pass::ALL.iter().map(|p| p.name()).collect();
+                    usage_error(&format!(
+                        "unknown pass {name:?}; passes are {}",
+                        known.join(", ")
+                    ))
+                })
+            })
+            .collect(),
+        None => Pass::ALL.to_vec(),
+    };
+    let profile = take_value(&mut args, "--profile").map(|p| load_profile(&p));
+    if args.len() != 1 {
+        eprintln!(
+            "Usage: runec opt <module.rune> [-o <out.rune>] [--passes <pass,...>] \
+             [--profile <run.profile>]"
+        );
+        std::process::exit(1);
+    }
+    let path = &args[0];
+    let out = out.unwrap_or_else(|| {
+        std::path::Path::new(path)
+            .with_extension("opt.rune")
+            .display()
+            .to_string()
+    });
+    let mut module = load_module(path);
+    let config = RuntimeConfig::new();
+    let report = match profile {
+        Some(profile) => rune::opt::optimize_with_profile(&mut module, &config, &passes, &profile)
+            .unwrap_or_else(|e| {
+                eprintln!("{e}");
+                std::process::exit(1);
+            }),
+        None => rune::opt::optimize(&mut module, &config, &passes),
+    };
+    for func in &report.unverified {
+        let name = &module.functions[*func as usize].name;
+        eprintln!("warning: func[{func}] {name} fails verification; left as it is");
+    }
+    std::fs::write(&out, module.to_bytes()).unwrap_or_else(|e| {
+        eprintln!("Cannot write {out}: {e}");
+        std::process::exit(1);
+    });
+    println!(
+        "{out}: {} ops, from {}",
+        report.ops_after, report.ops_before
+    );
+}
+
+fn cmd_compile(args: &[String]) {
+    if args.iter().any(|a| a.ends_with(".runet")) {
+        cmd_assemble(args);
+    } else {
+        cmd_compile_native(args);
+    }
+}
+
+/// Assemble a module from its text form, verify it, and write it as a
+/// `.rune` file, with a source map pointing back at the text.
+fn cmd_assemble(args: &[String]) {
+    let mut args = args.to_vec();
+    let out = take_value(&mut args, "-o");
+    if args.len() != 1 {
+        eprintln!("Usage: runec compile <module.runet> [-o <module.rune>]");
+        std::process::exit(1);
+    }
+    let path = &args[0];
+    let out = out.unwrap_or_else(|| {
+        std::path::Path::new(path)
+            .with_extension("rune")
+            .display()
+            .to_string()
+    });
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Cannot read {path}: {e}");
+        std::process::exit(1);
+    });
+    let module = Module::from_text_with_source_map(&text, path).unwrap_or_else(|e| {
+        eprintln!("{path}: {e}");
+        std::process::exit(1);
+    });
+    if let Err(e) = rune::verify::verify_module(&module, &RuntimeConfig::new()) {
+        let loc = e.func.zip(e.op).and_then(|(func, op)| {
+            let map = module.source_map.as_ref()?;
+            map.lookup(func, op)
+        });
+        match loc {
+            Some(loc) => eprintln!("{path}: line {}, column {}: {e}", loc.line, loc.column),
+            None => eprintln!("{path}: {e}"),
+        }
+        std::process::exit(1);
+    }
+    std::fs::write(&out, module.to_bytes()).unwrap_or_else(|e| {
+        eprintln!("Cannot write {out}: {e}");
+        std::process::exit(1);
+    });
+    println!("{out}: {} functions", module.functions.len());
+}
+
+/// Build a module into a shared library that hosts load with
+/// `Runtime::load_library`, so its code is never compiled at run time.
+#[cfg(feature = "cranelift")]
+fn cmd_compile_native(args: &[String]) {
+    let mut args = args.to_vec();
+    let out = take_value(&mut args, "-o");
+    let mut config = RuntimeConfig::new();
+    config.set_compiler(take_compiler_options(&mut args));
+    if args.len() != 1 {
+        eprintln!("Usage: runec compile <module.rune> [-o <module.so>] [compiler options]");
+        std::process::exit(1);
+    }
+    let path = &args[0];
+    let out = out.unwrap_or_else(|| {
+        std::path::Path::new(path)
+            .with_extension("so")
+            .display()
+            .to_string()
+    });
+    let module = load_module(path);
+    let lib = rune::compiler::library::build(&module, &config).unwrap_or_else(|e| {
+        eprintln!("Compilation failed: {e}");
+        std::process::exit(1);
+    });
+    std::fs::write(&out, lib).unwrap_or_else(|e| {
+        eprintln!("Cannot write {out}: {e}");
+        std::process::exit(1);
+    });
+}
+
+#[cfg(not(feature = "cranelift"))]
+fn cmd_compile_native(_args: &[String]) {
+    eprintln!("runec was built without native compilation; rebuild with --features cranelift");
+    std::process::exit(1);
+}
+
+/// Remove the compiler options from `args`, for the runtime's
+/// [`CompilerConfig`].
+fn take_compiler_options(args: &mut Vec<String>) -> CompilerConfig {
+    let mut compiler = CompilerConfig::default();
+    if let Some(level) = take_value(args, "--opt-level") {
+        compiler.opt_level = match level.as_str() {
+            "none" => OptLevel::None,
+            "speed" => OptLevel::Speed,
+            "speed-and-size" => OptLevel::SpeedAndSize,
+            _ => usage_error("--opt-level expects none, speed or speed-and-size"),
+        };
+    }
+    compiler.target = take_value(args, "--target");
+    compiler.cpu_features = take_value(args, "--cpu-features").map(|features| {
+        features
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .map(str::to_string)
+            .collect()
+    });
+    compiler.debug_info = take_flag(args, "--debug-info");
+    if let Some(checks) = take_value(args, "--bounds-checks") {
+        compiler.bounds_checks = match checks.as_str() {
+            "inline" => BoundsChecks::Inline,
+            "helper" => BoundsChecks::Helper,
+            "guard" => BoundsChecks::Guard,
+            _ => usage_error("--bounds-checks expects inline, helper or guard"),
+        };
+    }
+    compiler
+}
+
+/// Remove `flag` from `args`, returning whether it was there.
+fn take_flag(args: &mut Vec<String>, flag: &str) -> bool {
+    let found = args.iter().position(|a| a == flag).map(|i| args.remove(i));
+    found.is_some()
+}
+
+/// Remove `flag` and the value after it from `args`, returning the value.
+fn take_value(args: &mut Vec<String>, flag: &str) -> Option<String> {
+    let i = args.iter().position(|a| a == flag)?;
+    let value = args
+        .get(i + 1)
+        .cloned()
+        .unwrap_or_else(|| usage_error(&format!("{flag} expects a value")));
+    args.drain(i..i + 2);
+    Some(value)
+}
+
+fn usage_error(msg: &str) -> ! {
+    eprintln!("{msg}");
+    std::process::exit(1);
+}
+
+fn load_module(path: &str) -> Module {
+    let bytes = std::fs::read(path).unwrap_or_else(|e| {
+        eprintln!("Cannot read {path}: {e}");
+        std::process::exit(1);
+    });
+    Module::from_bytes(&bytes).unwrap_or_else(|e| {
+        eprintln!("Invalid module: {e}");
+        std::process::exit(1);
+    })
+}
+
+fn load_profile(path: &str) -> Profile {
+    let text = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Cannot read {path}: {e}");
+        std::process::exit(1);
+    });
+    text.parse().unwrap_or_else(|e| {
+        eprintln!("Invalid profile: {e}");
+        std::process::exit(1);
+    })
+}
+
+fn describe_loc(module: &Module, loc: Option<SourceLoc>) -> String {
+    let (Some(sm), Some(loc)) = (&module.source_map, loc) else {
+        return String::new();
+    };
+    let file = sm.file_name(loc.file).unwrap_or("?");
+    format!("  ; {file}:{}:{}", loc.line, loc.column)
+}
+
+/// Parse a `runec run` argument, typed by an `i32:`, `i64:`, `f32:` or
+/// `f64:` prefix, else as `param`, else as i32. Integers may be decimal or
+/// `0x` hex, and unsigned values up to the type's width wrap around.
+fn parse_arg(arg: &str, param: Option<ValType>) -> Option<Val> {
+    let (ty, value) = match arg.split_once(':') {
+        Some(("i32", value)) => (ValType::I32, value),
+        Some(("i64", value)) => (ValType::I64, value),
+        Some(("f32", value)) => (ValType::F32, value),
+        Some(("f64", value)) => (ValType::F64, value),
+        Some(_) => return None,
+        None => (param.unwrap_or(ValType::I32), arg),
+    };
+    let unsigned = |value: &str| match value.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok(),
+        None => value.parse().ok(),
+    };
+    Some(match ty {
+        ValType::I32 => Val::I32(
+            value
+                .parse()
+                .ok()
+                .or_else(|| u32::try_from(unsigned(value)?).ok().map(|u| u as i32))?,
+        ),
+        ValType::I64 => Val::I64(
+            value
+                .parse()
+                .ok()
+                .or_else(|| unsigned(value).map(|u| u as i64))?,
+        ),
+        ValType::F32 => Val::F32(value.parse().ok()?),
+        ValType::F64 => Val::F64(value.parse().ok()?),
+    })
+}
+
+/// Write a value as `runec run` takes it: `i32:7`, `f64:2.5`.
+fn format_val(val: Val) -> String {
+    match val {
+        Val::I32(v) => format!("i32:{v}"),
+        Val::I64(v) => format!("i64:{v}"),
+        Val::F32(v) => format!("f32:{v:?}"),
+        Val::F64(v) => format!("f64:{v:?}"),
+    }
+}
+
+fn type_name(ty: ValType) -> &'static str {
+    match ty {
+        ValType::I32 => "i32",
+        ValType::I64 => "i64",
+        ValType::F32 => "f32",
+        ValType::F64 => "f64",
+    }
+}
+
+/// Parse `<start>..<end>`, each bound decimal or `0x` hex.
+fn parse_range(s: &str) -> Option<std::ops::Range<usize>> {
+    let num = |n: &str| match n.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).ok(),
+        None => n.parse().ok(),
+    };
+    let (start, end) = s.split_once("..")?;
+    Some(num(start)?..num(end)?)
 }
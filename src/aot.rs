@@ -0,0 +1,148 @@
+//! Ahead-of-time compilation in the background, behind
+//! [`Runtime::compile_background`](crate::runtime::Runtime::compile_background).
+//!
+//! Compiling starts on its own thread and never blocks instantiation or
+//! calls: instances of the module run on the interpreter meanwhile. Once
+//! native code is ready, instances switch to it between calls, so a call
+//! already running finishes on the interpreter. Native code comes from
+//! [`compiler`](crate::compiler), behind the `cranelift` feature; without
+//! it, compilation only checks the module against the runtime's
+//! configuration and reports [`CompileOutcome::Interpreted`], so embedders
+//! can code against the handle either way.
+
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::time::Duration;
+
+#[cfg(feature = "cranelift")]
+use crate::compiler::{self, CodeCache, CodeSlot};
+use crate::{
+    config::RuntimeConfig,
+    instance::PreparedModule,
+    module::Module,
+    trap::{Result, Trap},
+};
+
+/// How instances of a compiled module execute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompileOutcome {
+    /// Native code was produced and replaces the interpreter.
+    Native,
+    /// No native backend is available; instances keep interpreting.
+    Interpreted,
+}
+
+/// A compilation started by
+/// [`Runtime::compile_background`](crate::runtime::Runtime::compile_background).
+/// Dropping it does not stop the compilation.
+#[derive(Clone)]
+pub struct CompileHandle {
+    module: Arc<Module>,
+    state: Arc<State>,
+}
+
+#[derive(Default)]
+struct State {
+    outcome: Mutex<Option<Result<CompileOutcome>>>,
+    done: Condvar,
+}
+
+impl CompileHandle {
+    /// Compile `module` on a new thread, filling `slot` with its code.
+    pub(crate) fn spawn(
+        module: Arc<Module>,
+        config: Arc<RuntimeConfig>,
+        #[cfg(feature = "cranelift")] slot: Arc<CodeSlot>,
+        #[cfg(feature = "cranelift")] cache: Option<Arc<CodeCache>>,
+    ) -> Self {
+        let state = Arc::new(State::default());
+        let handle = CompileHandle {
+            module: module.clone(),
+            state: state.clone(),
+        };
+        let spawned = std::thread::Builder::new()
+            .name("rune-compile".into())
+            .spawn({
+                let state = state.clone();
+                move || {
+                    #[cfg(feature = "cranelift")]
+                    let outcome = compile(&module, &config, &slot, cache.as_deref());
+                    #[cfg(not(feature = "cranelift"))]
+                    let outcome = compile(&module, &config);
+                    state.finish(outcome)
+                }
+            });
+        if let Err(e) = spawned {
+            state.finish(Err(Trap::HostError(format!(
+                "cannot start compiler thread: {e}"
+            ))));
+        }
+        handle
+    }
+
+    /// The module being compiled.
+    pub fn module(&self) -> &Arc<Module> {
+        &self.module
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.state.lock().is_some()
+    }
+
+    /// Block until compilation finishes. Fails with the error that made the
+    /// module unusable under the runtime's configuration, the same one
+    /// instantiating it would report.
+    pub fn wait(&self) -> Result<CompileOutcome> {
+        let mut outcome = self.state.lock();
+        while outcome.is_none() {
+            outcome = self
+                .state
+                .done
+                .wait(outcome)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+        outcome.clone().expect("checked above")
+    }
+
+    /// [`wait`](Self::wait) for at most `timeout`; `None` if compilation is
+    /// still running.
+    pub fn wait_timeout(&self, timeout: Duration) -> Option<Result<CompileOutcome>> {
+        let outcome = self.state.lock();
+        let (outcome, _) = self
+            .state
+            .done
+            .wait_timeout_while(outcome, timeout, |o| o.is_none())
+            .unwrap_or_else(PoisonError::into_inner);
+        outcome.clone()
+    }
+}
+
+impl State {
+    fn lock(&self) -> std::sync::MutexGuard<'_, Option<Result<CompileOutcome>>> {
+        self.outcome.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn finish(&self, outcome: Result<CompileOutcome>) {
+        *self.lock() = Some(outcome);
+        self.done.notify_all();
+    }
+}
+
+/// Compile `module` for `config` into `slot`, unless an instance under
+/// [`Strategy::Cranelift`](crate::config::Strategy) already did. Without
+/// the `cranelift` feature this only validates the module.
+fn compile(
+    module: &Module,
+    config: &Arc<RuntimeConfig>,
+    #[cfg(feature = "cranelift")] slot: &CodeSlot,
+    #[cfg(feature = "cranelift")] cache: Option<&CodeCache>,
+) -> Result<CompileOutcome> {
+    PreparedModule::new(module, config)?;
+    #[cfg(feature = "cranelift")]
+    if slot
+        .get_or_init(|| compiler::compile(module, config, cache))
+        .is_some()
+    {
+        return Ok(CompileOutcome::Native);
+    }
+    Ok(CompileOutcome::Interpreted)
+}
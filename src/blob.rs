@@ -0,0 +1,123 @@
+//! Blob stores — where external data segment bytes come from.
+//!
+//! A module may keep large data segments out-of-line, recording only their
+//! SHA-256 and length. The embedder supplies the bytes at instantiation
+//! through a [`BlobStore`], and the runtime verifies them against the hash.
+//! Stores that can stream (files, [`DataSources`]) are read straight into
+//! guest memory, so a large segment is never held twice.
+
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::PathBuf;
+
+use crate::hash::to_hex;
+
+/// Content-addressed source of external data segment bytes.
+pub trait BlobStore {
+    /// Return the bytes whose SHA-256 is `hash`, or `None` if unknown.
+    fn fetch(&self, hash: &[u8; 32]) -> Option<Cow<'_, [u8]>>;
+
+    /// Open the blob whose SHA-256 is `hash` for streaming. The default
+    /// reads from [`fetch`](Self::fetch); stores backed by files or
+    /// sockets override it to avoid buffering the whole blob.
+    fn open(&self, hash: &[u8; 32]) -> Option<Box<dyn Read + '_>> {
+        let bytes = self.fetch(hash)?;
+        Some(Box::new(Cursor::new(bytes)))
+    }
+}
+
+impl BlobStore for HashMap<[u8; 32], Vec<u8>> {
+    fn fetch(&self, hash: &[u8; 32]) -> Option<Cow<'_, [u8]>> {
+        self.get(hash).map(|b| Cow::Borrowed(b.as_slice()))
+    }
+}
+
+/// A store that has no blobs; used when none is supplied.
+pub(crate) struct NoBlobs;
+
+impl BlobStore for NoBlobs {
+    fn fetch(&self, _hash: &[u8; 32]) -> Option<Cow<'_, [u8]>> {
+        None
+    }
+}
+
+/// Blobs stored as files in a directory, each named by its lowercase hex hash.
+pub struct DirBlobStore {
+    root: PathBuf,
+}
+
+impl DirBlobStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        DirBlobStore { root: root.into() }
+    }
+
+    /// Path of the file holding the blob with this hash.
+    pub fn path_for(&self, hash: &[u8; 32]) -> PathBuf {
+        self.root.join(to_hex(hash))
+    }
+}
+
+impl BlobStore for DirBlobStore {
+    fn fetch(&self, hash: &[u8; 32]) -> Option<Cow<'_, [u8]>> {
+        std::fs::read(self.path_for(hash)).ok().map(Cow::Owned)
+    }
+
+    fn open(&self, hash: &[u8; 32]) -> Option<Box<dyn Read + '_>> {
+        let file = File::open(self.path_for(hash)).ok()?;
+        Some(Box::new(file))
+    }
+}
+
+/// External segment contents supplied per instantiation, each as a file
+/// path or an arbitrary reader, keyed by the segment's hash.
+///
+/// A reader can be consumed only once: a second instantiation from the same
+/// `DataSources` finds it gone and fails as if it had not been supplied.
+/// Files are reopened every time.
+#[derive(Default)]
+pub struct DataSources {
+    sources: HashMap<[u8; 32], Source>,
+}
+
+enum Source {
+    File(PathBuf),
+    Reader(RefCell<Option<Box<dyn Read>>>),
+}
+
+impl DataSources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the segment with this hash from the file at `path`.
+    pub fn add_file(&mut self, hash: [u8; 32], path: impl Into<PathBuf>) -> &mut Self {
+        self.sources.insert(hash, Source::File(path.into()));
+        self
+    }
+
+    /// Read the segment with this hash from `reader`.
+    pub fn add_reader(&mut self, hash: [u8; 32], reader: impl Read + 'static) -> &mut Self {
+        let reader: Box<dyn Read> = Box::new(reader);
+        self.sources
+            .insert(hash, Source::Reader(RefCell::new(Some(reader))));
+        self
+    }
+}
+
+impl BlobStore for DataSources {
+    fn fetch(&self, hash: &[u8; 32]) -> Option<Cow<'_, [u8]>> {
+        let mut bytes = Vec::new();
+        self.open(hash)?.read_to_end(&mut bytes).ok()?;
+        Some(Cow::Owned(bytes))
+    }
+
+    fn open(&self, hash: &[u8; 32]) -> Option<Box<dyn Read + '_>> {
+        match self.sources.get(hash)? {
+            Source::File(path) => Some(Box::new(File::open(path).ok()?)),
+            Source::Reader(reader) => reader.borrow_mut().take(),
+        }
+    }
+}
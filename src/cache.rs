@@ -0,0 +1,140 @@
+//! Content-addressed cache of decoded and prepared modules, behind
+//! [`Runtime::load_cached`](crate::runtime::Runtime::load_cached).
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::{instance::PreparedModule, module::Module};
+
+/// Decoded modules keyed by the SHA-256 of their encoding, holding at most
+/// `capacity` encoded bytes and evicting the least recently used first.
+/// Each keeps its functions once prepared, for every later instance.
+pub(crate) struct ModuleCache {
+    capacity: usize,
+    used: usize,
+    clock: u64,
+    entries: HashMap<[u8; 32], Entry>,
+    /// Hash of each cached module by its address, which stays unique while
+    /// the entry holds the module.
+    by_address: HashMap<usize, [u8; 32]>,
+}
+
+struct Entry {
+    module: Arc<Module>,
+    prepared: Option<Arc<PreparedModule>>,
+    size: usize,
+    last_used: u64,
+}
+
+fn address(module: &Module) -> usize {
+    module as *const Module as usize
+}
+
+impl ModuleCache {
+    pub(crate) fn new(capacity: usize) -> Self {
+        ModuleCache {
+            capacity,
+            used: 0,
+            clock: 0,
+            entries: HashMap::new(),
+            by_address: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn get(&mut self, hash: &[u8; 32]) -> Option<Arc<Module>> {
+        self.clock += 1;
+        let entry = self.entries.get_mut(hash)?;
+        entry.last_used = self.clock;
+        Some(entry.module.clone())
+    }
+
+    /// Cache `module`, decoded from `size` bytes hashing to `hash`. A module
+    /// larger than the whole cache is not kept.
+    pub(crate) fn insert(&mut self, hash: [u8; 32], size: usize, module: Arc<Module>) {
+        if size > self.capacity || self.entries.contains_key(&hash) {
+            return;
+        }
+        while self.used + size > self.capacity {
+            self.evict_oldest();
+        }
+        self.clock += 1;
+        self.used += size;
+        self.by_address.insert(address(&module), hash);
+        self.entries.insert(
+            hash,
+            Entry {
+                module,
+                prepared: None,
+                size,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    /// The functions prepared for `module`, if it is cached and has been
+    /// prepared.
+    pub(crate) fn prepared(&self, module: &Module) -> Option<Arc<PreparedModule>> {
+        let hash = self.by_address.get(&address(module))?;
+        self.entries.get(hash)?.prepared.clone()
+    }
+
+    /// Keep `prepared` for `module`, if it is cached.
+    pub(crate) fn set_prepared(&mut self, module: &Module, prepared: Arc<PreparedModule>) {
+        let Some(hash) = self.by_address.get(&address(module)) else {
+            return;
+        };
+        if let Some(entry) = self.entries.get_mut(hash) {
+            entry.prepared.get_or_insert(prepared);
+        }
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.entries.clear();
+        self.by_address.clear();
+        self.used = 0;
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .entries
+            .iter()
+            .min_by_key(|(_, e)| e.last_used)
+            .map(|(hash, _)| *hash);
+        if let Some(entry) = oldest.and_then(|hash| self.entries.remove(&hash)) {
+            self.by_address.remove(&address(&entry.module));
+            self.used -= entry.size;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(n: u8) -> [u8; 32] {
+        [n; 32]
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = ModuleCache::new(30);
+        cache.insert(key(1), 10, Arc::new(Module::new()));
+        cache.insert(key(2), 10, Arc::new(Module::new()));
+        cache.insert(key(3), 10, Arc::new(Module::new()));
+        assert!(cache.get(&key(1)).is_some());
+        cache.insert(key(4), 15, Arc::new(Module::new()));
+        // 2 and 3 were least recently used; 1 was touched.
+        assert!(cache.get(&key(1)).is_some());
+        assert!(cache.get(&key(2)).is_none());
+        assert!(cache.get(&key(3)).is_none());
+        assert_eq!(cache.len(), 2);
+
+        cache.insert(key(5), 31, Arc::new(Module::new()));
+        assert!(cache.get(&key(5)).is_none());
+        assert_eq!(cache.len(), 2);
+    }
+}
@@ -0,0 +1,63 @@
+//! Cancelling guest calls from another thread, behind
+//! [`Instance::call_cancellable`](crate::instance::Instance::call_cancellable).
+//!
+//! A server ties a call to the request it serves by handing the call a
+//! clone of the request's [`CancellationToken`] and cancelling the token
+//! when the request goes away, or by holding a [`DropGuard`] in the
+//! request's future so that dropping the future cancels it. The call then
+//! returns `Trap::Interrupted` at its next loop iteration or function call,
+//! like an epoch deadline.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A flag shared between clones; once cancelled, stays cancelled.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Interrupt every call running with this token or a clone of it, and
+    /// any started with one later.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// A guard that cancels the token when dropped, unless
+    /// [`disarm`](DropGuard::disarm)ed first.
+    pub fn drop_guard(&self) -> DropGuard {
+        DropGuard {
+            token: Some(self.clone()),
+        }
+    }
+}
+
+/// Cancels its token when dropped; from [`CancellationToken::drop_guard`].
+#[derive(Debug)]
+pub struct DropGuard {
+    token: Option<CancellationToken>,
+}
+
+impl DropGuard {
+    /// Give the token back without cancelling it.
+    pub fn disarm(mut self) -> CancellationToken {
+        self.token.take().unwrap_or_default()
+    }
+}
+
+impl Drop for DropGuard {
+    fn drop(&mut self) {
+        if let Some(token) = &self.token {
+            token.cancel();
+        }
+    }
+}
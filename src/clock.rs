@@ -0,0 +1,123 @@
+//! Time sources for the host library's `clock_now` and `sleep_until`,
+//! chosen with [`HostLib::clock`](crate::hostlib::HostLib::clock), and for
+//! [timers](crate::timer), chosen with
+//! [`RuntimeConfig::set_clock`](crate::config::RuntimeConfig::set_clock).
+//!
+//! [`SystemClock`] follows real time. [`ManualClock`] moves only when the
+//! host advances it, or when a guest sleeps on it, which then returns at
+//! once with the clock moved to the deadline: tests control exactly what
+//! guests observe, and runs with the same inputs see the same times.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::sys::Instant;
+
+/// A monotonic clock guests can read and sleep on.
+pub trait Clock: Send + Sync {
+    /// Time since the clock's fixed starting point; never decreases.
+    fn now(&self) -> Duration;
+
+    /// Return once [`now`](Self::now) has reached `deadline`.
+    fn sleep_until(&self, deadline: Duration);
+
+    /// Whether readings depend only on what the host and guests did to the
+    /// clock, not on real time. Functions backed by a deterministic clock
+    /// carry no capability label, so they stay callable under
+    /// [`SandboxProfile::Strict`](crate::sandbox::SandboxProfile::Strict).
+    fn is_deterministic(&self) -> bool {
+        false
+    }
+}
+
+/// Real time, from when the clock was created.
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> Duration {
+        self.start.elapsed()
+    }
+
+    #[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+    fn sleep_until(&self, deadline: Duration) {
+        std::thread::sleep(deadline.saturating_sub(self.now()));
+    }
+
+    /// Spins: there is no thread to put to sleep.
+    #[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+    fn sleep_until(&self, deadline: Duration) {
+        while self.now() < deadline {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// Install the time source the runtime reads on `wasm32-unknown-unknown`,
+/// where the standard library has no clock. `now` returns the time since
+/// the Unix epoch and must never decrease; in a browser that is
+/// `performance.timeOrigin + performance.now()`. Until a source is
+/// installed time stands still, so CPU-time budgets never run out.
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub fn set_time_source(now: fn() -> Duration) {
+    crate::sys::set_time_source(now);
+}
+
+/// Virtual time, starting at zero. Clones share the same time, so the host
+/// keeps one to advance the clock a host library was given.
+#[derive(Debug, Clone, Default)]
+pub struct ManualClock {
+    nanos: Arc<AtomicU64>,
+}
+
+impl ManualClock {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Move the clock forward by `by`.
+    pub fn advance(&self, by: Duration) {
+        self.nanos.fetch_add(nanos(by), Ordering::Relaxed);
+    }
+
+    /// Move the clock to `to`, unless it is already later.
+    pub fn advance_to(&self, to: Duration) {
+        self.nanos.fetch_max(nanos(to), Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Ordering::Relaxed))
+    }
+
+    /// Jump to `deadline` without blocking.
+    fn sleep_until(&self, deadline: Duration) {
+        self.advance_to(deadline);
+    }
+
+    fn is_deterministic(&self) -> bool {
+        true
+    }
+}
+
+fn nanos(d: Duration) -> u64 {
+    u64::try_from(d.as_nanos()).unwrap_or(u64::MAX)
+}
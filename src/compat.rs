@@ -0,0 +1,203 @@
+//! Host API versioning: what a module says it needs from the host beyond
+//! its imports, what the host says it provides, and
+//! [`Runtime::check_compat`](crate::runtime::Runtime::check_compat)
+//! comparing the two.
+//!
+//! Import names and signatures catch a host function that was removed or
+//! retyped, but not one whose meaning changed. For that, a module records
+//! the host API version it was built against and the capability names it
+//! relies on in its [`HostRequirements`], and the embedder declares a
+//! [`HostApi`] on the runtime's config. Versions compare like semver: the
+//! major must match and the host's minor must be at least the module's.
+//!
+//! Instantiating a module whose requirements the host does not meet fails
+//! with `Trap::UnsupportedFeature`. A module that declares nothing runs on
+//! any host.
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+use crate::trap::{Result, Trap};
+
+/// A host API version, `major.minor`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct ApiVersion {
+    /// Bumped when a host function changes meaning or is removed.
+    pub major: u32,
+    /// Bumped when host functions are added.
+    pub minor: u32,
+}
+
+impl ApiVersion {
+    pub const fn new(major: u32, minor: u32) -> Self {
+        ApiVersion { major, minor }
+    }
+
+    /// Whether a host providing this version can run a module built
+    /// against `required`.
+    pub fn satisfies(self, required: ApiVersion) -> bool {
+        self.major == required.major && self.minor >= required.minor
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.major, self.minor)
+    }
+}
+
+impl std::str::FromStr for ApiVersion {
+    type Err = Trap;
+
+    /// Parse `major.minor`.
+    fn from_str(s: &str) -> Result<Self> {
+        let parsed = s.split_once('.').and_then(|(major, minor)| {
+            Some(ApiVersion::new(major.parse().ok()?, minor.parse().ok()?))
+        });
+        parsed.ok_or_else(|| Trap::InvalidModule(format!("invalid API version `{s}`")))
+    }
+}
+
+/// What a module needs from its host, stored in the module.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostRequirements {
+    /// Host API version the module was built against, if it declares one.
+    pub api_version: Option<ApiVersion>,
+    /// Capabilities the host must provide, by name.
+    pub capabilities: Vec<String>,
+}
+
+impl HostRequirements {
+    /// Whether the module declares nothing, and so runs on any host.
+    pub fn is_empty(&self) -> bool {
+        self.api_version.is_none() && self.capabilities.is_empty()
+    }
+
+    /// Combine the requirements of two modules merged into one: the later
+    /// minor version and every capability either names. Fails with
+    /// `Trap::InvalidModule` if they require different major versions.
+    pub(crate) fn merge(&mut self, other: HostRequirements) -> Result<()> {
+        self.api_version = match (self.api_version, other.api_version) {
+            (Some(a), Some(b)) if a.major != b.major => {
+                return Err(Trap::InvalidModule(format!(
+                    "modules require host API {a} and {b}"
+                )))
+            }
+            (a, b) => a.max(b),
+        };
+        for cap in other.capabilities {
+            if !self.capabilities.contains(&cap) {
+                self.capabilities.push(cap);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// What a host provides, declared with
+/// [`RuntimeConfig::set_host_api`](crate::config::RuntimeConfig::set_host_api).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostApi {
+    version: Option<ApiVersion>,
+    capabilities: BTreeSet<String>,
+}
+
+impl HostApi {
+    /// A host at `version` providing no capabilities yet.
+    pub fn new(version: ApiVersion) -> Self {
+        HostApi {
+            version: Some(version),
+            capabilities: BTreeSet::new(),
+        }
+    }
+
+    /// Declare that the host provides capability `name`.
+    pub fn provide(&mut self, name: impl Into<String>) -> &mut Self {
+        self.capabilities.insert(name.into());
+        self
+    }
+
+    pub fn version(&self) -> Option<ApiVersion> {
+        self.version
+    }
+
+    pub fn provides(&self, name: &str) -> bool {
+        self.capabilities.contains(name)
+    }
+
+    /// Compare against what a module requires.
+    pub fn check(&self, required: &HostRequirements) -> CompatReport {
+        CompatReport {
+            required_version: required.api_version,
+            host_version: self.version,
+            missing_capabilities: required
+                .capabilities
+                .iter()
+                .filter(|cap| !self.provides(cap))
+                .cloned()
+                .collect(),
+        }
+    }
+}
+
+/// Outcome of checking a module against a host, from
+/// [`Runtime::check_compat`](crate::runtime::Runtime::check_compat).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompatReport {
+    /// Version the module was built against, if it declares one.
+    pub required_version: Option<ApiVersion>,
+    /// Version the host declares, if any.
+    pub host_version: Option<ApiVersion>,
+    /// Capabilities the module requires that the host does not provide.
+    pub missing_capabilities: Vec<String>,
+}
+
+impl CompatReport {
+    /// Whether the host's version satisfies the module's. A module with no
+    /// version runs anywhere; one with a version needs a host declaring one.
+    pub fn version_compatible(&self) -> bool {
+        match (self.required_version, self.host_version) {
+            (None, _) => true,
+            (Some(required), Some(host)) => host.satisfies(required),
+            (Some(_), None) => false,
+        }
+    }
+
+    /// Whether the module can run on the host.
+    pub fn is_compatible(&self) -> bool {
+        self.version_compatible() && self.missing_capabilities.is_empty()
+    }
+
+    /// `Ok` if compatible, else `Trap::UnsupportedFeature` describing why.
+    pub fn into_result(self) -> Result<()> {
+        if self.is_compatible() {
+            Ok(())
+        } else {
+            Err(Trap::UnsupportedFeature(self.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for CompatReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_compatible() {
+            return write!(f, "compatible");
+        }
+        let mut problems = Vec::new();
+        if !self.version_compatible() {
+            if let Some(required) = self.required_version {
+                problems.push(match self.host_version {
+                    Some(host) => format!("host API {required} (host provides {host})"),
+                    None => format!("host API {required} (host declares no version)"),
+                });
+            }
+        }
+        if !self.missing_capabilities.is_empty() {
+            problems.push(format!(
+                "host capabilities {}",
+                self.missing_capabilities.join(", ")
+            ));
+        }
+        write!(f, "module requires {}", problems.join(" and "))
+    }
+}
@@ -0,0 +1,448 @@
+//! Machine code that can be loaded at any address: a module's code with
+//! every address that differs between processes left as a relocation.
+//!
+//! The baseline JIT always produces an artifact and loads it straight
+//! away. Cranelift produces one for the [code cache](super::code_cache),
+//! which saves it to disk with [`Artifact::to_bytes`] so a later process
+//! can load it instead of compiling again, and for shared libraries built
+//! by [`library`](super::library). Artifacts are only built for x86-64
+//! Linux.
+
+use std::sync::atomic::AtomicU64;
+
+use super::{
+    debug_info::{self, Symbol},
+    native::{CodeMemory, Entry, Helper, NativeModule},
+};
+use crate::{
+    hash::sha256,
+    module::Module,
+    sys::Mapping,
+    trap::{Result, Trap},
+};
+
+const MAGIC: &[u8; 8] = b"RUNECODE";
+
+/// Version of the encoding and of the code either compiler emits. Bump it
+/// whenever a change to either would make old artifacts run differently.
+pub(crate) const FORMAT: u32 = 4;
+
+/// A module's machine code before it is loaded.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Artifact {
+    pub(crate) code: Vec<u8>,
+    /// Offset of each function's [`Entry`], `None` if it stays interpreted.
+    pub(crate) entries: Vec<Option<u32>>,
+    pub(crate) relocs: Vec<Reloc>,
+    /// Loads and stores that leave bounds to guard pages: the offset of
+    /// each, and of the code that makes the access through a helper when
+    /// it faults.
+    pub(crate) faults: Vec<(u32, u32)>,
+    /// Where each compiled function's code lies, under
+    /// [debug info](super::debug_info); empty without.
+    pub(crate) symbols: Vec<Symbol>,
+}
+
+/// A place in the code to patch with an address once it is loaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Reloc {
+    pub(crate) at: u32,
+    pub(crate) kind: RelocKind,
+    pub(crate) target: Target,
+    pub(crate) addend: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum RelocKind {
+    /// The target's address, as 8 bytes.
+    Abs8,
+    /// The target's distance from the patched place, as 4 bytes.
+    PcRel4,
+    /// The distance to a GOT slot holding the target's address, as 4
+    /// bytes; position-independent code reaches helpers this way.
+    GotPcRel4,
+    /// An AArch64 `bl`, to the target's distance in words.
+    Call26,
+    /// An AArch64 `adrp`, to the distance in 4 KiB pages to the GOT slot
+    /// holding the target's address.
+    AdrGotPage21,
+    /// The AArch64 `ldr` after an `AdrGotPage21`, to the slot's offset
+    /// within its page.
+    Ld64GotLo12,
+}
+
+impl RelocKind {
+    /// Whether the code reaches the target through a GOT slot.
+    pub(crate) fn via_got(self) -> bool {
+        matches!(
+            self,
+            RelocKind::GotPcRel4 | RelocKind::AdrGotPage21 | RelocKind::Ld64GotLo12
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Target {
+    /// An offset into the code itself.
+    Code(u32),
+    Helper(Helper),
+    LibCall(LibCall),
+    /// The call counter of a function, for tiered execution.
+    Counter(u32),
+}
+
+/// The float operations Cranelift calls out for on CPUs without SSE4.1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum LibCall {
+    CeilF32,
+    CeilF64,
+    FloorF32,
+    FloorF64,
+    TruncF32,
+    TruncF64,
+    NearestF32,
+    NearestF64,
+}
+
+impl LibCall {
+    pub(crate) const ALL: [LibCall; 8] = [
+        LibCall::CeilF32,
+        LibCall::CeilF64,
+        LibCall::FloorF32,
+        LibCall::FloorF64,
+        LibCall::TruncF32,
+        LibCall::TruncF64,
+        LibCall::NearestF32,
+        LibCall::NearestF64,
+    ];
+
+    pub(crate) fn address(self) -> *const u8 {
+        match self {
+            LibCall::CeilF32 => ceil_f32 as *const u8,
+            LibCall::CeilF64 => ceil_f64 as *const u8,
+            LibCall::FloorF32 => floor_f32 as *const u8,
+            LibCall::FloorF64 => floor_f64 as *const u8,
+            LibCall::TruncF32 => trunc_f32 as *const u8,
+            LibCall::TruncF64 => trunc_f64 as *const u8,
+            LibCall::NearestF32 => nearest_f32 as *const u8,
+            LibCall::NearestF64 => nearest_f64 as *const u8,
+        }
+    }
+}
+
+extern "C" fn ceil_f32(x: f32) -> f32 {
+    x.ceil()
+}
+
+extern "C" fn ceil_f64(x: f64) -> f64 {
+    x.ceil()
+}
+
+extern "C" fn floor_f32(x: f32) -> f32 {
+    x.floor()
+}
+
+extern "C" fn floor_f64(x: f64) -> f64 {
+    x.floor()
+}
+
+extern "C" fn trunc_f32(x: f32) -> f32 {
+    x.trunc()
+}
+
+extern "C" fn trunc_f64(x: f64) -> f64 {
+    x.trunc()
+}
+
+extern "C" fn nearest_f32(x: f32) -> f32 {
+    x.round_ties_even()
+}
+
+extern "C" fn nearest_f64(x: f64) -> f64 {
+    x.round_ties_even()
+}
+
+impl Artifact {
+    /// Copy the code of `module` into executable memory and patch it. Code
+    /// counting its calls needs `calls`, which must outlive every run of
+    /// it.
+    pub(crate) fn load(
+        &self,
+        module: &Module,
+        calls: Option<&[AtomicU64]>,
+    ) -> Result<NativeModule> {
+        if !self.faults.is_empty() {
+            super::native::prepare_guard_pages()?;
+        }
+        let got = self.got();
+        let got_at = self.code.len().next_multiple_of(8);
+        let mut mapping = Mapping::new((got_at + 8 * got.len()).max(1), None)
+            .ok_or_else(|| Trap::HostError("cannot map code memory".into()))?;
+        let base = mapping.as_ptr();
+        // SAFETY: the mapping is fresh, writable and long enough for the
+        // code followed by the GOT.
+        unsafe { std::ptr::copy_nonoverlapping(self.code.as_ptr(), base, self.code.len()) };
+        let resolve = |target| -> Result<*const u8> {
+            Ok(match target {
+                // SAFETY: `from_bytes` and the compilers keep offsets within
+                // the code.
+                Target::Code(offset) => unsafe { base.add(offset as usize) as *const u8 },
+                Target::Helper(helper) => helper.address(),
+                Target::LibCall(libcall) => libcall.address(),
+                Target::Counter(func) => {
+                    let counter = calls
+                        .and_then(|calls| calls.get(func as usize))
+                        .ok_or_else(|| {
+                            Trap::HostError("code counts calls but no counters".into())
+                        })?;
+                    counter as *const AtomicU64 as *const u8
+                }
+            })
+        };
+        for (slot, &target) in got.iter().enumerate() {
+            // SAFETY: slot `slot` of the GOT lies within the mapping.
+            unsafe {
+                base.add(got_at + 8 * slot)
+                    .cast::<*const u8>()
+                    .write(resolve(target)?)
+            };
+        }
+        for reloc in &self.relocs {
+            let target = match reloc.kind {
+                kind if kind.via_got() => {
+                    let slot = got
+                        .iter()
+                        .position(|&t| t == reloc.target)
+                        .expect("in the GOT");
+                    // SAFETY: as above.
+                    unsafe { base.add(got_at + 8 * slot) as *const u8 }
+                }
+                _ => resolve(reloc.target)?,
+            };
+            let value = (target as i64).wrapping_add(reloc.addend);
+            // SAFETY: as above, the patched bytes lie within the code.
+            let at = unsafe { base.add(reloc.at as usize) };
+            match reloc.kind {
+                RelocKind::Abs8 => unsafe { at.cast::<i64>().write_unaligned(value) },
+                RelocKind::PcRel4 | RelocKind::GotPcRel4 => {
+                    let rel = i32::try_from(value.wrapping_sub(at as i64))
+                        .map_err(|_| Trap::HostError("relocation out of range".into()))?;
+                    unsafe { at.cast::<i32>().write_unaligned(rel) }
+                }
+                // Only shared libraries hold code for other hosts.
+                RelocKind::Call26 | RelocKind::AdrGotPage21 | RelocKind::Ld64GotLo12 => {
+                    return Err(Trap::HostError("code for another architecture".into()))
+                }
+            }
+        }
+        if !mapping.make_executable() {
+            return Err(Trap::HostError("cannot make code executable".into()));
+        }
+        if !self.symbols.is_empty() {
+            // SAFETY: the code is readable and no longer written to.
+            let code = unsafe { std::slice::from_raw_parts(base, self.code.len()) };
+            let code: Vec<_> = self
+                .symbols
+                .iter()
+                .map(|s| (&code[s.start as usize..][..s.len as usize], s))
+                .collect();
+            debug_info::announce(module, &code);
+        }
+        let entries = self
+            .entries
+            .iter()
+            .map(|entry| {
+                entry.map(|offset| {
+                    // SAFETY: the compilers put a function with the
+                    // signature of `Entry` at every entry offset.
+                    unsafe { std::mem::transmute::<*const u8, Entry>(base.add(offset as usize)) }
+                })
+            })
+            .collect();
+        let faults = self
+            .faults
+            .iter()
+            .map(|&(at, to)| (base as usize + at as usize, base as usize + to as usize))
+            .collect();
+        Ok(NativeModule::new(CodeMemory::Mapped(mapping), entries).with_faults(faults))
+    }
+
+    /// The targets code reaches through the GOT, one slot each, in order.
+    pub(crate) fn got(&self) -> Vec<Target> {
+        let mut got = Vec::new();
+        for reloc in &self.relocs {
+            if reloc.kind.via_got() && !got.contains(&reloc.target) {
+                got.push(reloc.target);
+            }
+        }
+        got
+    }
+
+    /// Encode the artifact, with a checksum so a damaged copy is noticed.
+    pub(crate) fn to_bytes(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&FORMAT.to_le_bytes());
+        put_u32(&mut out, self.code.len() as u32);
+        out.extend_from_slice(&self.code);
+        put_u32(&mut out, self.entries.len() as u32);
+        for entry in &self.entries {
+            put_u32(&mut out, entry.unwrap_or(u32::MAX));
+        }
+        put_u32(&mut out, self.relocs.len() as u32);
+        for reloc in &self.relocs {
+            put_u32(&mut out, reloc.at);
+            out.push(match reloc.kind {
+                RelocKind::Abs8 => 0,
+                RelocKind::PcRel4 => 1,
+                RelocKind::GotPcRel4 => 2,
+                RelocKind::Call26 => 3,
+                RelocKind::AdrGotPage21 => 4,
+                RelocKind::Ld64GotLo12 => 5,
+            });
+            let (tag, index) = match reloc.target {
+                Target::Code(offset) => (0, offset),
+                Target::Helper(h) => (1, index_of(&Helper::ALL, h)),
+                Target::LibCall(l) => (2, index_of(&LibCall::ALL, l)),
+                Target::Counter(func) => (3, func),
+            };
+            out.push(tag);
+            put_u32(&mut out, index);
+            out.extend_from_slice(&reloc.addend.to_le_bytes());
+        }
+        put_u32(&mut out, self.faults.len() as u32);
+        for &(at, to) in &self.faults {
+            put_u32(&mut out, at);
+            put_u32(&mut out, to);
+        }
+        put_u32(&mut out, self.symbols.len() as u32);
+        for symbol in &self.symbols {
+            put_u32(&mut out, symbol.func);
+            put_u32(&mut out, symbol.start);
+            put_u32(&mut out, symbol.len);
+            put_u32(&mut out, symbol.ops.len() as u32);
+            for &(at, op) in &symbol.ops {
+                put_u32(&mut out, at);
+                put_u32(&mut out, op);
+            }
+        }
+        let checksum = sha256(&out);
+        out.extend_from_slice(&checksum);
+        out
+    }
+
+    /// Decode what [`to_bytes`](Self::to_bytes) encoded, or `None` if
+    /// `bytes` are damaged, from another format version, or point outside
+    /// the code.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Option<Artifact> {
+        let (body, checksum) = bytes.split_at_checked(bytes.len().checked_sub(32)?)?;
+        if sha256(body) != checksum {
+            return None;
+        }
+        let mut r = Reader { bytes: body };
+        if r.take(8)? != MAGIC || r.u32()? != FORMAT {
+            return None;
+        }
+        let len = r.u32()? as usize;
+        let code = r.take(len)?.to_vec();
+        let entries = (0..r.u32()?)
+            .map(|_| match r.u32()? {
+                u32::MAX => Some(None),
+                offset if (offset as usize) < len => Some(Some(offset)),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let relocs = (0..r.u32()?)
+            .map(|_| {
+                let at = r.u32()?;
+                let (kind, width) = match r.u8()? {
+                    0 => (RelocKind::Abs8, 8),
+                    1 => (RelocKind::PcRel4, 4),
+                    2 => (RelocKind::GotPcRel4, 4),
+                    3 => (RelocKind::Call26, 4),
+                    4 => (RelocKind::AdrGotPage21, 4),
+                    5 => (RelocKind::Ld64GotLo12, 4),
+                    _ => return None,
+                };
+                if at as usize + width > len {
+                    return None;
+                }
+                let (tag, index) = (r.u8()?, r.u32()?);
+                let target = match tag {
+                    0 if (index as usize) < len => Target::Code(index),
+                    1 => Target::Helper(*Helper::ALL.get(index as usize)?),
+                    2 => Target::LibCall(*LibCall::ALL.get(index as usize)?),
+                    3 if (index as usize) < entries.len() => Target::Counter(index),
+                    _ => return None,
+                };
+                let addend = i64::from_le_bytes(r.take(8)?.try_into().ok()?);
+                Some(Reloc {
+                    at,
+                    kind,
+                    target,
+                    addend,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let faults = (0..r.u32()?)
+            .map(|_| {
+                let (at, to) = (r.u32()?, r.u32()?);
+                ((at as usize) < len && (to as usize) < len).then_some((at, to))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        let symbols = (0..r.u32()?)
+            .map(|_| {
+                let (func, start, len) = (r.u32()?, r.u32()?, r.u32()?);
+                if func as usize >= entries.len() || start as usize + len as usize > code.len() {
+                    return None;
+                }
+                let ops = (0..r.u32()?)
+                    .map(|_| {
+                        let (at, op) = (r.u32()?, r.u32()?);
+                        (at < len).then_some((at, op))
+                    })
+                    .collect::<Option<Vec<_>>>()?;
+                Some(Symbol {
+                    func,
+                    start,
+                    len,
+                    ops,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        r.bytes.is_empty().then_some(Artifact {
+            code,
+            entries,
+            relocs,
+            faults,
+            symbols,
+        })
+    }
+}
+
+fn index_of<T: PartialEq>(all: &[T], item: T) -> u32 {
+    all.iter().position(|x| *x == item).expect("listed in ALL") as u32
+}
+
+fn put_u32(out: &mut Vec<u8>, v: u32) {
+    out.extend_from_slice(&v.to_le_bytes());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Option<&'a [u8]> {
+        let (head, rest) = self.bytes.split_at_checked(n)?;
+        self.bytes = rest;
+        Some(head)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.take(4)?.try_into().ok()?))
+    }
+}
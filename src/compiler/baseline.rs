@@ -0,0 +1,83 @@
+//! The baseline JIT: a single pass over each function that emits a fixed
+//! machine-code template per op, with no IR and no register allocation.
+//!
+//! It compiles in a fraction of the time Cranelift takes, so a module runs
+//! natively almost as soon as it is instantiated, at some cost in speed:
+//! operands live in the native stack frame rather than in registers. The
+//! code shares everything else with Cranelift's: the context, helpers and
+//! entry ABI in [`native`](super::native), and the interpreter's results,
+//! traps, backtraces and fuel counts.
+//!
+//! Under [`BoundsChecks::Guard`](crate::config::BoundsChecks::Guard),
+//! loads and stores skip the bounds compare and go straight to memory
+//! reserved with guard pages. One that misses faults, and the fault
+//! handler resumes it on the path that calls the runtime, which traps as
+//! the interpreter does.
+//!
+//! Templates exist for x86-64 on Linux. A function using an op without
+//! one (float truncation, `min`/`max`, `ceil`/`floor` and unsigned 64-bit
+//! conversion) or that the translator cannot type stays interpreted.
+
+use std::sync::atomic::AtomicU64;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use super::artifact::Artifact;
+use super::NativeModule;
+use crate::{config::RuntimeConfig, module::Module, trap::Result};
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod x64;
+
+/// Compile `module` with the baseline JIT, to run under `config`. Fails
+/// with `Trap::UnsupportedFeature` on hosts without templates. Functions it
+/// cannot compile are left out; see [`NativeModule::is_compiled`].
+pub fn compile(module: &Module, config: &RuntimeConfig) -> Result<NativeModule> {
+    compile_only(module, config, &vec![true; module.functions.len()], None)
+}
+
+/// [`compile`] only the functions `include` selects, leaving the rest to
+/// the interpreter. Given `calls`, each compiled function counts its own
+/// calls there on entry, so the counters must outlive every run of the
+/// code.
+pub(crate) fn compile_only(
+    module: &Module,
+    config: &RuntimeConfig,
+    include: &[bool],
+    calls: Option<&[AtomicU64]>,
+) -> Result<NativeModule> {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    return artifact_only(module, config, include, calls.is_some())?.load(module, calls);
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    {
+        let _ = (module, config, include, calls);
+        Err(crate::trap::Trap::UnsupportedFeature(
+            "baseline JIT for this host".into(),
+        ))
+    }
+}
+
+/// [`compile`] into an artifact, for the code cache.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) fn artifact(module: &Module, config: &RuntimeConfig) -> Result<Artifact> {
+    artifact_only(module, config, &vec![true; module.functions.len()], false)
+}
+
+/// Translate the functions `include` selects, once the host is known to
+/// be the target and have the CPU features `config` names.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn artifact_only(
+    module: &Module,
+    config: &RuntimeConfig,
+    include: &[bool],
+    counted: bool,
+) -> Result<Artifact> {
+    super::codegen::check_host(config.compiler())?;
+    Ok(x64::compile(module, config, include, counted))
+}
+
+/// What besides the module and configuration decides the code
+/// [`artifact`] emits on this host.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) fn target(config: &RuntimeConfig) -> String {
+    format!("x86_64-linux popcnt={}", x64::uses_popcnt(config))
+}
@@ -0,0 +1,1777 @@
+//! Baseline templates for x86-64, System V calling convention.
+//!
+//! Every function takes the [`Entry`] arguments directly, so compiled
+//! functions call each other the way the runtime calls them. The frame,
+//! below the saved `rbp`, `rbx` and `r12`:
+//!
+//! ```text
+//! rbp - 24           call depth, saved across a call
+//! rbp - 32 - 8 * i   local i, then operand stack slot i - locals, then
+//!                    the result of each op hoisted out of a loop
+//! rsp + 8 + 8 * i    argument i of an outgoing call
+//! rsp                result of an outgoing call
+//! ```
+//!
+//! `rbx` holds the context and `r12` the result pointer. Values stay in
+//! their slots between ops, 32-bit ones in the low half, so a template may
+//! use any other register.
+
+use std::arch::is_x86_feature_detected;
+
+use crate::{
+    compiler::{
+        artifact::{Artifact, Reloc, RelocKind, Target},
+        debug_info::Symbol,
+        fuel_runs,
+        native::{Helper, TrapCode, VmCtx, Window},
+        ssa::{self, Body, VReg},
+        verified,
+    },
+    config::{AddressOverflow, BoundsChecks, RuntimeConfig},
+    instance::{prepare_func, PreparedFunc},
+    ir::{BlockType, Op},
+    memory::Poison,
+    module::Module,
+    types::{FuncType, ValType},
+};
+
+const RAX: u8 = 0;
+const RCX: u8 = 1;
+const RDX: u8 = 2;
+const RBX: u8 = 3;
+const RSP: u8 = 4;
+const RBP: u8 = 5;
+const RSI: u8 = 6;
+const RDI: u8 = 7;
+const R8: u8 = 8;
+const R12: u8 = 12;
+
+/// Condition codes, as in `jcc`, `setcc` and `cmovcc`.
+const B: u8 = 0x2;
+const AE: u8 = 0x3;
+const E: u8 = 0x4;
+const NE: u8 = 0x5;
+const BE: u8 = 0x6;
+const A: u8 = 0x7;
+const P: u8 = 0xA;
+const NP: u8 = 0xB;
+const L: u8 = 0xC;
+const GE: u8 = 0xD;
+const LE: u8 = 0xE;
+const G: u8 = 0xF;
+
+/// Registers helpers take their arguments in, after the context in `rdi`.
+const ARG_REGS: [u8; 4] = [RSI, RDX, RCX, R8];
+
+const DEPTH_SLOT: i32 = -24;
+
+/// Whether the code uses `popcnt`, without which it differs: if the host
+/// has it, unless `config` lists CPU features without it.
+pub(super) fn uses_popcnt(config: &RuntimeConfig) -> bool {
+    match &config.compiler().cpu_features {
+        Some(features) => features.iter().any(|f| f == "popcnt"),
+        None => is_x86_feature_detected!("popcnt"),
+    }
+}
+
+/// Translate the functions `include` selects; the rest stay interpreted.
+pub(super) fn compile(
+    module: &Module,
+    config: &RuntimeConfig,
+    include: &[bool],
+    counted: bool,
+) -> Artifact {
+    let prepared: Vec<PreparedFunc> = module
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(i, f)| prepare_func(i, f))
+        .collect();
+    // Calls to functions that stay interpreted go through a helper, so if
+    // any fail, translate the rest again knowing which.
+    let mut env = Env {
+        module,
+        config,
+        compiled: verified(module, config, include),
+        counted,
+        popcnt: uses_popcnt(config),
+    };
+    let mut out = translate_all(&env, &prepared);
+    if env
+        .compiled
+        .iter()
+        .zip(&out.starts)
+        .any(|(&ok, start)| ok && start.is_none())
+    {
+        for (ok, start) in env.compiled.iter_mut().zip(&out.starts) {
+            *ok = start.is_some();
+        }
+        out = translate_all(&env, &prepared);
+    }
+    for &(at, callee) in &out.calls {
+        let target = out.starts[callee as usize].expect("direct calls go to compiled functions");
+        out.asm.patch(at, target);
+    }
+
+    Artifact {
+        code: out.asm.code,
+        entries: out.starts.iter().map(|s| s.map(|s| s as u32)).collect(),
+        relocs: out.asm.relocs,
+        faults: out.asm.faults,
+        symbols: out.symbols,
+    }
+}
+
+/// Machine code for a whole module.
+struct Output {
+    asm: Asm,
+    /// Offset of each compiled function.
+    starts: Vec<Option<usize>>,
+    /// Direct calls to patch: offset of the `rel32` and the callee.
+    calls: Vec<(usize, u32)>,
+    /// Each compiled function's code, under debug info.
+    symbols: Vec<Symbol>,
+}
+
+fn translate_all(env: &Env<'_>, prepared: &[PreparedFunc]) -> Output {
+    let mut out = Output {
+        asm: Asm::default(),
+        starts: Vec::with_capacity(prepared.len()),
+        calls: Vec::new(),
+        symbols: Vec::new(),
+    };
+    for pf in prepared {
+        if !env.compiled[pf.index as usize] {
+            out.starts.push(None);
+            continue;
+        }
+        while !out.asm.pos().is_multiple_of(16) {
+            out.asm.byte(0xCC);
+        }
+        let start = out.asm.pos();
+        let (relocs, faults) = (out.asm.relocs.len(), out.asm.faults.len());
+        let translated = ssa::lower(env.module, env.config, pf)
+            .ok_or(Unsupported)
+            .and_then(|body| Translator::new(env, &mut out.asm, pf, body).translate());
+        match translated {
+            Ok(mut calls) => {
+                out.starts.push(Some(start));
+                out.calls.append(&mut calls);
+                if env.config.compiler().debug_info {
+                    let ops = std::mem::take(&mut out.asm.ops);
+                    out.symbols.push(Symbol {
+                        func: pf.index,
+                        start: start as u32,
+                        len: (out.asm.pos() - start) as u32,
+                        ops: ops
+                            .into_iter()
+                            .map(|(at, op)| (at - start as u32, op))
+                            .collect(),
+                    });
+                }
+            }
+            Err(Unsupported) => {
+                out.asm.code.truncate(start);
+                out.asm.relocs.truncate(relocs);
+                out.asm.faults.truncate(faults);
+                out.asm.ops.clear();
+                out.starts.push(None);
+            }
+        }
+    }
+    out
+}
+
+/// An x86-64 instruction encoder, just wide enough for the templates.
+/// Memory operands always take a 32-bit displacement.
+#[derive(Default)]
+struct Asm {
+    code: Vec<u8>,
+    /// Addresses to patch in once the code is loaded.
+    relocs: Vec<Reloc>,
+    /// Accesses left to guard pages, and where each goes if it faults.
+    faults: Vec<(u32, u32)>,
+    /// Where the code of each op of the function being translated starts,
+    /// under debug info.
+    ops: Vec<(u32, u32)>,
+}
+
+impl Asm {
+    fn pos(&self) -> usize {
+        self.code.len()
+    }
+
+    /// Note that the code of op `i` starts here, replacing an op before it
+    /// that emitted nothing.
+    fn mark_op(&mut self, i: usize) {
+        let at = self.pos() as u32;
+        match self.ops.last_mut() {
+            Some(last) if last.0 == at => last.1 = i as u32,
+            _ => self.ops.push((at, i as u32)),
+        }
+    }
+
+    fn byte(&mut self, b: u8) {
+        self.code.push(b);
+    }
+
+    fn bytes(&mut self, b: &[u8]) {
+        self.code.extend_from_slice(b);
+    }
+
+    fn dword(&mut self, v: i32) {
+        self.bytes(&v.to_le_bytes());
+    }
+
+    fn rex(&mut self, w: bool, reg: u8, index: u8, base: u8) {
+        let rex = 0x40 | u8::from(w) << 3 | (reg >> 3) << 2 | (index >> 3) << 1 | base >> 3;
+        if rex != 0x40 {
+            self.byte(rex);
+        }
+    }
+
+    /// `op reg, [base + disp]`; `reg` is the opcode extension of `/n`
+    /// forms.
+    fn mem(&mut self, prefix: &[u8], w: bool, op: &[u8], reg: u8, base: u8, disp: i32) {
+        self.bytes(prefix);
+        self.rex(w, reg, 0, base);
+        self.bytes(op);
+        self.byte(0x80 | (reg & 7) << 3 | (base & 7));
+        if base & 7 == RSP {
+            self.byte(0x24);
+        }
+        self.dword(disp);
+    }
+
+    /// `op reg, [base + index]`, for a `base` other than `rbp` or `r13`.
+    fn mem_index(&mut self, w: bool, op: &[u8], reg: u8, base: u8, index: u8) {
+        debug_assert_ne!(base & 7, RBP);
+        self.rex(w, reg, index, base);
+        self.bytes(op);
+        self.byte(0x04 | (reg & 7) << 3);
+        self.byte((index & 7) << 3 | (base & 7));
+    }
+
+    /// `op reg, rm` between registers.
+    fn rr(&mut self, prefix: &[u8], w: bool, op: &[u8], reg: u8, rm: u8) {
+        self.bytes(prefix);
+        self.rex(w, reg, 0, rm);
+        self.bytes(op);
+        self.byte(0xC0 | (reg & 7) << 3 | (rm & 7));
+    }
+
+    fn load(&mut self, w: bool, reg: u8, base: u8, disp: i32) {
+        self.mem(&[], w, &[0x8B], reg, base, disp);
+    }
+
+    fn store(&mut self, w: bool, reg: u8, base: u8, disp: i32) {
+        self.mem(&[], w, &[0x89], reg, base, disp);
+    }
+
+    fn store_imm(&mut self, w: bool, base: u8, disp: i32, imm: i32) {
+        self.mem(&[], w, &[0xC7], 0, base, disp);
+        self.dword(imm);
+    }
+
+    fn cmp_imm(&mut self, w: bool, base: u8, disp: i32, imm: i32) {
+        self.mem(&[], w, &[0x81], 7, base, disp);
+        self.dword(imm);
+    }
+
+    /// A group-1 op on a register: `ext` 0 adds, 4 ands, 5 subtracts, 6
+    /// xors and 7 compares.
+    fn alu_imm(&mut self, w: bool, ext: u8, reg: u8, imm: i32) {
+        self.rr(&[], w, &[0x81], ext, reg);
+        self.dword(imm);
+    }
+
+    fn mov(&mut self, w: bool, dst: u8, src: u8) {
+        self.rr(&[], w, &[0x8B], dst, src);
+    }
+
+    /// Load `imm`, zero-extending it if it fits in 32 bits.
+    fn mov_imm(&mut self, reg: u8, imm: u64) {
+        match u32::try_from(imm) {
+            Ok(imm) => {
+                self.rex(false, 0, 0, reg);
+                self.byte(0xB8 | (reg & 7));
+                self.bytes(&imm.to_le_bytes());
+            }
+            Err(_) => {
+                self.rex(true, 0, 0, reg);
+                self.byte(0xB8 | (reg & 7));
+                self.bytes(&imm.to_le_bytes());
+            }
+        }
+    }
+
+    fn lea(&mut self, dst: u8, base: u8, disp: i32) {
+        self.mem(&[], true, &[0x8D], dst, base, disp);
+    }
+
+    fn test(&mut self, w: bool, a: u8, b: u8) {
+        self.rr(&[], w, &[0x85], b, a);
+    }
+
+    fn shift_imm(&mut self, w: bool, ext: u8, reg: u8, by: u8) {
+        self.rr(&[], w, &[0xC1], ext, reg);
+        self.byte(by);
+    }
+
+    /// `eax = cc ? 1 : 0`, from the flags.
+    fn set_flag(&mut self, cc: u8) {
+        self.rr(&[], false, &[0x0F, 0x90 | cc], 0, RAX);
+        self.rr(&[], false, &[0x0F, 0xB6], RAX, RAX);
+    }
+
+    /// Load the address of `target`, patched in at load time.
+    fn mov_addr(&mut self, reg: u8, target: Target) {
+        self.rex(true, 0, 0, reg);
+        self.byte(0xB8 | (reg & 7));
+        self.relocs.push(Reloc {
+            at: self.pos() as u32,
+            kind: RelocKind::Abs8,
+            target,
+            addend: 0,
+        });
+        self.bytes(&[0; 8]);
+    }
+
+    fn call_abs(&mut self, target: Target) {
+        self.mov_addr(RAX, target);
+        self.rr(&[], false, &[0xFF], 2, RAX);
+    }
+
+    /// Emit a jump or call `op` with a zero `rel32`, returning its offset.
+    fn jump(&mut self, op: &[u8]) -> usize {
+        self.bytes(op);
+        let at = self.pos();
+        self.dword(0);
+        at
+    }
+
+    fn patch(&mut self, at: usize, target: usize) {
+        let rel = target as i64 - (at as i64 + 4);
+        self.code[at..at + 4].copy_from_slice(&(rel as i32).to_le_bytes());
+    }
+}
+
+/// What translating every function of a module shares.
+struct Env<'a> {
+    module: &'a Module,
+    config: &'a RuntimeConfig,
+    /// Which functions are compiled; calls to the others use a helper.
+    compiled: Vec<bool>,
+    /// Whether functions bump their call counter on entry.
+    counted: bool,
+    popcnt: bool,
+}
+
+/// The function cannot be compiled and stays interpreted.
+struct Unsupported;
+
+type Translated<T = ()> = std::result::Result<T, Unsupported>;
+
+type Label = usize;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Block,
+    Loop,
+    If,
+}
+
+/// A block, loop or if being translated.
+struct Region {
+    kind: Kind,
+    /// After the `End`.
+    end: Label,
+    /// Whether anything jumps to `end` or falls through to it.
+    reached: bool,
+    /// Where branches to a loop go.
+    header: Option<Label>,
+    /// The else branch of an if, until translation reaches it.
+    else_label: Option<Label>,
+    /// Index of the `End` op.
+    end_op: usize,
+}
+
+enum Frame {
+    Live(Region),
+    /// Opened in unreachable code: only its `End` matters.
+    Dead,
+}
+
+/// A helper argument.
+#[derive(Clone, Copy)]
+enum Arg {
+    Imm(u64),
+    /// All 64 bits of the slot at this displacement from `rbp`.
+    Slot(i32),
+    Reg(u8),
+    /// Where an outgoing call's result goes.
+    Ret,
+    /// Where an outgoing call's arguments are.
+    Args,
+}
+
+struct Translator<'a> {
+    env: &'a Env<'a>,
+    a: &'a mut Asm,
+    pf: &'a PreparedFunc,
+    body: Body,
+    runs: Vec<u32>,
+    locals: Vec<ValType>,
+    /// The op being translated, and how many of its uses it has popped.
+    at: usize,
+    popped: usize,
+    max_args: usize,
+    frames: Vec<Frame>,
+    reachable: bool,
+    labels: Vec<Option<usize>>,
+    /// Jumps to patch: offset of the `rel32` and its target.
+    fixups: Vec<(usize, Label)>,
+    /// Trap exits: label, code and op index.
+    stubs: Vec<(Label, TrapCode, usize)>,
+    /// Out-of-fuel exits, with the run's first op; the fuel left is in
+    /// `rax`.
+    fuel_stubs: Vec<(Label, usize)>,
+    /// Accesses left to guard pages: offset, and the slow path to resume
+    /// at if it faults.
+    faults: Vec<(usize, Label)>,
+    calls: Vec<(usize, u32)>,
+    epilogue: Label,
+    /// Offset of the frame size in the prologue.
+    frame_size_at: usize,
+}
+
+impl<'a> Translator<'a> {
+    fn new(env: &'a Env<'a>, a: &'a mut Asm, pf: &'a PreparedFunc, body: Body) -> Self {
+        let ty = &env.module.functions[pf.index as usize].ty;
+        let max_args = pf
+            .ops
+            .iter()
+            .filter_map(|op| match op {
+                Op::Call(f) => env
+                    .module
+                    .functions
+                    .get(*f as usize)
+                    .map(|f| f.ty.params.len()),
+                Op::CallHost(i) => env
+                    .module
+                    .imports
+                    .get(*i as usize)
+                    .map(|i| i.ty.params.len()),
+                Op::Ext { opcode, .. } => env.config.extension(*opcode).map(|e| e.ty.params.len()),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+        Translator {
+            env,
+            a,
+            pf,
+            body,
+            runs: fuel_runs(&pf.ops),
+            locals: ty.params.iter().chain(&pf.extra_locals).copied().collect(),
+            at: 0,
+            popped: 0,
+            max_args,
+            frames: Vec::new(),
+            reachable: true,
+            labels: vec![None],
+            fixups: Vec::new(),
+            stubs: Vec::new(),
+            fuel_stubs: Vec::new(),
+            faults: Vec::new(),
+            calls: Vec::new(),
+            epilogue: 0,
+            frame_size_at: 0,
+        }
+    }
+
+    /// Translate the function, returning the direct calls it makes.
+    fn translate(mut self) -> Translated<Vec<(usize, u32)>> {
+        self.prologue();
+        let pf = self.pf;
+        let debug_info = self.env.config.compiler().debug_info;
+        let mut finished = false;
+        for (i, op) in pf.ops.iter().enumerate() {
+            (self.at, self.popped) = (i, 0);
+            if debug_info {
+                self.a.mark_op(i);
+            }
+            let done = if self.reachable {
+                // A loop charges its own run at the header branches return to.
+                if self.runs[i] > 0 && !matches!(op, Op::Loop(_)) {
+                    self.charge(self.runs[i], i);
+                }
+                match self.body.hoisted(i) {
+                    Some(hoist) => {
+                        self.reuse(hoist.root);
+                        false
+                    }
+                    None => self.op(i, op)?,
+                }
+            } else {
+                self.skip(op)?
+            };
+            if done {
+                finished = true;
+                break;
+            }
+        }
+        if !finished {
+            // Falling off the end returns, like the final `End`.
+            if !self.frames.is_empty() {
+                return Err(Unsupported);
+            }
+            (self.at, self.popped) = (pf.ops.len(), 0);
+            if self.reachable {
+                self.ret()?;
+            }
+        }
+        self.finish();
+        Ok(self.calls)
+    }
+
+    fn prologue(&mut self) {
+        self.a.byte(0x55); // push rbp
+        self.a.mov(true, RBP, RSP);
+        self.a.byte(0x53); // push rbx
+        self.a.bytes(&[0x41, 0x54]); // push r12
+        self.frame_size_at = self.a.jump(&[0x48, 0x81, 0xEC]); // sub rsp, imm32
+        self.a.mov(true, RBX, RDI);
+        self.a.mov(true, R12, RDX);
+        if self.env.counted {
+            self.a.mov_addr(RAX, Target::Counter(self.pf.index));
+            self.a.mem(&[0xF0], true, &[0x83], 0, RAX, 0); // lock add qword [rax], 1
+            self.a.byte(1);
+        }
+        let params = self.pf.n_params;
+        for k in 0..params {
+            self.a.load(true, RAX, RSI, 8 * k as i32);
+            self.a.store(true, RAX, RBP, local(k));
+        }
+        if self.locals.len() > params {
+            self.a.rr(&[], false, &[0x33], RAX, RAX); // xor eax, eax
+            for k in params..self.locals.len() {
+                self.a.store(true, RAX, RBP, local(k));
+            }
+        }
+    }
+
+    /// Emit the epilogue and trap exits and resolve jumps.
+    fn finish(&mut self) {
+        let epilogue = self.epilogue;
+        self.bind(epilogue);
+        self.a.lea(RSP, RBP, -16);
+        self.a.bytes(&[0x41, 0x5C, 0x5B, 0x5D, 0xC3]); // pop r12; pop rbx; pop rbp; ret
+
+        let tail = self.label();
+        for (label, start) in std::mem::take(&mut self.fuel_stubs) {
+            self.bind(label);
+            self.a.store_imm(true, RBX, VmCtx::FUEL, 0);
+            self.a.mov(false, RCX, RAX);
+            self.a.alu_imm(false, 0, RCX, start as i32);
+            self.a.mov_imm(RSI, TrapCode::OutOfFuel as u64);
+            self.jmp(tail);
+        }
+        for (label, code, op) in std::mem::take(&mut self.stubs) {
+            self.bind(label);
+            self.a.mov_imm(RSI, code as u64);
+            self.a.mov_imm(RCX, op as u64);
+            self.jmp(tail);
+        }
+        self.bind(tail);
+        self.a.mov_imm(RDX, self.pf.index as u64);
+        self.a.mov(true, RDI, RBX);
+        self.a.call_abs(Target::Helper(Helper::Trap));
+        self.jmp(epilogue);
+
+        for &(at, label) in &self.fixups {
+            let target = self.labels[label].expect("jump targets are bound");
+            self.a.patch(at, target);
+        }
+        for &(at, label) in &self.faults {
+            let target = self.labels[label].expect("slow paths are bound");
+            self.a.faults.push((at as u32, target as u32));
+        }
+        let slots = 2 + self.locals.len() + self.body.max_height + self.body.roots + self.max_args;
+        let size = (8 * slots as i32 + 15) & !15;
+        let at = self.frame_size_at;
+        self.a.code[at..at + 4].copy_from_slice(&size.to_le_bytes());
+    }
+
+    /// Translate reachable op `i`; true once the function's final `End` is
+    /// reached.
+    fn op(&mut self, i: usize, op: &Op) -> Translated<bool> {
+        use ValType::{F32, F64, I32, I64};
+        match op {
+            // ── Constants ────────────────────────────────────────────────────
+            Op::I32Const(v) => {
+                let d = self.push(I32);
+                self.a.store_imm(false, RBP, d, *v);
+            }
+            Op::I64Const(v) => {
+                self.a.mov_imm(RAX, *v as u64);
+                let d = self.push(I64);
+                self.a.store(true, RAX, RBP, d);
+            }
+            Op::F32Const(v) => {
+                let d = self.push(F32);
+                self.a.store_imm(false, RBP, d, v.to_bits() as i32);
+            }
+            Op::F64Const(v) => {
+                self.a.mov_imm(RAX, v.to_bits());
+                let d = self.push(F64);
+                self.a.store(true, RAX, RBP, d);
+            }
+
+            // ── Stack and locals ─────────────────────────────────────────────
+            Op::Drop => {
+                self.pop()?;
+            }
+            Op::Select => {
+                let c = self.pop_ty(I32)?;
+                let (b, ty) = self.pop()?;
+                let a = self.pop_ty(ty)?;
+                self.a.load(false, RCX, RBP, c);
+                self.a.load(true, RAX, RBP, a);
+                self.a.test(false, RCX, RCX);
+                self.a.mem(&[], true, &[0x0F, 0x40 | E], RAX, RBP, b); // cmovz
+                let d = self.push(ty);
+                self.a.store(true, RAX, RBP, d);
+            }
+            Op::LocalGet(l) => {
+                let ty = self.local_type(*l)?;
+                self.a.load(true, RAX, RBP, local(*l as usize));
+                let d = self.push(ty);
+                self.a.store(true, RAX, RBP, d);
+            }
+            Op::LocalSet(l) | Op::LocalTee(l) => {
+                let ty = self.local_type(*l)?;
+                let d = self.pop_ty(ty)?;
+                self.a.load(true, RAX, RBP, d);
+                self.a.store(true, RAX, RBP, local(*l as usize));
+                if matches!(op, Op::LocalTee(_)) {
+                    self.push(ty);
+                }
+            }
+            Op::GlobalGet(g) => {
+                let ty = self.global_type(*g)?;
+                self.call_helper(Helper::GlobalGet, &[Arg::Imm(*g as u64), Arg::Ret]);
+                self.check_status(i);
+                self.a.load(true, RAX, RSP, 0);
+                let d = self.push(ty);
+                self.a.store(true, RAX, RBP, d);
+            }
+            Op::GlobalSet(g) => {
+                let ty = self.global_type(*g)?;
+                let d = self.pop_ty(ty)?;
+                self.call_helper(Helper::GlobalSet, &[Arg::Imm(*g as u64), Arg::Slot(d)]);
+                self.check_status(i);
+            }
+            Op::Nop => {}
+            Op::Unreachable => self.trap(TrapCode::Unreachable, i),
+
+            // ── Memory ───────────────────────────────────────────────────────
+            Op::I32Load {
+                align,
+                offset,
+                memory,
+            } => self.load(i, I32, *align, *offset, *memory)?,
+            Op::I64Load {
+                align,
+                offset,
+                memory,
+            } => self.load(i, I64, *align, *offset, *memory)?,
+            Op::F32Load {
+                align,
+                offset,
+                memory,
+            } => self.load(i, F32, *align, *offset, *memory)?,
+            Op::F64Load {
+                align,
+                offset,
+                memory,
+            } => self.load(i, F64, *align, *offset, *memory)?,
+            Op::I32Store {
+                align,
+                offset,
+                memory,
+            } => self.store(i, I32, *align, *offset, *memory)?,
+            Op::I64Store {
+                align,
+                offset,
+                memory,
+            } => self.store(i, I64, *align, *offset, *memory)?,
+            Op::F32Store {
+                align,
+                offset,
+                memory,
+            } => self.store(i, F32, *align, *offset, *memory)?,
+            Op::F64Store {
+                align,
+                offset,
+                memory,
+            } => self.store(i, F64, *align, *offset, *memory)?,
+            Op::MemorySize => {
+                self.a.load(true, RCX, RBX, VmCtx::WINDOWS);
+                self.a.load(true, RAX, RCX, Window::SIZE);
+                self.a.shift_imm(true, 5, RAX, 16);
+                let d = self.push(I32);
+                self.a.store(false, RAX, RBP, d);
+            }
+            Op::MemoryGrow => {
+                let delta = self.pop_ty(I32)?;
+                self.call_helper(Helper::MemoryGrow, &[Arg::Slot(delta), Arg::Ret]);
+                self.check_status(i);
+                self.a.load(false, RAX, RSP, 0);
+                let d = self.push(I32);
+                self.a.store(false, RAX, RBP, d);
+            }
+            Op::MemoryDiscard => {
+                let len = self.pop_ty(I32)?;
+                let addr = self.pop_ty(I32)?;
+                self.call_helper(Helper::MemoryDiscard, &[Arg::Slot(addr), Arg::Slot(len)]);
+                self.check_status(i);
+            }
+
+            // ── Integer arithmetic ───────────────────────────────────────────
+            Op::I32Add => self.binary(I32, &[0x03])?,
+            Op::I32Sub => self.binary(I32, &[0x2B])?,
+            Op::I32Mul => self.binary(I32, &[0x0F, 0xAF])?,
+            Op::I32And => self.binary(I32, &[0x23])?,
+            Op::I32Or => self.binary(I32, &[0x0B])?,
+            Op::I32Xor => self.binary(I32, &[0x33])?,
+            Op::I64Add => self.binary(I64, &[0x03])?,
+            Op::I64Sub => self.binary(I64, &[0x2B])?,
+            Op::I64Mul => self.binary(I64, &[0x0F, 0xAF])?,
+            Op::I64And => self.binary(I64, &[0x23])?,
+            Op::I64Or => self.binary(I64, &[0x0B])?,
+            Op::I64Xor => self.binary(I64, &[0x33])?,
+            // x86 takes shift counts modulo the width, as the interpreter's
+            // wrapping shifts do.
+            Op::I32Shl => self.shift(I32, 4)?,
+            Op::I32ShrU => self.shift(I32, 5)?,
+            Op::I32ShrS => self.shift(I32, 7)?,
+            Op::I64Shl => self.shift(I64, 4)?,
+            Op::I64ShrU => self.shift(I64, 5)?,
+            Op::I64ShrS => self.shift(I64, 7)?,
+            Op::I32DivS => {
+                let (a, b) = self.divide(i, I32)?;
+                // i32::MIN / -1 overflows, which the interpreter reports as
+                // unreachable.
+                let ok = self.label();
+                self.a.cmp_imm(false, RBP, a, i32::MIN);
+                self.jcc(NE, ok);
+                self.a.cmp_imm(false, RBP, b, -1);
+                self.trap_if(E, TrapCode::Unreachable, i);
+                self.bind(ok);
+                self.a.load(false, RAX, RBP, a);
+                self.a.byte(0x99); // cdq
+                self.a.mem(&[], false, &[0xF7], 7, RBP, b); // idiv
+                self.a.store(false, RAX, RBP, a);
+                self.push(I32);
+            }
+            Op::I64DivS => {
+                let (a, b) = self.divide(i, I64)?;
+                // Wrapping: i64::MIN / -1 is i64::MIN.
+                let (divide, done) = (self.label(), self.label());
+                self.a.cmp_imm(true, RBP, b, -1);
+                self.jcc(NE, divide);
+                self.a.load(true, RAX, RBP, a);
+                self.a.rr(&[], true, &[0xF7], 3, RAX); // neg
+                self.jmp(done);
+                self.bind(divide);
+                self.a.load(true, RAX, RBP, a);
+                self.a.bytes(&[0x48, 0x99]); // cqo
+                self.a.mem(&[], true, &[0xF7], 7, RBP, b); // idiv
+                self.bind(done);
+                self.a.store(true, RAX, RBP, a);
+                self.push(I64);
+            }
+            Op::I32RemS | Op::I64RemS => {
+                let ty = if matches!(op, Op::I32RemS) { I32 } else { I64 };
+                let w = ty == I64;
+                let (a, b) = self.divide(i, ty)?;
+                // Wrapping: anything rem -1 is 0, including MIN.
+                let (divide, done) = (self.label(), self.label());
+                self.a.cmp_imm(w, RBP, b, -1);
+                self.jcc(NE, divide);
+                self.a.rr(&[], false, &[0x33], RAX, RAX); // xor eax, eax
+                self.jmp(done);
+                self.bind(divide);
+                self.a.load(w, RAX, RBP, a);
+                if w {
+                    self.a.byte(0x48);
+                }
+                self.a.byte(0x99); // cdq or cqo
+                self.a.mem(&[], w, &[0xF7], 7, RBP, b); // idiv
+                self.a.mov(true, RAX, RDX);
+                self.bind(done);
+                self.a.store(w, RAX, RBP, a);
+                self.push(ty);
+            }
+            Op::I32DivU | Op::I64DivU | Op::I32RemU | Op::I64RemU => {
+                let ty = if matches!(op, Op::I32DivU | Op::I32RemU) {
+                    I32
+                } else {
+                    I64
+                };
+                let w = ty == I64;
+                let (a, b) = self.divide(i, ty)?;
+                self.a.load(w, RAX, RBP, a);
+                self.a.rr(&[], false, &[0x33], RDX, RDX); // xor edx, edx
+                self.a.mem(&[], w, &[0xF7], 6, RBP, b); // div
+                let result = if matches!(op, Op::I32DivU | Op::I64DivU) {
+                    RAX
+                } else {
+                    RDX
+                };
+                self.a.store(w, result, RBP, a);
+                self.push(ty);
+            }
+            Op::I32Clz => {
+                // bsr leaves ZF set for zero, whose count is 32.
+                let a = self.pop_ty(I32)?;
+                self.a.mem(&[], false, &[0x0F, 0xBD], RAX, RBP, a); // bsr
+                self.a.mov_imm(RCX, u32::MAX as u64);
+                self.a.rr(&[], false, &[0x0F, 0x40 | E], RAX, RCX); // cmovz
+                self.a.rr(&[], false, &[0xF7], 3, RAX); // neg
+                self.a.alu_imm(false, 0, RAX, 31);
+                let d = self.push(I32);
+                self.a.store(false, RAX, RBP, d);
+            }
+            Op::I32Ctz => {
+                let a = self.pop_ty(I32)?;
+                self.a.mem(&[], false, &[0x0F, 0xBC], RAX, RBP, a); // bsf
+                self.a.mov_imm(RCX, 32);
+                self.a.rr(&[], false, &[0x0F, 0x40 | E], RAX, RCX); // cmovz
+                let d = self.push(I32);
+                self.a.store(false, RAX, RBP, d);
+            }
+            Op::I32Popcnt => {
+                if !self.env.popcnt {
+                    return Err(Unsupported);
+                }
+                let a = self.pop_ty(I32)?;
+                self.a.mem(&[0xF3], false, &[0x0F, 0xB8], RAX, RBP, a);
+                let d = self.push(I32);
+                self.a.store(false, RAX, RBP, d);
+            }
+            Op::I32Eqz | Op::I64Eqz => {
+                let ty = if matches!(op, Op::I32Eqz) { I32 } else { I64 };
+                let a = self.pop_ty(ty)?;
+                self.a.cmp_imm(ty == I64, RBP, a, 0);
+                self.a.set_flag(E);
+                let d = self.push(I32);
+                self.a.store(false, RAX, RBP, d);
+            }
+
+            // ── Float arithmetic ─────────────────────────────────────────────
+            Op::F32Add => self.float(F32, 0x58)?,
+            Op::F32Sub => self.float(F32, 0x5C)?,
+            Op::F32Mul => self.float(F32, 0x59)?,
+            Op::F32Div => self.float(F32, 0x5E)?,
+            Op::F64Add => self.float(F64, 0x58)?,
+            Op::F64Sub => self.float(F64, 0x5C)?,
+            Op::F64Mul => self.float(F64, 0x59)?,
+            Op::F64Div => self.float(F64, 0x5E)?,
+            Op::F32Sqrt | Op::F64Sqrt => {
+                let ty = if matches!(op, Op::F32Sqrt) { F32 } else { F64 };
+                let a = self.pop_ty(ty)?;
+                let p = [scalar_prefix(ty)];
+                self.a.mem(&p, false, &[0x0F, 0x51], 0, RBP, a);
+                self.a.mem(&p, false, &[0x0F, 0x11], 0, RBP, a);
+                self.push(ty);
+            }
+            // Sign bit operations, on the bits.
+            Op::F32Abs | Op::F32Neg => {
+                let a = self.pop_ty(F32)?;
+                self.a.load(false, RAX, RBP, a);
+                if matches!(op, Op::F32Abs) {
+                    self.a.alu_imm(false, 4, RAX, i32::MAX);
+                } else {
+                    self.a.alu_imm(false, 6, RAX, i32::MIN);
+                }
+                self.a.store(false, RAX, RBP, a);
+                self.push(F32);
+            }
+            Op::F64Abs | Op::F64Neg => {
+                let a = self.pop_ty(F64)?;
+                self.a.load(true, RAX, RBP, a);
+                if matches!(op, Op::F64Abs) {
+                    self.a.mov_imm(RCX, i64::MAX as u64);
+                    self.a.rr(&[], true, &[0x23], RAX, RCX); // and
+                } else {
+                    self.a.mov_imm(RCX, i64::MIN as u64);
+                    self.a.rr(&[], true, &[0x33], RAX, RCX); // xor
+                }
+                self.a.store(true, RAX, RBP, a);
+                self.push(F64);
+            }
+            // No template: these need SSE4.1 or more than a few instructions
+            // to match Rust's semantics.
+            Op::F32Min
+            | Op::F32Max
+            | Op::F32Ceil
+            | Op::F32Floor
+            | Op::F64Min
+            | Op::F64Max
+            | Op::F64Ceil
+            | Op::F64Floor => return Err(Unsupported),
+
+            // ── Comparisons ──────────────────────────────────────────────────
+            Op::I32Eq => self.compare(I32, E)?,
+            Op::I32Ne => self.compare(I32, NE)?,
+            Op::I32LtS => self.compare(I32, L)?,
+            Op::I32LtU => self.compare(I32, B)?,
+            Op::I32GtS => self.compare(I32, G)?,
+            Op::I32GtU => self.compare(I32, A)?,
+            Op::I32LeS => self.compare(I32, LE)?,
+            Op::I32LeU => self.compare(I32, BE)?,
+            Op::I32GeS => self.compare(I32, GE)?,
+            Op::I32GeU => self.compare(I32, AE)?,
+            Op::I64Eq => self.compare(I64, E)?,
+            Op::I64Ne => self.compare(I64, NE)?,
+            Op::I64LtS => self.compare(I64, L)?,
+            Op::I64LtU => self.compare(I64, B)?,
+            Op::I64GtS => self.compare(I64, G)?,
+            Op::I64GtU => self.compare(I64, A)?,
+            Op::I64LeS => self.compare(I64, LE)?,
+            Op::I64LeU => self.compare(I64, BE)?,
+            Op::I64GeS => self.compare(I64, GE)?,
+            Op::I64GeU => self.compare(I64, AE)?,
+            Op::F32Eq => self.compare_float(F32, FloatCmp::Eq)?,
+            Op::F32Ne => self.compare_float(F32, FloatCmp::Ne)?,
+            Op::F32Lt => self.compare_float(F32, FloatCmp::Lt)?,
+            Op::F32Gt => self.compare_float(F32, FloatCmp::Gt)?,
+            Op::F32Le => self.compare_float(F32, FloatCmp::Le)?,
+            Op::F32Ge => self.compare_float(F32, FloatCmp::Ge)?,
+            Op::F64Eq => self.compare_float(F64, FloatCmp::Eq)?,
+            Op::F64Ne => self.compare_float(F64, FloatCmp::Ne)?,
+            Op::F64Lt => self.compare_float(F64, FloatCmp::Lt)?,
+            Op::F64Gt => self.compare_float(F64, FloatCmp::Gt)?,
+            Op::F64Le => self.compare_float(F64, FloatCmp::Le)?,
+            Op::F64Ge => self.compare_float(F64, FloatCmp::Ge)?,
+
+            // ── Conversions ──────────────────────────────────────────────────
+            // Wrapping and reinterpreting only change the slot's type.
+            Op::I32WrapI64 => self.retype(I64, I32)?,
+            Op::I32ReinterpretF32 => self.retype(F32, I32)?,
+            Op::F32ReinterpretI32 => self.retype(I32, F32)?,
+            Op::I64ReinterpretF64 => self.retype(F64, I64)?,
+            Op::F64ReinterpretI64 => self.retype(I64, F64)?,
+            Op::I64ExtendI32S => {
+                let a = self.pop_ty(I32)?;
+                self.a.mem(&[], true, &[0x63], RAX, RBP, a); // movsxd
+                let d = self.push(I64);
+                self.a.store(true, RAX, RBP, d);
+            }
+            Op::I64ExtendI32U => {
+                let a = self.pop_ty(I32)?;
+                self.a.load(false, RAX, RBP, a);
+                let d = self.push(I64);
+                self.a.store(true, RAX, RBP, d);
+            }
+            Op::F32ConvertI32S => self.convert_int(I32, F32, true)?,
+            Op::F32ConvertI32U => self.convert_int(I32, F32, false)?,
+            Op::F64ConvertI32S => self.convert_int(I32, F64, true)?,
+            Op::F64ConvertI32U => self.convert_int(I32, F64, false)?,
+            Op::F64ConvertI64S => self.convert_int(I64, F64, true)?,
+            Op::F32DemoteF64 | Op::F64PromoteF32 => {
+                let (from, to) = if matches!(op, Op::F32DemoteF64) {
+                    (F64, F32)
+                } else {
+                    (F32, F64)
+                };
+                let a = self.pop_ty(from)?;
+                self.a
+                    .mem(&[scalar_prefix(from)], false, &[0x0F, 0x5A], 0, RBP, a);
+                let d = self.push(to);
+                self.a
+                    .mem(&[scalar_prefix(to)], false, &[0x0F, 0x11], 0, RBP, d);
+            }
+            // No template: saturating truncation and unsigned 64-bit
+            // conversion take more than a few instructions.
+            Op::F64ConvertI64U
+            | Op::I32TruncF32S
+            | Op::I32TruncF32U
+            | Op::I32TruncF64S
+            | Op::I32TruncF64U => return Err(Unsupported),
+
+            // ── Control flow ─────────────────────────────────────────────────
+            Op::Block(_) => self.open(Kind::Block, i),
+            Op::Loop(_) => {
+                self.preheader(i)?;
+                let header = self.label();
+                self.bind(header);
+                // Every entry, first or by branch, runs the `Loop` op.
+                self.charge(1, i);
+                self.check_deadlines(i);
+                self.open(Kind::Loop, i);
+                self.region(0)?.header = Some(header);
+            }
+            Op::If(bt) => {
+                let c = self.pop_ty(I32)?;
+                let result = block_result(bt);
+                let has_else = self.pf.elses[i] != usize::MAX;
+                // Without an else, a false condition leaves no result.
+                if !has_else && result.is_some() {
+                    return Err(Unsupported);
+                }
+                self.open(Kind::If, i);
+                self.a.cmp_imm(false, RBP, c, 0);
+                if has_else {
+                    let otherwise = self.label();
+                    self.jcc(E, otherwise);
+                    self.region(0)?.else_label = Some(otherwise);
+                } else {
+                    // The interpreter jumps to the `End` and runs it.
+                    let then = self.label();
+                    self.jcc(NE, then);
+                    let end_op = self.pf.ends[i];
+                    self.charge(1, end_op);
+                    let end = self.region(0)?.end;
+                    self.jmp(end);
+                    self.region(0)?.reached = true;
+                    self.bind(then);
+                }
+            }
+            Op::Else => {
+                let Some(Frame::Live(region)) = self.frames.last() else {
+                    return Err(Unsupported);
+                };
+                if region.kind != Kind::If || region.else_label.is_none() {
+                    return Err(Unsupported);
+                }
+                let (end, end_op) = (region.end, region.end_op);
+                // The then branch jumps to the `End`, which runs too.
+                self.carry_to(end_op);
+                self.charge(1, end_op);
+                self.jmp(end);
+                self.region(0)?.reached = true;
+                self.enter_else()?;
+            }
+            Op::End => match self.frames.pop() {
+                None => {
+                    self.ret()?;
+                    return Ok(true);
+                }
+                Some(Frame::Live(mut region)) => {
+                    self.carry_to(region.end_op);
+                    region.reached = true;
+                    self.close(region);
+                }
+                Some(Frame::Dead) => return Err(Unsupported),
+            },
+            Op::Br(depth) => {
+                self.branch(i, *depth, None)?;
+                self.reachable = false;
+            }
+            Op::BrIf(depth) => {
+                let c = self.pop_ty(I32)?;
+                self.branch(i, *depth, Some(c))?;
+            }
+            Op::Return => self.ret()?,
+
+            // ── Calls ────────────────────────────────────────────────────────
+            Op::Call(f) => self.call(i, *f)?,
+            Op::CallHost(idx) => {
+                let ty = &self
+                    .env
+                    .module
+                    .imports
+                    .get(*idx as usize)
+                    .ok_or(Unsupported)?
+                    .ty;
+                self.call_indirect(i, Helper::CallHost, &[Arg::Imm(*idx as u64)], ty)?;
+            }
+            Op::Ext { opcode, imm } => {
+                let ty = &self.env.config.extension(*opcode).ok_or(Unsupported)?.ty;
+                let lead = [Arg::Imm(*opcode as u64), Arg::Imm(*imm as u64)];
+                self.call_indirect(i, Helper::CallExt, &lead, ty)?;
+            }
+        }
+        Ok(false)
+    }
+
+    /// Follow op `op` in unreachable code; true at the function's final
+    /// `End`.
+    fn skip(&mut self, op: &Op) -> Translated<bool> {
+        match op {
+            Op::Block(_) | Op::Loop(_) | Op::If(_) => self.frames.push(Frame::Dead),
+            Op::Else => match self.frames.last() {
+                Some(Frame::Dead) => {}
+                Some(Frame::Live(region))
+                    if region.kind == Kind::If && region.else_label.is_some() =>
+                {
+                    self.enter_else()?
+                }
+                _ => return Err(Unsupported),
+            },
+            Op::End => match self.frames.pop() {
+                None => return Ok(true),
+                Some(Frame::Dead) => {}
+                Some(Frame::Live(region)) => self.close(region),
+            },
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    // ── Operands ─────────────────────────────────────────────────────────────
+
+    /// The slot of the current op's result, of type `ty`.
+    fn push(&mut self, ty: ValType) -> i32 {
+        let reg = self
+            .body
+            .at(self.at)
+            .def
+            .expect("the lowering defines a register for every result");
+        debug_assert_eq!(self.body.types[reg as usize], ty);
+        self.reg_slot(reg)
+    }
+
+    /// The slot and type of the current op's next operand, from the top of
+    /// the stack down.
+    fn pop(&mut self) -> Translated<(i32, ValType)> {
+        let uses = &self.body.at(self.at).uses;
+        let index = uses.len().checked_sub(self.popped + 1).ok_or(Unsupported)?;
+        let reg = uses[index];
+        self.popped += 1;
+        Ok((self.reg_slot(reg), self.body.types[reg as usize]))
+    }
+
+    fn pop_ty(&mut self, ty: ValType) -> Translated<i32> {
+        match self.pop()? {
+            (d, t) if t == ty => Ok(d),
+            _ => Err(Unsupported),
+        }
+    }
+
+    /// Pop arguments of types `params`, the last on top.
+    fn pop_args(&mut self, params: &[ValType]) -> Translated<Vec<i32>> {
+        let mut args = params
+            .iter()
+            .rev()
+            .map(|&ty| self.pop_ty(ty))
+            .collect::<Translated<Vec<_>>>()?;
+        args.reverse();
+        Ok(args)
+    }
+
+    /// The slot a hoisted op's result is kept in, by its number among
+    /// those read in their loops.
+    fn root_slot(&self, root: u32) -> i32 {
+        self.slot(self.body.max_height + root as usize)
+    }
+
+    fn reg_slot(&self, reg: VReg) -> i32 {
+        self.slot(self.body.heights[reg as usize] as usize)
+    }
+
+    fn slot(&self, height: usize) -> i32 {
+        local(self.locals.len() + height)
+    }
+
+    fn local_type(&self, l: u32) -> Translated<ValType> {
+        self.locals.get(l as usize).copied().ok_or(Unsupported)
+    }
+
+    fn global_type(&self, global: u32) -> Translated<ValType> {
+        self.env
+            .module
+            .global_imports
+            .get(global as usize)
+            .map(|g| g.ty)
+            .ok_or(Unsupported)
+    }
+
+    fn retype(&mut self, from: ValType, to: ValType) -> Translated {
+        self.pop_ty(from)?;
+        self.push(to);
+        Ok(())
+    }
+
+    /// `a = a op b` through `rax`.
+    fn binary(&mut self, ty: ValType, op: &[u8]) -> Translated {
+        let w = ty == ValType::I64;
+        let b = self.pop_ty(ty)?;
+        let a = self.pop_ty(ty)?;
+        self.a.load(w, RAX, RBP, a);
+        self.a.mem(&[], w, op, RAX, RBP, b);
+        self.a.store(w, RAX, RBP, a);
+        self.push(ty);
+        Ok(())
+    }
+
+    fn shift(&mut self, ty: ValType, ext: u8) -> Translated {
+        let w = ty == ValType::I64;
+        let b = self.pop_ty(ty)?;
+        let a = self.pop_ty(ty)?;
+        self.a.load(false, RCX, RBP, b);
+        self.a.load(w, RAX, RBP, a);
+        self.a.rr(&[], w, &[0xD3], ext, RAX);
+        self.a.store(w, RAX, RBP, a);
+        self.push(ty);
+        Ok(())
+    }
+
+    fn compare(&mut self, ty: ValType, cc: u8) -> Translated {
+        let w = ty == ValType::I64;
+        let b = self.pop_ty(ty)?;
+        let a = self.pop_ty(ty)?;
+        self.a.load(w, RAX, RBP, a);
+        self.a.mem(&[], w, &[0x3B], RAX, RBP, b);
+        self.a.set_flag(cc);
+        let d = self.push(ValType::I32);
+        self.a.store(false, RAX, RBP, d);
+        Ok(())
+    }
+
+    /// Pop a dividend and divisor, trapping if the divisor is zero.
+    fn divide(&mut self, op: usize, ty: ValType) -> Translated<(i32, i32)> {
+        let b = self.pop_ty(ty)?;
+        let a = self.pop_ty(ty)?;
+        self.a.cmp_imm(ty == ValType::I64, RBP, b, 0);
+        self.trap_if(E, TrapCode::DivisionByZero, op);
+        Ok((a, b))
+    }
+
+    /// `a = a op b` through `xmm0`, for a scalar SSE op.
+    fn float(&mut self, ty: ValType, op: u8) -> Translated {
+        let b = self.pop_ty(ty)?;
+        let a = self.pop_ty(ty)?;
+        let p = [scalar_prefix(ty)];
+        self.a.mem(&p, false, &[0x0F, 0x10], 0, RBP, a);
+        self.a.mem(&p, false, &[0x0F, op], 0, RBP, b);
+        self.a.mem(&p, false, &[0x0F, 0x11], 0, RBP, a);
+        self.push(ty);
+        Ok(())
+    }
+
+    fn compare_float(&mut self, ty: ValType, cmp: FloatCmp) -> Translated {
+        let b = self.pop_ty(ty)?;
+        let a = self.pop_ty(ty)?;
+        let p = [scalar_prefix(ty)];
+        self.a.mem(&p, false, &[0x0F, 0x10], 0, RBP, a);
+        self.a.mem(&p, false, &[0x0F, 0x10], 1, RBP, b);
+        // ucomis sets ZF, PF and CF for unordered operands, so "above" is
+        // false on NaN; less-than is greater-than with swapped operands.
+        let ucomis: &[u8] = if ty == ValType::F64 { &[0x66] } else { &[] };
+        let (x, y) = match cmp {
+            FloatCmp::Lt | FloatCmp::Le => (1, 0),
+            _ => (0, 1),
+        };
+        self.a.rr(ucomis, false, &[0x0F, 0x2E], x, y);
+        match cmp {
+            FloatCmp::Eq | FloatCmp::Ne => {
+                let (cc, parity, combine) = match cmp {
+                    FloatCmp::Eq => (E, NP, 0x20), // and
+                    _ => (NE, P, 0x08),            // or
+                };
+                self.a.rr(&[], false, &[0x0F, 0x90 | cc], 0, RAX);
+                self.a.rr(&[], false, &[0x0F, 0x90 | parity], 0, RCX);
+                self.a.rr(&[], false, &[combine], RCX, RAX);
+                self.a.rr(&[], false, &[0x0F, 0xB6], RAX, RAX);
+            }
+            FloatCmp::Gt | FloatCmp::Lt => self.a.set_flag(A),
+            FloatCmp::Ge | FloatCmp::Le => self.a.set_flag(AE),
+        }
+        let d = self.push(ValType::I32);
+        self.a.store(false, RAX, RBP, d);
+        Ok(())
+    }
+
+    /// Convert an integer to a float, `signed` or not. Unsigned 32-bit
+    /// values convert exactly as signed 64-bit ones.
+    fn convert_int(&mut self, from: ValType, to: ValType, signed: bool) -> Translated {
+        let a = self.pop_ty(from)?;
+        let p = [scalar_prefix(to)];
+        if signed {
+            self.a
+                .mem(&p, from == ValType::I64, &[0x0F, 0x2A], 0, RBP, a);
+        } else {
+            self.a.load(false, RAX, RBP, a);
+            self.a.rr(&p, true, &[0x0F, 0x2A], 0, RAX);
+        }
+        let d = self.push(to);
+        self.a.mem(&p, false, &[0x0F, 0x11], 0, RBP, d);
+        Ok(())
+    }
+
+    /// Run the ops hoisted out of the loop at op `start`, keeping the
+    /// results its ops read.
+    fn preheader(&mut self, start: usize) -> Translated {
+        let pf = self.pf;
+        for j in self.body.preheader(start, pf.ends[start]) {
+            (self.at, self.popped) = (j, 0);
+            self.op(j, &pf.ops[j])?;
+            let root = self.body.hoisted(j).and_then(|h| h.root);
+            if let (Some(root), Some(reg)) = (root, self.body.ops[j].def) {
+                self.a.load(true, RAX, RBP, self.reg_slot(reg));
+                self.a.store(true, RAX, RBP, self.root_slot(root));
+            }
+        }
+        (self.at, self.popped) = (start, 0);
+        Ok(())
+    }
+
+    /// Stand in for the current op, hoisted out of its loop: bring back
+    /// its result if the loop reads it.
+    fn reuse(&mut self, root: Option<u32>) {
+        let def = self.body.at(self.at).def;
+        if let (Some(root), Some(reg)) = (root, def) {
+            self.a.load(true, RAX, RBP, self.root_slot(root));
+            self.a.store(true, RAX, RBP, self.reg_slot(reg));
+        }
+    }
+
+    // ── Control ──────────────────────────────────────────────────────────────
+
+    fn label(&mut self) -> Label {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn bind(&mut self, label: Label) {
+        self.labels[label] = Some(self.a.pos());
+    }
+
+    fn jmp(&mut self, label: Label) {
+        let at = self.a.jump(&[0xE9]);
+        self.fixups.push((at, label));
+    }
+
+    fn jcc(&mut self, cc: u8, label: Label) {
+        let at = self.a.jump(&[0x0F, 0x80 | cc]);
+        self.fixups.push((at, label));
+    }
+
+    fn open(&mut self, kind: Kind, op: usize) {
+        let end = self.label();
+        self.frames.push(Frame::Live(Region {
+            kind,
+            end,
+            reached: false,
+            header: None,
+            else_label: None,
+            end_op: self.pf.ends[op],
+        }));
+    }
+
+    /// The region `depth` frames out, which must be live.
+    fn region(&mut self, depth: usize) -> Translated<&mut Region> {
+        let index = self
+            .frames
+            .len()
+            .checked_sub(depth + 1)
+            .ok_or(Unsupported)?;
+        match &mut self.frames[index] {
+            Frame::Live(region) => Ok(region),
+            Frame::Dead => Err(Unsupported),
+        }
+    }
+
+    /// Continue after `region`'s `End`, if anything reaches it.
+    fn close(&mut self, region: Region) {
+        self.bind(region.end);
+        self.reachable = region.reached;
+    }
+
+    /// Start the else branch of the innermost `If`.
+    fn enter_else(&mut self) -> Translated {
+        let region = self.region(0)?;
+        let label = region.else_label.take().ok_or(Unsupported)?;
+        self.bind(label);
+        self.reachable = true;
+        Ok(())
+    }
+
+    /// The slot of the value the current op carries: its use it has not
+    /// popped, if any.
+    fn carried(&self) -> Option<i32> {
+        let uses = &self.body.at(self.at).uses;
+        let reg = *uses[..uses.len() - self.popped].last()?;
+        Some(self.reg_slot(reg))
+    }
+
+    /// Copy the value the current op carries into the result of the block
+    /// whose `End` is op `end_op`.
+    fn carry_to(&mut self, end_op: usize) {
+        let to = self.body.ops[end_op].def.map(|reg| self.reg_slot(reg));
+        if let (Some(from), Some(to)) = (self.carried(), to) {
+            if from != to {
+                self.a.load(true, RAX, RBP, from);
+                self.a.store(true, RAX, RBP, to);
+            }
+        }
+    }
+
+    /// Branch `depth` frames out, if the slot `cond` is nonzero when given.
+    fn branch(&mut self, op: usize, depth: u32, cond: Option<i32>) -> Translated {
+        let depth = depth as usize;
+        if depth >= self.frames.len() {
+            // The interpreter traps on a branch that names no frame.
+            match cond {
+                Some(c) => {
+                    self.a.cmp_imm(false, RBP, c, 0);
+                    self.trap_if(NE, TrapCode::TypeMismatch, op);
+                }
+                None => self.trap(TrapCode::TypeMismatch, op),
+            }
+            return Ok(());
+        }
+        let region = self.region(depth)?;
+        let (target, end_op) = if region.kind == Kind::Loop {
+            (region.header.ok_or(Unsupported)?, None)
+        } else {
+            region.reached = true;
+            (region.end, Some(region.end_op))
+        };
+        let skip = cond.map(|c| {
+            self.a.cmp_imm(false, RBP, c, 0);
+            let skip = self.label();
+            self.jcc(E, skip);
+            skip
+        });
+        // The interpreter carries the top value out.
+        if let Some(end_op) = end_op {
+            self.carry_to(end_op);
+        }
+        self.jmp(target);
+        if let Some(skip) = skip {
+            self.bind(skip);
+        }
+        Ok(())
+    }
+
+    fn ret(&mut self) -> Translated {
+        if let Some(d) = self.carried() {
+            self.a.load(true, RAX, RBP, d);
+            self.a.store(true, RAX, R12, 0);
+        }
+        let epilogue = self.epilogue;
+        self.jmp(epilogue);
+        self.reachable = false;
+        Ok(())
+    }
+
+    // ── Fuel, deadlines and traps ────────────────────────────────────────────
+
+    /// Charge `n` units for the run starting at op `start`.
+    fn charge(&mut self, n: u32, start: usize) {
+        self.a.load(true, RAX, RBX, VmCtx::FUEL);
+        self.a.alu_imm(true, 7, RAX, n as i32);
+        let out = self.label();
+        self.fuel_stubs.push((out, start));
+        self.jcc(B, out);
+        self.a.alu_imm(true, 5, RAX, n as i32);
+        self.a.store(true, RAX, RBX, VmCtx::FUEL);
+    }
+
+    fn check_deadlines(&mut self, op: usize) {
+        let skip = self.label();
+        self.a.cmp_imm(false, RBX, VmCtx::INTERRUPTIBLE, 0);
+        self.jcc(E, skip);
+        self.call_helper(Helper::CheckDeadlines, &[]);
+        self.check_status(op);
+        self.bind(skip);
+    }
+
+    fn trap(&mut self, code: TrapCode, op: usize) {
+        let label = self.label();
+        self.stubs.push((label, code, op));
+        self.jmp(label);
+        self.reachable = false;
+    }
+
+    fn trap_if(&mut self, cc: u8, code: TrapCode, op: usize) {
+        let label = self.label();
+        self.stubs.push((label, code, op));
+        self.jcc(cc, label);
+    }
+
+    /// Trap if the helper just called returned a nonzero status.
+    fn check_status(&mut self, op: usize) {
+        self.a.test(false, RAX, RAX);
+        self.trap_if(NE, TrapCode::Pending, op);
+    }
+
+    // ── Memory ───────────────────────────────────────────────────────────────
+
+    /// Compute the address from the base in slot `base` into `rax`, as the
+    /// interpreter does.
+    fn address(&mut self, op: usize, base: i32, align: u32, offset: u32) {
+        self.a.load(false, RAX, RBP, base);
+        if offset != 0 {
+            match self.env.config.address_overflow() {
+                AddressOverflow::Trap => {
+                    self.a.mov_imm(RCX, offset as u64);
+                    self.a.rr(&[], true, &[0x03], RAX, RCX); // add
+                    self.a.mov(true, RCX, RAX);
+                    self.a.shift_imm(true, 5, RCX, 32);
+                    self.trap_if(NE, TrapCode::OutOfBounds, op);
+                }
+                AddressOverflow::Wrap => self.a.alu_imm(false, 0, RAX, offset as i32),
+            }
+        }
+        if self.env.config.strict_alignment() {
+            let mask = 1u64.checked_shl(align).map_or(u64::MAX, |a| a - 1);
+            if mask != 0 {
+                self.a.mov_imm(RCX, mask);
+                self.a.test(true, RAX, RCX);
+                self.trap_if(NE, TrapCode::UnalignedAccess, op);
+            }
+        }
+    }
+
+    /// Whether accesses try the direct window before the helpers.
+    fn inline_bounds(&self) -> bool {
+        matches!(
+            self.env.config.compiler().bounds_checks,
+            BoundsChecks::Inline | BoundsChecks::Guard
+        )
+    }
+
+    /// Whether accesses go straight to memory, leaving bounds to guard
+    /// pages. Not with poison checked, which every access needs.
+    fn guard_pages(&self) -> bool {
+        self.env.config.guard_pages() && self.env.config.poison() != Poison::Check
+    }
+
+    /// Leave memory `memory`'s guarded base in `rcx`.
+    fn guarded(&mut self, memory: u32) {
+        let window = memory as i32 * Window::STRIDE as i32;
+        self.a.load(true, RCX, RBX, VmCtx::WINDOWS);
+        self.a.load(true, RCX, RCX, window + Window::GUARDED);
+    }
+
+    /// Record that the access about to be emitted faults to `slow`, with
+    /// the address still in `rax`, when it misses.
+    fn may_fault(&mut self, slow: Label) {
+        self.faults.push((self.a.pos(), slow));
+    }
+
+    /// Jump to `slow` unless `size` bytes at `rax` lie in memory `memory`'s
+    /// direct window; otherwise leave its base in `rcx`.
+    fn window(&mut self, memory: u32, size: u32, slow: Label) {
+        let window = memory as i32 * Window::STRIDE as i32;
+        self.a.load(true, RCX, RBX, VmCtx::WINDOWS);
+        self.a.lea(RDX, RAX, size as i32);
+        self.a
+            .mem(&[], true, &[0x3B], RDX, RCX, window + Window::DIRECT); // cmp
+        self.jcc(A, slow);
+        self.a.load(true, RCX, RCX, window + Window::BASE);
+    }
+
+    fn load(&mut self, op: usize, ty: ValType, align: u32, offset: u32, memory: u32) -> Translated {
+        let (w, size) = width(ty);
+        let base = self.pop_ty(ValType::I32)?;
+        self.address(op, base, align, offset);
+        let (slow, done) = (self.label(), self.label());
+        if self.guard_pages() {
+            self.guarded(memory);
+            self.may_fault(slow);
+            self.a.mem_index(w, &[0x8B], RDX, RCX, RAX);
+            self.jmp(done);
+        } else if self.inline_bounds() {
+            self.window(memory, size, slow);
+            self.a.mem_index(w, &[0x8B], RDX, RCX, RAX);
+            self.jmp(done);
+        }
+
+        self.bind(slow);
+        let args = [
+            Arg::Imm(memory as u64),
+            Arg::Reg(RAX),
+            Arg::Imm(size as u64),
+            Arg::Ret,
+        ];
+        self.call_helper(Helper::Load, &args);
+        self.check_status(op);
+        self.a.load(true, RDX, RSP, 0);
+
+        self.bind(done);
+        let d = self.push(ty);
+        self.a.store(w, RDX, RBP, d);
+        Ok(())
+    }
+
+    fn store(
+        &mut self,
+        op: usize,
+        ty: ValType,
+        align: u32,
+        offset: u32,
+        memory: u32,
+    ) -> Translated {
+        let (w, size) = width(ty);
+        let value = self.pop_ty(ty)?;
+        let base = self.pop_ty(ValType::I32)?;
+        self.address(op, base, align, offset);
+        let (slow, done) = (self.label(), self.label());
+        if self.guard_pages() {
+            self.guarded(memory);
+            self.a.load(w, RDX, RBP, value);
+            self.may_fault(slow);
+            self.a.mem_index(w, &[0x89], RDX, RCX, RAX);
+            self.jmp(done);
+        } else if self.inline_bounds() {
+            self.window(memory, size, slow);
+            self.a.load(w, RDX, RBP, value);
+            self.a.mem_index(w, &[0x89], RDX, RCX, RAX);
+            self.jmp(done);
+        }
+
+        self.bind(slow);
+        let args = [
+            Arg::Imm(memory as u64),
+            Arg::Reg(RAX),
+            Arg::Imm(size as u64),
+            Arg::Slot(value),
+        ];
+        self.call_helper(Helper::Store, &args);
+        self.check_status(op);
+
+        self.bind(done);
+        Ok(())
+    }
+
+    // ── Calls ────────────────────────────────────────────────────────────────
+
+    fn call(&mut self, op: usize, func: u32) -> Translated {
+        let ty = &self
+            .env
+            .module
+            .functions
+            .get(func as usize)
+            .ok_or(Unsupported)?
+            .ty;
+        self.check_deadlines(op);
+        let args = self.pop_args(&ty.params)?;
+        self.spill_args(&args);
+
+        self.a.load(true, RAX, RBX, VmCtx::DEPTH);
+        self.a.mem(&[], true, &[0x3B], RAX, RBX, VmCtx::MAX_DEPTH); // cmp
+        self.trap_if(AE, TrapCode::StackOverflow, op);
+        self.a.store(true, RAX, RBP, DEPTH_SLOT);
+        self.a.alu_imm(true, 0, RAX, 1);
+        self.a.store(true, RAX, RBX, VmCtx::DEPTH);
+
+        if self.env.compiled[func as usize] {
+            self.a.mov(true, RDI, RBX);
+            self.a.lea(RSI, RSP, 8);
+            self.a.lea(RDX, RSP, 0);
+            let at = self.a.jump(&[0xE8]);
+            self.calls.push((at, func));
+            self.restore_depth();
+            self.a.cmp_imm(false, RBX, VmCtx::TRAPPED, 0);
+            self.trap_if(NE, TrapCode::Pending, op);
+        } else {
+            let args = [Arg::Imm(func as u64), Arg::Args, Arg::Ret];
+            self.call_helper(Helper::Call, &args);
+            self.restore_depth();
+            self.check_status(op);
+        }
+        self.push_result(ty.results.first().copied());
+        Ok(())
+    }
+
+    fn restore_depth(&mut self) {
+        self.a.load(true, RCX, RBP, DEPTH_SLOT);
+        self.a.store(true, RCX, RBX, VmCtx::DEPTH);
+    }
+
+    /// Call a host function or extension of type `ty` through `helper`,
+    /// which takes `lead` before the argument and result pointers.
+    fn call_indirect(
+        &mut self,
+        op: usize,
+        helper: Helper,
+        lead: &[Arg],
+        ty: &FuncType,
+    ) -> Translated {
+        let args = self.pop_args(&ty.params)?;
+        self.spill_args(&args);
+        let mut helper_args = lead.to_vec();
+        helper_args.extend([Arg::Args, Arg::Ret]);
+        self.call_helper(helper, &helper_args);
+        self.check_status(op);
+        self.push_result(ty.results.first().copied());
+        Ok(())
+    }
+
+    /// Copy the arguments in slots `args` to the outgoing call area.
+    fn spill_args(&mut self, args: &[i32]) {
+        for (i, &d) in args.iter().enumerate() {
+            self.a.load(true, RAX, RBP, d);
+            self.a.store(true, RAX, RSP, 8 + 8 * i as i32);
+        }
+    }
+
+    fn push_result(&mut self, result: Option<ValType>) {
+        if let Some(ty) = result {
+            self.a.load(true, RAX, RSP, 0);
+            let d = self.push(ty);
+            self.a.store(true, RAX, RBP, d);
+        }
+    }
+
+    fn call_helper(&mut self, helper: Helper, args: &[Arg]) {
+        debug_assert_eq!(args.len(), helper.params().len());
+        for (&arg, reg) in args.iter().zip(ARG_REGS) {
+            match arg {
+                Arg::Imm(v) => self.a.mov_imm(reg, v),
+                Arg::Slot(d) => self.a.load(true, reg, RBP, d),
+                Arg::Reg(r) => self.a.mov(true, reg, r),
+                Arg::Ret => self.a.lea(reg, RSP, 0),
+                Arg::Args => self.a.lea(reg, RSP, 8),
+            }
+        }
+        self.a.mov(true, RDI, RBX);
+        self.a.call_abs(Target::Helper(helper));
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum FloatCmp {
+    Eq,
+    Ne,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+}
+
+/// Displacement of local `i` from `rbp`.
+fn local(i: usize) -> i32 {
+    -32 - 8 * i as i32
+}
+
+/// Whether accesses to `ty` are 64-bit, and their size in bytes.
+fn width(ty: ValType) -> (bool, u32) {
+    match ty {
+        ValType::I32 | ValType::F32 => (false, 4),
+        ValType::I64 | ValType::F64 => (true, 8),
+    }
+}
+
+/// The prefix selecting the scalar single or double form of an SSE op.
+fn scalar_prefix(ty: ValType) -> u8 {
+    if ty == ValType::F64 {
+        0xF2
+    } else {
+        0xF3
+    }
+}
+
+fn block_result(bt: &BlockType) -> Option<ValType> {
+    match bt {
+        BlockType::Empty => None,
+        BlockType::Val(ty) => Some(*ty),
+    }
+}
@@ -0,0 +1,109 @@
+//! The on-disk code cache, behind
+//! [`Runtime::enable_code_cache`](crate::runtime::Runtime::enable_code_cache).
+//!
+//! Compiled code is saved as an [`Artifact`](super::artifact::Artifact),
+//! in a file named by a key over everything that decides the machine code:
+//! the module's digest, the backend, the target and its CPU features, the
+//! compiler's version, and the parts of the configuration compiled in. A
+//! later run computing the same key loads the file instead of compiling.
+//! A file that fails to read or validate is compiled over, and a cache
+//! that cannot be written to only costs the compile, so the directory can
+//! be shared, pruned or wiped at any time.
+//!
+//! Only x86-64 Linux hosts cache code, and tiered execution never does:
+//! which functions it compiles depends on the run.
+
+use std::path::PathBuf;
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use std::{
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use super::{artifact::Artifact, baseline, codegen, settings_digest, NativeModule};
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use crate::{
+    config::{RuntimeConfig, Strategy},
+    hash::{to_hex, Sha256},
+    module::Module,
+    trap::Result,
+};
+
+/// A directory of compiled modules.
+pub(crate) struct CodeCache {
+    #[cfg_attr(
+        not(all(target_os = "linux", target_arch = "x86_64")),
+        allow(dead_code)
+    )]
+    dir: PathBuf,
+}
+
+impl CodeCache {
+    pub(crate) fn new(dir: PathBuf) -> Self {
+        CodeCache { dir }
+    }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+impl CodeCache {
+    /// `module`'s code under `config`, loaded from the cache if it is
+    /// there and compiled into it otherwise.
+    pub(crate) fn compile(&self, module: &Module, config: &RuntimeConfig) -> Result<NativeModule> {
+        let baseline = config.strategy() == Strategy::Baseline;
+        let path = self
+            .dir
+            .join(format!("{}.code", to_hex(&key(module, config, baseline)?)));
+        let cached = std::fs::read(&path).ok();
+        if let Some(artifact) = cached.as_deref().and_then(Artifact::from_bytes) {
+            if let Ok(code) = artifact.load(module, None) {
+                return Ok(code);
+            }
+        }
+        let artifact = if baseline {
+            baseline::artifact(module, config)?
+        } else {
+            match codegen::artifact(module, config) {
+                Ok(artifact) => artifact,
+                // Code Cranelift only links in memory is not cached.
+                Err(_) => return codegen::compile(module, config),
+            }
+        };
+        let code = artifact.load(module, None)?;
+        self.store(&path, &artifact.to_bytes());
+        Ok(code)
+    }
+
+    /// Write `bytes` to `path` through a temporary file, so concurrent
+    /// readers see the whole file or none of it. Failures are ignored.
+    fn store(&self, path: &Path, bytes: &[u8]) {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let n = NEXT.fetch_add(1, Ordering::Relaxed);
+        let tmp = path.with_extension(format!("tmp{}-{n}", std::process::id()));
+        if std::fs::write(&tmp, bytes).is_err() || std::fs::rename(&tmp, path).is_err() {
+            let _ = std::fs::remove_file(&tmp);
+        }
+    }
+}
+
+/// Key over everything that decides the machine code the baseline JIT, or
+/// Cranelift, generates for `module` under `config`. Code compiled under
+/// one key only runs under the same.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) fn key(module: &Module, config: &RuntimeConfig, baseline: bool) -> Result<[u8; 32]> {
+    let (strategy, target) = if baseline {
+        (Strategy::Baseline, baseline::target(config))
+    } else {
+        (Strategy::Cranelift, codegen::target(config)?)
+    };
+    let mut h = Sha256::new();
+    h.update(b"rune-code-v1\0");
+    h.update(&module.digest());
+    h.update(format!("{strategy:?}\0").as_bytes());
+    h.update(target.as_bytes());
+    h.update(env!("CARGO_PKG_VERSION").as_bytes());
+    h.update(&super::artifact::FORMAT.to_le_bytes());
+    h.update(&settings_digest(config));
+    h.update(format!("{:?}", config.compiler()).as_bytes());
+    Ok(h.finish())
+}
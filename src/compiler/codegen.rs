@@ -1,9 +1,1925 @@
-//! Code generation stub — to be implemented in Phase 1 Week 3.
+//! Translation of RuneIR to Cranelift IR, compiled for the host as the
+//! runtime's [`CompilerConfig`] directs.
+//!
+//! Each guest function becomes a native function taking the context
+//! pointer and its parameters and returning its result, plus an entry
+//! trampoline the runtime calls it through. Functions are translated from
+//! their [register form](super::ssa): each register becomes an SSA value,
+//! and locals become Cranelift variables.
+//!
+//! The code does what the interpreter does, op for op:
+//!
+//! - Fuel is charged once per run of ops that ends at the first op with an
+//!   effect beyond its result: a memory access, division, call, branch and
+//!   so on. When the fuel left does not cover a run, it traps at the op the
+//!   interpreter would stop at; the ops before it have nothing to undo.
+//! - Deadlines are checked at loops and calls, and the call depth at calls.
+//! - Loads and stores within a memory's window go straight to it. Anything
+//!   else, including every access to memory with page protection or poison
+//!   checking, and every access under [`BoundsChecks::Helper`], goes
+//!   through the same checks as the interpreter's. Bounds are compared
+//!   under [`BoundsChecks::Guard`] too; only the baseline JIT leaves them
+//!   to guard pages.
+//!
+//! A function that fails the [verifier](crate::verify), such as one whose
+//! stack height differs between two paths into a block, or one with an op
+//! that would trap with `Trap::TypeMismatch`, is left to the interpreter.
 
-use crate::{ir::Function, trap::Result};
+use std::collections::HashMap;
+use std::fmt;
 
-/// Placeholder: in the real implementation, emits native code via Cranelift.
-pub fn compile(_func: &Function) -> Result<Vec<u8>> {
-    // TODO: translate IR → Cranelift IR → machine code
-    Ok(Vec::new())
+use cranelift_codegen::{
+    ir::{
+        condcodes::{FloatCC, IntCC},
+        types, AbiParam, AliasRegion, Block, Endianness, FuncRef, Function, InstBuilder, MemFlags,
+        Signature, StackSlot, StackSlotData, StackSlotKind, Type, UserFuncName, Value,
+    },
+    isa::{self, CallConv, OwnedTargetIsa},
+    settings::{self, Configurable},
+    CompiledCode,
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext, Variable};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, FuncId, Linkage, Module as _};
+use target_lexicon::Triple;
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+use super::artifact::{Artifact, LibCall, Reloc, RelocKind, Target};
+use super::{
+    debug_info::{self, Symbol},
+    fuel_runs,
+    native::{Abi, CodeMemory, Entry, Helper, NativeModule, TrapCode, VmCtx, Window},
+    ssa::{self, Body},
+    verified,
+};
+use crate::{
+    config::{AddressOverflow, BoundsChecks, CompilerConfig, OptLevel, RuntimeConfig},
+    instance::{prepare_func, PreparedFunc},
+    ir::{BlockType, Op},
+    module::Module,
+    trap::{Result, Trap},
+    types::{FuncType, ValType},
+};
+
+/// Compile `module` to native code for this host, to run under `config`.
+/// Fails with `Trap::UnsupportedFeature` if Cranelift cannot target the
+/// host. Functions that cannot be translated are left out, so the result
+/// may have nothing compiled; see [`NativeModule::is_compiled`].
+pub fn compile(module: &Module, config: &RuntimeConfig) -> Result<NativeModule> {
+    compile_only(module, config, &vec![true; module.functions.len()])
+}
+
+/// [`compile`] only the functions `include` selects, leaving the rest to
+/// the interpreter.
+pub(crate) fn compile_only(
+    module: &Module,
+    config: &RuntimeConfig,
+    include: &[bool],
+) -> Result<NativeModule> {
+    let isa = host_isa(config.compiler(), true)?;
+    let Lowered {
+        mut jit,
+        funcs,
+        bodies,
+        ..
+    } = lower(module, config, include, isa)?;
+    let debug_info = config.compiler().debug_info;
+    let mut ctx = jit.make_context();
+    let mut trampolines = Vec::with_capacity(funcs.len());
+    let mut symbols = Vec::new();
+    for (i, body) in bodies.into_iter().enumerate() {
+        let Some((body, entry)) = body else {
+            trampolines.push(None);
+            continue;
+        };
+        ctx.func = body;
+        jit.define_function(funcs[i], &mut ctx)
+            .map_err(codegen_error)?;
+        if let Some(compiled) = ctx.compiled_code().filter(|_| debug_info) {
+            symbols.push(symbol(i, 0, compiled));
+        }
+        jit.clear_context(&mut ctx);
+
+        let id = jit
+            .declare_function(&format!("rune_entry{i}"), Linkage::Local, &entry.signature)
+            .map_err(codegen_error)?;
+        ctx.func = entry;
+        jit.define_function(id, &mut ctx).map_err(codegen_error)?;
+        jit.clear_context(&mut ctx);
+        trampolines.push(Some(id));
+    }
+    jit.finalize_definitions().map_err(codegen_error)?;
+    if !symbols.is_empty() {
+        let code: Vec<_> = symbols
+            .iter()
+            .map(|s| {
+                let start = jit.get_finalized_function(funcs[s.func as usize]);
+                // SAFETY: the function's code is finalized, readable and
+                // `len` bytes long.
+                (
+                    unsafe { std::slice::from_raw_parts(start, s.len as usize) },
+                    s,
+                )
+            })
+            .collect();
+        debug_info::announce(module, &code);
+    }
+
+    let entries = trampolines
+        .into_iter()
+        .map(|id| {
+            id.map(|id| {
+                let code = jit.get_finalized_function(id);
+                // SAFETY: the trampoline was built with the signature of
+                // `Entry` in the host's calling convention.
+                unsafe { std::mem::transmute::<*const u8, Entry>(code) }
+            })
+        })
+        .collect();
+    Ok(NativeModule::new(
+        CodeMemory::Cranelift(Box::new(jit)),
+        entries,
+    ))
+}
+
+/// [`compile`] into an artifact, for the code cache. Fails if Cranelift
+/// emits a relocation an artifact cannot describe.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) fn artifact(module: &Module, config: &RuntimeConfig) -> Result<Artifact> {
+    artifact_for(module, config, host_isa(config.compiler(), false)?)
+}
+
+/// [`artifact`] for the target and CPU features `config` names, by
+/// default any CPU of the target's architecture, as position-independent
+/// code reaching helpers through a GOT, for a shared library.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) fn portable_artifact(module: &Module, config: &RuntimeConfig) -> Result<Artifact> {
+    artifact_for(module, config, library_isa(config.compiler())?)
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn artifact_for(module: &Module, config: &RuntimeConfig, isa: OwnedTargetIsa) -> Result<Artifact> {
+    use cranelift_codegen::{
+        binemit::Reloc as ClifReloc, control::ControlPlane, ir::ExternalName, FinalizedRelocTarget,
+    };
+
+    /// A relocation whose target's offset is not known yet.
+    enum Pending {
+        Func(FuncId),
+        Done(Target),
+    }
+
+    let include = vec![true; module.functions.len()];
+    let Lowered {
+        jit,
+        funcs,
+        helpers,
+        bodies,
+    } = lower(module, config, &include, isa.clone())?;
+    let debug_info = config.compiler().debug_info;
+    let mut ctx = jit.make_context();
+    let mut code = Vec::new();
+    let mut symbols = Vec::new();
+    let mut starts = HashMap::new();
+    let mut entries = vec![None; funcs.len()];
+    let mut pending = Vec::new();
+    for (i, body) in bodies.into_iter().enumerate() {
+        let Some((body, entry)) = body else {
+            continue;
+        };
+        for (func, is_entry) in [(body, false), (entry, true)] {
+            code.resize(code.len().next_multiple_of(16), 0xCC);
+            let start = code.len() as u32;
+            ctx.func = func;
+            let compiled = ctx
+                .compile(&*isa, &mut ControlPlane::default())
+                .map_err(|e| codegen_error(e.inner))?;
+            code.extend_from_slice(compiled.code_buffer());
+            if debug_info && !is_entry {
+                symbols.push(symbol(i, start, compiled));
+            }
+            let relocs = compiled.buffer.relocs().to_vec();
+            for reloc in relocs {
+                let kind = match reloc.kind {
+                    ClifReloc::Abs8 => RelocKind::Abs8,
+                    ClifReloc::X86PCRel4 | ClifReloc::X86CallPCRel4 => RelocKind::PcRel4,
+                    ClifReloc::X86GOTPCRel4 => RelocKind::GotPcRel4,
+                    ClifReloc::Arm64Call => RelocKind::Call26,
+                    ClifReloc::Aarch64AdrGotPage21 => RelocKind::AdrGotPage21,
+                    ClifReloc::Aarch64Ld64GotLo12Nc => RelocKind::Ld64GotLo12,
+                    kind => return Err(codegen_error(format!("relocation {kind}"))),
+                };
+                let target = match reloc.target {
+                    FinalizedRelocTarget::Func(offset) => {
+                        Pending::Done(Target::Code(start + offset))
+                    }
+                    FinalizedRelocTarget::ExternalName(ExternalName::User(name)) => {
+                        let name = &ctx.func.params.user_named_funcs()[name];
+                        Pending::Func(FuncId::from_u32(name.index))
+                    }
+                    FinalizedRelocTarget::ExternalName(ExternalName::LibCall(libcall)) => {
+                        Pending::Done(Target::LibCall(lib_call(libcall)?))
+                    }
+                    FinalizedRelocTarget::ExternalName(name) => {
+                        return Err(codegen_error(format!("relocation to {name:?}")))
+                    }
+                };
+                pending.push((start + reloc.offset, kind, target, reloc.addend));
+            }
+            jit.clear_context(&mut ctx);
+            if is_entry {
+                entries[i] = Some(start);
+            } else {
+                starts.insert(funcs[i], start);
+            }
+        }
+    }
+    let relocs = pending
+        .into_iter()
+        .map(|(at, kind, target, addend)| {
+            let target = match target {
+                Pending::Done(target) => target,
+                Pending::Func(id) => match helpers.iter().position(|&h| h == id) {
+                    Some(h) => Target::Helper(Helper::ALL[h]),
+                    None => Target::Code(
+                        *starts
+                            .get(&id)
+                            .ok_or_else(|| codegen_error("call to an uncompiled function"))?,
+                    ),
+                },
+            };
+            Ok(Reloc {
+                at,
+                kind,
+                target,
+                addend,
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(Artifact {
+        code,
+        entries,
+        relocs,
+        // Cranelift's code checks bounds itself, guard pages or not.
+        faults: Vec::new(),
+        symbols,
+    })
+}
+
+/// Where the code of function `func`, compiled into `compiled` at `start`,
+/// lies, with the ops [`Translator`] tagged its instructions with.
+fn symbol(func: usize, start: u32, compiled: &CompiledCode) -> Symbol {
+    let mut ops: Vec<(u32, u32)> = Vec::new();
+    for loc in compiled.buffer.get_srclocs_sorted() {
+        if loc.loc.is_default() || loc.start == loc.end {
+            continue;
+        }
+        match ops.last_mut() {
+            Some(last) if last.1 == loc.loc.bits() => {}
+            Some(last) if last.0 == loc.start => last.1 = loc.loc.bits(),
+            _ => ops.push((loc.start, loc.loc.bits())),
+        }
+    }
+    Symbol {
+        func: func as u32,
+        start,
+        len: compiled.code_buffer().len() as u32,
+        ops,
+    }
+}
+
+/// The artifact's equivalent of a Cranelift libcall.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn lib_call(libcall: cranelift_codegen::ir::LibCall) -> Result<LibCall> {
+    use cranelift_codegen::ir::LibCall as Clif;
+    Ok(match libcall {
+        Clif::CeilF32 => LibCall::CeilF32,
+        Clif::CeilF64 => LibCall::CeilF64,
+        Clif::FloorF32 => LibCall::FloorF32,
+        Clif::FloorF64 => LibCall::FloorF64,
+        Clif::TruncF32 => LibCall::TruncF32,
+        Clif::TruncF64 => LibCall::TruncF64,
+        Clif::NearestF32 => LibCall::NearestF32,
+        Clif::NearestF64 => LibCall::NearestF64,
+        other => return Err(codegen_error(format!("libcall {other}"))),
+    })
+}
+
+/// What besides the module and configuration decides the code
+/// [`artifact`] emits on this host: the compiler, the target and the CPU
+/// features Cranelift uses.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) fn target(config: &RuntimeConfig) -> Result<String> {
+    let isa = host_isa(config.compiler(), false)?;
+    let mut target = format!("{CRANELIFT} {}\n{}", isa.triple(), isa.flags());
+    for flag in isa.isa_flags() {
+        target.push_str(&format!("{flag}\n"));
+    }
+    Ok(target)
+}
+
+/// The Cranelift release compiling the code; keep in step with
+/// `Cargo.toml`.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const CRANELIFT: &str = "cranelift-0.116";
+
+/// A module translated to Cranelift IR.
+struct Lowered {
+    /// Holds the declarations of the functions below.
+    jit: JITModule,
+    funcs: Vec<FuncId>,
+    helpers: Vec<FuncId>,
+    /// Each translated function and its entry trampoline.
+    bodies: Vec<Option<(Function, Function)>>,
+}
+
+/// Translate the functions of `module` that `include` selects for `isa`.
+fn lower(
+    module: &Module,
+    config: &RuntimeConfig,
+    include: &[bool],
+    isa: OwnedTargetIsa,
+) -> Result<Lowered> {
+    let call_conv = isa.default_call_conv();
+    let ptr = isa.pointer_type();
+    let mut builder = JITBuilder::with_isa(isa, default_libcall_names());
+    for helper in Helper::ALL {
+        builder.symbol(helper.name(), helper.address());
+    }
+    let mut jit = JITModule::new(builder);
+
+    let helpers = Helper::ALL
+        .iter()
+        .map(|&h| {
+            let sig = helper_signature(h, ptr, call_conv);
+            jit.declare_function(h.name(), Linkage::Import, &sig)
+                .map_err(codegen_error)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let funcs = module
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(i, f)| {
+            let sig = func_signature(&f.ty, ptr, call_conv);
+            jit.declare_function(&format!("rune_func{i}"), Linkage::Local, &sig)
+                .map_err(codegen_error)
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let prepared: Vec<PreparedFunc> = module
+        .functions
+        .iter()
+        .enumerate()
+        .map(|(i, f)| prepare_func(i, f))
+        .collect();
+
+    // Calls to functions that stay interpreted go through a helper, so if
+    // any fail, translate the rest again knowing which.
+    let mut compiled = verified(module, config, include);
+    let mut bodies = translate_all(
+        module, config, &mut jit, &prepared, &funcs, &helpers, &compiled, ptr,
+    );
+    if compiled
+        .iter()
+        .zip(&bodies)
+        .any(|(&ok, body)| ok && body.is_none())
+    {
+        for (ok, body) in compiled.iter_mut().zip(&bodies) {
+            *ok = body.is_some();
+        }
+        bodies = translate_all(
+            module, config, &mut jit, &prepared, &funcs, &helpers, &compiled, ptr,
+        );
+    }
+
+    let mut fctx = FunctionBuilderContext::new();
+    let bodies = bodies
+        .into_iter()
+        .enumerate()
+        .map(|(i, body)| {
+            let sig = trampoline_signature(ptr, call_conv);
+            let ty = &module.functions[i].ty;
+            body.map(|body| (body, trampoline(&mut jit, &mut fctx, funcs[i], ty, sig)))
+        })
+        .collect();
+    Ok(Lowered {
+        jit,
+        funcs,
+        helpers,
+        bodies,
+    })
+}
+
+/// The Cranelift ISA `config` selects for code run in this process: the
+/// host, with the CPU features it has unless `config` lists them. Fails if
+/// `config` names another target or a feature the host lacks. The JIT
+/// needs position-independent code with long-range calls; an artifact
+/// instead has helper addresses patched in when it is loaded.
+fn host_isa(config: &CompilerConfig, pic: bool) -> Result<OwnedTargetIsa> {
+    let mut isa = cranelift_native::builder_with_options(false).map_err(host_error)?;
+    if let Some(target) = &config.target {
+        if lookup(target)?.triple() != isa.triple() {
+            return Err(Trap::UnsupportedFeature(format!(
+                "running code for {target} on this host"
+            )));
+        }
+    }
+    match &config.cpu_features {
+        None => cranelift_native::infer_native_flags(&mut isa).map_err(host_error)?,
+        Some(features) => {
+            let host = host_features()?;
+            for feature in features {
+                if !host.contains(feature) {
+                    return Err(Trap::UnsupportedFeature(format!(
+                        "CPU feature {feature} on this host"
+                    )));
+                }
+                enable(&mut isa, feature)?;
+            }
+        }
+    }
+    finish_isa(isa, config, pic)
+}
+
+/// Check that code for `config` can run on this host, as [`host_isa`]
+/// does, for the baseline JIT.
+pub(crate) fn check_host(config: &CompilerConfig) -> Result<()> {
+    if config.target.is_some() || config.cpu_features.is_some() {
+        host_isa(config, false)?;
+    }
+    Ok(())
+}
+
+/// The ISA for a shared library: `config`'s target with only the CPU
+/// features it lists, as position-independent code.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn library_isa(config: &CompilerConfig) -> Result<OwnedTargetIsa> {
+    let mut isa = match &config.target {
+        Some(target) => lookup(target)?,
+        None => cranelift_native::builder_with_options(false).map_err(host_error)?,
+    };
+    for feature in config.cpu_features.iter().flatten() {
+        enable(&mut isa, feature)?;
+    }
+    finish_isa(isa, config, true)
+}
+
+/// The CPU features of the host Cranelift can use, by the names of
+/// [`CompilerConfig::cpu_features`].
+pub(crate) fn host_features() -> Result<Vec<String>> {
+    let isa = cranelift_native::builder().map_err(host_error)?;
+    let isa = isa
+        .finish(settings::Flags::new(settings::builder()))
+        .map_err(codegen_error)?;
+    Ok(isa
+        .isa_flags()
+        .iter()
+        .filter(|flag| flag.as_bool() == Some(true))
+        .filter_map(|flag| flag.name.strip_prefix("has_"))
+        .map(str::to_string)
+        .collect())
+}
+
+fn lookup(target: &str) -> Result<isa::Builder> {
+    let triple = target
+        .parse::<Triple>()
+        .map_err(|e| Trap::UnsupportedFeature(format!("target {target}: {e}")))?;
+    isa::lookup(triple).map_err(|e| Trap::UnsupportedFeature(format!("target {target}: {e}")))
+}
+
+fn enable(isa: &mut isa::Builder, feature: &str) -> Result<()> {
+    isa.enable(&format!("has_{feature}"))
+        .map_err(|_| Trap::UnsupportedFeature(format!("CPU feature {feature}")))
+}
+
+fn finish_isa(isa: isa::Builder, config: &CompilerConfig, pic: bool) -> Result<OwnedTargetIsa> {
+    let opt_level = match config.opt_level {
+        OptLevel::None => "none",
+        OptLevel::Speed => "speed",
+        OptLevel::SpeedAndSize => "speed_and_size",
+    };
+    let mut flags = settings::builder();
+    for (name, value) in [
+        ("opt_level", opt_level),
+        ("use_colocated_libcalls", "false"),
+        ("is_pic", if pic { "true" } else { "false" }),
+        (
+            "preserve_frame_pointers",
+            if config.debug_info { "true" } else { "false" },
+        ),
+    ] {
+        flags.set(name, value).map_err(codegen_error)?;
+    }
+    isa.finish(settings::Flags::new(flags))
+        .map_err(codegen_error)
+}
+
+fn host_error(err: &str) -> Trap {
+    Trap::UnsupportedFeature(format!("native code for this host: {err}"))
+}
+
+fn codegen_error(err: impl fmt::Display) -> Trap {
+    Trap::HostError(format!("native code generation failed: {err}"))
+}
+
+#[allow(clippy::too_many_arguments)]
+fn translate_all(
+    module: &Module,
+    config: &RuntimeConfig,
+    jit: &mut JITModule,
+    prepared: &[PreparedFunc],
+    funcs: &[FuncId],
+    helpers: &[FuncId],
+    compiled: &[bool],
+    ptr: Type,
+) -> Vec<Option<Function>> {
+    let env = Env {
+        module,
+        config,
+        ptr,
+        funcs,
+        helpers,
+        compiled,
+    };
+    let mut fctx = FunctionBuilderContext::new();
+    prepared
+        .iter()
+        .zip(compiled)
+        .map(|(pf, &ok)| {
+            if !ok {
+                return None;
+            }
+            let sig = jit
+                .declarations()
+                .get_function_decl(funcs[pf.index as usize])
+                .signature
+                .clone();
+            let mut func = Function::with_name_signature(UserFuncName::user(0, pf.index), sig);
+            let translated = ssa::lower(module, config, pf)
+                .ok_or(Unsupported)
+                .and_then(|body| {
+                    Translator::new(&env, jit, &mut func, &mut fctx, pf, body).translate()
+                });
+            match translated {
+                Ok(()) => Some(func),
+                Err(Unsupported) => {
+                    // The builder was abandoned part way; start clean.
+                    fctx = FunctionBuilderContext::new();
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn clif(ty: ValType) -> Type {
+    match ty {
+        ValType::I32 => types::I32,
+        ValType::I64 => types::I64,
+        ValType::F32 => types::F32,
+        ValType::F64 => types::F64,
+    }
+}
+
+fn func_signature(ty: &FuncType, ptr: Type, call_conv: CallConv) -> Signature {
+    let mut sig = Signature::new(call_conv);
+    sig.params.push(AbiParam::new(ptr));
+    sig.params
+        .extend(ty.params.iter().map(|&p| AbiParam::new(clif(p))));
+    sig.returns
+        .extend(ty.results.first().map(|&r| AbiParam::new(clif(r))));
+    sig
+}
+
+fn helper_signature(helper: Helper, ptr: Type, call_conv: CallConv) -> Signature {
+    let mut sig = Signature::new(call_conv);
+    sig.params.push(AbiParam::new(ptr));
+    sig.params.extend(helper.params().iter().map(|abi| {
+        AbiParam::new(match abi {
+            Abi::Ptr => ptr,
+            Abi::I32 => types::I32,
+            Abi::I64 => types::I64,
+        })
+    }));
+    if helper.returns_status() {
+        sig.returns.push(AbiParam::new(types::I32));
+    }
+    sig
+}
+
+fn trampoline_signature(ptr: Type, call_conv: CallConv) -> Signature {
+    let mut sig = Signature::new(call_conv);
+    sig.params.extend([AbiParam::new(ptr); 3]);
+    sig
+}
+
+/// An [`Entry`] for function `func` of type `ty`: unpack the arguments,
+/// call it and pack its result.
+fn trampoline(
+    jit: &mut JITModule,
+    fctx: &mut FunctionBuilderContext,
+    func: FuncId,
+    ty: &FuncType,
+    sig: Signature,
+) -> Function {
+    let mut function = Function::with_name_signature(UserFuncName::default(), sig);
+    let mut b = FunctionBuilder::new(&mut function, fctx);
+    let entry = b.create_block();
+    b.append_block_params_for_function_params(entry);
+    b.switch_to_block(entry);
+    let &[vm, args, ret] = b.block_params(entry) else {
+        unreachable!("trampolines take three parameters");
+    };
+    let callee = jit.declare_func_in_func(func, b.func);
+    let mut call_args = vec![vm];
+    for (i, &ty) in ty.params.iter().enumerate() {
+        let bits = b
+            .ins()
+            .load(types::I64, MemFlags::trusted(), args, i as i32 * 8);
+        call_args.push(from_bits(&mut b, ty, bits));
+    }
+    let call = b.ins().call(callee, &call_args);
+    if let (Some(&ty), Some(&result)) = (ty.results.first(), b.inst_results(call).first()) {
+        let bits = to_bits(&mut b, ty, result);
+        b.ins().store(MemFlags::trusted(), bits, ret, 0);
+    }
+    b.ins().return_(&[]);
+    b.seal_all_blocks();
+    b.finalize();
+    function
+}
+
+/// A value of type `ty` from the bits [`to_bits`] gives.
+fn from_bits(b: &mut FunctionBuilder, ty: ValType, bits: Value) -> Value {
+    match ty {
+        ValType::I32 => b.ins().ireduce(types::I32, bits),
+        ValType::I64 => bits,
+        ValType::F32 => {
+            let word = b.ins().ireduce(types::I32, bits);
+            b.ins().bitcast(types::F32, MemFlags::new(), word)
+        }
+        ValType::F64 => b.ins().bitcast(types::F64, MemFlags::new(), bits),
+    }
+}
+
+/// `value`'s bits in an `i64`, as [`crate::global`] stores values.
+fn to_bits(b: &mut FunctionBuilder, ty: ValType, value: Value) -> Value {
+    match ty {
+        ValType::I32 => b.ins().uextend(types::I64, value),
+        ValType::I64 => value,
+        ValType::F32 => {
+            let word = b.ins().bitcast(types::I32, MemFlags::new(), value);
+            b.ins().uextend(types::I64, word)
+        }
+        ValType::F64 => b.ins().bitcast(types::I64, MemFlags::new(), value),
+    }
+}
+
+/// What translating every function of a module shares.
+struct Env<'a> {
+    module: &'a Module,
+    config: &'a RuntimeConfig,
+    ptr: Type,
+    funcs: &'a [FuncId],
+    helpers: &'a [FuncId],
+    /// Which functions are compiled; calls to the others use a helper.
+    compiled: &'a [bool],
+}
+
+/// The function cannot be compiled and stays interpreted.
+struct Unsupported;
+
+type Translated<T = ()> = std::result::Result<T, Unsupported>;
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Block,
+    Loop,
+    If,
+}
+
+/// A block, loop or if being translated.
+struct Region {
+    kind: Kind,
+    result: Option<ValType>,
+    /// Where execution continues after the `End`, taking the result.
+    next: Block,
+    /// Whether anything jumps to `next`.
+    reached: bool,
+    /// The loop header branches to a loop go to.
+    header: Option<Block>,
+    /// The else branch of an if, until translation reaches it.
+    else_block: Option<Block>,
+    /// Index of the `End` op.
+    end: usize,
+}
+
+enum Frame {
+    Live(Region),
+    /// Opened in unreachable code: only its `End` matters.
+    Dead,
+}
+
+struct Translator<'a, 'f> {
+    env: &'a Env<'a>,
+    jit: &'a mut JITModule,
+    b: FunctionBuilder<'f>,
+    pf: &'a PreparedFunc,
+    body: Body,
+    /// Length of the fuel run starting at each op that starts one, else 0.
+    runs: Vec<u32>,
+    vm: Value,
+    locals: Vec<(Variable, ValType)>,
+    /// The value bound to each register once its definition is translated.
+    values: Vec<Option<Value>>,
+    /// The op being translated, and how many of its uses it has popped.
+    at: usize,
+    popped: usize,
+    frames: Vec<Frame>,
+    reachable: bool,
+    /// Takes the trap code and op index.
+    trap_block: Block,
+    /// Takes the first op of the run fuel ran out in and the fuel left.
+    fuel_block: Block,
+    trap_used: bool,
+    fuel_used: bool,
+    /// Result then arguments of helper calls, created on first use.
+    scratch: Option<StackSlot>,
+    scratch_size: u32,
+    refs: HashMap<FuncId, FuncRef>,
+}
+
+impl<'a, 'f> Translator<'a, 'f> {
+    fn new(
+        env: &'a Env<'a>,
+        jit: &'a mut JITModule,
+        func: &'f mut Function,
+        fctx: &'f mut FunctionBuilderContext,
+        pf: &'a PreparedFunc,
+        body: Body,
+    ) -> Self {
+        let ops = &pf.ops;
+        let runs = fuel_runs(ops);
+        let max_args = ops
+            .iter()
+            .filter_map(|op| match op {
+                Op::Call(f) => env
+                    .module
+                    .functions
+                    .get(*f as usize)
+                    .map(|f| f.ty.params.len()),
+                Op::CallHost(i) => env
+                    .module
+                    .imports
+                    .get(*i as usize)
+                    .map(|i| i.ty.params.len()),
+                Op::Ext { opcode, .. } => env.config.extension(*opcode).map(|e| e.ty.params.len()),
+                _ => None,
+            })
+            .max()
+            .unwrap_or(0);
+
+        let mut b = FunctionBuilder::new(func, fctx);
+        let entry = b.create_block();
+        b.append_block_params_for_function_params(entry);
+        b.switch_to_block(entry);
+        let params = b.block_params(entry).to_vec();
+        let trap_block = b.create_block();
+        b.append_block_param(trap_block, types::I32);
+        b.append_block_param(trap_block, types::I32);
+        let fuel_block = b.create_block();
+        b.append_block_param(fuel_block, types::I32);
+        b.append_block_param(fuel_block, types::I64);
+
+        let param_types = &env.module.functions[pf.index as usize].ty.params;
+        let mut locals = Vec::with_capacity(param_types.len() + pf.extra_locals.len());
+        for (i, &ty) in param_types.iter().chain(&pf.extra_locals).enumerate() {
+            let var = Variable::from_u32(i as u32);
+            b.declare_var(var, clif(ty));
+            let init = match params.get(i + 1) {
+                Some(&param) => param,
+                None => zero(&mut b, ty),
+            };
+            b.def_var(var, init);
+            locals.push((var, ty));
+        }
+
+        Translator {
+            env,
+            jit,
+            b,
+            pf,
+            values: vec![None; body.types.len()],
+            body,
+            runs,
+            vm: params[0],
+            locals,
+            at: 0,
+            popped: 0,
+            frames: Vec::new(),
+            reachable: true,
+            trap_block,
+            fuel_block,
+            trap_used: false,
+            fuel_used: false,
+            scratch: None,
+            scratch_size: 8 * (max_args as u32 + 1),
+            refs: HashMap::new(),
+        }
+    }
+
+    fn translate(mut self) -> Translated {
+        let ops = self.pf.ops.clone();
+        let debug_info = self.env.config.compiler().debug_info;
+        let mut finished = false;
+        for (i, op) in ops.iter().enumerate() {
+            (self.at, self.popped) = (i, 0);
+            if debug_info {
+                self.b
+                    .set_srcloc(cranelift_codegen::ir::SourceLoc::new(i as u32));
+            }
+            let done = if self.reachable {
+                // A loop charges its own run at the header branches return to.
+                if self.runs[i] > 0 && !matches!(op, Op::Loop(_)) {
+                    self.charge(self.runs[i], i);
+                }
+                // Hoisted ops ran before their loop, which binds their
+                // results.
+                if self.body.hoisted(i).is_some() {
+                    false
+                } else {
+                    self.op(i, op)?
+                }
+            } else {
+                self.skip(op)?
+            };
+            if done {
+                finished = true;
+                break;
+            }
+        }
+        if !finished {
+            // Falling off the end returns, like the final `End`.
+            if !self.frames.is_empty() {
+                return Err(Unsupported);
+            }
+            (self.at, self.popped) = (ops.len(), 0);
+            if self.reachable {
+                self.ret()?;
+            }
+        }
+
+        if self.fuel_used {
+            self.b.switch_to_block(self.fuel_block);
+            let &[start, fuel] = self.b.block_params(self.fuel_block) else {
+                unreachable!("the fuel block takes two parameters");
+            };
+            let empty = self.b.ins().iconst(types::I64, 0);
+            self.store_vm(empty, VmCtx::FUEL);
+            let spent = self.b.ins().ireduce(types::I32, fuel);
+            let op = self.b.ins().iadd(start, spent);
+            let code = self.b.ins().iconst(types::I32, TrapCode::OutOfFuel as i64);
+            self.b.ins().jump(self.trap_block, &[code, op]);
+            self.trap_used = true;
+        }
+        if self.trap_used {
+            self.b.switch_to_block(self.trap_block);
+            let &[code, op] = self.b.block_params(self.trap_block) else {
+                unreachable!("the trap block takes two parameters");
+            };
+            let func = self.b.ins().iconst(types::I32, self.pf.index as i64);
+            self.call_helper(Helper::Trap, &[code, func, op]);
+            let result: Vec<Value> = self
+                .pf
+                .result_type
+                .map(|ty| zero(&mut self.b, ty))
+                .into_iter()
+                .collect();
+            self.b.ins().return_(&result);
+        }
+        self.b.seal_all_blocks();
+        self.b.finalize();
+        Ok(())
+    }
+
+    /// Translate reachable op `i`; true once the function's final `End` is
+    /// reached.
+    fn op(&mut self, i: usize, op: &Op) -> Translated<bool> {
+        use ValType::{F32, F64, I32, I64};
+        match op {
+            // ── Constants ────────────────────────────────────────────────────
+            Op::I32Const(v) => {
+                let v = self.b.ins().iconst(types::I32, *v as i64);
+                self.push(v, I32);
+            }
+            Op::I64Const(v) => {
+                let v = self.b.ins().iconst(types::I64, *v);
+                self.push(v, I64);
+            }
+            Op::F32Const(v) => {
+                let v = self.b.ins().f32const(*v);
+                self.push(v, F32);
+            }
+            Op::F64Const(v) => {
+                let v = self.b.ins().f64const(*v);
+                self.push(v, F64);
+            }
+
+            // ── Stack and locals ─────────────────────────────────────────────
+            Op::Drop => {
+                self.pop()?;
+            }
+            Op::Select => {
+                let cond = self.pop_ty(I32)?;
+                let (b, ty) = self.pop()?;
+                let a = self.pop_ty(ty)?;
+                let v = self.b.ins().select(cond, a, b);
+                self.push(v, ty);
+            }
+            Op::LocalGet(l) => {
+                let &(var, ty) = self.locals.get(*l as usize).ok_or(Unsupported)?;
+                let v = self.b.use_var(var);
+                self.push(v, ty);
+            }
+            Op::LocalSet(l) => {
+                let &(var, ty) = self.locals.get(*l as usize).ok_or(Unsupported)?;
+                let v = self.pop_ty(ty)?;
+                self.b.def_var(var, v);
+            }
+            Op::LocalTee(l) => {
+                let &(var, ty) = self.locals.get(*l as usize).ok_or(Unsupported)?;
+                let v = self.pop_ty(ty)?;
+                self.b.def_var(var, v);
+                self.push(v, ty);
+            }
+            Op::GlobalGet(g) => {
+                let ty = self.global_type(*g)?;
+                let index = self.b.ins().iconst(types::I32, *g as i64);
+                let out = self.scratch_addr(0);
+                let status = self.call_status(Helper::GlobalGet, &[index, out]);
+                self.trap_if(status, TrapCode::Pending, i);
+                let bits = self.load_scratch(0);
+                let v = from_bits(&mut self.b, ty, bits);
+                self.push(v, ty);
+            }
+            Op::GlobalSet(g) => {
+                let ty = self.global_type(*g)?;
+                let v = self.pop_ty(ty)?;
+                let bits = to_bits(&mut self.b, ty, v);
+                let index = self.b.ins().iconst(types::I32, *g as i64);
+                let status = self.call_status(Helper::GlobalSet, &[index, bits]);
+                self.trap_if(status, TrapCode::Pending, i);
+            }
+            Op::Nop => {}
+            Op::Unreachable => self.trap(TrapCode::Unreachable, i),
+
+            // ── Memory ───────────────────────────────────────────────────────
+            Op::I32Load {
+                align,
+                offset,
+                memory,
+            } => self.load(i, I32, *align, *offset, *memory)?,
+            Op::I64Load {
+                align,
+                offset,
+                memory,
+            } => self.load(i, I64, *align, *offset, *memory)?,
+            Op::F32Load {
+                align,
+                offset,
+                memory,
+            } => self.load(i, F32, *align, *offset, *memory)?,
+            Op::F64Load {
+                align,
+                offset,
+                memory,
+            } => self.load(i, F64, *align, *offset, *memory)?,
+            Op::I32Store {
+                align,
+                offset,
+                memory,
+            } => self.store(i, I32, *align, *offset, *memory)?,
+            Op::I64Store {
+                align,
+                offset,
+                memory,
+            } => self.store(i, I64, *align, *offset, *memory)?,
+            Op::F32Store {
+                align,
+                offset,
+                memory,
+            } => self.store(i, F32, *align, *offset, *memory)?,
+            Op::F64Store {
+                align,
+                offset,
+                memory,
+            } => self.store(i, F64, *align, *offset, *memory)?,
+            Op::MemorySize => {
+                let windows = self.load_vm(self.env.ptr, VmCtx::WINDOWS);
+                let size = self
+                    .b
+                    .ins()
+                    .load(types::I64, vm_flags(), windows, Window::SIZE);
+                let pages = self.b.ins().ushr_imm(size, 16);
+                let pages = self.b.ins().ireduce(types::I32, pages);
+                self.push(pages, I32);
+            }
+            Op::MemoryGrow => {
+                let delta = self.pop_ty(I32)?;
+                let out = self.scratch_addr(0);
+                let status = self.call_status(Helper::MemoryGrow, &[delta, out]);
+                self.trap_if(status, TrapCode::Pending, i);
+                let old = self.load_scratch(0);
+                let old = self.b.ins().ireduce(types::I32, old);
+                self.push(old, I32);
+            }
+            Op::MemoryDiscard => {
+                let len = self.pop_ty(I32)?;
+                let addr = self.pop_ty(I32)?;
+                let status = self.call_status(Helper::MemoryDiscard, &[addr, len]);
+                self.trap_if(status, TrapCode::Pending, i);
+            }
+
+            // ── Integer arithmetic ───────────────────────────────────────────
+            Op::I32Add => self.binary(I32, |b, x, y| b.ins().iadd(x, y))?,
+            Op::I32Sub => self.binary(I32, |b, x, y| b.ins().isub(x, y))?,
+            Op::I32Mul => self.binary(I32, |b, x, y| b.ins().imul(x, y))?,
+            Op::I32DivS | Op::I64DivS => {
+                let ty = if matches!(op, Op::I32DivS) { I32 } else { I64 };
+                let (x, y) = self.divide(i, ty)?;
+                let minus_one = self.b.ins().icmp_imm(IntCC::Equal, y, -1);
+                let q = if ty == I32 {
+                    // i32::MIN / -1 overflows, which the interpreter reports
+                    // as unreachable.
+                    let min = self.b.ins().icmp_imm(IntCC::Equal, x, i32::MIN as i64);
+                    let overflow = self.b.ins().band(min, minus_one);
+                    self.trap_if(overflow, TrapCode::Unreachable, i);
+                    self.b.ins().sdiv(x, y)
+                } else {
+                    // Wrapping: i64::MIN / -1 is i64::MIN.
+                    let one = self.b.ins().iconst(types::I64, 1);
+                    let safe = self.b.ins().select(minus_one, one, y);
+                    let q = self.b.ins().sdiv(x, safe);
+                    let negated = self.b.ins().ineg(x);
+                    self.b.ins().select(minus_one, negated, q)
+                };
+                self.push(q, ty);
+            }
+            Op::I32RemS | Op::I64RemS => {
+                let ty = if matches!(op, Op::I32RemS) { I32 } else { I64 };
+                let (x, y) = self.divide(i, ty)?;
+                // Wrapping: anything rem -1 is 0, including MIN.
+                let minus_one = self.b.ins().icmp_imm(IntCC::Equal, y, -1);
+                let one = self.b.ins().iconst(clif(ty), 1);
+                let safe = self.b.ins().select(minus_one, one, y);
+                let r = self.b.ins().srem(x, safe);
+                self.push(r, ty);
+            }
+            Op::I32DivU | Op::I64DivU => {
+                let ty = if matches!(op, Op::I32DivU) { I32 } else { I64 };
+                let (x, y) = self.divide(i, ty)?;
+                let q = self.b.ins().udiv(x, y);
+                self.push(q, ty);
+            }
+            Op::I32RemU | Op::I64RemU => {
+                let ty = if matches!(op, Op::I32RemU) { I32 } else { I64 };
+                let (x, y) = self.divide(i, ty)?;
+                let r = self.b.ins().urem(x, y);
+                self.push(r, ty);
+            }
+            Op::I32And => self.binary(I32, |b, x, y| b.ins().band(x, y))?,
+            Op::I32Or => self.binary(I32, |b, x, y| b.ins().bor(x, y))?,
+            Op::I32Xor => self.binary(I32, |b, x, y| b.ins().bxor(x, y))?,
+            // Cranelift takes shift counts modulo the width, as the
+            // interpreter's wrapping shifts do.
+            Op::I32Shl => self.binary(I32, |b, x, y| b.ins().ishl(x, y))?,
+            Op::I32ShrS => self.binary(I32, |b, x, y| b.ins().sshr(x, y))?,
+            Op::I32ShrU => self.binary(I32, |b, x, y| b.ins().ushr(x, y))?,
+            Op::I32Clz => self.unary(I32, I32, |b, x| b.ins().clz(x))?,
+            Op::I32Ctz => self.unary(I32, I32, |b, x| b.ins().ctz(x))?,
+            Op::I32Popcnt => self.unary(I32, I32, |b, x| b.ins().popcnt(x))?,
+            Op::I32Eqz => self.unary(I32, I32, |b, x| {
+                let c = b.ins().icmp_imm(IntCC::Equal, x, 0);
+                b.ins().uextend(types::I32, c)
+            })?,
+            Op::I64Add => self.binary(I64, |b, x, y| b.ins().iadd(x, y))?,
+            Op::I64Sub => self.binary(I64, |b, x, y| b.ins().isub(x, y))?,
+            Op::I64Mul => self.binary(I64, |b, x, y| b.ins().imul(x, y))?,
+            Op::I64And => self.binary(I64, |b, x, y| b.ins().band(x, y))?,
+            Op::I64Or => self.binary(I64, |b, x, y| b.ins().bor(x, y))?,
+            Op::I64Xor => self.binary(I64, |b, x, y| b.ins().bxor(x, y))?,
+            Op::I64Shl => self.binary(I64, |b, x, y| b.ins().ishl(x, y))?,
+            Op::I64ShrS => self.binary(I64, |b, x, y| b.ins().sshr(x, y))?,
+            Op::I64ShrU => self.binary(I64, |b, x, y| b.ins().ushr(x, y))?,
+            Op::I64Eqz => self.unary(I64, I32, |b, x| {
+                let c = b.ins().icmp_imm(IntCC::Equal, x, 0);
+                b.ins().uextend(types::I32, c)
+            })?,
+
+            // ── Float arithmetic ─────────────────────────────────────────────
+            Op::F32Add => self.binary(F32, |b, x, y| b.ins().fadd(x, y))?,
+            Op::F32Sub => self.binary(F32, |b, x, y| b.ins().fsub(x, y))?,
+            Op::F32Mul => self.binary(F32, |b, x, y| b.ins().fmul(x, y))?,
+            Op::F32Div => self.binary(F32, |b, x, y| b.ins().fdiv(x, y))?,
+            Op::F32Sqrt => self.unary(F32, F32, |b, x| b.ins().sqrt(x))?,
+            Op::F32Min => self.binary(F32, |b, x, y| min_max(b, x, y, FloatCC::LessThan))?,
+            Op::F32Max => self.binary(F32, |b, x, y| min_max(b, x, y, FloatCC::GreaterThan))?,
+            Op::F32Abs => self.unary(F32, F32, |b, x| b.ins().fabs(x))?,
+            Op::F32Neg => self.unary(F32, F32, |b, x| b.ins().fneg(x))?,
+            Op::F32Ceil => self.unary(F32, F32, |b, x| b.ins().ceil(x))?,
+            Op::F32Floor => self.unary(F32, F32, |b, x| b.ins().floor(x))?,
+            Op::F64Add => self.binary(F64, |b, x, y| b.ins().fadd(x, y))?,
+            Op::F64Sub => self.binary(F64, |b, x, y| b.ins().fsub(x, y))?,
+            Op::F64Mul => self.binary(F64, |b, x, y| b.ins().fmul(x, y))?,
+            Op::F64Div => self.binary(F64, |b, x, y| b.ins().fdiv(x, y))?,
+            Op::F64Sqrt => self.unary(F64, F64, |b, x| b.ins().sqrt(x))?,
+            Op::F64Min => self.binary(F64, |b, x, y| min_max(b, x, y, FloatCC::LessThan))?,
+            Op::F64Max => self.binary(F64, |b, x, y| min_max(b, x, y, FloatCC::GreaterThan))?,
+            Op::F64Abs => self.unary(F64, F64, |b, x| b.ins().fabs(x))?,
+            Op::F64Neg => self.unary(F64, F64, |b, x| b.ins().fneg(x))?,
+            Op::F64Ceil => self.unary(F64, F64, |b, x| b.ins().ceil(x))?,
+            Op::F64Floor => self.unary(F64, F64, |b, x| b.ins().floor(x))?,
+
+            // ── Comparisons ──────────────────────────────────────────────────
+            Op::I32Eq => self.icmp(I32, IntCC::Equal)?,
+            Op::I32Ne => self.icmp(I32, IntCC::NotEqual)?,
+            Op::I32LtS => self.icmp(I32, IntCC::SignedLessThan)?,
+            Op::I32LtU => self.icmp(I32, IntCC::UnsignedLessThan)?,
+            Op::I32GtS => self.icmp(I32, IntCC::SignedGreaterThan)?,
+            Op::I32GtU => self.icmp(I32, IntCC::UnsignedGreaterThan)?,
+            Op::I32LeS => self.icmp(I32, IntCC::SignedLessThanOrEqual)?,
+            Op::I32LeU => self.icmp(I32, IntCC::UnsignedLessThanOrEqual)?,
+            Op::I32GeS => self.icmp(I32, IntCC::SignedGreaterThanOrEqual)?,
+            Op::I32GeU => self.icmp(I32, IntCC::UnsignedGreaterThanOrEqual)?,
+            Op::I64Eq => self.icmp(I64, IntCC::Equal)?,
+            Op::I64Ne => self.icmp(I64, IntCC::NotEqual)?,
+            Op::I64LtS => self.icmp(I64, IntCC::SignedLessThan)?,
+            Op::I64LtU => self.icmp(I64, IntCC::UnsignedLessThan)?,
+            Op::I64GtS => self.icmp(I64, IntCC::SignedGreaterThan)?,
+            Op::I64GtU => self.icmp(I64, IntCC::UnsignedGreaterThan)?,
+            Op::I64LeS => self.icmp(I64, IntCC::SignedLessThanOrEqual)?,
+            Op::I64LeU => self.icmp(I64, IntCC::UnsignedLessThanOrEqual)?,
+            Op::I64GeS => self.icmp(I64, IntCC::SignedGreaterThanOrEqual)?,
+            Op::I64GeU => self.icmp(I64, IntCC::UnsignedGreaterThanOrEqual)?,
+            // `!=` is true when either side is NaN; the others are false.
+            Op::F32Eq => self.fcmp(F32, FloatCC::Equal)?,
+            Op::F32Ne => self.fcmp(F32, FloatCC::NotEqual)?,
+            Op::F32Lt => self.fcmp(F32, FloatCC::LessThan)?,
+            Op::F32Gt => self.fcmp(F32, FloatCC::GreaterThan)?,
+            Op::F32Le => self.fcmp(F32, FloatCC::LessThanOrEqual)?,
+            Op::F32Ge => self.fcmp(F32, FloatCC::GreaterThanOrEqual)?,
+            Op::F64Eq => self.fcmp(F64, FloatCC::Equal)?,
+            Op::F64Ne => self.fcmp(F64, FloatCC::NotEqual)?,
+            Op::F64Lt => self.fcmp(F64, FloatCC::LessThan)?,
+            Op::F64Gt => self.fcmp(F64, FloatCC::GreaterThan)?,
+            Op::F64Le => self.fcmp(F64, FloatCC::LessThanOrEqual)?,
+            Op::F64Ge => self.fcmp(F64, FloatCC::GreaterThanOrEqual)?,
+
+            // ── Conversions ──────────────────────────────────────────────────
+            // Float to int conversions saturate, as Rust's `as` does.
+            Op::I32WrapI64 => self.unary(I64, I32, |b, x| b.ins().ireduce(types::I32, x))?,
+            Op::I64ExtendI32S => self.unary(I32, I64, |b, x| b.ins().sextend(types::I64, x))?,
+            Op::I64ExtendI32U => self.unary(I32, I64, |b, x| b.ins().uextend(types::I64, x))?,
+            Op::F32ConvertI32S => {
+                self.unary(I32, F32, |b, x| b.ins().fcvt_from_sint(types::F32, x))?
+            }
+            Op::F32ConvertI32U => {
+                self.unary(I32, F32, |b, x| b.ins().fcvt_from_uint(types::F32, x))?
+            }
+            Op::F64ConvertI32S => {
+                self.unary(I32, F64, |b, x| b.ins().fcvt_from_sint(types::F64, x))?
+            }
+            Op::F64ConvertI32U => {
+                self.unary(I32, F64, |b, x| b.ins().fcvt_from_uint(types::F64, x))?
+            }
+            Op::F64ConvertI64S => {
+                self.unary(I64, F64, |b, x| b.ins().fcvt_from_sint(types::F64, x))?
+            }
+            Op::F64ConvertI64U => {
+                self.unary(I64, F64, |b, x| b.ins().fcvt_from_uint(types::F64, x))?
+            }
+            Op::I32TruncF32S => {
+                self.unary(F32, I32, |b, x| b.ins().fcvt_to_sint_sat(types::I32, x))?
+            }
+            Op::I32TruncF32U => {
+                self.unary(F32, I32, |b, x| b.ins().fcvt_to_uint_sat(types::I32, x))?
+            }
+            Op::I32TruncF64S => {
+                self.unary(F64, I32, |b, x| b.ins().fcvt_to_sint_sat(types::I32, x))?
+            }
+            Op::I32TruncF64U => {
+                self.unary(F64, I32, |b, x| b.ins().fcvt_to_uint_sat(types::I32, x))?
+            }
+            Op::F32DemoteF64 => self.unary(F64, F32, |b, x| b.ins().fdemote(types::F32, x))?,
+            Op::F64PromoteF32 => self.unary(F32, F64, |b, x| b.ins().fpromote(types::F64, x))?,
+            Op::I32ReinterpretF32 => self.unary(F32, I32, |b, x| {
+                b.ins().bitcast(types::I32, MemFlags::new(), x)
+            })?,
+            Op::F32ReinterpretI32 => self.unary(I32, F32, |b, x| {
+                b.ins().bitcast(types::F32, MemFlags::new(), x)
+            })?,
+            Op::I64ReinterpretF64 => self.unary(F64, I64, |b, x| {
+                b.ins().bitcast(types::I64, MemFlags::new(), x)
+            })?,
+            Op::F64ReinterpretI64 => self.unary(I64, F64, |b, x| {
+                b.ins().bitcast(types::F64, MemFlags::new(), x)
+            })?,
+
+            // ── Control flow ─────────────────────────────────────────────────
+            Op::Block(bt) => {
+                let result = block_result(bt);
+                let next = self.continuation(result);
+                self.open(Kind::Block, result, next, i);
+            }
+            Op::Loop(bt) => {
+                let pf = self.pf;
+                for j in self.body.preheader(i, pf.ends[i]) {
+                    (self.at, self.popped) = (j, 0);
+                    self.op(j, &pf.ops[j])?;
+                }
+                (self.at, self.popped) = (i, 0);
+                let header = self.b.create_block();
+                self.b.ins().jump(header, &[]);
+                self.b.switch_to_block(header);
+                // Every entry, first or by branch, runs the `Loop` op.
+                self.charge(1, i);
+                self.check_deadlines(i);
+                let result = block_result(bt);
+                let next = self.continuation(result);
+                self.open(Kind::Loop, result, next, i);
+                if let Some(Frame::Live(region)) = self.frames.last_mut() {
+                    region.header = Some(header);
+                }
+            }
+            Op::If(bt) => {
+                let cond = self.pop_ty(I32)?;
+                let result = block_result(bt);
+                let has_else = self.pf.elses[i] != usize::MAX;
+                // Without an else, a false condition leaves no result.
+                if !has_else && result.is_some() {
+                    return Err(Unsupported);
+                }
+                let then = self.b.create_block();
+                let otherwise = self.b.create_block();
+                let next = self.continuation(result);
+                self.b.ins().brif(cond, then, &[], otherwise, &[]);
+                self.open(Kind::If, result, next, i);
+                let end = self.pf.ends[i];
+                if has_else {
+                    if let Some(Frame::Live(region)) = self.frames.last_mut() {
+                        region.else_block = Some(otherwise);
+                    }
+                } else {
+                    // The interpreter jumps to the `End` and runs it.
+                    self.b.switch_to_block(otherwise);
+                    self.charge(1, end);
+                    self.b.ins().jump(next, &[]);
+                    self.region(0)?.reached = true;
+                }
+                self.b.switch_to_block(then);
+            }
+            Op::Else => {
+                let Some(Frame::Live(region)) = self.frames.last() else {
+                    return Err(Unsupported);
+                };
+                let (next, end) = (region.next, region.end);
+                if region.kind != Kind::If || region.else_block.is_none() {
+                    return Err(Unsupported);
+                }
+                // The then branch jumps to the `End`, which runs too.
+                let args = self.carried()?;
+                self.charge(1, end);
+                self.b.ins().jump(next, &args);
+                self.region(0)?.reached = true;
+                self.enter_else()?;
+            }
+            Op::End => match self.frames.pop() {
+                None => {
+                    self.ret()?;
+                    return Ok(true);
+                }
+                Some(Frame::Live(mut region)) => {
+                    let args = self.carried()?;
+                    self.b.ins().jump(region.next, &args);
+                    region.reached = true;
+                    self.close(region);
+                }
+                Some(Frame::Dead) => return Err(Unsupported),
+            },
+            Op::Br(depth) => {
+                match self.branch_target(*depth)? {
+                    Some((target, args)) => {
+                        self.b.ins().jump(target, &args);
+                    }
+                    None => self.trap(TrapCode::TypeMismatch, i),
+                }
+                self.reachable = false;
+            }
+            Op::BrIf(depth) => {
+                let cond = self.pop_ty(I32)?;
+                match self.branch_target(*depth)? {
+                    Some((target, args)) => {
+                        let cont = self.b.create_block();
+                        self.b.ins().brif(cond, target, &args, cont, &[]);
+                        self.b.switch_to_block(cont);
+                    }
+                    None => self.trap_if(cond, TrapCode::TypeMismatch, i),
+                }
+            }
+            Op::Return => self.ret()?,
+
+            // ── Calls ────────────────────────────────────────────────────────
+            Op::Call(f) => self.call(i, *f)?,
+            Op::CallHost(idx) => {
+                let ty = &self
+                    .env
+                    .module
+                    .imports
+                    .get(*idx as usize)
+                    .ok_or(Unsupported)?
+                    .ty;
+                let index = self.b.ins().iconst(types::I32, *idx as i64);
+                self.call_indirect(i, Helper::CallHost, &[index], ty)?;
+            }
+            Op::Ext { opcode, imm } => {
+                let ty = &self.env.config.extension(*opcode).ok_or(Unsupported)?.ty;
+                let opcode = self.b.ins().iconst(types::I32, *opcode as i64);
+                let imm = self.b.ins().iconst(types::I32, *imm as i64);
+                self.call_indirect(i, Helper::CallExt, &[opcode, imm], ty)?;
+            }
+        }
+        Ok(false)
+    }
+
+    /// Follow op `op` in unreachable code; true at the function's final
+    /// `End`.
+    fn skip(&mut self, op: &Op) -> Translated<bool> {
+        match op {
+            Op::Block(_) | Op::Loop(_) | Op::If(_) => self.frames.push(Frame::Dead),
+            Op::Else => match self.frames.last() {
+                Some(Frame::Dead) => {}
+                Some(Frame::Live(region))
+                    if region.kind == Kind::If && region.else_block.is_some() =>
+                {
+                    self.enter_else()?
+                }
+                _ => return Err(Unsupported),
+            },
+            Op::End => match self.frames.pop() {
+                None => return Ok(true),
+                Some(Frame::Dead) => {}
+                Some(Frame::Live(region)) => self.close(region),
+            },
+            _ => {}
+        }
+        Ok(false)
+    }
+
+    // ── Operands ─────────────────────────────────────────────────────────────
+
+    /// Bind the current op's result to `value`.
+    fn push(&mut self, value: Value, ty: ValType) {
+        if let Some(reg) = self.body.at(self.at).def {
+            debug_assert_eq!(self.body.types[reg as usize], ty);
+            self.values[reg as usize] = Some(value);
+        }
+    }
+
+    /// The current op's next operand, from the top of the stack down.
+    fn pop(&mut self) -> Translated<(Value, ValType)> {
+        let uses = &self.body.at(self.at).uses;
+        let index = uses.len().checked_sub(self.popped + 1).ok_or(Unsupported)?;
+        let reg = uses[index] as usize;
+        self.popped += 1;
+        Ok((self.values[reg].ok_or(Unsupported)?, self.body.types[reg]))
+    }
+
+    fn pop_ty(&mut self, ty: ValType) -> Translated<Value> {
+        match self.pop()? {
+            (value, t) if t == ty => Ok(value),
+            _ => Err(Unsupported),
+        }
+    }
+
+    /// The current op's operands it has not popped: what an `Else`, `End`,
+    /// branch or `Return` carries.
+    fn carried(&self) -> Translated<Vec<Value>> {
+        let uses = &self.body.at(self.at).uses;
+        uses[..uses.len() - self.popped]
+            .iter()
+            .map(|&reg| self.values[reg as usize].ok_or(Unsupported))
+            .collect()
+    }
+
+    /// Pop arguments of types `params`, the last on top.
+    fn pop_args(&mut self, params: &[ValType]) -> Translated<Vec<Value>> {
+        let mut args = params
+            .iter()
+            .rev()
+            .map(|&ty| self.pop_ty(ty))
+            .collect::<Translated<Vec<_>>>()?;
+        args.reverse();
+        Ok(args)
+    }
+
+    fn unary(
+        &mut self,
+        from: ValType,
+        to: ValType,
+        f: impl FnOnce(&mut FunctionBuilder, Value) -> Value,
+    ) -> Translated {
+        let x = self.pop_ty(from)?;
+        let v = f(&mut self.b, x);
+        self.push(v, to);
+        Ok(())
+    }
+
+    fn binary(
+        &mut self,
+        ty: ValType,
+        f: impl FnOnce(&mut FunctionBuilder, Value, Value) -> Value,
+    ) -> Translated {
+        let y = self.pop_ty(ty)?;
+        let x = self.pop_ty(ty)?;
+        let v = f(&mut self.b, x, y);
+        self.push(v, ty);
+        Ok(())
+    }
+
+    fn icmp(&mut self, ty: ValType, cc: IntCC) -> Translated {
+        let y = self.pop_ty(ty)?;
+        let x = self.pop_ty(ty)?;
+        let c = self.b.ins().icmp(cc, x, y);
+        let v = self.b.ins().uextend(types::I32, c);
+        self.push(v, ValType::I32);
+        Ok(())
+    }
+
+    fn fcmp(&mut self, ty: ValType, cc: FloatCC) -> Translated {
+        let y = self.pop_ty(ty)?;
+        let x = self.pop_ty(ty)?;
+        let c = self.b.ins().fcmp(cc, x, y);
+        let v = self.b.ins().uextend(types::I32, c);
+        self.push(v, ValType::I32);
+        Ok(())
+    }
+
+    /// Pop a dividend and divisor, trapping if the divisor is zero.
+    fn divide(&mut self, op: usize, ty: ValType) -> Translated<(Value, Value)> {
+        let y = self.pop_ty(ty)?;
+        let x = self.pop_ty(ty)?;
+        let zero = self.b.ins().icmp_imm(IntCC::Equal, y, 0);
+        self.trap_if(zero, TrapCode::DivisionByZero, op);
+        Ok((x, y))
+    }
+
+    // ── Control ──────────────────────────────────────────────────────────────
+
+    /// A block taking `result`, if any, as its parameter.
+    fn continuation(&mut self, result: Option<ValType>) -> Block {
+        let block = self.b.create_block();
+        if let Some(ty) = result {
+            self.b.append_block_param(block, clif(ty));
+        }
+        block
+    }
+
+    fn open(&mut self, kind: Kind, result: Option<ValType>, next: Block, op: usize) {
+        self.frames.push(Frame::Live(Region {
+            kind,
+            result,
+            next,
+            reached: false,
+            header: None,
+            else_block: None,
+            end: self.pf.ends[op],
+        }));
+    }
+
+    /// The region `depth` frames out, which must be live.
+    fn region(&mut self, depth: usize) -> Translated<&mut Region> {
+        let index = self
+            .frames
+            .len()
+            .checked_sub(depth + 1)
+            .ok_or(Unsupported)?;
+        match &mut self.frames[index] {
+            Frame::Live(region) => Ok(region),
+            Frame::Dead => Err(Unsupported),
+        }
+    }
+
+    /// Continue after `region`'s `End`, if anything reaches it.
+    fn close(&mut self, region: Region) {
+        self.reachable = region.reached;
+        if region.reached {
+            self.b.switch_to_block(region.next);
+            if let (Some(ty), Some(&v)) = (region.result, self.b.block_params(region.next).first())
+            {
+                self.push(v, ty);
+            }
+        }
+    }
+
+    /// Start the else branch of the innermost `If`.
+    fn enter_else(&mut self) -> Translated {
+        let region = self.region(0)?;
+        let block = region.else_block.take().ok_or(Unsupported)?;
+        self.b.switch_to_block(block);
+        self.reachable = true;
+        Ok(())
+    }
+
+    /// Where `Br(depth)` goes and what it passes, or `None` if it names no
+    /// frame, which the interpreter traps on.
+    fn branch_target(&mut self, depth: u32) -> Translated<Option<(Block, Vec<Value>)>> {
+        let depth = depth as usize;
+        if depth >= self.frames.len() {
+            return Ok(None);
+        }
+        let args = self.carried()?;
+        let region = self.region(depth)?;
+        if region.kind == Kind::Loop {
+            return Ok(Some((region.header.ok_or(Unsupported)?, Vec::new())));
+        }
+        region.reached = true;
+        Ok(Some((region.next, args)))
+    }
+
+    fn ret(&mut self) -> Translated {
+        let result = self.carried()?;
+        self.b.ins().return_(&result);
+        self.reachable = false;
+        Ok(())
+    }
+
+    // ── Fuel, deadlines and traps ────────────────────────────────────────────
+
+    /// Charge `n` units for the run starting at op `start`.
+    fn charge(&mut self, n: u32, start: usize) {
+        let fuel = self.load_vm(types::I64, VmCtx::FUEL);
+        let enough = self
+            .b
+            .ins()
+            .icmp_imm(IntCC::UnsignedGreaterThanOrEqual, fuel, n as i64);
+        let cont = self.b.create_block();
+        let start = self.b.ins().iconst(types::I32, start as i64);
+        self.b
+            .ins()
+            .brif(enough, cont, &[], self.fuel_block, &[start, fuel]);
+        self.fuel_used = true;
+        self.b.switch_to_block(cont);
+        let left = self.b.ins().iadd_imm(fuel, -(n as i64));
+        self.store_vm(left, VmCtx::FUEL);
+    }
+
+    fn check_deadlines(&mut self, op: usize) {
+        let interruptible = self.load_vm(types::I32, VmCtx::INTERRUPTIBLE);
+        let check = self.b.create_block();
+        let cont = self.b.create_block();
+        self.b.ins().brif(interruptible, check, &[], cont, &[]);
+        self.b.switch_to_block(check);
+        let status = self.call_status(Helper::CheckDeadlines, &[]);
+        let (code, op) = self.trap_args(TrapCode::Pending, op);
+        self.b
+            .ins()
+            .brif(status, self.trap_block, &[code, op], cont, &[]);
+        self.b.switch_to_block(cont);
+    }
+
+    fn trap_args(&mut self, code: TrapCode, op: usize) -> (Value, Value) {
+        self.trap_used = true;
+        let code = self.b.ins().iconst(types::I32, code as i64);
+        let op = self.b.ins().iconst(types::I32, op as i64);
+        (code, op)
+    }
+
+    fn trap(&mut self, code: TrapCode, op: usize) {
+        let (code, op) = self.trap_args(code, op);
+        self.b.ins().jump(self.trap_block, &[code, op]);
+        self.reachable = false;
+    }
+
+    fn trap_if(&mut self, cond: Value, code: TrapCode, op: usize) {
+        let (code, op) = self.trap_args(code, op);
+        let cont = self.b.create_block();
+        self.b
+            .ins()
+            .brif(cond, self.trap_block, &[code, op], cont, &[]);
+        self.b.switch_to_block(cont);
+    }
+
+    // ── Memory ───────────────────────────────────────────────────────────────
+
+    /// Pop a base address and add `offset`, as the interpreter does.
+    fn address(&mut self, op: usize, align: u32, offset: u32) -> Translated<Value> {
+        let base = self.pop_ty(ValType::I32)?;
+        let addr = match self.env.config.address_overflow() {
+            AddressOverflow::Trap => {
+                let wide = self.b.ins().uextend(types::I64, base);
+                if offset == 0 {
+                    wide
+                } else {
+                    let addr = self.b.ins().iadd_imm(wide, offset as i64);
+                    let over =
+                        self.b
+                            .ins()
+                            .icmp_imm(IntCC::UnsignedGreaterThan, addr, u32::MAX as i64);
+                    self.trap_if(over, TrapCode::OutOfBounds, op);
+                    addr
+                }
+            }
+            AddressOverflow::Wrap => {
+                let addr = self.b.ins().iadd_imm(base, offset as i32 as i64);
+                self.b.ins().uextend(types::I64, addr)
+            }
+        };
+        if self.env.config.strict_alignment() {
+            let mask = 1u64.checked_shl(align).map_or(u64::MAX, |a| a - 1);
+            if mask != 0 {
+                let low = self.b.ins().band_imm(addr, mask as i64);
+                let unaligned = self.b.ins().icmp_imm(IntCC::NotEqual, low, 0);
+                self.trap_if(unaligned, TrapCode::UnalignedAccess, op);
+            }
+        }
+        Ok(addr)
+    }
+
+    /// The address of `size` bytes at `addr` in memory `memory`'s direct
+    /// window, reached in a new block, if they lie in it; otherwise, or
+    /// always when bounds are checked by helpers, branch to `slow`.
+    fn direct_access(&mut self, memory: u32, addr: Value, size: u32, slow: Block) -> Option<Value> {
+        if self.env.config.compiler().bounds_checks == BoundsChecks::Helper {
+            self.b.ins().jump(slow, &[]);
+            return None;
+        }
+        let fast = self.b.create_block();
+        let windows = self.load_vm(self.env.ptr, VmCtx::WINDOWS);
+        let window = memory as i64 * Window::STRIDE;
+        let direct = self.b.ins().load(
+            types::I64,
+            vm_flags(),
+            windows,
+            (window + Window::DIRECT as i64) as i32,
+        );
+        let end = self.b.ins().iadd_imm(addr, size as i64);
+        let inside = self
+            .b
+            .ins()
+            .icmp(IntCC::UnsignedLessThanOrEqual, end, direct);
+        self.b.ins().brif(inside, fast, &[], slow, &[]);
+        self.b.switch_to_block(fast);
+        let base = self.b.ins().load(
+            self.env.ptr,
+            vm_flags(),
+            windows,
+            (window + Window::BASE as i64) as i32,
+        );
+        let offset = if self.env.ptr == types::I64 {
+            addr
+        } else {
+            self.b.ins().ireduce(self.env.ptr, addr)
+        };
+        Some(self.b.ins().iadd(base, offset))
+    }
+
+    fn load(&mut self, op: usize, ty: ValType, align: u32, offset: u32, memory: u32) -> Translated {
+        let addr = self.address(op, align, offset)?;
+        let size = clif(ty).bytes();
+        let slow = self.b.create_block();
+        let done = self.continuation(Some(ty));
+        if let Some(p) = self.direct_access(memory, addr, size, slow) {
+            let v = self.b.ins().load(clif(ty), heap_flags(), p, 0);
+            self.b.ins().jump(done, &[v]);
+        }
+
+        self.b.switch_to_block(slow);
+        let memory = self.b.ins().iconst(types::I32, memory as i64);
+        let size = self.b.ins().iconst(types::I32, size as i64);
+        let out = self.scratch_addr(0);
+        let status = self.call_status(Helper::Load, &[memory, addr, size, out]);
+        self.trap_if(status, TrapCode::Pending, op);
+        let bits = self.load_scratch(0);
+        let v = from_bits(&mut self.b, ty, bits);
+        self.b.ins().jump(done, &[v]);
+
+        self.b.switch_to_block(done);
+        let v = self.b.block_params(done)[0];
+        self.push(v, ty);
+        Ok(())
+    }
+
+    fn store(
+        &mut self,
+        op: usize,
+        ty: ValType,
+        align: u32,
+        offset: u32,
+        memory: u32,
+    ) -> Translated {
+        let v = self.pop_ty(ty)?;
+        let addr = self.address(op, align, offset)?;
+        let size = clif(ty).bytes();
+        let slow = self.b.create_block();
+        let done = self.b.create_block();
+        if let Some(p) = self.direct_access(memory, addr, size, slow) {
+            self.b.ins().store(heap_flags(), v, p, 0);
+            self.b.ins().jump(done, &[]);
+        }
+
+        self.b.switch_to_block(slow);
+        let memory = self.b.ins().iconst(types::I32, memory as i64);
+        let size = self.b.ins().iconst(types::I32, size as i64);
+        let bits = to_bits(&mut self.b, ty, v);
+        let status = self.call_status(Helper::Store, &[memory, addr, size, bits]);
+        self.trap_if(status, TrapCode::Pending, op);
+        self.b.ins().jump(done, &[]);
+
+        self.b.switch_to_block(done);
+        Ok(())
+    }
+
+    // ── Calls ────────────────────────────────────────────────────────────────
+
+    fn call(&mut self, op: usize, func: u32) -> Translated {
+        let callee = self
+            .env
+            .module
+            .functions
+            .get(func as usize)
+            .ok_or(Unsupported)?;
+        let ty = &callee.ty;
+        self.check_deadlines(op);
+        let args = self.pop_args(&ty.params)?;
+        let result = ty.results.first().copied();
+
+        let depth = self.load_vm(types::I64, VmCtx::DEPTH);
+        let max = self.load_vm(types::I64, VmCtx::MAX_DEPTH);
+        let too_deep = self
+            .b
+            .ins()
+            .icmp(IntCC::UnsignedGreaterThanOrEqual, depth, max);
+        self.trap_if(too_deep, TrapCode::StackOverflow, op);
+        let deeper = self.b.ins().iadd_imm(depth, 1);
+        self.store_vm(deeper, VmCtx::DEPTH);
+
+        let value = if self.env.compiled[func as usize] {
+            let callee = self.func_ref(self.env.funcs[func as usize]);
+            let mut call_args = vec![self.vm];
+            call_args.extend(&args);
+            let call = self.b.ins().call(callee, &call_args);
+            let value = self.b.inst_results(call).first().copied();
+            self.store_vm(depth, VmCtx::DEPTH);
+            let trapped = self.load_vm(types::I32, VmCtx::TRAPPED);
+            self.trap_if(trapped, TrapCode::Pending, op);
+            value
+        } else {
+            self.spill_args(&ty.params, &args);
+            let index = self.b.ins().iconst(types::I32, func as i64);
+            let (args, ret) = (self.scratch_addr(8), self.scratch_addr(0));
+            let status = self.call_status(Helper::Call, &[index, args, ret]);
+            self.store_vm(depth, VmCtx::DEPTH);
+            self.trap_if(status, TrapCode::Pending, op);
+            result.map(|ty| {
+                let bits = self.load_scratch(0);
+                from_bits(&mut self.b, ty, bits)
+            })
+        };
+        if let (Some(ty), Some(v)) = (result, value) {
+            self.push(v, ty);
+        }
+        Ok(())
+    }
+
+    /// Call a host function or extension of type `ty` through `helper`,
+    /// which takes `lead` before the argument and result pointers.
+    fn call_indirect(
+        &mut self,
+        op: usize,
+        helper: Helper,
+        lead: &[Value],
+        ty: &FuncType,
+    ) -> Translated {
+        let args = self.pop_args(&ty.params)?;
+        self.spill_args(&ty.params, &args);
+        let mut helper_args = lead.to_vec();
+        helper_args.push(self.scratch_addr(8));
+        helper_args.push(self.scratch_addr(0));
+        let status = self.call_status(helper, &helper_args);
+        self.trap_if(status, TrapCode::Pending, op);
+        if let Some(&ty) = ty.results.first() {
+            let bits = self.load_scratch(0);
+            let v = from_bits(&mut self.b, ty, bits);
+            self.push(v, ty);
+        }
+        Ok(())
+    }
+
+    /// Store helper call arguments after the result in the scratch slot.
+    fn spill_args(&mut self, params: &[ValType], args: &[Value]) {
+        let slot = self.scratch();
+        for (i, (&ty, &v)) in params.iter().zip(args).enumerate() {
+            let bits = to_bits(&mut self.b, ty, v);
+            self.b.ins().stack_store(bits, slot, 8 + 8 * i as i32);
+        }
+    }
+
+    fn func_ref(&mut self, id: FuncId) -> FuncRef {
+        *self
+            .refs
+            .entry(id)
+            .or_insert_with(|| self.jit.declare_func_in_func(id, self.b.func))
+    }
+
+    fn call_helper(&mut self, helper: Helper, args: &[Value]) -> Option<Value> {
+        let callee = self.func_ref(self.env.helpers[helper as usize]);
+        let mut call_args = vec![self.vm];
+        call_args.extend(args);
+        let call = self.b.ins().call(callee, &call_args);
+        self.b.inst_results(call).first().copied()
+    }
+
+    /// Call `helper` and return its status.
+    fn call_status(&mut self, helper: Helper, args: &[Value]) -> Value {
+        self.call_helper(helper, args)
+            .expect("helper returns a status")
+    }
+
+    fn global_type(&self, global: u32) -> Translated<ValType> {
+        self.env
+            .module
+            .global_imports
+            .get(global as usize)
+            .map(|g| g.ty)
+            .ok_or(Unsupported)
+    }
+
+    // ── Context and scratch access ───────────────────────────────────────────
+
+    fn load_vm(&mut self, ty: Type, offset: i32) -> Value {
+        self.b.ins().load(ty, vm_flags(), self.vm, offset)
+    }
+
+    fn store_vm(&mut self, value: Value, offset: i32) {
+        self.b.ins().store(vm_flags(), value, self.vm, offset);
+    }
+
+    fn scratch(&mut self) -> StackSlot {
+        let size = self.scratch_size;
+        *self.scratch.get_or_insert_with(|| {
+            self.b
+                .create_sized_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, size, 3))
+        })
+    }
+
+    fn scratch_addr(&mut self, offset: i32) -> Value {
+        let slot = self.scratch();
+        self.b.ins().stack_addr(self.env.ptr, slot, offset)
+    }
+
+    fn load_scratch(&mut self, offset: i32) -> Value {
+        let slot = self.scratch();
+        self.b.ins().stack_load(types::I64, slot, offset)
+    }
+}
+
+fn block_result(bt: &BlockType) -> Option<ValType> {
+    match bt {
+        BlockType::Empty => None,
+        BlockType::Val(ty) => Some(*ty),
+    }
+}
+
+fn zero(b: &mut FunctionBuilder, ty: ValType) -> Value {
+    match ty {
+        ValType::I32 => b.ins().iconst(types::I32, 0),
+        ValType::I64 => b.ins().iconst(types::I64, 0),
+        ValType::F32 => b.ins().f32const(0.0),
+        ValType::F64 => b.ins().f64const(0.0),
+    }
+}
+
+/// Rust's `min` (with `cc` less-than) or `max`: a NaN operand yields the
+/// other one.
+fn min_max(b: &mut FunctionBuilder, x: Value, y: Value, cc: FloatCC) -> Value {
+    let x_wins = b.ins().fcmp(cc, x, y);
+    let pick = b.ins().select(x_wins, x, y);
+    let y_nan = b.ins().fcmp(FloatCC::Unordered, y, y);
+    let pick = b.ins().select(y_nan, x, pick);
+    let x_nan = b.ins().fcmp(FloatCC::Unordered, x, x);
+    b.ins().select(x_nan, y, pick)
+}
+
+/// Context and scratch accesses: always valid and aligned.
+fn vm_flags() -> MemFlags {
+    MemFlags::trusted().with_alias_region(Some(AliasRegion::Vmctx))
+}
+
+/// Guest memory accesses, bounds-checked beforehand; guest memory is
+/// little-endian on every host.
+fn heap_flags() -> MemFlags {
+    MemFlags::new()
+        .with_notrap()
+        .with_endianness(Endianness::Little)
+        .with_alias_region(Some(AliasRegion::Heap))
 }
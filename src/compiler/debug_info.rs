@@ -0,0 +1,169 @@
+//! Debug info for compiled code, under
+//! [`CompilerConfig::debug_info`](crate::config::CompilerConfig::debug_info).
+//!
+//! Profilers and debuggers know nothing of code the runtime maps itself,
+//! and show it as anonymous addresses. With debug info on, the compilers
+//! note where each function's code lies and which op each stretch of it
+//! comes from, and the runtime announces the code to `perf` as it loads
+//! it, through a jitdump file `jit-<pid>.dump` in `$JITDUMPDIR`, or in the
+//! temporary directory if that is unset:
+//!
+//! ```text
+//! perf record -k mono -g ./host
+//! perf inject --jit -i perf.data -o perf.jit.data
+//! perf report -i perf.jit.data
+//! ```
+//!
+//! Guest functions then show by name, at the file and line the module's
+//! source map gives for their ops. Shared libraries built with debug info
+//! name every compiled function in their symbol table instead, which
+//! profilers and debuggers read without help. Only x86-64 Linux hosts get
+//! either.
+
+use crate::module::Module;
+
+/// Where a compiled function's code lies.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) struct Symbol {
+    pub(crate) func: u32,
+    /// Offset of the code in its artifact, and its length.
+    pub(crate) start: u32,
+    pub(crate) len: u32,
+    /// Where the code of each op begins, as an offset from `start` and the
+    /// op's index, sorted by offset. Ops with no code of their own are
+    /// left out.
+    pub(crate) ops: Vec<(u32, u32)>,
+}
+
+/// The name function `func` of `module` goes by in native tools: its own,
+/// or `func<index>` if it has none.
+pub(crate) fn name(module: &Module, func: u32) -> String {
+    match module.functions.get(func as usize) {
+        Some(f) if !f.name.is_empty() => f.name.replace('\0', ""),
+        _ => format!("func{func}"),
+    }
+}
+
+/// Announce loaded code of `module` to profilers: each symbol, with the
+/// code it describes where it was loaded. Failures only cost the names.
+pub(crate) fn announce(module: &Module, code: &[(&[u8], &Symbol)]) {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    jitdump::write(module, code);
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    let _ = (module, code);
+}
+
+/// The jitdump format `perf inject --jit` reads, as in `perf`'s
+/// `jitdump-specification.txt`: a header, then records for each piece of
+/// code, its line table ahead of the code itself.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod jitdump {
+    use std::fs::File;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use std::sync::{Mutex, OnceLock, PoisonError};
+
+    use super::{name, Module, Symbol};
+    use crate::sys::{map_for_profiler, monotonic_nanos};
+
+    const MAGIC: u32 = 0x4A69_5444;
+    const VERSION: u32 = 1;
+    const HEADER: u32 = 40;
+    const EM_X86_64: u32 = 62;
+    const CODE_LOAD: u32 = 0;
+    const DEBUG_INFO: u32 = 2;
+
+    struct Dump {
+        file: File,
+        /// Index of the next piece of code, unique within the file.
+        next: u64,
+    }
+
+    /// The process's dump, opened on first use; `None` if it could not be.
+    static DUMP: OnceLock<Option<Mutex<Dump>>> = OnceLock::new();
+
+    fn open() -> Option<Mutex<Dump>> {
+        let dir = std::env::var_os("JITDUMPDIR").map_or_else(std::env::temp_dir, PathBuf::from);
+        let pid = std::process::id();
+        let mut file = File::options()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(dir.join(format!("jit-{pid}.dump")))
+            .ok()?;
+        let mut header = Vec::with_capacity(HEADER as usize);
+        for field in [MAGIC, VERSION, HEADER, EM_X86_64, 0, pid] {
+            header.extend_from_slice(&field.to_le_bytes());
+        }
+        header.extend_from_slice(&monotonic_nanos().to_le_bytes());
+        header.extend_from_slice(&0u64.to_le_bytes()); // flags
+        file.write_all(&header).ok()?;
+        map_for_profiler(&file).then(|| Mutex::new(Dump { file, next: 0 }))
+    }
+
+    pub(super) fn write(module: &Module, code: &[(&[u8], &Symbol)]) {
+        let Some(dump) = DUMP.get_or_init(open) else {
+            return;
+        };
+        let mut dump = dump.lock().unwrap_or_else(PoisonError::into_inner);
+        let pid = std::process::id();
+        let mut out = Vec::new();
+        for &(bytes, symbol) in code {
+            let addr = bytes.as_ptr() as u64;
+            let lines = lines(module, addr, symbol);
+            if !lines.is_empty() {
+                let mut body = Vec::new();
+                body.extend_from_slice(&addr.to_le_bytes());
+                body.extend_from_slice(&(lines.len() as u64).to_le_bytes());
+                for (at, line, file) in lines {
+                    body.extend_from_slice(&at.to_le_bytes());
+                    body.extend_from_slice(&line.to_le_bytes());
+                    body.extend_from_slice(&0u32.to_le_bytes()); // discriminator
+                    body.extend(file.bytes().filter(|&b| b != 0));
+                    body.push(0);
+                }
+                record(&mut out, DEBUG_INFO, &body);
+            }
+            let mut body = Vec::new();
+            body.extend_from_slice(&pid.to_le_bytes());
+            body.extend_from_slice(&pid.to_le_bytes()); // thread
+            body.extend_from_slice(&addr.to_le_bytes()); // vma
+            body.extend_from_slice(&addr.to_le_bytes());
+            body.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+            body.extend_from_slice(&dump.next.to_le_bytes());
+            body.extend_from_slice(name(module, symbol.func).as_bytes());
+            body.push(0);
+            body.extend_from_slice(bytes);
+            record(&mut out, CODE_LOAD, &body);
+            dump.next += 1;
+        }
+        let _ = dump.file.write_all(&out);
+    }
+
+    /// The line table of `symbol`'s code at `addr`: the address each
+    /// source line starts at, with the line and file.
+    fn lines<'m>(module: &'m Module, addr: u64, symbol: &Symbol) -> Vec<(u64, u32, &'m str)> {
+        let Some(map) = &module.source_map else {
+            return Vec::new();
+        };
+        let mut lines: Vec<(u64, u32, &str)> = Vec::new();
+        for &(offset, op) in &symbol.ops {
+            let Some(loc) = map.lookup(symbol.func, op as usize) else {
+                continue;
+            };
+            let file = map.file_name(loc.file).unwrap_or("");
+            if lines.last().map(|&(_, line, f)| (line, f)) != Some((loc.line, file)) {
+                lines.push((addr + offset as u64, loc.line, file));
+            }
+        }
+        lines
+    }
+
+    fn record(out: &mut Vec<u8>, id: u32, body: &[u8]) {
+        out.extend_from_slice(&id.to_le_bytes());
+        out.extend_from_slice(&(16 + body.len() as u32).to_le_bytes());
+        out.extend_from_slice(&monotonic_nanos().to_le_bytes());
+        out.extend_from_slice(body);
+    }
+}
@@ -0,0 +1,97 @@
+//! Code compiled ahead of time for a module embedded in the host binary,
+//! behind [`EmbeddedModule`](crate::embed::EmbeddedModule).
+//!
+//! [`build`] compiles a module, with the baseline JIT under
+//! [`Strategy::Baseline`] and with Cranelift otherwise, into an artifact,
+//! and puts a header before it:
+//!
+//! ```text
+//! 0    "RUNEEMBD"
+//! 8    u8 backend: 0 Cranelift, 1 baseline
+//! 9    key over the module, backend, target, version and settings
+//! 41   the artifact
+//! ```
+//!
+//! The key is the one the [code cache](super::code_cache) names files by.
+//! Unlike a [shared library](super::library), the code needs no file to
+//! be opened: [`load`] copies it from the binary's data into executable
+//! memory and patches it. It only loads under the runtime version and
+//! compiled-in settings it was built for, on a host of the target and CPU
+//! features it was built for; only x86-64 Linux hosts build or load it.
+
+use super::NativeModule;
+use crate::{
+    config::RuntimeConfig,
+    module::Module,
+    trap::{Result, Trap},
+};
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const MAGIC: &[u8; 8] = b"RUNEEMBD";
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+const HEADER: usize = 41;
+
+/// Compile `module` for `config` into code to embed beside it. Fails with
+/// `Trap::UnsupportedFeature` on hosts other than x86-64 Linux, or if
+/// Cranelift cannot link the module's code ahead of time.
+pub fn build(module: &Module, config: &RuntimeConfig) -> Result<Vec<u8>> {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    {
+        use super::{baseline, code_cache, codegen};
+        use crate::config::Strategy;
+
+        let baseline = config.strategy() == Strategy::Baseline;
+        let artifact = if baseline {
+            baseline::artifact(module, config)?
+        } else {
+            codegen::artifact(module, config)?
+        };
+        let mut out = MAGIC.to_vec();
+        out.push(baseline.into());
+        out.extend_from_slice(&code_cache::key(module, config, baseline)?);
+        out.extend_from_slice(&artifact.to_bytes());
+        Ok(out)
+    }
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    {
+        let _ = (module, config);
+        Err(Trap::UnsupportedFeature(
+            "embedded code for this host".into(),
+        ))
+    }
+}
+
+/// Load the code [`build`] compiled for `module`, checking it was built
+/// for this runtime version, host and `config`'s compiled-in settings.
+pub(crate) fn load(code: &[u8], module: &Module, config: &RuntimeConfig) -> Result<NativeModule> {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    {
+        use super::{artifact::Artifact, code_cache};
+
+        let invalid = || Trap::InvalidModule("damaged embedded code".into());
+        if code.len() < HEADER || &code[..8] != MAGIC {
+            return Err(invalid());
+        }
+        let baseline = match code[8] {
+            0 => false,
+            1 => true,
+            _ => return Err(invalid()),
+        };
+        if code[9..HEADER] != code_cache::key(module, config, baseline)? {
+            return Err(Trap::InvalidModule(
+                "embedded code was compiled for another module, Rune version, host or settings"
+                    .into(),
+            ));
+        }
+        Artifact::from_bytes(&code[HEADER..])
+            .ok_or_else(invalid)?
+            .load(module, None)
+    }
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    {
+        let _ = (code, module, config);
+        Err(Trap::UnsupportedFeature(
+            "embedded code for this host".into(),
+        ))
+    }
+}
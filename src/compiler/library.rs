@@ -0,0 +1,621 @@
+//! Modules compiled ahead of time into shared libraries, for hosts that
+//! must not compile at run time.
+//!
+//! [`build`] compiles a module with Cranelift into an ELF shared object
+//! for x86-64 or AArch64 Linux, as `runec compile module.rune -o
+//! module.so` does; `--target aarch64-unknown-linux-gnu` cross-compiles.
+//! Each compiled export is a dynamic symbol of the same name, with the
+//! `Entry` signature the runtime calls it with, and
+//! `rune_module` points to a descriptor the runtime reads when it loads the
+//! library with
+//! [`Runtime::load_library`](crate::runtime::Runtime::load_library):
+//!
+//! ```text
+//! 0    "RUNELIB\0"
+//! 8    u32 format, u32 number of functions
+//! 16   version string: i64 offset, u64 length
+//! 32   encoded module: i64 offset, u64 length
+//! 48   settings digest: i64 offset
+//! 56   GOT: i64 offset, u32 slots, u32 reserved
+//! 72   target: i64 offset, u64 length
+//! 88   per function, i64 offset of its entry or 0 if interpreted
+//!      per GOT slot, u32 kind (1 helper, 2 libcall) and u32 index
+//! ```
+//!
+//! Offsets are relative to the descriptor, so the object needs no dynamic
+//! relocations: the code is position-independent and reaches the runtime's
+//! helpers through a GOT in its data, which the runtime fills on load. The
+//! target is the triple followed by the CPU features the code uses, by
+//! default none beyond the architecture's baseline; [`target_of`] reads it
+//! back. A library only loads on a host of that architecture with those
+//! features, under the runtime version and compiled-in settings it was
+//! built for. Only x86-64 Linux hosts write or load libraries so far.
+//!
+//! Built with [debug info](crate::config::CompilerConfig::debug_info), a
+//! library also has a symbol table naming every compiled function, for
+//! profilers and debuggers.
+
+use std::path::Path;
+
+use super::NativeModule;
+use crate::{
+    config::RuntimeConfig,
+    module::Module,
+    trap::{Result, Trap},
+};
+
+/// Compile `module` for `config` into a shared library, for the target and
+/// CPU features in its [`CompilerConfig`](crate::config::CompilerConfig).
+/// Fails with `Trap::UnsupportedFeature` on hosts other than x86-64 Linux
+/// or for other targets. Functions Cranelift cannot handle stay
+/// interpreted when the library is loaded.
+pub fn build(module: &Module, config: &RuntimeConfig) -> Result<Vec<u8>> {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    return elf::write(module, config);
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    {
+        let _ = (module, config);
+        Err(Trap::UnsupportedFeature(
+            "shared libraries for this host".into(),
+        ))
+    }
+}
+
+/// The target of a library [`build`] wrote: its triple and the CPU
+/// features its code may use. Fails with `Trap::InvalidModule` if
+/// `library` is not one.
+pub fn target_of(library: &[u8]) -> Result<(String, Vec<String>)> {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    {
+        let (triple, features) = elf::target_of(library)
+            .ok_or_else(|| Trap::InvalidModule("not a Rune library".into()))?;
+        let features = features
+            .split(',')
+            .filter(|f| !f.is_empty())
+            .map(str::to_string)
+            .collect();
+        Ok((triple.to_string(), features))
+    }
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    {
+        let _ = library;
+        Err(Trap::UnsupportedFeature(
+            "shared libraries for this host".into(),
+        ))
+    }
+}
+
+/// Load a library [`build`] wrote, checking it was built for this runtime
+/// version and `config`'s compiled-in settings.
+pub(crate) fn load(path: &Path, config: &RuntimeConfig) -> Result<(Module, NativeModule)> {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    return elf::load(path, config);
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    {
+        let _ = (path, config);
+        Err(Trap::UnsupportedFeature(
+            "shared libraries for this host".into(),
+        ))
+    }
+}
+
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+mod elf {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    use target_lexicon::{Architecture, OperatingSystem, Triple};
+
+    use super::{Module, NativeModule, Result, RuntimeConfig, Trap};
+    use crate::compiler::{
+        artifact::{LibCall, RelocKind, Target, FORMAT},
+        codegen, debug_info,
+        native::{CodeMemory, Entry, Helper},
+        settings_digest,
+    };
+    use crate::sys::Library;
+
+    const MAGIC: &[u8; 8] = b"RUNELIB\0";
+    const DESCRIPTOR: &str = "rune_module";
+    const VERSION: &str = concat!("rune ", env!("CARGO_PKG_VERSION"));
+    const HEADER: usize = 88;
+
+    const EHDR: usize = 64;
+    const PHDR: usize = 56;
+    const SHDR: usize = 64;
+    const SYM: usize = 24;
+    const PHNUM: usize = 4;
+
+    /// Indices of the sections other headers refer to, in the order the
+    /// section headers are written.
+    const DYNSYM: u16 = 2;
+    const DYNSTR: u16 = 3;
+    const TEXT: u16 = 4;
+    const DATA: u16 = 6;
+    const SHSTRTAB: u16 = 8;
+    const SYMTAB: u16 = 9;
+    const STRTAB: u16 = 10;
+
+    pub(super) fn write(module: &Module, config: &RuntimeConfig) -> Result<Vec<u8>> {
+        let (triple, target) = target(config)?;
+        let (machine, page) = machine(&triple).expect("`target` checks the architecture");
+        let artifact = codegen::portable_artifact(module, config)?;
+        let got = artifact.got();
+
+        // Dynamic symbols: the descriptor, then each compiled export.
+        let mut names = vec![DESCRIPTOR.to_string()];
+        let mut exports = Vec::new();
+        for (name, func) in &module.exports {
+            let entry = artifact.entries.get(*func as usize).copied().flatten();
+            if let Some(entry) = entry.filter(|_| !names.contains(name) && !name.contains('\0')) {
+                names.push(name.clone());
+                exports.push(entry);
+            }
+        }
+        let mut dynstr = vec![0u8];
+        let name_at: Vec<u32> = names
+            .iter()
+            .map(|name| {
+                let at = dynstr.len() as u32;
+                dynstr.extend_from_slice(name.as_bytes());
+                dynstr.push(0);
+                at
+            })
+            .collect();
+        let nsyms = names.len() + 1;
+        let hash = sysv_hash(&names);
+
+        // The read-only, executable segment.
+        let hash_at = EHDR + PHNUM * PHDR;
+        let dynsym_at = align(hash_at + hash.len(), 8);
+        let dynstr_at = dynsym_at + nsyms * SYM;
+        let text_at = align(dynstr_at + dynstr.len(), 16);
+        let version_at = align(text_at + artifact.code.len(), 8);
+        let target_at = version_at + VERSION.len();
+        let settings_at = target_at + target.len();
+        let module_bytes = module.to_bytes();
+        let module_at = settings_at + 32;
+        let rodata_end = module_at + module_bytes.len();
+
+        // The writable segment: descriptor, GOT and dynamic section.
+        let data_at = align(rodata_end, page);
+        let n = artifact.entries.len();
+        let got_at = align(data_at + HEADER + 8 * n + 8 * got.len(), 8);
+        let dynamic_at = got_at + 8 * got.len();
+        let dynamic = [
+            (4, hash_at as u64),       // DT_HASH
+            (5, dynstr_at as u64),     // DT_STRTAB
+            (6, dynsym_at as u64),     // DT_SYMTAB
+            (10, dynstr.len() as u64), // DT_STRSZ
+            (11, SYM as u64),          // DT_SYMENT
+            (0, 0),                    // DT_NULL
+        ];
+        let data_end = dynamic_at + 16 * dynamic.len();
+
+        let mut out = vec![0u8; data_end];
+        let mut w = Writer { out: &mut out };
+
+        // Code, patched for where it lands. Helpers only go through the GOT.
+        w.put(text_at, &artifact.code);
+        for reloc in &artifact.relocs {
+            let at = text_at + reloc.at as usize;
+            let target = match (reloc.kind, reloc.target) {
+                (RelocKind::PcRel4 | RelocKind::Call26, Target::Code(offset)) => {
+                    (text_at + offset as usize) as i64
+                }
+                (kind, target) if kind.via_got() => {
+                    let slot = got.iter().position(|&t| t == target).expect("in the GOT");
+                    (got_at + 8 * slot) as i64
+                }
+                _ => {
+                    return Err(Trap::HostError(
+                        "shared library: code is not position-independent".into(),
+                    ))
+                }
+            } + reloc.addend;
+            let rel = target - at as i64;
+            let too_large = || Trap::HostError("shared library: code too large".into());
+            match reloc.kind {
+                RelocKind::PcRel4 | RelocKind::GotPcRel4 => {
+                    let rel = i32::try_from(rel).map_err(|_| too_large())?;
+                    w.put(at, &rel.to_le_bytes());
+                }
+                RelocKind::Call26 => {
+                    let words = rel >> 2;
+                    if !(-(1 << 25)..1 << 25).contains(&words) {
+                        return Err(too_large());
+                    }
+                    w.patch(at, 0x03FF_FFFF, words as u32);
+                }
+                RelocKind::AdrGotPage21 => {
+                    let pages = (target >> 12) - (at as i64 >> 12);
+                    if !(-(1 << 20)..1 << 20).contains(&pages) {
+                        return Err(too_large());
+                    }
+                    let pages = pages as u32;
+                    w.patch(at, 0x60FF_FFE0, (pages & 3) << 29 | (pages >> 2) << 5);
+                }
+                RelocKind::Ld64GotLo12 => {
+                    w.patch(at, 0x003F_FC00, ((target & 0xFFF) as u32 >> 3) << 10);
+                }
+                RelocKind::Abs8 => unreachable!("rejected as not position-independent"),
+            }
+        }
+        w.put(version_at, VERSION.as_bytes());
+        w.put(target_at, target.as_bytes());
+        w.put(settings_at, &settings_digest(config));
+        w.put(module_at, &module_bytes);
+
+        let rel = |at: usize| (at as i64 - data_at as i64).to_le_bytes();
+        w.put(data_at, MAGIC);
+        w.put(data_at + 8, &FORMAT.to_le_bytes());
+        w.put(data_at + 12, &(n as u32).to_le_bytes());
+        w.put(data_at + 16, &rel(version_at));
+        w.put(data_at + 24, &(VERSION.len() as u64).to_le_bytes());
+        w.put(data_at + 32, &rel(module_at));
+        w.put(data_at + 40, &(module_bytes.len() as u64).to_le_bytes());
+        w.put(data_at + 48, &rel(settings_at));
+        w.put(data_at + 56, &rel(got_at));
+        w.put(data_at + 64, &(got.len() as u32).to_le_bytes());
+        w.put(data_at + 72, &rel(target_at));
+        w.put(data_at + 80, &(target.len() as u64).to_le_bytes());
+        for (i, entry) in artifact.entries.iter().enumerate() {
+            let offset = entry.map_or([0; 8], |e| rel(text_at + e as usize));
+            w.put(data_at + HEADER + 8 * i, &offset);
+        }
+        for (slot, target) in got.iter().enumerate() {
+            let (kind, index) = match *target {
+                Target::Helper(h) => (1u32, Helper::ALL.iter().position(|&x| x == h)),
+                Target::LibCall(l) => (2, LibCall::ALL.iter().position(|&x| x == l)),
+                _ => (0, None),
+            };
+            let index = index
+                .ok_or_else(|| Trap::HostError("shared library: unexpected GOT entry".into()))?;
+            let at = data_at + HEADER + 8 * n + 8 * slot;
+            w.put(at, &kind.to_le_bytes());
+            w.put(at + 4, &(index as u32).to_le_bytes());
+        }
+        for (i, (tag, value)) in dynamic.iter().enumerate() {
+            w.put(dynamic_at + 16 * i, &(*tag as i64).to_le_bytes());
+            w.put(dynamic_at + 16 * i + 8, &value.to_le_bytes());
+        }
+
+        w.put(hash_at, &hash);
+        w.put(dynstr_at, &dynstr);
+        // Symbol 0 stays null.
+        let descriptor_size = (got_at - data_at) as u64;
+        let symbols = std::iter::once((DATA, data_at, descriptor_size, 0x11)) // global object
+            .chain(
+                exports
+                    .iter()
+                    .map(|&e| (TEXT, text_at + e as usize, 0, 0x12)), // global function
+            );
+        for (i, (shndx, value, size, info)) in symbols.enumerate() {
+            let at = dynsym_at + SYM * (i + 1);
+            w.put(at, &name_at[i].to_le_bytes());
+            w.put(at + 4, &[info, 0]);
+            w.put(at + 6, &shndx.to_le_bytes());
+            w.put(at + 8, &(value as u64).to_le_bytes());
+            w.put(at + 16, &size.to_le_bytes());
+        }
+
+        // Under debug info, a symbol table naming each compiled function.
+        let mut symtab = Vec::new();
+        let mut strtab = Vec::new();
+        if !artifact.symbols.is_empty() {
+            symtab.resize(SYM, 0); // symbol 0 stays null
+            strtab.push(0);
+        }
+        for symbol in &artifact.symbols {
+            let value = (text_at + symbol.start as usize) as u64;
+            symtab.extend_from_slice(&(strtab.len() as u32).to_le_bytes());
+            symtab.extend_from_slice(&[0x02, 0]); // local function
+            symtab.extend_from_slice(&TEXT.to_le_bytes());
+            symtab.extend_from_slice(&value.to_le_bytes());
+            symtab.extend_from_slice(&(symbol.len as u64).to_le_bytes());
+            strtab.extend_from_slice(debug_info::name(module, symbol.func).as_bytes());
+            strtab.push(0);
+        }
+
+        // Section names and headers, after the loaded part.
+        let shstrtab_at = data_end;
+        let mut shstrtab = vec![0u8];
+        let mut sections = vec![
+            (".hash", 5, 2, hash_at, hash.len(), DYNSYM, 0, 8, 4),
+            (".dynsym", 11, 2, dynsym_at, nsyms * SYM, DYNSTR, 1, 8, SYM),
+            (".dynstr", 3, 2, dynstr_at, dynstr.len(), 0, 0, 1, 0),
+            (".text", 1, 6, text_at, artifact.code.len(), 0, 0, 16, 0),
+            (
+                ".rodata",
+                1,
+                2,
+                version_at,
+                rodata_end - version_at,
+                0,
+                0,
+                8,
+                0,
+            ),
+            (".data", 1, 3, data_at, dynamic_at - data_at, 0, 0, 8, 0),
+            (
+                ".dynamic",
+                6,
+                3,
+                dynamic_at,
+                16 * dynamic.len(),
+                DYNSTR,
+                0,
+                8,
+                16,
+            ),
+            (".shstrtab", 3, 0, 0, 0, 0, 0, 1, 0),
+        ];
+        if !symtab.is_empty() {
+            let locals = symtab.len() / SYM;
+            sections.push((".symtab", 2, 0, 0, 0, STRTAB, locals, 8, SYM));
+            sections.push((".strtab", 3, 0, 0, 0, 0, 0, 1, 0));
+        }
+        let mut section_names = Vec::new();
+        for (name, ..) in &sections {
+            section_names.push(shstrtab.len() as u32);
+            shstrtab.extend_from_slice(name.as_bytes());
+            shstrtab.push(0);
+        }
+        let symtab_at = align(shstrtab_at + shstrtab.len(), 8);
+        let strtab_at = symtab_at + symtab.len();
+        let shoff = align(strtab_at + strtab.len(), 8);
+        w.out.resize(shoff + SHDR * (sections.len() + 1), 0);
+        w.put(shstrtab_at, &shstrtab);
+        w.put(symtab_at, &symtab);
+        w.put(strtab_at, &strtab);
+        for (i, &(_, ty, flags, at, size, link, info, alignment, entsize)) in
+            sections.iter().enumerate()
+        {
+            let (addr, at, size) = match i as u16 + 1 {
+                SHSTRTAB => (0, shstrtab_at, shstrtab.len()),
+                SYMTAB => (0, symtab_at, symtab.len()),
+                STRTAB => (0, strtab_at, strtab.len()),
+                _ => (at, at, size),
+            };
+            let h = shoff + SHDR * (i + 1);
+            w.put(h, &section_names[i].to_le_bytes());
+            w.put(h + 4, &(ty as u32).to_le_bytes());
+            w.put(h + 8, &(flags as u64).to_le_bytes());
+            w.put(h + 16, &(addr as u64).to_le_bytes());
+            w.put(h + 24, &(at as u64).to_le_bytes());
+            w.put(h + 32, &(size as u64).to_le_bytes());
+            w.put(h + 40, &(link as u32).to_le_bytes());
+            w.put(h + 44, &(info as u32).to_le_bytes());
+            w.put(h + 48, &(alignment as u64).to_le_bytes());
+            w.put(h + 56, &(entsize as u64).to_le_bytes());
+        }
+
+        // Segments: code and read-only data, data, the dynamic section and
+        // a non-executable stack.
+        let segments = [
+            (1, 5, 0, rodata_end, page),               // PT_LOAD, R+X
+            (1, 6, data_at, data_end - data_at, page), // PT_LOAD, R+W
+            (2, 6, dynamic_at, 16 * dynamic.len(), 8), // PT_DYNAMIC
+            (0x6474_E551, 6, 0, 0, 16),                // PT_GNU_STACK
+        ];
+        for (i, &(ty, flags, at, size, alignment)) in segments.iter().enumerate() {
+            let h = EHDR + PHDR * i;
+            w.put(h, &(ty as u32).to_le_bytes());
+            w.put(h + 4, &(flags as u32).to_le_bytes());
+            let fields = [at, at, at, size, size, alignment];
+            for (k, v) in fields.iter().enumerate() {
+                w.put(h + 8 + 8 * k, &(*v as u64).to_le_bytes());
+            }
+        }
+
+        let mut ident = [0u8; 16];
+        ident[..7].copy_from_slice(&[0x7F, b'E', b'L', b'F', 2, 1, 1]);
+        w.put(0, &ident);
+        w.put(16, &3u16.to_le_bytes()); // ET_DYN
+        w.put(18, &machine.to_le_bytes());
+        w.put(20, &1u32.to_le_bytes());
+        w.put(32, &(EHDR as u64).to_le_bytes());
+        w.put(40, &(shoff as u64).to_le_bytes());
+        w.put(52, &(EHDR as u16).to_le_bytes());
+        w.put(54, &(PHDR as u16).to_le_bytes());
+        w.put(56, &(PHNUM as u16).to_le_bytes());
+        w.put(58, &(SHDR as u16).to_le_bytes());
+        w.put(60, &(sections.len() as u16 + 1).to_le_bytes());
+        w.put(62, &SHSTRTAB.to_le_bytes());
+        Ok(out)
+    }
+
+    pub(super) fn load(path: &Path, config: &RuntimeConfig) -> Result<(Module, NativeModule)> {
+        let fail = |msg: &str| Trap::HostError(format!("cannot load {}: {msg}", path.display()));
+        let invalid = || Trap::InvalidModule(format!("{}: not a Rune library", path.display()));
+        // `dlopen` would only say a library for another host is not one.
+        let bytes = std::fs::read(path).map_err(|e| fail(&e.to_string()))?;
+        let (triple, _) = target_of(&bytes).ok_or_else(invalid)?;
+        let host = Triple::host();
+        match triple.parse::<Triple>() {
+            Ok(t)
+                if t.architecture == host.architecture
+                    && t.operating_system == host.operating_system => {}
+            _ => return Err(fail(&format!("built for {triple}, not {host}"))),
+        }
+        // `dlopen` searches the library path for names without a slash.
+        let path = std::fs::canonicalize(path).map_err(|e| fail(&e.to_string()))?;
+        let c_path = CString::new(path.as_os_str().as_bytes()).map_err(|e| fail(&e.to_string()))?;
+        // SAFETY: the library has no initializers of its own.
+        let lib = unsafe { Library::open(&c_path) }.map_err(|e| fail(&e))?;
+        let name = CString::new(DESCRIPTOR).expect("no NUL");
+        let base = lib.symbol(&name).ok_or_else(invalid)?;
+
+        // SAFETY: the descriptor was laid out by `write`, and the offsets in
+        // it stay within the library's segments. Its magic, format and
+        // version are checked before anything else in it is trusted.
+        unsafe {
+            let at = |offset: usize| base.add(offset);
+            let u32_at = |offset: usize| at(offset).cast::<u32>().read_unaligned();
+            let u64_at = |offset: usize| at(offset).cast::<u64>().read_unaligned();
+            let ptr_at = |offset: usize| base.offset(u64_at(offset) as i64 as isize);
+            if std::slice::from_raw_parts(base, 8) != MAGIC || u32_at(8) != FORMAT {
+                return Err(invalid());
+            }
+            let version = std::slice::from_raw_parts(ptr_at(16), u64_at(24) as usize);
+            if version != VERSION.as_bytes() {
+                return Err(fail(&format!(
+                    "built by {}, not {VERSION}",
+                    String::from_utf8_lossy(version)
+                )));
+            }
+            let module_bytes = std::slice::from_raw_parts(ptr_at(32), u64_at(40) as usize);
+            let module = Module::from_bytes(module_bytes)?;
+            let settings = std::slice::from_raw_parts(ptr_at(48), 32);
+            if settings != settings_digest(config) {
+                return Err(fail("built for other runtime settings"));
+            }
+            let target = std::slice::from_raw_parts(ptr_at(72), u64_at(80) as usize);
+            let (_, features) = std::str::from_utf8(target)
+                .ok()
+                .and_then(|target| target.split_once(' '))
+                .ok_or_else(invalid)?;
+            let host = codegen::host_features()?;
+            let missing = features
+                .split(',')
+                .find(|f| !f.is_empty() && !host.iter().any(|h| h == f));
+            if let Some(feature) = missing {
+                return Err(fail(&format!("needs CPU feature {feature}")));
+            }
+            let n = u32_at(12) as usize;
+            if n != module.functions.len() {
+                return Err(invalid());
+            }
+            let got = ptr_at(56).cast::<*const u8>();
+            for slot in 0..u32_at(64) as usize {
+                let kind = u32_at(HEADER + 8 * n + 8 * slot);
+                let index = u32_at(HEADER + 8 * n + 8 * slot + 4) as usize;
+                let address = match kind {
+                    1 => Helper::ALL.get(index).map(|h| h.address()),
+                    2 => LibCall::ALL.get(index).map(|l| l.address()),
+                    _ => None,
+                };
+                got.add(slot).write(address.ok_or_else(invalid)?);
+            }
+            let entries = (0..n)
+                .map(|i| match u64_at(HEADER + 8 * i) {
+                    0 => None,
+                    _ => Some(std::mem::transmute::<*const u8, Entry>(ptr_at(
+                        HEADER + 8 * i,
+                    ))),
+                })
+                .collect();
+            Ok((module, NativeModule::new(CodeMemory::Library(lib), entries)))
+        }
+    }
+
+    /// The triple and CPU features a library's descriptor records, read
+    /// from the file: the descriptor starts the writable segment, the
+    /// second in the program headers.
+    pub(super) fn target_of(library: &[u8]) -> Option<(&str, &str)> {
+        let u32_at = |at: usize| {
+            let bytes = library.get(at..at.checked_add(4)?)?;
+            Some(u32::from_le_bytes(bytes.try_into().ok()?))
+        };
+        let u64_at = |at: usize| {
+            let bytes = library.get(at..at.checked_add(8)?)?;
+            Some(u64::from_le_bytes(bytes.try_into().ok()?))
+        };
+        if library.get(..4)? != b"\x7FELF" {
+            return None;
+        }
+        let data_at = u64_at((u64_at(32)? as usize).checked_add(PHDR + 8)?)? as usize;
+        if library.get(data_at..data_at.checked_add(8)?)? != MAGIC || u32_at(data_at + 8)? != FORMAT
+        {
+            return None;
+        }
+        let at = data_at.checked_add_signed(u64_at(data_at + 72)? as i64 as isize)?;
+        let len = u64_at(data_at + 80)? as usize;
+        let target = std::str::from_utf8(library.get(at..at.checked_add(len)?)?).ok()?;
+        target.split_once(' ')
+    }
+
+    /// The library's target: the triple `config` names, checked to be one
+    /// libraries are written for, and the CPU features the code may use.
+    fn target(config: &RuntimeConfig) -> Result<(Triple, String)> {
+        let compiler = config.compiler();
+        let triple = match &compiler.target {
+            Some(target) => target
+                .parse::<Triple>()
+                .map_err(|e| Trap::UnsupportedFeature(format!("target {target}: {e}")))?,
+            None => Triple::host(),
+        };
+        if machine(&triple).is_none() || triple.operating_system != OperatingSystem::Linux {
+            return Err(Trap::UnsupportedFeature(format!(
+                "shared libraries for {triple}"
+            )));
+        }
+        let features = compiler.cpu_features.as_deref().unwrap_or_default();
+        let target = format!("{triple} {}", features.join(","));
+        Ok((triple, target))
+    }
+
+    /// The ELF machine of `triple`'s architecture, if libraries are written
+    /// for it, and the largest page size its kernels use.
+    fn machine(triple: &Triple) -> Option<(u16, usize)> {
+        match triple.architecture {
+            Architecture::X86_64 => Some((62, 4 << 10)),
+            Architecture::Aarch64(_) => Some((183, 64 << 10)),
+            _ => None,
+        }
+    }
+
+    /// A SysV `.hash` table over symbols 1.. named `names`.
+    fn sysv_hash(names: &[String]) -> Vec<u8> {
+        let nsyms = names.len() + 1;
+        let nbucket = names.len().max(1);
+        let mut buckets = vec![0u32; nbucket];
+        let mut chains = vec![0u32; nsyms];
+        for (i, name) in names.iter().enumerate() {
+            let b = elf_hash(name.as_bytes()) as usize % nbucket;
+            chains[i + 1] = buckets[b];
+            buckets[b] = i as u32 + 1;
+        }
+        [nbucket as u32, nsyms as u32]
+            .into_iter()
+            .chain(buckets)
+            .chain(chains)
+            .flat_map(u32::to_le_bytes)
+            .collect()
+    }
+
+    fn elf_hash(name: &[u8]) -> u32 {
+        let mut h = 0u32;
+        for &c in name {
+            h = (h << 4).wrapping_add(c as u32);
+            let g = h & 0xF000_0000;
+            if g != 0 {
+                h ^= g >> 24;
+            }
+            h &= !g;
+        }
+        h
+    }
+
+    fn align(n: usize, to: usize) -> usize {
+        n.next_multiple_of(to)
+    }
+
+    struct Writer<'a> {
+        out: &'a mut Vec<u8>,
+    }
+
+    impl Writer<'_> {
+        fn put(&mut self, at: usize, bytes: &[u8]) {
+            self.out[at..at + bytes.len()].copy_from_slice(bytes);
+        }
+
+        /// Replace the bits `mask` selects of the instruction at `at` with
+        /// those of `bits`.
+        fn patch(&mut self, at: usize, mask: u32, bits: u32) {
+            let insn = u32::from_le_bytes(self.out[at..at + 4].try_into().expect("4 bytes"));
+            self.put(at, &(insn & !mask | bits & mask).to_le_bytes());
+        }
+    }
+}
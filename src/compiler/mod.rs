@@ -1,7 +1,234 @@
-//! Cranelift AOT compiler backend (Phase 1 stub).
+//! Native code generation, behind the `cranelift` feature.
 //!
-//! In the full implementation this module translates RuneIR → Cranelift IR →
-//! native machine code. For the MVP, execution goes through the interpreter in
-//! `instance.rs`.
+//! [`codegen`] translates each function of a module from RuneIR to
+//! Cranelift IR and compiles it for the host into a [`NativeModule`].
+//! Instances run their exports on that code instead of the interpreter,
+//! with the same results, traps, fuel accounting and backtraces. A function
+//! that fails the [verifier](crate::verify), say one whose stack height
+//! differs between two paths into a block, stays interpreted; compiled and
+//! interpreted functions call each other freely. [`baseline`] compiles
+//! much faster and produces slower code, for modules that must start fast.
+//!
+//! A [`Runtime`](crate::runtime::Runtime) compiles a module when it is first
+//! instantiated under [`Strategy::Cranelift`] or [`Strategy::Baseline`], or
+//! on request with
+//! [`compile_background`](crate::runtime::Runtime::compile_background),
+//! and shares the code between all of that module's instances. Under
+//! [`Strategy::Tiered`] it compiles functions one by one as they get hot.
+//! With [`enable_code_cache`](crate::runtime::Runtime::enable_code_cache),
+//! code is also saved to disk and loaded from there by later runs.
+
+use std::sync::{Arc, Mutex, OnceLock, PoisonError, Weak};
+
+use crate::{
+    config::{RuntimeConfig, Strategy},
+    ir::Op,
+    module::Module,
+    verify::verify_function,
+};
 
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) mod artifact;
+pub mod baseline;
+pub(crate) mod code_cache;
 pub mod codegen;
+pub(crate) mod debug_info;
+pub mod embedded;
+pub mod library;
+pub(crate) mod native;
+pub(crate) mod ssa;
+pub(crate) mod tier;
+
+pub(crate) use code_cache::CodeCache;
+pub use native::NativeModule;
+pub(crate) use tier::Tiers;
+
+/// A module's native code, set once compiling finishes: `None` if it
+/// failed and the module stays interpreted.
+pub(crate) type CodeSlot = OnceLock<Option<Arc<NativeModule>>>;
+
+/// Compile `module` for `config` with the backend its strategy names, or
+/// `None` if the host cannot run native code or code generation failed.
+/// Goes through `cache` if given.
+pub(crate) fn compile(
+    module: &Module,
+    config: &RuntimeConfig,
+    cache: Option<&CodeCache>,
+) -> Option<Arc<NativeModule>> {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    if let Some(cache) = cache {
+        return cache.compile(module, config).ok().map(Arc::new);
+    }
+    #[cfg(not(all(target_os = "linux", target_arch = "x86_64")))]
+    let _ = cache;
+    let code = match config.strategy() {
+        Strategy::Baseline => baseline::compile(module, config),
+        _ => codegen::compile(module, config),
+    };
+    code.ok().map(Arc::new)
+}
+
+/// Which of the functions `include` selects pass the
+/// [verifier](crate::verify); the compilers only generate code for those.
+pub(crate) fn verified(module: &Module, config: &RuntimeConfig, include: &[bool]) -> Vec<bool> {
+    module
+        .functions
+        .iter()
+        .zip(include)
+        .map(|(f, &include)| include && verify_function(module, f, config).is_ok())
+        .collect()
+}
+
+/// SHA-256 of the settings in `config` that compiled code depends on, so
+/// code kept beyond the runtime that compiled it is only run under the
+/// same.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) fn settings_digest(config: &RuntimeConfig) -> [u8; 32] {
+    use crate::{
+        extension::{EXT_OPCODE_FIRST, EXT_OPCODE_LAST},
+        hash::Sha256,
+        memory::Poison,
+    };
+
+    let mut h = Sha256::new();
+    h.update(format!("{:?}", config.address_overflow()).as_bytes());
+    h.update(&[config.strict_alignment().into()]);
+    // Guard pages leave bounds to the hardware only without poison checks.
+    h.update(&[(config.poison() == Poison::Check).into()]);
+    // Extension ops compile to calls typed by their signatures.
+    for opcode in EXT_OPCODE_FIRST..=EXT_OPCODE_LAST {
+        if let Some(ext) = config.extension(opcode) {
+            h.update(format!("{opcode}{:?}", ext.ty).as_bytes());
+        }
+    }
+    h.finish()
+}
+
+/// A runtime's native code, one entry per module, found by the identity
+/// of the module's function bodies so clones of a module share code.
+/// Entries go once the module is dropped.
+#[derive(Default)]
+pub(crate) struct CodeRegistry {
+    entries: Mutex<Vec<RegistryEntry>>,
+    cache: Mutex<Option<Arc<CodeCache>>>,
+}
+
+/// A module's function bodies and its code.
+struct RegistryEntry {
+    bodies: Vec<Weak<Vec<Op>>>,
+    slot: Arc<CodeSlot>,
+    tiers: Option<Arc<Tiers>>,
+}
+
+impl CodeRegistry {
+    /// The slot for `module`'s code, empty until something compiles it.
+    pub(crate) fn slot(&self, module: &Module) -> Arc<CodeSlot> {
+        self.with_entry(module, |entry| entry.slot.clone())
+    }
+
+    /// The tiering state of `module`, created as `config` says on first
+    /// use.
+    pub(crate) fn tiers(&self, module: &Module, config: &RuntimeConfig) -> Arc<Tiers> {
+        self.with_entry(module, |entry| {
+            entry
+                .tiers
+                .get_or_insert_with(|| Arc::new(Tiers::new(module, config)))
+                .clone()
+        })
+    }
+
+    pub(crate) fn cache(&self) -> Option<Arc<CodeCache>> {
+        self.cache
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    pub(crate) fn set_cache(&self, cache: CodeCache) {
+        *self.cache.lock().unwrap_or_else(PoisonError::into_inner) = Some(Arc::new(cache));
+    }
+
+    fn with_entry<T>(&self, module: &Module, f: impl FnOnce(&mut RegistryEntry) -> T) -> T {
+        let mut entries = self.entries.lock().unwrap_or_else(PoisonError::into_inner);
+        entries.retain(|entry| entry.bodies.iter().all(|b| b.strong_count() > 0));
+        let found = entries.iter().position(|entry| {
+            entry.bodies.len() == module.functions.len()
+                && entry
+                    .bodies
+                    .iter()
+                    .zip(&module.functions)
+                    .all(|(b, f)| std::ptr::eq(b.as_ptr(), Arc::as_ptr(&f.body)))
+        });
+        let index = found.unwrap_or_else(|| {
+            entries.push(RegistryEntry {
+                bodies: module
+                    .functions
+                    .iter()
+                    .map(|f| Arc::downgrade(&f.body))
+                    .collect(),
+                slot: Arc::new(CodeSlot::new()),
+                tiers: None,
+            });
+            entries.len() - 1
+        });
+        f(&mut entries[index])
+    }
+}
+
+/// Length of the fuel run starting at each op that starts one, else 0.
+/// Compiled code charges a run's fuel at once: a run starts at the first
+/// op, after each op ending one and at each loop, which a branch can jump
+/// back to.
+pub(crate) fn fuel_runs(ops: &[Op]) -> Vec<u32> {
+    let mut runs = vec![0u32; ops.len()];
+    let mut len = 0u32;
+    for i in (0..ops.len()).rev() {
+        let next_is_loop = matches!(ops.get(i + 1), Some(Op::Loop(_)));
+        len = if ends_run(&ops[i]) || next_is_loop {
+            1
+        } else {
+            len + 1
+        };
+        let starts = i == 0 || ends_run(&ops[i - 1]) || matches!(ops[i], Op::Loop(_));
+        runs[i] = if starts { len } else { 0 };
+    }
+    runs
+}
+
+/// Whether `op` ends a fuel run: it has an effect beyond its result, or
+/// control may leave the straight line after it.
+fn ends_run(op: &Op) -> bool {
+    matches!(
+        op,
+        Op::I32Load { .. }
+            | Op::I64Load { .. }
+            | Op::F32Load { .. }
+            | Op::F64Load { .. }
+            | Op::I32Store { .. }
+            | Op::I64Store { .. }
+            | Op::F32Store { .. }
+            | Op::F64Store { .. }
+            | Op::MemoryGrow
+            | Op::MemoryDiscard
+            | Op::GlobalSet(_)
+            | Op::I32DivS
+            | Op::I32DivU
+            | Op::I32RemS
+            | Op::I32RemU
+            | Op::I64DivS
+            | Op::I64DivU
+            | Op::I64RemS
+            | Op::I64RemU
+            | Op::Unreachable
+            | Op::Loop(_)
+            | Op::If(_)
+            | Op::Else
+            | Op::End
+            | Op::Br(_)
+            | Op::BrIf(_)
+            | Op::Return
+            | Op::Call(_)
+            | Op::CallHost(_)
+            | Op::Ext { .. }
+    )
+}
@@ -0,0 +1,665 @@
+//! Compiled code and what connects it to an instance: the context native
+//! functions are passed, the helpers they call for anything beyond
+//! arithmetic and in-bounds memory access, and the entry into a function.
+//!
+//! Every native function takes a pointer to a [`VmCtx`] first. Fuel, call
+//! depth and the memory windows live there, so compiled code charges and
+//! checks them with plain loads and stores. A trap is recorded by
+//! `rune_trap`, which sets `trapped`; after every call, compiled code checks
+//! the flag and, if set, records its own frame and returns, so the
+//! backtrace is built innermost first as the interpreter builds it.
+//!
+//! Host functions are called through a trampoline, `rune_call_host`,
+//! rather than the interpreter: compiled code stores the arguments' bits
+//! in its frame, and the trampoline reads them into a buffer on its own
+//! stack, lends the instance to the function through a `Caller`, and
+//! stores the result's bits back. A trap the function returns is recorded
+//! like any helper's. The memory windows need no refresh afterwards: a
+//! host function reaches memory only through a `MemoryView`, which can
+//! neither resize nor protect it.
+
+use std::any::Any;
+use std::cell::Cell;
+use std::mem::{offset_of, size_of};
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::Arc;
+
+use cranelift_jit::JITModule;
+
+use crate::{
+    global::{from_bits, to_bits},
+    instance::{Instance, PreparedFunc},
+    memory::PAGE_SIZE,
+    trap::{Result, Trap},
+    types::{Val, ValType},
+};
+
+/// Entry trampoline of a compiled function: arguments and result are
+/// passed as the bits [`to_bits`] gives.
+pub(crate) type Entry = unsafe extern "C" fn(*mut VmCtx, *const u64, *mut u64);
+
+/// A module compiled to native code for this host, by
+/// [`codegen::compile`](super::codegen::compile) or
+/// [`baseline::compile`](super::baseline::compile). Functions the compiler
+/// could not handle are left to the interpreter.
+pub struct NativeModule {
+    code: Option<CodeMemory>, // taken only to free the code on drop
+    entries: Vec<Option<Entry>>,
+    /// Loads and stores that leave bounds to guard pages: the address of
+    /// each, which faults if the access misses, and of the code that makes
+    /// the access through a helper instead. Sorted.
+    faults: Vec<(usize, usize)>,
+}
+
+/// Where a module's machine code lives.
+pub(crate) enum CodeMemory {
+    Cranelift(Box<JITModule>),
+    /// An [`Artifact`](super::artifact::Artifact) loaded into memory.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    Mapped(crate::sys::Mapping),
+    /// A shared library built by [`library`](super::library).
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    Library(crate::sys::Library),
+    /// Other modules' code, whose entries this one picks from.
+    Shared(Vec<Arc<NativeModule>>),
+}
+
+// SAFETY: once its code is finalized the code memory is only used to free
+// it on drop; the code is immutable and the entries are plain function
+// pointers.
+unsafe impl Send for NativeModule {}
+unsafe impl Sync for NativeModule {}
+
+impl NativeModule {
+    pub(crate) fn new(code: CodeMemory, entries: Vec<Option<Entry>>) -> Self {
+        NativeModule {
+            code: Some(code),
+            entries,
+            faults: Vec::new(),
+        }
+    }
+
+    /// This module, with the accesses `faults` leaves to guard pages.
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    pub(crate) fn with_faults(mut self, mut faults: Vec<(usize, usize)>) -> Self {
+        faults.sort_unstable();
+        self.faults = faults;
+        self
+    }
+
+    /// A module running each function from the first of `layers` that
+    /// compiled it.
+    pub(crate) fn layered(layers: Vec<Arc<NativeModule>>) -> Self {
+        let functions = layers.iter().map(|l| l.functions()).max().unwrap_or(0);
+        let entries = (0..functions as u32)
+            .map(|i| layers.iter().find_map(|l| l.entry(i)))
+            .collect();
+        NativeModule {
+            code: Some(CodeMemory::Shared(layers)),
+            entries,
+            faults: Vec::new(),
+        }
+    }
+
+    /// Number of functions in the module, compiled or not.
+    pub fn functions(&self) -> usize {
+        self.entries.len()
+    }
+
+    /// Whether function `index` runs as native code.
+    pub fn is_compiled(&self, index: u32) -> bool {
+        self.entry(index).is_some()
+    }
+
+    /// Number of the module's functions that run as native code.
+    pub fn compiled_functions(&self) -> usize {
+        self.entries.iter().filter(|e| e.is_some()).count()
+    }
+
+    fn entry(&self, index: u32) -> Option<Entry> {
+        self.entries.get(index as usize).copied().flatten()
+    }
+
+    /// Where code faulting at `pc` carries on, if `pc` is one of the
+    /// module's accesses left to guard pages.
+    fn fault_target(&self, pc: usize) -> Option<usize> {
+        if let Some(CodeMemory::Shared(layers)) = &self.code {
+            return layers.iter().find_map(|layer| layer.fault_target(pc));
+        }
+        let i = self.faults.binary_search_by_key(&pc, |&(at, _)| at).ok()?;
+        Some(self.faults[i].1)
+    }
+}
+
+thread_local! {
+    /// The module whose code this thread is running, if any.
+    static RUNNING: Cell<*const NativeModule> = const { Cell::new(std::ptr::null()) };
+}
+
+/// Where the code running on this thread carries on after a fault at `pc`,
+/// if the fault is an access missing its memory.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+fn redirect_fault(pc: usize) -> Option<usize> {
+    RUNNING
+        .try_with(|running| {
+            // SAFETY: `run` points `RUNNING` at a module only while its code
+            // runs, holding a reference to it.
+            unsafe { running.get().as_ref() }?.fault_target(pc)
+        })
+        .ok()
+        .flatten()
+}
+
+/// A reservation nothing can access. Code that leaves bounds to guard
+/// pages reaches memories without them through it, so each of their
+/// accesses faults into the helpers, which check it.
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+static UNGUARDED: std::sync::OnceLock<Option<crate::sys::Mapping>> = std::sync::OnceLock::new();
+
+/// Get the process ready to run code that leaves bounds to guard pages:
+/// catch faults, and reserve [`UNGUARDED`].
+#[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+pub(crate) fn prepare_guard_pages() -> Result<()> {
+    let unguarded = UNGUARDED.get_or_init(|| crate::sys::Mapping::guarded(0, None));
+    if unguarded.is_none() || !crate::sys::redirect_faults(redirect_fault) {
+        return Err(Trap::HostError("cannot set up guard pages".into()));
+    }
+    Ok(())
+}
+
+/// Base of [`UNGUARDED`], or null if no code uses it.
+fn unguarded() -> *mut u8 {
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    if let Some(Some(mapping)) = UNGUARDED.get() {
+        return mapping.as_ptr();
+    }
+    std::ptr::null_mut()
+}
+
+impl Drop for NativeModule {
+    fn drop(&mut self) {
+        // SAFETY: instances hold the module in an `Arc` while they run its
+        // code, so none is running now.
+        match self.code.take() {
+            Some(CodeMemory::Cranelift(jit)) => unsafe { jit.free_memory() },
+            #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+            Some(CodeMemory::Mapped(mapping)) => drop(mapping),
+            #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+            Some(CodeMemory::Library(lib)) => drop(lib),
+            Some(CodeMemory::Shared(layers)) => drop(layers),
+            None => {}
+        }
+    }
+}
+
+/// The part of a call's state compiled code reads and writes directly.
+#[repr(C)]
+pub(crate) struct VmCtx {
+    windows: *const Window,
+    /// Fuel left; `u64::MAX` when the instance is not metered.
+    fuel: u64,
+    /// Guest functions active, the running one included.
+    depth: u64,
+    max_depth: u64,
+    /// Nonzero if deadlines need checking at loops and calls.
+    interruptible: u32,
+    trapped: u32,
+}
+
+impl VmCtx {
+    pub(crate) const WINDOWS: i32 = offset_of!(VmCtx, windows) as i32;
+    pub(crate) const FUEL: i32 = offset_of!(VmCtx, fuel) as i32;
+    pub(crate) const DEPTH: i32 = offset_of!(VmCtx, depth) as i32;
+    pub(crate) const MAX_DEPTH: i32 = offset_of!(VmCtx, max_depth) as i32;
+    pub(crate) const INTERRUPTIBLE: i32 = offset_of!(VmCtx, interruptible) as i32;
+    pub(crate) const TRAPPED: i32 = offset_of!(VmCtx, trapped) as i32;
+}
+
+/// One linear memory as compiled code sees it.
+#[repr(C)]
+pub(crate) struct Window {
+    base: *mut u8,
+    size: u64,
+    /// Bytes from `base` that can be accessed without a helper.
+    direct: u64,
+    /// Where code that leaves bounds to guard pages accesses the memory:
+    /// `base` if every access past `direct` faults, otherwise
+    /// [`UNGUARDED`], where every access does.
+    guarded: *mut u8,
+}
+
+impl Window {
+    pub(crate) const BASE: i32 = offset_of!(Window, base) as i32;
+    pub(crate) const SIZE: i32 = offset_of!(Window, size) as i32;
+    pub(crate) const DIRECT: i32 = offset_of!(Window, direct) as i32;
+    pub(crate) const GUARDED: i32 = offset_of!(Window, guarded) as i32;
+    pub(crate) const STRIDE: i64 = size_of::<Window>() as i64;
+}
+
+/// Why compiled code trapped, as passed to `rune_trap`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u32)]
+pub(crate) enum TrapCode {
+    /// A helper or callee already recorded the trap.
+    Pending = 0,
+    OutOfBounds,
+    DivisionByZero,
+    Unreachable,
+    StackOverflow,
+    TypeMismatch,
+    UnalignedAccess,
+    OutOfFuel,
+}
+
+impl TrapCode {
+    fn trap(code: u32) -> Option<Trap> {
+        Some(match code {
+            1 => Trap::OutOfBounds,
+            2 => Trap::DivisionByZero,
+            3 => Trap::Unreachable,
+            4 => Trap::StackOverflow,
+            5 => Trap::TypeMismatch,
+            6 => Trap::UnalignedAccess,
+            7 => Trap::OutOfFuel,
+            _ => return None,
+        })
+    }
+}
+
+/// Helper argument and result types, as compiled code passes them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Abi {
+    Ptr,
+    I32,
+    I64,
+}
+
+/// The runtime functions compiled code calls. All but `Trap` return a
+/// status: nonzero if they trapped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Helper {
+    Trap,
+    CheckDeadlines,
+    Call,
+    CallHost,
+    CallExt,
+    Load,
+    Store,
+    MemoryGrow,
+    MemoryDiscard,
+    GlobalGet,
+    GlobalSet,
+}
+
+impl Helper {
+    pub(crate) const ALL: [Helper; 11] = [
+        Helper::Trap,
+        Helper::CheckDeadlines,
+        Helper::Call,
+        Helper::CallHost,
+        Helper::CallExt,
+        Helper::Load,
+        Helper::Store,
+        Helper::MemoryGrow,
+        Helper::MemoryDiscard,
+        Helper::GlobalGet,
+        Helper::GlobalSet,
+    ];
+
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Helper::Trap => "rune_trap",
+            Helper::CheckDeadlines => "rune_check_deadlines",
+            Helper::Call => "rune_call",
+            Helper::CallHost => "rune_call_host",
+            Helper::CallExt => "rune_call_ext",
+            Helper::Load => "rune_load",
+            Helper::Store => "rune_store",
+            Helper::MemoryGrow => "rune_memory_grow",
+            Helper::MemoryDiscard => "rune_memory_discard",
+            Helper::GlobalGet => "rune_global_get",
+            Helper::GlobalSet => "rune_global_set",
+        }
+    }
+
+    /// Parameters after the leading `VmCtx` pointer.
+    pub(crate) fn params(self) -> &'static [Abi] {
+        use Abi::*;
+        match self {
+            Helper::Trap => &[I32, I32, I32],
+            Helper::CheckDeadlines => &[],
+            Helper::Call | Helper::CallHost => &[I32, Ptr, Ptr],
+            Helper::CallExt => &[I32, I32, Ptr, Ptr],
+            Helper::Load => &[I32, I64, I32, Ptr],
+            Helper::Store => &[I32, I64, I32, I64],
+            Helper::MemoryGrow => &[I32, Ptr],
+            Helper::MemoryDiscard => &[I32, I32],
+            Helper::GlobalGet => &[I32, Ptr],
+            Helper::GlobalSet => &[I32, I64],
+        }
+    }
+
+    pub(crate) fn returns_status(self) -> bool {
+        self != Helper::Trap
+    }
+
+    pub(crate) fn address(self) -> *const u8 {
+        match self {
+            Helper::Trap => rune_trap as *const u8,
+            Helper::CheckDeadlines => rune_check_deadlines as *const u8,
+            Helper::Call => rune_call as *const u8,
+            Helper::CallHost => rune_call_host as *const u8,
+            Helper::CallExt => rune_call_ext as *const u8,
+            Helper::Load => rune_load as *const u8,
+            Helper::Store => rune_store as *const u8,
+            Helper::MemoryGrow => rune_memory_grow as *const u8,
+            Helper::MemoryDiscard => rune_memory_discard as *const u8,
+            Helper::GlobalGet => rune_global_get as *const u8,
+            Helper::GlobalSet => rune_global_set as *const u8,
+        }
+    }
+}
+
+/// A call's full state. `vm` comes first so helpers can recover the whole
+/// context from the pointer compiled code passes them.
+#[repr(C)]
+struct Context {
+    vm: VmCtx,
+    instance: *mut Instance<'static>,
+    windows: Vec<Window>,
+    pending: Option<Trap>,
+    panic: Option<Box<dyn Any + Send>>,
+}
+
+impl Context {
+    /// Point the windows at the instance's memories again, after anything
+    /// that could grow, move or protect them.
+    fn refresh(&mut self, instance: &mut Instance<'_>) {
+        self.windows.clear();
+        self.windows
+            .extend(instance.memories.iter_mut().map(|memory| {
+                let (base, size, direct, guarded) = memory.native_window();
+                Window {
+                    base,
+                    size: size as u64,
+                    direct: direct as u64,
+                    guarded: if guarded { base } else { unguarded() },
+                }
+            }));
+        self.vm.windows = self.windows.as_ptr();
+    }
+}
+
+/// Run compiled function `pf` of `code` on `instance`, in place of the
+/// interpreter; `locals` starts with its arguments.
+pub(crate) fn run(
+    instance: &mut Instance<'_>,
+    code: &NativeModule,
+    pf: &PreparedFunc,
+    locals: &[Val],
+) -> Result<Option<Val>> {
+    let entry = code.entry(pf.index).ok_or(Trap::TypeMismatch)?;
+    if let Err(trap) = instance.check_call_limits() {
+        instance.push_trap_frame(pf, 0);
+        return Err(trap);
+    }
+    let args: Vec<u64> = locals[..pf.n_params].iter().map(|&v| to_bits(v)).collect();
+    let depth = instance.depth();
+    let metered = instance.fuel().is_some();
+    let mut cx = Context {
+        vm: VmCtx {
+            windows: std::ptr::null(),
+            fuel: instance.fuel().unwrap_or(u64::MAX),
+            depth: depth as u64,
+            max_depth: instance
+                .config()
+                .max_call_depth()
+                .map_or(u64::MAX, |max| max as u64),
+            interruptible: instance.is_interruptible().into(),
+            trapped: 0,
+        },
+        instance: (instance as *mut Instance<'_>).cast(),
+        windows: Vec::with_capacity(instance.memories.len()),
+        pending: None,
+        panic: None,
+    };
+    cx.refresh(instance);
+    let mut ret = 0u64;
+    let outer = RUNNING.replace(code);
+    // SAFETY: `entry` was compiled for this module with the signature of
+    // `Entry`, and `cx` outlives the call. The instance is only reached
+    // through `cx.instance` until it returns.
+    unsafe { entry(&mut cx.vm, args.as_ptr(), &mut ret) };
+    RUNNING.set(outer);
+    instance.set_depth(depth);
+    if metered {
+        instance.set_fuel(cx.vm.fuel);
+    }
+    if let Some(payload) = cx.panic.take() {
+        panic::resume_unwind(payload);
+    }
+    if cx.vm.trapped != 0 {
+        return Err(cx.pending.take().unwrap_or(Trap::Unreachable));
+    }
+    Ok(pf.result_type.map(|ty| from_bits(ty, ret)))
+}
+
+/// Run a helper's body on the context behind `vm`, recording a trap or a
+/// panic for [`run`] and returning 1 if there was one.
+///
+/// # Safety
+/// `vm` must be the context of a call in progress.
+unsafe fn helper(
+    vm: *mut VmCtx,
+    body: impl FnOnce(&mut Context, &mut Instance<'static>) -> Result<()>,
+) -> u32 {
+    let cx = &mut *vm.cast::<Context>();
+    let instance = &mut *cx.instance;
+    match panic::catch_unwind(AssertUnwindSafe(|| body(cx, instance))) {
+        Ok(Ok(())) => 0,
+        Ok(Err(trap)) => {
+            cx.pending = Some(trap);
+            1
+        }
+        Err(payload) => {
+            cx.panic = Some(payload);
+            1
+        }
+    }
+}
+
+/// Most arguments [`read_args`] holds on the stack.
+const INLINE_ARGS: usize = 8;
+
+/// Arguments compiled code passed, held on the stack unless there are many.
+enum Args {
+    Inline([Val; INLINE_ARGS], usize),
+    Spilled(Vec<Val>),
+}
+
+impl std::ops::Deref for Args {
+    type Target = [Val];
+
+    fn deref(&self) -> &[Val] {
+        match self {
+            Args::Inline(vals, n) => &vals[..*n],
+            Args::Spilled(vals) => vals,
+        }
+    }
+}
+
+/// The `n` arguments at `args`, typed by `params`.
+unsafe fn read_args(params: &[ValType], args: *const u64) -> Args {
+    let read = |i: usize| from_bits(params[i], *args.add(i));
+    if params.len() > INLINE_ARGS {
+        return Args::Spilled((0..params.len()).map(read).collect());
+    }
+    let mut vals = [Val::I32(0); INLINE_ARGS];
+    for (i, val) in vals.iter_mut().enumerate().take(params.len()) {
+        *val = read(i);
+    }
+    Args::Inline(vals, params.len())
+}
+
+/// Store a callee's result at `ret`, which compiled code reads as `ty`.
+unsafe fn write_result(ret: *mut u64, ty: Option<ValType>, result: Option<Val>) -> Result<()> {
+    match (ty, result) {
+        (None, _) => Ok(()),
+        (Some(ty), Some(val)) if val.ty() == ty => {
+            *ret = to_bits(val);
+            Ok(())
+        }
+        _ => Err(Trap::TypeMismatch),
+    }
+}
+
+/// Record a trap of `code` (unless a helper already did) and the frame of
+/// function `func` at `op`.
+unsafe extern "C" fn rune_trap(vm: *mut VmCtx, code: u32, func: u32, op: u32) {
+    let cx = &mut *vm.cast::<Context>();
+    if let Some(trap) = TrapCode::trap(code) {
+        cx.pending = Some(trap);
+    }
+    cx.vm.trapped = 1;
+    let instance = &mut *cx.instance;
+    if let Some(pf) = instance.prepared_func(func) {
+        instance.push_trap_frame(&pf, op as usize);
+    }
+}
+
+unsafe extern "C" fn rune_check_deadlines(vm: *mut VmCtx) -> u32 {
+    helper(vm, |_, instance| instance.check_deadlines())
+}
+
+/// Call a function that stays interpreted. The depth is already counted.
+unsafe extern "C" fn rune_call(vm: *mut VmCtx, func: u32, args: *const u64, ret: *mut u64) -> u32 {
+    helper(vm, |cx, instance| {
+        let pf = instance.prepared_func(func).ok_or(Trap::TypeMismatch)?;
+        let args = read_args(&instance.module().functions[func as usize].ty.params, args);
+        instance.set_depth(cx.vm.depth as usize);
+        let metered = instance.fuel().is_some();
+        if metered {
+            instance.set_fuel(cx.vm.fuel);
+        }
+        let result = instance.call_prepared(&pf, &args);
+        if metered {
+            cx.vm.fuel = instance.fuel().unwrap_or(0);
+        }
+        cx.refresh(instance);
+        write_result(ret, pf.result_type, result?)
+    })
+}
+
+/// The trampoline into host function `import`; see the module docs.
+unsafe extern "C" fn rune_call_host(
+    vm: *mut VmCtx,
+    import: u32,
+    args: *const u64,
+    ret: *mut u64,
+) -> u32 {
+    helper(vm, |_, instance| {
+        let ty = &instance.module().imports[import as usize].ty;
+        let result_ty = ty.results.first().copied();
+        let args = read_args(&ty.params, args);
+        let result = instance.call_host(import as usize, &args);
+        write_result(ret, result_ty, result?)
+    })
+}
+
+unsafe extern "C" fn rune_call_ext(
+    vm: *mut VmCtx,
+    opcode: u32,
+    imm: u32,
+    args: *const u64,
+    ret: *mut u64,
+) -> u32 {
+    helper(vm, |cx, instance| {
+        let config = instance.config().clone();
+        let ext = config
+            .extension(opcode as u8)
+            .ok_or_else(|| Trap::UnsupportedFeature(format!("extension opcode {opcode:#04x}")))?;
+        let args = read_args(&ext.ty.params, args);
+        let result = ext.handler.execute(imm, &args, &mut instance.memories[0]);
+        cx.refresh(instance);
+        write_result(ret, ext.ty.results.first().copied(), result?)
+    })
+}
+
+/// A load compiled code could not do directly: out of bounds, or the
+/// memory checks protection or poison.
+unsafe extern "C" fn rune_load(
+    vm: *mut VmCtx,
+    memory: u32,
+    addr: u64,
+    size: u32,
+    out: *mut u64,
+) -> u32 {
+    helper(vm, |_, instance| {
+        let memory = &instance.memories[memory as usize];
+        let addr = usize::try_from(addr).map_err(|_| Trap::OutOfBounds)?;
+        *out = match size {
+            4 => memory.read_u32(addr)?.into(),
+            _ => memory.read_u64(addr)?,
+        };
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn rune_store(
+    vm: *mut VmCtx,
+    memory: u32,
+    addr: u64,
+    size: u32,
+    bits: u64,
+) -> u32 {
+    helper(vm, |_, instance| {
+        let memory = &mut instance.memories[memory as usize];
+        let addr = usize::try_from(addr).map_err(|_| Trap::OutOfBounds)?;
+        match size {
+            4 => memory.write_u32(addr, bits as u32),
+            _ => memory.write_u64(addr, bits),
+        }
+    })
+}
+
+unsafe extern "C" fn rune_memory_grow(vm: *mut VmCtx, delta: u32, out: *mut u64) -> u32 {
+    helper(vm, |cx, instance| {
+        let delta = delta as i32 as usize;
+        let old = instance.memories[0]
+            .grow(delta)
+            .map(|p| p as i32)
+            .unwrap_or(-1);
+        *out = old as u32 as u64;
+        cx.refresh(instance);
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn rune_memory_discard(vm: *mut VmCtx, addr: u32, len: u32) -> u32 {
+    helper(vm, |cx, instance| {
+        let (addr, len) = (addr as usize, len as usize);
+        if !addr.is_multiple_of(PAGE_SIZE) || !len.is_multiple_of(PAGE_SIZE) {
+            return Err(Trap::OutOfBounds);
+        }
+        let first = addr / PAGE_SIZE;
+        let result = instance.memories[0].discard(first..first + len / PAGE_SIZE);
+        cx.refresh(instance);
+        result
+    })
+}
+
+unsafe extern "C" fn rune_global_get(vm: *mut VmCtx, idx: u32, out: *mut u64) -> u32 {
+    helper(vm, |_, instance| {
+        let global = instance.global(idx as usize).ok_or(Trap::TypeMismatch)?;
+        *out = to_bits(global.get());
+        Ok(())
+    })
+}
+
+unsafe extern "C" fn rune_global_set(vm: *mut VmCtx, idx: u32, bits: u64) -> u32 {
+    helper(vm, |_, instance| {
+        let ty = instance.module().global_imports[idx as usize].ty;
+        let global = instance.global(idx as usize).ok_or(Trap::TypeMismatch)?;
+        global.set(from_bits(ty, bits))
+    })
+}
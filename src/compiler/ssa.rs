@@ -0,0 +1,563 @@
+//! Lowering of RuneIR's operand stack to virtual registers, shared by the
+//! backends.
+//!
+//! Every value an op pushes becomes a register of its own, defined by that
+//! op alone, and every op lists the registers it reads. The backends
+//! translate from this form rather than each replaying the operand stack
+//! themselves: Cranelift binds each register to an SSA value, and the
+//! baseline JIT gives each a frame slot.
+//!
+//! Where control flow merges, at the `End` of a block with a result, the
+//! `End` defines a register that takes its value from whichever way the
+//! block was left: the fall through into the `End` or a branch to the
+//! block, each carrying the value it leaves with. It is an SSA block
+//! parameter, in Cranelift's terms.
+//!
+//! Only functions that pass the [verifier](crate::verify) are lowered, so
+//! the stack is known to agree on every path and ops to find operands of
+//! their types.
+//!
+//! Unless the [optimization level](crate::config::OptLevel) is `None`, ops
+//! inside a loop that compute the same value every time round, such as
+//! constants, reads of locals the loop never writes, and arithmetic on
+//! those, are hoisted: the backends run them once before the loop starts,
+//! and where the loop had them only reuse their results. They neither trap
+//! nor have effects, so running them early, or when the loop would not
+//! have, changes nothing; fuel is still charged for them where they were.
+
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    config::{OptLevel, RuntimeConfig},
+    instance::PreparedFunc,
+    ir::BlockType,
+    ir::Op,
+    module::Module,
+    types::ValType,
+};
+
+/// A virtual register, numbered from 0 in the order of definition.
+pub(crate) type VReg = u32;
+
+/// A function's ops in register form.
+pub(crate) struct Body {
+    /// What each op reads and defines, by op index.
+    pub ops: Vec<Operands>,
+    /// Falling off the end of a body without a final `End`, which returns
+    /// like one.
+    pub tail: Operands,
+    /// The type of each register.
+    pub types: Vec<ValType>,
+    /// The operand stack height each register is pushed at.
+    pub heights: Vec<u32>,
+    /// The most values on the operand stack at once.
+    pub max_height: usize,
+    /// The loop each op is hoisted out of, by op index; ops past the end
+    /// are not.
+    pub hoisted: Vec<Option<Hoist>>,
+    /// How many hoisted ops have results read in their loops.
+    pub roots: usize,
+}
+
+/// An op hoisted out of a loop.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Hoist {
+    /// The `Loop` op it runs before.
+    pub to: usize,
+    /// For an op whose result ops left in the loop read, its number among
+    /// such ops of the function, from 0. The results of the others are
+    /// read only by other hoisted ops.
+    pub root: Option<u32>,
+}
+
+/// The registers an op reads and defines.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub(crate) struct Operands {
+    /// Whether the op can run. Ops that cannot read nothing, but an `End`
+    /// among them still defines the result of a block branched out of.
+    pub reachable: bool,
+    /// The registers it reads, the top of the stack last: its operands,
+    /// or the value a branch, `Else`, `End` or `Return` carries. A `BrIf`
+    /// reads the value it carries, which stays on the stack, and then its
+    /// condition.
+    pub uses: Vec<VReg>,
+    /// The register it defines: its result, or for an `End`, the block's
+    /// result when anything reaches the end of the block.
+    pub def: Option<VReg>,
+}
+
+impl Body {
+    /// The operands of op `i`, or of the `tail` just past the last op.
+    pub fn at(&self, i: usize) -> &Operands {
+        self.ops.get(i).unwrap_or(&self.tail)
+    }
+
+    /// How op `i` is hoisted, if it is.
+    pub fn hoisted(&self, i: usize) -> Option<Hoist> {
+        self.hoisted.get(i).copied().flatten()
+    }
+
+    /// The ops hoisted out of the loop at op `start`, whose `End` is op
+    /// `end`, in the order they run.
+    pub fn preheader(&self, start: usize, end: usize) -> Vec<usize> {
+        (start + 1..end)
+            .filter(|&j| self.hoisted(j).is_some_and(|h| h.to == start))
+            .collect()
+    }
+}
+
+/// Lower `pf`, a function of `module` with extension ops typed by
+/// `config`, or `None` if its ops do not keep to the discipline the
+/// verifier checks.
+pub(crate) fn lower(module: &Module, config: &RuntimeConfig, pf: &PreparedFunc) -> Option<Body> {
+    let mut body = registers(module, config, pf)?;
+    if config.compiler().opt_level != OptLevel::None {
+        hoist(&mut body, &pf.ops);
+    }
+    Some(body)
+}
+
+fn registers(module: &Module, config: &RuntimeConfig, pf: &PreparedFunc) -> Option<Body> {
+    let ty = &module.functions.get(pf.index as usize)?.ty;
+    let mut l = Lowering {
+        module,
+        config,
+        pf,
+        locals: ty.params.iter().chain(&pf.extra_locals).copied().collect(),
+        result: pf.result_type,
+        body: Body {
+            ops: Vec::with_capacity(pf.ops.len()),
+            tail: Operands::default(),
+            types: Vec::new(),
+            heights: Vec::new(),
+            max_height: 0,
+            hoisted: Vec::new(),
+            roots: 0,
+        },
+        stack: Vec::new(),
+        frames: Vec::new(),
+        reachable: true,
+    };
+    for (i, op) in pf.ops.iter().enumerate() {
+        let mut operands = Operands {
+            reachable: l.reachable,
+            ..Operands::default()
+        };
+        let done = if l.reachable {
+            l.op(i, op, &mut operands)?
+        } else {
+            l.skip(op, &mut operands)?
+        };
+        l.body.ops.push(operands);
+        if done {
+            return Some(l.body);
+        }
+    }
+    // Falling off the end returns, like the final `End`.
+    if !l.frames.is_empty() {
+        return None;
+    }
+    let mut tail = Operands {
+        reachable: l.reachable,
+        ..Operands::default()
+    };
+    if l.reachable {
+        l.carry(l.result, &mut tail)?;
+    }
+    l.body.tail = tail;
+    Some(l.body)
+}
+
+/// Hoist out of their innermost loop the ops of `body` whose values are
+/// the same every time round. `ops` are the function's ops.
+///
+/// An op is hoisted if it is [invariant](invariant) or reads a local its
+/// loop never writes, and every op whose result it reads is hoisted too.
+/// Constants and reads of locals are left where they are unless a
+/// hoisted op reads them: on their own they cost as much to reuse as to
+/// run again.
+fn hoist(body: &mut Body, ops: &[Op]) {
+    let n = body.ops.len();
+    // The innermost loop around each op, and the locals each loop writes.
+    let mut inner = vec![None; n];
+    let mut written: HashMap<usize, HashSet<u32>> = HashMap::new();
+    let mut open: Vec<Option<usize>> = Vec::new();
+    for (i, op) in ops[..n].iter().enumerate() {
+        let here = open.last().copied().flatten();
+        inner[i] = here;
+        match op {
+            Op::Block(_) | Op::If(_) => open.push(here),
+            Op::Loop(_) => open.push(Some(i)),
+            Op::End => {
+                open.pop();
+            }
+            Op::LocalSet(l) | Op::LocalTee(l) => {
+                for &start in open.iter().flatten() {
+                    written.entry(start).or_default().insert(*l);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // Operands are defined before the ops that read them, so one pass
+    // sees each operand's op decided first.
+    let mut def_op = vec![usize::MAX; body.types.len()];
+    let mut hoisted = vec![false; n];
+    for i in 0..n {
+        let Some(start) = inner[i] else { continue };
+        let operands = &body.ops[i];
+        hoisted[i] = operands.reachable
+            && match ops[i] {
+                Op::LocalGet(l) => !written.get(&start).is_some_and(|w| w.contains(&l)),
+                ref op => invariant(op),
+            }
+            && operands.uses.iter().all(|&reg| {
+                let d = def_op[reg as usize];
+                d != usize::MAX && hoisted[d] && inner[d] == Some(start)
+            });
+        if let Some(reg) = operands.def {
+            def_op[reg as usize] = i;
+        }
+    }
+
+    // Whether each register is read by a hoisted op, and by one left in
+    // the loop.
+    let mut read_hoisted = vec![false; body.types.len()];
+    let mut read_left = vec![false; body.types.len()];
+    for (i, operands) in body.ops.iter().chain([&body.tail]).enumerate() {
+        let read = match hoisted.get(i) {
+            Some(true) => &mut read_hoisted,
+            _ => &mut read_left,
+        };
+        for &reg in &operands.uses {
+            read[reg as usize] = true;
+        }
+    }
+
+    body.hoisted = vec![None; n];
+    for i in 0..n {
+        let Some(reg) = body.ops[i].def.filter(|_| hoisted[i]) else {
+            continue;
+        };
+        if body.ops[i].uses.is_empty() && !read_hoisted[reg as usize] {
+            continue;
+        }
+        let root = read_left[reg as usize].then(|| {
+            body.roots += 1;
+            body.roots as u32 - 1
+        });
+        body.hoisted[i] = inner[i].map(|to| Hoist { to, root });
+    }
+}
+
+/// Whether `op` computes its result from its operands alone, with no
+/// other effect, and never traps.
+fn invariant(op: &Op) -> bool {
+    use Op::*;
+    matches!(
+        op,
+        I32Const(_)
+            | I64Const(_)
+            | F32Const(_)
+            | F64Const(_)
+            | I32Add
+            | I32Sub
+            | I32Mul
+            | I32And
+            | I32Or
+            | I32Xor
+            | I32Shl
+            | I32ShrS
+            | I32ShrU
+            | I64Add
+            | I64Sub
+            | I64Mul
+            | I64And
+            | I64Or
+            | I64Xor
+            | I64Shl
+            | I64ShrS
+            | I64ShrU
+            | I32Eqz
+            | I64Eqz
+            | I32Eq
+            | I32Ne
+            | I32LtS
+            | I32LtU
+            | I32GtS
+            | I32GtU
+            | I32LeS
+            | I32LeU
+            | I32GeS
+            | I32GeU
+            | I64Eq
+            | I64Ne
+            | I64LtS
+            | I64LtU
+            | I64GtS
+            | I64GtU
+            | I64LeS
+            | I64LeU
+            | I64GeS
+            | I64GeU
+            | I32WrapI64
+            | I64ExtendI32S
+            | I64ExtendI32U
+            | F32Add
+            | F32Sub
+            | F32Mul
+            | F32Div
+            | F64Add
+            | F64Sub
+            | F64Mul
+            | F64Div
+    )
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Block,
+    Loop,
+    If,
+}
+
+/// An open block, loop or if.
+struct Frame {
+    kind: Kind,
+    /// Stack height at entry.
+    height: usize,
+    result: Option<ValType>,
+    /// Whether anything reaches its `End`.
+    reached: bool,
+    /// Opened in unreachable code, so nothing in it runs.
+    dead: bool,
+}
+
+struct Lowering<'a> {
+    module: &'a Module,
+    config: &'a RuntimeConfig,
+    pf: &'a PreparedFunc,
+    locals: Vec<ValType>,
+    result: Option<ValType>,
+    body: Body,
+    stack: Vec<VReg>,
+    frames: Vec<Frame>,
+    reachable: bool,
+}
+
+impl Lowering<'_> {
+    /// Lower reachable op `i`, `op`, into `out`; true at the function's
+    /// final `End`.
+    fn op(&mut self, i: usize, op: &Op, out: &mut Operands) -> Option<bool> {
+        if let Some((params, result)) = op.signature() {
+            self.call(params, result, out)?;
+            return Some(false);
+        }
+        match op {
+            Op::Drop => self.pop(out)?,
+            Op::Select => {
+                self.pop(out)?;
+                self.pop(out)?;
+                self.pop(out)?;
+                out.uses.reverse();
+                let ty = self.body.types[out.uses[0] as usize];
+                self.push(ty, out);
+            }
+            Op::LocalGet(l) => {
+                let ty = *self.locals.get(*l as usize)?;
+                self.push(ty, out);
+            }
+            Op::LocalSet(_) | Op::GlobalSet(_) => self.pop(out)?,
+            Op::LocalTee(l) => {
+                let ty = *self.locals.get(*l as usize)?;
+                self.pop(out)?;
+                self.push(ty, out);
+            }
+            Op::GlobalGet(g) => {
+                let ty = self.module.global_imports.get(*g as usize)?.ty;
+                self.push(ty, out);
+            }
+            Op::Unreachable => self.set_unreachable(),
+            Op::Block(bt) => self.open(Kind::Block, bt),
+            Op::Loop(bt) => self.open(Kind::Loop, bt),
+            Op::If(bt) => {
+                self.pop(out)?;
+                self.open(Kind::If, bt);
+                // Without an else, a false condition goes to the `End`.
+                if self.pf.elses.get(i) == Some(&usize::MAX) {
+                    self.frames.last_mut()?.reached = true;
+                }
+            }
+            Op::Else => {
+                let frame = self.frames.last_mut()?;
+                frame.reached = true;
+                let result = frame.result;
+                self.carry(result, out)?;
+                self.enter_else()?;
+            }
+            Op::End => {
+                let Some(frame) = self.frames.last_mut() else {
+                    self.carry(self.result, out)?;
+                    return Some(true);
+                };
+                frame.reached = true;
+                let result = frame.result;
+                self.carry(result, out)?;
+                self.close(out)?;
+            }
+            Op::Br(depth) => {
+                self.branch(*depth, out)?;
+                self.set_unreachable();
+            }
+            Op::BrIf(depth) => {
+                let cond = self.stack.pop()?;
+                self.branch(*depth, out)?;
+                out.uses.push(cond);
+            }
+            Op::Return => {
+                self.carry(self.result, out)?;
+                self.set_unreachable();
+            }
+            Op::Call(f) => {
+                let ty = &self.module.functions.get(*f as usize)?.ty;
+                self.call(&ty.params, ty.results.first().copied(), out)?;
+            }
+            Op::CallHost(i) => {
+                let ty = &self.module.imports.get(*i as usize)?.ty;
+                self.call(&ty.params, ty.results.first().copied(), out)?;
+            }
+            Op::Ext { opcode, .. } => {
+                let ty = &self.config.extension(*opcode)?.ty;
+                self.call(&ty.params, ty.results.first().copied(), out)?;
+            }
+            _ => unreachable!("`{}` has a signature", op.mnemonic()),
+        }
+        Some(false)
+    }
+
+    /// Follow `op` in unreachable code; true at the function's final
+    /// `End`.
+    fn skip(&mut self, op: &Op, out: &mut Operands) -> Option<bool> {
+        match op {
+            Op::Block(bt) | Op::Loop(bt) | Op::If(bt) => {
+                self.open(Kind::Block, bt);
+                self.frames.last_mut()?.dead = true;
+            }
+            Op::Else if !self.frames.last()?.dead => self.enter_else()?,
+            Op::End => match self.frames.last() {
+                None => return Some(true),
+                Some(frame) if frame.dead => {
+                    self.frames.pop();
+                }
+                Some(_) => self.close(out)?,
+            },
+            _ => {}
+        }
+        Some(false)
+    }
+
+    fn push(&mut self, ty: ValType, out: &mut Operands) {
+        let reg = self.body.types.len() as VReg;
+        self.body.types.push(ty);
+        self.body.heights.push(self.stack.len() as u32);
+        self.stack.push(reg);
+        self.body.max_height = self.body.max_height.max(self.stack.len());
+        out.def = Some(reg);
+    }
+
+    /// Pop a value pushed within the innermost block into `out`'s uses,
+    /// which end up top first.
+    fn pop(&mut self, out: &mut Operands) -> Option<()> {
+        let floor = self.frames.last().map_or(0, |f| f.height);
+        if self.stack.len() <= floor {
+            return None;
+        }
+        out.uses.push(self.stack.pop()?);
+        Some(())
+    }
+
+    /// Pop `params`, then push `result`.
+    fn call(
+        &mut self,
+        params: &[ValType],
+        result: Option<ValType>,
+        out: &mut Operands,
+    ) -> Option<()> {
+        for _ in params {
+            self.pop(out)?;
+        }
+        out.uses.reverse();
+        if let Some(ty) = result {
+            self.push(ty, out);
+        }
+        Some(())
+    }
+
+    /// Read the value `result`, if any, that leaves with the op, from the
+    /// top of the stack.
+    fn carry(&mut self, result: Option<ValType>, out: &mut Operands) -> Option<()> {
+        if result.is_some() {
+            out.uses.push(*self.stack.last()?);
+        }
+        Some(())
+    }
+
+    fn open(&mut self, kind: Kind, bt: &BlockType) {
+        self.frames.push(Frame {
+            kind,
+            height: self.stack.len(),
+            result: match bt {
+                BlockType::Empty => None,
+                BlockType::Val(ty) => Some(*ty),
+            },
+            reached: false,
+            dead: false,
+        });
+    }
+
+    /// Close the innermost block at its `End`, defining its result if
+    /// anything reaches it.
+    fn close(&mut self, out: &mut Operands) -> Option<()> {
+        let frame = self.frames.pop()?;
+        self.stack.truncate(frame.height);
+        self.reachable = frame.reached;
+        if let (true, Some(ty)) = (frame.reached, frame.result) {
+            self.push(ty, out);
+        }
+        Some(())
+    }
+
+    /// Start the else branch of the innermost `If`, which runs whenever the
+    /// `If` did.
+    fn enter_else(&mut self) -> Option<()> {
+        let frame = self.frames.last()?;
+        if frame.kind != Kind::If {
+            return None;
+        }
+        self.stack.truncate(frame.height);
+        self.reachable = true;
+        Some(())
+    }
+
+    /// Record a branch to the block `depth` frames out: a loop goes back
+    /// to its start and takes nothing, any other block to its end with its
+    /// result, which stays on the stack.
+    fn branch(&mut self, depth: u32, out: &mut Operands) -> Option<()> {
+        let index = self.frames.len().checked_sub(depth as usize + 1)?;
+        let frame = &mut self.frames[index];
+        if frame.kind == Kind::Loop {
+            return Some(());
+        }
+        frame.reached = true;
+        let result = frame.result;
+        self.carry(result, out)
+    }
+
+    /// Mark the rest of the block unreachable, dropping its operands.
+    fn set_unreachable(&mut self) {
+        let floor = self.frames.last().map_or(0, |f| f.height);
+        self.stack.truncate(floor);
+        self.reachable = false;
+    }
+}
@@ -0,0 +1,218 @@
+//! Tiered execution, for [`Strategy::Tiered`].
+//!
+//! Every function of a module starts interpreted, and each call counts
+//! towards promoting it. Past the baseline threshold it is compiled by the
+//! baseline JIT on the calling thread, which takes next to no time; past
+//! the optimized threshold, by Cranelift on a background thread. Each tier
+//! recompiles every function promoted to it so far, so they call each
+//! other directly, and the code instances run layers Cranelift's over the
+//! baseline's.
+//!
+//! A [tier profile](crate::config::RuntimeConfig::set_tier_profile) starts
+//! the counts where an earlier run left them, so its hot functions are
+//! promoted on their first calls.
+//!
+//! Instances switch to newly published code as calls start: from the host,
+//! from interpreted code, or from native code calling a function it did
+//! not compile. A call already running natively finishes on the code it
+//! started on. Baseline code counts its own calls, so hot functions it
+//! calls directly still move up.
+
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+use super::{baseline, codegen, NativeModule};
+use crate::{
+    config::{RuntimeConfig, Strategy, TierThresholds},
+    events::Event,
+    module::Module,
+};
+
+const INTERPRETED: u8 = 0;
+const BASELINE: u8 = 1;
+const OPTIMIZED: u8 = 2;
+
+/// A module's tiering state, shared by the runtime's instances of it.
+pub(crate) struct Tiers {
+    thresholds: TierThresholds,
+    /// Calls to each function so far. Baseline code bumps these directly:
+    /// instances running it hold the `Tiers`, so they outlive its runs.
+    calls: Box<[AtomicU64]>,
+    /// The highest tier each function was promoted to.
+    tiers: Box<[AtomicU8]>,
+    /// Bumped each time `State::current` changes.
+    generation: AtomicU64,
+    state: Mutex<State>,
+}
+
+struct State {
+    baseline: Option<Arc<NativeModule>>,
+    optimized: Option<Arc<NativeModule>>,
+    /// Functions promoted to each tier.
+    baseline_set: Vec<bool>,
+    optimized_set: Vec<bool>,
+    /// Whether a background compile is running; it compiles again for
+    /// functions promoted meanwhile.
+    optimizing: bool,
+    /// The code instances run.
+    current: Option<Arc<NativeModule>>,
+}
+
+impl Tiers {
+    /// Tiering state for `module`, with calls counted from those of the
+    /// config's tier profile if it fits the module.
+    pub(crate) fn new(module: &Module, config: &RuntimeConfig) -> Self {
+        let functions = module.functions.len();
+        let profile = config.tier_profile().filter(|p| p.fits(module));
+        Tiers {
+            thresholds: config.tier_thresholds(),
+            calls: (0..functions)
+                .map(|i| AtomicU64::new(profile.map_or(0, |p| p.calls(i as u32))))
+                .collect(),
+            tiers: (0..functions).map(|_| AtomicU8::new(INTERPRETED)).collect(),
+            generation: AtomicU64::new(0),
+            state: Mutex::new(State {
+                baseline: None,
+                optimized: None,
+                baseline_set: vec![false; functions],
+                optimized_set: vec![false; functions],
+                optimizing: false,
+                current: None,
+            }),
+        }
+    }
+
+    /// Count a call to function `func` of `module`, promoting it if it got
+    /// hot. Returns the code to run from now on if it changed since
+    /// generation `seen`, which is updated.
+    pub(crate) fn enter(
+        self: &Arc<Self>,
+        func: u32,
+        seen: &mut u64,
+        module: &Module,
+        config: &Arc<RuntimeConfig>,
+    ) -> Option<Arc<NativeModule>> {
+        let i = func as usize;
+        let calls = self.calls[i]
+            .fetch_add(1, Ordering::Relaxed)
+            .saturating_add(1);
+        let tier = self.tiers[i].load(Ordering::Relaxed);
+        if tier < BASELINE && calls >= self.thresholds.baseline {
+            if self.claim(i, tier, BASELINE) {
+                self.promote_baseline(i, module, config);
+            }
+        } else if tier < OPTIMIZED
+            && self.thresholds.optimized.is_some_and(|t| calls >= t)
+            && self.claim(i, tier, OPTIMIZED)
+        {
+            self.promote_optimized(i, module, config);
+        }
+        if self.generation.load(Ordering::Acquire) == *seen {
+            return None;
+        }
+        let state = self.lock();
+        *seen = self.generation.load(Ordering::Acquire);
+        state.current.clone()
+    }
+
+    /// Move function `func` from `tier` to `to`, unless another thread
+    /// already moved it.
+    fn claim(&self, func: usize, tier: u8, to: u8) -> bool {
+        self.tiers[func]
+            .compare_exchange(tier, to, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    }
+
+    fn promote_baseline(&self, func: usize, module: &Module, config: &RuntimeConfig) {
+        let mut state = self.lock();
+        state.baseline_set[func] = true;
+        let code = baseline::compile_only(module, config, &state.baseline_set, Some(&self.calls));
+        let Ok(code) = code else {
+            return;
+        };
+        let promoted = newly_compiled(state.baseline.as_deref(), &code);
+        state.baseline = Some(Arc::new(code));
+        self.publish(&mut state);
+        drop(state);
+        announce(module, config, &promoted, Strategy::Baseline);
+    }
+
+    fn promote_optimized(
+        self: &Arc<Self>,
+        func: usize,
+        module: &Module,
+        config: &Arc<RuntimeConfig>,
+    ) {
+        let mut state = self.lock();
+        state.optimized_set[func] = true;
+        if state.optimizing {
+            return;
+        }
+        state.optimizing = true;
+        drop(state);
+        let (tiers, module, config) = (self.clone(), Arc::new(module.clone()), config.clone());
+        let spawned = std::thread::Builder::new()
+            .name("rune-tier-up".into())
+            .spawn(move || tiers.optimize(&module, &config));
+        if spawned.is_err() {
+            // Stay on the baseline; the next promotion tries again.
+            self.lock().optimizing = false;
+        }
+    }
+
+    /// Compile the functions promoted to the optimized tier with
+    /// Cranelift, until no more are promoted meanwhile.
+    fn optimize(&self, module: &Module, config: &RuntimeConfig) {
+        loop {
+            let include = self.lock().optimized_set.clone();
+            let code = codegen::compile_only(module, config, &include);
+            let mut state = self.lock();
+            let mut promoted = Vec::new();
+            if let Ok(code) = code {
+                promoted = newly_compiled(state.optimized.as_deref(), &code);
+                state.optimized = Some(Arc::new(code));
+                self.publish(&mut state);
+            }
+            let done = state.optimized_set == include;
+            if done {
+                state.optimizing = false;
+            }
+            drop(state);
+            announce(module, config, &promoted, Strategy::Cranelift);
+            if done {
+                return;
+            }
+        }
+    }
+
+    fn publish(&self, state: &mut State) {
+        let layers = [&state.optimized, &state.baseline]
+            .into_iter()
+            .flatten()
+            .cloned()
+            .collect();
+        state.current = Some(Arc::new(NativeModule::layered(layers)));
+        self.generation.fetch_add(1, Ordering::Release);
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// Functions `code` compiled that `old` did not.
+fn newly_compiled(old: Option<&NativeModule>, code: &NativeModule) -> Vec<u32> {
+    (0..code.functions() as u32)
+        .filter(|&f| code.is_compiled(f) && !old.is_some_and(|old| old.is_compiled(f)))
+        .collect()
+}
+
+fn announce(module: &Module, config: &RuntimeConfig, funcs: &[u32], tier: Strategy) {
+    if !config.has_event_hooks() {
+        return;
+    }
+    for &f in funcs {
+        let func = &module.functions[f as usize].name;
+        config.emit(&Event::TierUp { func, tier });
+    }
+}
@@ -0,0 +1,591 @@
+//! Runtime configuration shared by every instance a [`Runtime`] creates.
+//!
+//! [`Runtime`]: crate::runtime::Runtime
+
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    compat::HostApi,
+    events::{Event, EventHook},
+    extension::{ExtensionOp, EXT_OPCODE_FIRST, EXT_OPCODE_LAST},
+    ir::{Function, Op},
+    memory::{MemoryLimiter, Poison},
+    metrics::{MetricsExporter, RuntimeMetrics},
+    module::Module,
+    profile::Profile,
+    sandbox::SandboxProfile,
+    trap::{Result, Trap},
+    types::FuncType,
+};
+
+const N_EXT: usize = (EXT_OPCODE_LAST - EXT_OPCODE_FIRST) as usize + 1;
+
+/// Default for [`RuntimeConfig::set_module_cache_size`]: 64 MiB.
+pub const DEFAULT_MODULE_CACHE_SIZE: usize = 64 << 20;
+
+/// A registered extension op with its signature cached.
+pub(crate) struct Extension {
+    pub handler: Arc<dyn ExtensionOp>,
+    pub ty: FuncType,
+}
+
+/// Settings applied to every instance of a runtime.
+pub struct RuntimeConfig {
+    extensions: [Option<Extension>; N_EXT],
+    memory_limiter: Option<Arc<dyn MemoryLimiter>>,
+    resource_limiter: Option<Arc<dyn ResourceLimiter>>,
+    memory_quota: Option<usize>,
+    max_instances: Option<usize>,
+    code_quota: Option<usize>,
+    max_memory_pages: Option<usize>,
+    fuel: Option<u64>,
+    max_call_depth: Option<usize>,
+    rng_seed: Option<u64>,
+    clock: Arc<dyn Clock>,
+    denied_capabilities: BTreeSet<String>,
+    module_cache_size: usize,
+    metrics_exporter: Option<(u64, Arc<MetricsExporter>)>,
+    event_hooks: Vec<Arc<EventHook>>,
+    host_api: HostApi,
+    strict_alignment: bool,
+    poison: Poison,
+    address_overflow: AddressOverflow,
+    strategy: Strategy,
+    tier_thresholds: TierThresholds,
+    compiler: CompilerConfig,
+    verify: bool,
+    profiling: bool,
+    tier_profile: Option<Arc<Profile>>,
+}
+
+/// Host admission control for everything a runtime hands out: memory, as
+/// a [`MemoryLimiter`], plus the instances themselves.
+///
+/// Consulted as each request happens rather than fixed up front, so a
+/// multi-tenant host can tighten or relax a tenant's allowance at any time
+/// from shared counters. Rune has no tables, so there is no table hook.
+pub trait ResourceLimiter: MemoryLimiter {
+    /// Whether another instance may be created while `live` instances of
+    /// the runtime exist. Returning `false` fails the instantiation with
+    /// `Trap::OutOfMemory`.
+    fn instance_creating(&self, live: usize) -> bool {
+        let _ = live;
+        true
+    }
+}
+
+/// How a runtime executes guest functions.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Strategy {
+    /// Interpret the IR, starting instantly. Native code from
+    /// [`Runtime::compile_background`] still takes over once it is built.
+    ///
+    /// [`Runtime::compile_background`]: crate::runtime::Runtime::compile_background
+    #[default]
+    Interpreter,
+    /// Compile each module with the baseline JIT when it is first
+    /// instantiated: the code is ready almost at once, though slower than
+    /// Cranelift's. Needs the `cranelift` feature and an x86-64 Linux host;
+    /// elsewhere modules are interpreted.
+    Baseline,
+    /// Compile each module to native code through Cranelift when it is
+    /// first instantiated, sharing the code with its later instances. Needs
+    /// the `cranelift` feature; without it, or on a host Cranelift does not
+    /// support, modules are interpreted.
+    Cranelift,
+    /// Interpret each function until it is called often enough, then run it
+    /// as baseline JIT code and, if it stays hot, as Cranelift's; see
+    /// [`TierThresholds`]. Calls switch tiers as they start, so cold code
+    /// is never compiled. Needs the `cranelift` feature; tiers the host
+    /// lacks are skipped.
+    Tiered,
+}
+
+/// When [`Strategy::Tiered`] promotes a function, in calls to it. Calls
+/// are counted across the runtime's instances of a module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TierThresholds {
+    /// Calls before the function is compiled with the baseline JIT.
+    pub baseline: u64,
+    /// Calls before it is compiled with Cranelift, on a background thread;
+    /// `None` stops at the baseline tier.
+    pub optimized: Option<u64>,
+}
+
+impl Default for TierThresholds {
+    fn default() -> Self {
+        TierThresholds {
+            baseline: 100,
+            optimized: Some(10_000),
+        }
+    }
+}
+
+/// How modules are compiled to native code: by the runtime's backends, and
+/// into shared libraries by `runec compile`. The defaults compile for the
+/// host, as fast as Cranelift makes it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CompilerConfig {
+    pub opt_level: OptLevel,
+    /// Target triple, such as `x86_64-unknown-linux-gnu`; `None` is the
+    /// host. Code a runtime runs itself must be for the host, so one set
+    /// to another target interprets its modules; other targets are for
+    /// libraries.
+    pub target: Option<String>,
+    /// CPU features the code may use beyond the target's baseline, by
+    /// Cranelift's names without the `has_` prefix, such as `popcnt` or
+    /// `avx2`. `None` uses whatever the host has for code the runtime runs
+    /// and none for libraries. A runtime whose host lacks a listed feature
+    /// interprets its modules.
+    pub cpu_features: Option<Vec<String>>,
+    /// Keep frame pointers in compiled code, so native profilers and
+    /// debuggers can walk through guest frames, and tell them which guest
+    /// function each piece of code is. Costs a register. Code the runtime
+    /// loads is announced to `perf` in a jitdump file, `jit-<pid>.dump` in
+    /// `$JITDUMPDIR` or the temporary directory, with the lines the
+    /// module's source map gives; shared libraries name their functions
+    /// in a symbol table. Only x86-64 Linux hosts get either.
+    pub debug_info: bool,
+    pub bounds_checks: BoundsChecks,
+}
+
+/// How hard compiled code is optimized. Above `None`, both backends hoist
+/// computations a loop repeats unchanged out of it; the baseline JIT does
+/// nothing more, and Cranelift optimizes further.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum OptLevel {
+    /// Compile fastest, with no optimization.
+    None,
+    #[default]
+    Speed,
+    /// Optimize for speed, then for code size.
+    SpeedAndSize,
+}
+
+/// How compiled code checks that memory accesses are in bounds. Whichever
+/// is used, out-of-bounds accesses trap as in the interpreter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BoundsChecks {
+    /// Compare each access against the memory's size inline, calling out
+    /// only for accesses that miss or need checks beyond bounds.
+    #[default]
+    Inline,
+    /// Call out to the runtime for every access: much smaller code, and
+    /// much slower memory access.
+    Helper,
+    /// Reserve each memory's whole 4 GiB address range plus a guard page,
+    /// with only the memory itself accessible, so no 32-bit address can
+    /// reach past the reservation. The baseline JIT then accesses memory
+    /// with no compare and turns the fault of an access that misses into
+    /// the call `Inline` would have made. Cranelift checks as with
+    /// `Inline`, as does everything where guard pages are unavailable
+    /// (only x86-64 Linux has them) or poison is checked.
+    Guard,
+}
+
+/// What a load or store does when `base + offset` exceeds `u32::MAX`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum AddressOverflow {
+    /// Trap with `Trap::OutOfBounds`.
+    #[default]
+    Trap,
+    /// Wrap modulo 2^32 and access the resulting address.
+    Wrap,
+}
+
+impl RuntimeConfig {
+    pub fn new() -> Self {
+        RuntimeConfig {
+            extensions: std::array::from_fn(|_| None),
+            memory_limiter: None,
+            resource_limiter: None,
+            memory_quota: None,
+            max_instances: None,
+            code_quota: None,
+            max_memory_pages: None,
+            fuel: None,
+            max_call_depth: None,
+            rng_seed: None,
+            clock: Arc::new(SystemClock::new()),
+            denied_capabilities: BTreeSet::new(),
+            module_cache_size: DEFAULT_MODULE_CACHE_SIZE,
+            metrics_exporter: None,
+            event_hooks: Vec::new(),
+            host_api: HostApi::default(),
+            strict_alignment: false,
+            poison: Poison::Off,
+            address_overflow: AddressOverflow::Trap,
+            strategy: Strategy::Interpreter,
+            tier_thresholds: TierThresholds::default(),
+            compiler: CompilerConfig::default(),
+            verify: false,
+            profiling: false,
+            tier_profile: None,
+        }
+    }
+
+    /// A config with the presets of `profile` and defaults otherwise.
+    pub fn with_profile(profile: SandboxProfile) -> Self {
+        let mut config = Self::new();
+        config.apply_profile(profile);
+        config
+    }
+
+    /// Trap with `Trap::UnalignedAccess` when a load or store address is not
+    /// a multiple of 2^`align` from the op's hint. Off by default, where the
+    /// hint is ignored as on x86; turn it on to catch accesses that would
+    /// fault on targets without unaligned loads.
+    pub fn set_strict_alignment(&mut self, strict: bool) {
+        self.strict_alignment = strict;
+    }
+
+    pub fn strict_alignment(&self) -> bool {
+        self.strict_alignment
+    }
+
+    /// Poison instance memory to surface reads of data the guest never
+    /// wrote; see [`Poison`]. Data segments count as written. For
+    /// development only: `Poison::Check` costs a bitmap lookup per access.
+    pub fn set_poison(&mut self, poison: Poison) {
+        self.poison = poison;
+    }
+
+    pub fn poison(&self) -> Poison {
+        self.poison
+    }
+
+    /// Whether instance memories are reserved with guard pages, for code
+    /// compiled with [`BoundsChecks::Guard`].
+    pub(crate) fn guard_pages(&self) -> bool {
+        self.compiler.bounds_checks == BoundsChecks::Guard
+    }
+
+    /// How effective addresses that overflow 32 bits are treated. The
+    /// arithmetic is done in `u32` either way, so the outcome does not
+    /// depend on the host's pointer width.
+    pub fn set_address_overflow(&mut self, mode: AddressOverflow) {
+        self.address_overflow = mode;
+    }
+
+    pub fn address_overflow(&self) -> AddressOverflow {
+        self.address_overflow
+    }
+
+    /// How instances run guest code; see [`Strategy`].
+    pub fn set_strategy(&mut self, strategy: Strategy) {
+        self.strategy = strategy;
+    }
+
+    pub fn strategy(&self) -> Strategy {
+        self.strategy
+    }
+
+    /// When [`Strategy::Tiered`] compiles a function.
+    pub fn set_tier_thresholds(&mut self, thresholds: TierThresholds) {
+        self.tier_thresholds = thresholds;
+    }
+
+    pub fn tier_thresholds(&self) -> TierThresholds {
+        self.tier_thresholds
+    }
+
+    /// Start [`Strategy::Tiered`]'s call counts from those of `profile`,
+    /// so functions it shows hot are compiled on their first calls.
+    /// Modules whose functions the profile does not [fit](Profile::fits)
+    /// count from zero.
+    pub fn set_tier_profile(&mut self, profile: Profile) {
+        self.tier_profile = Some(Arc::new(profile));
+    }
+
+    pub fn tier_profile(&self) -> Option<&Profile> {
+        self.tier_profile.as_deref()
+    }
+
+    /// How modules are compiled to native code; see [`CompilerConfig`].
+    pub fn set_compiler(&mut self, compiler: CompilerConfig) {
+        self.compiler = compiler;
+    }
+
+    pub fn compiler(&self) -> &CompilerConfig {
+        &self.compiler
+    }
+
+    /// Run the [verifier](crate::verify) over every module when it is
+    /// instantiated, and over functions swapped in with
+    /// `replace_function`, failing with `Trap::InvalidModule` instead of
+    /// trapping with `Trap::TypeMismatch` when a malformed function runs.
+    /// Off by default; compiled code is always verified.
+    pub fn set_verify(&mut self, verify: bool) {
+        self.verify = verify;
+    }
+
+    pub fn verify(&self) -> bool {
+        self.verify
+    }
+
+    /// Have instances record a [`Profile`] of what their functions do,
+    /// read back with `Instance::profile`. Profiled instances run every
+    /// function in the interpreter, whatever the strategy. Off by default.
+    pub fn set_profiling(&mut self, profiling: bool) {
+        self.profiling = profiling;
+    }
+
+    pub fn profiling(&self) -> bool {
+        self.profiling
+    }
+
+    /// Limiter given to each new instance's memory. Individual instances can
+    /// swap it with `Instance::set_memory_limiter`.
+    pub fn set_memory_limiter(&mut self, limiter: impl MemoryLimiter + 'static) {
+        self.memory_limiter = Some(Arc::new(limiter));
+    }
+
+    pub(crate) fn memory_limiter(&self) -> Option<&Arc<dyn MemoryLimiter>> {
+        self.memory_limiter.as_ref()
+    }
+
+    /// Limiter the runtime consults before creating an instance and, unless
+    /// replaced by a later `set_memory_limiter`, before any of its memories
+    /// grow. An instance can be given its own memory policy with
+    /// `Instance::set_memory_limiter`, which accepts the same limiter.
+    pub fn set_resource_limiter(&mut self, limiter: impl ResourceLimiter + 'static) {
+        let limiter: Arc<dyn ResourceLimiter> = Arc::new(limiter);
+        self.memory_limiter = Some(limiter.clone());
+        self.resource_limiter = Some(limiter);
+    }
+
+    pub(crate) fn resource_limiter(&self) -> Option<&Arc<dyn ResourceLimiter>> {
+        self.resource_limiter.as_ref()
+    }
+
+    /// Cap the bytes of memory held by all live instances of the runtime
+    /// together. Instantiating or growing past it fails with
+    /// `Trap::OutOfMemory`, and a guest's `memory.grow` returns -1. Memory
+    /// is returned to the budget when an instance is dropped. Unlimited by
+    /// default.
+    pub fn set_memory_quota(&mut self, bytes: usize) {
+        self.memory_quota = Some(bytes);
+    }
+
+    pub fn memory_quota(&self) -> Option<usize> {
+        self.memory_quota
+    }
+
+    /// Cap the number of instances of the runtime alive at once.
+    /// Instantiating past it fails with `Trap::OutOfMemory` until one is
+    /// dropped. Unlimited by default.
+    pub fn set_max_instances(&mut self, n: usize) {
+        self.max_instances = Some(n);
+    }
+
+    pub fn max_instances(&self) -> Option<usize> {
+        self.max_instances
+    }
+
+    /// Cap the bytes of prepared code held by all live instances of the
+    /// runtime together, counting each instance's functions in full even
+    /// when instances share them. Instantiating past it fails with
+    /// `Trap::OutOfMemory`. Unlimited by default.
+    pub fn set_code_quota(&mut self, bytes: usize) {
+        self.code_quota = Some(bytes);
+    }
+
+    pub fn code_quota(&self) -> Option<usize> {
+        self.code_quota
+    }
+
+    /// Cap each instance's default memory at `pages`, below the module's own
+    /// maximum if that is higher. A module starting larger fails to
+    /// instantiate with `Trap::OutOfMemory`. Unlimited by default.
+    pub fn set_max_memory_pages(&mut self, pages: usize) {
+        self.max_memory_pages = Some(pages);
+    }
+
+    pub fn max_memory_pages(&self) -> Option<usize> {
+        self.max_memory_pages
+    }
+
+    /// The maximum `module`'s default memory may reach under this config.
+    pub(crate) fn memory_max_for(&self, module: &Module) -> Option<usize> {
+        match (module.max_memory_pages, self.max_memory_pages) {
+            (Some(a), Some(b)) => Some(a.min(b)),
+            (a, b) => a.or(b),
+        }
+    }
+
+    /// Meter every new instance with `fuel` units, one per op executed.
+    /// An instance that runs out traps with `Trap::OutOfFuel`; see
+    /// `Instance::set_fuel` to refill it. Unmetered by default.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+    }
+
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Trap with `Trap::StackOverflow` when a call would make more than
+    /// `depth` guest functions active at once, the one called from the host
+    /// included. Unlimited by default, where only the host's native stack
+    /// bounds recursion.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = Some(depth);
+    }
+
+    pub fn max_call_depth(&self) -> Option<usize> {
+        self.max_call_depth
+    }
+
+    /// Seed every new instance's random number generator with `seed`, so
+    /// its random draws repeat from run to run. Unseeded by default, where
+    /// each instance seeds itself from the host's entropy; see
+    /// [`rng`](crate::rng).
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.rng_seed = Some(seed);
+    }
+
+    pub fn rng_seed(&self) -> Option<u64> {
+        self.rng_seed
+    }
+
+    /// Measure [`timer`](crate::timer) delays on `clock` instead of a
+    /// [`SystemClock`] started with the config.
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = Arc::new(clock);
+    }
+
+    pub fn clock(&self) -> &dyn Clock {
+        &*self.clock
+    }
+
+    /// Withhold host functions labelled `capability` from every instance,
+    /// whatever the linker grants: calling one traps with
+    /// `Trap::PermissionDenied`.
+    pub fn deny_capability(&mut self, capability: impl Into<String>) {
+        self.denied_capabilities.insert(capability.into());
+    }
+
+    pub fn denies_capability(&self, capability: &str) -> bool {
+        self.denied_capabilities.contains(capability)
+    }
+
+    /// Replace the fuel, memory cap, call-depth limit and denied
+    /// capabilities with the presets of `profile`. Settings the profile does
+    /// not cover are left alone, and any of these can be adjusted after.
+    pub fn apply_profile(&mut self, profile: SandboxProfile) {
+        self.fuel = profile.fuel();
+        self.max_memory_pages = profile.max_memory_pages();
+        self.max_call_depth = profile.max_call_depth();
+        self.denied_capabilities = profile
+            .denied_capabilities()
+            .iter()
+            .map(|c| c.to_string())
+            .collect();
+    }
+
+    /// Total size, in encoded bytes, of the modules `Runtime::load_cached`
+    /// keeps; the least recently used are evicted past it. 0 disables the
+    /// cache.
+    pub fn set_module_cache_size(&mut self, bytes: usize) {
+        self.module_cache_size = bytes;
+    }
+
+    pub fn module_cache_size(&self) -> usize {
+        self.module_cache_size
+    }
+
+    /// Hand a snapshot of `Runtime::metrics` to `export` after every
+    /// `every` calls (at least 1) into any instance of the runtime, on the
+    /// thread that made the call that reached the count. The exporter runs
+    /// inside the call, so it should hand the numbers off rather than block.
+    pub fn set_metrics_exporter(
+        &mut self,
+        every: u64,
+        export: impl Fn(&RuntimeMetrics) + Send + Sync + 'static,
+    ) {
+        self.metrics_exporter = Some((every.max(1), Arc::new(export)));
+    }
+
+    pub(crate) fn metrics_exporter(&self) -> Option<(u64, Arc<MetricsExporter>)> {
+        self.metrics_exporter.clone()
+    }
+
+    /// Call `hook` with every [`Event`] from the runtime's instances, after
+    /// any hooks added before it. Hooks run on the thread doing the work,
+    /// inside it, so they should hand events off rather than block.
+    pub fn add_event_hook(&mut self, hook: impl Fn(&Event<'_>) + Send + Sync + 'static) {
+        self.event_hooks.push(Arc::new(hook));
+    }
+
+    pub(crate) fn has_event_hooks(&self) -> bool {
+        !self.event_hooks.is_empty()
+    }
+
+    pub(crate) fn emit(&self, event: &Event<'_>) {
+        for hook in &self.event_hooks {
+            hook(event);
+        }
+    }
+
+    /// Declare the host API version and capabilities this host provides.
+    /// Modules whose [`HostRequirements`] it does not meet fail to
+    /// instantiate with `Trap::UnsupportedFeature`. By default the host
+    /// declares no version and no capabilities, so only modules that
+    /// declare nothing run.
+    ///
+    /// [`HostRequirements`]: crate::compat::HostRequirements
+    pub fn set_host_api(&mut self, api: HostApi) {
+        self.host_api = api;
+    }
+
+    pub fn host_api(&self) -> &HostApi {
+        &self.host_api
+    }
+
+    /// Register the handler for an extension opcode (0xE0..=0xFF), replacing
+    /// any previous one.
+    pub fn register_extension(&mut self, opcode: u8, op: impl ExtensionOp + 'static) -> Result<()> {
+        if !(EXT_OPCODE_FIRST..=EXT_OPCODE_LAST).contains(&opcode) {
+            return Err(Trap::InvalidModule(format!(
+                "opcode {opcode:#04x} is outside the extension range"
+            )));
+        }
+        let ty = op.signature();
+        if ty.results.len() > 1 {
+            return Err(Trap::TypeMismatch);
+        }
+        self.extensions[(opcode - EXT_OPCODE_FIRST) as usize] = Some(Extension {
+            handler: Arc::new(op),
+            ty,
+        });
+        Ok(())
+    }
+
+    /// The handler registered for `opcode`, if any.
+    pub(crate) fn extension(&self, opcode: u8) -> Option<&Extension> {
+        let slot = opcode.checked_sub(EXT_OPCODE_FIRST)? as usize;
+        self.extensions.get(slot)?.as_ref()
+    }
+
+    /// Check that every extension op in `func` has a handler that accepts
+    /// its immediate.
+    pub(crate) fn check_extensions(&self, func: &Function) -> Result<()> {
+        for op in func.body.iter() {
+            if let Op::Ext { opcode, imm } = op {
+                let ext = self.extension(*opcode).ok_or_else(|| {
+                    Trap::UnsupportedFeature(format!("extension opcode {opcode:#04x}"))
+                })?;
+                ext.handler.decode(*imm)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for RuntimeConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,84 @@
+//! Modules embedded in the host binary at build time.
+//!
+//! Shipping plugin files beside a binary means deploying, versioning and
+//! finding them at run time. Instead, the host's build script hands each
+//! module to the `rune-build` crate, which decodes and
+//! [verifies](crate::verify) it, failing the build if it is invalid, can
+//! compile it ahead of time, and leaves the result in `OUT_DIR`.
+//! [`include_rune!`](crate::include_rune) then bakes it into the binary as
+//! static data:
+//!
+//! ```ignore
+//! // build.rs
+//! fn main() {
+//!     rune_build::embed("plugins/filter.rune").unwrap();
+//! }
+//!
+//! // main.rs
+//! static FILTER: rune::embed::EmbeddedModule = rune::include_rune!("filter.rune");
+//!
+//! let module = runtime.load_embedded(&FILTER)?;
+//! let mut inst = runtime.instantiate(&module)?;
+//! ```
+//!
+//! Nothing is read from disk at run time, and the module is decoded once,
+//! on first use, however many runtimes load it. Code compiled ahead of
+//! time is copied into executable memory instead of compiled; see
+//! [`Runtime::load_embedded`](crate::runtime::Runtime::load_embedded).
+
+use std::sync::{Arc, OnceLock};
+
+use crate::{module::Module, trap::Result};
+
+/// A module embedded in the binary, with any code compiled for it ahead
+/// of time. [`include_rune!`](crate::include_rune) builds these.
+pub struct EmbeddedModule {
+    module: &'static [u8],
+    code: Option<&'static [u8]>,
+    decoded: OnceLock<Arc<Module>>,
+}
+
+impl EmbeddedModule {
+    /// The module encoded in `module`, as `Module::to_bytes` writes it,
+    /// with `code` from
+    /// [`compiler::embedded::build`](crate::compiler::embedded::build) if
+    /// it was compiled ahead of time.
+    pub const fn new(module: &'static [u8], code: Option<&'static [u8]>) -> Self {
+        EmbeddedModule {
+            module,
+            code,
+            decoded: OnceLock::new(),
+        }
+    }
+
+    /// The encoded module.
+    pub fn bytes(&self) -> &'static [u8] {
+        self.module
+    }
+
+    /// The code compiled ahead of time, if any.
+    pub fn code(&self) -> Option<&'static [u8]> {
+        self.code
+    }
+
+    /// The module, decoded on first use. Fails with `Trap::InvalidModule`
+    /// if the bytes do not decode, which a build through `rune-build`
+    /// rules out.
+    pub fn module(&self) -> Result<Arc<Module>> {
+        if let Some(module) = self.decoded.get() {
+            return Ok(module.clone());
+        }
+        let module = Arc::new(Module::from_bytes(self.module)?);
+        Ok(self.decoded.get_or_init(|| module).clone())
+    }
+}
+
+/// Embed a module that the build script embedded with `rune-build` under
+/// the file name `name`, as a [`EmbeddedModule`] constant expression.
+/// Fails to compile if the build script did not embed it.
+#[macro_export]
+macro_rules! include_rune {
+    ($name:literal) => {
+        include!(concat!(env!("OUT_DIR"), "/rune/", $name, ".rs"))
+    };
+}
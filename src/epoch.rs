@@ -0,0 +1,44 @@
+//! Background thread advancing a runtime's epoch, behind
+//! [`Runtime::enable_epoch_ticker`](crate::runtime::Runtime::enable_epoch_ticker).
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use crate::trap::{Result, Trap};
+
+/// Increments an epoch every `interval` until dropped.
+pub(crate) struct EpochTicker {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl EpochTicker {
+    pub(crate) fn spawn(epoch: Arc<AtomicU64>, interval: Duration) -> Result<Self> {
+        let (stop, stopped) = mpsc::channel::<()>();
+        let thread = std::thread::Builder::new()
+            .name("rune-epoch".into())
+            .spawn(move || {
+                // Any message, or the sender going away, ends the loop.
+                while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                    epoch.fetch_add(1, Ordering::Relaxed);
+                }
+            })
+            .map_err(|e| Trap::HostError(format!("cannot start epoch ticker: {e}")))?;
+        Ok(EpochTicker {
+            stop: Some(stop),
+            thread: Some(thread),
+        })
+    }
+}
+
+impl Drop for EpochTicker {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
@@ -0,0 +1,55 @@
+//! Structured events for monitoring a runtime's instances, behind
+//! [`RuntimeConfig::add_event_hook`].
+//!
+//! Hooks see instantiations, calls from the host into exports, guest calls
+//! out to host functions, traps and tier changes: coarse enough to leave
+//! on in production and feed an APM or tracing system. Nothing is timed or
+//! emitted while no hook is registered.
+//!
+//! [`RuntimeConfig::add_event_hook`]: crate::config::RuntimeConfig::add_event_hook
+
+use std::time::Duration;
+
+use crate::{
+    config::Strategy,
+    module::{Import, Module},
+    trap::Trap,
+};
+
+/// Callback registered with
+/// [`RuntimeConfig::add_event_hook`](crate::config::RuntimeConfig::add_event_hook).
+pub type EventHook = dyn Fn(&Event<'_>) + Send + Sync;
+
+/// Something an instance of the runtime did.
+#[derive(Debug, Clone, Copy)]
+pub enum Event<'a> {
+    /// An instance was created. `elapsed` covers linking, preparing its
+    /// functions and building its memory.
+    Instantiated {
+        module: &'a Module,
+        elapsed: Duration,
+    },
+    /// The host called into function `func`.
+    CallStart { func: &'a str },
+    /// A call from the host returned; `trapped` if it failed, after a
+    /// [`Trap`](Event::Trap) event.
+    CallEnd {
+        func: &'a str,
+        elapsed: Duration,
+        trapped: bool,
+    },
+    /// The guest called the host function bound to `import`.
+    HostCallStart { import: &'a Import },
+    /// A host function returned to the guest, or failed with `trapped`.
+    HostCallEnd {
+        import: &'a Import,
+        elapsed: Duration,
+        trapped: bool,
+    },
+    /// A call from the host into `func` failed with `trap`.
+    Trap { func: &'a str, trap: &'a Trap },
+    /// Under [`Strategy::Tiered`], `func` got hot enough to run as code
+    /// compiled by `tier`, either `Baseline` or `Cranelift`, from its next
+    /// call on. Emitted on the thread that compiled it.
+    TierUp { func: &'a str, tier: Strategy },
+}
@@ -0,0 +1,83 @@
+//! Calls with a wall-clock timeout, behind
+//! [`Runtime::call_blocking`](crate::runtime::Runtime::call_blocking).
+//!
+//! The call runs on a worker thread while the calling thread waits for it.
+//! If the timeout passes first, the call is cancelled through a
+//! [`CancellationToken`] and stops with `Trap::Interrupted` at its next
+//! loop iteration or function call; the caller then waits for it to
+//! unwind, so the instance is never left running in the background.
+
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::Duration;
+
+use crate::{
+    cancel::CancellationToken,
+    instance::Instance,
+    sys::Instant,
+    trap::{Result, Trap},
+    types::Val,
+};
+
+/// What a call made with
+/// [`Runtime::call_blocking`](crate::runtime::Runtime::call_blocking) did.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CallOutcome {
+    /// The call's result; `Trap::Interrupted` if it was stopped at the
+    /// timeout.
+    pub result: Result<Option<Val>>,
+    /// Whether the timeout passed before the call returned.
+    pub timed_out: bool,
+    /// Fuel the call consumed, if the instance is metered.
+    pub fuel_used: Option<u64>,
+    /// Wall-clock time from starting the worker to the call returning.
+    pub duration: Duration,
+}
+
+pub(crate) fn call_with_timeout(
+    instance: &mut Instance<'_>,
+    func: &str,
+    args: &[Val],
+    timeout: Duration,
+) -> Result<CallOutcome> {
+    let token = CancellationToken::new();
+    let fuel_before = instance.fuel();
+    let started = Instant::now();
+    let (result, timed_out) = std::thread::scope(|s| {
+        let (done, finished) = mpsc::channel();
+        let worker = std::thread::Builder::new()
+            .name("rune-call".into())
+            .spawn_scoped(s, {
+                let (instance, token) = (&mut *instance, &token);
+                move || {
+                    // The receiver outlives the worker; a failed send
+                    // cannot happen.
+                    let _ = done.send(instance.call_cancellable(func, args, token));
+                }
+            })
+            .map_err(|e| Trap::HostError(format!("cannot start call worker: {e}")))?;
+        let outcome = match finished.recv_timeout(timeout) {
+            Ok(result) => (result, false),
+            Err(RecvTimeoutError::Timeout) => {
+                token.cancel();
+                match finished.recv() {
+                    Ok(result) => (result, true),
+                    Err(_) => std::panic::resume_unwind(worker.join().unwrap_err()),
+                }
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                std::panic::resume_unwind(worker.join().unwrap_err())
+            }
+        };
+        Ok::<_, Trap>(outcome)
+    })?;
+    let duration = started.elapsed();
+    let fuel_used = fuel_before
+        .zip(instance.fuel())
+        .map(|(before, after)| before.saturating_sub(after));
+    Ok(CallOutcome {
+        result,
+        timed_out,
+        fuel_used,
+        duration,
+    })
+}
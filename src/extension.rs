@@ -0,0 +1,41 @@
+//! Embedder-defined extension opcodes.
+//!
+//! Opcodes 0xE0..=0xFF are reserved for embedders. A module encodes them as
+//! `Op::Ext { opcode, imm }`; what they do is supplied at runtime by an
+//! [`ExtensionOp`] registered in the [`RuntimeConfig`]. This lets a host add
+//! domain-specific instructions (say, fetching a row from its own storage)
+//! without forking the instruction set.
+//!
+//! [`RuntimeConfig`]: crate::config::RuntimeConfig
+
+use crate::{
+    memory::Memory,
+    trap::Result,
+    types::{FuncType, Val},
+};
+
+/// First opcode reserved for embedder extensions.
+pub const EXT_OPCODE_FIRST: u8 = 0xE0;
+/// Last opcode reserved for embedder extensions.
+pub const EXT_OPCODE_LAST: u8 = 0xFF;
+
+/// Behaviour of one extension opcode.
+pub trait ExtensionOp: Send + Sync {
+    /// Name used in diagnostics.
+    fn name(&self) -> &str;
+
+    /// Stack effect: values popped (`params`) and pushed (`results`, at most
+    /// one). Queried once, at registration.
+    fn signature(&self) -> FuncType;
+
+    /// Validate an occurrence's immediate. Called for every use of the opcode
+    /// when a module is instantiated, so malformed immediates are rejected
+    /// before anything runs.
+    fn decode(&self, imm: u32) -> Result<()> {
+        let _ = imm;
+        Ok(())
+    }
+
+    /// Execute the op with the popped arguments, in push order.
+    fn execute(&self, imm: u32, args: &[Val], memory: &mut Memory) -> Result<Option<Val>>;
+}
@@ -0,0 +1,129 @@
+//! Optional op families a module may depend on.
+//!
+//! A module records the families it uses in its header. Loading or
+//! instantiating it on a runtime that lacks one fails up front with
+//! [`Trap::UnsupportedFeature`] instead of somewhere in the middle of
+//! execution.
+
+use std::fmt;
+use std::ops::{BitAnd, BitOr, BitOrAssign, Not};
+
+use crate::trap::{Result, Trap};
+
+/// A set of optional features, stored as a 64-bit mask in the module header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub struct Features(u64);
+
+impl Features {
+    pub const NONE: Features = Features(0);
+    /// 128-bit vector ops.
+    pub const SIMD: Features = Features(1 << 0);
+    /// Atomic memory ops and shared memory.
+    pub const ATOMICS: Features = Features(1 << 1);
+    /// 64-bit memory addressing.
+    pub const MEMORY64: Features = Features(1 << 2);
+    /// Guaranteed tail calls.
+    pub const TAIL_CALLS: Features = Features(1 << 3);
+    /// More than one linear memory.
+    pub const MULTI_MEMORY: Features = Features(1 << 4);
+    /// Bulk memory copy and fill ops.
+    pub const BULK_MEMORY: Features = Features(1 << 5);
+
+    /// Everything this build of the runtime can execute.
+    pub const SUPPORTED: Features = Features::MULTI_MEMORY;
+
+    const NAMES: [(Features, &'static str); 6] = [
+        (Features::SIMD, "simd"),
+        (Features::ATOMICS, "atomics"),
+        (Features::MEMORY64, "memory64"),
+        (Features::TAIL_CALLS, "tail-calls"),
+        (Features::MULTI_MEMORY, "multi-memory"),
+        (Features::BULK_MEMORY, "bulk-memory"),
+    ];
+
+    pub const fn from_bits(bits: u64) -> Self {
+        Features(bits)
+    }
+
+    pub const fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub const fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub const fn contains(self, other: Features) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// Look up a feature by the name used in messages and the JSON format.
+    pub fn from_name(name: &str) -> Option<Features> {
+        Self::NAMES
+            .iter()
+            .find(|(_, n)| *n == name)
+            .map(|(f, _)| *f)
+    }
+
+    /// Names of the features in this set. Bits this runtime does not know
+    /// are rendered as `bit N`.
+    pub fn names(self) -> Vec<String> {
+        (0..64)
+            .map(|bit| Features(1 << bit))
+            .filter(|f| self.contains(*f))
+            .map(
+                |f| match Self::NAMES.iter().find(|(known, _)| *known == f) {
+                    Some((_, name)) => name.to_string(),
+                    None => format!("bit {}", f.0.trailing_zeros()),
+                },
+            )
+            .collect()
+    }
+
+    /// Fail with `Trap::UnsupportedFeature` unless this runtime supports
+    /// every feature in the set.
+    pub fn check_supported(self) -> Result<()> {
+        let missing = self & !Features::SUPPORTED;
+        if missing.is_empty() {
+            Ok(())
+        } else {
+            Err(Trap::UnsupportedFeature(missing.to_string()))
+        }
+    }
+}
+
+impl fmt::Display for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_empty() {
+            return write!(f, "none");
+        }
+        write!(f, "{}", self.names().join(", "))
+    }
+}
+
+impl BitOr for Features {
+    type Output = Features;
+    fn bitor(self, rhs: Features) -> Features {
+        Features(self.0 | rhs.0)
+    }
+}
+
+impl BitOrAssign for Features {
+    fn bitor_assign(&mut self, rhs: Features) {
+        self.0 |= rhs.0;
+    }
+}
+
+impl BitAnd for Features {
+    type Output = Features;
+    fn bitand(self, rhs: Features) -> Features {
+        Features(self.0 & rhs.0)
+    }
+}
+
+impl Not for Features {
+    type Output = Features;
+    fn not(self) -> Features {
+        Features(!self.0)
+    }
+}
@@ -3,7 +3,14 @@
 //!
 //! This module exposes a stable C ABI so Rune can be embedded from C, Python,
 //! Go, Swift, or any other language. All heap-allocated objects are opaque
-//! pointers managed by the caller via the `_free` functions.
+//! pointers managed by the caller via the `_free` functions; [`handles`]
+//! offers the same objects behind integer handles instead.
+//!
+//! No panic unwinds across the boundary: every entry point catches it and
+//! fails with `RUNE_HOST_ERROR`. Callbacks use the `C-unwind` ABI, so one
+//! that unwinds (a Rust panic, or a C++ exception) is caught too: a host
+//! function traps the guest with a host error, and a trap callback is
+//! ignored.
 //!
 //! Status: Phase 2 — implementations are correct for the interpreter path.
 //!         AOT path will wire in automatically once `instance.rs` switches to
@@ -11,22 +18,94 @@
 
 #![allow(clippy::missing_safety_doc)]
 
-use std::ffi::CStr;
+pub mod handles;
+#[cfg(feature = "hostlib")]
+pub mod hostlib;
+#[cfg(feature = "jni")]
+mod jni;
+
+use std::cell::RefCell;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::mem::MaybeUninit;
 use std::os::raw::{c_char, c_void};
+use std::panic::{self, AssertUnwindSafe};
 use std::ptr;
 use std::slice;
+use std::sync::{Arc, Mutex, MutexGuard, OnceLock, PoisonError, RwLock};
+use std::time::Duration;
 
 use crate::{
-    instance::Instance,
-    module::Module,
+    compat::ApiVersion,
+    executor,
+    features::Features,
+    instance::{Caller, Instance, OwnedInstance},
+    ir::Function,
+    linker::Linker,
+    module::{op_from_parts, Module},
     runtime::Runtime,
     trap::Trap,
     types::{FuncType, Val, ValType},
 };
 
+// ── ABI version ───────────────────────────────────────────────────────────────
+
+/// Version of the C ABI: the minor number is bumped when entry points are
+/// added, the major number when an existing one changes or goes away.
+/// Mirrors `RUNE_ABI_VERSION_MAJOR`/`_MINOR` in rune.h.
+pub const ABI_VERSION: ApiVersion = ApiVersion::new(1, 10);
+
+/// Entry-point groups `rune_has_feature` reports, beyond op families.
+const ABI_FEATURES: &[&str] = &[
+    "instance",
+    "linker",
+    "memory-access",
+    "module-builder",
+    "last-error",
+    "fuel",
+    "deadline",
+    "exports",
+    "trap-callback",
+    "handles",
+    "handle-linker",
+    "linker-instances",
+    "typed-values",
+    "caller-memory",
+];
+
+/// The C ABI version this library implements, as `major << 16 | minor`.
+#[no_mangle]
+pub extern "C" fn rune_abi_version() -> u32 {
+    guard(|| ABI_VERSION.major << 16 | ABI_VERSION.minor)
+}
+
+/// Whether this library provides `name`: an entry-point group such as
+/// `"module-builder"`, `"hostlib"` or `"jni"` if built with those
+/// features, or an op family such as `"simd"` that it can execute.
+/// Unknown names, including ones added by later versions, report false.
+///
+/// # Safety
+/// `name` must be null or a valid null-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn rune_has_feature(name: *const c_char) -> bool {
+    guard(|| {
+        if name.is_null() {
+            return false;
+        }
+        let Ok(name) = CStr::from_ptr(name).to_str() else {
+            return false;
+        };
+        ABI_FEATURES.contains(&name)
+            || (name == "hostlib" && cfg!(feature = "hostlib"))
+            || (name == "jni" && cfg!(feature = "jni"))
+            || Features::from_name(name).is_some_and(|f| Features::SUPPORTED.contains(f))
+    })
+}
+
 // ── C-compatible error codes ──────────────────────────────────────────────────
 
 #[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RuneError {
     Ok = 0,
     InvalidModule = 1,
@@ -39,6 +118,17 @@ pub enum RuneError {
     UndefinedExport = 8,
     UndefinedImport = 9,
     HostError = 10,
+    UnsupportedFeature = 11,
+    TrapAccessViolation = 12,
+    InvalidUtf8 = 13,
+    TrapUnaligned = 14,
+    TrapUninitialized = 15,
+    PermissionDenied = 16,
+    Interrupted = 17,
+    OutOfFuel = 18,
+    CpuTimeExceeded = 19,
+    /// A null handle or buffer where one is required.
+    InvalidArgument = 20,
 }
 
 impl From<&Trap> for RuneError {
@@ -54,14 +144,125 @@ impl From<&Trap> for RuneError {
             Trap::UndefinedImport(_) => RuneError::UndefinedImport,
             Trap::InvalidModule(_) => RuneError::InvalidModule,
             Trap::HostError(_) => RuneError::HostError,
+            Trap::UnsupportedFeature(_) => RuneError::UnsupportedFeature,
+            Trap::AccessViolation => RuneError::TrapAccessViolation,
+            Trap::InvalidUtf8 => RuneError::InvalidUtf8,
+            Trap::UnalignedAccess => RuneError::TrapUnaligned,
+            Trap::UninitializedRead(_) => RuneError::TrapUninitialized,
+            Trap::PermissionDenied(_) => RuneError::PermissionDenied,
+            Trap::Interrupted => RuneError::Interrupted,
+            Trap::OutOfFuel => RuneError::OutOfFuel,
+            Trap::CpuTimeExceeded => RuneError::CpuTimeExceeded,
         }
     }
 }
 
+// ── Last error ────────────────────────────────────────────────────────────────
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<(RuneError, CString)>> = const { RefCell::new(None) };
+}
+
+/// Record `code` and `msg` as this thread's last error, for
+/// `rune_last_error_message`.
+fn set_last_error(code: RuneError, msg: impl fmt::Display) {
+    // Interior NULs would truncate the message in C; drop them instead.
+    let msg = msg.to_string().replace('\0', "");
+    let msg = CString::new(msg).expect("NULs removed above");
+    LAST_ERROR.with(|last| *last.borrow_mut() = Some((code, msg)));
+}
+
+/// Record `trap` and return its code.
+fn fail(trap: &Trap) -> RuneError {
+    let code = RuneError::from(trap);
+    set_last_error(code, trap);
+    code
+}
+
+/// Record `msg` and return `code`, for failures that are not traps.
+fn fail_with(code: RuneError, msg: impl fmt::Display) -> RuneError {
+    set_last_error(code, msg);
+    code
+}
+
+/// Record `code` and `msg` and return null, for constructors.
+fn fail_null<T>(code: RuneError, msg: impl fmt::Display) -> *mut T {
+    set_last_error(code, msg);
+    ptr::null_mut()
+}
+
+// ── Panics ────────────────────────────────────────────────────────────────────
+
+/// Run `f`, catching a panic as `"panic: <message>"`.
+fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        let msg = payload
+            .downcast_ref::<&str>()
+            .copied()
+            .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+            .unwrap_or("unknown payload");
+        format!("panic: {msg}")
+    })
+}
+
+/// What an entry point returns when it panics.
+trait OnPanic {
+    fn on_panic() -> Self;
+}
+
+impl OnPanic for RuneError {
+    fn on_panic() -> Self {
+        RuneError::HostError
+    }
+}
+
+impl<T> OnPanic for *mut T {
+    fn on_panic() -> Self {
+        ptr::null_mut()
+    }
+}
+
+impl<T> OnPanic for *const T {
+    fn on_panic() -> Self {
+        ptr::null()
+    }
+}
+
+macro_rules! on_panic_default {
+    ($($ty:ty),*) => {
+        $(impl OnPanic for $ty {
+            fn on_panic() -> Self {
+                Self::default()
+            }
+        })*
+    };
+}
+
+impl OnPanic for RuneTypedVal {
+    fn on_panic() -> Self {
+        RuneTypedVal::from_val(None)
+    }
+}
+
+// `u64` covers `RuneHandle`, whose failure value is 0.
+on_panic_default!((), bool, u32, u64, usize);
+
+/// Body of an entry point: a panic in `f` is recorded as the last error
+/// and the entry point's failure value returned instead of unwinding into C.
+fn guard<R: OnPanic>(f: impl FnOnce() -> R) -> R {
+    catch_panic(f).unwrap_or_else(|msg| {
+        fail(&Trap::HostError(msg));
+        R::on_panic()
+    })
+}
+
 // ── C-compatible value types ──────────────────────────────────────────────────
 
 #[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RuneValType {
+    /// No value: the result tag of a function returning nothing.
+    Void = 0,
     I32 = 0x7F,
     I64 = 0x7E,
     F32 = 0x7D,
@@ -72,6 +273,7 @@ impl TryFrom<u8> for RuneValType {
     type Error = ();
     fn try_from(v: u8) -> Result<Self, ()> {
         match v {
+            0x00 => Ok(RuneValType::Void),
             0x7F => Ok(RuneValType::I32),
             0x7E => Ok(RuneValType::I64),
             0x7D => Ok(RuneValType::F32),
@@ -81,19 +283,33 @@ impl TryFrom<u8> for RuneValType {
     }
 }
 
-impl From<RuneValType> for ValType {
-    fn from(r: RuneValType) -> ValType {
+impl TryFrom<RuneValType> for ValType {
+    type Error = ();
+    fn try_from(r: RuneValType) -> Result<ValType, ()> {
         match r {
-            RuneValType::I32 => ValType::I32,
-            RuneValType::I64 => ValType::I64,
-            RuneValType::F32 => ValType::F32,
-            RuneValType::F64 => ValType::F64,
+            RuneValType::Void => Err(()),
+            RuneValType::I32 => Ok(ValType::I32),
+            RuneValType::I64 => Ok(ValType::I64),
+            RuneValType::F32 => Ok(ValType::F32),
+            RuneValType::F64 => Ok(ValType::F64),
+        }
+    }
+}
+
+impl From<ValType> for RuneValType {
+    fn from(ty: ValType) -> RuneValType {
+        match ty {
+            ValType::I32 => RuneValType::I32,
+            ValType::I64 => RuneValType::I64,
+            ValType::F32 => RuneValType::F32,
+            ValType::F64 => RuneValType::F64,
         }
     }
 }
 
-/// C-compatible tagged union for values.
+/// C-compatible untagged union for values.
 #[repr(C)]
+#[derive(Clone, Copy)]
 pub union RuneVal {
     pub i32: i32,
     pub i64: i64,
@@ -101,6 +317,63 @@ pub union RuneVal {
     pub f64: f64,
 }
 
+/// A value with its type, as passed to and returned from calls.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RuneTypedVal {
+    pub ty: RuneValType,
+    pub val: RuneVal,
+}
+
+impl RuneTypedVal {
+    /// The value, or `None` for `Void`.
+    fn to_val(self) -> Option<Val> {
+        let ty = ValType::try_from(self.ty).ok()?;
+        Some(rune_val_to_val(&self.val, ty))
+    }
+
+    /// The value the embedder wrote at `this`, or `None` for `Void`. Fails
+    /// with the tag if it is not a `RuneValType`, which is checked as an
+    /// integer before it is read as one.
+    ///
+    /// # Safety
+    /// `this` must be valid for reads.
+    unsafe fn read(this: *const Self) -> Result<Option<Val>, u32> {
+        let ty = read_tag(ptr::addr_of!((*this).ty))?;
+        let val = ptr::addr_of!((*this).val).read();
+        Ok(ValType::try_from(ty)
+            .ok()
+            .map(|ty| rune_val_to_val(&val, ty)))
+    }
+
+    fn from_val(v: Option<Val>) -> Self {
+        match v {
+            Some(v) => RuneTypedVal {
+                ty: v.ty().into(),
+                val: val_to_rune_val(v),
+            },
+            None => RuneTypedVal {
+                ty: RuneValType::Void,
+                val: RuneVal { i64: 0 },
+            },
+        }
+    }
+}
+
+/// The type tag the embedder wrote at `tag`, or the raw tag if it is not a
+/// `RuneValType`.
+///
+/// # Safety
+/// `tag` must be valid for reads.
+unsafe fn read_tag(tag: *const RuneValType) -> Result<RuneValType, u32> {
+    // C enums are `int`-sized; read it as one in case it is out of range.
+    let raw = tag.cast::<u32>().read();
+    u8::try_from(raw)
+        .ok()
+        .and_then(|t| RuneValType::try_from(t).ok())
+        .ok_or(raw)
+}
+
 fn rune_val_to_val(rv: &RuneVal, ty: ValType) -> Val {
     match ty {
         ValType::I32 => Val::I32(unsafe { rv.i32 }),
@@ -119,44 +392,208 @@ fn val_to_rune_val(v: Val) -> RuneVal {
     }
 }
 
+// ── Values ────────────────────────────────────────────────────────────────────
+
+macro_rules! typed_val_fns {
+    ($($variant:ident($rust:ty): $new:ident, $get:ident;)*) => {
+        $(
+            #[doc = concat!("A `", stringify!($rust), "` tagged with its type.")]
+            #[no_mangle]
+            pub extern "C" fn $new(x: $rust) -> RuneTypedVal {
+                guard(|| RuneTypedVal::from_val(Some(Val::$variant(x))))
+            }
+
+            #[doc = concat!(
+                "Write `v` to `out` and return true if it is tagged `",
+                stringify!($rust),
+                "`; otherwise leave `out` alone and return false."
+            )]
+            ///
+            /// # Safety
+            /// `v` and `out` must be null or valid.
+            #[no_mangle]
+            pub unsafe extern "C" fn $get(v: *const RuneTypedVal, out: *mut $rust) -> bool {
+                guard(|| {
+                    if v.is_null() || out.is_null() {
+                        return false;
+                    }
+                    match RuneTypedVal::read(v) {
+                        Ok(Some(Val::$variant(x))) => {
+                            out.write(x);
+                            true
+                        }
+                        _ => false,
+                    }
+                })
+            }
+        )*
+    };
+}
+
+typed_val_fns! {
+    I32(i32): rune_val_i32, rune_val_as_i32;
+    I64(i64): rune_val_i64, rune_val_as_i64;
+    F32(f32): rune_val_f32, rune_val_as_f32;
+    F64(f64): rune_val_f64, rune_val_as_f64;
+}
+
+/// No value, tagged `RUNE_VOID`.
+#[no_mangle]
+pub extern "C" fn rune_val_void() -> RuneTypedVal {
+    guard(|| RuneTypedVal::from_val(None))
+}
+
+/// Whether `v` is tagged with a known `RuneValType`, including `RUNE_VOID`.
+///
+/// # Safety
+/// `v` must be null or valid for reads.
+#[no_mangle]
+pub unsafe extern "C" fn rune_val_is_valid(v: *const RuneTypedVal) -> bool {
+    guard(|| !v.is_null() && RuneTypedVal::read(v).is_ok())
+}
+
 // ── Host function callback type ───────────────────────────────────────────────
 
-pub type RuneHostFn = unsafe extern "C" fn(
-    instance: *mut CInstance,
+/// A host function taking and returning untagged values; see
+/// `rune_linker_define`.
+pub type RuneHostFn = unsafe extern "C-unwind" fn(
+    caller: *mut CCaller,
     args: *const RuneVal,
     n_args: usize,
     result: *mut RuneVal,
     user_data: *mut c_void,
 ) -> RuneError;
 
+/// A host function taking and returning tagged values; see
+/// `rune_linker_define_typed`.
+pub type RuneTypedHostFn = unsafe extern "C-unwind" fn(
+    caller: *mut CCaller,
+    args: *const RuneTypedVal,
+    n_args: usize,
+    result: *mut RuneTypedVal,
+    user_data: *mut c_void,
+) -> RuneError;
+
+/// A host function's signature, as given to `rune_linker_define_typed`.
+#[repr(C)]
+pub struct RuneSignature {
+    pub params: *const RuneValType,
+    pub n_params: usize,
+    /// `Void` for none.
+    pub result: RuneValType,
+}
+
+impl RuneSignature {
+    /// # Safety
+    /// `params` must be valid for `n_params` types.
+    unsafe fn to_func_type(&self) -> Result<FuncType, RuneError> {
+        if self.params.is_null() && self.n_params > 0 {
+            return Err(fail_with(
+                RuneError::InvalidArgument,
+                "signature params is null",
+            ));
+        }
+        let params = (0..self.n_params)
+            .map(|i| {
+                read_tag(self.params.add(i))
+                    .ok()
+                    .and_then(|t| ValType::try_from(t).ok())
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or_else(|| fail_with(RuneError::TrapTypeMismatch, "invalid parameter type"))?;
+        let result = read_tag(ptr::addr_of!(self.result))
+            .map_err(|_| fail_with(RuneError::TrapTypeMismatch, "invalid result type"))?;
+        Ok(FuncType {
+            params,
+            results: ValType::try_from(result).ok().into_iter().collect(),
+        })
+    }
+}
+
+/// The embedder's pointer, handed back to its callback on whichever thread
+/// the calling instance runs.
+#[derive(Clone, Copy)]
+struct UserData(*mut c_void);
+
+// SAFETY: Rune never dereferences it; thread-safety of what it points to is
+// the embedder's contract, as documented on `rune_linker_define`.
+unsafe impl Send for UserData {}
+unsafe impl Sync for UserData {}
+
 // ── Opaque C wrappers ─────────────────────────────────────────────────────────
 
-pub struct CRuntime(Runtime);
-pub struct CModule(Module);
-/// The Instance borrows the module, but for C ABI we need ownership.
-/// We store the module inside the instance box.
+pub struct CRuntime {
+    runtime: Runtime,
+    /// Shared with every instance created in this runtime.
+    on_trap: Arc<TrapCallback>,
+}
+pub struct CModule {
+    /// Held in an `Arc` so instances can keep the module alive after
+    /// `rune_module_free`.
+    module: Arc<Module>,
+    /// NUL-terminated export names for `rune_module_export_name`, built on
+    /// first use.
+    export_names: OnceLock<Vec<CString>>,
+}
 pub struct CInstance {
-    module: Box<Module>,
-    // instance: Instance<'???> — lifetime issue with C ABI
-    // Solution: store the module in the box and use raw ptr lifetime.
-    // In Phase 2 we'll use an Arc<Module> to solve this cleanly.
-    _placeholder: (),
+    /// Shared with linkers it was defined on by `rune_linker_define_instance`.
+    instance: Arc<Mutex<OwnedInstance>>,
+    on_trap: Arc<TrapCallback>,
+}
+pub struct CLinker(Linker);
+/// The instance calling a host function, valid only during the callback.
+pub struct CCaller<'a, 'b>(&'a mut Caller<'b>);
+
+impl CModule {
+    fn new(module: Module) -> Self {
+        CModule {
+            module: Arc::new(module),
+            export_names: OnceLock::new(),
+        }
+    }
+
+    /// The module, to be changed by the builder functions. Instances
+    /// already created keep their copy.
+    fn module_mut(&mut self) -> &mut Module {
+        self.export_names = OnceLock::new();
+        Arc::make_mut(&mut self.module)
+    }
+}
+
+impl CInstance {
+    fn new(instance: OwnedInstance, on_trap: Arc<TrapCallback>) -> Self {
+        CInstance {
+            instance: Arc::new(Mutex::new(instance)),
+            on_trap,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, OwnedInstance> {
+        self.instance.lock().unwrap_or_else(PoisonError::into_inner)
+    }
 }
 
 // ── Runtime ───────────────────────────────────────────────────────────────────
 
 #[no_mangle]
 pub extern "C" fn rune_runtime_new() -> *mut CRuntime {
-    Box::into_raw(Box::new(CRuntime(Runtime::new())))
+    guard(|| {
+        Box::into_raw(Box::new(CRuntime {
+            runtime: Runtime::new(),
+            on_trap: Arc::default(),
+        }))
+    })
 }
 
 /// # Safety
 /// Must only be called with a pointer returned by `rune_runtime_new`.
 #[no_mangle]
 pub unsafe extern "C" fn rune_runtime_free(rt: *mut CRuntime) {
-    if !rt.is_null() {
-        drop(Box::from_raw(rt));
-    }
+    guard(|| {
+        if !rt.is_null() {
+            drop(Box::from_raw(rt));
+        }
+    })
 }
 
 // ── Module loading ────────────────────────────────────────────────────────────
@@ -169,50 +606,1174 @@ pub unsafe extern "C" fn rune_module_load_bytes(
     data: *const u8,
     len: usize,
 ) -> *mut CModule {
-    if data.is_null() {
-        return ptr::null_mut();
-    }
-    let bytes = slice::from_raw_parts(data, len);
-    match Module::from_bytes(bytes) {
-        Ok(m) => Box::into_raw(Box::new(CModule(m))),
-        Err(_) => ptr::null_mut(),
-    }
+    guard(|| {
+        if data.is_null() {
+            return fail_null(RuneError::InvalidArgument, "module data is null");
+        }
+        let bytes = slice::from_raw_parts(data, len);
+        match Module::from_bytes(bytes) {
+            Ok(m) => Box::into_raw(Box::new(CModule::new(m))),
+            Err(trap) => fail_null(RuneError::from(&trap), trap),
+        }
+    })
 }
 
 /// # Safety
 /// `path` must be a valid null-terminated C string.
 #[no_mangle]
 pub unsafe extern "C" fn rune_module_load_file(
-    rt: *mut CRuntime,
+    _rt: *mut CRuntime,
     path: *const c_char,
 ) -> *mut CModule {
-    if path.is_null() {
-        return ptr::null_mut();
+    guard(|| {
+        if path.is_null() {
+            return fail_null(RuneError::InvalidArgument, "module path is null");
+        }
+        let path_str = match CStr::from_ptr(path).to_str() {
+            Ok(s) => s,
+            Err(_) => return fail_null(RuneError::InvalidUtf8, "module path is not valid UTF-8"),
+        };
+        let bytes = match std::fs::read(path_str) {
+            Ok(b) => b,
+            Err(e) => {
+                return fail_null(
+                    RuneError::HostError,
+                    format_args!("cannot read {path_str}: {e}"),
+                )
+            }
+        };
+        match Module::from_bytes(&bytes) {
+            Ok(m) => Box::into_raw(Box::new(CModule::new(m))),
+            Err(trap) => fail_null(RuneError::from(&trap), format_args!("{path_str}: {trap}")),
+        }
+    })
+}
+
+/// # Safety
+#[no_mangle]
+pub unsafe extern "C" fn rune_module_free(module: *mut CModule) {
+    guard(|| {
+        if !module.is_null() {
+            drop(Box::from_raw(module));
+        }
+    })
+}
+
+// ── Module building ───────────────────────────────────────────────────────────
+
+/// One op for `rune_module_add_function`, in the binary format's opcode
+/// numbering with its immediates unpacked.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct RuneOp {
+    pub opcode: u8,
+    /// Constant bits, local/function/import/global index, branch depth,
+    /// block type byte, or a load/store's alignment.
+    pub imm: u64,
+    /// Load/store offset.
+    pub offset: u32,
+    /// Load/store memory index; 0 is the default memory.
+    pub memory: u32,
+}
+
+/// Create an empty module with one page of memory, to be filled with
+/// `rune_module_add_function` and `rune_module_add_export`.
+#[no_mangle]
+pub extern "C" fn rune_module_new() -> *mut CModule {
+    guard(|| Box::into_raw(Box::new(CModule::new(Module::new()))))
+}
+
+/// Append function `name` with signature `sig`, extra `locals` and body
+/// `ops`, writing its index to `out_index` (may be null). Instances already
+/// created from `module` keep the module as it was.
+///
+/// # Safety
+/// `module` must be a live module handle, `name` a valid null-terminated C
+/// string, and `locals` and `ops` valid for `n_locals` and `n_ops` entries.
+#[no_mangle]
+pub unsafe extern "C" fn rune_module_add_function(
+    module: *mut CModule,
+    name: *const c_char,
+    sig: *const RuneSignature,
+    locals: *const RuneValType,
+    n_locals: usize,
+    ops: *const RuneOp,
+    n_ops: usize,
+    out_index: *mut u32,
+) -> RuneError {
+    guard(|| {
+        if module.is_null()
+            || name.is_null()
+            || sig.is_null()
+            || (locals.is_null() && n_locals > 0)
+            || (ops.is_null() && n_ops > 0)
+        {
+            return fail_with(
+                RuneError::InvalidArgument,
+                "null handle or pointer argument",
+            );
+        }
+        let Ok(name) = CStr::from_ptr(name).to_str() else {
+            return fail_with(RuneError::InvalidUtf8, "name is not valid UTF-8");
+        };
+        let ty = match (*sig).to_func_type() {
+            Ok(ty) => ty,
+            Err(err) => return err,
+        };
+        let locals = if n_locals == 0 {
+            &[][..]
+        } else {
+            slice::from_raw_parts(locals, n_locals)
+        };
+        let Ok(locals) = locals
+            .iter()
+            .map(|&t| ValType::try_from(t))
+            .collect::<Result<Vec<_>, ()>>()
+        else {
+            return fail_with(RuneError::TrapTypeMismatch, "invalid local type");
+        };
+        let ops = if n_ops == 0 {
+            &[][..]
+        } else {
+            slice::from_raw_parts(ops, n_ops)
+        };
+        let mut body = Vec::with_capacity(ops.len());
+        for (i, op) in ops.iter().enumerate() {
+            match op_from_parts(op.opcode, op.imm, op.offset, op.memory) {
+                Some(op) => body.push(op),
+                None => {
+                    return fail(&Trap::InvalidModule(format!(
+                        "{name}: op {i}: invalid opcode {:#04x} or immediate",
+                        op.opcode
+                    )))
+                }
+            }
+        }
+        let module = (*module).module_mut();
+        let Ok(index) = u32::try_from(module.functions.len()) else {
+            return fail(&Trap::InvalidModule("too many functions".into()));
+        };
+        module.functions.push(Function::new(name, ty, locals, body));
+        if !out_index.is_null() {
+            *out_index = index;
+        }
+        RuneError::Ok
+    })
+}
+
+/// Export function `func_index` of `module` as `name`.
+///
+/// # Safety
+/// `module` must be a live module handle and `name` a valid null-terminated
+/// C string.
+#[no_mangle]
+pub unsafe extern "C" fn rune_module_add_export(
+    module: *mut CModule,
+    name: *const c_char,
+    func_index: u32,
+) -> RuneError {
+    guard(|| {
+        if module.is_null() || name.is_null() {
+            return fail_with(
+                RuneError::InvalidArgument,
+                "null handle or pointer argument",
+            );
+        }
+        let Ok(name) = CStr::from_ptr(name).to_str() else {
+            return fail_with(RuneError::InvalidUtf8, "name is not valid UTF-8");
+        };
+        let module = (*module).module_mut();
+        if func_index as usize >= module.functions.len() {
+            return fail(&Trap::UndefinedExport(format!(
+                "{name}: no function {func_index}"
+            )));
+        }
+        if module.find_export(name).is_some() {
+            return fail(&Trap::InvalidModule(format!(
+                "export {name} is already defined"
+            )));
+        }
+        module.exports.push((name.to_owned(), func_index));
+        RuneError::Ok
+    })
+}
+
+/// Serialize `module`, built or loaded, to the binary format, handing back
+/// a buffer the caller releases with `rune_buffer_free`.
+///
+/// # Safety
+/// `module` must be a live module handle; `out_data` and `out_len` must be
+/// writable.
+#[no_mangle]
+pub unsafe extern "C" fn rune_module_to_bytes(
+    module: *const CModule,
+    out_data: *mut *mut u8,
+    out_len: *mut usize,
+) -> RuneError {
+    guard(|| {
+        if module.is_null() || out_data.is_null() || out_len.is_null() {
+            return fail_with(
+                RuneError::InvalidArgument,
+                "null handle or pointer argument",
+            );
+        }
+        let bytes = (*module).module.to_bytes().into_boxed_slice();
+        *out_len = bytes.len();
+        *out_data = Box::into_raw(bytes) as *mut u8;
+        RuneError::Ok
+    })
+}
+
+/// Free a buffer from `rune_module_to_bytes`; null is ignored.
+///
+/// # Safety
+/// `data` and `len` must come from one `rune_module_to_bytes` call.
+#[no_mangle]
+pub unsafe extern "C" fn rune_buffer_free(data: *mut u8, len: usize) {
+    guard(|| {
+        if !data.is_null() {
+            drop(Box::from_raw(ptr::slice_from_raw_parts_mut(data, len)));
+        }
+    })
+}
+
+/// The name `rune_buffer_free` had before ABI 1.9.
+///
+/// # Safety
+/// As for [`rune_buffer_free`].
+#[no_mangle]
+pub unsafe extern "C" fn rune_bytes_free(data: *mut u8, len: usize) {
+    rune_buffer_free(data, len)
+}
+
+// ── Module exports ────────────────────────────────────────────────────────────
+
+/// Number of functions `module` exports; 0 for a null handle.
+///
+/// # Safety
+/// `module` must be null or a live module handle.
+#[no_mangle]
+pub unsafe extern "C" fn rune_module_export_count(module: *const CModule) -> usize {
+    guard(|| {
+        if module.is_null() {
+            return 0;
+        }
+        let module = &*module;
+        module.module.exports.len()
+    })
+}
+
+/// Name of export `index`, or null if there is none. Valid until the module
+/// is changed by the builder functions or freed.
+///
+/// # Safety
+/// `module` must be null or a live module handle.
+#[no_mangle]
+pub unsafe extern "C" fn rune_module_export_name(
+    module: *const CModule,
+    index: usize,
+) -> *const c_char {
+    guard(|| {
+        if module.is_null() {
+            return ptr::null();
+        }
+        let module = &*module;
+        let names = module.export_names.get_or_init(|| {
+            module
+                .module
+                .exports
+                .iter()
+                // Names from the binary format cannot hold NULs, but ones built
+                // through the Rust API can; such names are cut at the first.
+                .map(|(name, _)| {
+                    let name = name.split('\0').next().unwrap_or_default();
+                    CString::new(name).expect("cut at the first NUL")
+                })
+                .collect()
+        });
+        names.get(index).map_or(ptr::null(), |name| name.as_ptr())
+    })
+}
+
+/// Signature of export `index`: the number of parameters goes to
+/// `out_n_params`, the first `max_params` of their types to `out_params`,
+/// and the result type, `RUNE_VOID` for none, to `out_result`. Any output
+/// may be null to skip it.
+///
+/// # Safety
+/// `module` must be a live module handle and `out_params` valid for
+/// `max_params` types.
+#[no_mangle]
+pub unsafe extern "C" fn rune_module_export_signature(
+    module: *const CModule,
+    index: usize,
+    out_params: *mut RuneValType,
+    max_params: usize,
+    out_n_params: *mut usize,
+    out_result: *mut RuneValType,
+) -> RuneError {
+    guard(|| {
+        if module.is_null() || (out_params.is_null() && max_params > 0) {
+            return fail_with(RuneError::InvalidArgument, "null module or params buffer");
+        }
+        let module = &(*module).module;
+        let Some((name, func)) = module.exports.get(index) else {
+            return fail(&Trap::UndefinedExport(format!("export {index}")));
+        };
+        let Some(func) = module.functions.get(*func as usize) else {
+            return fail(&Trap::InvalidModule(format!(
+                "export {name} refers to missing function {func}"
+            )));
+        };
+        let result = match func.ty.results[..] {
+            [] => RuneValType::Void,
+            [ty] => ty.into(),
+            _ => {
+                return fail_with(
+                    RuneError::UnsupportedFeature,
+                    format_args!("{name} returns more than one value"),
+                )
+            }
+        };
+        for (i, &ty) in func.ty.params.iter().take(max_params).enumerate() {
+            out_params.add(i).write(ty.into());
+        }
+        if !out_n_params.is_null() {
+            *out_n_params = func.ty.params.len();
+        }
+        if !out_result.is_null() {
+            *out_result = result;
+        }
+        RuneError::Ok
+    })
+}
+
+// ── Instantiation ─────────────────────────────────────────────────────────────
+
+/// # Safety
+/// `rt` and `module` must be live pointers from `rune_runtime_new` and
+/// `rune_module_load_*`. The instance shares the module, which may be freed
+/// before it.
+#[no_mangle]
+pub unsafe extern "C" fn rune_instance_new(
+    rt: *mut CRuntime,
+    module: *mut CModule,
+) -> *mut CInstance {
+    guard(|| {
+        if rt.is_null() || module.is_null() {
+            return fail_null(RuneError::InvalidArgument, "null runtime or module");
+        }
+        let rt = &*rt;
+        match rt.runtime.instantiate_owned((*module).module.clone()) {
+            Ok(instance) => Box::into_raw(Box::new(CInstance::new(instance, rt.on_trap.clone()))),
+            Err(trap) => fail_null(RuneError::from(&trap), trap),
+        }
+    })
+}
+
+/// # Safety
+/// Must only be called with a pointer returned by `rune_instance_new`.
+#[no_mangle]
+pub unsafe extern "C" fn rune_instance_free(inst: *mut CInstance) {
+    guard(|| {
+        if !inst.is_null() {
+            drop(Box::from_raw(inst));
+        }
+    })
+}
+
+// ── Linking ───────────────────────────────────────────────────────────────────
+
+#[no_mangle]
+pub extern "C" fn rune_linker_new() -> *mut CLinker {
+    guard(|| Box::into_raw(Box::new(CLinker(Linker::new()))))
+}
+
+/// # Safety
+/// Must only be called with a pointer returned by `rune_linker_new`.
+/// Instances created through the linker stay valid.
+#[no_mangle]
+pub unsafe extern "C" fn rune_linker_free(linker: *mut CLinker) {
+    guard(|| {
+        if !linker.is_null() {
+            drop(Box::from_raw(linker));
+        }
+    })
+}
+
+/// Define host function `module.name` with signature `sig`, implemented by
+/// `func` with untagged values.
+///
+/// Deprecated: a callback that misreads the signature reads and writes the
+/// wrong union member. Use `rune_linker_define_typed`.
+///
+/// # Safety
+/// As for `rune_linker_define_typed`.
+#[deprecated(note = "use `rune_linker_define_typed`, whose values carry their type")]
+#[no_mangle]
+pub unsafe extern "C" fn rune_linker_define(
+    linker: *mut CLinker,
+    module: *const c_char,
+    name: *const c_char,
+    sig: *const RuneSignature,
+    func: RuneHostFn,
+    user_data: *mut c_void,
+) -> RuneError {
+    guard(|| {
+        let user_data = UserData(user_data);
+        define_host_fn(linker, module, name, sig, move |caller, args, result| {
+            let user_data = user_data;
+            let raw: Vec<RuneVal> = args.iter().map(|&v| val_to_rune_val(v)).collect();
+            let mut out = RuneVal { i64: 0 };
+            // SAFETY: the pointers are valid for the duration of the call.
+            match unsafe { func(caller, raw.as_ptr(), raw.len(), &mut out, user_data.0) } {
+                RuneError::Ok => Ok(result.map(|ty| rune_val_to_val(&out, ty))),
+                err => Err(error_str(err).trim_end_matches('\0').to_owned()),
+            }
+        })
+    })
+}
+
+/// Define host function `module.name` with signature `sig`, implemented by
+/// `func`. Arguments arrive tagged with their types, and the result is
+/// tagged with the signature's result type (`RUNE_VOID` for none) before
+/// the call; a callback that returns it with a different tag traps the
+/// guest. Each call passes `user_data` back to `func`, on whichever thread
+/// the calling instance runs, so it must be safe to use from there. A
+/// callback returning an error traps the guest with `RUNE_HOST_ERROR`.
+///
+/// # Safety
+/// `linker` must come from `rune_linker_new`, `module` and `name` must be
+/// valid null-terminated C strings, and `sig.params` must be valid for
+/// `sig.n_params` types.
+#[no_mangle]
+pub unsafe extern "C" fn rune_linker_define_typed(
+    linker: *mut CLinker,
+    module: *const c_char,
+    name: *const c_char,
+    sig: *const RuneSignature,
+    func: RuneTypedHostFn,
+    user_data: *mut c_void,
+) -> RuneError {
+    guard(|| {
+        let user_data = UserData(user_data);
+        define_host_fn(linker, module, name, sig, move |caller, args, result| {
+            let user_data = user_data;
+            let typed: Vec<RuneTypedVal> = args
+                .iter()
+                .map(|&v| RuneTypedVal::from_val(Some(v)))
+                .collect();
+            // The callback may write any tag, so `out` is only read through
+            // `RuneTypedVal::read`.
+            let mut out = MaybeUninit::new(RuneTypedVal::from_val(result.map(Val::default_for)));
+            // SAFETY: the pointers are valid for the duration of the call.
+            let err = unsafe {
+                func(
+                    caller,
+                    typed.as_ptr(),
+                    typed.len(),
+                    out.as_mut_ptr(),
+                    user_data.0,
+                )
+            };
+            if err != RuneError::Ok {
+                return Err(error_str(err).trim_end_matches('\0').to_owned());
+            }
+            // SAFETY: `out` is initialized.
+            let val = unsafe { RuneTypedVal::read(out.as_ptr()) }
+                .map_err(|tag| format!("returned a value of invalid type {tag:#x}"))?;
+            if val.map(|v| v.ty()) != result {
+                return Err(format!(
+                    "returned {}, expected {}",
+                    type_name(val.map(|v| v.ty())),
+                    type_name(result)
+                ));
+            }
+            Ok(val)
+        })
+    })
+}
+
+/// Shared body of the `rune_linker_define*` functions. `invoke` makes one
+/// call with the guest's arguments and the signature's result type, and
+/// returns the result or why the callback failed.
+unsafe fn define_host_fn<F>(
+    linker: *mut CLinker,
+    module: *const c_char,
+    name: *const c_char,
+    sig: *const RuneSignature,
+    invoke: F,
+) -> RuneError
+where
+    F: Fn(&mut CCaller, &[Val], Option<ValType>) -> Result<Option<Val>, String>
+        + Send
+        + Sync
+        + 'static,
+{
+    if linker.is_null() || module.is_null() || name.is_null() || sig.is_null() {
+        return fail_with(
+            RuneError::InvalidArgument,
+            "null handle or pointer argument",
+        );
     }
-    let path_str = match CStr::from_ptr(path).to_str() {
-        Ok(s) => s,
-        Err(_) => return ptr::null_mut(),
+    let (Ok(module), Ok(name)) = (
+        CStr::from_ptr(module).to_str(),
+        CStr::from_ptr(name).to_str(),
+    ) else {
+        return fail_with(RuneError::InvalidUtf8, "name is not valid UTF-8");
     };
-    let bytes = match std::fs::read(path_str) {
-        Ok(b) => b,
-        Err(_) => return ptr::null_mut(),
+    let ty = match (*sig).to_func_type() {
+        Ok(ty) => ty,
+        Err(err) => return err,
     };
-    rune_module_load_bytes(rt, bytes.as_ptr(), bytes.len())
+    let result = ty.results.first().copied();
+    let key = format!("{module}.{name}");
+    let defined = (*linker)
+        .0
+        .func_with_caller(module, name, ty, move |caller, args| {
+            let mut caller = CCaller(caller);
+            catch_panic(|| invoke(&mut caller, args, result))
+                .and_then(|result| result)
+                .map_err(|msg| Trap::HostError(format!("{key}: {msg}")))
+        });
+    match defined {
+        Ok(_) => RuneError::Ok,
+        Err(trap) => fail(&trap),
+    }
+}
+
+/// `"i32"` and so on, or `"nothing"` for no value.
+fn type_name(ty: Option<ValType>) -> &'static str {
+    match ty {
+        Some(ValType::I32) => "i32",
+        Some(ValType::I64) => "i64",
+        Some(ValType::F32) => "f32",
+        Some(ValType::F64) => "f64",
+        None => "nothing",
+    }
 }
 
+/// Define every export of `inst` on `linker` as `module.<export>`, so
+/// modules instantiated through it can import them. Calls into `inst` from
+/// other instances take turns with calls made on it directly. `inst` may be
+/// freed afterwards: the linker keeps what it needs alive.
+///
 /// # Safety
+/// `linker` must come from `rune_linker_new`, `inst` from
+/// `rune_instance_new` or `rune_linker_instantiate`, and `module` must be a
+/// valid null-terminated C string.
 #[no_mangle]
-pub unsafe extern "C" fn rune_module_free(module: *mut CModule) {
-    if !module.is_null() {
-        drop(Box::from_raw(module));
+pub unsafe extern "C" fn rune_linker_define_instance(
+    linker: *mut CLinker,
+    module: *const c_char,
+    inst: *const CInstance,
+) -> RuneError {
+    guard(|| {
+        if linker.is_null() || module.is_null() || inst.is_null() {
+            return fail_with(
+                RuneError::InvalidArgument,
+                "null handle or pointer argument",
+            );
+        }
+        let Ok(module) = CStr::from_ptr(module).to_str() else {
+            return fail_with(RuneError::InvalidUtf8, "name is not valid UTF-8");
+        };
+        match (*linker).0.instance(module, &(*inst).instance) {
+            Ok(_) => RuneError::Ok,
+            Err(trap) => fail(&trap),
+        }
+    })
+}
+
+/// Return how many of `module`'s imports `linker` does not define.
+///
+/// # Safety
+/// `linker` and `module` must be null or live handles.
+#[no_mangle]
+pub unsafe extern "C" fn rune_linker_unresolved_count(
+    linker: *const CLinker,
+    module: *const CModule,
+) -> usize {
+    guard(|| {
+        if linker.is_null() || module.is_null() {
+            return 0;
+        }
+        (*linker).0.unresolved(&(*module).module).len()
+    })
+}
+
+/// Copy the `index`th import of `module` that `linker` does not define, as
+/// `"module.name"`, into `buf`, truncated to `buf_len - 1` bytes and
+/// NUL-terminated. Returns the full length of the name, excluding the NUL,
+/// or 0 if `index` is out of range, like `snprintf`.
+///
+/// # Safety
+/// `linker` and `module` must be null or live handles, and `buf` must be
+/// valid for `buf_len` bytes, or null if `buf_len` is 0.
+#[no_mangle]
+pub unsafe extern "C" fn rune_linker_unresolved_name(
+    linker: *const CLinker,
+    module: *const CModule,
+    index: usize,
+    buf: *mut c_char,
+    buf_len: usize,
+) -> usize {
+    guard(|| {
+        if linker.is_null() || module.is_null() {
+            return 0;
+        }
+        let unresolved = (*linker).0.unresolved(&(*module).module);
+        let Some(name) = unresolved.get(index) else {
+            return 0;
+        };
+        if !buf.is_null() && buf_len > 0 {
+            let n = name.len().min(buf_len - 1);
+            ptr::copy_nonoverlapping(name.as_ptr().cast::<c_char>(), buf, n);
+            buf.add(n).write(0);
+        }
+        name.len()
+    })
+}
+
+/// Instantiate `module` in `rt` with its imports bound to the functions
+/// defined on `linker`. Returns NULL on error, including imports the linker
+/// does not define, which fail with `RUNE_UNDEFINED_IMPORT`;
+/// `rune_linker_unresolved_name` lists them.
+///
+/// # Safety
+/// The pointers must be live handles from their `_new` and `_load_*`
+/// functions.
+#[no_mangle]
+pub unsafe extern "C" fn rune_linker_instantiate(
+    linker: *mut CLinker,
+    rt: *mut CRuntime,
+    module: *mut CModule,
+) -> *mut CInstance {
+    guard(|| {
+        if linker.is_null() || rt.is_null() || module.is_null() {
+            return fail_null(RuneError::InvalidArgument, "null linker, runtime or module");
+        }
+        let rt = &*rt;
+        match (*linker)
+            .0
+            .instantiate_owned(&rt.runtime, (*module).module.clone())
+        {
+            Ok(instance) => Box::into_raw(Box::new(CInstance::new(instance, rt.on_trap.clone()))),
+            Err(trap) => fail_null(RuneError::from(&trap), trap),
+        }
+    })
+}
+
+// ── Function calls ────────────────────────────────────────────────────────────
+
+/// Call export `name` with `n_args` typed values, which must match its
+/// parameters in number and type, and write its result to `out_result`
+/// unless that is null: tagged `RUNE_VOID` if the export returns nothing.
+///
+/// # Safety
+/// `inst` must come from `rune_instance_new`, `name` must be a valid
+/// null-terminated C string, and `args` must be valid for `n_args` values.
+#[no_mangle]
+pub unsafe extern "C" fn rune_instance_call(
+    inst: *mut CInstance,
+    name: *const c_char,
+    args: *const RuneTypedVal,
+    n_args: usize,
+    out_result: *mut RuneTypedVal,
+) -> RuneError {
+    guard(|| {
+        let (name, args) = match call_args(inst, name, args, n_args) {
+            Ok(call) => call,
+            Err(err) => return err,
+        };
+        let result = (*inst).lock().call(name, &args);
+        finish_call(&*inst, result, out_result)
+    })
+}
+
+/// Like `rune_instance_call`, but the call is cancelled with
+/// `RUNE_INTERRUPTED` if it has not returned after `deadline_ms`
+/// milliseconds. It runs on a worker thread while this one waits, and has
+/// stopped by the time this returns.
+///
+/// # Safety
+/// As for `rune_instance_call`.
+#[no_mangle]
+pub unsafe extern "C" fn rune_call_with_deadline_ms(
+    inst: *mut CInstance,
+    name: *const c_char,
+    args: *const RuneTypedVal,
+    n_args: usize,
+    out_result: *mut RuneTypedVal,
+    deadline_ms: u64,
+) -> RuneError {
+    guard(|| {
+        let (name, args) = match call_args(inst, name, args, n_args) {
+            Ok(call) => call,
+            Err(err) => return err,
+        };
+        let timeout = Duration::from_millis(deadline_ms);
+        let outcome = executor::call_with_timeout(&mut (*inst).lock(), name, &args, timeout);
+        match outcome {
+            Ok(outcome) => finish_call(&*inst, outcome.result, out_result),
+            Err(trap) => fail(&trap),
+        }
+    })
+}
+
+/// Check a call's handle, export name and arguments against the export's
+/// parameters.
+unsafe fn call_args<'a>(
+    inst: *mut CInstance,
+    name: *const c_char,
+    args: *const RuneTypedVal,
+    n_args: usize,
+) -> Result<(&'a str, Vec<Val>), RuneError> {
+    if inst.is_null() || name.is_null() || (args.is_null() && n_args > 0) {
+        return Err(fail_with(
+            RuneError::InvalidArgument,
+            "null handle or pointer argument",
+        ));
+    }
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return Err(fail_with(RuneError::InvalidUtf8, "name is not valid UTF-8"));
+    };
+    let instance = (*inst).lock();
+    let module = instance.module();
+    let Some(idx) = module.find_export(name) else {
+        return Err(fail(&Trap::UndefinedExport(name.into())));
+    };
+    let args: Option<Vec<Val>> = (0..n_args)
+        .map(|i| RuneTypedVal::read(args.add(i)))
+        .collect::<Result<_, _>>()
+        .map_err(|tag| {
+            fail_with(
+                RuneError::TrapTypeMismatch,
+                format_args!("{name}: invalid value type {tag:#x}"),
+            )
+        })?;
+    let matches = args.as_ref().is_some_and(|args| {
+        let params = &module.functions[idx as usize].ty.params;
+        args.len() == params.len() && args.iter().zip(params).all(|(a, &p)| a.ty() == p)
+    });
+    match (matches, args) {
+        (true, Some(args)) => Ok((name, args)),
+        _ => Err(fail_with(
+            RuneError::TrapTypeMismatch,
+            format_args!("{name}: arguments do not match its parameters"),
+        )),
+    }
+}
+
+/// Write a call's result to `out_result`, if given, or record its trap and
+/// report it to the runtime's trap callback.
+unsafe fn finish_call(
+    inst: &CInstance,
+    result: crate::trap::Result<Option<Val>>,
+    out_result: *mut RuneTypedVal,
+) -> RuneError {
+    match result {
+        Ok(result) => {
+            if !out_result.is_null() {
+                out_result.write(RuneTypedVal::from_val(result));
+            }
+            RuneError::Ok
+        }
+        Err(trap) => {
+            let err = fail(&trap);
+            inst.on_trap.report(&inst.lock(), &trap);
+            err
+        }
     }
 }
 
+// ── Trap callback ─────────────────────────────────────────────────────────────
+
+/// Called with every trap a call through the C API ends in, before the
+/// call returns.
+pub type RuneTrapFn = unsafe extern "C-unwind" fn(
+    code: RuneError,
+    message: *const c_char,
+    frames: *const RuneFrame,
+    n_frames: usize,
+    user_data: *mut c_void,
+);
+
+/// One guest frame of a trap's backtrace, innermost first.
+#[repr(C)]
+pub struct RuneFrame {
+    pub func_index: u32,
+    /// Null if the function has no name.
+    pub func_name: *const c_char,
+    /// Index of the op that was executing.
+    pub op_index: usize,
+    /// Source file, or null without a source map entry.
+    pub file: *const c_char,
+    /// 1-based; 0 without a source map entry.
+    pub line: u32,
+    pub column: u32,
+}
+
+#[derive(Default)]
+struct TrapCallback(RwLock<Option<(RuneTrapFn, UserData)>>);
+
+impl TrapCallback {
+    fn set(&self, callback: Option<(RuneTrapFn, UserData)>) {
+        *self.0.write().unwrap_or_else(PoisonError::into_inner) = callback;
+    }
+
+    fn report(&self, instance: &OwnedInstance, trap: &Trap) {
+        let Some((callback, user_data)) = *self.0.read().unwrap_or_else(PoisonError::into_inner)
+        else {
+            return;
+        };
+        let module = instance.module();
+        let c_str = |s: &str| CString::new(s.replace('\0', "")).expect("NULs removed");
+        // Owns the strings the frames point into until the callback returns.
+        let mut strings = Vec::new();
+        let frames: Vec<RuneFrame> = instance
+            .trap_backtrace()
+            .iter()
+            .map(|frame| {
+                let name = module
+                    .functions
+                    .get(frame.func_index as usize)
+                    .map(|f| c_str(&f.name));
+                let file = frame
+                    .loc
+                    .zip(module.source_map.as_ref())
+                    .and_then(|(loc, sm)| sm.file_name(loc.file))
+                    .map(c_str);
+                let as_ptr = |s: &Option<CString>| s.as_ref().map_or(ptr::null(), |s| s.as_ptr());
+                let out = RuneFrame {
+                    func_index: frame.func_index,
+                    func_name: as_ptr(&name),
+                    op_index: frame.op_index,
+                    file: as_ptr(&file),
+                    line: frame.loc.map_or(0, |loc| loc.line),
+                    column: frame.loc.map_or(0, |loc| loc.column),
+                };
+                strings.extend(name.into_iter().chain(file));
+                out
+            })
+            .collect();
+        let message = c_str(&trap.to_string());
+        // SAFETY: every pointer outlives the call. A callback that unwinds
+        // is ignored; the call still fails with `trap`.
+        let _ = catch_panic(|| unsafe {
+            callback(
+                RuneError::from(trap),
+                message.as_ptr(),
+                frames.as_ptr(),
+                frames.len(),
+                user_data.0,
+            )
+        });
+    }
+}
+
+/// Call `callback` with every trap a call on an instance of `rt` ends in,
+/// before the call returns, on the calling thread; null `callback` removes
+/// it. Applies to instances already created.
+///
+/// # Safety
+/// `rt` must come from `rune_runtime_new`; `user_data` must be safe to use
+/// from any thread that calls into the runtime's instances.
+#[no_mangle]
+pub unsafe extern "C" fn rune_runtime_set_trap_callback(
+    rt: *mut CRuntime,
+    callback: Option<RuneTrapFn>,
+    user_data: *mut c_void,
+) -> RuneError {
+    guard(|| {
+        if rt.is_null() {
+            return fail_with(RuneError::InvalidArgument, "null runtime");
+        }
+        (*rt)
+            .on_trap
+            .set(callback.map(|cb| (cb, UserData(user_data))));
+        RuneError::Ok
+    })
+}
+
+// ── Fuel ──────────────────────────────────────────────────────────────────────
+
+/// Meter the instance's execution with `fuel` units, replacing whatever was
+/// left. Each op costs one; running out fails the call with
+/// `RUNE_OUT_OF_FUEL`, after which the instance can be refilled.
+///
+/// # Safety
+/// `inst` must come from `rune_instance_new` or `rune_linker_instantiate`.
+#[no_mangle]
+pub unsafe extern "C" fn rune_instance_set_fuel(inst: *mut CInstance, fuel: u64) -> RuneError {
+    guard(|| {
+        if inst.is_null() {
+            return fail_with(RuneError::InvalidArgument, "null instance");
+        }
+        (*inst).lock().set_fuel(fuel);
+        RuneError::Ok
+    })
+}
+
+/// Stop metering the instance's execution.
+///
+/// # Safety
+/// `inst` must be null or a live instance handle.
+#[no_mangle]
+pub unsafe extern "C" fn rune_instance_clear_fuel(inst: *mut CInstance) {
+    guard(|| {
+        if !inst.is_null() {
+            (*inst).lock().clear_fuel();
+        }
+    })
+}
+
+/// Write the fuel left to `out_fuel` and return true, or return false if
+/// the instance is not metered.
+///
+/// # Safety
+/// `inst` must be null or a live instance handle, and `out_fuel` writable.
+#[no_mangle]
+pub unsafe extern "C" fn rune_instance_remaining_fuel(
+    inst: *const CInstance,
+    out_fuel: *mut u64,
+) -> bool {
+    guard(|| {
+        if inst.is_null() || out_fuel.is_null() {
+            return false;
+        }
+        match (*inst).lock().fuel() {
+            Some(fuel) => {
+                *out_fuel = fuel;
+                true
+            }
+            None => false,
+        }
+    })
+}
+
+// ── Memory access ─────────────────────────────────────────────────────────────
+
+/// Base of the instance's default memory, or null. Valid until the memory
+/// grows or the instance is freed.
+///
+/// # Safety
+/// `inst` must come from `rune_instance_new` or `rune_linker_instantiate`.
+#[no_mangle]
+pub unsafe extern "C" fn rune_memory_base(inst: *mut CInstance) -> *mut u8 {
+    guard(|| {
+        if inst.is_null() {
+            return ptr::null_mut();
+        }
+        (*inst).lock().memory_mut().bytes_mut().as_mut_ptr()
+    })
+}
+
+/// Size of the instance's default memory in bytes; 0 for a null handle.
+///
+/// # Safety
+/// `inst` must be null or come from `rune_instance_new` or
+/// `rune_linker_instantiate`.
+#[no_mangle]
+pub unsafe extern "C" fn rune_memory_size(inst: *mut CInstance) -> usize {
+    guard(|| {
+        if inst.is_null() {
+            return 0;
+        }
+        (*inst).lock().memory().size()
+    })
+}
+
+/// Grow the default memory by `delta_pages` pages, subject to its maximum
+/// and the runtime's limits.
+///
+/// # Safety
+/// `inst` must come from `rune_instance_new` or `rune_linker_instantiate`.
+#[no_mangle]
+pub unsafe extern "C" fn rune_memory_grow(inst: *mut CInstance, delta_pages: usize) -> RuneError {
+    guard(|| {
+        if inst.is_null() {
+            return fail_with(
+                RuneError::InvalidArgument,
+                "null handle or pointer argument",
+            );
+        }
+        match (*inst).lock().memory_mut().grow(delta_pages) {
+            Ok(_) => RuneError::Ok,
+            Err(trap) => fail(&trap),
+        }
+    })
+}
+
+/// Copy `len` bytes at `offset` in the default memory to `dst`. Nothing is
+/// copied unless the whole range is in bounds.
+///
+/// # Safety
+/// `inst` must come from `rune_instance_new` or `rune_linker_instantiate`,
+/// and `dst` must be valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rune_memory_read(
+    inst: *mut CInstance,
+    offset: usize,
+    dst: *mut c_void,
+    len: usize,
+) -> RuneError {
+    guard(|| {
+        if inst.is_null() || (dst.is_null() && len > 0) {
+            return fail_with(
+                RuneError::InvalidArgument,
+                "null handle or pointer argument",
+            );
+        }
+        let buf = if len == 0 {
+            &mut [][..]
+        } else {
+            slice::from_raw_parts_mut(dst as *mut u8, len)
+        };
+        match (*inst).lock().memory().read_into(offset, buf) {
+            Ok(()) => RuneError::Ok,
+            Err(trap) => fail(&trap),
+        }
+    })
+}
+
+/// Copy `len` bytes from `src` to `offset` in the default memory. Nothing is
+/// copied unless the whole range is in bounds.
+///
+/// # Safety
+/// `inst` must come from `rune_instance_new` or `rune_linker_instantiate`,
+/// and `src` must be valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rune_memory_write(
+    inst: *mut CInstance,
+    offset: usize,
+    src: *const c_void,
+    len: usize,
+) -> RuneError {
+    guard(|| {
+        if inst.is_null() || (src.is_null() && len > 0) {
+            return fail_with(
+                RuneError::InvalidArgument,
+                "null handle or pointer argument",
+            );
+        }
+        let bytes = if len == 0 {
+            &[][..]
+        } else {
+            slice::from_raw_parts(src as *const u8, len)
+        };
+        match (*inst).lock().memory_mut().write_from(offset, bytes) {
+            Ok(()) => RuneError::Ok,
+            Err(trap) => fail(&trap),
+        }
+    })
+}
+
+// ── Caller memory ─────────────────────────────────────────────────────────────
+
+/// Size of the calling instance's default memory in bytes; 0 for a null
+/// caller.
+///
+/// # Safety
+/// `caller` must be null or the pointer handed to a running host callback.
+#[no_mangle]
+pub unsafe extern "C" fn rune_caller_memory_size(caller: *mut CCaller) -> usize {
+    guard(|| {
+        if caller.is_null() {
+            return 0;
+        }
+        (*caller).0.memory().size()
+    })
+}
+
+/// `rune_memory_read` on the calling instance, which is held by the call
+/// and so cannot be passed to `rune_memory_read` from its own callback.
+///
+/// # Safety
+/// `caller` must be the pointer handed to a running host callback, and
+/// `dst` must be valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rune_caller_memory_read(
+    caller: *mut CCaller,
+    offset: usize,
+    dst: *mut c_void,
+    len: usize,
+) -> RuneError {
+    guard(|| {
+        if caller.is_null() || (dst.is_null() && len > 0) {
+            return fail_with(
+                RuneError::InvalidArgument,
+                "null handle or pointer argument",
+            );
+        }
+        match (*caller).0.memory().slice(offset, len) {
+            Ok(bytes) => {
+                if len > 0 {
+                    ptr::copy_nonoverlapping(bytes.as_ptr(), dst as *mut u8, len);
+                }
+                RuneError::Ok
+            }
+            Err(trap) => fail(&trap),
+        }
+    })
+}
+
+/// `rune_memory_write` on the calling instance; see
+/// `rune_caller_memory_read`.
+///
+/// # Safety
+/// `caller` must be the pointer handed to a running host callback, and
+/// `src` must be valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rune_caller_memory_write(
+    caller: *mut CCaller,
+    offset: usize,
+    src: *const c_void,
+    len: usize,
+) -> RuneError {
+    guard(|| {
+        if caller.is_null() || (src.is_null() && len > 0) {
+            return fail_with(
+                RuneError::InvalidArgument,
+                "null handle or pointer argument",
+            );
+        }
+        match (*caller).0.memory().slice_mut(offset, len) {
+            Ok(bytes) => {
+                if len > 0 {
+                    ptr::copy_nonoverlapping(src as *const u8, bytes.as_mut_ptr(), len);
+                }
+                RuneError::Ok
+            }
+            Err(trap) => fail(&trap),
+        }
+    })
+}
+
 // ── Error strings ─────────────────────────────────────────────────────────────
 
 #[no_mangle]
 pub extern "C" fn rune_error_string(err: RuneError) -> *const c_char {
-    let s = match err {
+    guard(|| error_str(err).as_ptr() as *const c_char)
+}
+
+/// The full message for the last call that failed on this thread, such as
+/// the export name or byte offset behind a coarse `RuneError`, or null if
+/// none has. Valid until the next failing call on the thread.
+#[no_mangle]
+pub extern "C" fn rune_last_error_message() -> *const c_char {
+    guard(|| {
+        LAST_ERROR.with(|last| {
+            last.borrow()
+                .as_ref()
+                .map_or(ptr::null(), |(_, msg)| msg.as_ptr())
+        })
+    })
+}
+
+/// The message for `err`, with its terminating NUL.
+fn error_str(err: RuneError) -> &'static str {
+    match err {
         RuneError::Ok => "ok\0",
         RuneError::InvalidModule => "invalid module\0",
         RuneError::OutOfMemory => "out of memory\0",
@@ -224,6 +1785,35 @@ pub extern "C" fn rune_error_string(err: RuneError) -> *const c_char {
         RuneError::UndefinedExport => "undefined export\0",
         RuneError::UndefinedImport => "undefined import\0",
         RuneError::HostError => "host error\0",
-    };
-    s.as_ptr() as *const c_char
+        RuneError::UnsupportedFeature => "runtime lacks a required feature\0",
+        RuneError::TrapAccessViolation => "memory access violation\0",
+        RuneError::InvalidUtf8 => "invalid UTF-8 in guest string\0",
+        RuneError::TrapUnaligned => "unaligned memory access\0",
+        RuneError::TrapUninitialized => "read of uninitialized memory\0",
+        RuneError::PermissionDenied => "host function not granted\0",
+        RuneError::Interrupted => "execution interrupted\0",
+        RuneError::OutOfFuel => "fuel exhausted\0",
+        RuneError::CpuTimeExceeded => "CPU time budget exceeded\0",
+        RuneError::InvalidArgument => "invalid argument\0",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn guard_turns_panics_into_failures() {
+        let message = || unsafe { CStr::from_ptr(rune_last_error_message()) };
+        assert_eq!(
+            guard(|| -> RuneError { panic!("boom") }),
+            RuneError::HostError
+        );
+        assert_eq!(message(), c"host error: panic: boom");
+        let n = 7;
+        assert!(guard(|| -> *mut CModule { panic!("bad {n}") }).is_null());
+        assert_eq!(message(), c"host error: panic: bad 7");
+        assert_eq!(guard(|| -> usize { panic!() }), 0);
+        assert_eq!(guard(|| 42u32), 42);
+    }
 }
@@ -0,0 +1,403 @@
+//! Handle-based variant of the C API, for languages with a garbage
+//! collector — Go, Java, C# — whose interop layers cannot easily own raw
+//! pointers.
+//!
+//! Runtimes, modules and instances live in a process-wide registry and are
+//! named by [`RuneHandle`]s, plain integers the host can store anywhere and
+//! copy freely. `rune_handle_release` drops the registry's reference; an
+//! object another call is still using stays alive until that call returns,
+//! and a released handle is never reused for a different object (its
+//! generation changes), so stale handles fail with `RUNE_INVALID_ARGUMENT`
+//! instead of reaching the wrong object. The `rune_h_*` functions forward
+//! to their pointer-based counterparts.
+
+use std::ffi::c_void;
+use std::os::raw::c_char;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+#[allow(deprecated)]
+use super::rune_linker_define;
+use super::{
+    fail_with, guard, rune_instance_call, rune_instance_new, rune_linker_define_instance,
+    rune_linker_define_typed, rune_linker_instantiate, rune_linker_new, rune_memory_read,
+    rune_memory_write, rune_module_load_bytes, rune_runtime_new, CInstance, CLinker, CModule,
+    CRuntime, RuneError, RuneHostFn, RuneSignature, RuneTypedHostFn, RuneTypedVal,
+};
+
+/// Names a registered object; 0 is never a valid handle.
+pub type RuneHandle = u64;
+
+enum Object {
+    Runtime(Arc<CRuntime>),
+    Module(Arc<CModule>),
+    /// Locked for the duration of each call, so calls on one instance from
+    /// different threads take turns.
+    Instance(Arc<Mutex<CInstance>>),
+    Linker(Arc<Mutex<CLinker>>),
+}
+
+/// Slots indexed by the low 32 bits of a handle (minus one); the high 32
+/// bits must match the slot's generation.
+#[derive(Default)]
+struct Registry {
+    slots: Vec<Slot>,
+    free: Vec<u32>,
+}
+
+#[derive(Default)]
+struct Slot {
+    generation: u32,
+    object: Option<Object>,
+}
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry {
+    slots: Vec::new(),
+    free: Vec::new(),
+});
+
+fn registry() -> MutexGuard<'static, Registry> {
+    REGISTRY.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+impl Registry {
+    fn insert(&mut self, object: Object) -> RuneHandle {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None => {
+                self.slots.push(Slot::default());
+                (self.slots.len() - 1) as u32
+            }
+        };
+        let slot = &mut self.slots[index as usize];
+        slot.object = Some(object);
+        (slot.generation as u64) << 32 | (index as u64 + 1)
+    }
+
+    fn slot(&mut self, handle: RuneHandle) -> Option<&mut Slot> {
+        let index = (handle as u32).checked_sub(1)?;
+        let slot = self.slots.get_mut(index as usize)?;
+        (slot.generation == (handle >> 32) as u32 && slot.object.is_some()).then_some(slot)
+    }
+
+    fn runtime(&mut self, handle: RuneHandle) -> Option<Arc<CRuntime>> {
+        match self.slot(handle)?.object.as_ref()? {
+            Object::Runtime(rt) => Some(rt.clone()),
+            _ => None,
+        }
+    }
+
+    fn module(&mut self, handle: RuneHandle) -> Option<Arc<CModule>> {
+        match self.slot(handle)?.object.as_ref()? {
+            Object::Module(module) => Some(module.clone()),
+            _ => None,
+        }
+    }
+
+    fn instance(&mut self, handle: RuneHandle) -> Option<Arc<Mutex<CInstance>>> {
+        match self.slot(handle)?.object.as_ref()? {
+            Object::Instance(inst) => Some(inst.clone()),
+            _ => None,
+        }
+    }
+
+    fn linker(&mut self, handle: RuneHandle) -> Option<Arc<Mutex<CLinker>>> {
+        match self.slot(handle)?.object.as_ref()? {
+            Object::Linker(linker) => Some(linker.clone()),
+            _ => None,
+        }
+    }
+}
+
+fn bad_handle(handle: RuneHandle, kind: &str) -> RuneError {
+    fail_with(
+        RuneError::InvalidArgument,
+        format_args!("{handle:#x} is not a live {kind} handle"),
+    )
+}
+
+/// Take ownership of an object a pointer-based constructor returned.
+///
+/// # Safety
+/// `ptr` must be null or fresh from `Box::into_raw`.
+unsafe fn adopt<T>(ptr: *mut T) -> Option<T> {
+    (!ptr.is_null()).then(|| *Box::from_raw(ptr))
+}
+
+/// Run `f` with exclusive access to instance `handle`.
+pub(super) fn with_instance(
+    handle: RuneHandle,
+    f: impl FnOnce(*mut CInstance) -> RuneError,
+) -> RuneError {
+    let Some(inst) = registry().instance(handle) else {
+        return bad_handle(handle, "instance");
+    };
+    let mut inst = inst.lock().unwrap_or_else(PoisonError::into_inner);
+    f(&mut *inst)
+}
+
+/// Run `f` with exclusive access to linker `handle`.
+pub(super) fn with_linker(
+    handle: RuneHandle,
+    f: impl FnOnce(*mut CLinker) -> RuneError,
+) -> RuneError {
+    let Some(linker) = registry().linker(handle) else {
+        return bad_handle(handle, "linker");
+    };
+    let mut linker = linker.lock().unwrap_or_else(PoisonError::into_inner);
+    f(&mut *linker)
+}
+
+/// Create a runtime. Returns 0 on failure.
+#[no_mangle]
+pub extern "C" fn rune_h_runtime_new() -> RuneHandle {
+    guard(|| {
+        // SAFETY: `rune_runtime_new` returns a fresh box.
+        let rt = unsafe { adopt(rune_runtime_new()) }.expect("runtime creation cannot fail");
+        registry().insert(Object::Runtime(Arc::new(rt)))
+    })
+}
+
+/// Load a module from `len` bytes at `data`. Returns 0 on failure, with
+/// the reason in `rune_last_error_message`.
+///
+/// # Safety
+/// `data` must be valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rune_h_module_load_bytes(
+    rt: RuneHandle,
+    data: *const u8,
+    len: usize,
+) -> RuneHandle {
+    guard(|| {
+        let Some(runtime) = registry().runtime(rt) else {
+            bad_handle(rt, "runtime");
+            return 0;
+        };
+        let rt_ptr = Arc::as_ptr(&runtime) as *mut CRuntime;
+        match adopt(rune_module_load_bytes(rt_ptr, data, len)) {
+            Some(module) => registry().insert(Object::Module(Arc::new(module))),
+            None => 0,
+        }
+    })
+}
+
+/// Instantiate module `module` in runtime `rt`. Returns 0 on failure.
+#[no_mangle]
+pub extern "C" fn rune_h_instance_new(rt: RuneHandle, module: RuneHandle) -> RuneHandle {
+    guard(|| {
+        let (runtime, loaded) = {
+            let mut registry = registry();
+            (registry.runtime(rt), registry.module(module))
+        };
+        let Some(runtime) = runtime else {
+            bad_handle(rt, "runtime");
+            return 0;
+        };
+        let Some(loaded) = loaded else {
+            bad_handle(module, "module");
+            return 0;
+        };
+        // SAFETY: both are live, and `rune_instance_new` only reads them.
+        let inst = unsafe {
+            adopt(rune_instance_new(
+                Arc::as_ptr(&runtime) as *mut CRuntime,
+                Arc::as_ptr(&loaded) as *mut CModule,
+            ))
+        };
+        match inst {
+            Some(inst) => registry().insert(Object::Instance(Arc::new(Mutex::new(inst)))),
+            None => 0,
+        }
+    })
+}
+
+/// `rune_instance_call` on instance `inst`.
+///
+/// # Safety
+/// As for `rune_instance_call`.
+#[no_mangle]
+pub unsafe extern "C" fn rune_h_instance_call(
+    inst: RuneHandle,
+    name: *const c_char,
+    args: *const RuneTypedVal,
+    n_args: usize,
+    out_result: *mut RuneTypedVal,
+) -> RuneError {
+    guard(|| {
+        with_instance(inst, |inst| unsafe {
+            rune_instance_call(inst, name, args, n_args, out_result)
+        })
+    })
+}
+
+/// `rune_memory_read` on instance `inst`.
+///
+/// # Safety
+/// `dst` must be valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rune_h_memory_read(
+    inst: RuneHandle,
+    offset: usize,
+    dst: *mut c_void,
+    len: usize,
+) -> RuneError {
+    guard(|| {
+        with_instance(inst, |inst| unsafe {
+            rune_memory_read(inst, offset, dst, len)
+        })
+    })
+}
+
+/// `rune_memory_write` on instance `inst`.
+///
+/// # Safety
+/// `src` must be valid for `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn rune_h_memory_write(
+    inst: RuneHandle,
+    offset: usize,
+    src: *const c_void,
+    len: usize,
+) -> RuneError {
+    guard(|| {
+        with_instance(inst, |inst| unsafe {
+            rune_memory_write(inst, offset, src, len)
+        })
+    })
+}
+
+/// Create an empty linker.
+#[no_mangle]
+pub extern "C" fn rune_h_linker_new() -> RuneHandle {
+    guard(|| {
+        // SAFETY: `rune_linker_new` returns a fresh box.
+        let linker = unsafe { adopt(rune_linker_new()) }.expect("linker creation cannot fail");
+        registry().insert(Object::Linker(Arc::new(Mutex::new(linker))))
+    })
+}
+
+/// `rune_linker_define` on linker `linker`.
+///
+/// # Safety
+/// As for `rune_linker_define`.
+#[deprecated(note = "use `rune_h_linker_define_typed`, whose values carry their type")]
+#[allow(deprecated)]
+#[no_mangle]
+pub unsafe extern "C" fn rune_h_linker_define(
+    linker: RuneHandle,
+    module: *const c_char,
+    name: *const c_char,
+    sig: *const RuneSignature,
+    func: RuneHostFn,
+    user_data: *mut c_void,
+) -> RuneError {
+    guard(|| {
+        with_linker(linker, |linker| unsafe {
+            rune_linker_define(linker, module, name, sig, func, user_data)
+        })
+    })
+}
+
+/// `rune_linker_define_typed` on linker `linker`.
+///
+/// # Safety
+/// As for `rune_linker_define_typed`.
+#[no_mangle]
+pub unsafe extern "C" fn rune_h_linker_define_typed(
+    linker: RuneHandle,
+    module: *const c_char,
+    name: *const c_char,
+    sig: *const RuneSignature,
+    func: RuneTypedHostFn,
+    user_data: *mut c_void,
+) -> RuneError {
+    guard(|| {
+        with_linker(linker, |linker| unsafe {
+            rune_linker_define_typed(linker, module, name, sig, func, user_data)
+        })
+    })
+}
+
+/// `rune_linker_define_instance` with instance `inst` on linker `linker`.
+///
+/// # Safety
+/// `module` must be a valid null-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn rune_h_linker_define_instance(
+    linker: RuneHandle,
+    module: *const c_char,
+    inst: RuneHandle,
+) -> RuneError {
+    guard(|| {
+        let Some(instance) = registry().instance(inst) else {
+            return bad_handle(inst, "instance");
+        };
+        let instance = instance.lock().unwrap_or_else(PoisonError::into_inner);
+        with_linker(linker, |linker| unsafe {
+            rune_linker_define_instance(linker, module, &*instance)
+        })
+    })
+}
+
+/// Instantiate module `module` in runtime `rt` against linker `linker`.
+/// Returns 0 on failure, including imports the linker does not define.
+#[no_mangle]
+pub extern "C" fn rune_h_linker_instantiate(
+    linker: RuneHandle,
+    rt: RuneHandle,
+    module: RuneHandle,
+) -> RuneHandle {
+    guard(|| {
+        let (runtime, loaded) = {
+            let mut registry = registry();
+            (registry.runtime(rt), registry.module(module))
+        };
+        let Some(runtime) = runtime else {
+            bad_handle(rt, "runtime");
+            return 0;
+        };
+        let Some(loaded) = loaded else {
+            bad_handle(module, "module");
+            return 0;
+        };
+        let mut inst = None;
+        with_linker(linker, |linker| {
+            // SAFETY: all three are live; the runtime and module are only read.
+            inst = unsafe {
+                adopt(rune_linker_instantiate(
+                    linker,
+                    Arc::as_ptr(&runtime) as *mut CRuntime,
+                    Arc::as_ptr(&loaded) as *mut CModule,
+                ))
+            };
+            RuneError::Ok
+        });
+        match inst {
+            Some(inst) => registry().insert(Object::Instance(Arc::new(Mutex::new(inst)))),
+            None => 0,
+        }
+    })
+}
+
+/// Drop the registry's reference to `handle`'s object, which is freed once
+/// no call is using it. Instances keep their module and runtime alive, so
+/// handles can be released in any order.
+#[no_mangle]
+pub extern "C" fn rune_handle_release(handle: RuneHandle) -> RuneError {
+    guard(|| {
+        let object = {
+            let mut registry = registry();
+            let Some(slot) = registry.slot(handle) else {
+                drop(registry);
+                return bad_handle(handle, "runtime, module or instance");
+            };
+            let object = slot.object.take();
+            slot.generation = slot.generation.wrapping_add(1);
+            registry.free.push((handle as u32) - 1);
+            object
+        };
+        // Dropped outside the lock: freeing an instance can take a while.
+        drop(object);
+        RuneError::Ok
+    })
+}
@@ -0,0 +1,276 @@
+//! The host library over the C API, behind the `hostlib` feature.
+//!
+//! A `RuneHostLib` collects the arguments, environment and output sinks a
+//! set of guests sees, then defines the [`crate::hostlib`] functions on a
+//! linker. Hosts without a console, such as GUI apps, give it write and
+//! log callbacks so guest output reaches them instead of the process's
+//! stdio.
+
+use std::ffi::CStr;
+use std::io::{self, Write};
+use std::os::raw::{c_char, c_void};
+
+use super::{catch_panic, fail, fail_with, guard, CLinker, RuneError, UserData};
+use crate::hostlib::{HostLib, LogLevel};
+
+/// Receives `len` bytes of guest output; returns false if they could not
+/// be written, which the guest sees as a failed write.
+pub type RuneWriteFn =
+    unsafe extern "C-unwind" fn(data: *const u8, len: usize, user_data: *mut c_void) -> bool;
+
+/// Receives a message the guest logged, NUL-terminated, of `len` bytes
+/// excluding the NUL.
+pub type RuneLogFn = unsafe extern "C-unwind" fn(
+    level: RuneLogLevel,
+    message: *const c_char,
+    len: usize,
+    user_data: *mut c_void,
+);
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuneLogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+    Trace = 4,
+}
+
+impl From<LogLevel> for RuneLogLevel {
+    fn from(level: LogLevel) -> Self {
+        match level {
+            LogLevel::Error => RuneLogLevel::Error,
+            LogLevel::Warn => RuneLogLevel::Warn,
+            LogLevel::Info => RuneLogLevel::Info,
+            LogLevel::Debug => RuneLogLevel::Debug,
+            LogLevel::Trace => RuneLogLevel::Trace,
+        }
+    }
+}
+
+pub struct CHostLib(HostLib);
+
+/// An output stream that hands each write to a C callback.
+struct CallbackWriter {
+    func: RuneWriteFn,
+    user_data: UserData,
+}
+
+impl Write for CallbackWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let user_data = self.user_data;
+        // SAFETY: `buf` is valid for the duration of the call.
+        let written = catch_panic(|| unsafe { (self.func)(buf.as_ptr(), buf.len(), user_data.0) });
+        match written {
+            Ok(true) => Ok(buf.len()),
+            Ok(false) => Err(io::Error::other("write callback failed")),
+            Err(msg) => Err(io::Error::other(msg)),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A sink for `func`, or one that discards everything if it is `None`.
+fn sink(func: Option<RuneWriteFn>, user_data: *mut c_void) -> Box<dyn Write + Send> {
+    match func {
+        Some(func) => Box::new(CallbackWriter {
+            func,
+            user_data: UserData(user_data),
+        }),
+        None => Box::new(io::sink()),
+    }
+}
+
+/// The UTF-8 strings behind `ptrs`, or the error to return.
+unsafe fn strs<'a, const N: usize>(ptrs: [*const c_char; N]) -> Result<[&'a str; N], RuneError> {
+    let mut out = [""; N];
+    for (out, ptr) in out.iter_mut().zip(ptrs) {
+        if ptr.is_null() {
+            return Err(fail_with(
+                RuneError::InvalidArgument,
+                "null handle or pointer argument",
+            ));
+        }
+        let Ok(s) = CStr::from_ptr(ptr).to_str() else {
+            return Err(fail_with(RuneError::InvalidUtf8, "name is not valid UTF-8"));
+        };
+        *out = s;
+    }
+    Ok(out)
+}
+
+/// A host library with no arguments, an empty environment, and output to
+/// the process's stdout and stderr. Must be freed with `rune_hostlib_free`.
+#[no_mangle]
+pub extern "C" fn rune_hostlib_new() -> *mut CHostLib {
+    guard(|| Box::into_raw(Box::new(CHostLib(HostLib::new()))))
+}
+
+/// # Safety
+/// Must only be called with a pointer returned by `rune_hostlib_new`.
+/// Linkers it was added to keep working.
+#[no_mangle]
+pub unsafe extern "C" fn rune_hostlib_free(hostlib: *mut CHostLib) {
+    guard(|| {
+        if !hostlib.is_null() {
+            drop(Box::from_raw(hostlib));
+        }
+    })
+}
+
+/// Append `arg` to the arguments.
+///
+/// # Safety
+/// `hostlib` must come from `rune_hostlib_new` and `arg` must be a valid
+/// null-terminated C string.
+#[no_mangle]
+pub unsafe extern "C" fn rune_hostlib_add_arg(
+    hostlib: *mut CHostLib,
+    arg: *const c_char,
+) -> RuneError {
+    guard(|| {
+        if hostlib.is_null() {
+            return fail_with(RuneError::InvalidArgument, "null host library");
+        }
+        match strs([arg]) {
+            Ok([arg]) => {
+                (*hostlib).0.args([arg]);
+                RuneError::Ok
+            }
+            Err(err) => err,
+        }
+    })
+}
+
+/// Set environment variable `key` to `value`, replacing any earlier value.
+///
+/// # Safety
+/// `hostlib` must come from `rune_hostlib_new`; `key` and `value` must be
+/// valid null-terminated C strings.
+#[no_mangle]
+pub unsafe extern "C" fn rune_hostlib_set_env(
+    hostlib: *mut CHostLib,
+    key: *const c_char,
+    value: *const c_char,
+) -> RuneError {
+    guard(|| {
+        if hostlib.is_null() {
+            return fail_with(RuneError::InvalidArgument, "null host library");
+        }
+        match strs([key, value]) {
+            Ok([key, value]) => {
+                (*hostlib).0.env(key, value);
+                RuneError::Ok
+            }
+            Err(err) => err,
+        }
+    })
+}
+
+/// Send the guest's stdout to `func`, or discard it if `func` is null.
+/// Each call passes `user_data` back to `func`, on whichever thread the
+/// guest runs.
+///
+/// # Safety
+/// `hostlib` must come from `rune_hostlib_new`; `user_data` must be safe to
+/// use from any thread that runs a linked instance.
+#[no_mangle]
+pub unsafe extern "C" fn rune_hostlib_set_stdout(
+    hostlib: *mut CHostLib,
+    func: Option<RuneWriteFn>,
+    user_data: *mut c_void,
+) -> RuneError {
+    guard(|| {
+        if hostlib.is_null() {
+            return fail_with(RuneError::InvalidArgument, "null host library");
+        }
+        (*hostlib).0.stdout(sink(func, user_data));
+        RuneError::Ok
+    })
+}
+
+/// As `rune_hostlib_set_stdout`, for stderr.
+///
+/// # Safety
+/// As for `rune_hostlib_set_stdout`.
+#[no_mangle]
+pub unsafe extern "C" fn rune_hostlib_set_stderr(
+    hostlib: *mut CHostLib,
+    func: Option<RuneWriteFn>,
+    user_data: *mut c_void,
+) -> RuneError {
+    guard(|| {
+        if hostlib.is_null() {
+            return fail_with(RuneError::InvalidArgument, "null host library");
+        }
+        (*hostlib).0.stderr(sink(func, user_data));
+        RuneError::Ok
+    })
+}
+
+/// Pass every message the guest logs to `func`, instead of writing it to
+/// stderr; a null `func` discards them. A callback that unwinds is
+/// ignored.
+///
+/// # Safety
+/// As for `rune_hostlib_set_stdout`.
+#[no_mangle]
+pub unsafe extern "C" fn rune_hostlib_set_log(
+    hostlib: *mut CHostLib,
+    func: Option<RuneLogFn>,
+    user_data: *mut c_void,
+) -> RuneError {
+    guard(|| {
+        if hostlib.is_null() {
+            return fail_with(RuneError::InvalidArgument, "null host library");
+        }
+        let user_data = UserData(user_data);
+        (*hostlib).0.logger(move |level, message| {
+            let user_data = user_data;
+            let Some(func) = func else {
+                return;
+            };
+            // Interior NULs are kept; `len` tells C where the message ends.
+            let mut bytes = Vec::with_capacity(message.len() + 1);
+            bytes.extend_from_slice(message.as_bytes());
+            bytes.push(0);
+            // SAFETY: `bytes` outlives the call.
+            let _ = catch_panic(|| unsafe {
+                func(
+                    level.into(),
+                    bytes.as_ptr().cast(),
+                    message.len(),
+                    user_data.0,
+                )
+            });
+        });
+        RuneError::Ok
+    })
+}
+
+/// Define every host library function on `linker`. Instances linked
+/// through it share the library's sinks, which stay in place if the
+/// library is later changed or freed.
+///
+/// # Safety
+/// `hostlib` must come from `rune_hostlib_new` and `linker` from
+/// `rune_linker_new`.
+#[no_mangle]
+pub unsafe extern "C" fn rune_hostlib_add_to_linker(
+    hostlib: *const CHostLib,
+    linker: *mut CLinker,
+) -> RuneError {
+    guard(|| {
+        if hostlib.is_null() || linker.is_null() {
+            return fail_with(RuneError::InvalidArgument, "null host library or linker");
+        }
+        match (*hostlib).0.add_to_linker(&mut (*linker).0) {
+            Ok(()) => RuneError::Ok,
+            Err(trap) => fail(&trap),
+        }
+    })
+}
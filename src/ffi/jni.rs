@@ -0,0 +1,500 @@
+//! JNI bindings behind the `jni` feature: the native half of the `io.rune`
+//! Java classes in `bindings/java`.
+//!
+//! Each Java object holds a [`RuneHandle`](super::handles::RuneHandle) from
+//! the handle-based API, so the garbage collector never sees a Rust
+//! pointer, and `close()` releases it. Values cross as boxed Java numbers
+//! (`Integer`, `Long`, `Float`, `Double`), typed by the function's
+//! signature. Failures throw `io.rune.RuneException` carrying the
+//! `RuneError` code and the full message.
+//!
+//! Host functions defined through `io.rune.Linker` call back into Java on
+//! whichever thread runs the guest, attaching it to the JVM if needed; an
+//! exception thrown by the callback traps the guest with a host error.
+
+use std::ffi::CString;
+
+use jni::objects::{
+    GlobalRef, JByteArray, JClass, JIntArray, JObject, JObjectArray, JString, JValue,
+};
+use jni::sys::{jint, jlong, jobject};
+use jni::{JNIEnv, JavaVM};
+
+use super::handles::{
+    rune_h_instance_new, rune_h_linker_define_instance, rune_h_linker_instantiate,
+    rune_h_linker_new, rune_h_module_load_bytes, rune_h_runtime_new, rune_handle_release,
+    with_instance, with_linker, RuneHandle,
+};
+use super::{
+    catch_panic, rune_instance_call, rune_memory_read, rune_memory_write, RuneError, RuneTypedVal,
+    RuneValType, LAST_ERROR,
+};
+use crate::{
+    trap::Trap,
+    types::{FuncType, Val, ValType},
+};
+
+/// Why a native method failed: a Rune error to throw as `RuneException`,
+/// or a Java exception that is already pending.
+enum Failure {
+    Rune(RuneError, String),
+    Java(jni::errors::Error),
+}
+
+impl From<jni::errors::Error> for Failure {
+    fn from(e: jni::errors::Error) -> Self {
+        Failure::Java(e)
+    }
+}
+
+type JResult<T> = std::result::Result<T, Failure>;
+
+/// The failure the C API function that just failed recorded.
+fn last_failure() -> Failure {
+    LAST_ERROR.with(|last| match &*last.borrow() {
+        Some((code, msg)) => Failure::Rune(*code, msg.to_string_lossy().into_owned()),
+        None => Failure::Rune(RuneError::HostError, "unknown error".into()),
+    })
+}
+
+/// A handle the C API returned, or the failure it recorded.
+fn check_handle(handle: RuneHandle) -> JResult<jlong> {
+    match handle {
+        0 => Err(last_failure()),
+        handle => Ok(handle as jlong),
+    }
+}
+
+fn check(err: RuneError) -> JResult<()> {
+    match err {
+        RuneError::Ok => Ok(()),
+        _ => Err(last_failure()),
+    }
+}
+
+/// Run the body of a native method or Java callback, turning a panic into
+/// a failure so it never unwinds into the JVM or the interpreter.
+fn guarded<T>(f: impl FnOnce() -> JResult<T>) -> JResult<T> {
+    catch_panic(f).unwrap_or_else(|msg| Err(Failure::Rune(RuneError::HostError, msg)))
+}
+
+/// Finish a native method: return `value`, or throw for `failure` and
+/// return `default`, which Java never sees.
+fn finish<T>(env: &mut JNIEnv, result: JResult<T>, default: T) -> T {
+    match result {
+        Ok(value) => value,
+        Err(failure) => {
+            if !env.exception_check().unwrap_or(true) {
+                let (code, message) = match failure {
+                    Failure::Rune(code, message) => (code as jint, message),
+                    Failure::Java(e) => (RuneError::HostError as jint, e.to_string()),
+                };
+                // If even this fails, a JVM error is pending and reported.
+                let _ = throw_rune_exception(env, code, &message);
+            }
+            default
+        }
+    }
+}
+
+fn throw_rune_exception(env: &mut JNIEnv, code: jint, message: &str) -> jni::errors::Result<()> {
+    let message = env.new_string(message)?;
+    let exception = env.new_object(
+        "io/rune/RuneException",
+        "(ILjava/lang/String;)V",
+        &[JValue::Int(code), JValue::Object(&message)],
+    )?;
+    env.throw(jni::objects::JThrowable::from(exception))
+}
+
+fn java_string(env: &mut JNIEnv, s: &JString) -> JResult<String> {
+    Ok(env.get_string(s)?.into())
+}
+
+fn c_string(s: String) -> JResult<CString> {
+    CString::new(s).map_err(|_| {
+        Failure::Rune(
+            RuneError::InvalidArgument,
+            "name contains a NUL character".into(),
+        )
+    })
+}
+
+/// Unbox a `java.lang.Number` as a value of type `ty`.
+fn unbox(env: &mut JNIEnv, obj: &JObject, ty: ValType) -> JResult<Val> {
+    if obj.is_null() || !env.is_instance_of(obj, "java/lang/Number")? {
+        return Err(Failure::Rune(
+            RuneError::TrapTypeMismatch,
+            format!("expected a number for an {ty:?} value"),
+        ));
+    }
+    Ok(match ty {
+        ValType::I32 => Val::I32(env.call_method(obj, "intValue", "()I", &[])?.i()?),
+        ValType::I64 => Val::I64(env.call_method(obj, "longValue", "()J", &[])?.j()?),
+        ValType::F32 => Val::F32(env.call_method(obj, "floatValue", "()F", &[])?.f()?),
+        ValType::F64 => Val::F64(env.call_method(obj, "doubleValue", "()D", &[])?.d()?),
+    })
+}
+
+fn boxed<'local>(env: &mut JNIEnv<'local>, val: Option<Val>) -> JResult<JObject<'local>> {
+    let (class, sig, arg) = match val {
+        None => return Ok(JObject::null()),
+        Some(Val::I32(x)) => (
+            "java/lang/Integer",
+            "(I)Ljava/lang/Integer;",
+            JValue::Int(x),
+        ),
+        Some(Val::I64(x)) => ("java/lang/Long", "(J)Ljava/lang/Long;", JValue::Long(x)),
+        Some(Val::F32(x)) => ("java/lang/Float", "(F)Ljava/lang/Float;", JValue::Float(x)),
+        Some(Val::F64(x)) => (
+            "java/lang/Double",
+            "(D)Ljava/lang/Double;",
+            JValue::Double(x),
+        ),
+    };
+    Ok(env.call_static_method(class, "valueOf", sig, &[arg])?.l()?)
+}
+
+fn val_type(code: jint) -> JResult<Option<ValType>> {
+    let ty = u8::try_from(code)
+        .ok()
+        .and_then(|code| RuneValType::try_from(code).ok())
+        .ok_or_else(|| {
+            Failure::Rune(
+                RuneError::TrapTypeMismatch,
+                format!("invalid value type {code:#x}"),
+            )
+        })?;
+    Ok(ValType::try_from(ty).ok())
+}
+
+// ── io.rune.Handle ───────────────────────────────────────────────────────────
+
+#[no_mangle]
+pub extern "system" fn Java_io_rune_Handle_nativeRelease(
+    _env: JNIEnv,
+    _class: JClass,
+    handle: jlong,
+) {
+    // Java clears its copy first, so a stale handle here is a double close
+    // racing on another thread; there is nothing left to free.
+    rune_handle_release(handle as RuneHandle);
+}
+
+// ── io.rune.Runtime ──────────────────────────────────────────────────────────
+
+#[no_mangle]
+pub extern "system" fn Java_io_rune_Runtime_nativeNew(_env: JNIEnv, _class: JClass) -> jlong {
+    rune_h_runtime_new() as jlong
+}
+
+// ── io.rune.Module ───────────────────────────────────────────────────────────
+
+#[no_mangle]
+pub extern "system" fn Java_io_rune_Module_nativeLoad(
+    mut env: JNIEnv,
+    _class: JClass,
+    rt: jlong,
+    bytes: JByteArray,
+) -> jlong {
+    let result = guarded(|| {
+        let bytes = env.convert_byte_array(&bytes)?;
+        // SAFETY: `bytes` is valid for its length.
+        check_handle(unsafe {
+            rune_h_module_load_bytes(rt as RuneHandle, bytes.as_ptr(), bytes.len())
+        })
+    });
+    finish(&mut env, result, 0)
+}
+
+// ── io.rune.Linker ───────────────────────────────────────────────────────────
+
+#[no_mangle]
+pub extern "system" fn Java_io_rune_Linker_nativeNew(_env: JNIEnv, _class: JClass) -> jlong {
+    rune_h_linker_new() as jlong
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_rune_Linker_nativeDefine(
+    mut env: JNIEnv,
+    _class: JClass,
+    linker: jlong,
+    module: JString,
+    name: JString,
+    params: JIntArray,
+    result: jint,
+    callback: JObject,
+) {
+    let defined = guarded(|| {
+        let module = java_string(&mut env, &module)?;
+        let name = java_string(&mut env, &name)?;
+        let n_params = env.get_array_length(&params)?;
+        let mut codes = vec![0; n_params as usize];
+        env.get_int_array_region(&params, 0, &mut codes)?;
+        let params = codes
+            .into_iter()
+            .map(|code| {
+                val_type(code)?.ok_or_else(|| {
+                    Failure::Rune(
+                        RuneError::TrapTypeMismatch,
+                        "parameters cannot be VOID".into(),
+                    )
+                })
+            })
+            .collect::<JResult<Vec<_>>>()?;
+        let result = val_type(result)?;
+        let ty = FuncType {
+            params,
+            results: result.into_iter().collect(),
+        };
+        let vm = env.get_java_vm()?;
+        let callback = env.new_global_ref(callback)?;
+        let key = format!("{module}.{name}");
+        let mut defined = Ok(());
+        check(with_linker(linker as RuneHandle, |linker| {
+            // SAFETY: `with_linker` hands out the live, locked linker.
+            let linker = unsafe { &mut (*linker).0 };
+            defined = linker
+                .func(&module, &name, ty, move |args| {
+                    call_java(&vm, &callback, args, result)
+                        .map_err(|msg| Trap::HostError(format!("{key}: {msg}")))
+                })
+                .map(|_| ());
+            RuneError::Ok
+        }))?;
+        defined.map_err(|trap| Failure::Rune(RuneError::from(&trap), trap.to_string()))
+    });
+    finish(&mut env, defined, ())
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_rune_Linker_nativeDefineInstance(
+    mut env: JNIEnv,
+    _class: JClass,
+    linker: jlong,
+    module: JString,
+    inst: jlong,
+) {
+    let defined = guarded(|| {
+        let module = c_string(java_string(&mut env, &module)?)?;
+        // SAFETY: `module` is a valid C string.
+        check(unsafe {
+            rune_h_linker_define_instance(linker as RuneHandle, module.as_ptr(), inst as RuneHandle)
+        })
+    });
+    finish(&mut env, defined, ())
+}
+
+/// Call `callback.call(Object[])` with `args` boxed, and unbox its return
+/// value as `result`.
+fn call_java(
+    vm: &JavaVM,
+    callback: &GlobalRef,
+    args: &[Val],
+    result: Option<ValType>,
+) -> std::result::Result<Option<Val>, String> {
+    let mut env = vm.attach_current_thread().map_err(|e| e.to_string())?;
+    let outcome = guarded(|| {
+        let array =
+            env.new_object_array(args.len() as jint, "java/lang/Object", JObject::null())?;
+        for (i, &arg) in args.iter().enumerate() {
+            let obj = boxed(&mut env, Some(arg))?;
+            env.set_object_array_element(&array, i as jint, obj)?;
+        }
+        let ret = env
+            .call_method(
+                callback,
+                "call",
+                "([Ljava/lang/Object;)Ljava/lang/Object;",
+                &[JValue::Object(&array)],
+            )?
+            .l()?;
+        match result {
+            Some(ty) => Ok(Some(unbox(&mut env, &ret, ty)?)),
+            None => Ok(None),
+        }
+    });
+    outcome.map_err(|failure| match failure {
+        Failure::Rune(_, message) => message,
+        Failure::Java(e) => describe_pending_exception(&mut env).unwrap_or_else(|| e.to_string()),
+    })
+}
+
+/// Clear the pending Java exception, returning its `toString()`.
+fn describe_pending_exception(env: &mut JNIEnv) -> Option<String> {
+    let exception = env.exception_occurred().ok()?;
+    env.exception_clear().ok()?;
+    if exception.is_null() {
+        return None;
+    }
+    let text = env
+        .call_method(&exception, "toString", "()Ljava/lang/String;", &[])
+        .ok()?
+        .l()
+        .ok()?;
+    Some(env.get_string(&JString::from(text)).ok()?.into())
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_rune_Linker_nativeInstantiate(
+    mut env: JNIEnv,
+    _class: JClass,
+    linker: jlong,
+    rt: jlong,
+    module: jlong,
+) -> jlong {
+    let result = check_handle(rune_h_linker_instantiate(
+        linker as RuneHandle,
+        rt as RuneHandle,
+        module as RuneHandle,
+    ));
+    finish(&mut env, result, 0)
+}
+
+// ── io.rune.Instance ─────────────────────────────────────────────────────────
+
+#[no_mangle]
+pub extern "system" fn Java_io_rune_Instance_nativeNew(
+    mut env: JNIEnv,
+    _class: JClass,
+    rt: jlong,
+    module: jlong,
+) -> jlong {
+    let result = check_handle(rune_h_instance_new(rt as RuneHandle, module as RuneHandle));
+    finish(&mut env, result, 0)
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_rune_Instance_nativeCall(
+    mut env: JNIEnv,
+    _class: JClass,
+    inst: jlong,
+    name: JString,
+    args: JObjectArray,
+) -> jobject {
+    let result = guarded(|| {
+        let name = java_string(&mut env, &name)?;
+        let n_args = if args.is_null() {
+            0
+        } else {
+            env.get_array_length(&args)?
+        };
+        let mut boxed_args = Vec::with_capacity(n_args as usize);
+        for i in 0..n_args {
+            boxed_args.push(env.get_object_array_element(&args, i)?);
+        }
+        let c_name = c_string(name.clone())?;
+        let mut outcome: JResult<Option<Val>> = Ok(None);
+        let err = with_instance(inst as RuneHandle, |inst| {
+            // The guard is dropped before `rune_instance_call`, which takes
+            // the same lock.
+            let params = {
+                // SAFETY: `with_instance` hands out the live instance.
+                let instance = unsafe { (*inst).lock() };
+                let module = instance.module();
+                module
+                    .find_export(&name)
+                    .map(|idx| module.functions[idx as usize].ty.params.clone())
+            };
+            let params = match params {
+                Some(params) => params,
+                None => {
+                    outcome = Err(Failure::Rune(
+                        RuneError::UndefinedExport,
+                        Trap::UndefinedExport(name.clone()).to_string(),
+                    ));
+                    return RuneError::Ok;
+                }
+            };
+            if params.len() != boxed_args.len() {
+                outcome = Err(Failure::Rune(
+                    RuneError::TrapTypeMismatch,
+                    format!(
+                        "{name} takes {} arguments, got {}",
+                        params.len(),
+                        boxed_args.len()
+                    ),
+                ));
+                return RuneError::Ok;
+            }
+            let typed = boxed_args
+                .iter()
+                .zip(&params)
+                .map(|(obj, &ty)| {
+                    let val = unbox(&mut env, obj, ty)?;
+                    Ok(RuneTypedVal::from_val(Some(val)))
+                })
+                .collect::<JResult<Vec<_>>>();
+            let typed = match typed {
+                Ok(typed) => typed,
+                Err(failure) => {
+                    outcome = Err(failure);
+                    return RuneError::Ok;
+                }
+            };
+            let mut out = RuneTypedVal::from_val(None);
+            // SAFETY: the arguments and result are valid for the call.
+            let err = unsafe {
+                rune_instance_call(inst, c_name.as_ptr(), typed.as_ptr(), typed.len(), &mut out)
+            };
+            outcome = match err {
+                RuneError::Ok => Ok(out.to_val()),
+                _ => Err(last_failure()),
+            };
+            RuneError::Ok
+        });
+        check(err)?;
+        let val = outcome?;
+        Ok(boxed(&mut env, val)?.into_raw())
+    });
+    finish(&mut env, result, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_rune_Instance_nativeRead(
+    mut env: JNIEnv,
+    _class: JClass,
+    inst: jlong,
+    offset: jlong,
+    len: jint,
+) -> jobject {
+    let result = guarded(|| {
+        let (Ok(offset), Ok(len)) = (usize::try_from(offset), usize::try_from(len)) else {
+            return Err(Failure::Rune(
+                RuneError::TrapOutOfBounds,
+                "negative offset or length".into(),
+            ));
+        };
+        let mut buf = vec![0u8; len];
+        check(with_instance(inst as RuneHandle, |inst| {
+            // SAFETY: `buf` is valid for `len` bytes.
+            unsafe { rune_memory_read(inst, offset, buf.as_mut_ptr().cast(), len) }
+        }))?;
+        Ok(env.byte_array_from_slice(&buf)?.into_raw())
+    });
+    finish(&mut env, result, std::ptr::null_mut())
+}
+
+#[no_mangle]
+pub extern "system" fn Java_io_rune_Instance_nativeWrite(
+    mut env: JNIEnv,
+    _class: JClass,
+    inst: jlong,
+    offset: jlong,
+    data: JByteArray,
+) {
+    let result = guarded(|| {
+        let Ok(offset) = usize::try_from(offset) else {
+            return Err(Failure::Rune(
+                RuneError::TrapOutOfBounds,
+                "negative offset".into(),
+            ));
+        };
+        let bytes = env.convert_byte_array(&data)?;
+        check(with_instance(inst as RuneHandle, |inst| {
+            // SAFETY: `bytes` is valid for its length.
+            unsafe { rune_memory_write(inst, offset, bytes.as_ptr().cast(), bytes.len()) }
+        }))
+    });
+    finish(&mut env, result, ())
+}
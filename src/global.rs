@@ -0,0 +1,87 @@
+//! Variables the host shares with guests, defined with
+//! [`Linker::global`](crate::linker::Linker::global).
+//!
+//! A module declares each global it reads with
+//! [`Module::import_global`](crate::module::Module::import_global) and
+//! accesses it with `GlobalGet`/`GlobalSet`, at the cost of a load rather
+//! than a host call. Every instance linked to a [`Global`] shares its value
+//! with the host and with each other: a host updating `config.max_items`
+//! is seen by the next `GlobalGet` in any of them.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::{
+    trap::{Result, Trap},
+    types::{Val, ValType},
+};
+
+/// A typed value shared between the host and the instances importing it.
+/// Clones refer to the same value.
+#[derive(Debug, Clone)]
+pub struct Global {
+    cell: Arc<Cell>,
+}
+
+#[derive(Debug)]
+struct Cell {
+    ty: ValType,
+    mutable: bool,
+    bits: AtomicU64,
+}
+
+impl Global {
+    /// A global holding `init`, of its type. Guests may write it only if
+    /// `mutable`; the host always may.
+    pub fn new(init: Val, mutable: bool) -> Self {
+        Global {
+            cell: Arc::new(Cell {
+                ty: init.ty(),
+                mutable,
+                bits: AtomicU64::new(to_bits(init)),
+            }),
+        }
+    }
+
+    pub fn ty(&self) -> ValType {
+        self.cell.ty
+    }
+
+    pub fn is_mutable(&self) -> bool {
+        self.cell.mutable
+    }
+
+    pub fn get(&self) -> Val {
+        from_bits(self.cell.ty, self.cell.bits.load(Ordering::Relaxed))
+    }
+
+    /// Replace the value. Fails with `Trap::TypeMismatch` if `val` is not of
+    /// the global's type.
+    pub fn set(&self, val: Val) -> Result<()> {
+        if val.ty() != self.cell.ty {
+            return Err(Trap::TypeMismatch);
+        }
+        self.cell.bits.store(to_bits(val), Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// The bits a value is stored as: zero-extended to 64 for 32-bit types.
+/// Native code passes values the same way.
+pub(crate) fn to_bits(val: Val) -> u64 {
+    match val {
+        Val::I32(v) => v as u32 as u64,
+        Val::I64(v) => v as u64,
+        Val::F32(v) => v.to_bits() as u64,
+        Val::F64(v) => v.to_bits(),
+    }
+}
+
+pub(crate) fn from_bits(ty: ValType, bits: u64) -> Val {
+    match ty {
+        ValType::I32 => Val::I32(bits as u32 as i32),
+        ValType::I64 => Val::I64(bits as i64),
+        ValType::F32 => Val::F32(f32::from_bits(bits as u32)),
+        ValType::F64 => Val::F64(f64::from_bits(bits)),
+    }
+}
@@ -0,0 +1,438 @@
+//! A small standard library of host functions, behind the `hostlib`
+//! feature (on by default).
+//!
+//! Every function lives in the [`NAMESPACE`] import module, so a guest
+//! toolchain can target one ABI whatever the embedder. Pointers and lengths
+//! are `i32` offsets into the caller's default memory; a range outside it
+//! traps with `Trap::OutOfBounds`.
+//!
+//! | Import | Signature | Capability | Behaviour |
+//! |---|---|---|---|
+//! | `clock_monotonic` | `() -> i64` | `clock` | Nanoseconds since an arbitrary fixed point; never decreases. |
+//! | `clock_wall` | `() -> i64` | `clock` | Nanoseconds since the Unix epoch; negative before it. |
+//! | `clock_now` | `() -> i64` | `clock`¹ | Nanoseconds on the library's [`Clock`]; never decreases. |
+//! | `sleep_until` | `(i64)` | `clock`¹ | Returns once `clock_now` has reached the argument. |
+//! | `random_get` | `(ptr, len)` | `random` | Fills `len` bytes at `ptr` with unpredictable bytes. Not suitable for cryptography. |
+//! | `random_bytes` | `(ptr, len)` | `random`² | Fills `len` bytes at `ptr` from the instance's [`rng`](crate::rng). |
+//! | `random_u64` | `() -> i64` | `random`² | The next 64 bits from the instance's generator. |
+//! | `set_timeout` | `(name_ptr, name_len, ms) -> i32` | | Calls the export named by the UTF-8 name after `ms` milliseconds, when the host next [polls](crate::timer); returns a timer id. |
+//! | `clear_timeout` | `(id) -> i32` | | Cancels a timer; returns 1, or 0 if it already ran. |
+//! | `yield_now` | `(name_ptr, name_len) -> i32` | | As `set_timeout` with no delay: continues in the export at the next poll. |
+//! | `write_stdout` | `(ptr, len) -> i32` | `stdio` | Writes the bytes to stdout; returns `len`, or -1 if the write failed. |
+//! | `write_stderr` | `(ptr, len) -> i32` | `stdio` | As `write_stdout`, to stderr. |
+//! | `log` | `(level, ptr, len)` | `stdio` | Logs the UTF-8 message at a [`LogLevel`], 0 (error) to 4 (trace). |
+//! | `args_count` | `() -> i32` | `env` | Number of arguments. |
+//! | `args_get` | `(index, ptr, cap) -> i32` | `env` | Copies up to `cap` bytes of argument `index` to `ptr`; returns its full length, or -1 if there is no such argument. |
+//! | `env_get` | `(key_ptr, key_len, ptr, cap) -> i32` | `env` | As `args_get`, for the variable named by the UTF-8 key; -1 if it is unset. |
+//!
+//! Each function carries the capability label shown (see
+//! [`Linker::set_capability`] and the constants in [`sandbox`]), so an
+//! instantiation can be refused the clock or randomness, for instance, to
+//! keep it deterministic.
+//!
+//! ¹ Only with a clock that is not [deterministic](Clock::is_deterministic).
+//! Given a [`ManualClock`](crate::clock::ManualClock), `clock_now` and
+//! `sleep_until` are unlabelled, so deterministic guests keep a clock.
+//!
+//! ² Only when the instance has no seed: these are unlabelled, but trap
+//! with `Trap::PermissionDenied` if they would need the host's entropy
+//! where `random` is denied.
+//!
+//! [`sandbox`]: crate::sandbox
+//!
+//! Nothing is inherited from the host process: a guest sees only the
+//! arguments and variables given to its [`HostLib`], and its output goes
+//! wherever the embedder points it. Log messages go to the
+//! [`logger`](HostLib::logger) if one is set, and otherwise to stderr as
+//! `[level] message` lines.
+//!
+//! ```rust
+//! use rune::{hostlib::HostLib, Linker};
+//!
+//! let mut linker = Linker::new();
+//! HostLib::new()
+//!     .args(["plugin", "--verbose"])
+//!     .env("LANG", "C")
+//!     .add_to_linker(&mut linker)
+//!     .unwrap();
+//! ```
+
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hasher};
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
+
+use crate::{
+    clock::{Clock, SystemClock},
+    instance::Caller,
+    linker::Linker,
+    rng::Rng,
+    sandbox::{CLOCK, ENV, RANDOM, STDIO},
+    sys::{wall_clock_nanos, Instant},
+    trap::{Result, Trap},
+    types::{FuncType, Val, ValType},
+};
+
+/// Import module name of every host library function.
+pub const NAMESPACE: &str = "rune_std";
+
+type SharedWriter = Arc<Mutex<Box<dyn Write + Send>>>;
+type Logger = dyn Fn(LogLevel, &str) + Send + Sync;
+
+/// Severity of a message a guest logs, as passed to `log`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+    Trace = 4,
+}
+
+impl LogLevel {
+    /// The level numbered `n` by the `log` import, if any.
+    pub fn from_i32(n: i32) -> Option<Self> {
+        Some(match n {
+            0 => LogLevel::Error,
+            1 => LogLevel::Warn,
+            2 => LogLevel::Info,
+            3 => LogLevel::Debug,
+            4 => LogLevel::Trace,
+            _ => return None,
+        })
+    }
+}
+
+impl fmt::Display for LogLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        })
+    }
+}
+
+/// The arguments, environment and output streams one set of guests sees.
+pub struct HostLib {
+    args: Vec<String>,
+    env: Vec<(String, String)>,
+    stdout: SharedWriter,
+    stderr: SharedWriter,
+    logger: Option<Arc<Logger>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl HostLib {
+    /// No arguments, an empty environment, output to the host's own
+    /// stdout and stderr, and a [`SystemClock`].
+    pub fn new() -> Self {
+        HostLib {
+            args: Vec::new(),
+            env: Vec::new(),
+            stdout: Arc::new(Mutex::new(Box::new(io::stdout()))),
+            stderr: Arc::new(Mutex::new(Box::new(io::stderr()))),
+            logger: None,
+            clock: Arc::new(SystemClock::new()),
+        }
+    }
+
+    /// Append to the arguments.
+    pub fn args<I, S>(&mut self, args: I) -> &mut Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.args.extend(args.into_iter().map(Into::into));
+        self
+    }
+
+    /// Set an environment variable, replacing any earlier value.
+    pub fn env(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        let key = key.into();
+        self.env.retain(|(k, _)| *k != key);
+        self.env.push((key, value.into()));
+        self
+    }
+
+    /// Send the guest's stdout to `out` instead.
+    pub fn stdout(&mut self, out: impl Write + Send + 'static) -> &mut Self {
+        self.stdout = Arc::new(Mutex::new(Box::new(out)));
+        self
+    }
+
+    /// Send the guest's stderr to `out` instead.
+    pub fn stderr(&mut self, out: impl Write + Send + 'static) -> &mut Self {
+        self.stderr = Arc::new(Mutex::new(Box::new(out)));
+        self
+    }
+
+    /// Pass every message the guest logs to `logger`, instead of writing
+    /// it to stderr.
+    pub fn logger(&mut self, logger: impl Fn(LogLevel, &str) + Send + Sync + 'static) -> &mut Self {
+        self.logger = Some(Arc::new(logger));
+        self
+    }
+
+    /// Back `clock_now` and `sleep_until` with `clock` instead.
+    pub fn clock(&mut self, clock: impl Clock + 'static) -> &mut Self {
+        self.clock = Arc::new(clock);
+        self
+    }
+
+    /// Define every function in [`NAMESPACE`] on `linker`, with its
+    /// capability label. Instances linked through it share this library's
+    /// streams. Fails with
+    /// `Trap::InvalidModule` if any of them is already defined.
+    pub fn add_to_linker(&self, linker: &mut Linker) -> Result<()> {
+        use ValType::{I32, I64};
+
+        let epoch = Instant::now();
+        linker.func(NAMESPACE, "clock_monotonic", sig(&[], &[I64]), move |_| {
+            Ok(Some(Val::I64(epoch.elapsed().as_nanos() as i64)))
+        })?;
+        linker.func(NAMESPACE, "clock_wall", sig(&[], &[I64]), |_| {
+            Ok(Some(Val::I64(wall_clock_nanos())))
+        })?;
+        let clock = self.clock.clone();
+        linker.func(NAMESPACE, "clock_now", sig(&[], &[I64]), move |_| {
+            Ok(Some(Val::I64(clock.now().as_nanos() as i64)))
+        })?;
+        let clock = self.clock.clone();
+        linker.func(NAMESPACE, "sleep_until", sig(&[I64], &[]), move |args| {
+            let deadline = args[0].as_i64().ok_or(Trap::TypeMismatch)?;
+            clock.sleep_until(Duration::from_nanos(deadline.max(0) as u64));
+            Ok(None)
+        })?;
+
+        let keys = RandomState::new();
+        let counter = AtomicU64::new(0);
+        linker.func_with_caller(
+            NAMESPACE,
+            "random_get",
+            sig(&[I32, I32], &[]),
+            move |caller, args| {
+                let mut memory = caller.memory();
+                let buf = memory.slice_mut(addr(args, 0)?, addr(args, 1)?)?;
+                for chunk in buf.chunks_mut(8) {
+                    let mut hasher = keys.build_hasher();
+                    hasher.write_u64(counter.fetch_add(1, Ordering::Relaxed));
+                    chunk.copy_from_slice(&hasher.finish().to_le_bytes()[..chunk.len()]);
+                }
+                Ok(None)
+            },
+        )?;
+        linker.func_with_caller(
+            NAMESPACE,
+            "random_bytes",
+            sig(&[I32, I32], &[]),
+            |caller, args| {
+                let (ptr, len) = (addr(args, 0)?, addr(args, 1)?);
+                seeded_rng(caller, "random_bytes")?;
+                if ptr
+                    .checked_add(len)
+                    .is_none_or(|end| end > caller.memory().size())
+                {
+                    return Err(Trap::OutOfBounds);
+                }
+                // The generator and memory both borrow the caller, so the
+                // bytes go through a fixed buffer rather than one sized by
+                // the guest. A multiple of 8 keeps the stream unchanged.
+                let mut buf = [0; 4096];
+                for start in (ptr..ptr + len).step_by(buf.len()) {
+                    let chunk = &mut buf[..(ptr + len - start).min(4096)];
+                    seeded_rng(caller, "random_bytes")?.fill_bytes(chunk);
+                    caller
+                        .memory()
+                        .slice_mut(start, chunk.len())?
+                        .copy_from_slice(chunk);
+                }
+                Ok(None)
+            },
+        )?;
+        linker.func_with_caller(NAMESPACE, "random_u64", sig(&[], &[I64]), |caller, _| {
+            let n = seeded_rng(caller, "random_u64")?.next_u64();
+            Ok(Some(Val::I64(n as i64)))
+        })?;
+        linker.func_with_caller(
+            NAMESPACE,
+            "set_timeout",
+            sig(&[I32, I32, I32], &[I32]),
+            |caller, a| {
+                let ms = a[2].as_i32().ok_or(Trap::TypeMismatch)?.max(0) as u64;
+                set_timeout(caller, a, Duration::from_millis(ms))
+            },
+        )?;
+        linker.func_with_caller(
+            NAMESPACE,
+            "clear_timeout",
+            sig(&[I32], &[I32]),
+            |caller, a| {
+                let id = a[0].as_i32().ok_or(Trap::TypeMismatch)? as u32;
+                Ok(Some(Val::I32(caller.clear_timeout(id) as i32)))
+            },
+        )?;
+        linker.func_with_caller(
+            NAMESPACE,
+            "yield_now",
+            sig(&[I32, I32], &[I32]),
+            |caller, a| set_timeout(caller, a, Duration::ZERO),
+        )?;
+
+        for (name, stream) in [
+            ("write_stdout", &self.stdout),
+            ("write_stderr", &self.stderr),
+        ] {
+            let stream = stream.clone();
+            linker.func_with_caller(
+                NAMESPACE,
+                name,
+                sig(&[I32, I32], &[I32]),
+                move |caller, args| {
+                    let len = addr(args, 1)?;
+                    let memory = caller.memory();
+                    let bytes = memory.slice(addr(args, 0)?, len)?;
+                    let mut out = stream.lock().unwrap_or_else(PoisonError::into_inner);
+                    let written = out.write_all(bytes).and_then(|_| out.flush());
+                    Ok(Some(Val::I32(if written.is_ok() {
+                        len as i32
+                    } else {
+                        -1
+                    })))
+                },
+            )?;
+        }
+
+        let logger = self.logger.clone();
+        let stderr = self.stderr.clone();
+        linker.func_with_caller(
+            NAMESPACE,
+            "log",
+            sig(&[I32, I32, I32], &[]),
+            move |caller, args| {
+                let level = args[0].as_i32().ok_or(Trap::TypeMismatch)?;
+                let level = LogLevel::from_i32(level)
+                    .ok_or_else(|| Trap::HostError(format!("invalid log level {level}")))?;
+                let memory = caller.memory();
+                let message = memory.read_str(addr(args, 1)?, addr(args, 2)?)?;
+                match &logger {
+                    Some(logger) => logger(level, message),
+                    None => {
+                        let mut out = stderr.lock().unwrap_or_else(PoisonError::into_inner);
+                        // There is no caller to report a failed write to.
+                        let _ = writeln!(out, "[{level}] {message}").and_then(|_| out.flush());
+                    }
+                }
+                Ok(None)
+            },
+        )?;
+
+        let args = Arc::new(self.args.clone());
+        let count = args.len() as i32;
+        linker.func(NAMESPACE, "args_count", sig(&[], &[I32]), move |_| {
+            Ok(Some(Val::I32(count)))
+        })?;
+        linker.func_with_caller(
+            NAMESPACE,
+            "args_get",
+            sig(&[I32, I32, I32], &[I32]),
+            move |caller, a| {
+                let index = a[0].as_i32().ok_or(Trap::TypeMismatch)?;
+                let arg = usize::try_from(index).ok().and_then(|i| args.get(i));
+                copy_out(caller, arg.map(String::as_str), addr(a, 1)?, addr(a, 2)?)
+            },
+        )?;
+
+        let env = Arc::new(self.env.clone());
+        linker.func_with_caller(
+            NAMESPACE,
+            "env_get",
+            sig(&[I32, I32, I32, I32], &[I32]),
+            move |caller, a| {
+                let memory = caller.memory();
+                let key = memory.read_str(addr(a, 0)?, addr(a, 1)?)?;
+                let value = env.iter().find(|(k, _)| k == key).map(|(_, v)| v.as_str());
+                copy_out(caller, value, addr(a, 2)?, addr(a, 3)?)
+            },
+        )?;
+
+        for (name, capability) in [
+            ("clock_monotonic", CLOCK),
+            ("clock_wall", CLOCK),
+            ("random_get", RANDOM),
+            ("write_stdout", STDIO),
+            ("write_stderr", STDIO),
+            ("log", STDIO),
+            ("args_count", ENV),
+            ("args_get", ENV),
+            ("env_get", ENV),
+        ] {
+            linker.set_capability(NAMESPACE, name, capability)?;
+        }
+        if !self.clock.is_deterministic() {
+            linker.set_capability(NAMESPACE, "clock_now", CLOCK)?;
+            linker.set_capability(NAMESPACE, "sleep_until", CLOCK)?;
+        }
+        Ok(())
+    }
+}
+
+impl Default for HostLib {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn sig(params: &[ValType], results: &[ValType]) -> FuncType {
+    FuncType {
+        params: params.to_vec(),
+        results: results.to_vec(),
+    }
+}
+
+/// The caller's generator, or `Trap::PermissionDenied` naming function
+/// `name` if it may not have one.
+fn seeded_rng<'c>(caller: &'c mut Caller<'_>, name: &str) -> Result<&'c mut Rng> {
+    caller
+        .rng()
+        .ok_or_else(|| Trap::PermissionDenied(format!("{NAMESPACE}.{name}")))
+}
+
+/// Schedule the export named by the string at arguments 0 and 1.
+fn set_timeout(caller: &mut Caller<'_>, args: &[Val], delay: Duration) -> Result<Option<Val>> {
+    let export = caller
+        .memory()
+        .read_str(addr(args, 0)?, addr(args, 1)?)?
+        .to_owned();
+    let id = caller.set_timeout(&export, delay)?;
+    Ok(Some(Val::I32(id as i32)))
+}
+
+/// Argument `i` as an unsigned guest address or length.
+fn addr(args: &[Val], i: usize) -> Result<usize> {
+    Ok(args[i].as_i32().ok_or(Trap::TypeMismatch)? as u32 as usize)
+}
+
+/// Copy up to `cap` bytes of `value` to `ptr`, returning its full length,
+/// or -1 for `None`.
+fn copy_out(
+    caller: &mut Caller<'_>,
+    value: Option<&str>,
+    ptr: usize,
+    cap: usize,
+) -> Result<Option<Val>> {
+    let Some(value) = value else {
+        return Ok(Some(Val::I32(-1)));
+    };
+    let n = value.len().min(cap);
+    caller
+        .memory()
+        .slice_mut(ptr, n)?
+        .copy_from_slice(&value.as_bytes()[..n]);
+    Ok(Some(Val::I32(value.len() as i32)))
+}
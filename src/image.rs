@@ -0,0 +1,233 @@
+//! Prepared initial-memory images, shared copy-on-write between instances.
+//!
+//! Instantiating a module normally allocates zeroed memory and copies every
+//! data segment into it. A [`MemoryImage`] does that work once; each instance
+//! created from it then starts from the same contents. On Linux the image
+//! lives in an in-memory file that instances map privately, so untouched
+//! pages are shared and a page is only copied when an instance writes to it.
+//! Elsewhere the image is plain bytes copied per instance, which still skips
+//! re-applying segments.
+//!
+//! A [`WarmImage`] goes further and captures an instance after its guest
+//! initialization has run, so instances started from it skip both the data
+//! segments and the init function.
+
+use std::fs::File;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::{
+    blob::BlobStore,
+    hash::{sha256, to_hex},
+    instance::PreparedModule,
+    memory::{Memory, PAGE_SIZE},
+    module::Module,
+    trap::{Result, Trap},
+};
+
+/// Largest reservation made for a mapped memory: the 4 GiB addressable by
+/// 32-bit loads and stores.
+const MAX_RESERVATION: usize = 1 << 32;
+
+/// Initial memory contents of a module, ready to back many instances.
+pub struct MemoryImage {
+    len: usize,
+    max_pages: Option<usize>,
+    backing: Backing,
+}
+
+enum Backing {
+    /// Shareable in-memory file (Linux).
+    #[allow(dead_code)]
+    File(File),
+    /// Fallback: bytes copied into each instance.
+    Bytes(Vec<u8>),
+}
+
+impl MemoryImage {
+    /// Build the image of `module`'s initial memory: zeroed pages with its
+    /// data segments applied, external ones fetched from `blobs`.
+    pub fn new(module: &Module, blobs: &dyn BlobStore) -> Result<Self> {
+        let mut memory = Memory::new(module.initial_memory_pages, module.max_memory_pages);
+        apply_data_segments(&mut memory, module, blobs)?;
+        Ok(Self::from_memory(&memory))
+    }
+
+    /// Capture the current contents and limits of `memory` as an image.
+    pub fn from_memory(memory: &Memory) -> Self {
+        let bytes = memory.bytes();
+        let backing = match image_file(bytes) {
+            Some(file) => Backing::File(file),
+            None => Backing::Bytes(bytes.to_vec()),
+        };
+        MemoryImage {
+            len: bytes.len(),
+            max_pages: memory.max_pages(),
+            backing,
+        }
+    }
+
+    /// Size of the image in pages.
+    pub fn pages(&self) -> usize {
+        self.len / PAGE_SIZE
+    }
+
+    /// Whether instances map this image copy-on-write rather than copying it.
+    pub fn is_shared(&self) -> bool {
+        matches!(self.backing, Backing::File(_))
+    }
+
+    /// Create a fresh memory holding the image's contents.
+    pub fn instantiate(&self) -> Result<Memory> {
+        self.instantiate_with(false)
+    }
+
+    /// [`instantiate`](Self::instantiate), into a guarded mapping if
+    /// `guarded` and the host has them.
+    pub(crate) fn instantiate_with(&self, guarded: bool) -> Result<Memory> {
+        match &self.backing {
+            Backing::Bytes(bytes) => {
+                #[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
+                if let Some(mut memory) = guarded
+                    .then(|| Memory::guarded(self.pages(), self.max_pages))
+                    .flatten()
+                {
+                    memory.bytes_mut().copy_from_slice(bytes);
+                    return Ok(memory);
+                }
+                Ok(Memory::from_vec(bytes.clone(), self.max_pages))
+            }
+            Backing::File(file) => self.map(file, guarded),
+        }
+    }
+
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    fn map(&self, file: &File, guarded: bool) -> Result<Memory> {
+        use std::os::unix::fs::FileExt;
+
+        #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+        if let Some(mapping) = guarded
+            .then(|| crate::sys::Mapping::guarded(self.len, Some((file, self.len))))
+            .flatten()
+        {
+            return Ok(Memory::from_mapping(mapping, self.len, self.max_pages));
+        }
+        #[cfg(not(all(feature = "cranelift", target_arch = "x86_64")))]
+        let _ = guarded;
+        // Reserve room to grow in place; growth past it moves to the heap.
+        let reserved = self
+            .max_pages
+            .map_or(MAX_RESERVATION, |max| max.saturating_mul(PAGE_SIZE))
+            .clamp(self.len, MAX_RESERVATION.max(self.len));
+        if let Some(mapping) = crate::sys::Mapping::new(reserved, Some((file, self.len))) {
+            return Ok(Memory::from_mapping(mapping, self.len, self.max_pages));
+        }
+        // Mapping failed (address-space limits, say): copy instead.
+        let mut bytes = vec![0u8; self.len];
+        file.read_exact_at(&mut bytes, 0)
+            .map_err(|_| Trap::OutOfMemory)?;
+        Ok(Memory::from_vec(bytes, self.max_pages))
+    }
+
+    #[cfg(not(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    )))]
+    fn map(&self, _file: &File, _guarded: bool) -> Result<Memory> {
+        unreachable!("file-backed images are only created on Linux")
+    }
+}
+
+/// An initialized instance's state, ready to start many instances, from
+/// [`Instance::snapshot`] or [`Runtime::warm_boot`].
+///
+/// Holds the default memory as a [`MemoryImage`], the host allocator's
+/// position and the instance's prepared functions, which warm instances
+/// share. Imported globals are the host's and stay linked to its values
+/// rather than being captured; random number generators, timers, fuel and
+/// host data start fresh.
+///
+/// [`Instance::snapshot`]: crate::instance::Instance::snapshot
+/// [`Runtime::warm_boot`]: crate::runtime::Runtime::warm_boot
+pub struct WarmImage {
+    pub(crate) memory: MemoryImage,
+    pub(crate) bump: Range<usize>,
+    pub(crate) prepared: Arc<PreparedModule>,
+}
+
+impl WarmImage {
+    /// Size of the captured memory in pages.
+    pub fn pages(&self) -> usize {
+        self.memory.pages()
+    }
+
+    /// Whether instances map the memory copy-on-write rather than copying it.
+    pub fn is_shared(&self) -> bool {
+        self.memory.is_shared()
+    }
+}
+
+/// Write `bytes` to a fresh in-memory file, skipping all-zero pages so the
+/// file stays sparse.
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+fn image_file(bytes: &[u8]) -> Option<File> {
+    use std::os::unix::fs::FileExt;
+
+    if bytes.is_empty() {
+        return None;
+    }
+    let file = crate::sys::memfd(c"rune-memory-image")?;
+    file.set_len(bytes.len() as u64).ok()?;
+    for (i, page) in bytes.chunks(PAGE_SIZE).enumerate() {
+        if page.iter().any(|&b| b != 0) {
+            file.write_all_at(page, (i * PAGE_SIZE) as u64).ok()?;
+        }
+    }
+    Some(file)
+}
+
+#[cfg(not(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+)))]
+fn image_file(_bytes: &[u8]) -> Option<File> {
+    None
+}
+
+/// Copy `module`'s inline data segments into `memory`, then its external
+/// segments, streaming each from `blobs` and checking it against its hash.
+pub(crate) fn apply_data_segments(
+    memory: &mut Memory,
+    module: &Module,
+    blobs: &dyn BlobStore,
+) -> Result<()> {
+    for (offset, bytes) in &module.data_segments {
+        memory.write_bytes(*offset as usize, bytes)?;
+    }
+    for seg in &module.external_segments {
+        let mut reader = blobs.open(&seg.hash).ok_or_else(|| {
+            Trap::InvalidModule(format!(
+                "external data segment {} not supplied",
+                to_hex(&seg.hash)
+            ))
+        })?;
+        // Read straight into guest memory, then verify what landed there.
+        let mut view = memory.view();
+        let dst = view.slice_mut(seg.offset as usize, seg.len as usize)?;
+        let complete =
+            reader.read_exact(dst).is_ok() && matches!(reader.read(&mut [0u8; 1]), Ok(0));
+        if !complete || sha256(dst) != seg.hash {
+            return Err(Trap::InvalidModule(format!(
+                "external data segment {} failed hash check",
+                to_hex(&seg.hash)
+            )));
+        }
+    }
+    Ok(())
+}
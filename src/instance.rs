@@ -20,14 +20,37 @@
 //! **Fix:** slice args directly from the value stack, copy into the new
 //! locals vec, then `stack.truncate()` (O(1), no allocation).
 
+use std::any::Any;
+use std::ops::{Deref, Range};
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
+#[cfg(feature = "cranelift")]
+use crate::compiler::{native, CodeSlot, NativeModule, Tiers};
 use crate::{
+    blob::{BlobStore, NoBlobs},
+    cancel::CancellationToken,
+    config::{AddressOverflow, RuntimeConfig},
+    events::Event,
+    global::Global,
+    image::{apply_data_segments, MemoryImage, WarmImage},
     ir::{BlockType, Op},
-    memory::Memory,
-    module::Module,
-    trap::{Result, Trap},
+    linker::{Capabilities, HostFn, HostFuncDef, Linker},
+    liveness,
+    memory::{
+        CodeCharge, Memory, MemoryLimiter, MemoryStats, MemoryUsage, MemoryView, Poison, PAGE_SIZE,
+    },
+    metrics::Metrics,
+    module::{GlobalImport, Module},
+    profile::Profile,
+    rng::Rng,
+    sandbox::RANDOM,
+    sys::Instant,
+    timer::Timers,
+    trap::{Result, Trap, TrapFrame},
     types::{Val, ValType},
+    verify,
 };
 
 // ── Prepared function (built once at instantiation time) ──────────────────────
@@ -36,21 +59,28 @@ use crate::{
 /// `Arc` fields make `clone()` O(1) — just bumps refcounts.
 #[derive(Clone)]
 pub(crate) struct PreparedFunc {
-    /// The instruction stream (shared, never mutated).
+    /// Index of this function in `Module::functions`.
+    pub index: u32,
+    /// The instruction stream (shared, never mutated), with its locals
+    /// [coalesced](crate::liveness) where that saves any.
     pub ops: Arc<Vec<Op>>,
+    /// The function's body as the module has it.
+    pub body: Arc<Vec<Op>>,
     /// `ends[i]` = index of the matching `End` for ops[i] (Block/Loop/If).
     pub ends: Arc<Vec<usize>>,
     /// `elses[i]` = index of the matching `Else` for ops[i] (If), or usize::MAX.
     pub elses: Arc<Vec<usize>>,
     /// Number of function parameters (= first N locals).
     pub n_params: usize,
-    /// Types of extra (non-param) locals, zero-initialised at call entry.
+    /// Types of extra (non-param) local slots, zero-initialised at call entry.
     pub extra_locals: Vec<ValType>,
     /// Return type, or None for void.
     pub result_type: Option<ValType>,
+    /// Replaced via `replace_function`; the module's source map no longer applies.
+    pub patched: bool,
 }
 
-fn prepare_func(func: &crate::ir::Function) -> PreparedFunc {
+pub(crate) fn prepare_func(index: usize, func: &crate::ir::Function) -> PreparedFunc {
     let ops = func.body.clone();
     let n = ops.len();
     let mut ends = vec![0usize; n];
@@ -74,13 +104,396 @@ fn prepare_func(func: &crate::ir::Function) -> PreparedFunc {
         }
     }
 
-    PreparedFunc {
+    let mut pf = PreparedFunc {
+        index: index as u32,
+        body: ops.clone(),
         ops,
         ends: Arc::new(ends),
         elses: Arc::new(elses),
         n_params: func.ty.params.len(),
         extra_locals: func.locals.clone(),
         result_type: func.ty.results.first().copied(),
+        patched: false,
+    };
+    if let Some((ops, extra_locals)) = liveness::coalesce(&func.ty.params, &pf) {
+        pf.ops = Arc::new(ops);
+        pf.extra_locals = extra_locals;
+    }
+    pf
+}
+
+/// Identifies a module function, either by index or by export name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FuncRef<'a> {
+    Index(u32),
+    Name(&'a str),
+}
+
+impl From<u32> for FuncRef<'_> {
+    fn from(idx: u32) -> Self {
+        FuncRef::Index(idx)
+    }
+}
+
+impl<'a> From<&'a str> for FuncRef<'a> {
+    fn from(name: &'a str) -> Self {
+        FuncRef::Name(name)
+    }
+}
+
+/// Export name of the guest allocator: `alloc(len: i32) -> i32`.
+pub const GUEST_ALLOC_EXPORT: &str = "alloc";
+/// Export name of the guest deallocator: `free(ptr: i32, len: i32)`.
+pub const GUEST_FREE_EXPORT: &str = "free";
+
+/// Context handed to host functions defined with
+/// [`Linker::func_with_caller`](crate::linker::Linker::func_with_caller)
+/// while the guest that called them is suspended.
+pub struct Caller<'a> {
+    memories: &'a mut [Memory],
+    data: &'a mut Option<Box<dyn Any + Send>>,
+    rng: &'a mut Option<Rng>,
+    timers: &'a mut Timers,
+    module: &'a Module,
+    config: &'a RuntimeConfig,
+}
+
+impl Caller<'_> {
+    /// The calling instance's host data (see [`Instance::set_data`]), if it
+    /// has some of type `T`.
+    pub fn data<T: Any>(&self) -> Option<&T> {
+        self.data.as_deref()?.downcast_ref()
+    }
+
+    pub fn data_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.data.as_deref_mut()?.downcast_mut()
+    }
+
+    /// Borrow the host data of type `T` and the default memory together,
+    /// for host functions that move data between them.
+    pub fn data_and_memory<T: Any>(&mut self) -> Option<(&mut T, MemoryView<'_>)> {
+        let data = self.data.as_deref_mut()?.downcast_mut()?;
+        Some((data, self.memories[0].view()))
+    }
+
+    /// Borrow the calling instance's default linear memory. Slices taken
+    /// from the view cannot outlive the host call, so they can never dangle
+    /// after the guest grows or the instance is dropped.
+    pub fn memory(&mut self) -> MemoryView<'_> {
+        self.memories[0].view()
+    }
+
+    /// Borrow memory `index` of the calling instance, if it has one.
+    pub fn memory_at(&mut self, index: u32) -> Option<MemoryView<'_>> {
+        self.memories.get_mut(index as usize).map(Memory::view)
+    }
+
+    /// The calling instance's random number generator, seeded on first use.
+    /// `None` if it has no seed and the runtime denies the `random`
+    /// capability, which seeding from the host's entropy would need.
+    pub fn rng(&mut self) -> Option<&mut Rng> {
+        if self.rng.is_none() {
+            *self.rng = match self.config.rng_seed() {
+                Some(seed) => Some(Rng::from_seed(seed)),
+                None if self.config.denies_capability(RANDOM) => None,
+                None => Some(Rng::from_entropy()),
+            };
+        }
+        self.rng.as_mut()
+    }
+
+    /// Schedule the calling instance's `export`; see
+    /// [`Instance::set_timeout`].
+    pub fn set_timeout(&mut self, export: &str, delay: Duration) -> Result<u32> {
+        schedule(self.timers, self.module, self.config, export, delay)
+    }
+
+    /// Cancel one of the calling instance's timers; see
+    /// [`Instance::clear_timeout`].
+    pub fn clear_timeout(&mut self, id: u32) -> bool {
+        self.timers.cancel(id)
+    }
+}
+
+fn schedule(
+    timers: &mut Timers,
+    module: &Module,
+    config: &RuntimeConfig,
+    export: &str,
+    delay: Duration,
+) -> Result<u32> {
+    if module.find_export(export).is_none() {
+        return Err(Trap::UndefinedExport(export.into()));
+    }
+    let due = config.clock().now().saturating_add(delay);
+    Ok(timers.schedule(export, due))
+}
+
+/// The host function behind one `CallHost` index of an instance.
+#[derive(Clone)]
+pub(crate) struct HostBinding {
+    n_params: usize,
+    func: Arc<HostFn>,
+}
+
+impl HostBinding {
+    pub(crate) fn new(def: &HostFuncDef) -> Self {
+        HostBinding {
+            n_params: def.ty.params.len(),
+            func: def.func.clone(),
+        }
+    }
+
+    /// A binding for `def` that traps with `Trap::PermissionDenied` instead
+    /// of running it.
+    pub(crate) fn denied(def: &HostFuncDef) -> Self {
+        let name = def.name.clone();
+        HostBinding {
+            n_params: def.ty.params.len(),
+            func: Arc::new(move |_, _| Err(Trap::PermissionDenied(name.clone()))),
+        }
+    }
+}
+
+/// Bind the functions `CallHost` ops reach, the module's imports, through
+/// `linker` with the capabilities granted. Without a linker only a module
+/// that imports nothing can be bound.
+fn bind_hosts(
+    module: &Module,
+    linker: Option<(&Linker, &Capabilities)>,
+    config: &RuntimeConfig,
+) -> Result<Vec<HostBinding>> {
+    match linker {
+        Some((linker, granted)) => linker.resolve(&module.imports, granted, config),
+        None => Linker::new().resolve(&module.imports, &Capabilities::all(), config),
+    }
+}
+
+/// Bind the globals `GlobalGet`/`GlobalSet` ops reach, the module's global
+/// imports, through `linker`.
+fn bind_globals(module: &Module, linker: Option<(&Linker, &Capabilities)>) -> Result<Vec<Global>> {
+    match linker {
+        Some((linker, _)) => linker.resolve_globals(&module.global_imports),
+        None => Linker::new().resolve_globals(&module.global_imports),
+    }
+}
+
+/// Check that every `GlobalGet`/`GlobalSet` in `func` names one of
+/// `globals`, and that every `GlobalSet` names a mutable one.
+fn check_global_indices(func: &crate::ir::Function, globals: &[GlobalImport]) -> Result<()> {
+    for op in func.body.iter() {
+        let (idx, write) = match op {
+            Op::GlobalGet(i) => (*i, false),
+            Op::GlobalSet(i) => (*i, true),
+            _ => continue,
+        };
+        match globals.get(idx as usize) {
+            None => {
+                return Err(Trap::InvalidModule(format!(
+                    "function `{}` uses global {idx}, but the module imports {}",
+                    func.name,
+                    globals.len()
+                )))
+            }
+            Some(global) if write && !global.mutable => {
+                return Err(Trap::InvalidModule(format!(
+                    "function `{}` writes immutable global {global}",
+                    func.name
+                )))
+            }
+            Some(_) => {}
+        }
+    }
+    Ok(())
+}
+
+/// Highest memory index any load or store in `ops` names.
+fn max_memory_index(ops: &[Op]) -> Option<u32> {
+    ops.iter().filter_map(Op::memory_index).max()
+}
+
+fn memory_index_error(func: &crate::ir::Function, memory: u32, count: usize) -> Trap {
+    Trap::InvalidModule(format!(
+        "function `{}` uses memory {memory}, but the instance has {count}",
+        func.name
+    ))
+}
+
+/// Check that every load and store in `func` names an existing memory.
+fn check_memory_indices(func: &crate::ir::Function, count: usize) -> Result<()> {
+    match max_memory_index(&func.body) {
+        Some(memory) if memory as usize >= count => Err(memory_index_error(func, memory, count)),
+        _ => Ok(()),
+    }
+}
+
+/// Check that an initial memory of `initial` pages for `module` fits under
+/// the config's cap, and ask the configured limiter whether it may be
+/// allocated at all.
+fn check_initial_memory(module: &Module, initial: usize, config: &RuntimeConfig) -> Result<()> {
+    let max = config.memory_max_for(module);
+    if max.is_some_and(|max| initial > max) {
+        return Err(Trap::OutOfMemory);
+    }
+    if let Some(limiter) = config.memory_limiter() {
+        if !limiter.memory_growing(0, initial, max) {
+            return Err(Trap::OutOfMemory);
+        }
+    }
+    Ok(())
+}
+
+/// A fresh default memory for `module`: in a guarded mapping if the
+/// config's compiled code leaves bounds to guard pages and the host has
+/// them, on the heap otherwise.
+fn new_memory(module: &Module, config: &RuntimeConfig) -> Memory {
+    #[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
+    if config.guard_pages() {
+        if let Some(memory) = Memory::guarded(module.initial_memory_pages, module.max_memory_pages)
+        {
+            return memory;
+        }
+    }
+    #[cfg(not(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64")))]
+    let _ = config;
+    Memory::new(module.initial_memory_pages, module.max_memory_pages)
+}
+
+// ── Prepared module ───────────────────────────────────────────────────────────
+
+/// A module's functions, checked against a runtime's configuration and
+/// with their jump tables built, from
+/// [`Runtime::prepare`](crate::runtime::Runtime::prepare).
+///
+/// Instances share one through an `Arc` instead of each preparing every
+/// function again. An instance that swaps a function with
+/// `replace_function` gets its own copy first, so the others never see it.
+#[derive(Clone)]
+pub struct PreparedModule {
+    funcs: Vec<PreparedFunc>, // one per module function
+    /// Highest memory index any function names, and the first function
+    /// naming it.
+    widest: Option<(u32, usize)>,
+    config: Arc<RuntimeConfig>,
+}
+
+impl PreparedModule {
+    /// Check that `module` can run under `config` and prepare its functions.
+    pub(crate) fn new(module: &Module, config: &Arc<RuntimeConfig>) -> Result<Self> {
+        module.required_features.check_supported()?;
+        if !module.host_requirements.is_empty() {
+            config
+                .host_api()
+                .check(&module.host_requirements)
+                .into_result()?;
+        }
+        if config.verify() {
+            verify::verify_module(module, config)?;
+        }
+        let mut widest: Option<(u32, usize)> = None;
+        for (i, f) in module.functions.iter().enumerate() {
+            config.check_extensions(f)?;
+            check_global_indices(f, &module.global_imports)?;
+            if let Some(memory) = max_memory_index(&f.body) {
+                if widest.is_none_or(|(w, _)| memory > w) {
+                    widest = Some((memory, i));
+                }
+            }
+        }
+        // Fix 2: precompute jump tables once, at load time.
+        let funcs = module
+            .functions
+            .iter()
+            .enumerate()
+            .map(|(i, f)| prepare_func(i, f))
+            .collect();
+        Ok(PreparedModule {
+            funcs,
+            widest,
+            config: config.clone(),
+        })
+    }
+
+    /// Fail with `Trap::InvalidModule` unless this was prepared from
+    /// `module`, or a clone of it, under `config`.
+    pub(crate) fn check(&self, module: &Module, config: &Arc<RuntimeConfig>) -> Result<()> {
+        let same = Arc::ptr_eq(&self.config, config)
+            && self.funcs.len() == module.functions.len()
+            && self
+                .funcs
+                .iter()
+                .zip(&module.functions)
+                .all(|(pf, f)| Arc::ptr_eq(&pf.body, &f.body));
+        if !same {
+            return Err(Trap::InvalidModule(
+                "prepared for another module or runtime".into(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Approximate heap bytes taken by the prepared functions: their ops,
+    /// jump tables and local types.
+    pub fn code_bytes(&self) -> usize {
+        self.funcs
+            .iter()
+            .map(|pf| {
+                pf.ops.len() * std::mem::size_of::<Op>()
+                    + (pf.ends.len() + pf.elses.len()) * std::mem::size_of::<usize>()
+                    + pf.extra_locals.len() * std::mem::size_of::<ValType>()
+            })
+            .sum()
+    }
+
+    /// Check that every load and store names one of `count` memories.
+    fn check_memories(&self, module: &Module, count: usize) -> Result<()> {
+        match self.widest {
+            Some((memory, i)) if memory as usize >= count => {
+                Err(memory_index_error(&module.functions[i], memory, count))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// What instantiating resolves before any memory exists: the module's host
+/// bindings and globals and its prepared functions.
+#[derive(Clone)]
+pub(crate) struct Linked {
+    hosts: Vec<HostBinding>,
+    globals: Vec<Global>,
+    prepared: Arc<PreparedModule>,
+}
+
+impl Linked {
+    /// Check that `module` can run under `config` with `memories` memories,
+    /// bind its host functions and globals and prepare its functions.
+    pub(crate) fn new(
+        module: &Module,
+        linker: Option<(&Linker, &Capabilities)>,
+        memories: usize,
+        config: &Arc<RuntimeConfig>,
+    ) -> Result<Self> {
+        let prepared = Arc::new(PreparedModule::new(module, config)?);
+        Self::with_prepared(module, linker, memories, prepared)
+    }
+
+    /// Like [`new`](Self::new), reusing functions already prepared from
+    /// `module`.
+    pub(crate) fn with_prepared(
+        module: &Module,
+        linker: Option<(&Linker, &Capabilities)>,
+        memories: usize,
+        prepared: Arc<PreparedModule>,
+    ) -> Result<Self> {
+        let hosts = bind_hosts(module, linker, &prepared.config)?;
+        let globals = bind_globals(module, linker)?;
+        prepared.check_memories(module, memories)?;
+        Ok(Linked {
+            hosts,
+            globals,
+            prepared,
+        })
     }
 }
 
@@ -100,39 +513,826 @@ struct CtrlFrame {
     result_type: Option<ValType>,
 }
 
+/// What changed when an instance was moved to a new module version by
+/// [`Runtime::reload`](crate::runtime::Runtime::reload).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ReloadReport {
+    /// Exports the new version adds.
+    pub added_exports: Vec<String>,
+    /// Exports of the old version the new one no longer has.
+    pub removed_exports: Vec<String>,
+    /// Pages the default memory grew by to reach the new initial size.
+    pub pages_added: usize,
+    /// Functions swapped with `replace_function` whose patches were dropped
+    /// in favour of the new version's bodies.
+    pub replaced_patches: usize,
+}
+
 // ── Instance ──────────────────────────────────────────────────────────────────
 
+/// The module an instance runs: borrowed, or shared through an `Arc` so the
+/// instance has no lifetime to outlive.
+#[derive(Clone)]
+pub(crate) enum ModuleRef<'m> {
+    Borrowed(&'m Module),
+    Shared(Arc<Module>),
+}
+
+impl Deref for ModuleRef<'_> {
+    type Target = Module;
+
+    fn deref(&self) -> &Module {
+        match self {
+            ModuleRef::Borrowed(m) => m,
+            ModuleRef::Shared(m) => m,
+        }
+    }
+}
+
+impl<'m> From<&'m Module> for ModuleRef<'m> {
+    fn from(module: &'m Module) -> Self {
+        ModuleRef::Borrowed(module)
+    }
+}
+
+impl From<Arc<Module>> for ModuleRef<'static> {
+    fn from(module: Arc<Module>) -> Self {
+        ModuleRef::Shared(module)
+    }
+}
+
+/// An instance that owns a share of its module, from
+/// [`Runtime::instantiate_owned`]. It borrows nothing, so it can be stored
+/// in structs, moved to other threads and held across `.await`s.
+///
+/// [`Runtime::instantiate_owned`]: crate::runtime::Runtime::instantiate_owned
+pub type OwnedInstance = Instance<'static>;
+
 /// A live instantiation of a Rune module.
+///
+/// `Send` but not `Sync`: see [the crate docs](crate#threads).
 pub struct Instance<'m> {
-    pub memory: Memory,
-    module: &'m Module,
-    prepared: Vec<PreparedFunc>, // one per module function
+    /// Linear memories, indexed by the `memory` immediate of loads and
+    /// stores. Memory 0 is built from the module's memory limits and data
+    /// segments and is the one `memory.size`/`memory.grow` act on. Never
+    /// fewer than the code uses; see [`take_memory`](Self::take_memory).
+    pub(crate) memories: Vec<Memory>,
+    module: ModuleRef<'m>,
+    prepared: Arc<PreparedModule>, // shared with other instances until patched
+    hosts: Vec<HostBinding>,       // indexed by `CallHost`
+    globals: Vec<Global>,          // indexed by `GlobalGet`/`GlobalSet`
+    data: Option<Box<dyn Any + Send>>,
+    backtrace: Vec<TrapFrame>, // frames of the last trap, innermost first
+    config: Arc<RuntimeConfig>,
+    metrics: Option<Arc<Metrics>>, // the creating runtime's counters
+    code: Option<CodeCharge>,      // prepared code held against the runtime's quota
+    epoch: Option<Arc<AtomicU64>>, // the creating runtime's epoch
+    epoch_deadline: Option<u64>,
+    fuel: Option<u64>,  // ops left to run, if metered
+    depth: usize,       // guest functions active
+    cpu_time: Duration, // spent in calls from the host so far
+    cpu_budget: Option<Duration>,
+    cpu_deadline: Option<Instant>, // when the running call exhausts the budget
+    cancel: Option<CancellationToken>, // of the running call, if cancellable
+    rng: Option<Rng>,              // seeded on first use
+    profile: Option<Box<Profile>>, // counts, when profiling
+    timers: Timers,
+    bump: Range<usize>, // host bump region, used when the guest has no `alloc`
+    #[cfg(feature = "cranelift")]
+    native: Option<Arc<CodeSlot>>, // the module's native code, once compiled
+    #[cfg(feature = "cranelift")]
+    running: Option<Arc<NativeModule>>, // native code of the call in progress
+    #[cfg(feature = "cranelift")]
+    tiers: Option<Arc<Tiers>>, // promotes hot functions, under `Strategy::Tiered`
+    #[cfg(feature = "cranelift")]
+    tier_generation: u64, // of the tiered code in `running`
 }
 
 impl<'m> Instance<'m> {
     pub fn new(module: &'m Module) -> Result<Self> {
-        let mut memory = Memory::new(module.initial_memory_pages, module.max_memory_pages);
-        for (offset, bytes) in &module.data_segments {
-            memory.write_bytes(*offset as usize, bytes)?;
+        Self::new_with_blobs(module, &NoBlobs)
+    }
+
+    /// Instantiate, fetching external data segments from `blobs`.
+    pub fn new_with_blobs(module: &'m Module, blobs: &dyn BlobStore) -> Result<Self> {
+        Self::with_config(
+            module.into(),
+            blobs,
+            Vec::new(),
+            None,
+            Arc::new(RuntimeConfig::new()),
+        )
+    }
+
+    /// Instantiate with `extra` appended after the default memory, as
+    /// memories 1, 2, …; see [`Runtime::instantiate_with_memories`]. Imports
+    /// are resolved through `linker`, granting the capabilities given with it.
+    ///
+    /// [`Runtime::instantiate_with_memories`]: crate::runtime::Runtime::instantiate_with_memories
+    pub(crate) fn with_config(
+        module: ModuleRef<'m>,
+        blobs: &dyn BlobStore,
+        extra: Vec<Memory>,
+        linker: Option<(&Linker, &Capabilities)>,
+        config: Arc<RuntimeConfig>,
+    ) -> Result<Self> {
+        let linked = Linked::new(&module, linker, 1 + extra.len(), &config)?;
+        Self::from_linked(module, blobs, extra, linked, config)
+    }
+
+    /// Instantiate with host bindings and functions resolved earlier by
+    /// [`Linked::new`], which must have been given this module, config and
+    /// number of memories.
+    pub(crate) fn from_linked(
+        module: ModuleRef<'m>,
+        blobs: &dyn BlobStore,
+        extra: Vec<Memory>,
+        linked: Linked,
+        config: Arc<RuntimeConfig>,
+    ) -> Result<Self> {
+        check_initial_memory(&module, module.initial_memory_pages, &config)?;
+        let mut memory = new_memory(&module, &config);
+        if config.poison() != Poison::Off {
+            memory.set_poison(config.poison());
+            memory.reset(module.initial_memory_pages)?;
         }
-        // Fix 2: precompute jump tables once, at load time.
-        let prepared = module.functions.iter().map(prepare_func).collect();
-        Ok(Instance {
+        apply_data_segments(&mut memory, &module, blobs)?;
+        Ok(Self::with_memory(module, memory, extra, linked, config))
+    }
+
+    /// Instantiate with memory taken from a prepared image of this module.
+    pub(crate) fn from_image(
+        module: ModuleRef<'m>,
+        image: &MemoryImage,
+        linked: Linked,
+        config: Arc<RuntimeConfig>,
+    ) -> Result<Self> {
+        if image.pages() != module.initial_memory_pages {
+            return Err(Trap::InvalidModule(
+                "memory image does not match the module".into(),
+            ));
+        }
+        check_initial_memory(&module, module.initial_memory_pages, &config)?;
+        // The image's contents, zero pages included, count as initialized.
+        let mut memory = image.instantiate_with(config.guard_pages())?;
+        memory.set_poison(config.poison());
+        Ok(Self::with_memory(
+            module,
             memory,
+            Vec::new(),
+            linked,
+            config,
+        ))
+    }
+
+    /// Instantiate from a snapshot of an initialized instance of this
+    /// module, with host bindings resolved by [`Linked`] over the image's
+    /// prepared functions.
+    pub(crate) fn from_warm_image(
+        module: ModuleRef<'m>,
+        image: &WarmImage,
+        linked: Linked,
+        config: Arc<RuntimeConfig>,
+    ) -> Result<Self> {
+        check_initial_memory(&module, image.pages(), &config)?;
+        let mut memory = image.memory.instantiate_with(config.guard_pages())?;
+        memory.set_poison(config.poison());
+        let mut instance = Self::with_memory(module, memory, Vec::new(), linked, config);
+        instance.bump = image.bump.clone();
+        Ok(instance)
+    }
+
+    fn with_memory(
+        module: ModuleRef<'m>,
+        mut memory: Memory,
+        extra: Vec<Memory>,
+        linked: Linked,
+        config: Arc<RuntimeConfig>,
+    ) -> Self {
+        memory.set_limiter(config.memory_limiter().cloned());
+        memory.set_max_pages(config.memory_max_for(&module));
+        let memories: Vec<Memory> = std::iter::once(memory).chain(extra).collect();
+        let fuel = config.fuel();
+        let profile = config.profiling().then(|| Box::new(Profile::new(&module)));
+        Instance {
+            memories,
             module,
-            prepared,
+            prepared: linked.prepared,
+            hosts: linked.hosts,
+            globals: linked.globals,
+            data: None,
+            backtrace: Vec::new(),
+            config,
+            metrics: None,
+            code: None,
+            epoch: None,
+            epoch_deadline: None,
+            fuel,
+            cpu_time: Duration::ZERO,
+            cpu_budget: None,
+            cpu_deadline: None,
+            cancel: None,
+            rng: None,
+            profile,
+            timers: Timers::default(),
+            depth: 0,
+            bump: 0..0,
+            #[cfg(feature = "cranelift")]
+            native: None,
+            #[cfg(feature = "cranelift")]
+            running: None,
+            #[cfg(feature = "cranelift")]
+            tiers: None,
+            #[cfg(feature = "cranelift")]
+            tier_generation: 0,
+        }
+    }
+
+    /// Put the default memory back in its freshly instantiated state:
+    /// shrunk to the module's initial size, zeroed, and with the inline data
+    /// segments re-applied. Functions stay prepared, so this is much cheaper
+    /// than instantiating again. Fails if the module has external segments;
+    /// use [`reset_memory_with_blobs`](Self::reset_memory_with_blobs).
+    pub fn reset_memory(&mut self) -> Result<()> {
+        self.reset_memory_with_blobs(&NoBlobs)
+    }
+
+    /// Like [`reset_memory`](Self::reset_memory), fetching external data
+    /// segments from `blobs`. If a segment is missing or fails its hash
+    /// check, memory is left zeroed at the initial size, with no segment
+    /// applied.
+    pub fn reset_memory_with_blobs(&mut self, blobs: &dyn BlobStore) -> Result<()> {
+        let memory = &mut self.memories[0];
+        let initial = self.module.initial_memory_pages;
+        memory.reset(initial)?;
+        self.bump = 0..0;
+        if let Err(trap) = apply_data_segments(memory, &self.module, blobs) {
+            // Segments stream straight into memory; drop the ones that
+            // landed before the failure.
+            memory.reset(initial)?;
+            return Err(trap);
+        }
+        Ok(())
+    }
+
+    /// Reset the default memory to the contents of `image`, which must have
+    /// been built from this instance's module. With a shared image this
+    /// remaps it rather than zeroing and copying.
+    pub fn reset_memory_from_image(&mut self, image: &MemoryImage) -> Result<()> {
+        if image.pages() != self.module.initial_memory_pages {
+            return Err(Trap::InvalidModule(
+                "memory image does not match the module".into(),
+            ));
+        }
+        let guarded = self.config.guard_pages();
+        self.memories[0].adopt(image.instantiate_with(guarded)?)?;
+        self.bump = 0..0;
+        Ok(())
+    }
+
+    /// Count this instance's calls and traps in `metrics`.
+    pub(crate) fn set_metrics(&mut self, metrics: Arc<Metrics>) {
+        self.metrics = Some(metrics);
+    }
+
+    pub(crate) fn set_epoch(&mut self, epoch: Arc<AtomicU64>) {
+        self.epoch = Some(epoch);
+    }
+
+    /// Hold this instance's prepared code against `usage`'s code quota
+    /// until it is dropped.
+    pub(crate) fn charge_code(&mut self, usage: &Arc<MemoryUsage>) -> Result<()> {
+        self.code = Some(usage.charge_code(self.prepared.code_bytes())?);
+        Ok(())
+    }
+
+    /// Interrupt guest code with `Trap::Interrupted` once the runtime's
+    /// epoch has advanced `ticks` past its current value. Checked on entry
+    /// to every loop iteration and function call, so even a guest spinning
+    /// in a tight loop stops promptly. Has no effect on an instance created
+    /// outside a [`Runtime`](crate::runtime::Runtime).
+    pub fn set_epoch_deadline(&mut self, ticks: u64) {
+        let now = self.epoch.as_ref().map_or(0, |e| e.load(Ordering::Relaxed));
+        self.epoch_deadline = Some(now.saturating_add(ticks));
+    }
+
+    /// Let guest code run regardless of the epoch; the default.
+    pub fn clear_epoch_deadline(&mut self) {
+        self.epoch_deadline = None;
+    }
+
+    /// Fuel left, or `None` if execution is not metered.
+    pub fn fuel(&self) -> Option<u64> {
+        self.fuel
+    }
+
+    /// Meter execution with `fuel` units, replacing whatever was left. Each
+    /// op executed costs one; running out traps with `Trap::OutOfFuel`,
+    /// after which the instance can be refilled and called again.
+    pub fn set_fuel(&mut self, fuel: u64) {
+        self.fuel = Some(fuel);
+    }
+
+    /// Stop metering execution.
+    pub fn clear_fuel(&mut self) {
+        self.fuel = None;
+    }
+
+    /// Reseed this instance's random number generator, overriding the
+    /// runtime's seed or entropy; see [`rng`](crate::rng).
+    pub fn set_rng_seed(&mut self, seed: u64) {
+        self.rng = Some(Rng::from_seed(seed));
+    }
+
+    /// Time spent running calls from the host, including the host
+    /// functions they call, since the instance was created or
+    /// [`reset_cpu_time`](Self::reset_cpu_time) was last called. Measured
+    /// as elapsed time on the calling thread, so it counts time the thread
+    /// was descheduled too.
+    pub fn cpu_time(&self) -> Duration {
+        self.cpu_time
+    }
+
+    /// Start accounting from zero again, as when billing a new period.
+    pub fn reset_cpu_time(&mut self) {
+        self.cpu_time = Duration::ZERO;
+    }
+
+    /// Trap with `Trap::CpuTimeExceeded` once [`cpu_time`](Self::cpu_time)
+    /// reaches `budget`. Checked on entry to every call from the host, loop
+    /// iteration and function call, like an epoch deadline; calls keep
+    /// trapping until the budget is raised or the time is reset.
+    pub fn set_cpu_budget(&mut self, budget: Duration) {
+        self.cpu_budget = Some(budget);
+    }
+
+    /// Let calls run however much time they have used; the default.
+    pub fn clear_cpu_budget(&mut self) {
+        self.cpu_budget = None;
+    }
+
+    pub fn cpu_budget(&self) -> Option<Duration> {
+        self.cpu_budget
+    }
+
+    pub(crate) fn check_deadlines(&self) -> Result<()> {
+        match (&self.epoch, self.epoch_deadline) {
+            (Some(epoch), Some(deadline)) if epoch.load(Ordering::Relaxed) >= deadline => {
+                Err(Trap::Interrupted)
+            }
+            _ => self.check_call_limits(),
+        }
+    }
+
+    /// The limits also checked on entry to a call from the host.
+    pub(crate) fn check_call_limits(&self) -> Result<()> {
+        if self
+            .cancel
+            .as_ref()
+            .is_some_and(CancellationToken::is_cancelled)
+        {
+            return Err(Trap::Interrupted);
+        }
+        match self.cpu_deadline {
+            Some(deadline) if Instant::now() >= deadline => Err(Trap::CpuTimeExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    /// The functions the instance runs, shared with the other instances
+    /// they were prepared for until [`replace_function`](Self::replace_function)
+    /// patches them.
+    pub fn prepared(&self) -> &Arc<PreparedModule> {
+        &self.prepared
+    }
+
+    pub(crate) fn module(&self) -> &Module {
+        &self.module
+    }
+
+    /// Whether this instance runs `module` itself (not merely an equal copy).
+    pub(crate) fn is_instance_of(&self, module: &Arc<Module>) -> bool {
+        matches!(&self.module, ModuleRef::Shared(m) if Arc::ptr_eq(m, module))
+    }
+
+    /// Capture the default memory and host allocator position as a
+    /// [`WarmImage`], typically right after calling the module's init
+    /// export, so later instances can start in this state. Other memories
+    /// are not captured. Fails with `Trap::InvalidModule` if a function was
+    /// replaced with [`replace_function`](Self::replace_function).
+    pub fn snapshot(&self) -> Result<WarmImage> {
+        if self.is_patched() {
+            return Err(Trap::InvalidModule(
+                "cannot snapshot an instance with replaced functions".into(),
+            ));
+        }
+        Ok(WarmImage {
+            memory: MemoryImage::from_memory(&self.memories[0]),
+            bump: self.bump.clone(),
+            prepared: self.prepared.clone(),
         })
     }
 
+    /// Whether a function was replaced with
+    /// [`replace_function`](Self::replace_function).
+    pub(crate) fn is_patched(&self) -> bool {
+        self.prepared.funcs.iter().any(|pf| pf.patched)
+    }
+
+    /// Return to the state of a fresh instance from `image` for reuse: only
+    /// the default memory, reset to the image, with the runtime's limiter and
+    /// poison mode, and no host data or trap backtrace.
+    pub(crate) fn recycle(&mut self, image: &MemoryImage) -> Result<()> {
+        self.data = None;
+        self.memories.truncate(1);
+        self.memories[0].set_limiter(self.config.memory_limiter().cloned());
+        self.reset_memory_from_image(image)?;
+        self.memories[0].set_poison(self.config.poison());
+        self.backtrace.clear();
+        self.epoch_deadline = None;
+        self.fuel = self.config.fuel();
+        self.cpu_time = Duration::ZERO;
+        self.cpu_budget = None;
+        self.rng = None;
+        self.timers.clear();
+        Ok(())
+    }
+
+    /// Replace the memory limiter inherited from the runtime configuration
+    /// for this instance only.
+    pub fn set_memory_limiter(&mut self, limiter: Option<Arc<dyn MemoryLimiter>>) {
+        for memory in &mut self.memories {
+            memory.set_limiter(limiter.clone());
+        }
+    }
+
+    // ── Host data ────────────────────────────────────────────────────────────
+
+    /// Attach embedder state to this instance, replacing any before. Host
+    /// functions reach it through [`Caller::data_mut`] without capturing
+    /// shared, locked state in their closures, so one set of host functions
+    /// can serve many instances that each carry their own `T`.
+    pub fn set_data<T: Any + Send>(&mut self, data: T) {
+        self.data = Some(Box::new(data));
+    }
+
+    /// The host data, if it is of type `T`.
+    pub fn data<T: Any>(&self) -> Option<&T> {
+        self.data.as_deref()?.downcast_ref()
+    }
+
+    pub fn data_mut<T: Any>(&mut self) -> Option<&mut T> {
+        self.data.as_deref_mut()?.downcast_mut()
+    }
+
+    /// Detach and return the host data if it is of type `T`; data of another
+    /// type is left in place.
+    pub fn take_data<T: Any>(&mut self) -> Option<T> {
+        if !self.data.as_deref()?.is::<T>() {
+            return None;
+        }
+        self.data.take()?.downcast().ok().map(|b| *b)
+    }
+
+    /// The default memory (index 0).
+    pub fn memory(&self) -> &Memory {
+        &self.memories[0]
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memories[0]
+    }
+
+    /// Memory `index`, if the instance has that many.
+    pub fn memory_at(&self, index: u32) -> Option<&Memory> {
+        self.memories.get(index as usize)
+    }
+
+    pub fn memory_at_mut(&mut self, index: u32) -> Option<&mut Memory> {
+        self.memories.get_mut(index as usize)
+    }
+
+    /// Number of memories, the default one included.
+    pub fn memory_count(&self) -> usize {
+        self.memories.len()
+    }
+
+    /// Attach `memory` at the next index, returning the index. Functions
+    /// [replaced](Self::replace_function) afterwards may use it.
+    pub fn add_memory(&mut self, memory: Memory) -> u32 {
+        self.memories.push(memory);
+        (self.memories.len() - 1) as u32
+    }
+
+    /// Detach and return the last memory, for instance to reclaim an
+    /// [external](Memory::into_external) buffer. Fails with
+    /// `Trap::InvalidModule` if it is the default memory or any load or
+    /// store of the instance's code names it.
+    pub fn take_memory(&mut self) -> Result<Memory> {
+        let last = self.memories.len() - 1;
+        let used = self
+            .prepared
+            .funcs
+            .iter()
+            .filter_map(|pf| max_memory_index(&pf.ops))
+            .max();
+        if last == 0 || used.is_some_and(|used| used as usize >= last) {
+            return Err(Trap::InvalidModule(format!(
+                "memory {last} is in use and cannot be taken"
+            )));
+        }
+        Ok(self.memories.pop().expect("checked above"))
+    }
+
+    /// Consume the instance, returning its memories, the default one first.
+    pub fn into_memories(self) -> Vec<Memory> {
+        self.memories
+    }
+
+    /// Current size, peak size and number of grows of the default memory.
+    pub fn memory_stats(&self) -> MemoryStats {
+        self.memories[0].stats()
+    }
+
+    /// Guest frames that were active when the last `call` trapped, innermost
+    /// first. Empty if the last call succeeded.
+    pub fn trap_backtrace(&self) -> &[TrapFrame] {
+        &self.backtrace
+    }
+
+    /// What the instance's functions did so far, if the runtime
+    /// [profiles](crate::config::RuntimeConfig::set_profiling) them.
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_deref()
+    }
+
+    /// Swap in a new body for one function without touching memory or any
+    /// other instance state. Only this function's jump tables are rebuilt.
+    ///
+    /// The replacement must have exactly the same signature as the module's
+    /// original function, otherwise `Trap::TypeMismatch` is returned. Calls
+    /// already in progress finish on the old body; every later call, including
+    /// internal `Call`s from other functions, runs the new one.
+    pub fn replace_function<'a>(
+        &mut self,
+        target: impl Into<FuncRef<'a>>,
+        func: crate::ir::Function,
+    ) -> Result<()> {
+        let idx = match target.into() {
+            FuncRef::Index(i) => i as usize,
+            FuncRef::Name(name) => {
+                self.module
+                    .find_export(name)
+                    .ok_or_else(|| Trap::UndefinedExport(name.into()))? as usize
+            }
+        };
+        let original = self
+            .module
+            .functions
+            .get(idx)
+            .ok_or_else(|| Trap::UndefinedExport(format!("func#{idx}")))?;
+        if original.ty != func.ty {
+            return Err(Trap::TypeMismatch);
+        }
+        self.config.check_extensions(&func)?;
+        check_memory_indices(&func, self.memories.len())?;
+        check_global_indices(&func, &self.module.global_imports)?;
+        if self.config.verify() {
+            verify::verify_function(&self.module, &func, &self.config).map_err(|err| {
+                Trap::from(verify::VerifyError {
+                    func: Some(idx as u32),
+                    ..err
+                })
+            })?;
+        }
+        let mut pf = prepare_func(idx, &func);
+        pf.patched = true;
+        Arc::make_mut(&mut self.prepared).funcs[idx] = pf;
+        // The native code was compiled from the module's bodies.
+        #[cfg(feature = "cranelift")]
+        {
+            self.native = None;
+            self.running = None;
+            self.tiers = None;
+        }
+        Ok(())
+    }
+
+    /// Switch to `module`, a new version of this instance's module, keeping
+    /// memory contents, host data and host bindings; see [`Runtime::reload`].
+    ///
+    /// [`Runtime::reload`]: crate::runtime::Runtime::reload
+    pub(crate) fn reload(&mut self, module: ModuleRef<'m>) -> Result<ReloadReport> {
+        module.required_features.check_supported()?;
+        let mut report = ReloadReport::default();
+        for (name, idx) in &self.module.exports {
+            let old_ty = &self.module.functions[*idx as usize].ty;
+            match module.find_export(name) {
+                None => report.removed_exports.push(name.clone()),
+                Some(new) => match module.functions.get(new as usize) {
+                    Some(f) if f.ty == *old_ty => {}
+                    _ => {
+                        return Err(Trap::InvalidModule(format!(
+                            "export `{name}` changed signature"
+                        )))
+                    }
+                },
+            }
+        }
+        for (name, _) in &module.exports {
+            if self.module.find_export(name).is_none() {
+                report.added_exports.push(name.clone());
+            }
+        }
+        if module.imports != self.module.imports
+            || module.global_imports != self.module.global_imports
+        {
+            return Err(Trap::InvalidModule(
+                "imports changed; instantiate the new version through a linker".into(),
+            ));
+        }
+        let prepared = PreparedModule::new(&module, &self.config)?;
+        prepared.check_memories(&module, self.memories.len())?;
+        let pages = self.memories[0].pages();
+        if module.max_memory_pages.is_some_and(|max| pages > max) {
+            return Err(Trap::InvalidModule(format!(
+                "memory has {pages} pages, more than the new maximum"
+            )));
+        }
+        report.replaced_patches = self.prepared.funcs.iter().filter(|pf| pf.patched).count();
+
+        // Last fallible step: make room for the new module's initial memory.
+        let memory = &mut self.memories[0];
+        report.pages_added = module.initial_memory_pages.saturating_sub(pages);
+        if report.pages_added > 0 {
+            memory.grow(report.pages_added)?;
+        }
+        memory.set_max_pages(self.config.memory_max_for(&module));
+        self.module = module;
+        self.prepared = Arc::new(prepared);
+        self.backtrace.clear();
+        #[cfg(feature = "cranelift")]
+        {
+            self.native = None;
+            self.running = None;
+            self.tiers = None;
+        }
+        Ok(report)
+    }
+
+    // ── Guest allocation ─────────────────────────────────────────────────────
+
+    /// Allocate `len` bytes of guest memory for the host to fill.
+    ///
+    /// Convention: if the module exports [`GUEST_ALLOC_EXPORT`] with
+    /// signature `(i32) -> i32`, it is called and must return a pointer to
+    /// `len` usable bytes, or 0 on failure. Otherwise the host carves 8-byte
+    /// aligned blocks out of pages it appends to memory with `grow`; guests
+    /// relying on this must not assume they own memory beyond the size they
+    /// last observed.
+    pub fn alloc_guest(&mut self, len: u32) -> Result<u32> {
+        if let Some(idx) = self.module.find_export(GUEST_ALLOC_EXPORT) {
+            let ty = &self.module.functions[idx as usize].ty;
+            if ty.params != [ValType::I32] || ty.results != [ValType::I32] {
+                return Err(Trap::TypeMismatch);
+            }
+            let ptr = match self.call(GUEST_ALLOC_EXPORT, &[Val::I32(len as i32)])? {
+                Some(Val::I32(p)) => p as u32,
+                _ => return Err(Trap::TypeMismatch),
+            };
+            if ptr == 0 {
+                return Err(Trap::OutOfMemory);
+            }
+            // The block must really exist before the host writes into it.
+            self.memories[0].read_bytes(ptr as usize, len as usize)?;
+            return Ok(ptr);
+        }
+        self.bump_alloc(len as usize)
+    }
+
+    /// Release a block from [`alloc_guest`](Self::alloc_guest). Calls the
+    /// guest's [`GUEST_FREE_EXPORT`] `(ptr, len)` if it has one; host bump
+    /// blocks are never reclaimed individually.
+    pub fn free_guest(&mut self, ptr: u32, len: u32) -> Result<()> {
+        if self.module.find_export(GUEST_FREE_EXPORT).is_some() {
+            self.call(
+                GUEST_FREE_EXPORT,
+                &[Val::I32(ptr as i32), Val::I32(len as i32)],
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Allocate guest memory for `bytes` and copy them in, returning the
+    /// `(ptr, len)` pair to pass to the guest.
+    pub fn copy_into_guest(&mut self, bytes: &[u8]) -> Result<(u32, u32)> {
+        let len = u32::try_from(bytes.len()).map_err(|_| Trap::OutOfMemory)?;
+        let ptr = self.alloc_guest(len)?;
+        self.memories[0].write_bytes(ptr as usize, bytes)?;
+        Ok((ptr, len))
+    }
+
+    fn bump_alloc(&mut self, len: usize) -> Result<u32> {
+        let size = self.memories[0].size();
+        if self.bump.end != size {
+            // First use, or the guest grew memory since: start a fresh region
+            // at the current end rather than handing out guest-owned pages.
+            self.bump = size..size;
+        }
+        // Address 0 is the null pointer; never hand it out.
+        let ptr = ((self.bump.start + 7) & !7).max(8);
+        let end = ptr.checked_add(len).ok_or(Trap::OutOfMemory)?;
+        if end > u32::MAX as usize {
+            return Err(Trap::OutOfMemory);
+        }
+        if end > self.bump.end {
+            self.memories[0].grow((end - self.bump.end).div_ceil(PAGE_SIZE))?;
+            self.bump.end = self.memories[0].size();
+        }
+        self.bump.start = end;
+        Ok(ptr as u32)
+    }
+
     /// Call an exported function by name.
     pub fn call(&mut self, func_name: &str, args: &[Val]) -> Result<Option<Val>> {
+        self.backtrace.clear();
         let idx = self
             .module
             .find_export(func_name)
             .ok_or_else(|| Trap::UndefinedExport(func_name.into()))? as usize;
+        self.call_index(idx, args)
+    }
+
+    /// Call `export`, with no arguments, at the first
+    /// [`poll_timers`](Self::poll_timers) once `delay` has passed on the
+    /// runtime's [clock](RuntimeConfig::set_clock). Returns an id for
+    /// [`clear_timeout`](Self::clear_timeout). Fails with
+    /// `Trap::UndefinedExport` if there is no such export.
+    pub fn set_timeout(&mut self, export: &str, delay: Duration) -> Result<u32> {
+        schedule(&mut self.timers, &self.module, &self.config, export, delay)
+    }
+
+    /// Cancel timer `id`; false if it already ran or never existed.
+    pub fn clear_timeout(&mut self, id: u32) -> bool {
+        self.timers.cancel(id)
+    }
+
+    pub fn pending_timers(&self) -> usize {
+        self.timers.len()
+    }
+
+    /// How long until the earliest pending timer is due, zero if one
+    /// already is.
+    pub fn next_timer(&self) -> Option<Duration> {
+        let due = self.timers.next_due()?;
+        Some(due.saturating_sub(self.config.clock().now()))
+    }
+
+    /// Run every timer due now, earliest first, and return how many ran.
+    /// Timers the callbacks schedule wait for the next poll, even with no
+    /// delay. Stops at the first callback that traps, returning its trap;
+    /// timers not yet run stay queued.
+    pub fn poll_timers(&mut self) -> Result<usize> {
+        let (ran, result) = self.run_due_timers();
+        result.map(|()| ran)
+    }
+
+    pub(crate) fn run_due_timers(&mut self) -> (usize, Result<()>) {
+        let now = self.config.clock().now();
+        let watermark = self.timers.watermark();
+        let mut ran = 0;
+        while let Some(export) = self.timers.pop_due(now, watermark) {
+            ran += 1;
+            if let Err(trap) = self.call(&export, &[]) {
+                return (ran, Err(trap));
+            }
+        }
+        (ran, Ok(()))
+    }
+
+    /// [`call`](Self::call), returning `Trap::Interrupted` once `token` is
+    /// cancelled: on entry if it already is, else at the next loop
+    /// iteration or function call. A host function already running is not
+    /// interrupted; the trap follows when it returns.
+    pub fn call_cancellable(
+        &mut self,
+        func_name: &str,
+        args: &[Val],
+        token: &CancellationToken,
+    ) -> Result<Option<Val>> {
+        self.cancel = Some(token.clone());
+        let result = self.call(func_name, args);
+        self.cancel = None;
+        result
+    }
+
+    /// [`call`](Self::call) with the export already looked up.
+    pub(crate) fn call_index(&mut self, idx: usize, args: &[Val]) -> Result<Option<Val>> {
+        self.backtrace.clear();
         // Fix 1: PreparedFunc::clone() is O(1).
         let pf = self
             .prepared
+            .funcs
             .get(idx)
             .ok_or_else(|| Trap::UndefinedExport(format!("func#{idx}")))?
             .clone();
@@ -141,12 +1341,70 @@ impl<'m> Instance<'m> {
         for &ty in &pf.extra_locals {
             locals.push(Val::default_for(ty));
         }
-        self.exec(&pf, locals)
+        self.depth = 1;
+        // Code compiled since the last call takes over from here. Tiered
+        // code changes as calls start instead.
+        #[cfg(feature = "cranelift")]
+        if self.tiers.is_none() {
+            self.running = self.native.as_ref().and_then(|slot| slot.get()?.clone());
+        }
+        let started = Instant::now();
+        self.cpu_deadline = self
+            .cpu_budget
+            .and_then(|budget| started.checked_add(budget.saturating_sub(self.cpu_time)));
+        let result = if self.config.has_event_hooks() {
+            self.exec_with_events(&pf, locals)
+        } else {
+            self.exec(&pf, locals)
+        };
+        self.cpu_time += started.elapsed();
+        self.cpu_deadline = None;
+        if let Some(metrics) = &self.metrics {
+            metrics.called(&result);
+        }
+        result
+    }
+
+    /// [`exec`](Self::exec) bracketed by call events.
+    fn exec_with_events(&mut self, pf: &PreparedFunc, locals: Vec<Val>) -> Result<Option<Val>> {
+        let config = self.config.clone();
+        let module = self.module.clone();
+        let func = &module.functions[pf.index as usize].name;
+        config.emit(&Event::CallStart { func });
+        let started = Instant::now();
+        let result = self.exec(pf, locals);
+        let elapsed = started.elapsed();
+        if let Err(trap) = &result {
+            config.emit(&Event::Trap { func, trap });
+        }
+        config.emit(&Event::CallEnd {
+            func,
+            elapsed,
+            trapped: result.is_err(),
+        });
+        result
     }
 
     // ── Core dispatch loop ────────────────────────────────────────────────────
 
     fn exec(&mut self, pf: &PreparedFunc, locals: Vec<Val>) -> Result<Option<Val>> {
+        if let Some(profile) = &mut self.profile {
+            profile.count_call(pf.index);
+        }
+        #[cfg(feature = "cranelift")]
+        if self.profile.is_none() {
+            if let Some(tiers) = &self.tiers {
+                let generation = &mut self.tier_generation;
+                if let Some(code) = tiers.enter(pf.index, generation, &self.module, &self.config) {
+                    self.running = Some(code);
+                }
+            }
+            if let Some(code) = self.running.clone() {
+                if code.is_compiled(pf.index) {
+                    return native::run(self, &code, pf, &locals);
+                }
+            }
+        }
         let ops = &pf.ops;
         let ends = &*pf.ends;
         let elses = &*pf.elses;
@@ -155,46 +1413,88 @@ impl<'m> Instance<'m> {
         let mut ctrl: Vec<CtrlFrame> = Vec::with_capacity(8);
         let mut locs = locals;
         let mut pc = 0usize;
+        let strict_alignment = self.config.strict_alignment();
+        let address_overflow = self.config.address_overflow();
+
+        // ── Trap macros ──────────────────────────────────────────────────────
+        //
+        // Every trap in the dispatch loop leaves through `trap!`, which
+        // records the faulting pc for the backtrace before returning.
+        macro_rules! trap {
+            ($trap:expr) => {
+                return Err(self.trap_frame(pf, pc, $trap))
+            };
+        }
+        macro_rules! check {
+            ($result:expr) => {
+                match $result {
+                    Ok(v) => v,
+                    Err(trap) => trap!(trap),
+                }
+            };
+        }
 
         // ── Typed-pop macros ─────────────────────────────────────────────────
         macro_rules! pop {
             () => {
-                stack.pop().ok_or(Trap::TypeMismatch)?
+                check!(stack.pop().ok_or(Trap::TypeMismatch))
             };
         }
         macro_rules! pop_i32 {
             () => {
-                match stack.pop().ok_or(Trap::TypeMismatch)? {
+                match check!(stack.pop().ok_or(Trap::TypeMismatch)) {
                     Val::I32(v) => v,
-                    _ => return Err(Trap::TypeMismatch),
+                    _ => trap!(Trap::TypeMismatch),
                 }
             };
         }
         macro_rules! pop_i64 {
             () => {
-                match stack.pop().ok_or(Trap::TypeMismatch)? {
+                match check!(stack.pop().ok_or(Trap::TypeMismatch)) {
                     Val::I64(v) => v,
-                    _ => return Err(Trap::TypeMismatch),
+                    _ => trap!(Trap::TypeMismatch),
                 }
             };
         }
         macro_rules! pop_f32 {
             () => {
-                match stack.pop().ok_or(Trap::TypeMismatch)? {
+                match check!(stack.pop().ok_or(Trap::TypeMismatch)) {
                     Val::F32(v) => v,
-                    _ => return Err(Trap::TypeMismatch),
+                    _ => trap!(Trap::TypeMismatch),
                 }
             };
         }
         macro_rules! pop_f64 {
             () => {
-                match stack.pop().ok_or(Trap::TypeMismatch)? {
+                match check!(stack.pop().ok_or(Trap::TypeMismatch)) {
                     Val::F64(v) => v,
-                    _ => return Err(Trap::TypeMismatch),
+                    _ => trap!(Trap::TypeMismatch),
                 }
             };
         }
 
+        // Pop a base address and add the static offset, in 32-bit unsigned
+        // arithmetic so the result is the same on every host. In strict
+        // mode the result must be a multiple of 2^align.
+        macro_rules! pop_addr {
+            ($align:expr, $offset:expr) => {{
+                let base = pop_i32!() as u32;
+                let addr = match address_overflow {
+                    AddressOverflow::Trap => {
+                        check!(base.checked_add($offset).ok_or(Trap::OutOfBounds))
+                    }
+                    AddressOverflow::Wrap => base.wrapping_add($offset),
+                } as usize;
+                if strict_alignment {
+                    let mask = 1usize.checked_shl($align).map_or(usize::MAX, |a| a - 1);
+                    if addr & mask != 0 {
+                        trap!(Trap::UnalignedAccess);
+                    }
+                }
+                addr
+            }};
+        }
+
         // ── Branch macro: Fix 2 — O(1) table lookup, no Vec allocation ───────
         //
         // Wasm branch semantics:
@@ -207,10 +1507,7 @@ impl<'m> Instance<'m> {
         macro_rules! do_branch {
             ($depth:expr) => {{
                 let depth = $depth as usize;
-                let frame_idx = ctrl
-                    .len()
-                    .checked_sub(1 + depth)
-                    .ok_or(Trap::TypeMismatch)?;
+                let frame_idx = check!(ctrl.len().checked_sub(1 + depth).ok_or(Trap::TypeMismatch));
                 let frame = &ctrl[frame_idx];
                 let is_loop = frame.kind == FrameKind::Loop;
                 let target = frame.target_pc;
@@ -238,12 +1535,16 @@ impl<'m> Instance<'m> {
             }};
         }
 
+        check!(self.check_call_limits());
         loop {
             if pc >= ops.len() {
                 break;
             }
             let op = &ops[pc];
             pc += 1;
+            if let Some(fuel) = &mut self.fuel {
+                *fuel = check!(fuel.checked_sub(1).ok_or(Trap::OutOfFuel));
+            }
 
             match op {
                 // ── Constants ─────────────────────────────────────────────────
@@ -254,16 +1555,27 @@ impl<'m> Instance<'m> {
 
                 // ── Locals ────────────────────────────────────────────────────
                 Op::LocalGet(i) => {
-                    let v = *locs.get(*i as usize).ok_or(Trap::TypeMismatch)?;
+                    let v = *check!(locs.get(*i as usize).ok_or(Trap::TypeMismatch));
                     stack.push(v);
                 }
                 Op::LocalSet(i) => {
                     let v = pop!();
-                    *locs.get_mut(*i as usize).ok_or(Trap::TypeMismatch)? = v;
+                    *check!(locs.get_mut(*i as usize).ok_or(Trap::TypeMismatch)) = v;
                 }
                 Op::LocalTee(i) => {
-                    let v = *stack.last().ok_or(Trap::TypeMismatch)?;
-                    *locs.get_mut(*i as usize).ok_or(Trap::TypeMismatch)? = v;
+                    let v = *check!(stack.last().ok_or(Trap::TypeMismatch));
+                    *check!(locs.get_mut(*i as usize).ok_or(Trap::TypeMismatch)) = v;
+                }
+
+                // ── Globals ───────────────────────────────────────────────────
+                Op::GlobalGet(i) => {
+                    let global = check!(self.globals.get(*i as usize).ok_or(Trap::TypeMismatch));
+                    stack.push(global.get());
+                }
+                Op::GlobalSet(i) => {
+                    let v = pop!();
+                    let global = check!(self.globals.get(*i as usize).ok_or(Trap::TypeMismatch));
+                    check!(global.set(v));
                 }
 
                 // ── Stack ops ─────────────────────────────────────────────────
@@ -277,7 +1589,7 @@ impl<'m> Instance<'m> {
                     stack.push(if cond != 0 { a } else { b });
                 }
                 Op::Nop => {}
-                Op::Unreachable => return Err(Trap::Unreachable),
+                Op::Unreachable => trap!(Trap::Unreachable),
 
                 // ── i32 arithmetic ────────────────────────────────────────────
                 Op::I32Add => {
@@ -299,10 +1611,10 @@ impl<'m> Instance<'m> {
                     let b = pop_i32!();
                     let a = pop_i32!();
                     if b == 0 {
-                        return Err(Trap::DivisionByZero);
+                        trap!(Trap::DivisionByZero);
                     }
                     if a == i32::MIN && b == -1 {
-                        return Err(Trap::Unreachable);
+                        trap!(Trap::Unreachable);
                     }
                     stack.push(Val::I32(a / b));
                 }
@@ -310,7 +1622,7 @@ impl<'m> Instance<'m> {
                     let b = pop_i32!() as u32;
                     let a = pop_i32!() as u32;
                     if b == 0 {
-                        return Err(Trap::DivisionByZero);
+                        trap!(Trap::DivisionByZero);
                     }
                     stack.push(Val::I32((a / b) as i32));
                 }
@@ -318,7 +1630,7 @@ impl<'m> Instance<'m> {
                     let b = pop_i32!();
                     let a = pop_i32!();
                     if b == 0 {
-                        return Err(Trap::DivisionByZero);
+                        trap!(Trap::DivisionByZero);
                     }
                     stack.push(Val::I32(a.wrapping_rem(b)));
                 }
@@ -326,7 +1638,7 @@ impl<'m> Instance<'m> {
                     let b = pop_i32!() as u32;
                     let a = pop_i32!() as u32;
                     if b == 0 {
-                        return Err(Trap::DivisionByZero);
+                        trap!(Trap::DivisionByZero);
                     }
                     stack.push(Val::I32((a % b) as i32));
                 }
@@ -449,7 +1761,7 @@ impl<'m> Instance<'m> {
                     let b = pop_i64!();
                     let a = pop_i64!();
                     if b == 0 {
-                        return Err(Trap::DivisionByZero);
+                        trap!(Trap::DivisionByZero);
                     }
                     stack.push(Val::I64(a.wrapping_div(b)));
                 }
@@ -457,7 +1769,7 @@ impl<'m> Instance<'m> {
                     let b = pop_i64!() as u64;
                     let a = pop_i64!() as u64;
                     if b == 0 {
-                        return Err(Trap::DivisionByZero);
+                        trap!(Trap::DivisionByZero);
                     }
                     stack.push(Val::I64((a / b) as i64));
                 }
@@ -465,7 +1777,7 @@ impl<'m> Instance<'m> {
                     let b = pop_i64!();
                     let a = pop_i64!();
                     if b == 0 {
-                        return Err(Trap::DivisionByZero);
+                        trap!(Trap::DivisionByZero);
                     }
                     stack.push(Val::I64(a.wrapping_rem(b)));
                 }
@@ -473,7 +1785,7 @@ impl<'m> Instance<'m> {
                     let b = pop_i64!() as u64;
                     let a = pop_i64!() as u64;
                     if b == 0 {
-                        return Err(Trap::DivisionByZero);
+                        trap!(Trap::DivisionByZero);
                     }
                     stack.push(Val::I64((a % b) as i64));
                 }
@@ -809,47 +2121,96 @@ impl<'m> Instance<'m> {
                 }
 
                 // ── Memory ops ────────────────────────────────────────────────
-                Op::MemorySize => stack.push(Val::I32(self.memory.pages() as i32)),
+                Op::MemorySize => stack.push(Val::I32(self.memories[0].pages() as i32)),
                 Op::MemoryGrow => {
                     let delta = pop_i32!() as usize;
-                    let old = self.memory.grow(delta).map(|p| p as i32).unwrap_or(-1);
+                    let old = self.memories[0].grow(delta).map(|p| p as i32).unwrap_or(-1);
                     stack.push(Val::I32(old));
                 }
-                Op::I32Load { offset, .. } => {
-                    let b = pop_i32!() as usize;
-                    stack.push(Val::I32(self.memory.read_i32(b + *offset as usize)?));
-                }
-                Op::I32Store { offset, .. } => {
+                Op::MemoryDiscard => {
+                    let len = pop_i32!() as u32 as usize;
+                    let addr = pop_i32!() as u32 as usize;
+                    if !addr.is_multiple_of(PAGE_SIZE) || !len.is_multiple_of(PAGE_SIZE) {
+                        trap!(Trap::OutOfBounds);
+                    }
+                    let first = addr / PAGE_SIZE;
+                    check!(self.memories[0].discard(first..first + len / PAGE_SIZE));
+                }
+                Op::I32Load {
+                    align,
+                    offset,
+                    memory,
+                } => {
+                    let addr = pop_addr!(*align, *offset);
+                    stack.push(Val::I32(check!(
+                        self.memories[*memory as usize].read_i32(addr)
+                    )));
+                }
+                Op::I32Store {
+                    align,
+                    offset,
+                    memory,
+                } => {
                     let v = pop_i32!();
-                    let b = pop_i32!() as usize;
-                    self.memory.write_i32(b + *offset as usize, v)?;
-                }
-                Op::I64Load { offset, .. } => {
-                    let b = pop_i32!() as usize;
-                    stack.push(Val::I64(self.memory.read_i64(b + *offset as usize)?));
-                }
-                Op::I64Store { offset, .. } => {
+                    let addr = pop_addr!(*align, *offset);
+                    check!(self.memories[*memory as usize].write_i32(addr, v));
+                }
+                Op::I64Load {
+                    align,
+                    offset,
+                    memory,
+                } => {
+                    let addr = pop_addr!(*align, *offset);
+                    stack.push(Val::I64(check!(
+                        self.memories[*memory as usize].read_i64(addr)
+                    )));
+                }
+                Op::I64Store {
+                    align,
+                    offset,
+                    memory,
+                } => {
                     let v = pop_i64!();
-                    let b = pop_i32!() as usize;
-                    self.memory.write_i64(b + *offset as usize, v)?;
-                }
-                Op::F32Load { offset, .. } => {
-                    let b = pop_i32!() as usize;
-                    stack.push(Val::F32(self.memory.read_f32(b + *offset as usize)?));
-                }
-                Op::F32Store { offset, .. } => {
+                    let addr = pop_addr!(*align, *offset);
+                    check!(self.memories[*memory as usize].write_i64(addr, v));
+                }
+                Op::F32Load {
+                    align,
+                    offset,
+                    memory,
+                } => {
+                    let addr = pop_addr!(*align, *offset);
+                    stack.push(Val::F32(check!(
+                        self.memories[*memory as usize].read_f32(addr)
+                    )));
+                }
+                Op::F32Store {
+                    align,
+                    offset,
+                    memory,
+                } => {
                     let v = pop_f32!();
-                    let b = pop_i32!() as usize;
-                    self.memory.write_f32(b + *offset as usize, v)?;
-                }
-                Op::F64Load { offset, .. } => {
-                    let b = pop_i32!() as usize;
-                    stack.push(Val::F64(self.memory.read_f64(b + *offset as usize)?));
-                }
-                Op::F64Store { offset, .. } => {
+                    let addr = pop_addr!(*align, *offset);
+                    check!(self.memories[*memory as usize].write_f32(addr, v));
+                }
+                Op::F64Load {
+                    align,
+                    offset,
+                    memory,
+                } => {
+                    let addr = pop_addr!(*align, *offset);
+                    stack.push(Val::F64(check!(
+                        self.memories[*memory as usize].read_f64(addr)
+                    )));
+                }
+                Op::F64Store {
+                    align,
+                    offset,
+                    memory,
+                } => {
                     let v = pop_f64!();
-                    let b = pop_i32!() as usize;
-                    self.memory.write_f64(b + *offset as usize, v)?;
+                    let addr = pop_addr!(*align, *offset);
+                    check!(self.memories[*memory as usize].write_f64(addr, v));
                 }
 
                 // ── Control flow ──────────────────────────────────────────────
@@ -862,6 +2223,7 @@ impl<'m> Instance<'m> {
                     });
                 }
                 Op::Loop(bt) => {
+                    check!(self.check_deadlines());
                     ctrl.push(CtrlFrame {
                         kind: FrameKind::Loop,
                         stack_base: stack.len(),
@@ -877,22 +2239,23 @@ impl<'m> Instance<'m> {
                         target_pc: ends[pc - 1],
                         result_type: block_result(bt),
                     });
+                    if let Some(profile) = &mut self.profile {
+                        profile.count_branch(pf.index, pc - 1, cond != 0);
+                    }
                     if cond == 0 {
                         // Fix 2: O(1) precomputed Else lookup (no linear scan).
                         let else_pc = elses[pc - 1];
                         if else_pc != usize::MAX {
                             pc = else_pc + 1;
                         } else {
+                            // The End pops the frame.
                             pc = ends[pc - 1];
-                            ctrl.pop();
                         }
                     }
                 }
                 Op::Else => {
-                    // End of "then" branch — jump to End.
-                    let end_pc = ctrl.last().ok_or(Trap::TypeMismatch)?.target_pc;
-                    ctrl.pop();
-                    pc = end_pc;
+                    // End of "then" branch — jump to End, which pops the frame.
+                    pc = check!(ctrl.last().ok_or(Trap::TypeMismatch)).target_pc;
                 }
                 Op::End => {
                     if !ctrl.is_empty() {
@@ -908,6 +2271,9 @@ impl<'m> Instance<'m> {
                 }
                 Op::BrIf(depth) => {
                     let cond = pop_i32!();
+                    if let Some(profile) = &mut self.profile {
+                        profile.count_branch(pf.index, pc - 1, cond != 0);
+                    }
                     if cond != 0 {
                         pc = do_branch!(*depth);
                     }
@@ -915,16 +2281,21 @@ impl<'m> Instance<'m> {
 
                 // ── Function calls ────────────────────────────────────────────
                 Op::Call(idx) => {
+                    check!(self.check_deadlines());
+                    if let Some(profile) = &mut self.profile {
+                        profile.count_site(pf.index, pc - 1);
+                    }
                     let idx = *idx as usize;
                     // Fix 1: O(1) clone (Arc refcount bump, no memcopy).
-                    let callee = self
+                    let callee = check!(self
                         .prepared
+                        .funcs
                         .get(idx)
-                        .ok_or_else(|| Trap::UndefinedExport(format!("func#{idx}")))?
-                        .clone();
+                        .ok_or_else(|| Trap::UndefinedExport(format!("func#{idx}"))))
+                    .clone();
                     let n = callee.n_params;
                     if stack.len() < n {
-                        return Err(Trap::TypeMismatch);
+                        trap!(Trap::TypeMismatch);
                     }
                     let arg_start = stack.len() - n;
 
@@ -937,26 +2308,61 @@ impl<'m> Instance<'m> {
                     }
                     stack.truncate(arg_start); // O(1) — just moves the length
 
-                    let result = self.exec(&callee, call_locals)?;
-                    if let Some(v) = result {
+                    if self
+                        .config
+                        .max_call_depth()
+                        .is_some_and(|max| self.depth >= max)
+                    {
+                        trap!(Trap::StackOverflow);
+                    }
+                    self.depth += 1;
+                    let result = self.exec(&callee, call_locals);
+                    self.depth -= 1;
+                    if let Some(v) = check!(result) {
                         stack.push(v);
                     }
                 }
                 Op::CallHost(idx) => {
                     let idx = *idx as usize;
-                    let host = self
-                        .module
-                        .host_funcs
+                    let n = check!(self
+                        .hosts
                         .get(idx)
-                        .ok_or_else(|| Trap::UndefinedImport(format!("host#{idx}")))?;
-                    let n = host.ty.params.len();
+                        .ok_or_else(|| Trap::UndefinedImport(format!("host#{idx}"))))
+                    .n_params;
                     if stack.len() < n {
-                        return Err(Trap::TypeMismatch);
+                        trap!(Trap::TypeMismatch);
                     }
                     let arg_start = stack.len() - n;
 
                     // Fix 3: pass args as slice — zero allocation on hot path.
-                    let result = (host.func)(&stack[arg_start..])?;
+                    let result = check!(self.call_host(idx, &stack[arg_start..]));
+                    stack.truncate(arg_start);
+                    if let Some(v) = result {
+                        stack.push(v);
+                    }
+                }
+
+                // ── Extensions ───────────────────────────────────────
+                Op::Ext { opcode, imm } => {
+                    // Presence was checked at instantiation.
+                    let ext = check!(self.config.extension(*opcode).ok_or_else(|| {
+                        Trap::UnsupportedFeature(format!("extension opcode {opcode:#04x}"))
+                    }));
+                    let n = ext.ty.params.len();
+                    if stack.len() < n {
+                        trap!(Trap::TypeMismatch);
+                    }
+                    let arg_start = stack.len() - n;
+                    // Handlers trust their arguments to match the signature.
+                    let mut args = stack[arg_start..].iter().zip(&ext.ty.params);
+                    if args.any(|(v, &ty)| v.ty() != ty) {
+                        trap!(Trap::TypeMismatch);
+                    }
+                    let result = check!(ext.handler.execute(
+                        *imm,
+                        &stack[arg_start..],
+                        &mut self.memories[0],
+                    ));
                     stack.truncate(arg_start);
                     if let Some(v) = result {
                         stack.push(v);
@@ -967,6 +2373,112 @@ impl<'m> Instance<'m> {
 
         Ok(pf.result_type.and_then(|_| stack.pop()))
     }
+
+    /// Run host function `idx` on `args` for a `CallHost`, with the
+    /// instance's state lent to it through a [`Caller`].
+    pub(crate) fn call_host(&mut self, idx: usize, args: &[Val]) -> Result<Option<Val>> {
+        let host = self
+            .hosts
+            .get(idx)
+            .ok_or_else(|| Trap::UndefinedImport(format!("host#{idx}")))?;
+        let mut caller = Caller {
+            memories: &mut self.memories,
+            data: &mut self.data,
+            rng: &mut self.rng,
+            timers: &mut self.timers,
+            module: &self.module,
+            config: &self.config,
+        };
+        if !self.config.has_event_hooks() {
+            return (host.func)(&mut caller, args);
+        }
+        let import = &self.module.imports[idx];
+        self.config.emit(&Event::HostCallStart { import });
+        let started = Instant::now();
+        let result = (host.func)(&mut caller, args);
+        self.config.emit(&Event::HostCallEnd {
+            import,
+            elapsed: started.elapsed(),
+            trapped: result.is_err(),
+        });
+        result
+    }
+
+    /// Record `pf`, trapped on the op before `pc`, in the trap backtrace
+    /// and pass `trap` on. Kept out of line so each trap site in
+    /// [`exec`](Self::exec) adds only a call to its stack frame.
+    #[cold]
+    #[inline(never)]
+    fn trap_frame(&mut self, pf: &PreparedFunc, pc: usize, trap: Trap) -> Trap {
+        self.push_trap_frame(pf, pc.saturating_sub(1));
+        trap
+    }
+
+    /// Record `pf`, trapped at `op_index`, as the next frame out in the
+    /// trap backtrace.
+    pub(crate) fn push_trap_frame(&mut self, pf: &PreparedFunc, op_index: usize) {
+        let loc = match &self.module.source_map {
+            Some(sm) if !pf.patched => sm.lookup(pf.index, op_index),
+            _ => None,
+        };
+        self.backtrace.push(TrapFrame {
+            func_index: pf.index,
+            op_index,
+            loc,
+        });
+    }
+}
+
+/// What native code needs from the instance running it.
+#[cfg(feature = "cranelift")]
+impl Instance<'_> {
+    /// Run the module's native code, from `slot` once it is filled, from
+    /// the next call on.
+    pub(crate) fn set_native(&mut self, slot: Arc<CodeSlot>) {
+        self.native = Some(slot);
+    }
+
+    /// Compile functions as they get hot, through `tiers`, instead of
+    /// running the code in the native slot.
+    pub(crate) fn set_tiers(&mut self, tiers: Arc<Tiers>) {
+        self.tiers = Some(tiers);
+    }
+
+    pub(crate) fn depth(&self) -> usize {
+        self.depth
+    }
+
+    pub(crate) fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+    }
+
+    /// Whether a deadline or cancellation can interrupt the call in
+    /// progress, so loops and calls need to check.
+    pub(crate) fn is_interruptible(&self) -> bool {
+        self.epoch_deadline.is_some() || self.cancel.is_some() || self.cpu_deadline.is_some()
+    }
+
+    pub(crate) fn config(&self) -> &Arc<RuntimeConfig> {
+        &self.config
+    }
+
+    pub(crate) fn global(&self, idx: usize) -> Option<&Global> {
+        self.globals.get(idx)
+    }
+
+    /// Function `func`, as prepared for this instance.
+    pub(crate) fn prepared_func(&self, func: u32) -> Option<PreparedFunc> {
+        self.prepared.funcs.get(func as usize).cloned()
+    }
+
+    /// Call function `func` from native code, as `Call` does once the
+    /// depth is accounted for.
+    pub(crate) fn call_prepared(&mut self, pf: &PreparedFunc, args: &[Val]) -> Result<Option<Val>> {
+        let mut locals = Vec::with_capacity(args.len() + pf.extra_locals.len());
+        locals.extend_from_slice(args);
+        locals.extend(pf.extra_locals.iter().map(|&ty| Val::default_for(ty)));
+        self.exec(pf, locals)
+    }
 }
 
 fn block_result(bt: &BlockType) -> Option<ValType> {
@@ -9,6 +9,7 @@ pub enum BlockType {
 }
 
 /// The Rune portable IR instruction set.
+#[rustfmt::skip]
 #[derive(Debug, Clone, PartialEq)]
 pub enum Op {
     // ── Constants ────────────────────────────────────────────────────────────
@@ -23,18 +24,27 @@ pub enum Op {
     LocalGet(u32),
     LocalSet(u32),
     LocalTee(u32),
+    /// Push the value of global import `i`.
+    GlobalGet(u32),
+    /// Pop a value into global import `i`, which must be mutable.
+    GlobalSet(u32),
 
     // ── Memory ───────────────────────────────────────────────────────────────
-    I32Load { align: u32, offset: u32 },
-    I32Store { align: u32, offset: u32 },
-    I64Load { align: u32, offset: u32 },
-    I64Store { align: u32, offset: u32 },
-    F32Load { align: u32, offset: u32 },
-    F32Store { align: u32, offset: u32 },
-    F64Load { align: u32, offset: u32 },
-    F64Store { align: u32, offset: u32 },
+    // `memory` indexes the instance's memories; 0 is the default memory.
+    I32Load { align: u32, offset: u32, memory: u32 },
+    I32Store { align: u32, offset: u32, memory: u32 },
+    I64Load { align: u32, offset: u32, memory: u32 },
+    I64Store { align: u32, offset: u32, memory: u32 },
+    F32Load { align: u32, offset: u32, memory: u32 },
+    F32Store { align: u32, offset: u32, memory: u32 },
+    F64Load { align: u32, offset: u32, memory: u32 },
+    F64Store { align: u32, offset: u32, memory: u32 },
     MemorySize,
     MemoryGrow,
+    /// Pop `len` then `addr` (bytes, both page multiples) and zero that
+    /// range, returning its pages to the OS where possible. A range that
+    /// is not whole pages traps with `OutOfBounds`, like one past the end.
+    MemoryDiscard,
 
     // ── i32 arithmetic ───────────────────────────────────────────────────────
     I32Add,
@@ -167,6 +177,302 @@ pub enum Op {
     // ── Calls ────────────────────────────────────────────────────────────────
     Call(u32),     // Index into module's function list
     CallHost(u32), // Index into module's import list
+
+    // ── Extensions ───────────────────────────────────────────────────────────
+    /// Embedder-defined op; `opcode` is in 0xE0..=0xFF and its meaning comes
+    /// from the `ExtensionOp` registered for it in the `RuntimeConfig`.
+    Ext { opcode: u8, imm: u32 },
+}
+
+impl Op {
+    /// The op's name in text form, following Wasm conventions (`i32.add`,
+    /// `local.get`, ...). Used by the JSON format and the disassembler.
+    pub fn mnemonic(&self) -> &'static str {
+        match self {
+            Op::I32Const(_) => "i32.const",
+            Op::I64Const(_) => "i64.const",
+            Op::F32Const(_) => "f32.const",
+            Op::F64Const(_) => "f64.const",
+            Op::Drop => "drop",
+            Op::Select => "select",
+            Op::LocalGet(_) => "local.get",
+            Op::LocalSet(_) => "local.set",
+            Op::LocalTee(_) => "local.tee",
+            Op::GlobalGet(_) => "global.get",
+            Op::GlobalSet(_) => "global.set",
+            Op::I32Load { .. } => "i32.load",
+            Op::I32Store { .. } => "i32.store",
+            Op::I64Load { .. } => "i64.load",
+            Op::I64Store { .. } => "i64.store",
+            Op::F32Load { .. } => "f32.load",
+            Op::F32Store { .. } => "f32.store",
+            Op::F64Load { .. } => "f64.load",
+            Op::F64Store { .. } => "f64.store",
+            Op::MemorySize => "memory.size",
+            Op::MemoryGrow => "memory.grow",
+            Op::MemoryDiscard => "memory.discard",
+            Op::I32Add => "i32.add",
+            Op::I32Sub => "i32.sub",
+            Op::I32Mul => "i32.mul",
+            Op::I32DivS => "i32.div_s",
+            Op::I32DivU => "i32.div_u",
+            Op::I32RemS => "i32.rem_s",
+            Op::I32RemU => "i32.rem_u",
+            Op::I32And => "i32.and",
+            Op::I32Or => "i32.or",
+            Op::I32Xor => "i32.xor",
+            Op::I32Shl => "i32.shl",
+            Op::I32ShrS => "i32.shr_s",
+            Op::I32ShrU => "i32.shr_u",
+            Op::I32Clz => "i32.clz",
+            Op::I32Ctz => "i32.ctz",
+            Op::I32Popcnt => "i32.popcnt",
+            Op::I32Eqz => "i32.eqz",
+            Op::I64Add => "i64.add",
+            Op::I64Sub => "i64.sub",
+            Op::I64Mul => "i64.mul",
+            Op::I64DivS => "i64.div_s",
+            Op::I64DivU => "i64.div_u",
+            Op::I64RemS => "i64.rem_s",
+            Op::I64RemU => "i64.rem_u",
+            Op::I64And => "i64.and",
+            Op::I64Or => "i64.or",
+            Op::I64Xor => "i64.xor",
+            Op::I64Shl => "i64.shl",
+            Op::I64ShrS => "i64.shr_s",
+            Op::I64ShrU => "i64.shr_u",
+            Op::I64Eqz => "i64.eqz",
+            Op::F32Add => "f32.add",
+            Op::F32Sub => "f32.sub",
+            Op::F32Mul => "f32.mul",
+            Op::F32Div => "f32.div",
+            Op::F32Sqrt => "f32.sqrt",
+            Op::F32Min => "f32.min",
+            Op::F32Max => "f32.max",
+            Op::F32Abs => "f32.abs",
+            Op::F32Neg => "f32.neg",
+            Op::F32Ceil => "f32.ceil",
+            Op::F32Floor => "f32.floor",
+            Op::F64Add => "f64.add",
+            Op::F64Sub => "f64.sub",
+            Op::F64Mul => "f64.mul",
+            Op::F64Div => "f64.div",
+            Op::F64Sqrt => "f64.sqrt",
+            Op::F64Min => "f64.min",
+            Op::F64Max => "f64.max",
+            Op::F64Abs => "f64.abs",
+            Op::F64Neg => "f64.neg",
+            Op::F64Ceil => "f64.ceil",
+            Op::F64Floor => "f64.floor",
+            Op::I32Eq => "i32.eq",
+            Op::I32Ne => "i32.ne",
+            Op::I32LtS => "i32.lt_s",
+            Op::I32LtU => "i32.lt_u",
+            Op::I32GtS => "i32.gt_s",
+            Op::I32GtU => "i32.gt_u",
+            Op::I32LeS => "i32.le_s",
+            Op::I32LeU => "i32.le_u",
+            Op::I32GeS => "i32.ge_s",
+            Op::I32GeU => "i32.ge_u",
+            Op::I64Eq => "i64.eq",
+            Op::I64Ne => "i64.ne",
+            Op::I64LtS => "i64.lt_s",
+            Op::I64LtU => "i64.lt_u",
+            Op::I64GtS => "i64.gt_s",
+            Op::I64GtU => "i64.gt_u",
+            Op::I64LeS => "i64.le_s",
+            Op::I64LeU => "i64.le_u",
+            Op::I64GeS => "i64.ge_s",
+            Op::I64GeU => "i64.ge_u",
+            Op::F32Eq => "f32.eq",
+            Op::F32Ne => "f32.ne",
+            Op::F32Lt => "f32.lt",
+            Op::F32Gt => "f32.gt",
+            Op::F32Le => "f32.le",
+            Op::F32Ge => "f32.ge",
+            Op::F64Eq => "f64.eq",
+            Op::F64Ne => "f64.ne",
+            Op::F64Lt => "f64.lt",
+            Op::F64Gt => "f64.gt",
+            Op::F64Le => "f64.le",
+            Op::F64Ge => "f64.ge",
+            Op::I32WrapI64 => "i32.wrap_i64",
+            Op::I64ExtendI32S => "i64.extend_i32_s",
+            Op::I64ExtendI32U => "i64.extend_i32_u",
+            Op::F32ConvertI32S => "f32.convert_i32_s",
+            Op::F32ConvertI32U => "f32.convert_i32_u",
+            Op::F64ConvertI32S => "f64.convert_i32_s",
+            Op::F64ConvertI32U => "f64.convert_i32_u",
+            Op::F64ConvertI64S => "f64.convert_i64_s",
+            Op::F64ConvertI64U => "f64.convert_i64_u",
+            Op::I32TruncF32S => "i32.trunc_f32_s",
+            Op::I32TruncF32U => "i32.trunc_f32_u",
+            Op::I32TruncF64S => "i32.trunc_f64_s",
+            Op::I32TruncF64U => "i32.trunc_f64_u",
+            Op::F32DemoteF64 => "f32.demote_f64",
+            Op::F64PromoteF32 => "f64.promote_f32",
+            Op::I32ReinterpretF32 => "i32.reinterpret_f32",
+            Op::F32ReinterpretI32 => "f32.reinterpret_i32",
+            Op::I64ReinterpretF64 => "i64.reinterpret_f64",
+            Op::F64ReinterpretI64 => "f64.reinterpret_i64",
+            Op::Nop => "nop",
+            Op::Unreachable => "unreachable",
+            Op::Block(_) => "block",
+            Op::Loop(_) => "loop",
+            Op::If(_) => "if",
+            Op::Else => "else",
+            Op::End => "end",
+            Op::Br(_) => "br",
+            Op::BrIf(_) => "br_if",
+            Op::Return => "return",
+            Op::Call(_) => "call",
+            Op::CallHost(_) => "call_host",
+            Op::Ext { .. } => "ext",
+        }
+    }
+
+    /// The memory a load or store names; `None` for other ops.
+    pub(crate) fn memory_index(&self) -> Option<u32> {
+        match self {
+            Op::I32Load { memory, .. }
+            | Op::I32Store { memory, .. }
+            | Op::I64Load { memory, .. }
+            | Op::I64Store { memory, .. }
+            | Op::F32Load { memory, .. }
+            | Op::F32Store { memory, .. }
+            | Op::F64Load { memory, .. }
+            | Op::F64Store { memory, .. } => Some(*memory),
+            _ => None,
+        }
+    }
+
+    /// The operands a plain op pops, the last on top, and the result it
+    /// pushes: for every op whose effect on the stack depends on nothing
+    /// but the op itself. `None` for locals, globals, control flow and
+    /// calls.
+    pub(crate) fn signature(&self) -> Option<(&'static [ValType], Option<ValType>)> {
+        use ValType::{F32, F64, I32, I64};
+        Some(match self {
+            Op::I32Const(_) | Op::MemorySize => (&[], Some(I32)),
+            Op::I64Const(_) => (&[], Some(I64)),
+            Op::F32Const(_) => (&[], Some(F32)),
+            Op::F64Const(_) => (&[], Some(F64)),
+
+            Op::I32Load { .. } => (&[I32], Some(I32)),
+            Op::I64Load { .. } => (&[I32], Some(I64)),
+            Op::F32Load { .. } => (&[I32], Some(F32)),
+            Op::F64Load { .. } => (&[I32], Some(F64)),
+            Op::I32Store { .. } => (&[I32, I32], None),
+            Op::I64Store { .. } => (&[I32, I64], None),
+            Op::F32Store { .. } => (&[I32, F32], None),
+            Op::F64Store { .. } => (&[I32, F64], None),
+            Op::MemoryGrow => (&[I32], Some(I32)),
+            Op::MemoryDiscard => (&[I32, I32], None),
+
+            Op::I32Add
+            | Op::I32Sub
+            | Op::I32Mul
+            | Op::I32DivS
+            | Op::I32DivU
+            | Op::I32RemS
+            | Op::I32RemU
+            | Op::I32And
+            | Op::I32Or
+            | Op::I32Xor
+            | Op::I32Shl
+            | Op::I32ShrS
+            | Op::I32ShrU
+            | Op::I32Eq
+            | Op::I32Ne
+            | Op::I32LtS
+            | Op::I32LtU
+            | Op::I32GtS
+            | Op::I32GtU
+            | Op::I32LeS
+            | Op::I32LeU
+            | Op::I32GeS
+            | Op::I32GeU => (&[I32, I32], Some(I32)),
+            Op::I32Clz | Op::I32Ctz | Op::I32Popcnt | Op::I32Eqz => (&[I32], Some(I32)),
+            Op::I64Add
+            | Op::I64Sub
+            | Op::I64Mul
+            | Op::I64DivS
+            | Op::I64DivU
+            | Op::I64RemS
+            | Op::I64RemU
+            | Op::I64And
+            | Op::I64Or
+            | Op::I64Xor
+            | Op::I64Shl
+            | Op::I64ShrS
+            | Op::I64ShrU => (&[I64, I64], Some(I64)),
+            Op::I64Eq
+            | Op::I64Ne
+            | Op::I64LtS
+            | Op::I64LtU
+            | Op::I64GtS
+            | Op::I64GtU
+            | Op::I64LeS
+            | Op::I64LeU
+            | Op::I64GeS
+            | Op::I64GeU => (&[I64, I64], Some(I32)),
+            Op::I64Eqz => (&[I64], Some(I32)),
+            Op::F32Add | Op::F32Sub | Op::F32Mul | Op::F32Div | Op::F32Min | Op::F32Max => {
+                (&[F32, F32], Some(F32))
+            }
+            Op::F32Sqrt | Op::F32Abs | Op::F32Neg | Op::F32Ceil | Op::F32Floor => {
+                (&[F32], Some(F32))
+            }
+            Op::F64Add | Op::F64Sub | Op::F64Mul | Op::F64Div | Op::F64Min | Op::F64Max => {
+                (&[F64, F64], Some(F64))
+            }
+            Op::F64Sqrt | Op::F64Abs | Op::F64Neg | Op::F64Ceil | Op::F64Floor => {
+                (&[F64], Some(F64))
+            }
+            Op::F32Eq | Op::F32Ne | Op::F32Lt | Op::F32Gt | Op::F32Le | Op::F32Ge => {
+                (&[F32, F32], Some(I32))
+            }
+            Op::F64Eq | Op::F64Ne | Op::F64Lt | Op::F64Gt | Op::F64Le | Op::F64Ge => {
+                (&[F64, F64], Some(I32))
+            }
+
+            Op::I32WrapI64 => (&[I64], Some(I32)),
+            Op::I64ExtendI32S | Op::I64ExtendI32U => (&[I32], Some(I64)),
+            Op::F32ConvertI32S | Op::F32ConvertI32U => (&[I32], Some(F32)),
+            Op::F64ConvertI32S | Op::F64ConvertI32U => (&[I32], Some(F64)),
+            Op::F64ConvertI64S | Op::F64ConvertI64U => (&[I64], Some(F64)),
+            Op::I32TruncF32S | Op::I32TruncF32U => (&[F32], Some(I32)),
+            Op::I32TruncF64S | Op::I32TruncF64U => (&[F64], Some(I32)),
+            Op::F32DemoteF64 => (&[F64], Some(F32)),
+            Op::F64PromoteF32 => (&[F32], Some(F64)),
+            Op::I32ReinterpretF32 => (&[F32], Some(I32)),
+            Op::F32ReinterpretI32 => (&[I32], Some(F32)),
+            Op::I64ReinterpretF64 => (&[F64], Some(I64)),
+            Op::F64ReinterpretI64 => (&[I64], Some(F64)),
+
+            Op::Nop => (&[], None),
+
+            Op::Drop
+            | Op::Select
+            | Op::LocalGet(_)
+            | Op::LocalSet(_)
+            | Op::LocalTee(_)
+            | Op::GlobalGet(_)
+            | Op::GlobalSet(_)
+            | Op::Unreachable
+            | Op::Block(_)
+            | Op::Loop(_)
+            | Op::If(_)
+            | Op::Else
+            | Op::End
+            | Op::Br(_)
+            | Op::BrIf(_)
+            | Op::Return
+            | Op::Call(_)
+            | Op::CallHost(_)
+            | Op::Ext { .. } => return None,
+        })
+    }
 }
 
 /// A compiled function (sequence of ops + metadata).
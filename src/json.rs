@@ -0,0 +1,1033 @@
+//! JSON import/export of modules, for tooling.
+//!
+//! This is a human-readable mirror of the binary format intended for
+//! inspectors, test generators and frontends written in other languages. It
+//! is not a replacement for `.rune` files: it is larger and slower to load.
+//!
+//! # Schema (version 1)
+//!
+//! ```text
+//! {
+//!   "version": 1,
+//!   "memory": { "initial": 1, "max": null },
+//!   "features": [],
+//!   "functions": [
+//!     {
+//!       "name": "add",
+//!       "params": ["i32", "i32"],
+//!       "results": ["i32"],
+//!       "locals": [],
+//!       "body": [
+//!         { "op": "local.get", "index": 0 },
+//!         { "op": "local.get", "index": 1 },
+//!         { "op": "i32.add" },
+//!         { "op": "return" }
+//!       ]
+//!     }
+//!   ],
+//!   "exports": [{ "name": "add", "function": 0 }],
+//!   "imports": [{ "module": "env", "name": "log", "params": ["i32"], "results": [] }],
+//!   "globals": [{ "module": "config", "name": "max_items", "type": "i32", "mutable": false }],
+//!   "host": { "api_version": "1.2", "capabilities": ["fs"] },
+//!   "data": [{ "offset": 0, "bytes": "68656c6c6f" }],
+//!   "external_data": [{ "offset": 64, "len": 4096, "sha256": "9f86d0…" }],
+//!   "source_map": {
+//!     "files": ["add.c"],
+//!     "functions": [[{ "op": 0, "file": 0, "line": 3, "column": 5 }]]
+//!   }
+//! }
+//! ```
+//!
+//! Each op is an object whose `"op"` field is its mnemonic (see
+//! [`Op::mnemonic`]). Ops with operands carry them as extra fields:
+//!
+//! | ops                                  | fields                          |
+//! |--------------------------------------|---------------------------------|
+//! | `i32.const`, `i64.const`             | `value` (integer)               |
+//! | `f32.const`, `f64.const`             | `value` (number) or `bits`      |
+//! | `local.get`, `local.set`, `local.tee`| `index`                         |
+//! | `block`, `loop`, `if`                | `result` (value type, optional) |
+//! | `br`, `br_if`                        | `depth`                         |
+//! | `call`                               | `function`                      |
+//! | `call_host`                          | `host`                          |
+//! | `global.get`, `global.set`           | `global`                        |
+//! | `*.load`, `*.store`                  | `align`, `offset`               |
+//! | `ext`                                | `opcode`, `imm`                 |
+//!
+//! Integers are written exactly, including full-range `i64` values; readers
+//! in languages with 53-bit numbers must parse them as big integers. Float
+//! constants that are NaN or infinite have no JSON number form and are
+//! written as their raw IEEE-754 bit pattern in `bits` instead of `value`.
+//! `data` bytes and `sha256` digests are lowercase hex. `features` lists
+//! required feature names (see [`Features::from_name`]). `host` holds the
+//! module's [`HostRequirements`], with the version as `"major.minor"`.
+//! `max`, `features`, `imports`, `globals`, a global's `mutable`, `host`,
+//! its `api_version` and `capabilities`, `source_map` and a block's `result`
+//! may be `null` or omitted.
+//!
+//! [`HostRequirements`]: crate::compat::HostRequirements
+//!
+//! Imports and globals are only their declarations; the host functions and
+//! values behind them are defined on a `Linker` by the embedder, exactly as
+//! with the binary format.
+
+use crate::{
+    compat::{ApiVersion, HostRequirements},
+    extension::{EXT_OPCODE_FIRST, EXT_OPCODE_LAST},
+    features::Features,
+    hash::to_hex,
+    ir::{BlockType, Function, Op},
+    module::{
+        implied_features, ExternalSegment, GlobalImport, Import, Module, MAX_ALIGN, SIMPLE_OPS,
+    },
+    sourcemap::{SourceLoc, SourceMap},
+    trap::{Result, Trap},
+    types::{FuncType, ValType},
+};
+
+/// Schema version written by [`Module::to_json`].
+pub const JSON_VERSION: u32 = 1;
+
+/// Maximum nesting depth accepted by the parser.
+const MAX_DEPTH: usize = 64;
+
+impl Module {
+    /// Render the module as JSON using the schema documented in [`crate::json`].
+    pub fn to_json(&self) -> String {
+        let functions = self.functions.iter().map(function_to_json).collect();
+        let exports = self
+            .exports
+            .iter()
+            .map(|(name, idx)| obj(vec![("name", text(name)), ("function", num(idx))]))
+            .collect();
+        let data = self
+            .data_segments
+            .iter()
+            .map(|(offset, bytes)| obj(vec![("offset", num(offset)), ("bytes", text(&hex(bytes)))]))
+            .collect();
+        let external = self
+            .external_segments
+            .iter()
+            .map(|seg| {
+                obj(vec![
+                    ("offset", num(seg.offset)),
+                    ("len", num(seg.len)),
+                    ("sha256", text(&to_hex(&seg.hash))),
+                ])
+            })
+            .collect();
+        let imports = self.imports.iter().map(import_to_json).collect();
+        let globals = self.global_imports.iter().map(global_to_json).collect();
+        let source_map = match &self.source_map {
+            Some(sm) => source_map_to_json(sm),
+            None => Json::Null,
+        };
+        let host = if self.host_requirements.is_empty() {
+            Json::Null
+        } else {
+            host_requirements_to_json(&self.host_requirements)
+        };
+        let root = obj(vec![
+            ("version", num(JSON_VERSION)),
+            (
+                "memory",
+                obj(vec![
+                    ("initial", num(self.initial_memory_pages)),
+                    ("max", self.max_memory_pages.map_or(Json::Null, num)),
+                ]),
+            ),
+            (
+                "features",
+                Json::Arr(
+                    self.required_features
+                        .names()
+                        .iter()
+                        .map(|n| text(n))
+                        .collect(),
+                ),
+            ),
+            ("functions", Json::Arr(functions)),
+            ("exports", Json::Arr(exports)),
+            ("imports", Json::Arr(imports)),
+            ("globals", Json::Arr(globals)),
+            ("host", host),
+            ("data", Json::Arr(data)),
+            ("external_data", Json::Arr(external)),
+            ("source_map", source_map),
+        ]);
+        let mut out = String::new();
+        root.write(&mut out, 0);
+        out.push('\n');
+        out
+    }
+
+    /// Parse a module from JSON produced by [`Module::to_json`] or another tool.
+    pub fn from_json(text: &str) -> Result<Module> {
+        let root = Parser::new(text).parse_document()?;
+        let version = root.field("version")?.as_u32()?;
+        if version != JSON_VERSION {
+            return Err(err(format!("unsupported version {version}")));
+        }
+
+        let mut module = Module::new();
+        if let Some(memory) = root.opt_field("memory") {
+            module.initial_memory_pages = memory.field("initial")?.as_u32()? as usize;
+            module.max_memory_pages = match memory.opt_field("max") {
+                Some(max) => Some(max.as_u32()? as usize),
+                None => None,
+            };
+        }
+        for name in opt_arr(&root, "features")? {
+            let name = name.as_str()?;
+            module.required_features |= Features::from_name(name)
+                .ok_or_else(|| err(format!("unknown feature `{name}`")))?;
+        }
+        for f in root.field("functions")?.as_arr()? {
+            module.functions.push(function_from_json(f)?);
+        }
+        for e in opt_arr(&root, "exports")? {
+            let name = e.field("name")?.as_str()?.to_string();
+            module.exports.push((name, e.field("function")?.as_u32()?));
+        }
+        for i in opt_arr(&root, "imports")? {
+            module.imports.push(import_from_json(i)?);
+        }
+        for g in opt_arr(&root, "globals")? {
+            module.global_imports.push(global_from_json(g)?);
+        }
+        if let Some(host) = root.opt_field("host") {
+            module.host_requirements = host_requirements_from_json(host)?;
+        }
+        for d in opt_arr(&root, "data")? {
+            let offset = d.field("offset")?.as_u32()?;
+            module
+                .data_segments
+                .push((offset, unhex(d.field("bytes")?.as_str()?)?));
+        }
+        for d in opt_arr(&root, "external_data")? {
+            let hash: [u8; 32] = unhex(d.field("sha256")?.as_str()?)?
+                .try_into()
+                .map_err(|_| err("sha256 must be 32 bytes".into()))?;
+            module.external_segments.push(ExternalSegment {
+                offset: d.field("offset")?.as_u32()?,
+                len: d.field("len")?.as_u32()?,
+                hash,
+            });
+        }
+        if let Some(sm) = root.opt_field("source_map") {
+            module.source_map = Some(source_map_from_json(sm)?);
+        }
+        module.required_features |= implied_features(&module.functions);
+        Ok(module)
+    }
+}
+
+// ── Module ↔ JSON tree ───────────────────────────────────────────────────────
+
+fn function_to_json(f: &Function) -> Json {
+    let types = |tys: &[ValType]| Json::Arr(tys.iter().map(|t| text(val_type_name(*t))).collect());
+    obj(vec![
+        ("name", text(&f.name)),
+        ("params", types(&f.ty.params)),
+        ("results", types(&f.ty.results)),
+        ("locals", types(&f.locals)),
+        ("body", Json::Arr(f.body.iter().map(op_to_json).collect())),
+    ])
+}
+
+fn function_from_json(f: &Json) -> Result<Function> {
+    let ty = func_type_from_json(f)?;
+    let types = |key: &str| -> Result<Vec<ValType>> {
+        f.field(key)?
+            .as_arr()?
+            .iter()
+            .map(|t| parse_val_type(t.as_str()?))
+            .collect()
+    };
+    let locals = match f.opt_field("locals") {
+        Some(_) => types("locals")?,
+        None => Vec::new(),
+    };
+    let body = f
+        .field("body")?
+        .as_arr()?
+        .iter()
+        .map(op_from_json)
+        .collect::<Result<Vec<_>>>()?;
+    Ok(Function::new(f.field("name")?.as_str()?, ty, locals, body))
+}
+
+fn import_to_json(i: &Import) -> Json {
+    let types = |tys: &[ValType]| Json::Arr(tys.iter().map(|t| text(val_type_name(*t))).collect());
+    obj(vec![
+        ("module", text(&i.module)),
+        ("name", text(&i.name)),
+        ("params", types(&i.ty.params)),
+        ("results", types(&i.ty.results)),
+    ])
+}
+
+fn import_from_json(i: &Json) -> Result<Import> {
+    Ok(Import {
+        module: i.field("module")?.as_str()?.to_string(),
+        name: i.field("name")?.as_str()?.to_string(),
+        ty: func_type_from_json(i)?,
+    })
+}
+
+fn global_to_json(g: &GlobalImport) -> Json {
+    obj(vec![
+        ("module", text(&g.module)),
+        ("name", text(&g.name)),
+        ("type", text(val_type_name(g.ty))),
+        ("mutable", Json::Bool(g.mutable)),
+    ])
+}
+
+fn global_from_json(g: &Json) -> Result<GlobalImport> {
+    Ok(GlobalImport {
+        module: g.field("module")?.as_str()?.to_string(),
+        name: g.field("name")?.as_str()?.to_string(),
+        ty: parse_val_type(g.field("type")?.as_str()?)?,
+        mutable: match g.opt_field("mutable") {
+            Some(m) => m.as_bool()?,
+            None => false,
+        },
+    })
+}
+
+/// The `params` and `results` fields of a function or import.
+fn host_requirements_to_json(req: &HostRequirements) -> Json {
+    obj(vec![
+        (
+            "api_version",
+            req.api_version.map_or(Json::Null, |v| text(&v.to_string())),
+        ),
+        (
+            "capabilities",
+            Json::Arr(req.capabilities.iter().map(|c| text(c)).collect()),
+        ),
+    ])
+}
+
+fn host_requirements_from_json(j: &Json) -> Result<HostRequirements> {
+    let api_version = match j.opt_field("api_version") {
+        Some(v) => {
+            let v = v.as_str()?;
+            let parsed: ApiVersion = v
+                .parse()
+                .map_err(|_| err(format!("invalid api_version `{v}`")))?;
+            Some(parsed)
+        }
+        None => None,
+    };
+    let capabilities = opt_arr(j, "capabilities")?
+        .iter()
+        .map(|c| Ok(c.as_str()?.to_string()))
+        .collect::<Result<_>>()?;
+    Ok(HostRequirements {
+        api_version,
+        capabilities,
+    })
+}
+
+fn func_type_from_json(j: &Json) -> Result<FuncType> {
+    let types = |key: &str| -> Result<Vec<ValType>> {
+        j.field(key)?
+            .as_arr()?
+            .iter()
+            .map(|t| parse_val_type(t.as_str()?))
+            .collect()
+    };
+    Ok(FuncType {
+        params: types("params")?,
+        results: types("results")?,
+    })
+}
+
+fn op_to_json(op: &Op) -> Json {
+    let mut fields = vec![("op", text(op.mnemonic()))];
+    match op {
+        Op::I32Const(v) => fields.push(("value", num(v))),
+        Op::I64Const(v) => fields.push(("value", num(v))),
+        Op::F32Const(v) if v.is_finite() => fields.push(("value", Json::Num(format!("{v:?}")))),
+        Op::F32Const(v) => fields.push(("bits", num(v.to_bits()))),
+        Op::F64Const(v) if v.is_finite() => fields.push(("value", Json::Num(format!("{v:?}")))),
+        Op::F64Const(v) => fields.push(("bits", num(v.to_bits()))),
+        Op::LocalGet(i) | Op::LocalSet(i) | Op::LocalTee(i) => fields.push(("index", num(i))),
+        Op::Block(bt) | Op::Loop(bt) | Op::If(bt) => {
+            if let BlockType::Val(t) = bt {
+                fields.push(("result", text(val_type_name(*t))));
+            }
+        }
+        Op::Br(d) | Op::BrIf(d) => fields.push(("depth", num(d))),
+        Op::Call(i) => fields.push(("function", num(i))),
+        Op::CallHost(i) => fields.push(("host", num(i))),
+        Op::GlobalGet(i) | Op::GlobalSet(i) => fields.push(("global", num(i))),
+        Op::Ext { opcode, imm } => {
+            fields.push(("opcode", num(opcode)));
+            fields.push(("imm", num(imm)));
+        }
+        Op::I32Load {
+            align,
+            offset,
+            memory,
+        }
+        | Op::I32Store {
+            align,
+            offset,
+            memory,
+        }
+        | Op::I64Load {
+            align,
+            offset,
+            memory,
+        }
+        | Op::I64Store {
+            align,
+            offset,
+            memory,
+        }
+        | Op::F32Load {
+            align,
+            offset,
+            memory,
+        }
+        | Op::F32Store {
+            align,
+            offset,
+            memory,
+        }
+        | Op::F64Load {
+            align,
+            offset,
+            memory,
+        }
+        | Op::F64Store {
+            align,
+            offset,
+            memory,
+        } => {
+            fields.push(("align", num(align)));
+            fields.push(("offset", num(offset)));
+            if *memory != 0 {
+                fields.push(("memory", num(memory)));
+            }
+        }
+        _ => {}
+    }
+    obj(fields)
+}
+
+fn op_from_json(j: &Json) -> Result<Op> {
+    let name = j.field("op")?.as_str()?;
+    let u32_field = |key: &str| j.field(key)?.as_u32();
+    let block_type = || -> Result<BlockType> {
+        match j.opt_field("result") {
+            Some(t) => Ok(BlockType::Val(parse_val_type(t.as_str()?)?)),
+            None => Ok(BlockType::Empty),
+        }
+    };
+    let mem = |make: fn(u32, u32, u32) -> Op| -> Result<Op> {
+        let align = match j.opt_field("align") {
+            Some(a) => a.as_u32()?,
+            None => 0,
+        };
+        if align > MAX_ALIGN {
+            return Err(err(format!("align {align} out of range")));
+        }
+        let offset = match j.opt_field("offset") {
+            Some(o) => o.as_u32()?,
+            None => 0,
+        };
+        let memory = match j.opt_field("memory") {
+            Some(m) => m.as_u32()?,
+            None => 0,
+        };
+        Ok(make(align, offset, memory))
+    };
+    let op = match name {
+        "i32.const" => Op::I32Const(j.field("value")?.parse()?),
+        "i64.const" => Op::I64Const(j.field("value")?.parse()?),
+        "f32.const" => Op::F32Const(match j.opt_field("bits") {
+            Some(bits) => f32::from_bits(bits.as_u32()?),
+            None => j.field("value")?.parse()?,
+        }),
+        "f64.const" => Op::F64Const(match j.opt_field("bits") {
+            Some(bits) => f64::from_bits(bits.parse()?),
+            None => j.field("value")?.parse()?,
+        }),
+        "local.get" => Op::LocalGet(u32_field("index")?),
+        "local.set" => Op::LocalSet(u32_field("index")?),
+        "local.tee" => Op::LocalTee(u32_field("index")?),
+        "block" => Op::Block(block_type()?),
+        "loop" => Op::Loop(block_type()?),
+        "if" => Op::If(block_type()?),
+        "br" => Op::Br(u32_field("depth")?),
+        "br_if" => Op::BrIf(u32_field("depth")?),
+        "call" => Op::Call(u32_field("function")?),
+        "call_host" => Op::CallHost(u32_field("host")?),
+        "global.get" => Op::GlobalGet(u32_field("global")?),
+        "global.set" => Op::GlobalSet(u32_field("global")?),
+        "ext" => {
+            let opcode: u8 = j.field("opcode")?.parse()?;
+            if !(EXT_OPCODE_FIRST..=EXT_OPCODE_LAST).contains(&opcode) {
+                return Err(err(format!("extension opcode {opcode:#04x} out of range")));
+            }
+            Op::Ext {
+                opcode,
+                imm: u32_field("imm")?,
+            }
+        }
+        "i32.load" => mem(|align, offset, memory| Op::I32Load {
+            align,
+            offset,
+            memory,
+        })?,
+        "i32.store" => mem(|align, offset, memory| Op::I32Store {
+            align,
+            offset,
+            memory,
+        })?,
+        "i64.load" => mem(|align, offset, memory| Op::I64Load {
+            align,
+            offset,
+            memory,
+        })?,
+        "i64.store" => mem(|align, offset, memory| Op::I64Store {
+            align,
+            offset,
+            memory,
+        })?,
+        "f32.load" => mem(|align, offset, memory| Op::F32Load {
+            align,
+            offset,
+            memory,
+        })?,
+        "f32.store" => mem(|align, offset, memory| Op::F32Store {
+            align,
+            offset,
+            memory,
+        })?,
+        "f64.load" => mem(|align, offset, memory| Op::F64Load {
+            align,
+            offset,
+            memory,
+        })?,
+        "f64.store" => mem(|align, offset, memory| Op::F64Store {
+            align,
+            offset,
+            memory,
+        })?,
+        _ => SIMPLE_OPS
+            .iter()
+            .find(|op| op.mnemonic() == name)
+            .cloned()
+            .ok_or_else(|| err(format!("unknown op `{name}`")))?,
+    };
+    Ok(op)
+}
+
+fn source_map_to_json(sm: &SourceMap) -> Json {
+    let files = sm.files.iter().map(|f| text(f)).collect();
+    let functions = sm
+        .functions
+        .iter()
+        .map(|table| {
+            Json::Arr(
+                table
+                    .iter()
+                    .map(|(op, loc)| {
+                        obj(vec![
+                            ("op", num(op)),
+                            ("file", num(loc.file)),
+                            ("line", num(loc.line)),
+                            ("column", num(loc.column)),
+                        ])
+                    })
+                    .collect(),
+            )
+        })
+        .collect();
+    obj(vec![
+        ("files", Json::Arr(files)),
+        ("functions", Json::Arr(functions)),
+    ])
+}
+
+fn source_map_from_json(j: &Json) -> Result<SourceMap> {
+    let mut sm = SourceMap::new();
+    for f in j.field("files")?.as_arr()? {
+        sm.files.push(f.as_str()?.to_string());
+    }
+    for table in j.field("functions")?.as_arr()? {
+        let entries = table
+            .as_arr()?
+            .iter()
+            .map(|e| {
+                let loc = SourceLoc {
+                    file: e.field("file")?.as_u32()?,
+                    line: e.field("line")?.as_u32()?,
+                    column: e.field("column")?.as_u32()?,
+                };
+                Ok((e.field("op")?.as_u32()?, loc))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        sm.functions.push(entries);
+    }
+    Ok(sm)
+}
+
+fn val_type_name(t: ValType) -> &'static str {
+    match t {
+        ValType::I32 => "i32",
+        ValType::I64 => "i64",
+        ValType::F32 => "f32",
+        ValType::F64 => "f64",
+    }
+}
+
+fn parse_val_type(s: &str) -> Result<ValType> {
+    match s {
+        "i32" => Ok(ValType::I32),
+        "i64" => Ok(ValType::I64),
+        "f32" => Ok(ValType::F32),
+        "f64" => Ok(ValType::F64),
+        _ => Err(err(format!("unknown value type `{s}`"))),
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+fn unhex(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return Err(err("hex string has odd length".into()));
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| {
+            s.get(i..i + 2)
+                .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+                .ok_or_else(|| err(format!("invalid hex `{s}`")))
+        })
+        .collect()
+}
+
+fn err(msg: String) -> Trap {
+    Trap::InvalidModule(format!("json: {msg}"))
+}
+
+fn opt_arr<'a>(j: &'a Json, key: &str) -> Result<&'a [Json]> {
+    match j.opt_field(key) {
+        Some(v) => v.as_arr(),
+        None => Ok(&[]),
+    }
+}
+
+// ── JSON values ──────────────────────────────────────────────────────────────
+
+/// A parsed JSON value. Numbers keep their source text so integers of any
+/// width round-trip exactly.
+#[derive(Debug)]
+enum Json {
+    Null,
+    Bool(bool),
+    Num(String),
+    Str(String),
+    Arr(Vec<Json>),
+    Obj(Vec<(String, Json)>),
+}
+
+fn obj(fields: Vec<(&str, Json)>) -> Json {
+    Json::Obj(
+        fields
+            .into_iter()
+            .map(|(k, v)| (k.to_string(), v))
+            .collect(),
+    )
+}
+
+fn text(s: &str) -> Json {
+    Json::Str(s.to_string())
+}
+
+fn num(n: impl std::fmt::Display) -> Json {
+    Json::Num(n.to_string())
+}
+
+impl Json {
+    /// `null` counts as absent.
+    fn opt_field(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Obj(fields) => fields
+                .iter()
+                .find(|(k, _)| k == key)
+                .map(|(_, v)| v)
+                .filter(|v| !matches!(v, Json::Null)),
+            _ => None,
+        }
+    }
+
+    fn field(&self, key: &str) -> Result<&Json> {
+        if !matches!(self, Json::Obj(_)) {
+            return Err(err(format!("expected an object with field `{key}`")));
+        }
+        self.opt_field(key)
+            .ok_or_else(|| err(format!("missing field `{key}`")))
+    }
+
+    fn as_str(&self) -> Result<&str> {
+        match self {
+            Json::Str(s) => Ok(s),
+            other => Err(err(format!("expected a string, found {}", other.kind()))),
+        }
+    }
+
+    fn as_bool(&self) -> Result<bool> {
+        match self {
+            Json::Bool(b) => Ok(*b),
+            other => Err(err(format!("expected a boolean, found {}", other.kind()))),
+        }
+    }
+
+    fn as_arr(&self) -> Result<&[Json]> {
+        match self {
+            Json::Arr(items) => Ok(items),
+            other => Err(err(format!("expected an array, found {}", other.kind()))),
+        }
+    }
+
+    fn as_u32(&self) -> Result<u32> {
+        self.parse()
+    }
+
+    fn parse<T: std::str::FromStr>(&self) -> Result<T> {
+        match self {
+            Json::Num(n) => n
+                .parse()
+                .map_err(|_| err(format!("number `{n}` out of range"))),
+            other => Err(err(format!("expected a number, found {}", other.kind()))),
+        }
+    }
+
+    fn kind(&self) -> &'static str {
+        match self {
+            Json::Null => "null",
+            Json::Bool(_) => "a boolean",
+            Json::Num(_) => "a number",
+            Json::Str(_) => "a string",
+            Json::Arr(_) => "an array",
+            Json::Obj(_) => "an object",
+        }
+    }
+
+    fn is_scalar(&self) -> bool {
+        !matches!(self, Json::Arr(_) | Json::Obj(_))
+    }
+
+    /// Pretty-print. Arrays and objects holding only scalars stay on one line,
+    /// so each op occupies a single line of output.
+    fn write(&self, out: &mut String, indent: usize) {
+        match self {
+            Json::Null => out.push_str("null"),
+            Json::Bool(b) => out.push_str(if *b { "true" } else { "false" }),
+            Json::Num(n) => out.push_str(n),
+            Json::Str(s) => write_str(out, s),
+            Json::Arr(items) if items.is_empty() => out.push_str("[]"),
+            Json::Arr(items) if items.iter().all(Json::is_scalar) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        out.push_str(", ");
+                    }
+                    item.write(out, indent);
+                }
+                out.push(']');
+            }
+            Json::Arr(items) => {
+                out.push('[');
+                for (i, item) in items.iter().enumerate() {
+                    out.push_str(if i > 0 { ",\n" } else { "\n" });
+                    push_indent(out, indent + 1);
+                    item.write(out, indent + 1);
+                }
+                out.push('\n');
+                push_indent(out, indent);
+                out.push(']');
+            }
+            Json::Obj(fields) if fields.iter().all(|(_, v)| v.is_scalar()) => {
+                out.push('{');
+                for (i, (k, v)) in fields.iter().enumerate() {
+                    out.push_str(if i > 0 { ", " } else { " " });
+                    write_str(out, k);
+                    out.push_str(": ");
+                    v.write(out, indent);
+                }
+                out.push_str(if fields.is_empty() { "}" } else { " }" });
+            }
+            Json::Obj(fields) => {
+                out.push('{');
+                for (i, (k, v)) in fields.iter().enumerate() {
+                    out.push_str(if i > 0 { ",\n" } else { "\n" });
+                    push_indent(out, indent + 1);
+                    write_str(out, k);
+                    out.push_str(": ");
+                    v.write(out, indent + 1);
+                }
+                out.push('\n');
+                push_indent(out, indent);
+                out.push('}');
+            }
+        }
+    }
+}
+
+fn push_indent(out: &mut String, level: usize) {
+    for _ in 0..level {
+        out.push_str("  ");
+    }
+}
+
+fn write_str(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
+
+// ── Parser ───────────────────────────────────────────────────────────────────
+
+struct Parser<'a> {
+    src: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Self {
+        Parser {
+            src: text.as_bytes(),
+            pos: 0,
+            depth: 0,
+        }
+    }
+
+    fn parse_document(&mut self) -> Result<Json> {
+        let value = self.value()?;
+        self.skip_ws();
+        if self.pos != self.src.len() {
+            return Err(self.error("trailing characters"));
+        }
+        Ok(value)
+    }
+
+    fn error(&self, msg: &str) -> Trap {
+        err(format!("{msg} at byte {}", self.pos))
+    }
+
+    fn skip_ws(&mut self) {
+        while let Some(b' ' | b'\t' | b'\n' | b'\r') = self.src.get(self.pos) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<u8> {
+        self.skip_ws();
+        self.src.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, byte: u8) -> Result<()> {
+        if self.peek() != Some(byte) {
+            return Err(self.error(&format!("expected `{}`", byte as char)));
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json> {
+        if self.src[self.pos..].starts_with(word.as_bytes()) {
+            self.pos += word.len();
+            Ok(value)
+        } else {
+            Err(self.error("invalid literal"))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json> {
+        match self.peek() {
+            Some(b'{') => self.nested(Self::object),
+            Some(b'[') => self.nested(Self::array),
+            Some(b'"') => Ok(Json::Str(self.string()?)),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(self.error("unexpected character")),
+            None => Err(self.error("unexpected end of input")),
+        }
+    }
+
+    fn nested(&mut self, f: fn(&mut Self) -> Result<Json>) -> Result<Json> {
+        self.depth += 1;
+        if self.depth > MAX_DEPTH {
+            return Err(self.error("nesting too deep"));
+        }
+        let value = f(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn object(&mut self) -> Result<Json> {
+        self.expect(b'{')?;
+        let mut fields = Vec::new();
+        if self.peek() == Some(b'}') {
+            self.pos += 1;
+            return Ok(Json::Obj(fields));
+        }
+        loop {
+            if self.peek() != Some(b'"') {
+                return Err(self.error("expected a field name"));
+            }
+            let key = self.string()?;
+            self.expect(b':')?;
+            fields.push((key, self.value()?));
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b'}') => {
+                    self.pos += 1;
+                    return Ok(Json::Obj(fields));
+                }
+                _ => return Err(self.error("expected `,` or `}`")),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        if self.peek() == Some(b']') {
+            self.pos += 1;
+            return Ok(Json::Arr(items));
+        }
+        loop {
+            items.push(self.value()?);
+            match self.peek() {
+                Some(b',') => self.pos += 1,
+                Some(b']') => {
+                    self.pos += 1;
+                    return Ok(Json::Arr(items));
+                }
+                _ => return Err(self.error("expected `,` or `]`")),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json> {
+        let start = self.pos;
+        let digits = |p: &mut Self| {
+            let from = p.pos;
+            while let Some(b'0'..=b'9') = p.src.get(p.pos) {
+                p.pos += 1;
+            }
+            p.pos > from
+        };
+        if self.src.get(self.pos) == Some(&b'-') {
+            self.pos += 1;
+        }
+        let mut ok = digits(self);
+        if self.src.get(self.pos) == Some(&b'.') {
+            self.pos += 1;
+            ok &= digits(self);
+        }
+        if let Some(b'e' | b'E') = self.src.get(self.pos) {
+            self.pos += 1;
+            if let Some(b'+' | b'-') = self.src.get(self.pos) {
+                self.pos += 1;
+            }
+            ok &= digits(self);
+        }
+        if !ok {
+            return Err(self.error("malformed number"));
+        }
+        // The scanned range is ASCII, so this cannot fail.
+        let text = std::str::from_utf8(&self.src[start..self.pos]).unwrap();
+        Ok(Json::Num(text.to_string()))
+    }
+
+    fn string(&mut self) -> Result<String> {
+        self.expect(b'"')?;
+        let mut out = Vec::new();
+        loop {
+            let Some(&b) = self.src.get(self.pos) else {
+                return Err(self.error("unterminated string"));
+            };
+            self.pos += 1;
+            match b {
+                b'"' => break,
+                b'\\' => {
+                    let Some(&esc) = self.src.get(self.pos) else {
+                        return Err(self.error("unterminated string"));
+                    };
+                    self.pos += 1;
+                    match esc {
+                        b'"' | b'\\' | b'/' => out.push(esc),
+                        b'b' => out.push(0x08),
+                        b'f' => out.push(0x0c),
+                        b'n' => out.push(b'\n'),
+                        b'r' => out.push(b'\r'),
+                        b't' => out.push(b'\t'),
+                        b'u' => {
+                            let c = self.unicode_escape()?;
+                            out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+                        }
+                        _ => return Err(self.error("invalid escape")),
+                    }
+                }
+                0x00..=0x1f => return Err(self.error("control character in string")),
+                _ => out.push(b),
+            }
+        }
+        // Input came from a `&str` and escapes are encoded as UTF-8.
+        String::from_utf8(out).map_err(|_| self.error("invalid UTF-8"))
+    }
+
+    fn hex4(&mut self) -> Result<u32> {
+        let digits = self
+            .src
+            .get(self.pos..self.pos + 4)
+            .and_then(|d| std::str::from_utf8(d).ok())
+            .and_then(|d| u32::from_str_radix(d, 16).ok())
+            .ok_or_else(|| self.error("invalid \\u escape"))?;
+        self.pos += 4;
+        Ok(digits)
+    }
+
+    fn unicode_escape(&mut self) -> Result<char> {
+        let hi = self.hex4()?;
+        let code = if (0xD800..0xDC00).contains(&hi) {
+            if !self.src[self.pos..].starts_with(b"\\u") {
+                return Err(self.error("unpaired surrogate"));
+            }
+            self.pos += 2;
+            let lo = self.hex4()?;
+            if !(0xDC00..0xE000).contains(&lo) {
+                return Err(self.error("unpaired surrogate"));
+            }
+            0x10000 + ((hi - 0xD800) << 10) + (lo - 0xDC00)
+        } else {
+            hi
+        };
+        char::from_u32(code).ok_or_else(|| self.error("invalid code point"))
+    }
+}
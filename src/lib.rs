@@ -19,18 +19,70 @@
 //! let result = inst.call("add", &[Val::I32(3), Val::I32(4)]).unwrap();
 //! assert_eq!(result, Some(Val::I32(7)));
 //! ```
+//!
+//! # Threads
+//!
+//! [`Module`], [`Runtime`], [`Linker`], [`InstancePre`],
+//! [`pool::InstancePool`] and [`image::MemoryImage`] are `Send + Sync`:
+//! share them between threads freely, behind an `Arc` or a plain reference. An [`Instance`] is `Send`
+//! but not `Sync`. It can be moved to another thread and called there, one
+//! call at a time, since every call takes `&mut self`; it cannot be called
+//! from two threads at once. To run a plugin on a worker pool, either move
+//! an [`OwnedInstance`] into the job, or borrow a module from a scoped
+//! thread. Host functions and extension ops must be `Send + Sync` because
+//! one definition may run on several threads at the same time, and host
+//! data attached with [`Instance::set_data`] must be `Send` so the instance
+//! can move.
 
+pub mod aot;
+pub mod blob;
+mod cache;
+pub mod cancel;
+pub mod clock;
+pub mod compat;
+#[cfg(feature = "cranelift")]
+pub mod compiler;
+pub mod config;
+pub mod embed;
+mod epoch;
+pub mod events;
+pub mod executor;
+pub mod extension;
+pub mod features;
 pub mod ffi;
+pub mod global;
+pub mod hash;
+#[cfg(feature = "hostlib")]
+pub mod hostlib;
+pub mod image;
 pub mod instance;
 pub mod ir;
+pub mod json;
+pub mod linker;
+mod liveness;
 pub mod memory;
+pub mod metrics;
 pub mod module;
+pub mod opt;
+pub mod plugin;
+pub mod pool;
+pub mod profile;
+pub mod rng;
 pub mod runtime;
+pub mod sandbox;
+pub mod sourcemap;
 pub mod stack;
+mod sys;
+pub mod text;
+pub mod timer;
 pub mod trap;
 pub mod types;
+pub mod verify;
 
-pub use instance::Instance;
+pub use config::RuntimeConfig;
+pub use features::Features;
+pub use instance::{Caller, FuncRef, Instance, OwnedInstance};
+pub use linker::{Capabilities, InstancePre, Linker};
 pub use module::Module;
 pub use runtime::Runtime;
 pub use trap::{Result, Trap};
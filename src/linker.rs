@@ -0,0 +1,486 @@
+//! Host functions shared between modules, resolved by name.
+//!
+//! A module lists the host functions it needs as [`Import`]s named
+//! `module.name`. The embedder defines them once in a [`Linker`] and
+//! instantiates any number of modules against it; each import is bound to
+//! the definition of the same name after its signature is checked.
+//!
+//! When one module is instantiated over and over, [`Linker::instantiate_pre`]
+//! does that resolution, and the preparation of every function, once; each
+//! [`InstancePre::instantiate`] then only builds memory.
+//!
+//! A definition can also be another instance's export: [`Linker::instance`]
+//! lets modules import from each other, so a plugin can be composed from
+//! smaller modules.
+//!
+//! Definitions can be labelled with a capability, such as `"fs"` or
+//! `"net"`, so one linker can serve plugins of different privilege: each
+//! instantiation grants a set of [`Capabilities`], and calling a function
+//! whose label was not granted traps with `Trap::PermissionDenied`.
+//!
+//! A linker also defines the [`Global`]s modules import with
+//! [`Module::import_global`], so the host can share values such as
+//! `config.max_items` with every instance it links.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::{
+    blob::{BlobStore, NoBlobs},
+    config::RuntimeConfig,
+    global::Global,
+    image::WarmImage,
+    instance::{Caller, HostBinding, Instance, Linked, ModuleRef, OwnedInstance},
+    module::{GlobalImport, Import, Module},
+    runtime::Runtime,
+    trap::{Result, Trap},
+    types::{FuncType, Val},
+};
+
+/// Callback behind a host function: the calling instance's context and the
+/// arguments, in push order.
+pub(crate) type HostFn = dyn Fn(&mut Caller<'_>, &[Val]) -> Result<Option<Val>> + Send + Sync;
+
+/// Signature and callback for a host-provided function.
+#[derive(Clone)]
+pub(crate) struct HostFuncDef {
+    pub name: String,
+    pub ty: FuncType,
+    pub func: Arc<HostFn>,
+}
+
+/// Host functions and globals keyed by `module.name`, for satisfying
+/// imports.
+#[derive(Clone, Default)]
+pub struct Linker {
+    funcs: HashMap<String, HostFuncDef>,
+    capabilities: HashMap<String, String>, // `module.name` -> label
+    globals: HashMap<String, Global>,
+}
+
+/// Capability labels granted to an instantiation; see
+/// [`Linker::set_capability`].
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    all: bool,
+    granted: HashSet<String>,
+}
+
+impl Capabilities {
+    /// Grant nothing: only unlabelled functions can be called.
+    pub fn none() -> Self {
+        Self::default()
+    }
+
+    /// Grant every label, as the plain `instantiate` methods do.
+    pub fn all() -> Self {
+        Capabilities {
+            all: true,
+            granted: HashSet::new(),
+        }
+    }
+
+    pub fn grant(&mut self, capability: impl Into<String>) -> &mut Self {
+        self.granted.insert(capability.into());
+        self
+    }
+
+    pub fn allows(&self, capability: &str) -> bool {
+        self.all || self.granted.contains(capability)
+    }
+}
+
+impl Linker {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Define `module.name`. Fails with `Trap::InvalidModule` if it is
+    /// already defined.
+    pub fn func<F>(&mut self, module: &str, name: &str, ty: FuncType, func: F) -> Result<&mut Self>
+    where
+        F: Fn(&[Val]) -> Result<Option<Val>> + Send + Sync + 'static,
+    {
+        self.func_with_caller(module, name, ty, move |_, args| func(args))
+    }
+
+    /// Define `module.name` with a function that also receives a
+    /// [`Caller`], through which it can borrow the calling instance's memory.
+    pub fn func_with_caller<F>(
+        &mut self,
+        module: &str,
+        name: &str,
+        ty: FuncType,
+        func: F,
+    ) -> Result<&mut Self>
+    where
+        F: Fn(&mut Caller<'_>, &[Val]) -> Result<Option<Val>> + Send + Sync + 'static,
+    {
+        let key = format!("{module}.{name}");
+        if self.funcs.contains_key(&key) {
+            return Err(Trap::InvalidModule(format!("{key} is already defined")));
+        }
+        self.funcs.insert(
+            key.clone(),
+            HostFuncDef {
+                name: key,
+                ty,
+                func: Arc::new(func),
+            },
+        );
+        Ok(self)
+    }
+
+    /// Signature of `module.name`, if defined.
+    pub fn get(&self, module: &str, name: &str) -> Option<&FuncType> {
+        self.funcs
+            .get(&format!("{module}.{name}"))
+            .map(|def| &def.ty)
+    }
+
+    /// Define global `module.name`. Every instance linked to it shares
+    /// `global`, so a value the host sets is seen by all of them. Fails with
+    /// `Trap::InvalidModule` if it is already defined.
+    pub fn global(&mut self, module: &str, name: &str, global: Global) -> Result<&mut Self> {
+        let key = format!("{module}.{name}");
+        if self.globals.contains_key(&key) {
+            return Err(Trap::InvalidModule(format!("{key} is already defined")));
+        }
+        self.globals.insert(key, global);
+        Ok(self)
+    }
+
+    /// Global `module.name`, if defined.
+    pub fn get_global(&self, module: &str, name: &str) -> Option<&Global> {
+        self.globals.get(&format!("{module}.{name}"))
+    }
+
+    /// Names of `module`'s function and global imports, as `module.name`,
+    /// that this linker does not define, in import order. Only names are
+    /// checked; an import defined with a different type still fails to
+    /// instantiate.
+    pub fn unresolved(&self, module: &Module) -> Vec<String> {
+        let funcs = module
+            .imports
+            .iter()
+            .map(ToString::to_string)
+            .filter(|key| !self.funcs.contains_key(key));
+        let globals = module
+            .global_imports
+            .iter()
+            .map(ToString::to_string)
+            .filter(|key| !self.globals.contains_key(key));
+        funcs.chain(globals).collect()
+    }
+
+    /// Define every export of `instance` as `module.<export>`, with the
+    /// export's signature, so other modules can import it. A call locks the
+    /// instance and runs the export directly, without looking it up by
+    /// name; the caller's memory is not shared, only argument and result
+    /// values cross. Calls from several threads take turns. A call through
+    /// an import cycle back into an instance it is already running on the
+    /// same thread fails with `Trap::HostError` instead of deadlocking; the
+    /// host should not hold the lock while calling into a module that
+    /// imports from the instance.
+    ///
+    /// Fails with `Trap::InvalidModule` if any of the names is already
+    /// defined, in which case none are.
+    pub fn instance(
+        &mut self,
+        module: &str,
+        instance: &Arc<Mutex<OwnedInstance>>,
+    ) -> Result<&mut Self> {
+        let exports: Vec<(String, usize, FuncType)> = {
+            let guard = instance.lock().unwrap_or_else(PoisonError::into_inner);
+            let m = guard.module();
+            m.exports
+                .iter()
+                .filter_map(|(name, idx)| {
+                    let f = m.functions.get(*idx as usize)?;
+                    Some((name.clone(), *idx as usize, f.ty.clone()))
+                })
+                .collect()
+        };
+        if let Some((name, ..)) = exports
+            .iter()
+            .find(|(name, ..)| self.funcs.contains_key(&format!("{module}.{name}")))
+        {
+            return Err(Trap::InvalidModule(format!(
+                "{module}.{name} is already defined"
+            )));
+        }
+        let label = format!("instance `{module}`");
+        for (name, idx, ty) in exports {
+            let target = instance.clone();
+            let label = label.clone();
+            self.func(module, &name, ty, move |args| {
+                call_instance(&target, &label, idx, args)
+            })?;
+        }
+        Ok(self)
+    }
+
+    /// Label `module.name` with `capability`, replacing any earlier label.
+    /// Instances created without that capability granted can still link
+    /// against the function, but calling it traps with
+    /// `Trap::PermissionDenied`. Fails with `Trap::UndefinedImport` if the
+    /// function is not defined.
+    pub fn set_capability(
+        &mut self,
+        module: &str,
+        name: &str,
+        capability: impl Into<String>,
+    ) -> Result<&mut Self> {
+        let key = format!("{module}.{name}");
+        if !self.funcs.contains_key(&key) {
+            return Err(Trap::UndefinedImport(key));
+        }
+        self.capabilities.insert(key, capability.into());
+        Ok(self)
+    }
+
+    /// Capability label of `module.name`, if it has one.
+    pub fn capability(&self, module: &str, name: &str) -> Option<&str> {
+        self.capabilities
+            .get(&format!("{module}.{name}"))
+            .map(String::as_str)
+    }
+
+    /// Instantiate `module` in `runtime`, binding its imports to this
+    /// linker's definitions. Every capability is granted.
+    pub fn instantiate<'m>(&self, runtime: &Runtime, module: &'m Module) -> Result<Instance<'m>> {
+        self.instantiate_with_capabilities(runtime, module, &Capabilities::all())
+    }
+
+    /// Like [`instantiate`](Self::instantiate), for a module held in an
+    /// `Arc`; see [`Runtime::instantiate_owned`].
+    pub fn instantiate_owned(
+        &self,
+        runtime: &Runtime,
+        module: Arc<Module>,
+    ) -> Result<OwnedInstance> {
+        self.instantiate_owned_with_capabilities(runtime, module, &Capabilities::all())
+    }
+
+    /// Like [`instantiate`](Self::instantiate), granting only `granted`.
+    pub fn instantiate_with_capabilities<'m>(
+        &self,
+        runtime: &Runtime,
+        module: &'m Module,
+        granted: &Capabilities,
+    ) -> Result<Instance<'m>> {
+        runtime.instantiate_linked(module.into(), self, granted)
+    }
+
+    /// Like [`instantiate_owned`](Self::instantiate_owned), granting only
+    /// `granted`.
+    pub fn instantiate_owned_with_capabilities(
+        &self,
+        runtime: &Runtime,
+        module: Arc<Module>,
+        granted: &Capabilities,
+    ) -> Result<OwnedInstance> {
+        runtime.instantiate_linked(module.into(), self, granted)
+    }
+
+    /// Like [`Runtime::instantiate_warm`], binding `module`'s imports to
+    /// this linker's definitions. Every capability is granted.
+    pub fn instantiate_warm<'m>(
+        &self,
+        runtime: &Runtime,
+        module: &'m Module,
+        image: &WarmImage,
+    ) -> Result<Instance<'m>> {
+        runtime.instantiate_warm_linked(module.into(), image, Some((self, &Capabilities::all())))
+    }
+
+    /// Resolve `module`'s imports and prepare its functions for `runtime`
+    /// now, returning a template that instantiates it without repeating
+    /// either. The template keeps the definitions it bound, so later changes
+    /// to this linker do not affect it.
+    pub fn instantiate_pre<'m>(
+        &self,
+        runtime: &Runtime,
+        module: &'m Module,
+    ) -> Result<InstancePre<'m>> {
+        InstancePre::new(runtime, module.into(), self, &Capabilities::all())
+    }
+
+    /// Like [`instantiate_pre`](Self::instantiate_pre), for a module held in
+    /// an `Arc`; the template's instances are [`OwnedInstance`]s.
+    pub fn instantiate_pre_owned(
+        &self,
+        runtime: &Runtime,
+        module: Arc<Module>,
+    ) -> Result<InstancePre<'static>> {
+        InstancePre::new(runtime, module.into(), self, &Capabilities::all())
+    }
+
+    /// Like [`instantiate_pre`](Self::instantiate_pre), granting the
+    /// template's instances only `granted`.
+    pub fn instantiate_pre_with_capabilities<'m>(
+        &self,
+        runtime: &Runtime,
+        module: &'m Module,
+        granted: &Capabilities,
+    ) -> Result<InstancePre<'m>> {
+        InstancePre::new(runtime, module.into(), self, granted)
+    }
+
+    /// Like [`instantiate_pre_owned`](Self::instantiate_pre_owned), granting
+    /// the template's instances only `granted`.
+    pub fn instantiate_pre_owned_with_capabilities(
+        &self,
+        runtime: &Runtime,
+        module: Arc<Module>,
+        granted: &Capabilities,
+    ) -> Result<InstancePre<'static>> {
+        InstancePre::new(runtime, module.into(), self, granted)
+    }
+
+    /// Bind each of `imports`, in order, those with a capability not in
+    /// `granted` or denied by `config` to a stub that traps. Fails with `Trap::UndefinedImport`
+    /// listing every name that is not defined, or with `Trap::InvalidModule`
+    /// naming the first import whose signature differs from its definition.
+    pub(crate) fn resolve(
+        &self,
+        imports: &[Import],
+        granted: &Capabilities,
+        config: &RuntimeConfig,
+    ) -> Result<Vec<HostBinding>> {
+        let mut missing = Vec::new();
+        let mut bound = Vec::with_capacity(imports.len());
+        for import in imports {
+            let Some(def) = self.funcs.get(&import.to_string()) else {
+                missing.push(import.to_string());
+                continue;
+            };
+            if def.ty != import.ty {
+                return Err(Trap::InvalidModule(format!(
+                    "import {import} expects {:?} -> {:?}, but the linker defines {:?} -> {:?}",
+                    import.ty.params, import.ty.results, def.ty.params, def.ty.results
+                )));
+            }
+            let allowed = self.capabilities.get(&def.name).is_none_or(|capability| {
+                granted.allows(capability) && !config.denies_capability(capability)
+            });
+            bound.push(if allowed {
+                HostBinding::new(def)
+            } else {
+                HostBinding::denied(def)
+            });
+        }
+        if !missing.is_empty() {
+            return Err(Trap::UndefinedImport(missing.join(", ")));
+        }
+        Ok(bound)
+    }
+
+    /// Bind each of `imports`, in order, to its definition. Fails with
+    /// `Trap::UndefinedImport` listing every name that is not defined, or
+    /// with `Trap::InvalidModule` naming the first import whose type differs
+    /// from its definition or that writes an immutable global.
+    pub(crate) fn resolve_globals(&self, imports: &[GlobalImport]) -> Result<Vec<Global>> {
+        let mut missing = Vec::new();
+        let mut bound = Vec::with_capacity(imports.len());
+        for import in imports {
+            let Some(global) = self.globals.get(&import.to_string()) else {
+                missing.push(import.to_string());
+                continue;
+            };
+            if global.ty() != import.ty {
+                return Err(Trap::InvalidModule(format!(
+                    "global import {import} expects {:?}, but the linker defines {:?}",
+                    import.ty,
+                    global.ty()
+                )));
+            }
+            if import.mutable && !global.is_mutable() {
+                return Err(Trap::InvalidModule(format!(
+                    "global import {import} is mutable, but the linker defines it immutable"
+                )));
+            }
+            bound.push(global.clone());
+        }
+        if !missing.is_empty() {
+            return Err(Trap::UndefinedImport(missing.join(", ")));
+        }
+        Ok(bound)
+    }
+}
+
+thread_local! {
+    /// Instances linked with [`Linker::instance`] running on this thread,
+    /// by address, innermost last.
+    static RUNNING: RefCell<Vec<usize>> = const { RefCell::new(Vec::new()) };
+}
+
+fn call_instance(
+    target: &Arc<Mutex<OwnedInstance>>,
+    label: &str,
+    idx: usize,
+    args: &[Val],
+) -> Result<Option<Val>> {
+    let key = Arc::as_ptr(target) as usize;
+    if RUNNING.with(|running| running.borrow().contains(&key)) {
+        return Err(Trap::HostError(format!("re-entrant call into {label}")));
+    }
+    RUNNING.with(|running| running.borrow_mut().push(key));
+    // Popped on the way out even if a host function panics.
+    struct Leave;
+    impl Drop for Leave {
+        fn drop(&mut self) {
+            RUNNING.with(|running| running.borrow_mut().pop());
+        }
+    }
+    let _leave = Leave;
+    target
+        .lock()
+        .unwrap_or_else(PoisonError::into_inner)
+        .call_index(idx, args)
+}
+
+/// A module linked and prepared for one runtime, from
+/// [`Linker::instantiate_pre`]. Instantiating it only allocates memory and
+/// applies data segments. `Send + Sync`, so one template can serve every
+/// worker thread.
+pub struct InstancePre<'m> {
+    runtime: Runtime,
+    module: ModuleRef<'m>,
+    linked: Linked,
+}
+
+impl<'m> InstancePre<'m> {
+    fn new(
+        runtime: &Runtime,
+        module: ModuleRef<'m>,
+        linker: &Linker,
+        granted: &Capabilities,
+    ) -> Result<Self> {
+        let prepared = runtime.prepare(&module)?;
+        let linked = Linked::with_prepared(&module, Some((linker, granted)), 1, prepared)?;
+        Ok(InstancePre {
+            runtime: runtime.share(),
+            module,
+            linked,
+        })
+    }
+
+    /// Create an instance, subject to the runtime's limiter and quota like
+    /// any other.
+    pub fn instantiate(&self) -> Result<Instance<'m>> {
+        self.instantiate_with_blobs(&NoBlobs)
+    }
+
+    /// Like [`instantiate`](Self::instantiate), fetching external data
+    /// segments from `blobs`.
+    pub fn instantiate_with_blobs(&self, blobs: &dyn BlobStore) -> Result<Instance<'m>> {
+        self.runtime
+            .instantiate_pre(self.module.clone(), &self.linked, blobs)
+    }
+
+    pub fn module(&self) -> &Module {
+        &self.module
+    }
+}
@@ -0,0 +1,274 @@
+//! Liveness of locals, so that locals never live at once share a slot.
+//!
+//! The interpreter gives every local of a function a value in its frame,
+//! and the baseline JIT a slot, however briefly the function uses it.
+//! Guests compiled from languages with block scoping declare a fresh local
+//! for every temporary, so their frames grow far past what they ever hold
+//! at once, and deeply recursive ones carry the waste into every call.
+//!
+//! [`coalesce`] works out, over the function's control flow, where each
+//! local holds a value some later op reads. Two locals of the same type
+//! interfere if one is written while the other is live, or both are live
+//! on entry; locals that do not interfere are renumbered into one slot.
+//! Renumbering only rewrites the index of `local.get`, `local.set` and
+//! `local.tee`, so op indices, and with them source maps, backtraces and
+//! fuel accounting, stay as they were. Stack slots need nothing of the
+//! kind: the backends already give values at the same stack height one
+//! slot.
+
+use crate::{instance::PreparedFunc, ir::Op, types::ValType};
+
+/// Functions with more locals than this keep them all; the interference
+/// matrix grows with the square of the count.
+const MAX_LOCALS: usize = 1024;
+
+/// Largest liveness table, in words, worth building for one function.
+const MAX_WORDS: usize = 1 << 20;
+
+/// Where each local of a function is live, op by op.
+pub(crate) struct Liveness {
+    n: usize,
+    /// Where control goes after each op: up to two op indices, one past
+    /// the last op for falling off the end.
+    succs: Vec<[Option<usize>; 2]>,
+    /// The locals live on entry to each op.
+    live_in: Vec<Set>,
+}
+
+impl Liveness {
+    /// Liveness of the `n` locals of a function with body `ops`, or `None`
+    /// if blocks do not nest properly, branches name blocks that are not
+    /// open, ops name locals past `n`, or the function is too large.
+    pub(crate) fn of(n: usize, ops: &[Op]) -> Option<Liveness> {
+        if ops.len().saturating_mul(n.div_ceil(64)) > MAX_WORDS {
+            return None;
+        }
+        if !ops.iter().all(|op| match op {
+            Op::LocalGet(l) | Op::LocalSet(l) | Op::LocalTee(l) => (*l as usize) < n,
+            _ => true,
+        }) {
+            return None;
+        }
+        let mut liveness = Liveness {
+            n,
+            succs: successors(ops)?,
+            live_in: vec![Set::new(n); ops.len()],
+        };
+        // Until nothing changes. Going backwards, each pass carries
+        // liveness back through the rest of the function and once more
+        // around each loop.
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for i in (0..ops.len()).rev() {
+                let mut live = liveness.live_out(i);
+                match ops[i] {
+                    Op::LocalGet(l) => live.insert(l as usize),
+                    Op::LocalSet(l) | Op::LocalTee(l) => live.remove(l as usize),
+                    _ => {}
+                }
+                if live != liveness.live_in[i] {
+                    liveness.live_in[i] = live;
+                    changed = true;
+                }
+            }
+        }
+        Some(liveness)
+    }
+
+    /// Whether some op may read local `l` after op `i` before writing it.
+    pub(crate) fn live_after(&self, i: usize, l: u32) -> bool {
+        self.live_out(i).contains(l as usize)
+    }
+
+    fn live_out(&self, i: usize) -> Set {
+        let mut out = Set::new(self.n);
+        for &s in self.succs[i].iter().flatten() {
+            if let Some(live) = self.live_in.get(s) {
+                out.union(live);
+            }
+        }
+        out
+    }
+
+    /// The locals live on entry to the function.
+    fn entry(&self) -> Set {
+        self.live_in
+            .first()
+            .cloned()
+            .unwrap_or_else(|| Set::new(self.n))
+    }
+}
+
+/// Ops of `pf`, a function taking `params`, with its locals renumbered
+/// into as few slots as their liveness allows, and the types of the slots
+/// after the parameters. `None` if no two locals can share, or the ops do
+/// not nest properly.
+pub(crate) fn coalesce(params: &[ValType], pf: &PreparedFunc) -> Option<(Vec<Op>, Vec<ValType>)> {
+    if pf.extra_locals.is_empty() || params.len() != pf.n_params {
+        return None;
+    }
+    let types: Vec<ValType> = params.iter().chain(&pf.extra_locals).copied().collect();
+    let n = types.len();
+    if n > MAX_LOCALS {
+        return None;
+    }
+    let ops = &pf.ops;
+    let liveness = Liveness::of(n, ops)?;
+
+    // Every local is written on entry, with its argument or zero.
+    let entry = liveness.entry();
+    let mut interferes = vec![Set::new(n); n];
+    for a in entry.iter() {
+        interferes[a].union(&entry);
+    }
+    for (i, op) in ops.iter().enumerate() {
+        if let Op::LocalSet(l) | Op::LocalTee(l) = *op {
+            let l = l as usize;
+            for other in liveness.live_out(i).iter().filter(|&other| other != l) {
+                interferes[l].insert(other);
+                interferes[other].insert(l);
+            }
+        }
+    }
+
+    // Parameters keep their slots. Each other local takes the first slot
+    // of its type it interferes with nothing in, short of a parameter's if
+    // it must start out zero.
+    let mut slot_of: Vec<u32> = (0..params.len() as u32).collect();
+    let mut slots: Vec<(ValType, Set)> = (0..params.len())
+        .map(|p| (types[p], interferes[p].clone()))
+        .collect();
+    for (l, &ty) in types.iter().enumerate().skip(params.len()) {
+        let free = |(s, (slot_ty, taken)): &(usize, &(ValType, Set))| {
+            *slot_ty == ty && !taken.contains(l) && !(*s < params.len() && entry.contains(l))
+        };
+        let slot = match slots.iter().enumerate().find(free) {
+            Some((s, _)) => s,
+            None => {
+                slots.push((ty, Set::new(n)));
+                slots.len() - 1
+            }
+        };
+        slots[slot].1.union(&interferes[l]);
+        slot_of.push(slot as u32);
+    }
+    if slots.len() == n {
+        return None;
+    }
+
+    let renumbered = ops
+        .iter()
+        .map(|op| match *op {
+            Op::LocalGet(l) => Op::LocalGet(slot_of[l as usize]),
+            Op::LocalSet(l) => Op::LocalSet(slot_of[l as usize]),
+            Op::LocalTee(l) => Op::LocalTee(slot_of[l as usize]),
+            ref op => op.clone(),
+        })
+        .collect();
+    let extra = slots[params.len()..].iter().map(|&(ty, _)| ty).collect();
+    Some((renumbered, extra))
+}
+
+/// Where control goes after each op of `ops`, or `None` unless blocks
+/// nest properly and branches name open blocks.
+fn successors(ops: &[Op]) -> Option<Vec<[Option<usize>; 2]>> {
+    // The `End` of each block, and the `Else` of each `If` that has one.
+    let mut ends = vec![usize::MAX; ops.len()];
+    let mut elses = vec![usize::MAX; ops.len()];
+    let mut open = Vec::new();
+    for (i, op) in ops.iter().enumerate() {
+        match op {
+            Op::Block(_) | Op::Loop(_) | Op::If(_) => open.push(i),
+            Op::Else => {
+                let &start = open.last()?;
+                if !matches!(ops[start], Op::If(_)) || elses[start] != usize::MAX {
+                    return None;
+                }
+                elses[start] = i;
+            }
+            Op::End => match open.pop() {
+                Some(start) => ends[start] = i,
+                None => break,
+            },
+            _ => {}
+        }
+    }
+    if !open.is_empty() {
+        return None;
+    }
+
+    let mut frames: Vec<(usize, bool)> = Vec::new();
+    let mut succs = Vec::with_capacity(ops.len());
+    for (i, op) in ops.iter().enumerate() {
+        let next = Some(i + 1);
+        // A branch `depth` blocks out: to the start of a loop's body, or
+        // the `End` of any other block.
+        let target = |depth: u32| {
+            let index = frames.len().checked_sub(depth as usize + 1)?;
+            let (start, is_loop) = frames[index];
+            Some(if is_loop { start + 1 } else { ends[start] })
+        };
+        succs.push(match op {
+            Op::Block(_) | Op::Loop(_) => {
+                frames.push((i, matches!(op, Op::Loop(_))));
+                [next, None]
+            }
+            Op::If(_) => {
+                frames.push((i, false));
+                let otherwise = match elses[i] {
+                    usize::MAX => ends[i],
+                    at => at + 1,
+                };
+                [next, Some(otherwise)]
+            }
+            Op::Else => [Some(ends[frames.last()?.0]), None],
+            Op::End => match frames.pop() {
+                Some(_) => [next, None],
+                // The function's final `End` returns.
+                None => [None, None],
+            },
+            Op::Br(depth) => [Some(target(*depth)?), None],
+            Op::BrIf(depth) => [next, Some(target(*depth)?)],
+            Op::Return | Op::Unreachable => [None, None],
+            _ => [next, None],
+        });
+    }
+    Some(succs)
+}
+
+/// A set of locals, one bit each.
+#[derive(Clone, PartialEq, Eq)]
+struct Set(Vec<u64>);
+
+impl Set {
+    fn new(n: usize) -> Self {
+        Set(vec![0; n.div_ceil(64)])
+    }
+
+    fn insert(&mut self, i: usize) {
+        self.0[i / 64] |= 1 << (i % 64);
+    }
+
+    fn remove(&mut self, i: usize) {
+        self.0[i / 64] &= !(1 << (i % 64));
+    }
+
+    fn contains(&self, i: usize) -> bool {
+        self.0[i / 64] & 1 << (i % 64) != 0
+    }
+
+    fn union(&mut self, other: &Set) {
+        for (a, b) in self.0.iter_mut().zip(&other.0) {
+            *a |= b;
+        }
+    }
+
+    fn iter(&self) -> impl Iterator<Item = usize> + '_ {
+        self.0.iter().enumerate().flat_map(|(w, &bits)| {
+            (0..64)
+                .filter(move |b| bits & 1 << b != 0)
+                .map(move |b| w * 64 + b)
+        })
+    }
+}
@@ -1,3 +1,9 @@
+use std::fmt;
+use std::io::{IoSlice, IoSliceMut};
+use std::ops::Range;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
 use crate::trap::{Result, Trap};
 
 /// Page size used by Rune (matches Wasm).
@@ -5,58 +11,729 @@ pub const PAGE_SIZE: usize = 65_536;
 
 /// Linear memory for a Rune instance.
 ///
-/// On real hardware this would use mmap with guard pages; here we use a
-/// Vec<u8> so the implementation works on all platforms without unsafe.
+/// Backed by a `Vec<u8>` by default. Memory created from a [`MemoryImage`]
+/// on Linux is instead a private mapping of the image, so pages are shared
+/// copy-on-write between instances until one of them writes. Memory created
+/// with [`Memory::from_external`] operates directly on a host buffer.
+///
+/// [`MemoryImage`]: crate::image::MemoryImage
 pub struct Memory {
-    data: Vec<u8>,
+    storage: Storage,
+    len: usize,
     max_pages: Option<usize>,
+    /// Per-page protection; empty while every page is read-write, so the
+    /// common case costs one branch per access.
+    protection: Vec<Protection>,
+    peak_pages: usize,
+    grow_count: u64,
+    /// Runtime-wide counters this memory reports into, if any.
+    usage: Option<Arc<MemoryUsage>>,
+    limiter: Option<Arc<dyn MemoryLimiter>>,
+    poison: Poison,
+    /// One bit per byte, set once written; only kept with `Poison::Check`.
+    written: Vec<u64>,
+}
+
+/// Debug aid for catching reads of memory the guest never initialized.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Poison {
+    /// Fresh pages are zero, as normal.
+    #[default]
+    Off,
+    /// Fresh and discarded pages are filled with [`POISON_BYTE`] instead of
+    /// zero, so stray reads produce recognisable garbage.
+    Fill,
+    /// As `Fill`, and reading a byte that was poisoned and not written
+    /// since traps with `Trap::UninitializedRead`. Borrowing bytes with
+    /// [`MemoryView::slice_mut`] counts as writing them.
+    Check,
+}
+
+/// Fill byte for poisoned memory.
+pub const POISON_BYTE: u8 = 0xA5;
+
+/// Host policy consulted before linear memory grows.
+///
+/// `max_pages` is a fixed ceiling; a limiter lets the host decide each
+/// request as it happens, for example refusing growth while the process as
+/// a whole is under memory pressure. It is asked once at instantiation (with
+/// `current` 0) and again before every `grow` by a non-zero amount.
+pub trait MemoryLimiter: Send + Sync {
+    /// Whether memory may grow from `current` to `desired` pages. `max` is
+    /// the memory's own limit, which `desired` never exceeds. Returning
+    /// `false` fails the instantiation with `Trap::OutOfMemory`, or makes
+    /// `memory.grow` return -1.
+    fn memory_growing(&self, current: usize, desired: usize, max: Option<usize>) -> bool;
+}
+
+/// Usage figures for one memory, from [`Memory::stats`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MemoryStats {
+    /// Bytes currently allocated.
+    pub current_bytes: usize,
+    /// Largest size reached, in pages.
+    pub peak_pages: usize,
+    /// Successful `grow` calls, including growth by zero pages.
+    pub grow_count: u64,
+}
+
+/// Usage aggregated over every live instance of a runtime, from
+/// [`Runtime::memory_stats`](crate::runtime::Runtime::memory_stats).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RuntimeMemoryStats {
+    /// Instances whose memory is still alive.
+    pub instances: usize,
+    /// Bytes currently allocated across those instances.
+    pub current_bytes: usize,
+    /// Highest value `current_bytes` has reached.
+    pub peak_bytes: usize,
+    /// Successful `grow` calls across all instances, live or dropped.
+    pub grow_count: u64,
+}
+
+/// Shared counters behind [`RuntimeMemoryStats`], updated by each tracked
+/// memory as it is created, grown and dropped, and the runtime-wide limits
+/// they are held to.
+#[derive(Default)]
+pub(crate) struct MemoryUsage {
+    instances: AtomicUsize,
+    current_bytes: AtomicUsize,
+    peak_bytes: AtomicUsize,
+    grow_count: AtomicU64,
+    code_bytes: AtomicUsize,
+    quota: Option<usize>,
+    max_instances: Option<usize>,
+    code_quota: Option<usize>,
+}
+
+impl MemoryUsage {
+    pub(crate) fn with_limits(
+        quota: Option<usize>,
+        max_instances: Option<usize>,
+        code_quota: Option<usize>,
+    ) -> Self {
+        MemoryUsage {
+            quota,
+            max_instances,
+            code_quota,
+            ..Default::default()
+        }
+    }
+
+    /// Count one more live instance, unless that would exceed the cap.
+    fn add_instance(&self) -> Result<()> {
+        let max = self.max_instances.unwrap_or(usize::MAX);
+        self.instances
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+                Some(n + 1).filter(|&n| n <= max)
+            })
+            .map(|_| ())
+            .map_err(|_| Trap::OutOfMemory)
+    }
+
+    /// Whether another instance would currently fit under the cap. Only a
+    /// hint, like [`fits`](Self::fits).
+    pub(crate) fn has_instance_room(&self) -> bool {
+        self.max_instances
+            .is_none_or(|max| self.instances.load(Ordering::Relaxed) < max)
+    }
+
+    /// Count `bytes` more of prepared code as held until the returned
+    /// charge is dropped, unless that would exceed the code quota.
+    pub(crate) fn charge_code(self: &Arc<Self>, bytes: usize) -> Result<CodeCharge> {
+        let quota = self.code_quota.unwrap_or(usize::MAX);
+        self.code_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |now| {
+                now.checked_add(bytes).filter(|&total| total <= quota)
+            })
+            .map_err(|_| Trap::OutOfMemory)?;
+        Ok(CodeCharge {
+            usage: self.clone(),
+            bytes,
+        })
+    }
+
+    /// Prepared code bytes held by live instances.
+    pub(crate) fn code_bytes(&self) -> usize {
+        self.code_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Count `bytes` more as allocated, unless that would exceed the quota.
+    fn reserve(&self, bytes: usize) -> Result<()> {
+        let quota = self.quota.unwrap_or(usize::MAX);
+        let before = self
+            .current_bytes
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |now| {
+                now.checked_add(bytes).filter(|&total| total <= quota)
+            })
+            .map_err(|_| Trap::OutOfMemory)?;
+        self.peak_bytes.fetch_max(before + bytes, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn release(&self, bytes: usize) {
+        self.current_bytes.fetch_sub(bytes, Ordering::Relaxed);
+    }
+
+    /// Whether `bytes` more would currently fit in the quota. Only a hint:
+    /// other instances may allocate before the bytes are reserved.
+    pub(crate) fn fits(&self, bytes: usize) -> bool {
+        self.quota.is_none_or(|quota| {
+            self.current_bytes
+                .load(Ordering::Relaxed)
+                .checked_add(bytes)
+                .is_some_and(|total| total <= quota)
+        })
+    }
+
+    pub(crate) fn snapshot(&self) -> RuntimeMemoryStats {
+        RuntimeMemoryStats {
+            instances: self.instances.load(Ordering::Relaxed),
+            current_bytes: self.current_bytes.load(Ordering::Relaxed),
+            peak_bytes: self.peak_bytes.load(Ordering::Relaxed),
+            grow_count: self.grow_count.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Access allowed to a page of linear memory.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protection {
+    ReadWrite,
+    /// Loads succeed; stores trap with `Trap::AccessViolation`.
+    ReadOnly,
+    /// Loads and stores trap with `Trap::AccessViolation`.
+    NoAccess,
+}
+
+/// A host-owned buffer a [`Memory`] can operate on in place, such as a frame
+/// buffer or a database page.
+///
+/// Both methods must return the same bytes every time, and the length must
+/// not change while the memory holds the buffer.
+pub trait ExternalMemory: Send + Sync {
+    fn as_slice(&self) -> &[u8];
+    fn as_mut_slice(&mut self) -> &mut [u8];
+}
+
+impl ExternalMemory for Vec<u8> {
+    fn as_slice(&self) -> &[u8] {
+        self
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+impl ExternalMemory for Box<[u8]> {
+    fn as_slice(&self) -> &[u8] {
+        self
+    }
+
+    fn as_mut_slice(&mut self) -> &mut [u8] {
+        self
+    }
+}
+
+enum Storage {
+    Heap(Vec<u8>),
+    External(Box<dyn ExternalMemory>),
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    Mapped(crate::sys::Mapping),
 }
 
 impl Memory {
     pub fn new(initial_pages: usize, max_pages: Option<usize>) -> Self {
         let size = initial_pages * PAGE_SIZE;
         Memory {
-            data: vec![0u8; size],
+            storage: Storage::Heap(vec![0u8; size]),
+            len: size,
+            max_pages,
+            protection: Vec::new(),
+            peak_pages: initial_pages,
+            grow_count: 0,
+            usage: None,
+            limiter: None,
+            poison: Poison::Off,
+            written: Vec::new(),
+        }
+    }
+
+    /// Memory whose contents are `bytes`, which must be a whole number of pages.
+    pub(crate) fn from_vec(bytes: Vec<u8>, max_pages: Option<usize>) -> Self {
+        debug_assert_eq!(bytes.len() % PAGE_SIZE, 0);
+        Memory {
+            len: bytes.len(),
+            peak_pages: bytes.len() / PAGE_SIZE,
+            storage: Storage::Heap(bytes),
             max_pages,
+            protection: Vec::new(),
+            grow_count: 0,
+            usage: None,
+            limiter: None,
+            poison: Poison::Off,
+            written: Vec::new(),
         }
     }
 
+    /// Memory backed by a private mapping; the first `len` bytes are in use.
+    #[cfg(all(
+        target_os = "linux",
+        any(target_arch = "x86_64", target_arch = "aarch64")
+    ))]
+    pub(crate) fn from_mapping(
+        mapping: crate::sys::Mapping,
+        len: usize,
+        max_pages: Option<usize>,
+    ) -> Self {
+        debug_assert!(len <= mapping.reserved());
+        Memory {
+            storage: Storage::Mapped(mapping),
+            len,
+            max_pages,
+            protection: Vec::new(),
+            peak_pages: len / PAGE_SIZE,
+            grow_count: 0,
+            usage: None,
+            limiter: None,
+            poison: Poison::Off,
+            written: Vec::new(),
+        }
+    }
+
+    /// Memory of `initial_pages` zero pages in a guarded mapping, for code
+    /// that leaves bounds to guard pages; `None` if the address space
+    /// cannot be reserved.
+    #[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
+    pub(crate) fn guarded(initial_pages: usize, max_pages: Option<usize>) -> Option<Self> {
+        let len = initial_pages.checked_mul(PAGE_SIZE)?;
+        let mapping = crate::sys::Mapping::guarded(len, None)?;
+        Some(Memory::from_mapping(mapping, len, max_pages))
+    }
+
+    /// Memory over a host-owned buffer, which must be a whole number of
+    /// pages. Guest loads and stores go straight to the buffer, with no
+    /// copies either way. The memory cannot grow past the buffer, so its
+    /// maximum is its initial size. Take the buffer back with
+    /// [`Memory::into_external`].
+    pub fn from_external(buffer: Box<dyn ExternalMemory>) -> Result<Self> {
+        let len = buffer.as_slice().len();
+        if !len.is_multiple_of(PAGE_SIZE) {
+            return Err(Trap::InvalidModule(format!(
+                "external memory of {len} bytes is not a whole number of pages"
+            )));
+        }
+        Ok(Memory {
+            storage: Storage::External(buffer),
+            len,
+            max_pages: Some(len / PAGE_SIZE),
+            protection: Vec::new(),
+            peak_pages: len / PAGE_SIZE,
+            grow_count: 0,
+            usage: None,
+            limiter: None,
+            poison: Poison::Off,
+            written: Vec::new(),
+        })
+    }
+
+    /// Give back the buffer of a memory made by [`Memory::from_external`],
+    /// or `None` for memory the runtime allocated.
+    pub fn into_external(mut self) -> Option<Box<dyn ExternalMemory>> {
+        match std::mem::replace(&mut self.storage, Storage::Heap(Vec::new())) {
+            Storage::External(buffer) => Some(buffer),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn set_max_pages(&mut self, max_pages: Option<usize>) {
+        self.max_pages = max_pages;
+    }
+
     /// Current size in bytes.
     pub fn size(&self) -> usize {
-        self.data.len()
+        self.len
     }
 
     /// Current size in pages.
     pub fn pages(&self) -> usize {
-        self.data.len() / PAGE_SIZE
+        self.len / PAGE_SIZE
+    }
+
+    /// Maximum size in pages, if limited.
+    pub fn max_pages(&self) -> Option<usize> {
+        self.max_pages
+    }
+
+    /// Current size, peak size and number of grows of this memory.
+    pub fn stats(&self) -> MemoryStats {
+        MemoryStats {
+            current_bytes: self.len,
+            peak_pages: self.peak_pages,
+            grow_count: self.grow_count,
+        }
+    }
+
+    /// Consult `limiter` before every later `grow`, replacing any previous
+    /// limiter; `None` leaves only `max_pages` in force.
+    pub fn set_limiter(&mut self, limiter: Option<Arc<dyn MemoryLimiter>>) {
+        self.limiter = limiter;
+    }
+
+    /// Report this memory's size and growth into `usage` until it is dropped.
+    /// Fails with `Trap::OutOfMemory`, leaving the memory as it was, if its
+    /// current size does not fit in `usage`'s quota or its instance cap is
+    /// reached.
+    pub(crate) fn track(&mut self, usage: Arc<MemoryUsage>) -> Result<()> {
+        usage.add_instance()?;
+        if let Err(trap) = usage.reserve(self.len) {
+            usage.instances.fetch_sub(1, Ordering::Relaxed);
+            return Err(trap);
+        }
+        if let Some(old) = self.usage.take() {
+            old.instances.fetch_sub(1, Ordering::Relaxed);
+            old.release(self.len);
+        }
+        self.usage = Some(usage);
+        Ok(())
+    }
+
+    /// Whether pages are shared copy-on-write with a [`MemoryImage`].
+    ///
+    /// [`MemoryImage`]: crate::image::MemoryImage
+    pub fn is_copy_on_write(&self) -> bool {
+        match &self.storage {
+            Storage::Heap(_) | Storage::External(_) => false,
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            Storage::Mapped(m) => m.is_shared(),
+        }
     }
 
     /// Raw base pointer (for zero-copy host access in the future).
+    #[deprecated(note = "borrow windows through `Memory::view` instead")]
     pub fn base(&self) -> *const u8 {
-        self.data.as_ptr()
+        self.bytes().as_ptr()
     }
 
+    #[deprecated(note = "borrow windows through `Memory::view` instead")]
     pub fn base_mut(&mut self) -> *mut u8 {
-        self.data.as_mut_ptr()
+        self.bytes_mut().as_mut_ptr()
+    }
+
+    /// Borrow this memory for zero-copy access to byte windows.
+    pub fn view(&mut self) -> MemoryView<'_> {
+        MemoryView { memory: self }
+    }
+
+    #[inline]
+    pub(crate) fn bytes(&self) -> &[u8] {
+        match &self.storage {
+            Storage::Heap(v) => v,
+            Storage::External(e) => &e.as_slice()[..self.len],
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            // SAFETY: the mapping is at least `len` bytes and owned by us.
+            Storage::Mapped(m) => unsafe { std::slice::from_raw_parts(m.as_ptr(), self.len) },
+        }
+    }
+
+    #[inline]
+    pub(crate) fn bytes_mut(&mut self) -> &mut [u8] {
+        match &mut self.storage {
+            Storage::Heap(v) => v,
+            Storage::External(e) => &mut e.as_mut_slice()[..self.len],
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            // SAFETY: as above; `&mut self` guarantees exclusive access.
+            Storage::Mapped(m) => unsafe { std::slice::from_raw_parts_mut(m.as_ptr(), self.len) },
+        }
+    }
+
+    /// Base pointer, current size, how many bytes from the base native
+    /// code may load and store directly, and whether every access past
+    /// those faults. The direct range is empty while page protection or
+    /// poison checking needs every access checked here.
+    #[cfg(feature = "cranelift")]
+    pub(crate) fn native_window(&mut self) -> (*mut u8, usize, usize, bool) {
+        let direct = if self.protection.is_empty() && self.poison != Poison::Check {
+            self.len
+        } else {
+            0
+        };
+        let guarded = match &self.storage {
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            Storage::Mapped(m) => m.is_guarded() && direct == self.len,
+            _ => false,
+        };
+        (self.bytes_mut().as_mut_ptr(), self.len, direct, guarded)
     }
 
     /// Grow by `delta` pages. Returns old page count, or error.
     pub fn grow(&mut self, delta: usize) -> Result<usize> {
         let old_pages = self.pages();
-        let new_pages = old_pages + delta;
+        let new_pages = old_pages.checked_add(delta).ok_or(Trap::OutOfMemory)?;
         if let Some(max) = self.max_pages {
             if new_pages > max {
                 return Err(Trap::OutOfMemory);
             }
         }
-        self.data.resize(new_pages * PAGE_SIZE, 0);
+        if let Some(limiter) = &self.limiter {
+            if delta > 0 && !limiter.memory_growing(old_pages, new_pages, self.max_pages) {
+                return Err(Trap::OutOfMemory);
+            }
+        }
+        let new_len = new_pages.checked_mul(PAGE_SIZE).ok_or(Trap::OutOfMemory)?;
+        if let Storage::External(e) = &self.storage {
+            if new_len > e.as_slice().len() {
+                return Err(Trap::OutOfMemory);
+            }
+        }
+        self.reserve_len(new_len)?;
+        if !self.set_accessible(self.len..new_len, true) {
+            self.release_len(new_len);
+            return Err(Trap::OutOfMemory);
+        }
+        if !self.protection.is_empty() {
+            self.protection.resize(new_pages, Protection::ReadWrite);
+        }
+        match &mut self.storage {
+            Storage::Heap(v) => v.resize(new_len, 0),
+            // The buffer's bytes past `len` are whatever the host left there.
+            Storage::External(e) => e.as_mut_slice()[self.len..new_len].fill(0),
+            // Untouched pages of the reservation are already zero.
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            Storage::Mapped(m) if new_len <= m.reserved() => {}
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            Storage::Mapped(_) => {
+                // Outgrew the reservation: move to the heap.
+                let mut v = self.bytes().to_vec();
+                v.resize(new_len, 0);
+                self.storage = Storage::Heap(v);
+            }
+        }
+        if let Some(usage) = &self.usage {
+            usage.grow_count.fetch_add(1, Ordering::Relaxed);
+        }
+        let old_len = self.len;
+        self.set_len(new_len);
+        self.grow_count += 1;
+        self.poison_fresh(old_len..new_len);
         Ok(old_pages)
     }
 
+    /// Return to the state of a freshly created memory of `pages` pages:
+    /// every byte zero, every page read-write. Limits, limiter and
+    /// statistics carry over; the limiter is not consulted.
+    pub fn reset(&mut self, pages: usize) -> Result<()> {
+        let new_len = pages.checked_mul(PAGE_SIZE).ok_or(Trap::OutOfMemory)?;
+        if let Storage::External(e) = &self.storage {
+            if new_len > e.as_slice().len() {
+                return Err(Trap::OutOfMemory);
+            }
+        }
+        self.reserve_len(new_len)?;
+        if !self.set_accessible(self.len..new_len, true) {
+            self.release_len(new_len);
+            return Err(Trap::OutOfMemory);
+        }
+        // Zeroing everything in use also keeps a mapping's pages past the
+        // new length zero, as `grow` expects of its reservation.
+        self.zero(0, self.len);
+        if !self.set_accessible(new_len..self.len, false) {
+            // Pages that should fault do not: give up the guard pages.
+            self.storage = Storage::Heap(vec![0; self.len]);
+        }
+        match &mut self.storage {
+            Storage::Heap(v) => v.resize(new_len, 0),
+            Storage::External(e) => e.as_mut_slice()[..new_len].fill(0),
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            Storage::Mapped(m) if new_len <= m.reserved() => {}
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            Storage::Mapped(_) => self.storage = Storage::Heap(vec![0; new_len]),
+        }
+        self.set_len(new_len);
+        self.protection = Vec::new();
+        self.poison_fresh(0..new_len);
+        Ok(())
+    }
+
+    /// Zero the pages with indices in `pages`. A mapped memory swaps in
+    /// fresh zero pages, releasing their physical memory; heap and external
+    /// memories are only zeroed in place and keep their allocation. Size is
+    /// unchanged, so a guest heap can hand back freed pages without giving
+    /// up address space. The pages must be writable.
+    pub fn discard(&mut self, pages: Range<usize>) -> Result<()> {
+        if pages.start > pages.end || pages.end > self.pages() {
+            return Err(Trap::OutOfBounds);
+        }
+        let offset = pages.start * PAGE_SIZE;
+        let len = pages.len() * PAGE_SIZE;
+        self.check_write(offset, len)?;
+        self.zero(offset, len);
+        self.poison_fresh(offset..offset + len);
+        Ok(())
+    }
+
+    /// Zero a page-aligned byte range, preferring to drop pages over writing.
+    fn zero(&mut self, offset: usize, len: usize) {
+        let released = match &mut self.storage {
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            Storage::Mapped(m) => m.discard(offset, len),
+            Storage::Heap(_) | Storage::External(_) => false,
+        };
+        if !released {
+            self.bytes_mut()[offset..offset + len].fill(0);
+        }
+    }
+
+    /// Take over `other`'s contents and size, keeping this memory's limits,
+    /// limiter, statistics and usage tracking.
+    pub(crate) fn adopt(&mut self, mut other: Memory) -> Result<()> {
+        self.reserve_len(other.len)?;
+        self.storage = std::mem::replace(&mut other.storage, Storage::Heap(Vec::new()));
+        self.set_len(other.len);
+        self.protection = Vec::new();
+        self.set_poison(self.poison);
+        Ok(())
+    }
+
+    /// Poison memory from now on: pages added by `grow` or zeroed by
+    /// `discard`/`reset` are filled with [`POISON_BYTE`], and with
+    /// `Poison::Check` reads of them trap until written. Current contents
+    /// count as initialized.
+    pub fn set_poison(&mut self, poison: Poison) {
+        self.poison = poison;
+        self.written = match poison {
+            Poison::Check => vec![u64::MAX; self.len.div_ceil(64)],
+            _ => Vec::new(),
+        };
+    }
+
+    /// Apply the poison mode to `range`, which has just become fresh memory.
+    fn poison_fresh(&mut self, range: Range<usize>) {
+        if self.poison == Poison::Off {
+            return;
+        }
+        if self.poison == Poison::Check {
+            self.written.resize(self.len.div_ceil(64), 0);
+            set_bits(&mut self.written, range.clone(), false);
+        }
+        self.bytes_mut()[range].fill(POISON_BYTE);
+    }
+
+    /// Before growing to `new_len` bytes, claim the extra bytes from the
+    /// runtime-wide quota.
+    fn reserve_len(&self, new_len: usize) -> Result<()> {
+        match &self.usage {
+            Some(usage) if new_len > self.len => usage.reserve(new_len - self.len),
+            _ => Ok(()),
+        }
+    }
+
+    /// Hand back what `reserve_len(new_len)` claimed, when the growth
+    /// fails after all.
+    fn release_len(&self, new_len: usize) {
+        if let Some(usage) = &self.usage {
+            usage.release(new_len.saturating_sub(self.len));
+        }
+    }
+
+    /// Make `range` of a guarded mapping accessible, or with `accessible`
+    /// false fault again; false if that failed. Other storage has no guard
+    /// pages, and a range past the reservation is left to the move to the
+    /// heap.
+    fn set_accessible(&mut self, range: Range<usize>, accessible: bool) -> bool {
+        if range.is_empty() {
+            return true;
+        }
+        match &mut self.storage {
+            #[cfg(all(
+                target_os = "linux",
+                any(target_arch = "x86_64", target_arch = "aarch64")
+            ))]
+            Storage::Mapped(m) if m.is_guarded() && range.end <= m.reserved() => {
+                m.set_accessible(range, accessible)
+            }
+            _ => true,
+        }
+    }
+
+    /// Change the length, keeping the peak and runtime-wide usage current.
+    /// Growth must already have been claimed with `reserve_len`.
+    fn set_len(&mut self, new_len: usize) {
+        if let Some(usage) = &self.usage {
+            usage.release(self.len.saturating_sub(new_len));
+        }
+        self.len = new_len;
+        self.peak_pages = self.peak_pages.max(new_len / PAGE_SIZE);
+    }
+
+    // ── Protection ───────────────────────────────────────────────────────────
+
+    /// Set the protection of the pages with indices in `pages`.
+    ///
+    /// Protection is enforced on every access made through this type — guest
+    /// loads and stores as well as the host's `read_*`/`write_*` calls — so a
+    /// host that wants to refill a read-only buffer lifts the protection,
+    /// writes, and restores it. Pages added by `grow` are read-write.
+    pub fn protect(&mut self, pages: Range<usize>, protection: Protection) -> Result<()> {
+        if pages.start > pages.end || pages.end > self.pages() {
+            return Err(Trap::OutOfBounds);
+        }
+        if self.protection.is_empty() {
+            if protection == Protection::ReadWrite {
+                return Ok(());
+            }
+            self.protection = vec![Protection::ReadWrite; self.pages()];
+        }
+        self.protection[pages].fill(protection);
+        if self.protection.iter().all(|p| *p == Protection::ReadWrite) {
+            self.protection = Vec::new();
+        }
+        Ok(())
+    }
+
+    /// Protection of page `page`.
+    pub fn protection(&self, page: usize) -> Protection {
+        self.protection
+            .get(page)
+            .copied()
+            .unwrap_or(Protection::ReadWrite)
+    }
+
     fn check(&self, offset: usize, len: usize) -> Result<()> {
         if offset
             .checked_add(len)
-            .map(|end| end <= self.data.len())
+            .map(|end| end <= self.len)
             .unwrap_or(false)
         {
             Ok(())
@@ -65,16 +742,69 @@ impl Memory {
         }
     }
 
+    #[inline]
+    fn check_read(&self, offset: usize, len: usize) -> Result<()> {
+        self.check(offset, len)?;
+        if !self.protection.is_empty() {
+            self.check_pages(offset, len, |p| p != Protection::NoAccess)?;
+        }
+        if self.poison == Poison::Check {
+            if let Some(addr) = first_clear(&self.written, offset..offset + len) {
+                return Err(Trap::UninitializedRead(addr));
+            }
+        }
+        Ok(())
+    }
+
+    #[inline]
+    fn check_write(&self, offset: usize, len: usize) -> Result<()> {
+        self.check(offset, len)?;
+        if self.protection.is_empty() {
+            return Ok(());
+        }
+        self.check_pages(offset, len, |p| p == Protection::ReadWrite)
+    }
+
+    /// Check a write of `len` bytes at `offset` and borrow them, counting
+    /// them as initialized.
+    #[inline]
+    fn window_mut(&mut self, offset: usize, len: usize) -> Result<&mut [u8]> {
+        self.check_write(offset, len)?;
+        if self.poison == Poison::Check {
+            set_bits(&mut self.written, offset..offset + len, true);
+        }
+        Ok(&mut self.bytes_mut()[offset..offset + len])
+    }
+
+    /// Bounds are already checked; verify every page the access touches.
+    fn check_pages(
+        &self,
+        offset: usize,
+        len: usize,
+        ok: impl Fn(Protection) -> bool,
+    ) -> Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let first = offset / PAGE_SIZE;
+        let last = (offset + len - 1) / PAGE_SIZE;
+        if self.protection[first..=last].iter().all(|p| ok(*p)) {
+            Ok(())
+        } else {
+            Err(Trap::AccessViolation)
+        }
+    }
+
     // ── Typed reads ──────────────────────────────────────────────────────────
 
     pub fn read_u8(&self, offset: usize) -> Result<u8> {
-        self.check(offset, 1)?;
-        Ok(self.data[offset])
+        self.check_read(offset, 1)?;
+        Ok(self.bytes()[offset])
     }
 
     pub fn read_u32(&self, offset: usize) -> Result<u32> {
-        self.check(offset, 4)?;
-        let bytes: [u8; 4] = self.data[offset..offset + 4].try_into().unwrap();
+        self.check_read(offset, 4)?;
+        let bytes: [u8; 4] = self.bytes()[offset..offset + 4].try_into().unwrap();
         Ok(u32::from_le_bytes(bytes))
     }
 
@@ -83,8 +813,8 @@ impl Memory {
     }
 
     pub fn read_u64(&self, offset: usize) -> Result<u64> {
-        self.check(offset, 8)?;
-        let bytes: [u8; 8] = self.data[offset..offset + 8].try_into().unwrap();
+        self.check_read(offset, 8)?;
+        let bytes: [u8; 8] = self.bytes()[offset..offset + 8].try_into().unwrap();
         Ok(u64::from_le_bytes(bytes))
     }
 
@@ -101,21 +831,20 @@ impl Memory {
     }
 
     pub fn read_bytes(&self, offset: usize, len: usize) -> Result<&[u8]> {
-        self.check(offset, len)?;
-        Ok(&self.data[offset..offset + len])
+        self.check_read(offset, len)?;
+        Ok(&self.bytes()[offset..offset + len])
     }
 
     // ── Typed writes ─────────────────────────────────────────────────────────
 
     pub fn write_u8(&mut self, offset: usize, val: u8) -> Result<()> {
-        self.check(offset, 1)?;
-        self.data[offset] = val;
+        self.window_mut(offset, 1)?[0] = val;
         Ok(())
     }
 
     pub fn write_u32(&mut self, offset: usize, val: u32) -> Result<()> {
-        self.check(offset, 4)?;
-        self.data[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+        self.window_mut(offset, 4)?
+            .copy_from_slice(&val.to_le_bytes());
         Ok(())
     }
 
@@ -124,8 +853,8 @@ impl Memory {
     }
 
     pub fn write_u64(&mut self, offset: usize, val: u64) -> Result<()> {
-        self.check(offset, 8)?;
-        self.data[offset..offset + 8].copy_from_slice(&val.to_le_bytes());
+        self.window_mut(offset, 8)?
+            .copy_from_slice(&val.to_le_bytes());
         Ok(())
     }
 
@@ -142,10 +871,366 @@ impl Memory {
     }
 
     pub fn write_bytes(&mut self, offset: usize, bytes: &[u8]) -> Result<()> {
-        self.check(offset, bytes.len())?;
-        self.data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        self.window_mut(offset, bytes.len())?.copy_from_slice(bytes);
+        Ok(())
+    }
+
+    // ── Bulk copies ──────────────────────────────────────────────────────────
+
+    /// Copy `buf.len()` bytes starting at `offset` into `buf`.
+    pub fn read_into(&self, offset: usize, buf: &mut [u8]) -> Result<()> {
+        buf.copy_from_slice(self.read_bytes(offset, buf.len())?);
         Ok(())
     }
+
+    /// Copy `buf` into memory starting at `offset`.
+    pub fn write_from(&mut self, offset: usize, buf: &[u8]) -> Result<()> {
+        self.write_bytes(offset, buf)
+    }
+
+    /// Fill `bufs` in order from consecutive memory starting at `offset`,
+    /// returning the number of bytes copied. The whole range is checked
+    /// before anything is copied.
+    pub fn read_vectored(&self, offset: usize, bufs: &mut [IoSliceMut<'_>]) -> Result<usize> {
+        let total = total_len(bufs.iter().map(|b| b.len()))?;
+        let mut src = self.read_bytes(offset, total)?;
+        for buf in bufs {
+            let (head, rest) = src.split_at(buf.len());
+            buf.copy_from_slice(head);
+            src = rest;
+        }
+        Ok(total)
+    }
+
+    /// Write `bufs` back to back starting at `offset`, returning the number
+    /// of bytes copied. Nothing is written unless the whole range is
+    /// writable.
+    pub fn write_vectored(&mut self, offset: usize, bufs: &[IoSlice<'_>]) -> Result<usize> {
+        let total = total_len(bufs.iter().map(|b| b.len()))?;
+        let mut dst = self.window_mut(offset, total)?;
+        for buf in bufs {
+            let (head, rest) = dst.split_at_mut(buf.len());
+            head.copy_from_slice(buf);
+            dst = rest;
+        }
+        Ok(total)
+    }
+
+    // ── Plain values and slices ──────────────────────────────────────────────
+
+    /// Read one little-endian value of any [`Pod`] type.
+    pub fn read_pod<T: Pod>(&self, offset: usize) -> Result<T> {
+        Ok(T::from_le(self.read_bytes(offset, T::SIZE)?))
+    }
+
+    /// Write one value of any [`Pod`] type, little-endian.
+    pub fn write_pod<T: Pod>(&mut self, offset: usize, val: T) -> Result<()> {
+        self.write_slice(offset, &[val])
+    }
+
+    /// Read `count` consecutive values starting at `offset`. The whole range
+    /// is checked once, up front.
+    pub fn read_slice<T: Pod>(&self, offset: usize, count: usize) -> Result<Vec<T>> {
+        let len = count.checked_mul(T::SIZE).ok_or(Trap::OutOfBounds)?;
+        let bytes = self.read_bytes(offset, len)?;
+        Ok(bytes.chunks_exact(T::SIZE).map(T::from_le).collect())
+    }
+
+    /// Write `vals` consecutively starting at `offset`. Nothing is written
+    /// unless the whole range is writable.
+    pub fn write_slice<T: Pod>(&mut self, offset: usize, vals: &[T]) -> Result<()> {
+        let len = vals.len().checked_mul(T::SIZE).ok_or(Trap::OutOfBounds)?;
+        let dst = self.window_mut(offset, len)?;
+        for (chunk, val) in dst.chunks_exact_mut(T::SIZE).zip(vals) {
+            val.write_le(chunk);
+        }
+        Ok(())
+    }
+
+    pub fn read_slice_i32(&self, offset: usize, count: usize) -> Result<Vec<i32>> {
+        self.read_slice(offset, count)
+    }
+
+    pub fn read_slice_i64(&self, offset: usize, count: usize) -> Result<Vec<i64>> {
+        self.read_slice(offset, count)
+    }
+
+    pub fn read_slice_f32(&self, offset: usize, count: usize) -> Result<Vec<f32>> {
+        self.read_slice(offset, count)
+    }
+
+    pub fn read_slice_f64(&self, offset: usize, count: usize) -> Result<Vec<f64>> {
+        self.read_slice(offset, count)
+    }
+
+    pub fn write_slice_i32(&mut self, offset: usize, vals: &[i32]) -> Result<()> {
+        self.write_slice(offset, vals)
+    }
+
+    pub fn write_slice_i64(&mut self, offset: usize, vals: &[i64]) -> Result<()> {
+        self.write_slice(offset, vals)
+    }
+
+    pub fn write_slice_f32(&mut self, offset: usize, vals: &[f32]) -> Result<()> {
+        self.write_slice(offset, vals)
+    }
+
+    pub fn write_slice_f64(&mut self, offset: usize, vals: &[f64]) -> Result<()> {
+        self.write_slice(offset, vals)
+    }
+
+    // ── Inspection ───────────────────────────────────────────────────────────
+
+    /// The bytes in `range` as 16-byte rows, for debugging. The range is
+    /// clamped to the current size, and page protection is ignored.
+    pub fn inspect(&self, range: Range<usize>) -> impl Iterator<Item = MemoryRow<'_>> {
+        let end = range.end.min(self.len);
+        let start = range.start.min(end);
+        self.bytes()[start..end]
+            .chunks(HEX_ROW)
+            .enumerate()
+            .map(move |(i, bytes)| MemoryRow {
+                offset: start + i * HEX_ROW,
+                bytes,
+            })
+    }
+
+    /// Render `range` in `hexdump -C` style, one row per line. Runs of
+    /// identical rows after the first are collapsed to a single `*`.
+    pub fn hexdump(&self, range: Range<usize>) -> String {
+        let mut out = String::new();
+        let mut prev: Option<&[u8]> = None;
+        let mut skipping = false;
+        for row in self.inspect(range) {
+            if prev == Some(row.bytes) {
+                if !skipping {
+                    out.push_str("*\n");
+                    skipping = true;
+                }
+                continue;
+            }
+            out.push_str(&row.to_string());
+            out.push('\n');
+            prev = Some(row.bytes);
+            skipping = false;
+        }
+        out
+    }
+
+    // ── Strings ──────────────────────────────────────────────────────────────
+
+    /// Borrow `len` bytes at `ptr` as UTF-8.
+    pub fn read_str(&self, ptr: usize, len: usize) -> Result<&str> {
+        std::str::from_utf8(self.read_bytes(ptr, len)?).map_err(|_| Trap::InvalidUtf8)
+    }
+
+    /// Borrow the NUL-terminated UTF-8 string at `ptr`, without the NUL.
+    /// A string that runs off the end of memory is `OutOfBounds`.
+    pub fn read_cstr(&self, ptr: usize) -> Result<&str> {
+        let tail = self.bytes().get(ptr..).ok_or(Trap::OutOfBounds)?;
+        let len = tail.iter().position(|&b| b == 0).ok_or(Trap::OutOfBounds)?;
+        // Only the string and its terminator are accessed.
+        self.check_read(ptr, len + 1)?;
+        self.read_str(ptr, len)
+    }
+
+    /// Read a string passed as a packed pointer/length pair (see [`pack_ptr_len`]).
+    pub fn read_str_packed(&self, packed: i64) -> Result<&str> {
+        let (ptr, len) = unpack_ptr_len(packed);
+        self.read_str(ptr as usize, len as usize)
+    }
+
+    /// Copy `s` to `ptr` without a terminator, returning its length in bytes.
+    pub fn write_str(&mut self, ptr: usize, s: &str) -> Result<usize> {
+        self.write_bytes(ptr, s.as_bytes())?;
+        Ok(s.len())
+    }
+}
+
+const HEX_ROW: usize = 16;
+
+/// One row of [`Memory::inspect`]: up to 16 bytes and where they start.
+/// Displays as a `hexdump -C` line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryRow<'a> {
+    pub offset: usize,
+    pub bytes: &'a [u8],
+}
+
+impl fmt::Display for MemoryRow<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:08x} ", self.offset)?;
+        for i in 0..HEX_ROW {
+            if i == HEX_ROW / 2 {
+                f.write_str(" ")?;
+            }
+            match self.bytes.get(i) {
+                Some(b) => write!(f, " {b:02x}")?,
+                None => f.write_str("   ")?,
+            }
+        }
+        f.write_str("  |")?;
+        for &b in self.bytes {
+            let c = if b.is_ascii_graphic() || b == b' ' {
+                b as char
+            } else {
+                '.'
+            };
+            write!(f, "{c}")?;
+        }
+        f.write_str("|")
+    }
+}
+
+/// Exclusive borrow of a [`Memory`] handing out checked byte windows.
+///
+/// Windows borrow the view, so the borrow checker rules out the dangling
+/// and aliasing bugs of working from a raw base pointer: memory cannot grow
+/// or move while a window is alive, and a mutable window is unique. Bounds
+/// and page protection are checked when each window is taken.
+pub struct MemoryView<'a> {
+    memory: &'a mut Memory,
+}
+
+impl MemoryView<'_> {
+    /// Current size in bytes.
+    pub fn size(&self) -> usize {
+        self.memory.size()
+    }
+
+    /// Borrow `len` bytes at `offset` for reading.
+    pub fn slice(&self, offset: usize, len: usize) -> Result<&[u8]> {
+        self.memory.read_bytes(offset, len)
+    }
+
+    /// Borrow `len` bytes at `offset` for writing. Under `Poison::Check`
+    /// every byte of the window counts as written from now on, whether or
+    /// not the caller stores to it, and reading it back through the window
+    /// is not checked. Borrow only the bytes you fill, or store with
+    /// [`write`](Self::write), which marks just the bytes it stores.
+    pub fn slice_mut(&mut self, offset: usize, len: usize) -> Result<&mut [u8]> {
+        self.memory.window_mut(offset, len)
+    }
+
+    /// Borrow `len` bytes at `ptr` as UTF-8.
+    pub fn read_str(&self, ptr: usize, len: usize) -> Result<&str> {
+        self.memory.read_str(ptr, len)
+    }
+
+    pub fn read<T: Pod>(&self, offset: usize) -> Result<T> {
+        self.memory.read_pod(offset)
+    }
+
+    pub fn write<T: Pod>(&mut self, offset: usize, val: T) -> Result<()> {
+        self.memory.write_pod(offset, val)
+    }
+}
+
+/// Set or clear bits `range` of a bitmap.
+fn set_bits(bits: &mut [u64], range: Range<usize>, on: bool) {
+    let mut i = range.start;
+    while i < range.end {
+        let (word, bit) = (i / 64, i % 64);
+        let n = (64 - bit).min(range.end - i);
+        let mask = (u64::MAX >> (64 - n)) << bit;
+        if on {
+            bits[word] |= mask;
+        } else {
+            bits[word] &= !mask;
+        }
+        i += n;
+    }
+}
+
+/// Index of the first clear bit in `range` of a bitmap.
+fn first_clear(bits: &[u64], range: Range<usize>) -> Option<usize> {
+    let mut i = range.start;
+    while i < range.end {
+        let (word, bit) = (i / 64, i % 64);
+        let n = (64 - bit).min(range.end - i);
+        let missing = !bits[word] & ((u64::MAX >> (64 - n)) << bit);
+        if missing != 0 {
+            return Some(word * 64 + missing.trailing_zeros() as usize);
+        }
+        i += n;
+    }
+    None
+}
+
+fn total_len(mut lens: impl Iterator<Item = usize>) -> Result<usize> {
+    lens.try_fold(0usize, |acc, len| acc.checked_add(len))
+        .ok_or(Trap::OutOfBounds)
+}
+
+/// A fixed-size value stored in guest memory as little-endian bytes,
+/// readable and writable with [`Memory::read_pod`], [`Memory::read_slice`]
+/// and friends. Implemented for the primitive integer and float types.
+pub trait Pod: Copy {
+    /// Encoded size in bytes.
+    const SIZE: usize;
+
+    /// Decode from exactly `SIZE` bytes.
+    fn from_le(bytes: &[u8]) -> Self;
+
+    /// Encode into exactly `SIZE` bytes.
+    fn write_le(self, out: &mut [u8]);
+}
+
+macro_rules! impl_pod {
+    ($($t:ty),*) => {$(
+        impl Pod for $t {
+            const SIZE: usize = std::mem::size_of::<$t>();
+
+            #[inline]
+            fn from_le(bytes: &[u8]) -> Self {
+                <$t>::from_le_bytes(bytes.try_into().unwrap())
+            }
+
+            #[inline]
+            fn write_le(self, out: &mut [u8]) {
+                out.copy_from_slice(&self.to_le_bytes());
+            }
+        }
+    )*};
+}
+
+impl_pod!(u8, i8, u16, i16, u32, i32, u64, i64, f32, f64);
+
+/// Pack a guest pointer and length into one `i64` — pointer in the low 32
+/// bits, length in the high 32 — so a guest function can return a string
+/// or buffer as a single value.
+pub fn pack_ptr_len(ptr: u32, len: u32) -> i64 {
+    ((len as u64) << 32 | ptr as u64) as i64
+}
+
+/// Split a value built by [`pack_ptr_len`] into `(ptr, len)`.
+pub fn unpack_ptr_len(packed: i64) -> (u32, u32) {
+    let bits = packed as u64;
+    (bits as u32, (bits >> 32) as u32)
+}
+
+/// Prepared code an instance holds against its runtime's code quota,
+/// released when dropped.
+pub(crate) struct CodeCharge {
+    usage: Arc<MemoryUsage>,
+    bytes: usize,
+}
+
+impl Drop for CodeCharge {
+    fn drop(&mut self) {
+        self.usage
+            .code_bytes
+            .fetch_sub(self.bytes, Ordering::Relaxed);
+    }
+}
+
+impl Drop for Memory {
+    fn drop(&mut self) {
+        if let Some(usage) = &self.usage {
+            usage.instances.fetch_sub(1, Ordering::Relaxed);
+            usage.release(self.len);
+        }
+    }
 }
 
 #[cfg(test)]
@@ -167,6 +1252,68 @@ mod tests {
         assert_eq!(m.pages(), 3);
     }
 
+    #[test]
+    fn reset() {
+        let mut m = Memory::new(1, Some(4));
+        m.grow(2).unwrap();
+        m.write_u32(PAGE_SIZE * 2, 5).unwrap();
+        m.protect(0..1, Protection::NoAccess).unwrap();
+        m.reset(1).unwrap();
+        assert_eq!(m.pages(), 1);
+        assert_eq!(m.read_u32(0).unwrap(), 0);
+        assert_eq!(m.stats().peak_pages, 3);
+        m.grow(2).unwrap();
+        assert_eq!(m.read_u32(PAGE_SIZE * 2).unwrap(), 0);
+    }
+
+    #[test]
+    fn discard() {
+        let mut m = Memory::new(3, None);
+        m.write_u32(PAGE_SIZE + 4, 9).unwrap();
+        m.write_u32(2 * PAGE_SIZE, 9).unwrap();
+        m.discard(1..2).unwrap();
+        assert_eq!(m.read_u32(PAGE_SIZE + 4).unwrap(), 0);
+        assert_eq!(m.read_u32(2 * PAGE_SIZE).unwrap(), 9);
+        assert_eq!(m.pages(), 3);
+        assert_eq!(m.discard(2..4), Err(Trap::OutOfBounds));
+        m.protect(2..3, Protection::ReadOnly).unwrap();
+        assert_eq!(m.discard(2..3), Err(Trap::AccessViolation));
+    }
+
+    #[test]
+    fn poison() {
+        let mut m = Memory::new(1, None);
+        m.write_u32(0, 1).unwrap();
+        m.set_poison(Poison::Check);
+        assert_eq!(m.read_u32(0).unwrap(), 1);
+        m.grow(1).unwrap();
+        assert_eq!(
+            m.read_u8(PAGE_SIZE + 70),
+            Err(Trap::UninitializedRead(PAGE_SIZE + 70))
+        );
+        m.write_u32(PAGE_SIZE + 70, 2).unwrap();
+        assert_eq!(m.read_u32(PAGE_SIZE + 70).unwrap(), 2);
+        assert_eq!(
+            m.read_u64(PAGE_SIZE + 66),
+            Err(Trap::UninitializedRead(PAGE_SIZE + 66))
+        );
+        assert_eq!(
+            m.read_bytes(PAGE_SIZE + 70, 6),
+            Err(Trap::UninitializedRead(PAGE_SIZE + 74))
+        );
+        m.discard(0..1).unwrap();
+        assert_eq!(m.read_u8(0), Err(Trap::UninitializedRead(0)));
+
+        // A mutable window counts as written, stored to or not.
+        m.view().slice_mut(8, 4).unwrap();
+        assert_eq!(m.read_u32(8).unwrap(), u32::from_ne_bytes([POISON_BYTE; 4]));
+        assert_eq!(m.read_u8(12), Err(Trap::UninitializedRead(12)));
+
+        m.set_poison(Poison::Fill);
+        m.grow(1).unwrap();
+        assert_eq!(m.read_u8(2 * PAGE_SIZE).unwrap(), POISON_BYTE);
+    }
+
     #[test]
     fn grow_exceed_limit() {
         let mut m = Memory::new(1, Some(2));
@@ -189,11 +1336,126 @@ mod tests {
         assert_eq!(m.read_u32(PAGE_SIZE - 2), Err(Trap::OutOfBounds));
     }
 
+    #[test]
+    fn bulk_copies() {
+        let mut m = Memory::new(1, None);
+        m.write_from(10, b"abc").unwrap();
+        let mut buf = [0u8; 3];
+        m.read_into(10, &mut buf).unwrap();
+        assert_eq!(&buf, b"abc");
+
+        let n = m
+            .write_vectored(
+                100,
+                &[
+                    IoSlice::new(b"head"),
+                    IoSlice::new(b""),
+                    IoSlice::new(b"tail"),
+                ],
+            )
+            .unwrap();
+        assert_eq!(n, 8);
+        let (mut a, mut b) = ([0u8; 2], [0u8; 6]);
+        let n = m
+            .read_vectored(100, &mut [IoSliceMut::new(&mut a), IoSliceMut::new(&mut b)])
+            .unwrap();
+        assert_eq!((n, &a, &b), (8, b"he", b"adtail"));
+
+        // All or nothing.
+        let end = PAGE_SIZE - 4;
+        assert_eq!(
+            m.write_vectored(end, &[IoSlice::new(b"1234"), IoSlice::new(b"5")]),
+            Err(Trap::OutOfBounds)
+        );
+        assert_eq!(m.read_u32(end).unwrap(), 0);
+        assert_eq!(m.read_into(end, &mut [0u8; 5]), Err(Trap::OutOfBounds));
+    }
+
+    #[test]
+    fn slices() {
+        let mut m = Memory::new(1, None);
+        m.write_slice_i32(8, &[1, -2, 3]).unwrap();
+        assert_eq!(m.read_slice_i32(8, 3).unwrap(), vec![1, -2, 3]);
+        assert_eq!(m.read_i32(12).unwrap(), -2);
+
+        m.write_slice_f32(64, &[0.5, -1.25]).unwrap();
+        assert_eq!(m.read_slice_f32(64, 2).unwrap(), vec![0.5, -1.25]);
+        m.write_pod(100, 0xBEEFu16).unwrap();
+        assert_eq!(m.read_pod::<u16>(100).unwrap(), 0xBEEF);
+        assert!(m.read_slice::<u64>(0, 0).unwrap().is_empty());
+
+        // A range that runs off the end fails without a partial write.
+        assert_eq!(
+            m.write_slice_i64(PAGE_SIZE - 8, &[7, 7]),
+            Err(Trap::OutOfBounds)
+        );
+        assert_eq!(m.read_i64(PAGE_SIZE - 8).unwrap(), 0);
+        assert_eq!(m.read_slice_f64(0, usize::MAX), Err(Trap::OutOfBounds));
+    }
+
+    #[test]
+    fn hexdump() {
+        let mut m = Memory::new(1, None);
+        m.write_bytes(0x10, b"Hello, rune!\0\x01").unwrap();
+        let rows: Vec<_> = m.inspect(0x10..0x14).collect();
+        assert_eq!(rows.len(), 1);
+        assert_eq!((rows[0].offset, rows[0].bytes), (0x10, &b"Hell"[..]));
+
+        assert_eq!(
+            m.hexdump(0..0x50),
+            "00000000  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|\n\
+             00000010  48 65 6c 6c 6f 2c 20 72  75 6e 65 21 00 01 00 00  |Hello, rune!....|\n\
+             00000020  00 00 00 00 00 00 00 00  00 00 00 00 00 00 00 00  |................|\n\
+             *\n"
+        );
+        // Clamped to the memory's size.
+        assert_eq!(m.inspect(PAGE_SIZE - 4..PAGE_SIZE + 100).count(), 1);
+        assert_eq!(m.hexdump(PAGE_SIZE + 1..PAGE_SIZE + 2), "");
+    }
+
+    #[test]
+    fn protection_enforced() {
+        let mut m = Memory::new(3, None);
+        m.write_u32(PAGE_SIZE, 7).unwrap();
+        m.protect(1..2, Protection::ReadOnly).unwrap();
+        m.protect(2..3, Protection::NoAccess).unwrap();
+
+        assert_eq!(m.read_u32(PAGE_SIZE).unwrap(), 7);
+        assert_eq!(m.write_u32(PAGE_SIZE, 8), Err(Trap::AccessViolation));
+        // An access straddling into a protected page is rejected as a whole.
+        assert_eq!(m.write_u32(PAGE_SIZE - 2, 0), Err(Trap::AccessViolation));
+        assert_eq!(m.read_u8(2 * PAGE_SIZE), Err(Trap::AccessViolation));
+        m.write_u32(0, 1).unwrap();
+
+        m.grow(1).unwrap();
+        assert_eq!(m.protection(3), Protection::ReadWrite);
+        m.write_u8(3 * PAGE_SIZE, 1).unwrap();
+
+        m.protect(0..4, Protection::ReadWrite).unwrap();
+        m.write_u32(PAGE_SIZE, 8).unwrap();
+        assert!(m.protect(2..5, Protection::ReadOnly).is_err());
+    }
+
+    #[test]
+    fn strings() {
+        let mut m = Memory::new(1, None);
+        assert_eq!(m.write_str(10, "héllo").unwrap(), 6);
+        assert_eq!(m.read_str(10, 6).unwrap(), "héllo");
+        assert_eq!(m.read_cstr(10).unwrap(), "héllo");
+        assert_eq!(m.read_str_packed(pack_ptr_len(10, 1)).unwrap(), "h");
+        assert_eq!(m.read_str(10, 2), Err(Trap::InvalidUtf8)); // splits 'é'
+        assert_eq!(m.read_str(PAGE_SIZE - 1, 2), Err(Trap::OutOfBounds));
+
+        m.write_bytes(PAGE_SIZE - 3, b"abc").unwrap();
+        assert_eq!(m.read_cstr(PAGE_SIZE - 3), Err(Trap::OutOfBounds));
+        assert_eq!(unpack_ptr_len(pack_ptr_len(u32::MAX, 7)), (u32::MAX, 7));
+    }
+
     #[test]
     fn zeroed_initial() {
         let m = Memory::new(1, None);
         for i in 0..PAGE_SIZE {
-            assert_eq!(m.data[i], 0);
+            assert_eq!(m.bytes()[i], 0);
         }
     }
 }
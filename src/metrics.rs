@@ -0,0 +1,111 @@
+//! Runtime-wide activity counters, behind
+//! [`Runtime::metrics`](crate::runtime::Runtime::metrics).
+//!
+//! Every instance a runtime creates reports into the same counters, so one
+//! snapshot describes the whole host. They are plain relaxed atomics: cheap
+//! enough to stay on for every call, but a snapshot taken while other
+//! threads are calling is not a consistent cut across fields.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use crate::{
+    memory::{MemoryUsage, RuntimeMemoryStats},
+    trap::{Result, TRAP_KINDS},
+    types::Val,
+};
+
+/// Callback handed a snapshot by [`RuntimeConfig::set_metrics_exporter`].
+///
+/// [`RuntimeConfig::set_metrics_exporter`]: crate::config::RuntimeConfig::set_metrics_exporter
+pub type MetricsExporter = dyn Fn(&RuntimeMetrics) + Send + Sync;
+
+/// Snapshot of a runtime's counters.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RuntimeMetrics {
+    /// Instances created successfully, live or dropped.
+    pub instantiations: u64,
+    /// Instances still alive.
+    pub active_instances: usize,
+    /// Calls into exports from the host, including ones that trapped.
+    pub calls: u64,
+    /// Calls that failed, keyed by [`Trap::kind`]. Kinds that never
+    /// occurred are absent.
+    ///
+    /// [`Trap::kind`]: crate::trap::Trap::kind
+    pub traps: BTreeMap<&'static str, u64>,
+    /// Memory held by the runtime's instances.
+    pub memory: RuntimeMemoryStats,
+    /// Bytes of prepared code held by the runtime's instances, counted
+    /// against [`RuntimeConfig::set_code_quota`].
+    ///
+    /// [`RuntimeConfig::set_code_quota`]: crate::config::RuntimeConfig::set_code_quota
+    pub code_bytes: usize,
+}
+
+impl RuntimeMetrics {
+    /// Calls that failed, of any kind.
+    pub fn total_traps(&self) -> u64 {
+        self.traps.values().sum()
+    }
+}
+
+/// The counters themselves, shared by a runtime and its instances.
+pub(crate) struct Metrics {
+    usage: Arc<MemoryUsage>,
+    instantiations: AtomicU64,
+    calls: AtomicU64,
+    traps: [AtomicU64; TRAP_KINDS.len()],
+    exporter: Option<(u64, Arc<MetricsExporter>)>,
+}
+
+impl Metrics {
+    pub(crate) fn new(
+        usage: Arc<MemoryUsage>,
+        exporter: Option<(u64, Arc<MetricsExporter>)>,
+    ) -> Self {
+        Metrics {
+            usage,
+            instantiations: AtomicU64::new(0),
+            calls: AtomicU64::new(0),
+            traps: std::array::from_fn(|_| AtomicU64::new(0)),
+            exporter,
+        }
+    }
+
+    pub(crate) fn instantiated(&self) {
+        self.instantiations.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Count a finished call, and run the exporter if it is due.
+    pub(crate) fn called(&self, result: &Result<Option<Val>>) {
+        if let Err(trap) = result {
+            self.traps[trap.kind_index()].fetch_add(1, Ordering::Relaxed);
+        }
+        let calls = self.calls.fetch_add(1, Ordering::Relaxed) + 1;
+        if let Some((every, export)) = &self.exporter {
+            if calls.is_multiple_of(*every) {
+                export(&self.snapshot());
+            }
+        }
+    }
+
+    pub(crate) fn snapshot(&self) -> RuntimeMetrics {
+        let memory = self.usage.snapshot();
+        let traps = TRAP_KINDS
+            .iter()
+            .zip(&self.traps)
+            .map(|(&kind, count)| (kind, count.load(Ordering::Relaxed)))
+            .filter(|&(_, count)| count > 0)
+            .collect();
+        RuntimeMetrics {
+            instantiations: self.instantiations.load(Ordering::Relaxed),
+            active_instances: memory.instances,
+            calls: self.calls.load(Ordering::Relaxed),
+            traps,
+            memory,
+            code_bytes: self.usage.code_bytes(),
+        }
+    }
+}
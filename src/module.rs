@@ -2,29 +2,69 @@
 
 //! Module format and serialization.
 
+use std::fmt;
+
 use crate::{
+    compat::{ApiVersion, HostRequirements},
+    extension::{EXT_OPCODE_FIRST, EXT_OPCODE_LAST},
+    features::Features,
+    hash::{sha256, Sha256},
     ir::Function,
+    sourcemap::{SourceLoc, SourceMap},
     trap::{Result, Trap},
-    types::{FuncType, Val, ValType},
+    types::{FuncType, ValType},
 };
 
 /// Magic bytes at the start of every .rune file.
 pub const MAGIC: [u8; 4] = *b"RUNE";
-/// Format version this implementation supports.
-pub const VERSION: u32 = 0x0001;
+/// Format version written by this implementation.
+pub const VERSION: u32 = 0x0003;
+/// Oldest format version this implementation can still read.
+pub const MIN_VERSION: u32 = 0x0001;
 
-// ── Host function registry ───────────────────────────────────────────────────
+// ── Imports ──────────────────────────────────────────────────────────────────
 
-/// Signature and callback for a host-provided function.
-pub struct HostFuncDef {
+/// A host function a module declares it needs, resolved by name through a
+/// [`Linker`](crate::linker::Linker) at instantiation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Import {
+    /// Namespace, such as `"env"`.
+    pub module: String,
     pub name: String,
     pub ty: FuncType,
-    pub func: Box<dyn Fn(&[Val]) -> Result<Option<Val>> + Send + Sync>,
+}
+
+impl fmt::Display for Import {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.module, self.name)
+    }
+}
+
+/// A host global a module declares it reads, resolved by name through a
+/// [`Linker`](crate::linker::Linker) at instantiation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GlobalImport {
+    pub module: String,
+    pub name: String,
+    pub ty: ValType,
+    /// Whether the module writes it with `GlobalSet`.
+    pub mutable: bool,
+}
+
+impl fmt::Display for GlobalImport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{}", self.module, self.name)
+    }
 }
 
 // ── Module ───────────────────────────────────────────────────────────────────
 
 /// A loaded Rune module, ready to be instantiated.
+///
+/// Plain data: it holds no host closures, so it serializes to exactly what
+/// it contains, and one module can be instantiated against different host
+/// bindings through different [`Linker`](crate::linker::Linker)s.
+#[derive(Debug, Clone)]
 pub struct Module {
     /// All functions defined in this module (internal + extern stubs).
     pub functions: Vec<Function>,
@@ -32,12 +72,24 @@ pub struct Module {
     pub exports: Vec<(String, u32)>,
     /// Data segments: (memory offset, bytes).
     pub data_segments: Vec<(u32, Vec<u8>)>,
+    /// Data segments stored out-of-line; bytes are supplied at instantiation.
+    pub external_segments: Vec<ExternalSegment>,
     /// Initial page count for linear memory.
     pub initial_memory_pages: usize,
     /// Maximum page count (None = unlimited).
     pub max_memory_pages: Option<usize>,
-    /// Host functions registered by the embedder.
-    pub host_funcs: Vec<HostFuncDef>,
+    /// Host functions the module imports by name. `CallHost(i)` calls
+    /// whatever import `i` is linked to.
+    pub imports: Vec<Import>,
+    /// Host globals the module imports by name. `GlobalGet(i)` and
+    /// `GlobalSet(i)` access whatever global import `i` is linked to.
+    pub global_imports: Vec<GlobalImport>,
+    /// Optional debug info mapping ops back to frontend source.
+    pub source_map: Option<SourceMap>,
+    /// Optional op families the module relies on.
+    pub required_features: Features,
+    /// Host API version and capabilities the module was built against.
+    pub host_requirements: HostRequirements,
 }
 
 impl Module {
@@ -47,22 +99,50 @@ impl Module {
             functions: Vec::new(),
             exports: Vec::new(),
             data_segments: Vec::new(),
+            external_segments: Vec::new(),
             initial_memory_pages: 1,
             max_memory_pages: None,
-            host_funcs: Vec::new(),
+            imports: Vec::new(),
+            global_imports: Vec::new(),
+            source_map: None,
+            required_features: Features::NONE,
+            host_requirements: HostRequirements::default(),
         }
     }
 
-    /// Register a host function. Must be called before instantiation.
-    pub fn register_host<F>(&mut self, name: impl Into<String>, ty: FuncType, func: F)
-    where
-        F: Fn(&[Val]) -> Result<Option<Val>> + Send + Sync + 'static,
-    {
-        self.host_funcs.push(HostFuncDef {
+    /// Declare an import of `module.name` with signature `ty`, returning the
+    /// index its `CallHost` ops use.
+    pub fn import(
+        &mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        ty: FuncType,
+    ) -> u32 {
+        self.imports.push(Import {
+            module: module.into(),
             name: name.into(),
             ty,
-            func: Box::new(func),
         });
+        self.imports.len() as u32 - 1
+    }
+
+    /// Declare an import of global `module.name` of type `ty`, returning the
+    /// index its `GlobalGet`/`GlobalSet` ops use. Only a `mutable` import
+    /// may be written.
+    pub fn import_global(
+        &mut self,
+        module: impl Into<String>,
+        name: impl Into<String>,
+        ty: ValType,
+        mutable: bool,
+    ) -> u32 {
+        self.global_imports.push(GlobalImport {
+            module: module.into(),
+            name: name.into(),
+            ty,
+            mutable,
+        });
+        self.global_imports.len() as u32 - 1
     }
 
     /// Find an export by name. Returns function index.
@@ -73,6 +153,201 @@ impl Module {
             .map(|(_, idx)| *idx)
     }
 
+    /// Move every inline data segment of at least `min_len` bytes out-of-line.
+    ///
+    /// Returns the removed `(hash, bytes)` pairs so the caller can put them in
+    /// a blob store; the module keeps only the hash and length.
+    pub fn externalize_segments(&mut self, min_len: usize) -> Vec<([u8; 32], Vec<u8>)> {
+        let mut blobs = Vec::new();
+        let mut kept = Vec::with_capacity(self.data_segments.len());
+        for (offset, bytes) in self.data_segments.drain(..) {
+            if bytes.len() < min_len {
+                kept.push((offset, bytes));
+                continue;
+            }
+            let hash = sha256(&bytes);
+            self.external_segments.push(ExternalSegment {
+                offset,
+                len: bytes.len() as u32,
+                hash,
+            });
+            blobs.push((hash, bytes));
+        }
+        self.data_segments = kept;
+        blobs
+    }
+
+    // ── Digest ───────────────────────────────────────────────────────────────
+
+    /// Stable SHA-256 content hash of the module, for cache keys, signing and
+    /// deduplication.
+    ///
+    /// The digest covers everything that affects execution: memory limits,
+    /// required features, function signatures, locals and bodies, exports, and data segments
+    /// (external ones by hash). It is computed over a canonical encoding
+    /// rather than `to_bytes()`, so it does not change with the binary format
+    /// version, string interning or constant pooling. Exports are hashed in
+    /// name order since their order carries no meaning. Imports are covered
+    /// by name and signature, global imports by name, type and mutability,
+    /// and host requirements by version and capability names. Function names
+    /// and the source map are not covered.
+    ///
+    /// Parts added to the format after the digest was introduced (imports,
+    /// host requirements, global imports and required features) are hashed
+    /// only when present, so a module without them keeps the digest it has
+    /// always had.
+    pub fn digest(&self) -> [u8; 32] {
+        let mut h = Sha256::new();
+        h.update(b"rune-digest-v1\0");
+        put_u32(&mut h, self.initial_memory_pages as u32);
+        match self.max_memory_pages {
+            Some(max) => {
+                h.update(&[1]);
+                put_u32(&mut h, max as u32);
+            }
+            None => h.update(&[0]),
+        }
+
+        put_u32(&mut h, self.functions.len() as u32);
+        for f in &self.functions {
+            for tys in [&f.ty.params, &f.ty.results, &f.locals] {
+                put_u32(&mut h, tys.len() as u32);
+                for ty in tys {
+                    h.update(&[*ty as u8]);
+                }
+            }
+            put_u32(&mut h, f.body.len() as u32);
+            for op in f.body.iter() {
+                digest_op(&mut h, op);
+            }
+        }
+
+        let mut exports: Vec<_> = self.exports.iter().collect();
+        exports.sort();
+        put_u32(&mut h, exports.len() as u32);
+        for (name, idx) in exports {
+            put_u32(&mut h, name.len() as u32);
+            h.update(name.as_bytes());
+            put_u32(&mut h, *idx);
+        }
+
+        put_u32(&mut h, self.data_segments.len() as u32);
+        for (offset, bytes) in &self.data_segments {
+            put_u32(&mut h, *offset);
+            h.update(&sha256(bytes));
+        }
+        put_u32(&mut h, self.external_segments.len() as u32);
+        for seg in &self.external_segments {
+            put_u32(&mut h, seg.offset);
+            put_u32(&mut h, seg.len);
+            h.update(&seg.hash);
+        }
+        // Appended only when present, so digests of import-free modules are
+        // unchanged from before imports existed.
+        if !self.imports.is_empty() {
+            put_u32(&mut h, self.imports.len() as u32);
+            for import in &self.imports {
+                for part in [&import.module, &import.name] {
+                    put_u32(&mut h, part.len() as u32);
+                    h.update(part.as_bytes());
+                }
+                for tys in [&import.ty.params, &import.ty.results] {
+                    put_u32(&mut h, tys.len() as u32);
+                    for ty in tys {
+                        h.update(&[*ty as u8]);
+                    }
+                }
+            }
+        }
+        // Likewise only when declared.
+        if !self.host_requirements.is_empty() {
+            h.update(b"host\0");
+            encode_host_requirements(&self.host_requirements, &mut |bytes| h.update(bytes));
+        }
+        if !self.global_imports.is_empty() {
+            h.update(b"globals\0");
+            encode_global_imports(&self.global_imports, &mut |bytes| h.update(bytes));
+        }
+        // Including those the code implies, so decoding, which adds them,
+        // leaves the digest alone.
+        let features = self.required_features | implied_features(&self.functions);
+        if !features.is_empty() {
+            h.update(b"features\0");
+            h.update(&features.bits().to_le_bytes());
+        }
+        h.finish()
+    }
+
+    // ── Merging ──────────────────────────────────────────────────────────────
+
+    /// Concatenate `other` onto this module, producing a single module.
+    ///
+    /// Functions, imports, global imports, data segments and exports of
+    /// `other` are appended after those of `self`; every `Call`, `CallHost`,
+    /// `GlobalGet` and `GlobalSet` index in `other`'s bodies and every
+    /// export index is rewritten to match. Export name
+    /// collisions are resolved according to `policy`.
+    ///
+    /// Memory limits are widened to fit both inputs: the larger initial page
+    /// count wins, and the maximum is unlimited if either side is. Host
+    /// requirements are combined, and must agree on the major API version.
+    pub fn merge(mut self, other: Module, policy: CollisionPolicy) -> Result<Module> {
+        self.host_requirements.merge(other.host_requirements)?;
+        let func_base = self.functions.len() as u32;
+        let host_base = self.imports.len() as u32;
+        let global_base = self.global_imports.len() as u32;
+
+        for (name, idx) in other.exports {
+            let idx = idx + func_base;
+            match self.exports.iter().position(|(n, _)| *n == name) {
+                None => self.exports.push((name, idx)),
+                Some(pos) => match &policy {
+                    CollisionPolicy::Error => {
+                        return Err(Trap::InvalidModule(format!(
+                            "duplicate export {name:?} while merging"
+                        )))
+                    }
+                    CollisionPolicy::KeepFirst => {}
+                    CollisionPolicy::KeepSecond => self.exports[pos].1 = idx,
+                    CollisionPolicy::Prefix(prefix) => {
+                        let renamed = format!("{prefix}{name}");
+                        if self.find_export(&renamed).is_some() {
+                            return Err(Trap::InvalidModule(format!(
+                                "duplicate export {renamed:?} while merging"
+                            )));
+                        }
+                        self.exports.push((renamed, idx));
+                    }
+                },
+            }
+        }
+
+        self.functions.extend(
+            other
+                .functions
+                .into_iter()
+                .map(|f| relocate(f, func_base, host_base, global_base)),
+        );
+        self.imports.extend(other.imports);
+        self.global_imports.extend(other.global_imports);
+        self.data_segments.extend(other.data_segments);
+        self.external_segments.extend(other.external_segments);
+        if let Some(theirs) = other.source_map {
+            self.source_map
+                .get_or_insert_with(SourceMap::new)
+                .append(func_base as usize, theirs);
+        }
+
+        self.initial_memory_pages = self.initial_memory_pages.max(other.initial_memory_pages);
+        self.max_memory_pages = match (self.max_memory_pages, other.max_memory_pages) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            _ => None,
+        };
+        self.required_features |= other.required_features;
+
+        Ok(self)
+    }
+
     // ── Serialisation (binary .rune format) ──────────────────────────────────
     //
     // Layout:
@@ -80,17 +355,43 @@ impl Module {
     //   [4]  version (LE u32)
     //   [4]  initial_memory_pages (LE u32)
     //   [4]  max_memory_pages: 0=none, else value (LE u32)
+    //   [8]  required feature bits (LE u64, see `Features`)     (v3+)
+    //   [4]  n_strings, for each: [4] len, [len] UTF-8 bytes      (v2+)
+    //   [4]  n_consts, for each: [8] LE u64 bit pattern          (v2+)
     //   [4]  n_functions (LE u32)
     //   for each function:
-    //     [4]  name_len, name bytes
+    //     [4]  name string index (v1: name_len, name bytes)
     //     [4]  n_params, [n_params] ValType bytes
     //     [4]  n_results, [n_results] ValType bytes
     //     [4]  n_locals, [n_locals] ValType bytes
-    //     [4]  n_ops — ops are stored as bincode via serde_json (text JSON for MVP)
+    //     [4]  ops_len, [ops_len] binary-encoded ops (see below)
     //   [4]  n_exports
-    //   for each export: [4] name_len, name, [4] fn_idx
+    //   for each export: [4] name string index (v1: inline name), [4] fn_idx
     //   [4]  n_data_segments
     //   for each: [4] offset, [4] len, [len] bytes
+    //   optional trailing sections, until end of input:
+    //     [1]  section id, [4] len, [len] payload
+    //   Unknown section ids are skipped, so older readers stay compatible.
+    //
+    // Section 0x01 — source map:
+    //   [4]  n_files, for each: [4] name_len, name bytes
+    //   [4]  n_tables, for each: [4] n_entries,
+    //        for each: [4] op index, [4] file, [4] line, [4] column
+    //
+    // Section 0x02 — external data segments:
+    //   [4]  n_segments, for each: [4] offset, [4] len, [32] SHA-256
+    //
+    // Section 0x03 — imports:
+    //   [4]  n_imports, for each: [4] module_len, module bytes,
+    //        [4] name_len, name bytes, [4] n_params, params, [4] n_results, results
+    //
+    // Section 0x04 — host requirements:
+    //   [1]  has API version, then if 1: [4] major, [4] minor
+    //   [4]  n_capabilities, for each: [4] name_len, name bytes
+    //
+    // Section 0x05 — global imports:
+    //   [4]  n_globals, for each: [4] module_len, module bytes,
+    //        [4] name_len, name bytes, [1] ValType, [1] mutable (0 or 1)
 
     /// Serialize to binary. Returns bytes.
     pub fn to_bytes(&self) -> Vec<u8> {
@@ -99,10 +400,36 @@ impl Module {
         out.extend_from_slice(&VERSION.to_le_bytes());
         out.extend_from_slice(&(self.initial_memory_pages as u32).to_le_bytes());
         out.extend_from_slice(&(self.max_memory_pages.unwrap_or(0) as u32).to_le_bytes());
+        let features = self.required_features | implied_features(&self.functions);
+        out.extend_from_slice(&features.bits().to_le_bytes());
+
+        // Names are interned so a name shared by a function and its export
+        // (or by many generated functions) is stored and decoded once.
+        let mut strings = StringTable::default();
+        let fn_names: Vec<u32> = self
+            .functions
+            .iter()
+            .map(|f| strings.intern(&f.name))
+            .collect();
+        let export_names: Vec<u32> = self
+            .exports
+            .iter()
+            .map(|(n, _)| strings.intern(n))
+            .collect();
+        out.extend_from_slice(&(strings.list.len() as u32).to_le_bytes());
+        for s in &strings.list {
+            write_str(&mut out, s);
+        }
+
+        let pool = ConstPool::build(&self.functions);
+        out.extend_from_slice(&(pool.list.len() as u32).to_le_bytes());
+        for bits in &pool.list {
+            out.extend_from_slice(&bits.to_le_bytes());
+        }
 
         out.extend_from_slice(&(self.functions.len() as u32).to_le_bytes());
-        for f in &self.functions {
-            write_str(&mut out, &f.name);
+        for (f, name) in self.functions.iter().zip(fn_names) {
+            out.extend_from_slice(&name.to_le_bytes());
             write_valtypes(&mut out, &f.ty.params);
             write_valtypes(&mut out, &f.ty.results);
             write_valtypes(&mut out, &f.locals);
@@ -110,14 +437,16 @@ impl Module {
             // This cuts module parse time by ~10x, fixing the cold-start benchmark.
             let mut ops_buf = Vec::with_capacity(f.body.len() * 2);
             for op in f.body.iter() {
-                encode_op(op, &mut ops_buf);
+                if !pool.encode(op, &mut ops_buf) {
+                    encode_op(op, &mut ops_buf);
+                }
             }
             write_bytes_len(&mut out, &ops_buf);
         }
 
         out.extend_from_slice(&(self.exports.len() as u32).to_le_bytes());
-        for (name, idx) in &self.exports {
-            write_str(&mut out, name);
+        for ((_, idx), name) in self.exports.iter().zip(export_names) {
+            out.extend_from_slice(&name.to_le_bytes());
             out.extend_from_slice(&idx.to_le_bytes());
         }
 
@@ -127,113 +456,630 @@ impl Module {
             write_bytes_len(&mut out, bytes);
         }
 
+        if let Some(sm) = &self.source_map {
+            let mut payload = Vec::new();
+            encode_source_map(sm, &mut payload);
+            out.push(SECTION_SOURCE_MAP);
+            write_bytes_len(&mut out, &payload);
+        }
+
+        if !self.external_segments.is_empty() {
+            let mut payload = Vec::new();
+            payload.extend_from_slice(&(self.external_segments.len() as u32).to_le_bytes());
+            for seg in &self.external_segments {
+                payload.extend_from_slice(&seg.offset.to_le_bytes());
+                payload.extend_from_slice(&seg.len.to_le_bytes());
+                payload.extend_from_slice(&seg.hash);
+            }
+            out.push(SECTION_EXTERNAL_DATA);
+            write_bytes_len(&mut out, &payload);
+        }
+
+        if !self.imports.is_empty() {
+            let mut payload = Vec::new();
+            payload.extend_from_slice(&(self.imports.len() as u32).to_le_bytes());
+            for import in &self.imports {
+                write_str(&mut payload, &import.module);
+                write_str(&mut payload, &import.name);
+                write_valtypes(&mut payload, &import.ty.params);
+                write_valtypes(&mut payload, &import.ty.results);
+            }
+            out.push(SECTION_IMPORTS);
+            write_bytes_len(&mut out, &payload);
+        }
+
+        if !self.host_requirements.is_empty() {
+            let mut payload = Vec::new();
+            encode_host_requirements(&self.host_requirements, &mut |bytes| {
+                payload.extend_from_slice(bytes)
+            });
+            out.push(SECTION_HOST_REQUIREMENTS);
+            write_bytes_len(&mut out, &payload);
+        }
+
+        if !self.global_imports.is_empty() {
+            let mut payload = Vec::new();
+            encode_global_imports(&self.global_imports, &mut |bytes| {
+                payload.extend_from_slice(bytes)
+            });
+            out.push(SECTION_GLOBAL_IMPORTS);
+            write_bytes_len(&mut out, &payload);
+        }
+
         out
     }
 
     /// Deserialize from binary bytes.
     pub fn from_bytes(data: &[u8]) -> Result<Self> {
-        let mut cur = 0usize;
+        let (header, mut cur) = Header::parse(data)?;
+        // Checked before the body is decoded: ops of an unsupported family
+        // would otherwise surface as an opaque "unknown opcode" error.
+        header.required_features.check_supported()?;
+        let consts: Vec<u64> = header
+            .consts
+            .chunks_exact(8)
+            .map(|c| u64::from_le_bytes(c.try_into().unwrap()))
+            .collect();
+        let read_name = |cur: &mut usize| header.read_name(data, cur);
 
-        let magic: [u8; 4] = read_arr(data, &mut cur)
-            .ok_or_else(|| Trap::InvalidModule("truncated magic".into()))?;
-        if magic != MAGIC {
-            return Err(Trap::InvalidModule("bad magic bytes".into()));
+        let n_funcs =
+            read_u32(data, &mut cur).ok_or_else(|| malformed("truncated fn count", cur))? as usize;
+
+        let mut functions = Vec::with_capacity(n_funcs);
+        for _ in 0..n_funcs {
+            let name = read_name(&mut cur).ok_or_else(|| malformed("truncated fn name", cur))?;
+            let params =
+                read_valtypes(data, &mut cur).ok_or_else(|| malformed("truncated params", cur))?;
+            let results =
+                read_valtypes(data, &mut cur).ok_or_else(|| malformed("truncated results", cur))?;
+            let locals =
+                read_valtypes(data, &mut cur).ok_or_else(|| malformed("truncated locals", cur))?;
+            let ops_at = cur;
+            let ops_bytes =
+                read_bytes_len(data, &mut cur).ok_or_else(|| malformed("truncated ops", ops_at))?;
+            let body = decode_ops(ops_bytes, &consts)
+                .ok_or_else(|| malformed("invalid binary ops", ops_at))?;
+            functions.push(Function {
+                name,
+                ty: FuncType { params, results },
+                locals,
+                body,
+            });
         }
 
-        let version = read_u32(data, &mut cur)
-            .ok_or_else(|| Trap::InvalidModule("truncated version".into()))?;
-        if version != VERSION {
-            return Err(Trap::InvalidModule(format!(
-                "unsupported version {version:#x}"
-            )));
+        let n_exports =
+            read_u32(data, &mut cur).ok_or_else(|| malformed("truncated exports", cur))? as usize;
+        let mut exports = Vec::with_capacity(n_exports);
+        for _ in 0..n_exports {
+            let name =
+                read_name(&mut cur).ok_or_else(|| malformed("truncated export name", cur))?;
+            let idx =
+                read_u32(data, &mut cur).ok_or_else(|| malformed("truncated export idx", cur))?;
+            exports.push((name, idx));
         }
 
-        let initial_memory_pages = read_u32(data, &mut cur)
-            .ok_or_else(|| Trap::InvalidModule("truncated memory info".into()))?
-            as usize;
-        let max_raw = read_u32(data, &mut cur)
-            .ok_or_else(|| Trap::InvalidModule("truncated memory info".into()))?;
-        let max_memory_pages = if max_raw == 0 {
-            None
-        } else {
-            Some(max_raw as usize)
-        };
+        let n_data = read_u32(data, &mut cur)
+            .ok_or_else(|| malformed("truncated data count", cur))? as usize;
+        let mut data_segments = Vec::with_capacity(n_data);
+        for _ in 0..n_data {
+            let offset =
+                read_u32(data, &mut cur).ok_or_else(|| malformed("truncated data offset", cur))?;
+            let bytes = read_bytes_len(data, &mut cur)
+                .ok_or_else(|| malformed("truncated data bytes", cur))?
+                .to_vec();
+            data_segments.push((offset, bytes));
+        }
+
+        let mut source_map = None;
+        let mut external_segments = Vec::new();
+        let mut imports = Vec::new();
+        let mut global_imports = Vec::new();
+        let mut host_requirements = HostRequirements::default();
+        while cur < data.len() {
+            let at = cur;
+            let id = data[cur];
+            cur += 1;
+            let payload =
+                read_bytes_len(data, &mut cur).ok_or_else(|| malformed("truncated section", at))?;
+            match id {
+                SECTION_SOURCE_MAP => {
+                    source_map = Some(
+                        decode_source_map(payload)
+                            .ok_or_else(|| malformed("invalid source map", at))?,
+                    );
+                }
+                SECTION_EXTERNAL_DATA => {
+                    external_segments = decode_external_segments(payload)
+                        .ok_or_else(|| malformed("invalid external data section", at))?;
+                }
+                SECTION_IMPORTS => {
+                    imports = decode_imports(payload)
+                        .ok_or_else(|| malformed("invalid import section", at))?;
+                }
+                SECTION_HOST_REQUIREMENTS => {
+                    host_requirements = decode_host_requirements(payload)
+                        .ok_or_else(|| malformed("invalid host requirements section", at))?;
+                }
+                SECTION_GLOBAL_IMPORTS => {
+                    global_imports = decode_global_imports(payload)
+                        .ok_or_else(|| malformed("invalid global import section", at))?;
+                }
+                _ => {}
+            }
+        }
+
+        let required_features = header.required_features | implied_features(&functions);
+        Ok(Module {
+            functions,
+            exports,
+            data_segments,
+            external_segments,
+            initial_memory_pages: header.initial_memory_pages,
+            max_memory_pages: header.max_memory_pages,
+            imports,
+            global_imports,
+            source_map,
+            required_features,
+            host_requirements,
+        })
+    }
+
+    /// Read a module's interface — memory limits, required features, function
+    /// signatures, exports, imports and host requirements — without decoding any function body.
+    ///
+    /// Meant for registries and tooling that index many modules: bodies are
+    /// skipped by length, so the cost is proportional to the number of
+    /// functions and exports rather than to code size. Unlike `from_bytes`,
+    /// this succeeds for modules that need features this runtime lacks.
+    pub fn interface(data: &[u8]) -> Result<ModuleInterface> {
+        let (header, mut cur) = Header::parse(data)?;
 
         let n_funcs = read_u32(data, &mut cur)
             .ok_or_else(|| Trap::InvalidModule("truncated fn count".into()))?
             as usize;
-
-        let mut functions = Vec::with_capacity(n_funcs);
+        let mut functions = Vec::with_capacity(n_funcs.min(data.len()));
         for _ in 0..n_funcs {
-            let name = read_str(data, &mut cur)
+            let name = header
+                .read_name(data, &mut cur)
                 .ok_or_else(|| Trap::InvalidModule("truncated fn name".into()))?;
             let params = read_valtypes(data, &mut cur)
                 .ok_or_else(|| Trap::InvalidModule("truncated params".into()))?;
             let results = read_valtypes(data, &mut cur)
                 .ok_or_else(|| Trap::InvalidModule("truncated results".into()))?;
-            let locals = read_valtypes(data, &mut cur)
+            read_valtypes(data, &mut cur)
                 .ok_or_else(|| Trap::InvalidModule("truncated locals".into()))?;
-            let ops_bytes = read_bytes_len(data, &mut cur)
+            read_bytes_len(data, &mut cur)
                 .ok_or_else(|| Trap::InvalidModule("truncated ops".into()))?;
-            let body = decode_ops(ops_bytes)
-                .ok_or_else(|| Trap::InvalidModule("invalid binary ops".into()))?;
-            functions.push(Function {
-                name,
-                ty: FuncType { params, results },
-                locals,
-                body,
-            });
+            functions.push((name, FuncType { params, results }));
         }
 
         let n_exports = read_u32(data, &mut cur)
             .ok_or_else(|| Trap::InvalidModule("truncated exports".into()))?
             as usize;
-        let mut exports = Vec::with_capacity(n_exports);
+        let mut exports = Vec::with_capacity(n_exports.min(data.len()));
         for _ in 0..n_exports {
-            let name = read_str(data, &mut cur)
+            let name = header
+                .read_name(data, &mut cur)
                 .ok_or_else(|| Trap::InvalidModule("truncated export name".into()))?;
-            let idx = read_u32(data, &mut cur)
+            let func_index = read_u32(data, &mut cur)
                 .ok_or_else(|| Trap::InvalidModule("truncated export idx".into()))?;
-            exports.push((name, idx));
+            let ty = functions
+                .get(func_index as usize)
+                .map(|(_, ty)| ty.clone())
+                .ok_or_else(|| {
+                    Trap::InvalidModule(format!(
+                        "export {name:?} refers to missing func#{func_index}"
+                    ))
+                })?;
+            exports.push(ExportInfo {
+                name,
+                func_index,
+                ty,
+            });
         }
 
+        // Imports live in a trailing section, past the data segments.
         let n_data = read_u32(data, &mut cur)
-            .ok_or_else(|| Trap::InvalidModule("truncated data count".into()))?
-            as usize;
-        let mut data_segments = Vec::with_capacity(n_data);
+            .ok_or_else(|| Trap::InvalidModule("truncated data count".into()))?;
         for _ in 0..n_data {
-            let offset = read_u32(data, &mut cur)
-                .ok_or_else(|| Trap::InvalidModule("truncated data offset".into()))?;
-            let bytes = read_bytes_len(data, &mut cur)
-                .ok_or_else(|| Trap::InvalidModule("truncated data bytes".into()))?
-                .to_vec();
-            data_segments.push((offset, bytes));
+            read_u32(data, &mut cur)
+                .and_then(|_| read_bytes_len(data, &mut cur))
+                .ok_or_else(|| Trap::InvalidModule("truncated data segment".into()))?;
+        }
+        let mut imports = Vec::new();
+        let mut global_imports = Vec::new();
+        let mut host_requirements = HostRequirements::default();
+        while cur < data.len() {
+            let id = data[cur];
+            cur += 1;
+            let payload = read_bytes_len(data, &mut cur)
+                .ok_or_else(|| Trap::InvalidModule("truncated section".into()))?;
+            match id {
+                SECTION_IMPORTS => {
+                    imports = decode_imports(payload)
+                        .ok_or_else(|| Trap::InvalidModule("invalid import section".into()))?;
+                }
+                SECTION_HOST_REQUIREMENTS => {
+                    host_requirements = decode_host_requirements(payload).ok_or_else(|| {
+                        Trap::InvalidModule("invalid host requirements section".into())
+                    })?;
+                }
+                SECTION_GLOBAL_IMPORTS => {
+                    global_imports = decode_global_imports(payload).ok_or_else(|| {
+                        Trap::InvalidModule("invalid global import section".into())
+                    })?;
+                }
+                _ => {}
+            }
         }
 
-        Ok(Module {
+        Ok(ModuleInterface {
+            version: header.version,
+            initial_memory_pages: header.initial_memory_pages,
+            max_memory_pages: header.max_memory_pages,
+            required_features: header.required_features,
             functions,
             exports,
-            data_segments,
-            initial_memory_pages,
-            max_memory_pages,
-            host_funcs: Vec::new(),
+            imports,
+            global_imports,
+            host_requirements,
         })
     }
 }
 
+/// What [`Module::interface`] reports about a serialized module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModuleInterface {
+    /// Binary format version the module was written with.
+    pub version: u32,
+    pub initial_memory_pages: usize,
+    pub max_memory_pages: Option<usize>,
+    pub required_features: Features,
+    /// Name and signature of every function, indexed like `Module::functions`.
+    pub functions: Vec<(String, FuncType)>,
+    pub exports: Vec<ExportInfo>,
+    pub imports: Vec<Import>,
+    pub global_imports: Vec<GlobalImport>,
+    pub host_requirements: HostRequirements,
+}
+
+/// An exported function and its signature.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExportInfo {
+    pub name: String,
+    pub func_index: u32,
+    pub ty: FuncType,
+}
+
 impl Default for Module {
     fn default() -> Self {
         Self::new()
     }
 }
 
+/// A data segment whose bytes live outside the module.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExternalSegment {
+    /// Memory offset the bytes are copied to.
+    pub offset: u32,
+    /// Length of the blob in bytes.
+    pub len: u32,
+    /// SHA-256 of the blob; the key used to fetch it from a `BlobStore`.
+    pub hash: [u8; 32],
+}
+
+/// How [`Module::merge`] resolves an export name present in both modules.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CollisionPolicy {
+    /// Fail the merge with `Trap::InvalidModule`.
+    Error,
+    /// Keep the existing export; the incoming one is dropped.
+    KeepFirst,
+    /// Point the export at the incoming function instead.
+    KeepSecond,
+    /// Keep both, exporting the incoming function as `prefix + name`.
+    Prefix(String),
+}
+
+/// Shift the function, host and global indices referenced by `func`'s body.
+fn relocate(func: Function, func_base: u32, host_base: u32, global_base: u32) -> Function {
+    if func_base == 0 && host_base == 0 && global_base == 0 {
+        return func;
+    }
+    let body = func
+        .body
+        .iter()
+        .map(|op| match op {
+            Op::Call(i) => Op::Call(i + func_base),
+            Op::CallHost(i) => Op::CallHost(i + host_base),
+            Op::GlobalGet(i) => Op::GlobalGet(i + global_base),
+            Op::GlobalSet(i) => Op::GlobalSet(i + global_base),
+            other => other.clone(),
+        })
+        .collect();
+    Function {
+        body: std::sync::Arc::new(body),
+        ..func
+    }
+}
+
+fn put_u32(h: &mut Sha256, v: u32) {
+    h.update(&v.to_le_bytes());
+}
+
+/// Feed one op to the digest as its mnemonic plus operands, so the digest
+/// does not depend on opcode numbering.
+fn digest_op(h: &mut Sha256, op: &Op) {
+    let name = op.mnemonic();
+    h.update(&[name.len() as u8]);
+    h.update(name.as_bytes());
+    match op {
+        Op::I32Const(v) => h.update(&v.to_le_bytes()),
+        Op::I64Const(v) => h.update(&v.to_le_bytes()),
+        Op::F32Const(v) => h.update(&v.to_bits().to_le_bytes()),
+        Op::F64Const(v) => h.update(&v.to_bits().to_le_bytes()),
+        Op::LocalGet(i)
+        | Op::LocalSet(i)
+        | Op::LocalTee(i)
+        | Op::Br(i)
+        | Op::BrIf(i)
+        | Op::Call(i)
+        | Op::CallHost(i)
+        | Op::GlobalGet(i)
+        | Op::GlobalSet(i) => put_u32(h, *i),
+        Op::Ext { opcode, imm } => {
+            h.update(&[*opcode]);
+            put_u32(h, *imm);
+        }
+        Op::Block(bt) | Op::Loop(bt) | Op::If(bt) => h.update(&[match bt {
+            BlockType::Empty => 0x40,
+            BlockType::Val(t) => *t as u8,
+        }]),
+        Op::I32Load {
+            align,
+            offset,
+            memory,
+        }
+        | Op::I32Store {
+            align,
+            offset,
+            memory,
+        }
+        | Op::I64Load {
+            align,
+            offset,
+            memory,
+        }
+        | Op::I64Store {
+            align,
+            offset,
+            memory,
+        }
+        | Op::F32Load {
+            align,
+            offset,
+            memory,
+        }
+        | Op::F32Store {
+            align,
+            offset,
+            memory,
+        }
+        | Op::F64Load {
+            align,
+            offset,
+            memory,
+        }
+        | Op::F64Store {
+            align,
+            offset,
+            memory,
+        } => {
+            // Same shape as the binary encoding, so digests of modules
+            // without memory indices are unchanged.
+            let mut memarg = Vec::new();
+            encode_memarg(*align, *offset, *memory, &mut memarg);
+            h.update(&memarg);
+        }
+        _ => {}
+    }
+}
+
+/// Everything before the function section: version, memory limits,
+/// features, and (v2+) the string table and constant pool.
+struct Header<'a> {
+    version: u32,
+    initial_memory_pages: usize,
+    max_memory_pages: Option<usize>,
+    required_features: Features,
+    strings: Vec<String>,
+    /// Raw constant pool, 8 bytes per entry; only decoded by `from_bytes`.
+    consts: &'a [u8],
+}
+
+impl<'a> Header<'a> {
+    /// Parse the header, returning it and the offset of the function section.
+    fn parse(data: &'a [u8]) -> Result<(Self, usize)> {
+        let mut cur = 0usize;
+
+        let magic: [u8; 4] = read_arr(data, &mut cur)
+            .ok_or_else(|| Trap::InvalidModule("truncated magic".into()))?;
+        if magic != MAGIC {
+            return Err(Trap::InvalidModule("bad magic bytes".into()));
+        }
+
+        let version = read_u32(data, &mut cur)
+            .ok_or_else(|| Trap::InvalidModule("truncated version".into()))?;
+        if !(MIN_VERSION..=VERSION).contains(&version) {
+            return Err(Trap::InvalidModule(format!(
+                "unsupported version {version:#x}"
+            )));
+        }
+
+        let initial_memory_pages = read_u32(data, &mut cur)
+            .ok_or_else(|| Trap::InvalidModule("truncated memory info".into()))?
+            as usize;
+        let max_raw = read_u32(data, &mut cur)
+            .ok_or_else(|| Trap::InvalidModule("truncated memory info".into()))?;
+        let max_memory_pages = if max_raw == 0 {
+            None
+        } else {
+            Some(max_raw as usize)
+        };
+
+        let required_features = if version >= 3 {
+            let bits = read_arr::<8>(data, &mut cur)
+                .ok_or_else(|| Trap::InvalidModule("truncated feature bits".into()))?;
+            Features::from_bits(u64::from_le_bytes(bits))
+        } else {
+            Features::NONE
+        };
+
+        let mut strings = Vec::new();
+        let mut consts: &[u8] = &[];
+        if version >= 2 {
+            let n_strings = read_u32(data, &mut cur)
+                .ok_or_else(|| Trap::InvalidModule("truncated string table".into()))?;
+            for _ in 0..n_strings {
+                strings.push(
+                    read_str(data, &mut cur)
+                        .ok_or_else(|| Trap::InvalidModule("truncated string table".into()))?,
+                );
+            }
+            let n_consts = read_u32(data, &mut cur)
+                .ok_or_else(|| Trap::InvalidModule("truncated constant pool".into()))?
+                as usize;
+            consts = n_consts
+                .checked_mul(8)
+                .and_then(|len| data.get(cur..cur.checked_add(len)?))
+                .ok_or_else(|| Trap::InvalidModule("truncated constant pool".into()))?;
+            cur += consts.len();
+        }
+
+        let header = Header {
+            version,
+            initial_memory_pages,
+            max_memory_pages,
+            required_features,
+            strings,
+            consts,
+        };
+        Ok((header, cur))
+    }
+
+    /// Read a function or export name: a string-table index in v2+, inline
+    /// in v1.
+    fn read_name(&self, data: &[u8], cur: &mut usize) -> Option<String> {
+        if self.version >= 2 {
+            let idx = read_u32(data, cur)? as usize;
+            self.strings.get(idx).cloned()
+        } else {
+            read_str(data, cur)
+        }
+    }
+}
+
 // ── Binary helpers ───────────────────────────────────────────────────────────
 
+const SECTION_SOURCE_MAP: u8 = 0x01;
+const SECTION_EXTERNAL_DATA: u8 = 0x02;
+const SECTION_IMPORTS: u8 = 0x03;
+const SECTION_HOST_REQUIREMENTS: u8 = 0x04;
+const SECTION_GLOBAL_IMPORTS: u8 = 0x05;
+
+/// Features `functions` use whether or not the module lists them:
+/// [`Features::MULTI_MEMORY`] if a load or store names a memory other than
+/// 0. Loaders add these, and the encoder writes them, so runtimes without
+/// them reject the module up front.
+pub(crate) fn implied_features(functions: &[Function]) -> Features {
+    let indexed = functions
+        .iter()
+        .flat_map(|f| f.body.iter())
+        .any(|op| op.memory_index().is_some_and(|memory| memory != 0));
+    if indexed {
+        Features::MULTI_MEMORY
+    } else {
+        Features::NONE
+    }
+}
+
+/// A parse error for the item at byte `at` of the module.
+fn malformed(what: &str, at: usize) -> Trap {
+    Trap::InvalidModule(format!("{what} at offset {at:#x}"))
+}
+
 fn write_str(out: &mut Vec<u8>, s: &str) {
     out.extend_from_slice(&(s.len() as u32).to_le_bytes());
     out.extend_from_slice(s.as_bytes());
 }
 
+/// Interned strings in first-use order.
+#[derive(Default)]
+struct StringTable<'a> {
+    list: Vec<&'a str>,
+    index: std::collections::HashMap<&'a str, u32>,
+}
+
+impl<'a> StringTable<'a> {
+    fn intern(&mut self, s: &'a str) -> u32 {
+        *self.index.entry(s).or_insert_with(|| {
+            self.list.push(s);
+            (self.list.len() - 1) as u32
+        })
+    }
+}
+
+/// 8-byte immediates that occur more than once across the module.
+struct ConstPool {
+    list: Vec<u64>,
+    index: std::collections::HashMap<u64, u32>,
+}
+
+impl ConstPool {
+    fn build(functions: &[Function]) -> Self {
+        let mut counts: std::collections::HashMap<u64, u32> = std::collections::HashMap::new();
+        let mut order = Vec::new();
+        for op in functions.iter().flat_map(|f| f.body.iter()) {
+            if let Some(bits) = wide_const_bits(op) {
+                let n = counts.entry(bits).or_insert(0);
+                if *n == 0 {
+                    order.push(bits);
+                }
+                *n += 1;
+            }
+        }
+        let list: Vec<u64> = order.into_iter().filter(|b| counts[b] > 1).collect();
+        let index = list
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (*b, i as u32))
+            .collect();
+        ConstPool { list, index }
+    }
+
+    /// Emit `op` as a pool reference if its immediate is pooled.
+    fn encode(&self, op: &Op, out: &mut Vec<u8>) -> bool {
+        let Some(idx) = wide_const_bits(op).and_then(|b| self.index.get(&b)) else {
+            return false;
+        };
+        out.push(if matches!(op, Op::I64Const(_)) {
+            0x96
+        } else {
+            0x97
+        });
+        out.extend_from_slice(&idx.to_le_bytes());
+        true
+    }
+}
+
+fn wide_const_bits(op: &Op) -> Option<u64> {
+    match op {
+        Op::I64Const(v) => Some(*v as u64),
+        Op::F64Const(v) => Some(v.to_bits()),
+        _ => None,
+    }
+}
+
 fn write_valtypes(out: &mut Vec<u8>, tys: &[ValType]) {
     out.extend_from_slice(&(tys.len() as u32).to_le_bytes());
     for t in tys {
@@ -286,6 +1132,154 @@ fn read_valtypes(data: &[u8], cur: &mut usize) -> Option<Vec<ValType>> {
     Some(out)
 }
 
+fn encode_source_map(sm: &SourceMap, out: &mut Vec<u8>) {
+    out.extend_from_slice(&(sm.files.len() as u32).to_le_bytes());
+    for f in &sm.files {
+        write_str(out, f);
+    }
+    out.extend_from_slice(&(sm.functions.len() as u32).to_le_bytes());
+    for table in &sm.functions {
+        out.extend_from_slice(&(table.len() as u32).to_le_bytes());
+        for (op, loc) in table {
+            for v in [*op, loc.file, loc.line, loc.column] {
+                out.extend_from_slice(&v.to_le_bytes());
+            }
+        }
+    }
+}
+
+fn decode_source_map(data: &[u8]) -> Option<SourceMap> {
+    let mut cur = 0usize;
+    let n_files = read_u32(data, &mut cur)? as usize;
+    let mut files = Vec::with_capacity(n_files.min(data.len()));
+    for _ in 0..n_files {
+        files.push(read_str(data, &mut cur)?);
+    }
+    let n_tables = read_u32(data, &mut cur)? as usize;
+    let mut functions = Vec::with_capacity(n_tables.min(data.len()));
+    for _ in 0..n_tables {
+        let n = read_u32(data, &mut cur)? as usize;
+        let mut table = Vec::with_capacity(n.min(data.len()));
+        for _ in 0..n {
+            let op = read_u32(data, &mut cur)?;
+            let loc = SourceLoc {
+                file: read_u32(data, &mut cur)?,
+                line: read_u32(data, &mut cur)?,
+                column: read_u32(data, &mut cur)?,
+            };
+            table.push((op, loc));
+        }
+        functions.push(table);
+    }
+    Some(SourceMap { files, functions })
+}
+
+fn decode_external_segments(data: &[u8]) -> Option<Vec<ExternalSegment>> {
+    let mut cur = 0usize;
+    let n = read_u32(data, &mut cur)? as usize;
+    let mut segs = Vec::with_capacity(n.min(data.len()));
+    for _ in 0..n {
+        segs.push(ExternalSegment {
+            offset: read_u32(data, &mut cur)?,
+            len: read_u32(data, &mut cur)?,
+            hash: read_arr(data, &mut cur)?,
+        });
+    }
+    Some(segs)
+}
+
+fn decode_imports(data: &[u8]) -> Option<Vec<Import>> {
+    let mut cur = 0;
+    let n = read_u32(data, &mut cur)? as usize;
+    let mut imports = Vec::with_capacity(n.min(data.len()));
+    for _ in 0..n {
+        let module = read_str(data, &mut cur)?;
+        let name = read_str(data, &mut cur)?;
+        let params = read_valtypes(data, &mut cur)?;
+        let results = read_valtypes(data, &mut cur)?;
+        imports.push(Import {
+            module,
+            name,
+            ty: FuncType { params, results },
+        });
+    }
+    Some(imports)
+}
+
+/// Encode host requirements in the section 0x04 layout, shared with the
+/// digest.
+fn encode_host_requirements(req: &HostRequirements, out: &mut dyn FnMut(&[u8])) {
+    match req.api_version {
+        Some(v) => {
+            out(&[1]);
+            out(&v.major.to_le_bytes());
+            out(&v.minor.to_le_bytes());
+        }
+        None => out(&[0]),
+    }
+    out(&(req.capabilities.len() as u32).to_le_bytes());
+    for cap in &req.capabilities {
+        out(&(cap.len() as u32).to_le_bytes());
+        out(cap.as_bytes());
+    }
+}
+
+fn decode_host_requirements(data: &[u8]) -> Option<HostRequirements> {
+    let mut cur = 0;
+    let api_version = match read_arr::<1>(data, &mut cur)? {
+        [0] => None,
+        [1] => Some(ApiVersion::new(
+            read_u32(data, &mut cur)?,
+            read_u32(data, &mut cur)?,
+        )),
+        _ => return None,
+    };
+    let n = read_u32(data, &mut cur)? as usize;
+    let mut capabilities = Vec::with_capacity(n.min(data.len()));
+    for _ in 0..n {
+        capabilities.push(read_str(data, &mut cur)?);
+    }
+    Some(HostRequirements {
+        api_version,
+        capabilities,
+    })
+}
+
+/// Encode global imports in the section 0x05 layout, shared with the
+/// digest.
+fn encode_global_imports(globals: &[GlobalImport], out: &mut dyn FnMut(&[u8])) {
+    out(&(globals.len() as u32).to_le_bytes());
+    for global in globals {
+        for part in [&global.module, &global.name] {
+            out(&(part.len() as u32).to_le_bytes());
+            out(part.as_bytes());
+        }
+        out(&[global.ty as u8, global.mutable as u8]);
+    }
+}
+
+fn decode_global_imports(data: &[u8]) -> Option<Vec<GlobalImport>> {
+    let mut cur = 0;
+    let n = read_u32(data, &mut cur)? as usize;
+    let mut globals = Vec::with_capacity(n.min(data.len()));
+    for _ in 0..n {
+        let module = read_str(data, &mut cur)?;
+        let name = read_str(data, &mut cur)?;
+        let [ty, mutable] = read_arr::<2>(data, &mut cur)?;
+        globals.push(GlobalImport {
+            module,
+            name,
+            ty: ValType::from_u8(ty)?,
+            mutable: match mutable {
+                0 => false,
+                1 => true,
+                _ => return None,
+            },
+        });
+    }
+    Some(globals)
+}
+
 fn read_bytes_len<'a>(data: &'a [u8], cur: &mut usize) -> Option<&'a [u8]> {
     let len = read_u32(data, cur)? as usize;
     if *cur + len > data.len() {
@@ -301,7 +1295,7 @@ fn read_bytes_len<'a>(data: &'a [u8], cur: &mut usize) -> Option<&'a [u8]> {
 // Each Op is encoded as 1 opcode byte followed by 0-8 payload bytes.
 // This replaces the old JSON encoding ("I32Add" = 8 chars) with a single byte.
 // Encoding table:
-//   0x00-0x5F  simple ops (no payload)
+//   0x00-0x71  simple ops (no payload)
 //   0x80       I32Const  + [4 bytes LE i32]
 //   0x81       I64Const  + [8 bytes LE i64]
 //   0x82       F32Const  + [4 bytes LE f32 bits]
@@ -316,19 +1310,28 @@ fn read_bytes_len<'a>(data: &'a [u8], cur: &mut usize) -> Option<&'a [u8]> {
 //   0x8B       Block     + [1 byte BlockType]
 //   0x8C       Loop      + [1 byte BlockType]
 //   0x8D       If        + [1 byte BlockType]
-//   0x8E       I32Load   + [4 bytes align, 4 bytes offset]
-//   0x8F       I32Store  + [4 bytes align, 4 bytes offset]
-//   0x90       I64Load   + [4 bytes align, 4 bytes offset]
-//   0x91       I64Store  + [4 bytes align, 4 bytes offset]
-//   0x92       F32Load   + [4 bytes align, 4 bytes offset]
-//   0x93       F32Store  + [4 bytes align, 4 bytes offset]
-//   0x94       F64Load   + [4 bytes align, 4 bytes offset]
-//   0x95       F64Store  + [4 bytes align, 4 bytes offset]
+//   0x8E       I32Load   + memarg
+//   0x8F       I32Store  + memarg
+//   0x90       I64Load   + memarg
+//   0x91       I64Store  + memarg
+//   0x92       F32Load   + memarg
+//   0x93       F32Store  + memarg
+//   0x94       F64Load   + memarg
+//   0x95       F64Store  + memarg
+//   0x96       I64Const  + [4 bytes LE constant pool index]   (v2+)
+//   0x97       F64Const  + [4 bytes LE constant pool index]   (v2+)
+//   0x98       GlobalGet + [4 bytes LE u32 global import index]
+//   0x99       GlobalSet + [4 bytes LE u32 global import index]
+//   0xE0-0xFF  Ext       + [4 bytes LE u32 immediate]  (embedder extensions)
+//
+// memarg = [4 bytes align, 4 bytes offset], then [4 bytes memory index] only
+// if bit 6 of align is set (as in Wasm multi-memory). Memory 0 is encoded
+// without the index, so such ops read the same as before indices existed.
 
 use crate::ir::{BlockType, Op};
 
 // Simple (no-payload) ops, in order. Index = opcode byte 0x00..
-static SIMPLE_OPS: &[Op] = &[
+pub(crate) static SIMPLE_OPS: &[Op] = &[
     Op::Nop,
     Op::Drop,
     Op::Select,
@@ -442,6 +1445,7 @@ static SIMPLE_OPS: &[Op] = &[
     Op::F32ReinterpretI32,
     Op::I64ReinterpretF64,
     Op::F64ReinterpretI64,
+    Op::MemoryDiscard,
 ];
 
 fn encode_op(op: &Op, out: &mut Vec<u8>) {
@@ -565,6 +1569,7 @@ fn encode_op(op: &Op, out: &mut Vec<u8>) {
                     | Op::F32ReinterpretI32
                     | Op::I64ReinterpretF64
                     | Op::F64ReinterpretI64
+                    | Op::MemoryDiscard
             ) {
                 out.push(i as u8);
                 return;
@@ -609,6 +1614,14 @@ fn encode_op(op: &Op, out: &mut Vec<u8>) {
             out.push(0x88);
             out.extend_from_slice(&i.to_le_bytes());
         }
+        Op::GlobalGet(i) => {
+            out.push(0x98);
+            out.extend_from_slice(&i.to_le_bytes());
+        }
+        Op::GlobalSet(i) => {
+            out.push(0x99);
+            out.extend_from_slice(&i.to_le_bytes());
+        }
         Op::Br(d) => {
             out.push(0x89);
             out.extend_from_slice(&d.to_le_bytes());
@@ -629,45 +1642,73 @@ fn encode_op(op: &Op, out: &mut Vec<u8>) {
             out.push(0x8D);
             out.push(encode_bt(bt));
         }
-        Op::I32Load { align, offset } => {
+        Op::I32Load {
+            align,
+            offset,
+            memory,
+        } => {
             out.push(0x8E);
-            out.extend_from_slice(&align.to_le_bytes());
-            out.extend_from_slice(&offset.to_le_bytes());
+            encode_memarg(*align, *offset, *memory, out);
         }
-        Op::I32Store { align, offset } => {
+        Op::I32Store {
+            align,
+            offset,
+            memory,
+        } => {
             out.push(0x8F);
-            out.extend_from_slice(&align.to_le_bytes());
-            out.extend_from_slice(&offset.to_le_bytes());
+            encode_memarg(*align, *offset, *memory, out);
         }
-        Op::I64Load { align, offset } => {
+        Op::I64Load {
+            align,
+            offset,
+            memory,
+        } => {
             out.push(0x90);
-            out.extend_from_slice(&align.to_le_bytes());
-            out.extend_from_slice(&offset.to_le_bytes());
+            encode_memarg(*align, *offset, *memory, out);
         }
-        Op::I64Store { align, offset } => {
+        Op::I64Store {
+            align,
+            offset,
+            memory,
+        } => {
             out.push(0x91);
-            out.extend_from_slice(&align.to_le_bytes());
-            out.extend_from_slice(&offset.to_le_bytes());
+            encode_memarg(*align, *offset, *memory, out);
         }
-        Op::F32Load { align, offset } => {
+        Op::F32Load {
+            align,
+            offset,
+            memory,
+        } => {
             out.push(0x92);
-            out.extend_from_slice(&align.to_le_bytes());
-            out.extend_from_slice(&offset.to_le_bytes());
+            encode_memarg(*align, *offset, *memory, out);
         }
-        Op::F32Store { align, offset } => {
+        Op::F32Store {
+            align,
+            offset,
+            memory,
+        } => {
             out.push(0x93);
-            out.extend_from_slice(&align.to_le_bytes());
-            out.extend_from_slice(&offset.to_le_bytes());
+            encode_memarg(*align, *offset, *memory, out);
         }
-        Op::F64Load { align, offset } => {
+        Op::F64Load {
+            align,
+            offset,
+            memory,
+        } => {
             out.push(0x94);
-            out.extend_from_slice(&align.to_le_bytes());
-            out.extend_from_slice(&offset.to_le_bytes());
+            encode_memarg(*align, *offset, *memory, out);
         }
-        Op::F64Store { align, offset } => {
+        Op::F64Store {
+            align,
+            offset,
+            memory,
+        } => {
             out.push(0x95);
-            out.extend_from_slice(&align.to_le_bytes());
-            out.extend_from_slice(&offset.to_le_bytes());
+            encode_memarg(*align, *offset, *memory, out);
+        }
+        Op::Ext { opcode, imm } => {
+            out.push(*opcode);
+            out.extend_from_slice(&imm.to_le_bytes());
         }
         _ => {} // unknown ops silently skipped (shouldn't happen)
     }
@@ -688,7 +1729,28 @@ fn decode_bt(b: u8) -> Option<BlockType> {
     ValType::from_u8(b).map(BlockType::Val)
 }
 
-fn decode_ops(data: &[u8]) -> Option<std::sync::Arc<Vec<Op>>> {
+/// Flag in an encoded `align` marking a following memory index.
+const MEMARG_INDEXED: u32 = 0x40;
+
+/// Largest `align` a load or store can carry without colliding with
+/// [`MEMARG_INDEXED`]. Loaders reject more; the verifier accepts at most 3.
+pub(crate) const MAX_ALIGN: u32 = MEMARG_INDEXED - 1;
+
+fn encode_memarg(align: u32, offset: u32, memory: u32, out: &mut Vec<u8>) {
+    // Written as the highest representable value, which stays as invalid,
+    // so the flag bit never misreads the rest of the stream.
+    let align = align.min(MAX_ALIGN);
+    if memory == 0 {
+        out.extend_from_slice(&align.to_le_bytes());
+        out.extend_from_slice(&offset.to_le_bytes());
+    } else {
+        out.extend_from_slice(&(align | MEMARG_INDEXED).to_le_bytes());
+        out.extend_from_slice(&offset.to_le_bytes());
+        out.extend_from_slice(&memory.to_le_bytes());
+    }
+}
+
+fn decode_ops(data: &[u8], consts: &[u64]) -> Option<std::sync::Arc<Vec<Op>>> {
     let mut ops = Vec::new();
     let mut i = 0usize;
 
@@ -721,6 +1783,17 @@ fn decode_ops(data: &[u8]) -> Option<std::sync::Arc<Vec<Op>>> {
                 v
             }};
         }
+        macro_rules! read_memarg {
+            () => {{
+                let align = read4!();
+                let offset = read4!();
+                if align & MEMARG_INDEXED != 0 {
+                    (align & !MEMARG_INDEXED, offset, read4!())
+                } else {
+                    (align, offset, 0)
+                }
+            }};
+        }
         macro_rules! read_bt {
             () => {{
                 if i >= data.len() {
@@ -748,69 +1821,77 @@ fn decode_ops(data: &[u8]) -> Option<std::sync::Arc<Vec<Op>>> {
             0x8C => Op::Loop(read_bt!()),
             0x8D => Op::If(read_bt!()),
             0x8E => {
-                let a = read4!();
-                let o = read4!();
+                let (align, offset, memory) = read_memarg!();
                 Op::I32Load {
-                    align: a,
-                    offset: o,
+                    align,
+                    offset,
+                    memory,
                 }
             }
             0x8F => {
-                let a = read4!();
-                let o = read4!();
+                let (align, offset, memory) = read_memarg!();
                 Op::I32Store {
-                    align: a,
-                    offset: o,
+                    align,
+                    offset,
+                    memory,
                 }
             }
             0x90 => {
-                let a = read4!();
-                let o = read4!();
+                let (align, offset, memory) = read_memarg!();
                 Op::I64Load {
-                    align: a,
-                    offset: o,
+                    align,
+                    offset,
+                    memory,
                 }
             }
             0x91 => {
-                let a = read4!();
-                let o = read4!();
+                let (align, offset, memory) = read_memarg!();
                 Op::I64Store {
-                    align: a,
-                    offset: o,
+                    align,
+                    offset,
+                    memory,
                 }
             }
             0x92 => {
-                let a = read4!();
-                let o = read4!();
+                let (align, offset, memory) = read_memarg!();
                 Op::F32Load {
-                    align: a,
-                    offset: o,
+                    align,
+                    offset,
+                    memory,
                 }
             }
             0x93 => {
-                let a = read4!();
-                let o = read4!();
+                let (align, offset, memory) = read_memarg!();
                 Op::F32Store {
-                    align: a,
-                    offset: o,
+                    align,
+                    offset,
+                    memory,
                 }
             }
             0x94 => {
-                let a = read4!();
-                let o = read4!();
+                let (align, offset, memory) = read_memarg!();
                 Op::F64Load {
-                    align: a,
-                    offset: o,
+                    align,
+                    offset,
+                    memory,
                 }
             }
             0x95 => {
-                let a = read4!();
-                let o = read4!();
+                let (align, offset, memory) = read_memarg!();
                 Op::F64Store {
-                    align: a,
-                    offset: o,
+                    align,
+                    offset,
+                    memory,
                 }
             }
+            0x96 => Op::I64Const(*consts.get(read4!() as usize)? as i64),
+            0x97 => Op::F64Const(f64::from_bits(*consts.get(read4!() as usize)?)),
+            0x98 => Op::GlobalGet(read4!()),
+            0x99 => Op::GlobalSet(read4!()),
+            EXT_OPCODE_FIRST..=EXT_OPCODE_LAST => Op::Ext {
+                opcode: byte,
+                imm: read4!(),
+            },
             _ => return None,
         };
         ops.push(op);
@@ -818,3 +1899,86 @@ fn decode_ops(data: &[u8]) -> Option<std::sync::Arc<Vec<Op>>> {
 
     Some(std::sync::Arc::new(ops))
 }
+
+/// Build one op from its binary opcode byte and unpacked immediates, for
+/// embedders that emit ops without the byte encoding (the C builder API).
+/// `imm` carries the constant bits, index, depth, block type or alignment;
+/// `offset` and `memory` complete a memarg. Pool-indexed constants (0x96,
+/// 0x97) only exist in encoded bodies and are rejected.
+pub(crate) fn op_from_parts(opcode: u8, imm: u64, offset: u32, memory: u32) -> Option<Op> {
+    if let Some(op) = SIMPLE_OPS.get(opcode as usize) {
+        return Some(op.clone());
+    }
+    let idx = || u32::try_from(imm).ok();
+    let bt = || decode_bt(u8::try_from(imm).ok()?);
+    let memarg = || Some((idx()?, offset, memory));
+    let op = match opcode {
+        0x80 => Op::I32Const(imm as u32 as i32),
+        0x81 => Op::I64Const(imm as i64),
+        0x82 => Op::F32Const(f32::from_bits(imm as u32)),
+        0x83 => Op::F64Const(f64::from_bits(imm)),
+        0x84 => Op::LocalGet(idx()?),
+        0x85 => Op::LocalSet(idx()?),
+        0x86 => Op::LocalTee(idx()?),
+        0x87 => Op::Call(idx()?),
+        0x88 => Op::CallHost(idx()?),
+        0x89 => Op::Br(idx()?),
+        0x8A => Op::BrIf(idx()?),
+        0x8B => Op::Block(bt()?),
+        0x8C => Op::Loop(bt()?),
+        0x8D => Op::If(bt()?),
+        0x8E..=0x95 => {
+            let (align, offset, memory) = memarg()?;
+            match opcode {
+                0x8E => Op::I32Load {
+                    align,
+                    offset,
+                    memory,
+                },
+                0x8F => Op::I32Store {
+                    align,
+                    offset,
+                    memory,
+                },
+                0x90 => Op::I64Load {
+                    align,
+                    offset,
+                    memory,
+                },
+                0x91 => Op::I64Store {
+                    align,
+                    offset,
+                    memory,
+                },
+                0x92 => Op::F32Load {
+                    align,
+                    offset,
+                    memory,
+                },
+                0x93 => Op::F32Store {
+                    align,
+                    offset,
+                    memory,
+                },
+                0x94 => Op::F64Load {
+                    align,
+                    offset,
+                    memory,
+                },
+                _ => Op::F64Store {
+                    align,
+                    offset,
+                    memory,
+                },
+            }
+        }
+        0x98 => Op::GlobalGet(idx()?),
+        0x99 => Op::GlobalSet(idx()?),
+        EXT_OPCODE_FIRST..=EXT_OPCODE_LAST => Op::Ext {
+            opcode,
+            imm: idx()?,
+        },
+        _ => return None,
+    };
+    Some(op)
+}
@@ -0,0 +1,581 @@
+//! Optimization passes over RuneIR.
+//!
+//! [`optimize`] rewrites each function of a module into ops that compute
+//! the same results and trap for the same reasons, in fewer or cheaper
+//! ops. It is meant for modules ahead of time, as `runec opt` runs it: the
+//! interpreter dispatches fewer ops and the compilers translate fewer.
+//! Op indices change, so the rewritten module spends less fuel and
+//! backtraces name other ops; the source map is carried over so they point
+//! at the same source lines.
+//!
+//! Functions that fail the [verifier](crate::verify) are left alone.
+//! [`Pass::ALL`] is the pipeline `runec opt` runs unless told otherwise.
+//!
+//! [`optimize_with_profile`] also takes a [`Profile`] of the module, for
+//! the passes that go by how the module ran: inlining hot calls and laying
+//! out branches. They run once, first; the others until they find nothing
+//! more to do.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::Arc;
+
+use crate::{
+    config::RuntimeConfig,
+    ir::{BlockType, Function, Op},
+    liveness::Liveness,
+    module::Module,
+    profile::{FunctionProfile, Profile},
+    sourcemap::SourceLoc,
+    trap::{Result, Trap},
+    types::ValType,
+    verify::verify_function,
+};
+
+/// Functions of at most this many ops are inlined at hot call sites.
+pub const INLINE_MAX_OPS: usize = 32;
+
+/// A call site is hot if it ran at least this fraction, as 1 in
+/// `HOT_SHARE`, of all the calls the profile counted.
+const HOT_SHARE: u64 = 100;
+
+/// A pass of the pipeline.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Pass {
+    /// Calls the profile shows hot, to functions of at most
+    /// [`INLINE_MAX_OPS`] ops other than the caller, replaced by the
+    /// function's body in a block: its arguments go to fresh locals of the
+    /// caller and its returns branch out of the block.
+    Inline,
+    /// `if`s whose else branch the profile shows ran more often than
+    /// their then branch turned around, testing the opposite condition, so
+    /// the branch usually taken falls through.
+    Layout,
+    /// Rewrites of short op sequences: multiplies and unsigned divides by
+    /// powers of two into shifts and masks, a comparison and the `eqz`
+    /// after it into the opposite comparison, tests of a branch condition
+    /// against zero and double negations dropped, stores to locals no op
+    /// reads again dropped, and identities such as adding zero removed.
+    /// A comparison is not fused with the branch it feeds, as RuneIR has no
+    /// compare-and-branch op; the optimizing compiler fuses the two when it
+    /// emits code.
+    Peephole,
+}
+
+impl Pass {
+    /// Every pass, in the order they run.
+    pub const ALL: &'static [Pass] = &[Pass::Inline, Pass::Layout, Pass::Peephole];
+
+    /// The name `runec opt --passes` knows the pass by.
+    pub fn name(self) -> &'static str {
+        match self {
+            Pass::Inline => "inline",
+            Pass::Layout => "layout",
+            Pass::Peephole => "peephole",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Pass> {
+        Self::ALL.iter().copied().find(|p| p.name() == name)
+    }
+
+    /// Whether the pass goes by a profile, doing nothing without one.
+    pub fn is_guided(self) -> bool {
+        matches!(self, Pass::Inline | Pass::Layout)
+    }
+
+    /// Run the pass over `body`; true if it changed anything.
+    fn run(self, body: &mut Body, cx: &Context<'_>) -> bool {
+        match self {
+            Pass::Inline => inline(body, cx),
+            Pass::Layout => layout(body, cx),
+            Pass::Peephole => {
+                let dead = dead_stores(body, cx.func.ty.params.len() + body.locals.len());
+                peephole(body) || dead
+            }
+        }
+    }
+}
+
+/// What [`optimize`] did to a module.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    /// Ops in all functions, before and after.
+    pub ops_before: usize,
+    pub ops_after: usize,
+    /// Functions left alone because they fail the verifier.
+    pub unverified: Vec<u32>,
+}
+
+/// Run `passes` over every function of `module`, with extension ops typed
+/// by `config`, until none changes anything more. Passes that go by a
+/// profile do nothing.
+pub fn optimize(module: &mut Module, config: &RuntimeConfig, passes: &[Pass]) -> Report {
+    run(module, config, passes, None)
+}
+
+/// [`optimize`], with the passes that go by a profile going by `profile`.
+/// Fails with `Trap::InvalidModule` if the profile was recorded from
+/// another module.
+pub fn optimize_with_profile(
+    module: &mut Module,
+    config: &RuntimeConfig,
+    passes: &[Pass],
+    profile: &Profile,
+) -> Result<Report> {
+    if !profile.is_of(module) {
+        return Err(Trap::InvalidModule(
+            "the profile was recorded from another module".into(),
+        ));
+    }
+    Ok(run(module, config, passes, Some(profile)))
+}
+
+fn run(
+    module: &mut Module,
+    config: &RuntimeConfig,
+    passes: &[Pass],
+    profile: Option<&Profile>,
+) -> Report {
+    let hot = profile.map(|profile| {
+        let sites: u64 = profile
+            .functions
+            .iter()
+            .flat_map(|f| f.call_sites.values())
+            .sum();
+        sites.div_ceil(HOT_SHARE).max(1)
+    });
+    let mut report = Report::default();
+    for i in 0..module.functions.len() {
+        let func = &module.functions[i];
+        report.ops_before += func.body.len();
+        if verify_function(module, func, config).is_err() {
+            report.unverified.push(i as u32);
+            report.ops_after += func.body.len();
+            continue;
+        }
+        let cx = Context {
+            module,
+            config,
+            index: i as u32,
+            func,
+            profile: profile.and_then(|p| p.functions.get(i)),
+            hot: hot.unwrap_or(u64::MAX),
+        };
+        let mut body = Body {
+            ops: func.body.to_vec(),
+            origins: (0..func.body.len() as u32).collect(),
+            locals: func.locals.clone(),
+        };
+        let mut changed = false;
+        for pass in passes.iter().filter(|p| p.is_guided()) {
+            changed |= pass.run(&mut body, &cx);
+        }
+        while passes
+            .iter()
+            .filter(|p| !p.is_guided())
+            .any(|pass| pass.run(&mut body, &cx))
+        {
+            changed = true;
+        }
+        let mut optimized = func.clone();
+        optimized.body = Arc::new(body.ops);
+        optimized.locals = body.locals;
+        // Every rewrite keeps the function valid; should one not, the
+        // function stays as it was.
+        if !changed || verify_function(module, &optimized, config).is_err() {
+            report.ops_after += func.body.len();
+            continue;
+        }
+        report.ops_after += optimized.body.len();
+        module.functions[i] = optimized;
+        if let Some(table) = module
+            .source_map
+            .as_mut()
+            .and_then(|map| map.functions.get_mut(i))
+        {
+            *table = remap(table, &body.origins);
+        }
+    }
+    report
+}
+
+/// The function a pass runs over, and the module it is part of.
+struct Context<'a> {
+    module: &'a Module,
+    config: &'a RuntimeConfig,
+    index: u32,
+    /// As the module has it, with the ops a body's origins refer to.
+    func: &'a Function,
+    profile: Option<&'a FunctionProfile>,
+    /// Runs that make a call site hot.
+    hot: u64,
+}
+
+impl Context<'_> {
+    /// Whether the profile shows the `call` at op `origin` of the original
+    /// body hot.
+    fn hot_call(&self, origin: u32) -> bool {
+        let runs = match (self.profile, self.func.body.get(origin as usize)) {
+            (Some(profile), Some(Op::Call(_))) => profile.call_sites.get(&origin),
+            _ => None,
+        };
+        runs.is_some_and(|&runs| runs >= self.hot)
+    }
+
+    /// Whether the profile shows the `if` at op `origin` of the original
+    /// body ran its else branch more often than its then branch.
+    fn else_hotter(&self, origin: u32) -> bool {
+        let branch = match (self.profile, self.func.body.get(origin as usize)) {
+            (Some(profile), Some(Op::If(_))) => profile.branches.get(&origin),
+            _ => None,
+        };
+        branch.is_some_and(|b| b.not_taken > b.taken)
+    }
+}
+
+/// A function body being rewritten: its ops, each with the index of the
+/// op in the original body it stands for, and the types of its locals
+/// after the parameters.
+struct Body {
+    ops: Vec<Op>,
+    origins: Vec<u32>,
+    locals: Vec<ValType>,
+}
+
+/// Carry a function's line table over to its rewritten ops: each op takes
+/// the location of the op it stands for.
+fn remap(table: &[(u32, SourceLoc)], origins: &[u32]) -> Vec<(u32, SourceLoc)> {
+    let mut out: Vec<(u32, SourceLoc)> = Vec::with_capacity(table.len());
+    for (at, &origin) in origins.iter().enumerate() {
+        let covering = table.partition_point(|&(op, _)| op <= origin);
+        let Some(&(_, loc)) = covering.checked_sub(1).map(|k| &table[k]) else {
+            continue;
+        };
+        if out.last().is_none_or(|last| last.1 != loc) {
+            out.push((at as u32, loc));
+        }
+    }
+    out
+}
+
+impl Body {
+    fn push(&mut self, op: Op, origin: u32) {
+        self.ops.push(op);
+        self.origins.push(origin);
+    }
+}
+
+/// Inline hot calls to small functions, as [`Pass::Inline`] describes.
+fn inline(body: &mut Body, cx: &Context<'_>) -> bool {
+    let mut out = Body {
+        ops: Vec::with_capacity(body.ops.len()),
+        origins: Vec::with_capacity(body.ops.len()),
+        locals: body.locals.clone(),
+    };
+    let mut changed = false;
+    for (op, &origin) in body.ops.iter().zip(&body.origins) {
+        let base = cx.func.ty.params.len() + out.locals.len();
+        let inlined = match *op {
+            Op::Call(f) if cx.hot_call(origin) => inlined(cx, f, base as u32),
+            _ => None,
+        };
+        match inlined {
+            Some((ops, locals)) => {
+                for op in ops {
+                    out.push(op, origin);
+                }
+                out.locals.extend(locals);
+                changed = true;
+            }
+            None => out.push(op.clone(), origin),
+        }
+    }
+    *body = out;
+    changed
+}
+
+/// The ops that stand in for a call to function `f`, with its locals
+/// numbered from `base` on, and the types of those locals; `None` if it
+/// is too large or cannot be inlined.
+fn inlined(cx: &Context<'_>, f: u32, base: u32) -> Option<(Vec<Op>, Vec<ValType>)> {
+    let callee = cx.module.functions.get(f as usize)?;
+    if f == cx.index
+        || callee.body.len() > INLINE_MAX_OPS
+        || callee.ty.results.len() > 1
+        || verify_function(cx.module, callee, cx.config).is_err()
+    {
+        return None;
+    }
+    let params = callee.ty.params.len() as u32;
+    let mut ops = Vec::with_capacity(callee.body.len() + 2 * callee.locals.len() + 8);
+    // The arguments, the last on top, then zeroes for the other locals,
+    // which may be read before any op in the body writes them.
+    ops.extend((0..params).rev().map(|p| Op::LocalSet(base + p)));
+    for (k, &ty) in callee.locals.iter().enumerate() {
+        ops.push(zero(ty));
+        ops.push(Op::LocalSet(base + params + k as u32));
+    }
+    ops.push(Op::Block(match callee.ty.results.first() {
+        Some(&ty) => BlockType::Val(ty),
+        None => BlockType::Empty,
+    }));
+    // Blocks open within the body; a return branches out of them all.
+    let mut depth = 0;
+    for op in callee.body.iter() {
+        ops.push(match *op {
+            Op::LocalGet(l) => Op::LocalGet(base + l),
+            Op::LocalSet(l) => Op::LocalSet(base + l),
+            Op::LocalTee(l) => Op::LocalTee(base + l),
+            Op::Return => Op::Br(depth),
+            Op::Block(_) | Op::Loop(_) | Op::If(_) => {
+                depth += 1;
+                op.clone()
+            }
+            // The function's final `End` closes the block.
+            Op::End if depth == 0 => break,
+            Op::End => {
+                depth -= 1;
+                Op::End
+            }
+            ref op => op.clone(),
+        });
+    }
+    ops.push(Op::End);
+    let locals = callee.ty.params.iter().chain(&callee.locals).copied();
+    Some((ops, locals.collect()))
+}
+
+fn zero(ty: ValType) -> Op {
+    match ty {
+        ValType::I32 => Op::I32Const(0),
+        ValType::I64 => Op::I64Const(0),
+        ValType::F32 => Op::F32Const(0.0),
+        ValType::F64 => Op::F64Const(0.0),
+    }
+}
+
+/// Turn `if`s around as [`Pass::Layout`] describes.
+fn layout(body: &mut Body, cx: &Context<'_>) -> bool {
+    if cx.profile.is_none() {
+        return false;
+    }
+    let mut out = Body {
+        ops: Vec::with_capacity(body.ops.len()),
+        origins: Vec::with_capacity(body.ops.len()),
+        locals: body.locals.clone(),
+    };
+    let branches = else_branches(&body.ops);
+    let changed = lay_out(body, 0..body.ops.len(), &branches, cx, &mut out);
+    *body = out;
+    changed
+}
+
+/// Copy the ops of `body` in `range` to `out`, turning around the `if`s
+/// whose else branch ran more often. `branches` holds the `Else` and `End`
+/// of each `if` that has an else branch.
+fn lay_out(
+    body: &Body,
+    range: Range<usize>,
+    branches: &HashMap<usize, (usize, usize)>,
+    cx: &Context<'_>,
+    out: &mut Body,
+) -> bool {
+    let mut changed = false;
+    let mut i = range.start;
+    while i < range.end {
+        let origin = body.origins[i];
+        if let (Op::If(bt), Some(&(e, k))) = (&body.ops[i], branches.get(&i)) {
+            if cx.else_hotter(origin) {
+                out.push(Op::I32Eqz, origin);
+                out.push(Op::If(bt.clone()), origin);
+                lay_out(body, e + 1..k, branches, cx, out);
+                out.push(Op::Else, body.origins[e]);
+                lay_out(body, i + 1..e, branches, cx, out);
+                out.push(Op::End, body.origins[k]);
+                changed = true;
+                i = k + 1;
+                continue;
+            }
+        }
+        out.push(body.ops[i].clone(), origin);
+        i += 1;
+    }
+    changed
+}
+
+/// The `Else` and `End` of each `if` of `ops` that has an else branch, by
+/// the index of the `if`.
+fn else_branches(ops: &[Op]) -> HashMap<usize, (usize, usize)> {
+    let mut open = Vec::new();
+    let mut elses = HashMap::new();
+    let mut branches = HashMap::new();
+    for (i, op) in ops.iter().enumerate() {
+        match op {
+            Op::Block(_) | Op::Loop(_) | Op::If(_) => open.push(i),
+            Op::Else => {
+                if let Some(&start) = open.last() {
+                    elses.insert(start, i);
+                }
+            }
+            Op::End => {
+                let Some(start) = open.pop() else { break };
+                if let Some(e) = elses.remove(&start) {
+                    branches.insert(start, (e, i));
+                }
+            }
+            _ => {}
+        }
+    }
+    branches
+}
+
+/// Turn stores to locals that no op reads again into drops; a
+/// `local.tee` whose local is dead leaves its value where it was.
+fn dead_stores(body: &mut Body, n_locals: usize) -> bool {
+    let Some(liveness) = Liveness::of(n_locals, &body.ops) else {
+        return false;
+    };
+    let mut changed = false;
+    let mut i = 0;
+    let mut at = 0;
+    body.ops.retain_mut(|op| {
+        let keep = match *op {
+            Op::LocalSet(l) if !liveness.live_after(i, l) => {
+                *op = Op::Drop;
+                changed = true;
+                true
+            }
+            Op::LocalTee(l) if !liveness.live_after(i, l) => {
+                changed = true;
+                false
+            }
+            _ => true,
+        };
+        if keep {
+            body.origins[at] = body.origins[i];
+            at += 1;
+        }
+        i += 1;
+        keep
+    });
+    body.origins.truncate(at);
+    changed
+}
+
+/// Apply [`rewrite`] at each op until it finds nothing more; true if it
+/// found anything.
+fn peephole(body: &mut Body) -> bool {
+    let mut ops = Vec::with_capacity(body.ops.len());
+    let mut origins = Vec::with_capacity(body.ops.len());
+    let mut changed = false;
+    for (op, origin) in body.ops.drain(..).zip(&body.origins) {
+        ops.push(op);
+        origins.push(*origin);
+        while let Some((replaced, with)) = rewrite(&ops) {
+            let start = ops.len() - replaced;
+            origins.truncate(start + with.len());
+            ops.truncate(start);
+            ops.extend(with);
+            changed = true;
+        }
+    }
+    body.ops = ops;
+    body.origins = origins;
+    changed
+}
+
+/// A rewrite of the last ops of `ops`: how many to replace, and with what.
+/// Patterns hold no ops that start or end blocks, so nothing branches into
+/// their middle, and only their last op may read a branch condition.
+fn rewrite(ops: &[Op]) -> Option<(usize, Vec<Op>)> {
+    use Op::*;
+    let pow2_32 = |c: i32| (c as u32).is_power_of_two();
+    let pow2_64 = |c: i64| (c as u64).is_power_of_two();
+    Some(match ops {
+        [.., Nop] => (1, vec![]),
+
+        // Values nobody uses.
+        [.., value, Drop] if pure(value) => (2, vec![]),
+        [.., LocalTee(l), Drop] => (2, vec![LocalSet(*l)]),
+        [.., LocalSet(a), LocalGet(b)] if a == b => (2, vec![LocalTee(*a)]),
+
+        // Identities.
+        [.., I32Const(0), I32Add | I32Sub | I32Or | I32Xor | I32Shl | I32ShrS | I32ShrU]
+        | [.., I64Const(0), I64Add | I64Sub | I64Or | I64Xor | I64Shl | I64ShrS | I64ShrU]
+        | [.., I32Const(1), I32Mul | I32DivS | I32DivU]
+        | [.., I64Const(1), I64Mul | I64DivS | I64DivU]
+        | [.., I32Const(-1), I32And]
+        | [.., I64Const(-1), I64And] => (2, vec![]),
+
+        // Strength reduction.
+        [.., I32Const(c), I32Mul] if pow2_32(*c) => {
+            (2, vec![I32Const(c.trailing_zeros() as i32), I32Shl])
+        }
+        [.., I32Const(c), I32DivU] if pow2_32(*c) => {
+            (2, vec![I32Const(c.trailing_zeros() as i32), I32ShrU])
+        }
+        [.., I32Const(c), I32RemU] if pow2_32(*c) => (2, vec![I32Const(c.wrapping_sub(1)), I32And]),
+        [.., I64Const(c), I64Mul] if pow2_64(*c) => {
+            (2, vec![I64Const(c.trailing_zeros() as i64), I64Shl])
+        }
+        [.., I64Const(c), I64DivU] if pow2_64(*c) => {
+            (2, vec![I64Const(c.trailing_zeros() as i64), I64ShrU])
+        }
+        [.., I64Const(c), I64RemU] if pow2_64(*c) => (2, vec![I64Const(c.wrapping_sub(1)), I64And]),
+
+        // Double negations.
+        [.., F32Neg, F32Neg] | [.., F64Neg, F64Neg] => (2, vec![]),
+        [.., I32Const(-1), I32Xor, I32Const(-1), I32Xor]
+        | [.., I64Const(-1), I64Xor, I64Const(-1), I64Xor] => (4, vec![]),
+        [.., I32Eqz, I32Eqz, I32Eqz] => (3, vec![I32Eqz]),
+
+        // Comparisons, and the branches they feed.
+        [.., I32Const(0), I32Eq] => (2, vec![I32Eqz]),
+        [.., I64Const(0), I64Eq] => (2, vec![I64Eqz]),
+        [.., cmp, I32Eqz] if opposite(cmp).is_some() => (2, vec![opposite(cmp)?]),
+        [.., I32Eqz, I32Eqz, test @ (BrIf(_) | If(_) | Select)]
+        | [.., I32Const(0), I32Ne, test @ (BrIf(_) | If(_) | Select)] => (3, vec![test.clone()]),
+
+        _ => return None,
+    })
+}
+
+/// Whether `op` only pushes a value, with no other effect.
+fn pure(op: &Op) -> bool {
+    use Op::*;
+    matches!(
+        op,
+        I32Const(_) | I64Const(_) | F32Const(_) | F64Const(_) | LocalGet(_) | GlobalGet(_)
+    )
+}
+
+/// The integer comparison true exactly when `cmp` is false. Float
+/// comparisons have none: with a NaN, a comparison and its opposite are
+/// both false.
+fn opposite(cmp: &Op) -> Option<Op> {
+    use Op::*;
+    Some(match cmp {
+        I32Eq => I32Ne,
+        I32Ne => I32Eq,
+        I32LtS => I32GeS,
+        I32LtU => I32GeU,
+        I32GtS => I32LeS,
+        I32GtU => I32LeU,
+        I32LeS => I32GtS,
+        I32LeU => I32GtU,
+        I32GeS => I32LtS,
+        I32GeU => I32LtU,
+        I64Eq => I64Ne,
+        I64Ne => I64Eq,
+        I64LtS => I64GeS,
+        I64LtU => I64GeU,
+        I64GtS => I64LeS,
+        I64GtU => I64LeU,
+        I64LeS => I64GtS,
+        I64LeU => I64GtU,
+        I64GeS => I64LtS,
+        I64GeU => I64LtU,
+        _ => return None,
+    })
+}
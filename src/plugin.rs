@@ -0,0 +1,140 @@
+//! Plugins discovered on disk, behind
+//! [`Runtime::load_dir`](crate::runtime::Runtime::load_dir).
+//!
+//! Scanning reads only each file's interface, so a host can list what is
+//! installed, check imports and exports, and pick what to run before paying
+//! for decoding. A [`Plugin`] decodes its module on first use, through the
+//! runtime's module cache.
+
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock};
+
+use crate::{
+    compat::CompatReport,
+    instance::OwnedInstance,
+    linker::Linker,
+    module::{Module, ModuleInterface},
+    runtime::Runtime,
+    trap::{Result, Trap},
+};
+
+/// File extension [`Runtime::load_dir`](crate::runtime::Runtime::load_dir)
+/// picks up.
+pub const PLUGIN_EXTENSION: &str = "rune";
+
+/// A `.rune` file and its interface, with the module decoded lazily.
+pub struct Plugin {
+    runtime: Runtime,
+    name: String,
+    path: PathBuf,
+    interface: ModuleInterface,
+    module: OnceLock<Arc<Module>>,
+}
+
+impl Plugin {
+    /// Read `path`'s interface without decoding any function.
+    pub(crate) fn open(runtime: &Runtime, path: PathBuf) -> Result<Self> {
+        let bytes = read(&path)?;
+        let interface = Module::interface(&bytes).map_err(|e| in_file(&path, e))?;
+        let name = path
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        Ok(Plugin {
+            runtime: runtime.share(),
+            name,
+            path,
+            interface,
+            module: OnceLock::new(),
+        })
+    }
+
+    /// The file name without its extension.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Memory limits, required features, exports and imports, as read when
+    /// the directory was scanned.
+    pub fn interface(&self) -> &ModuleInterface {
+        &self.interface
+    }
+
+    /// Compare the plugin's host requirements with what the runtime's
+    /// config provides, without decoding it; see
+    /// [`Runtime::check_compat`](crate::runtime::Runtime::check_compat).
+    pub fn check_compat(&self) -> CompatReport {
+        self.runtime
+            .config()
+            .host_api()
+            .check(&self.interface.host_requirements)
+    }
+
+    /// Whether the module has been decoded yet.
+    pub fn is_loaded(&self) -> bool {
+        self.module.get().is_some()
+    }
+
+    /// The module, read and decoded on the first call. The file is read
+    /// again at that point, so a plugin replaced on disk since the scan
+    /// loads its new contents.
+    pub fn module(&self) -> Result<Arc<Module>> {
+        if let Some(module) = self.module.get() {
+            return Ok(module.clone());
+        }
+        let bytes = read(&self.path)?;
+        let module = self
+            .runtime
+            .load_cached(&bytes)
+            .map_err(|e| in_file(&self.path, e))?;
+        Ok(self.module.get_or_init(|| module).clone())
+    }
+
+    /// Instantiate the plugin in the runtime that found it. Only for
+    /// plugins without imports; see [`instantiate_with`](Self::instantiate_with).
+    pub fn instantiate(&self) -> Result<OwnedInstance> {
+        self.runtime.instantiate_owned(self.module()?)
+    }
+
+    /// Instantiate the plugin, resolving its imports through `linker`.
+    pub fn instantiate_with(&self, linker: &Linker) -> Result<OwnedInstance> {
+        linker.instantiate_owned(&self.runtime, self.module()?)
+    }
+}
+
+/// Every `.rune` file directly in `dir`, in file name order.
+pub(crate) fn scan(runtime: &Runtime, dir: &Path) -> Result<Vec<Plugin>> {
+    let entries = std::fs::read_dir(dir).map_err(|e| io_error(dir, e))?;
+    let mut paths = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|e| io_error(dir, e))?.path();
+        if path.is_file() && path.extension().is_some_and(|ext| ext == PLUGIN_EXTENSION) {
+            paths.push(path);
+        }
+    }
+    paths.sort();
+    paths
+        .into_iter()
+        .map(|path| Plugin::open(runtime, path))
+        .collect()
+}
+
+fn read(path: &Path) -> Result<Vec<u8>> {
+    std::fs::read(path).map_err(|e| io_error(path, e))
+}
+
+fn io_error(path: &Path, e: std::io::Error) -> Trap {
+    Trap::HostError(format!("cannot read {}: {e}", path.display()))
+}
+
+/// Name the offending file in a decoding error.
+fn in_file(path: &Path, trap: Trap) -> Trap {
+    match trap {
+        Trap::InvalidModule(msg) => Trap::InvalidModule(format!("{}: {msg}", path.display())),
+        other => other,
+    }
+}
@@ -0,0 +1,161 @@
+//! Pools of ready-to-run instances.
+//!
+//! Instantiating prepares every function and builds the initial memory,
+//! which dominates the cost of a short call. An [`InstancePool`] prepares
+//! the functions once, shared by all its instances, builds memory up front
+//! for a fixed number of instances, hands them out, and puts each
+//! returned instance back in its freshly instantiated state so the next
+//! request can reuse it. Modules with imports are pooled through
+//! [`with_linker`](InstancePool::with_linker), which binds them once for
+//! every instance.
+
+use std::sync::{Arc, Mutex, PoisonError};
+
+use crate::{
+    blob::{BlobStore, NoBlobs},
+    image::MemoryImage,
+    instance::{Linked, OwnedInstance},
+    linker::{Capabilities, Linker},
+    module::Module,
+    runtime::Runtime,
+    trap::{Result, Trap},
+};
+
+/// Instances of one module, reset on return and shared between threads.
+pub struct InstancePool {
+    runtime: Runtime,
+    module: Arc<Module>,
+    linked: Linked,
+    image: MemoryImage,
+    capacity: usize,
+    idle: Mutex<Vec<OwnedInstance>>,
+}
+
+impl InstancePool {
+    /// Pre-instantiate `capacity` instances of `module` in `runtime`.
+    /// Fails with `Trap::UndefinedImport` if `module` imports anything;
+    /// use [`with_linker`](Self::with_linker) for such modules.
+    pub fn new(runtime: &Runtime, module: Arc<Module>, capacity: usize) -> Result<Self> {
+        Self::new_with_blobs(runtime, module, capacity, &NoBlobs)
+    }
+
+    /// Like [`new`](Self::new), fetching external data segments from
+    /// `blobs`. They are fetched once; returned instances are reset from a
+    /// [`MemoryImage`] of the initial memory.
+    pub fn new_with_blobs(
+        runtime: &Runtime,
+        module: Arc<Module>,
+        capacity: usize,
+        blobs: &dyn BlobStore,
+    ) -> Result<Self> {
+        Self::build(runtime, None, module, capacity, blobs)
+    }
+
+    /// Like [`new`](Self::new), binding `module`'s imports to `linker`'s
+    /// definitions once for every instance. Every capability is granted,
+    /// and later changes to `linker` do not affect the pool.
+    pub fn with_linker(
+        runtime: &Runtime,
+        linker: &Linker,
+        module: Arc<Module>,
+        capacity: usize,
+    ) -> Result<Self> {
+        Self::with_linker_and_blobs(runtime, linker, module, capacity, &NoBlobs)
+    }
+
+    /// Like [`with_linker`](Self::with_linker), fetching external data
+    /// segments from `blobs` as [`new_with_blobs`](Self::new_with_blobs)
+    /// does.
+    pub fn with_linker_and_blobs(
+        runtime: &Runtime,
+        linker: &Linker,
+        module: Arc<Module>,
+        capacity: usize,
+        blobs: &dyn BlobStore,
+    ) -> Result<Self> {
+        let granted = Capabilities::all();
+        Self::build(runtime, Some((linker, &granted)), module, capacity, blobs)
+    }
+
+    fn build(
+        runtime: &Runtime,
+        linker: Option<(&Linker, &Capabilities)>,
+        module: Arc<Module>,
+        capacity: usize,
+        blobs: &dyn BlobStore,
+    ) -> Result<Self> {
+        let prepared = runtime.prepare(&module)?;
+        let linked = Linked::with_prepared(&module, linker, 1, prepared)?;
+        let image = MemoryImage::new(&module, blobs)?;
+        let pool = InstancePool {
+            runtime: runtime.share(),
+            module,
+            linked,
+            image,
+            capacity,
+            idle: Mutex::new(Vec::with_capacity(capacity)),
+        };
+        let instances = (0..capacity)
+            .map(|_| pool.instantiate())
+            .collect::<Result<Vec<_>>>()?;
+        *pool.lock() = instances;
+        Ok(pool)
+    }
+
+    /// Take an idle instance, or instantiate a new one if none is left.
+    pub fn checkout(&self) -> Result<OwnedInstance> {
+        match self.lock().pop() {
+            Some(instance) => Ok(instance),
+            None => self.instantiate(),
+        }
+    }
+
+    /// Return an instance taken with [`checkout`](Self::checkout). Its
+    /// default memory is reset to the module's initial contents, memories
+    /// the host attached are detached, its host data, epoch deadline, CPU
+    /// time, CPU budget, random state and timers are dropped, and the
+    /// runtime's memory limiter, fuel and RNG seed are restored. Once
+    /// `capacity` instances are idle, further ones are dropped.
+    ///
+    /// Fails with `Trap::InvalidModule` for an instance of another module,
+    /// or one with functions swapped by `replace_function`, whose code
+    /// must not reach the next borrower; the instance is dropped.
+    pub fn checkin(&self, mut instance: OwnedInstance) -> Result<()> {
+        if !instance.is_instance_of(&self.module) {
+            return Err(Trap::InvalidModule(
+                "instance does not belong to this pool".into(),
+            ));
+        }
+        if instance.is_patched() {
+            return Err(Trap::InvalidModule(
+                "cannot check in an instance with replaced functions".into(),
+            ));
+        }
+        instance.recycle(&self.image)?;
+        let mut idle = self.lock();
+        if idle.len() < self.capacity {
+            idle.push(instance);
+        }
+        Ok(())
+    }
+
+    /// Instances ready to be checked out without instantiating.
+    pub fn idle(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn module(&self) -> &Arc<Module> {
+        &self.module
+    }
+
+    fn instantiate(&self) -> Result<OwnedInstance> {
+        self.runtime
+            .instantiate_owned_from_image(self.module.clone(), &self.image, &self.linked)
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, Vec<OwnedInstance>> {
+        // An instance is only pushed once fully reset, so the list is
+        // consistent even if a holder of the lock panicked.
+        self.idle.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
@@ -0,0 +1,279 @@
+//! Execution profiles, for profile-guided optimization.
+//!
+//! With [`RuntimeConfig::set_profiling`] on, instances run every function
+//! in the interpreter and count, for each function, how often it is
+//! called, how often each of its `call` ops runs, and which way each of
+//! its `if` and `br_if` ops goes. [`Instance::profile`] hands the counts
+//! back. Profiles of several instances or runs [merge](Profile::merge),
+//! and keep in a text file through their `Display` and `FromStr` impls, as
+//! `runec run --record-profile` writes them.
+//!
+//! A profile recorded in staging then steers what production makes of
+//! the module:
+//!
+//! - [`optimize_with_profile`] inlines small functions at the call sites
+//!   the profile shows hot, and turns `if`s around so the branch usually
+//!   taken falls through, as `runec opt --profile` does.
+//! - [`RuntimeConfig::set_tier_profile`] has [`Strategy::Tiered`] compile
+//!   the functions the profile shows hot on their first call, rather than
+//!   after as many calls again.
+//!
+//! Counts are kept by function index and by op index in the function's
+//! body as the module has it. Inlining and layout need the very module the
+//! profile was recorded from; tiering only needs the functions to be the
+//! same, so a profile still serves a module [optimized](crate::opt) from
+//! it.
+//!
+//! [`RuntimeConfig::set_profiling`]: crate::config::RuntimeConfig::set_profiling
+//! [`RuntimeConfig::set_tier_profile`]: crate::config::RuntimeConfig::set_tier_profile
+//! [`Instance::profile`]: crate::instance::Instance::profile
+//! [`optimize_with_profile`]: crate::opt::optimize_with_profile
+//! [`Strategy::Tiered`]: crate::config::Strategy::Tiered
+//!
+//! # Format
+//!
+//! ```text
+//! rune-profile 1
+//! module 9f86d0… 3
+//! func 0 1200 dot
+//! call 7 1200
+//! branch 12 1100 100
+//! ```
+//!
+//! After the header, the module's digest and its number of functions, each
+//! `func` line gives a function's index, calls and name, and the `call`
+//! and `branch` lines after it its op indices with their counts: runs of a
+//! `call`, and the times an `if` or `br_if` found its condition true, then
+//! false. Functions never called are left out.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::{
+    hash::to_hex,
+    module::Module,
+    trap::{Result, Trap},
+};
+
+/// Version written in a profile's header.
+pub const PROFILE_VERSION: u32 = 1;
+
+/// Counts of what a module's functions did while it ran.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Profile {
+    /// Digest of the module recorded, as [`Module::digest`].
+    pub module: [u8; 32],
+    /// Indexed like `Module::functions`.
+    pub functions: Vec<FunctionProfile>,
+}
+
+/// Counts for one function.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct FunctionProfile {
+    pub name: String,
+    pub calls: u64,
+    /// Runs of each `call` op that ran, by op index.
+    pub call_sites: BTreeMap<u32, u64>,
+    /// Which way each `if` and `br_if` that ran went, by op index.
+    pub branches: BTreeMap<u32, Branch>,
+}
+
+/// How often a conditional op found its condition true and false.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Branch {
+    pub taken: u64,
+    pub not_taken: u64,
+}
+
+impl Profile {
+    /// An empty profile of `module`.
+    pub fn new(module: &Module) -> Self {
+        Profile {
+            module: module.digest(),
+            functions: module
+                .functions
+                .iter()
+                .map(|f| FunctionProfile {
+                    name: f.name.clone(),
+                    ..FunctionProfile::default()
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether the profile was recorded from `module`.
+    pub fn is_of(&self, module: &Module) -> bool {
+        self.module == module.digest()
+    }
+
+    /// Whether `module` has the profile's functions, by index and name,
+    /// as a module optimized from the one recorded does.
+    pub fn fits(&self, module: &Module) -> bool {
+        self.functions.len() == module.functions.len()
+            && self
+                .functions
+                .iter()
+                .zip(&module.functions)
+                .all(|(p, f)| p.calls == 0 || p.name == f.name)
+    }
+
+    /// Add `other`'s counts, recorded from the same module, to these.
+    /// Fails with `Trap::InvalidModule` if it is of another module.
+    pub fn merge(&mut self, other: &Profile) -> Result<()> {
+        if other.module != self.module || other.functions.len() != self.functions.len() {
+            return Err(Trap::InvalidModule(
+                "profiles of different modules do not merge".into(),
+            ));
+        }
+        for (mine, theirs) in self.functions.iter_mut().zip(&other.functions) {
+            if mine.calls == 0 {
+                mine.name.clone_from(&theirs.name);
+            }
+            mine.calls += theirs.calls;
+            for (&op, &n) in &theirs.call_sites {
+                *mine.call_sites.entry(op).or_default() += n;
+            }
+            for (&op, branch) in &theirs.branches {
+                let mine = mine.branches.entry(op).or_default();
+                mine.taken += branch.taken;
+                mine.not_taken += branch.not_taken;
+            }
+        }
+        Ok(())
+    }
+
+    /// Calls to function `func`, or 0 if it has none or is out of range.
+    pub fn calls(&self, func: u32) -> u64 {
+        self.functions.get(func as usize).map_or(0, |f| f.calls)
+    }
+
+    pub(crate) fn count_call(&mut self, func: u32) {
+        if let Some(f) = self.functions.get_mut(func as usize) {
+            f.calls += 1;
+        }
+    }
+
+    pub(crate) fn count_site(&mut self, func: u32, op: usize) {
+        if let Some(f) = self.functions.get_mut(func as usize) {
+            *f.call_sites.entry(op as u32).or_default() += 1;
+        }
+    }
+
+    pub(crate) fn count_branch(&mut self, func: u32, op: usize, taken: bool) {
+        if let Some(f) = self.functions.get_mut(func as usize) {
+            let branch = f.branches.entry(op as u32).or_default();
+            if taken {
+                branch.taken += 1;
+            } else {
+                branch.not_taken += 1;
+            }
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "rune-profile {PROFILE_VERSION}")?;
+        writeln!(
+            f,
+            "module {} {}",
+            to_hex(&self.module),
+            self.functions.len()
+        )?;
+        for (i, func) in self.functions.iter().enumerate() {
+            if func.calls == 0 {
+                continue;
+            }
+            writeln!(f, "func {i} {} {}", func.calls, func.name)?;
+            for (op, n) in &func.call_sites {
+                writeln!(f, "call {op} {n}")?;
+            }
+            for (op, branch) in &func.branches {
+                writeln!(f, "branch {op} {} {}", branch.taken, branch.not_taken)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Profile {
+    type Err = Trap;
+
+    /// Parse a profile in the [format](crate::profile#format) `Display`
+    /// writes. Fails with `Trap::InvalidModule` naming the line at fault.
+    fn from_str(s: &str) -> Result<Self> {
+        let error =
+            |n: usize, what: &str| Trap::InvalidModule(format!("profile line {}: {what}", n + 1));
+        let mut lines = s.lines().enumerate();
+        match lines.next() {
+            Some((_, header)) if header == format!("rune-profile {PROFILE_VERSION}") => {}
+            _ => {
+                return Err(error(
+                    0,
+                    &format!("expected `rune-profile {PROFILE_VERSION}`"),
+                ))
+            }
+        }
+        let mut profile = Profile::default();
+        let mut current: Option<usize> = None;
+        for (n, line) in lines {
+            let words: Vec<&str> = line.split_whitespace().collect();
+            let number = |i: usize| -> Result<u64> {
+                let word = words.get(i).ok_or_else(|| error(n, "line too short"))?;
+                word.parse()
+                    .map_err(|_| error(n, &format!("invalid number `{word}`")))
+            };
+            match words.first().copied() {
+                None => {}
+                Some("module") => {
+                    let hex = words.get(1).copied().unwrap_or("");
+                    profile.module = parse_digest(hex).ok_or_else(|| error(n, "invalid digest"))?;
+                    profile.functions = vec![FunctionProfile::default(); number(2)? as usize];
+                }
+                Some("func") => {
+                    let index = number(1)? as usize;
+                    // The name is the rest of the line, spaces and all.
+                    let name = line.splitn(4, ' ').nth(3).unwrap_or("").to_string();
+                    let func = profile
+                        .functions
+                        .get_mut(index)
+                        .ok_or_else(|| error(n, "function index out of range"))?;
+                    *func = FunctionProfile {
+                        name,
+                        calls: number(2)?,
+                        ..FunctionProfile::default()
+                    };
+                    current = Some(index);
+                }
+                Some(kind @ ("call" | "branch")) => {
+                    let func = current.ok_or_else(|| error(n, "op counts before any `func`"))?;
+                    let op = number(1)? as u32;
+                    let counts = &mut profile.functions[func];
+                    if kind == "call" {
+                        counts.call_sites.insert(op, number(2)?);
+                    } else {
+                        let branch = Branch {
+                            taken: number(2)?,
+                            not_taken: number(3)?,
+                        };
+                        counts.branches.insert(op, branch);
+                    }
+                }
+                Some(other) => return Err(error(n, &format!("unknown line `{other}`"))),
+            }
+        }
+        Ok(profile)
+    }
+}
+
+fn parse_digest(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut digest = [0; 32];
+    for (i, byte) in digest.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(digest)
+}
@@ -0,0 +1,69 @@
+//! The per-instance random number generator behind the host library's
+//! `random_bytes` and `random_u64`, reachable from any host function
+//! through [`Caller::rng`](crate::instance::Caller::rng).
+//!
+//! With a seed from [`RuntimeConfig::set_rng_seed`] or
+//! [`Instance::set_rng_seed`], an instance draws the same sequence on every
+//! run, so it stays deterministic. Without one it is seeded from the host's
+//! entropy on first use, which counts as the `random` capability and is
+//! refused where that capability is denied.
+//!
+//! The generator is xoshiro256\*\*: fast and statistically sound, but not
+//! suitable for cryptography.
+//!
+//! [`RuntimeConfig::set_rng_seed`]: crate::config::RuntimeConfig::set_rng_seed
+//! [`Instance::set_rng_seed`]: crate::instance::Instance::set_rng_seed
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+
+/// A seedable pseudo-random number generator.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rng {
+    s: [u64; 4],
+}
+
+impl Rng {
+    /// The generator `seed` always produces the same sequence from.
+    pub fn from_seed(seed: u64) -> Self {
+        // Expand the seed with SplitMix64, which never yields the all-zero
+        // state xoshiro cannot leave.
+        let mut x = seed;
+        let mut next = || {
+            x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+            let mut z = x;
+            z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+            z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+            z ^ (z >> 31)
+        };
+        Rng {
+            s: [next(), next(), next(), next()],
+        }
+    }
+
+    /// A generator seeded unpredictably from the host.
+    pub fn from_entropy() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(0);
+        Self::from_seed(hasher.finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        let s = &mut self.s;
+        let result = s[1].wrapping_mul(5).rotate_left(7).wrapping_mul(9);
+        let t = s[1] << 17;
+        s[2] ^= s[0];
+        s[3] ^= s[1];
+        s[1] ^= s[2];
+        s[0] ^= s[3];
+        s[2] ^= t;
+        s[3] = s[3].rotate_left(45);
+        result
+    }
+
+    pub fn fill_bytes(&mut self, buf: &mut [u8]) {
+        for chunk in buf.chunks_mut(8) {
+            chunk.copy_from_slice(&self.next_u64().to_le_bytes()[..chunk.len()]);
+        }
+    }
+}
@@ -1,19 +1,677 @@
-use crate::{instance::Instance, module::Module, trap::Result};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::time::Duration;
 
-/// Top-level runtime context. Currently lightweight; reserve for future
-/// shared resources (fuel budgets, JIT caches, etc.).
+use crate::{
+    aot::CompileHandle,
+    blob::{BlobStore, DataSources, NoBlobs},
+    cache::ModuleCache,
+    compat::CompatReport,
+    config::RuntimeConfig,
+    embed::EmbeddedModule,
+    epoch::EpochTicker,
+    events::Event,
+    executor::{self, CallOutcome},
+    hash::sha256,
+    image::{MemoryImage, WarmImage},
+    instance::{Instance, Linked, ModuleRef, OwnedInstance, PreparedModule, ReloadReport},
+    linker::{Capabilities, Linker},
+    memory::{Memory, MemoryUsage, RuntimeMemoryStats, PAGE_SIZE},
+    metrics::{Metrics, RuntimeMetrics},
+    module::Module,
+    plugin::{self, Plugin},
+    sys::Instant,
+    timer::PollReport,
+    trap::{Result, Trap},
+    types::Val,
+};
+#[cfg(feature = "cranelift")]
+use crate::{
+    compiler::{self, CodeRegistry},
+    config::Strategy,
+};
+
+/// Top-level runtime context. Holds the configuration shared by every
+/// instance it creates and tracks their combined memory usage and activity;
+/// reserved for future shared resources (fuel budgets, JIT caches, etc.).
 pub struct Runtime {
-    _priv: (),
+    config: Arc<RuntimeConfig>,
+    usage: Arc<MemoryUsage>,
+    metrics: Arc<Metrics>,
+    modules: Arc<Mutex<ModuleCache>>,
+    epoch: Arc<AtomicU64>,
+    ticker: Arc<Mutex<Option<EpochTicker>>>,
+    #[cfg(feature = "cranelift")]
+    code: Arc<CodeRegistry>,
 }
 
 impl Runtime {
     pub fn new() -> Self {
-        Runtime { _priv: () }
+        Self::with_config(RuntimeConfig::new())
+    }
+
+    /// Create a runtime whose instances use `config`.
+    pub fn with_config(config: RuntimeConfig) -> Self {
+        let usage = Arc::new(MemoryUsage::with_limits(
+            config.memory_quota(),
+            config.max_instances(),
+            config.code_quota(),
+        ));
+        Runtime {
+            metrics: Arc::new(Metrics::new(usage.clone(), config.metrics_exporter())),
+            usage,
+            modules: Arc::new(Mutex::new(ModuleCache::new(config.module_cache_size()))),
+            epoch: Arc::new(AtomicU64::new(0)),
+            ticker: Arc::new(Mutex::new(None)),
+            #[cfg(feature = "cranelift")]
+            code: Arc::default(),
+            config: Arc::new(config),
+        }
+    }
+
+    pub fn config(&self) -> &RuntimeConfig {
+        &self.config
+    }
+
+    /// Memory usage summed over the instances this runtime has created.
+    pub fn memory_stats(&self) -> RuntimeMemoryStats {
+        self.usage.snapshot()
+    }
+
+    /// Counters summed over every instance this runtime has created: how
+    /// many were created and are alive, how many calls they served and how
+    /// those failed, and the memory they hold. Push them somewhere
+    /// periodically with [`RuntimeConfig::set_metrics_exporter`].
+    pub fn metrics(&self) -> RuntimeMetrics {
+        self.metrics.snapshot()
+    }
+
+    /// Current epoch. It starts at 0 and only moves forward, through
+    /// [`increment_epoch`](Self::increment_epoch) or the epoch ticker.
+    pub fn epoch(&self) -> u64 {
+        self.epoch.load(Ordering::Relaxed)
+    }
+
+    /// Advance the epoch by one, interrupting every instance whose deadline
+    /// it reaches; see [`Instance::set_epoch_deadline`]. Cheap and callable
+    /// from any thread, such as a host timer.
+    pub fn increment_epoch(&self) {
+        self.epoch.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Advance the epoch every `interval` from a background thread, so an
+    /// instance's deadline becomes a time limit of roughly `ticks *
+    /// interval`. Replaces any ticker already running. The thread stops
+    /// when [`disable_epoch_ticker`](Self::disable_epoch_ticker) is called
+    /// or the runtime and everything sharing it (pools, templates) is
+    /// dropped.
+    pub fn enable_epoch_ticker(&self, interval: Duration) -> Result<()> {
+        let ticker = EpochTicker::spawn(self.epoch.clone(), interval)?;
+        // The old ticker, if any, is joined after the lock is released.
+        let _old = self.epoch_ticker().replace(ticker);
+        Ok(())
+    }
+
+    /// Stop the epoch ticker, if running. The epoch keeps its value.
+    pub fn disable_epoch_ticker(&self) {
+        let _old = self.epoch_ticker().take();
+    }
+
+    fn epoch_ticker(&self) -> std::sync::MutexGuard<'_, Option<EpochTicker>> {
+        self.ticker.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Decode a module from `bytes`, or return the one already decoded from
+    /// identical bytes. Modules are keyed by the SHA-256 of their encoding
+    /// and kept up to [`RuntimeConfig::set_module_cache_size`], least
+    /// recently used first out. The first instantiation prepares the
+    /// module's functions and the cache keeps them, so later instances of
+    /// it, through this runtime or a [`Linker`], share them instead of
+    /// preparing again. Instantiate it through a [`Linker`] if it has
+    /// imports.
+    pub fn load_cached(&self, bytes: &[u8]) -> Result<Arc<Module>> {
+        let hash = sha256(bytes);
+        if let Some(module) = self.module_cache().get(&hash) {
+            return Ok(module);
+        }
+        // Decode unlocked; a racing load of the same bytes just loses.
+        let module = Arc::new(Module::from_bytes(bytes)?);
+        self.module_cache()
+            .insert(hash, bytes.len(), module.clone());
+        Ok(module)
+    }
+
+    /// Compare the host API version and capabilities `module` was built
+    /// against with what this runtime's config declares. Instantiation
+    /// fails on the same mismatches; this reports all of them up front.
+    pub fn check_compat(&self, module: &Module) -> CompatReport {
+        self.config.host_api().check(&module.host_requirements)
+    }
+
+    /// Find the plugins in `dir`: every `.rune` file directly inside it, in
+    /// file name order. Only their interfaces are read now; each module is
+    /// decoded, through [`load_cached`](Self::load_cached), when its
+    /// [`Plugin`] is first instantiated. Fails with `Trap::HostError` if the
+    /// directory or a file cannot be read, and with `Trap::InvalidModule`,
+    /// naming the file, if one is not a module.
+    pub fn load_dir(&self, dir: impl AsRef<Path>) -> Result<Vec<Plugin>> {
+        plugin::scan(self, dir.as_ref())
+    }
+
+    /// Number of modules held by [`load_cached`](Self::load_cached)'s cache.
+    pub fn cached_modules(&self) -> usize {
+        self.module_cache().len()
+    }
+
+    /// Drop every module from the cache. Modules still in use stay alive.
+    pub fn clear_module_cache(&self) {
+        self.module_cache().clear();
+    }
+
+    fn module_cache(&self) -> std::sync::MutexGuard<'_, ModuleCache> {
+        self.modules.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Bind `module`'s imports and check it against `memories` memories,
+    /// reusing the functions prepared for it if it came from
+    /// [`load_cached`](Self::load_cached), and keeping them there if not
+    /// yet prepared.
+    fn link(
+        &self,
+        module: &Module,
+        linker: Option<(&Linker, &Capabilities)>,
+        memories: usize,
+    ) -> Result<Linked> {
+        let cached = self.module_cache().prepared(module);
+        let prepared = match cached {
+            Some(prepared) => prepared,
+            None => {
+                let prepared = self.prepare(module)?;
+                self.module_cache().set_prepared(module, prepared.clone());
+                prepared
+            }
+        };
+        Linked::with_prepared(module, linker, memories, prepared)
     }
 
     /// Instantiate a module, applying data segments and wiring host functions.
     pub fn instantiate<'m>(&self, module: &'m Module) -> Result<Instance<'m>> {
-        Instance::new(module)
+        self.instantiate_with_blobs(module, &NoBlobs)
+    }
+
+    /// Instantiate a module whose external data segments are fetched from
+    /// `blobs` and verified against their recorded hashes.
+    pub fn instantiate_with_blobs<'m>(
+        &self,
+        module: &'m Module,
+        blobs: &dyn BlobStore,
+    ) -> Result<Instance<'m>> {
+        let admitted = self.admit(module)?;
+        let linked = self.link(module, None, 1)?;
+        let instance = Instance::from_linked(
+            module.into(),
+            blobs,
+            Vec::new(),
+            linked,
+            self.config.clone(),
+        )?;
+        self.track(instance, admitted)
+    }
+
+    /// Instantiate a module held in an `Arc`. The instance keeps the module
+    /// alive itself instead of borrowing it, so it can outlive the caller's
+    /// scope: stored in a struct, sent to another thread or held by an async
+    /// task. Instances of one module share it.
+    pub fn instantiate_owned(&self, module: Arc<Module>) -> Result<OwnedInstance> {
+        let admitted = self.admit(&module)?;
+        let linked = self.link(&module, None, 1)?;
+        let instance = Instance::from_linked(
+            module.into(),
+            &NoBlobs,
+            Vec::new(),
+            linked,
+            self.config.clone(),
+        )?;
+        self.track(instance, admitted)
+    }
+
+    /// Instantiate a module with `memories` attached after its default
+    /// memory, as memories 1, 2, … in order, so its loads and stores can
+    /// name them from the start. Typically these wrap host buffers with
+    /// [`Memory::from_external`]; take them back with
+    /// [`Instance::into_memories`] when done. They keep their own limits and
+    /// limiter and do not count towards [`memory_stats`](Self::memory_stats).
+    ///
+    /// [`Memory::from_external`]: crate::memory::Memory::from_external
+    pub fn instantiate_with_memories<'m>(
+        &self,
+        module: &'m Module,
+        memories: Vec<Memory>,
+    ) -> Result<Instance<'m>> {
+        let admitted = self.admit(module)?;
+        let linked = self.link(module, None, 1 + memories.len())?;
+        let instance = Instance::from_linked(
+            module.into(),
+            &NoBlobs,
+            memories,
+            linked,
+            self.config.clone(),
+        )?;
+        self.track(instance, admitted)
+    }
+
+    /// Instantiate a module whose external data segments are streamed from
+    /// the files and readers in `sources` directly into guest memory.
+    pub fn instantiate_with_data_sources<'m>(
+        &self,
+        module: &'m Module,
+        sources: &DataSources,
+    ) -> Result<Instance<'m>> {
+        self.instantiate_with_blobs(module, sources)
+    }
+
+    /// Instantiate a module with its memory initialised from `image`, which
+    /// must have been built from the same module. On Linux the image's pages
+    /// are shared copy-on-write, so creating many short-lived instances does
+    /// not copy data segments each time.
+    pub fn instantiate_from_image<'m>(
+        &self,
+        module: &'m Module,
+        image: &MemoryImage,
+    ) -> Result<Instance<'m>> {
+        let admitted = self.admit(module)?;
+        let linked = self.link(module, None, 1)?;
+        let instance = Instance::from_image(module.into(), image, linked, self.config.clone())?;
+        self.track(instance, admitted)
+    }
+
+    pub(crate) fn instantiate_owned_from_image(
+        &self,
+        module: Arc<Module>,
+        image: &MemoryImage,
+        linked: &Linked,
+    ) -> Result<OwnedInstance> {
+        self.instantiate_image_linked(module.into(), image, linked.clone())
+    }
+
+    fn instantiate_image_prepared<'m>(
+        &self,
+        module: ModuleRef<'m>,
+        image: &MemoryImage,
+        prepared: &Arc<PreparedModule>,
+    ) -> Result<Instance<'m>> {
+        let linked = Linked::with_prepared(&module, None, 1, prepared.clone())?;
+        self.instantiate_image_linked(module, image, linked)
+    }
+
+    fn instantiate_image_linked<'m>(
+        &self,
+        module: ModuleRef<'m>,
+        image: &MemoryImage,
+        linked: Linked,
+    ) -> Result<Instance<'m>> {
+        let admitted = self.admit(&module)?;
+        let instance = Instance::from_image(module, image, linked, self.config.clone())?;
+        self.track(instance, admitted)
+    }
+
+    /// Instantiate `module`, call its export `init` with no arguments and
+    /// snapshot the result, for [`instantiate_warm`](Self::instantiate_warm)
+    /// to start instances from without applying data segments or running
+    /// `init` again. Instantiate through a [`Linker`] and use
+    /// [`Instance::snapshot`] instead if `init` needs imports.
+    pub fn warm_boot(&self, module: &Module, init: &str) -> Result<WarmImage> {
+        let mut instance = self.instantiate(module)?;
+        instance.call(init, &[])?;
+        instance.snapshot()
+    }
+
+    /// Instantiate `module` in the state captured by `image`, which must
+    /// come from an instance of this module, or a clone of it, created by
+    /// this runtime or a handle sharing its configuration. Anything else
+    /// fails with `Trap::InvalidModule`. On Linux the memory is mapped
+    /// copy-on-write from the image.
+    pub fn instantiate_warm<'m>(
+        &self,
+        module: &'m Module,
+        image: &WarmImage,
+    ) -> Result<Instance<'m>> {
+        self.instantiate_warm_linked(module.into(), image, None)
+    }
+
+    pub(crate) fn instantiate_warm_linked<'m>(
+        &self,
+        module: ModuleRef<'m>,
+        image: &WarmImage,
+        linker: Option<(&Linker, &Capabilities)>,
+    ) -> Result<Instance<'m>> {
+        image.prepared.check(&module, &self.config)?;
+        let admitted = self.admit(&module)?;
+        let linked = Linked::with_prepared(&module, linker, 1, image.prepared.clone())?;
+        let instance = Instance::from_warm_image(module, image, linked, self.config.clone())?;
+        self.track(instance, admitted)
+    }
+
+    /// Start compiling `module` to native code on a background thread and
+    /// return a handle to follow it. Instances of the module can be created
+    /// and called meanwhile; they run on the interpreter until native code
+    /// is ready, and keep doing so if compilation fails or no backend is
+    /// available. See [`aot`](crate::aot).
+    pub fn compile_background(&self, module: &Arc<Module>) -> CompileHandle {
+        #[cfg(feature = "cranelift")]
+        let handle = CompileHandle::spawn(
+            module.clone(),
+            self.config.clone(),
+            self.code.slot(module),
+            self.code.cache(),
+        );
+        #[cfg(not(feature = "cranelift"))]
+        let handle = CompileHandle::spawn(module.clone(), self.config.clone());
+        handle
+    }
+
+    /// Save native code this runtime compiles from now on under `dir`,
+    /// creating it if needed, and load code from there instead of compiling
+    /// when a module was compiled before for the same host, compiler and
+    /// configuration. Damaged or stale files are compiled over. Only x86-64
+    /// Linux hosts cache code, and only under [`Strategy::Cranelift`] and
+    /// [`Strategy::Baseline`].
+    #[cfg(feature = "cranelift")]
+    pub fn enable_code_cache(&self, dir: impl AsRef<Path>) -> Result<()> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)
+            .map_err(|e| Trap::HostError(format!("cannot create {}: {e}", dir.display())))?;
+        self.code
+            .set_cache(compiler::CodeCache::new(dir.to_path_buf()));
+        Ok(())
+    }
+
+    /// Load a module compiled ahead of time into a shared library by
+    /// [`library::build`](compiler::library::build) or `runec compile -o
+    /// <lib>.so`. Its instances from this runtime run the library's code
+    /// under any strategy, with nothing compiled at run time; functions the
+    /// library left out are interpreted. Fails if the library was built by
+    /// another version of Rune or for other compiled-in settings, or on
+    /// hosts other than x86-64 Linux.
+    #[cfg(feature = "cranelift")]
+    pub fn load_library(&self, path: impl AsRef<Path>) -> Result<Arc<Module>> {
+        let (module, code) = compiler::library::load(path.as_ref(), &self.config)?;
+        let module = Arc::new(module);
+        let _ = self.code.slot(&module).set(Some(Arc::new(code)));
+        Ok(module)
+    }
+
+    /// The module `embedded` holds, with the code it was compiled to ahead
+    /// of time, if any, in place of anything this runtime would compile.
+    /// Like a library's, the code runs under any strategy and only loads
+    /// under the runtime version, host and compiled-in settings it was
+    /// built for; without the `cranelift` feature it is ignored and the
+    /// module interpreted.
+    pub fn load_embedded(&self, embedded: &EmbeddedModule) -> Result<Arc<Module>> {
+        let module = embedded.module()?;
+        #[cfg(feature = "cranelift")]
+        if let Some(code) = embedded.code() {
+            let code = compiler::embedded::load(code, &module, &self.config)?;
+            let _ = self.code.slot(&module).set(Some(Arc::new(code)));
+        }
+        Ok(module)
+    }
+
+    /// Check `module` against this runtime's configuration and build its
+    /// functions' jump tables once, for [`instantiate_prepared`] to share
+    /// between instances instead of repeating per instantiation.
+    ///
+    /// [`instantiate_prepared`]: Self::instantiate_prepared
+    pub fn prepare(&self, module: &Module) -> Result<Arc<PreparedModule>> {
+        Ok(Arc::new(PreparedModule::new(module, &self.config)?))
+    }
+
+    /// Instantiate `module` reusing `prepared`, which must come from
+    /// [`prepare`](Self::prepare) on this runtime, or a handle sharing its
+    /// configuration, given this module or a clone of it. Anything else
+    /// fails with `Trap::InvalidModule`.
+    pub fn instantiate_prepared<'m>(
+        &self,
+        module: &'m Module,
+        prepared: &Arc<PreparedModule>,
+    ) -> Result<Instance<'m>> {
+        prepared.check(module, &self.config)?;
+        let admitted = self.admit(module)?;
+        let linked = Linked::with_prepared(module, None, 1, prepared.clone())?;
+        let instance = Instance::from_linked(
+            module.into(),
+            &NoBlobs,
+            Vec::new(),
+            linked,
+            self.config.clone(),
+        )?;
+        self.track(instance, admitted)
+    }
+
+    /// Create `n` instances of `module` in parallel, in order. Its functions
+    /// are prepared and its initial memory built once and shared by every
+    /// instance; the instances themselves are created on scoped threads, up
+    /// to one per available core. Only for modules without imports or
+    /// external data segments. If any instantiation fails, the first error
+    /// in instance order is returned and the rest are dropped.
+    pub fn instantiate_batch<'m>(&self, module: &'m Module, n: usize) -> Result<Vec<Instance<'m>>> {
+        self.batch(module.into(), n)
+    }
+
+    /// [`instantiate_batch`](Self::instantiate_batch) for instances that
+    /// each hold a share of `module`.
+    pub fn instantiate_batch_owned(
+        &self,
+        module: Arc<Module>,
+        n: usize,
+    ) -> Result<Vec<OwnedInstance>> {
+        self.batch(module.into(), n)
+    }
+
+    fn batch<'m>(&self, module: ModuleRef<'m>, n: usize) -> Result<Vec<Instance<'m>>> {
+        if n == 0 {
+            return Ok(Vec::new());
+        }
+        let prepared = self.prepare(&module)?;
+        let image = MemoryImage::new(&module, &NoBlobs)?;
+        let workers = std::thread::available_parallelism().map_or(1, |p| p.get());
+        if workers == 1 {
+            // Build them here: targets without threads cannot start one.
+            return (0..n)
+                .map(|_| self.instantiate_image_prepared(module.clone(), &image, &prepared))
+                .collect();
+        }
+        let per_worker = n.div_ceil(workers.min(n));
+        let chunks: Vec<Result<Vec<Instance<'m>>>> = std::thread::scope(|s| {
+            let handles: Vec<_> = (0..n)
+                .step_by(per_worker)
+                .map(|start| {
+                    let count = per_worker.min(n - start);
+                    let (module, image, prepared) = (module.clone(), &image, &prepared);
+                    s.spawn(move || {
+                        (0..count)
+                            .map(|_| {
+                                self.instantiate_image_prepared(module.clone(), image, prepared)
+                            })
+                            .collect()
+                    })
+                })
+                .collect();
+            handles
+                .into_iter()
+                .map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+                .collect()
+        });
+        let mut instances = Vec::with_capacity(n);
+        for chunk in chunks {
+            instances.extend(chunk?);
+        }
+        Ok(instances)
+    }
+
+    /// Run the due timers of every instance in `instances`, as
+    /// [`Instance::poll_timers`] does for one. A trapping callback stops
+    /// only its own instance's timers for this pass. Call it from the
+    /// host's event loop, sleeping up to the report's `next_due` between
+    /// passes.
+    pub fn poll_timers<'a, 'm: 'a>(
+        &self,
+        instances: impl IntoIterator<Item = &'a mut Instance<'m>>,
+    ) -> PollReport {
+        let mut report = PollReport::default();
+        for (i, instance) in instances.into_iter().enumerate() {
+            let (ran, result) = instance.run_due_timers();
+            report.ran += ran;
+            if let Err(trap) = result {
+                report.traps.push((i, trap));
+            }
+            if let Some(next) = instance.next_timer() {
+                report.next_due = Some(report.next_due.map_or(next, |n| n.min(next)));
+            }
+        }
+        report
+    }
+
+    /// Call export `func` of `instance` on a worker thread, cancelling it
+    /// with `Trap::Interrupted` if it has not returned after `timeout`, and
+    /// wait for it either way. A host function already running is not
+    /// interrupted; the call stops when it returns. Fails only if the
+    /// worker cannot be started; the call's own error is in the outcome.
+    pub fn call_blocking(
+        &self,
+        instance: &mut Instance<'_>,
+        func: &str,
+        args: &[Val],
+        timeout: Duration,
+    ) -> Result<CallOutcome> {
+        executor::call_with_timeout(instance, func, args, timeout)
+    }
+
+    /// Move `instance` to `module`, a new version of its module, keeping its
+    /// linear memory, host data and host bindings so a running plugin picks
+    /// up new code without losing state. Data segments are not re-applied;
+    /// memory only grows if the new version starts larger.
+    ///
+    /// Every export the two versions share must keep its signature, and the
+    /// new version must import exactly what the old one did. On any error
+    /// the instance is left unchanged. Returns what changed.
+    pub fn reload<'m>(
+        &self,
+        instance: &mut Instance<'m>,
+        module: &'m Module,
+    ) -> Result<ReloadReport> {
+        instance.reload(module.into())
+    }
+
+    /// [`reload`](Self::reload) for an [`OwnedInstance`], which takes a share
+    /// of the new module and releases the old one.
+    pub fn reload_owned(
+        &self,
+        instance: &mut OwnedInstance,
+        module: Arc<Module>,
+    ) -> Result<ReloadReport> {
+        instance.reload(module.into())
+    }
+
+    pub(crate) fn instantiate_linked<'m>(
+        &self,
+        module: ModuleRef<'m>,
+        linker: &Linker,
+        granted: &Capabilities,
+    ) -> Result<Instance<'m>> {
+        let admitted = self.admit(&module)?;
+        let linked = self.link(&module, Some((linker, granted)), 1)?;
+        let instance =
+            Instance::from_linked(module, &NoBlobs, Vec::new(), linked, self.config.clone())?;
+        self.track(instance, admitted)
+    }
+
+    pub(crate) fn instantiate_pre<'m>(
+        &self,
+        module: ModuleRef<'m>,
+        linked: &Linked,
+        blobs: &dyn BlobStore,
+    ) -> Result<Instance<'m>> {
+        let admitted = self.admit(&module)?;
+        let instance = Instance::from_linked(
+            module,
+            blobs,
+            Vec::new(),
+            linked.clone(),
+            self.config.clone(),
+        )?;
+        self.track(instance, admitted)
+    }
+
+    /// Another handle on this runtime's configuration and usage counters.
+    pub(crate) fn share(&self) -> Runtime {
+        Runtime {
+            config: self.config.clone(),
+            usage: self.usage.clone(),
+            metrics: self.metrics.clone(),
+            modules: self.modules.clone(),
+            epoch: self.epoch.clone(),
+            ticker: self.ticker.clone(),
+            #[cfg(feature = "cranelift")]
+            code: self.code.clone(),
+        }
+    }
+
+    /// Fail early, before allocating, when the instance cap is reached, the
+    /// resource limiter refuses another instance or `module`'s initial
+    /// memory would not fit in the memory quota. Returns when admission
+    /// started, for timing the instantiation.
+    fn admit(&self, module: &Module) -> Result<Instant> {
+        let started = Instant::now();
+        if !self.usage.has_instance_room() {
+            return Err(Trap::OutOfMemory);
+        }
+        if let Some(limiter) = self.config.resource_limiter() {
+            if !limiter.instance_creating(self.usage.snapshot().instances) {
+                return Err(Trap::OutOfMemory);
+            }
+        }
+        let bytes = module.initial_memory_pages.saturating_mul(PAGE_SIZE);
+        if self.usage.fits(bytes) {
+            Ok(started)
+        } else {
+            Err(Trap::OutOfMemory)
+        }
+    }
+
+    fn track<'m>(&self, mut instance: Instance<'m>, admitted: Instant) -> Result<Instance<'m>> {
+        instance.charge_code(&self.usage)?;
+        // Only the default memory is the runtime's; any others are the host's.
+        instance.memories[0].track(self.usage.clone())?;
+        instance.set_metrics(self.metrics.clone());
+        instance.set_epoch(self.epoch.clone());
+        #[cfg(feature = "cranelift")]
+        {
+            let slot = self.code.slot(instance.module());
+            match self.config.strategy() {
+                // Code compiled or loaded up front replaces tiering.
+                Strategy::Tiered if slot.get().and_then(Option::as_ref).is_none() => {
+                    instance.set_tiers(self.code.tiers(instance.module(), &self.config));
+                }
+                Strategy::Interpreter | Strategy::Tiered => {}
+                Strategy::Baseline | Strategy::Cranelift => {
+                    slot.get_or_init(|| {
+                        let cache = self.code.cache();
+                        compiler::compile(instance.module(), &self.config, cache.as_deref())
+                    });
+                }
+            }
+            instance.set_native(slot);
+        }
+        self.metrics.instantiated();
+        if self.config.has_event_hooks() {
+            self.config.emit(&Event::Instantiated {
+                module: instance.module(),
+                elapsed: admitted.elapsed(),
+            });
+        }
+        Ok(instance)
     }
 }
 
@@ -0,0 +1,86 @@
+//! Preset configurations for running code of differing trust, behind
+//! [`RuntimeConfig::with_profile`](crate::config::RuntimeConfig::with_profile).
+//!
+//! | Setting | `Strict` | `Default` | `Trusted` |
+//! |---|---|---|---|
+//! | Fuel per instance | 100 M ops | 2 G ops | unmetered |
+//! | Default memory cap | 256 pages (16 MiB) | 4096 pages (256 MiB) | module's own |
+//! | Call depth | 256 | 512 | unlimited |
+//! | Denied capabilities | clock, random, stdio, env | none | none |
+//!
+//! `Strict` denies every source of outside input the host library has, so
+//! a run depends only on the module and its arguments: two runs with the
+//! same inputs produce the same results, and run out of fuel on the same op.
+//! Epoch deadlines, being wall-clock based, are the host's to avoid. Guests
+//! that need time can still be given a host library on a
+//! [`ManualClock`](crate::clock::ManualClock), whose functions are not
+//! labelled `clock`, and random numbers by seeding their generator with
+//! [`RuntimeConfig::set_rng_seed`](crate::config::RuntimeConfig::set_rng_seed).
+//!
+//! The capability constants below are the labels the `hostlib` module
+//! gives its functions; embedders can reuse them for their own functions of
+//! the same kind.
+
+/// Label of functions that read a clock.
+pub const CLOCK: &str = "clock";
+/// Label of functions that produce random bytes.
+pub const RANDOM: &str = "random";
+/// Label of functions that write to the host's output streams.
+pub const STDIO: &str = "stdio";
+/// Label of functions that read the arguments or environment.
+pub const ENV: &str = "env";
+
+/// A bundle of resource limits and capability denials.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SandboxProfile {
+    /// For untrusted code: tight limits and deterministic execution.
+    Strict,
+    /// For code from known sources: generous limits against runaway guests.
+    #[default]
+    Default,
+    /// For the embedder's own code: no limits beyond the module's.
+    Trusted,
+}
+
+impl SandboxProfile {
+    /// Fuel each instance starts with.
+    pub fn fuel(self) -> Option<u64> {
+        match self {
+            SandboxProfile::Strict => Some(100_000_000),
+            SandboxProfile::Default => Some(2_000_000_000),
+            SandboxProfile::Trusted => None,
+        }
+    }
+
+    /// Cap on each instance's default memory, in pages.
+    pub fn max_memory_pages(self) -> Option<usize> {
+        match self {
+            SandboxProfile::Strict => Some(256),
+            SandboxProfile::Default => Some(4096),
+            SandboxProfile::Trusted => None,
+        }
+    }
+
+    /// Most guest functions active at once.
+    pub fn max_call_depth(self) -> Option<usize> {
+        match self {
+            SandboxProfile::Strict => Some(256),
+            SandboxProfile::Default => Some(512),
+            SandboxProfile::Trusted => None,
+        }
+    }
+
+    /// Capability labels withheld from every instance.
+    pub fn denied_capabilities(self) -> &'static [&'static str] {
+        match self {
+            SandboxProfile::Strict => &[CLOCK, RANDOM, STDIO, ENV],
+            SandboxProfile::Default | SandboxProfile::Trusted => &[],
+        }
+    }
+
+    /// Whether results depend only on the module and its inputs.
+    pub fn is_deterministic(self) -> bool {
+        let denied = self.denied_capabilities();
+        denied.contains(&CLOCK) && denied.contains(&RANDOM)
+    }
+}
@@ -0,0 +1,125 @@
+//! Source maps — optional per-function line tables.
+//!
+//! Frontends record where each op came from so traps and the disassembler
+//! can point back at the original source. The map is pure metadata: it is
+//! never consulted on the execution hot path, only when a trap is reported.
+
+/// A position in a frontend source file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SourceLoc {
+    /// Index into [`SourceMap::files`].
+    pub file: u32,
+    pub line: u32,
+    pub column: u32,
+}
+
+/// Maps op indices to source locations, one line table per function.
+///
+/// Each table is a list of `(op index, location)` entries sorted by op index.
+/// An entry covers every op from its index up to the next entry, so a
+/// frontend only needs to emit one entry per source statement.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SourceMap {
+    /// File names referenced by [`SourceLoc::file`].
+    pub files: Vec<String>,
+    /// Line tables, indexed like `Module::functions`.
+    pub functions: Vec<Vec<(u32, SourceLoc)>>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Intern a file name, returning its id.
+    pub fn add_file(&mut self, name: impl Into<String>) -> u32 {
+        let name = name.into();
+        if let Some(id) = self.files.iter().position(|f| *f == name) {
+            return id as u32;
+        }
+        self.files.push(name);
+        (self.files.len() - 1) as u32
+    }
+
+    /// Record that ops of function `func` starting at `op_index` come from `loc`.
+    pub fn add_entry(&mut self, func: u32, op_index: u32, loc: SourceLoc) {
+        let func = func as usize;
+        if self.functions.len() <= func {
+            self.functions.resize(func + 1, Vec::new());
+        }
+        let table = &mut self.functions[func];
+        let pos = table.partition_point(|(op, _)| *op <= op_index);
+        table.insert(pos, (op_index, loc));
+    }
+
+    /// Find the source location covering op `op_index` of function `func`.
+    pub fn lookup(&self, func: u32, op_index: usize) -> Option<SourceLoc> {
+        let table = self.functions.get(func as usize)?;
+        let pos = table.partition_point(|(op, _)| *op as usize <= op_index);
+        pos.checked_sub(1).map(|i| table[i].1)
+    }
+
+    /// Name of the file with id `file`, if any.
+    pub fn file_name(&self, file: u32) -> Option<&str> {
+        self.files.get(file as usize).map(String::as_str)
+    }
+
+    /// Append `other`'s tables after the first `n_funcs` functions of this map.
+    pub(crate) fn append(&mut self, n_funcs: usize, other: SourceMap) {
+        let file_base = self.files.len() as u32;
+        self.files.extend(other.files);
+        self.functions.resize(n_funcs, Vec::new());
+        self.functions
+            .extend(other.functions.into_iter().map(|table| {
+                table
+                    .into_iter()
+                    .map(|(op, loc)| {
+                        let file = loc.file + file_base;
+                        (op, SourceLoc { file, ..loc })
+                    })
+                    .collect()
+            }));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loc(line: u32) -> SourceLoc {
+        SourceLoc {
+            file: 0,
+            line,
+            column: 1,
+        }
+    }
+
+    #[test]
+    fn lookup_covers_following_ops() {
+        let mut sm = SourceMap::new();
+        sm.add_file("main.c");
+        sm.add_entry(0, 0, loc(10));
+        sm.add_entry(0, 4, loc(11));
+        assert_eq!(sm.lookup(0, 0), Some(loc(10)));
+        assert_eq!(sm.lookup(0, 3), Some(loc(10)));
+        assert_eq!(sm.lookup(0, 4), Some(loc(11)));
+        assert_eq!(sm.lookup(0, 99), Some(loc(11)));
+        assert_eq!(sm.lookup(1, 0), None);
+    }
+
+    #[test]
+    fn entries_kept_sorted() {
+        let mut sm = SourceMap::new();
+        sm.add_entry(0, 8, loc(3));
+        sm.add_entry(0, 2, loc(1));
+        assert_eq!(sm.lookup(0, 5), Some(loc(1)));
+    }
+
+    #[test]
+    fn files_are_interned() {
+        let mut sm = SourceMap::new();
+        assert_eq!(sm.add_file("a.c"), 0);
+        assert_eq!(sm.add_file("b.c"), 1);
+        assert_eq!(sm.add_file("a.c"), 0);
+    }
+}
@@ -0,0 +1,511 @@
+//! Thin OS bindings used for memory mapping and reading the time.
+//!
+//! Declared directly rather than through the `libc` crate to keep the core
+//! crate dependency-free. Only Linux on x86-64/AArch64 is covered, where the
+//! constants below are identical; elsewhere callers fall back to plain heap
+//! memory.
+//!
+//! On `wasm32-unknown-unknown` the standard library's clocks panic, so time
+//! comes from a source the embedder installs with
+//! [`clock::set_time_source`](crate::clock::set_time_source) instead.
+
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+mod imp {
+    use std::fs::File;
+    use std::os::fd::{AsRawFd, FromRawFd};
+    use std::os::raw::{c_char, c_int, c_uint, c_void};
+
+    const PROT_NONE: c_int = 0;
+    const PROT_READ: c_int = 1;
+    const PROT_WRITE: c_int = 2;
+    #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+    const PROT_EXEC: c_int = 4;
+    const MAP_PRIVATE: c_int = 0x02;
+    const MAP_FIXED: c_int = 0x10;
+    const MAP_ANONYMOUS: c_int = 0x20;
+    const MAP_NORESERVE: c_int = 0x4000;
+    const MFD_CLOEXEC: c_uint = 1;
+
+    extern "C" {
+        fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: i64,
+        ) -> *mut c_void;
+        fn munmap(addr: *mut c_void, len: usize) -> c_int;
+        fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+        fn memfd_create(name: *const c_char, flags: c_uint) -> c_int;
+        #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+        fn dlopen(filename: *const c_char, flags: c_int) -> *mut c_void;
+        #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+        fn dlsym(handle: *mut c_void, symbol: *const c_char) -> *mut c_void;
+        #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+        fn dlclose(handle: *mut c_void) -> c_int;
+        #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+        fn dlerror() -> *mut c_char;
+        #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+        fn clock_gettime(clock: c_int, ts: *mut [i64; 2]) -> c_int;
+    }
+
+    fn failed(p: *mut c_void) -> bool {
+        p as isize == -1
+    }
+
+    /// An anonymous in-memory file, used to hold a shareable memory image.
+    pub fn memfd(name: &std::ffi::CStr) -> Option<File> {
+        // SAFETY: `name` is NUL-terminated; a non-negative return value is a
+        // freshly opened descriptor that we take sole ownership of.
+        let fd = unsafe { memfd_create(name.as_ptr(), MFD_CLOEXEC) };
+        (fd >= 0).then(|| unsafe { File::from_raw_fd(fd) })
+    }
+
+    /// A private read-write mapping of `reserved` bytes. Optionally the start
+    /// of it is a copy-on-write view of an image file; the rest is
+    /// zero-filled anonymous memory.
+    pub struct Mapping {
+        ptr: *mut u8,
+        reserved: usize,
+        /// Whether only a prefix is accessible, the rest faulting.
+        guarded: bool,
+        /// Whether the start is a view of an image file.
+        shared: bool,
+    }
+
+    // SAFETY: the mapping is exclusively owned and only reached through
+    // `&self`/`&mut self`, like a `Vec<u8>`.
+    unsafe impl Send for Mapping {}
+    unsafe impl Sync for Mapping {}
+
+    /// Bytes a guarded mapping reserves: everything a 32-bit address and an
+    /// access of up to 8 bytes can reach from its base, rounded up to a
+    /// whole guard page.
+    #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+    pub const GUARDED_RESERVATION: usize = (1 << 32) + (64 << 10);
+
+    impl Mapping {
+        pub fn new(reserved: usize, image: Option<(&File, usize)>) -> Option<Mapping> {
+            Mapping::map(reserved, reserved, image)
+        }
+
+        /// A mapping of [`GUARDED_RESERVATION`] bytes of which only the
+        /// first `len` are accessible, optionally starting with a view of
+        /// an image file as in [`new`](Self::new).
+        #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+        pub fn guarded(len: usize, image: Option<(&File, usize)>) -> Option<Mapping> {
+            Mapping::map(GUARDED_RESERVATION, len, image)
+        }
+
+        /// Reserve `reserved` bytes, the first `accessible` of them
+        /// read-write.
+        fn map(
+            reserved: usize,
+            accessible: usize,
+            image: Option<(&File, usize)>,
+        ) -> Option<Mapping> {
+            if reserved == 0 {
+                return None;
+            }
+            let guarded = accessible < reserved;
+            // SAFETY: a fresh anonymous mapping at an address of the kernel's
+            // choosing; it aliases nothing.
+            let base = unsafe {
+                mmap(
+                    std::ptr::null_mut(),
+                    reserved,
+                    if guarded {
+                        PROT_NONE
+                    } else {
+                        PROT_READ | PROT_WRITE
+                    },
+                    MAP_PRIVATE | MAP_ANONYMOUS | MAP_NORESERVE,
+                    -1,
+                    0,
+                )
+            };
+            if failed(base) {
+                return None;
+            }
+            let image = image.filter(|(_, len)| *len > 0);
+            let mut mapping = Mapping {
+                ptr: base as *mut u8,
+                reserved,
+                guarded,
+                shared: image.is_some(),
+            };
+            if guarded && !mapping.set_accessible(0..accessible, true) {
+                return None; // `mapping` is unmapped on drop
+            }
+            if let Some((file, len)) = image {
+                assert!(len <= accessible);
+                // SAFETY: replaces the first `len` bytes of our own mapping.
+                let p = unsafe {
+                    mmap(
+                        base,
+                        len,
+                        PROT_READ | PROT_WRITE,
+                        MAP_PRIVATE | MAP_FIXED,
+                        file.as_raw_fd(),
+                        0,
+                    )
+                };
+                if failed(p) {
+                    return None;
+                }
+            }
+            Some(mapping)
+        }
+
+        /// Make `range` of a guarded mapping read-write, or make it fault
+        /// again. Its ends must be multiples of the OS page size.
+        pub fn set_accessible(&mut self, range: std::ops::Range<usize>, accessible: bool) -> bool {
+            assert!(self.guarded && range.start <= range.end && range.end <= self.reserved);
+            if range.is_empty() {
+                return true;
+            }
+            let prot = if accessible {
+                PROT_READ | PROT_WRITE
+            } else {
+                PROT_NONE
+            };
+            // SAFETY: changes the protection of part of our own mapping;
+            // `&mut self` means no slice into it is alive.
+            unsafe { mprotect(self.ptr.add(range.start) as *mut c_void, range.len(), prot) == 0 }
+        }
+
+        /// Replace `len` bytes at `offset` with fresh zero pages, handing the
+        /// old ones (private copies or image pages alike) back to the OS.
+        /// Both must be multiples of the OS page size.
+        pub fn discard(&mut self, offset: usize, len: usize) -> bool {
+            assert!(offset + len <= self.reserved);
+            if len == 0 {
+                return true;
+            }
+            // SAFETY: replaces part of our own mapping; `&mut self` means no
+            // slice into it is alive.
+            let p = unsafe {
+                mmap(
+                    self.ptr.add(offset) as *mut c_void,
+                    len,
+                    PROT_READ | PROT_WRITE,
+                    MAP_PRIVATE | MAP_ANONYMOUS | MAP_FIXED | MAP_NORESERVE,
+                    -1,
+                    0,
+                )
+            };
+            !failed(p)
+        }
+
+        /// Make the whole mapping read-only and executable, once code has
+        /// been written to it.
+        #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+        pub fn make_executable(&mut self) -> bool {
+            // SAFETY: changes the protection of our own mapping; `&mut self`
+            // means no slice into it is alive.
+            unsafe {
+                mprotect(
+                    self.ptr as *mut c_void,
+                    self.reserved,
+                    PROT_READ | PROT_EXEC,
+                ) == 0
+            }
+        }
+
+        pub fn reserved(&self) -> usize {
+            self.reserved
+        }
+
+        /// Whether this is a [`guarded`](Self::guarded) mapping.
+        pub fn is_guarded(&self) -> bool {
+            self.guarded
+        }
+
+        /// Whether the start is a view of an image file.
+        pub fn is_shared(&self) -> bool {
+            self.shared
+        }
+
+        pub fn as_ptr(&self) -> *mut u8 {
+            self.ptr
+        }
+    }
+
+    impl Drop for Mapping {
+        fn drop(&mut self) {
+            // SAFETY: `ptr`/`reserved` describe a mapping we created.
+            unsafe {
+                munmap(self.ptr as *mut c_void, self.reserved);
+            }
+        }
+    }
+
+    /// Send a fault (`SIGSEGV`) at a `pc` that `redirect` recognizes to
+    /// the address it returns instead, so the faulting code carries on
+    /// there, and any other fault to whatever handled them before. The
+    /// handler is installed once per process, with the first `redirect`;
+    /// false if it could not be.
+    #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+    pub fn redirect_faults(redirect: fn(usize) -> Option<usize>) -> bool {
+        faults::install(redirect)
+    }
+
+    #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+    mod faults {
+        use std::os::raw::{c_int, c_void};
+        use std::sync::OnceLock;
+
+        const SIGSEGV: c_int = 11;
+        const SA_SIGINFO: c_int = 4;
+        const SA_ONSTACK: c_int = 0x0800_0000;
+        const SIG_DFL: usize = 0;
+        const SIG_IGN: usize = 1;
+        /// Offset of the saved `rip` in a `ucontext_t`: past `uc_flags`,
+        /// `uc_link` and `uc_stack`, entry `REG_RIP` of `gregs`.
+        const UCONTEXT_RIP: usize = 40 + 16 * 8;
+
+        #[repr(C)]
+        #[derive(Clone, Copy)]
+        struct SigAction {
+            handler: usize,
+            mask: [u64; 16],
+            flags: c_int,
+            restorer: usize,
+        }
+
+        extern "C" {
+            fn sigaction(sig: c_int, act: *const SigAction, old: *mut SigAction) -> c_int;
+        }
+
+        struct Installed {
+            redirect: fn(usize) -> Option<usize>,
+            previous: SigAction,
+        }
+
+        static INSTALLED: OnceLock<Option<Installed>> = OnceLock::new();
+
+        pub fn install(redirect: fn(usize) -> Option<usize>) -> bool {
+            INSTALLED
+                .get_or_init(|| {
+                    let action = SigAction {
+                        handler: on_fault as *const () as usize,
+                        mask: [0; 16],
+                        flags: SA_SIGINFO | SA_ONSTACK,
+                        restorer: 0,
+                    };
+                    let mut previous = SigAction {
+                        handler: SIG_DFL,
+                        mask: [0; 16],
+                        flags: 0,
+                        restorer: 0,
+                    };
+                    // SAFETY: both actions are valid for the call; the
+                    // handler only touches what is safe in one.
+                    let ok = unsafe { sigaction(SIGSEGV, &action, &mut previous) } == 0;
+                    ok.then_some(Installed { redirect, previous })
+                })
+                .is_some()
+        }
+
+        unsafe extern "C" fn on_fault(sig: c_int, info: *mut c_void, context: *mut c_void) {
+            let previous = match INSTALLED.get() {
+                Some(Some(installed)) => {
+                    let rip = context.cast::<u8>().add(UCONTEXT_RIP).cast::<usize>();
+                    if let Some(target) = (installed.redirect)(*rip) {
+                        *rip = target;
+                        return;
+                    }
+                    installed.previous
+                }
+                // Still installing: all we can do is fall back to the default.
+                _ => SigAction {
+                    handler: SIG_DFL,
+                    mask: [0; 16],
+                    flags: 0,
+                    restorer: 0,
+                },
+            };
+            match previous.handler {
+                // Put the old action back; returning runs the access again,
+                // which now faults to it.
+                SIG_DFL | SIG_IGN => {
+                    sigaction(sig, &previous, std::ptr::null_mut());
+                }
+                handler if previous.flags & SA_SIGINFO != 0 => {
+                    let handler: unsafe extern "C" fn(c_int, *mut c_void, *mut c_void) =
+                        std::mem::transmute(handler);
+                    handler(sig, info, context);
+                }
+                handler => {
+                    let handler: unsafe extern "C" fn(c_int) = std::mem::transmute(handler);
+                    handler(sig);
+                }
+            }
+        }
+    }
+
+    /// A shared object loaded with `dlopen`, unloaded on drop.
+    #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+    pub struct Library {
+        handle: *mut c_void,
+    }
+
+    // SAFETY: the dynamic loader's handles are process-wide and its
+    // functions thread-safe.
+    #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+    unsafe impl Send for Library {}
+    #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+    unsafe impl Sync for Library {}
+
+    #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+    impl Library {
+        /// Load the shared object at `path`, resolving everything now and
+        /// keeping its symbols out of the global namespace.
+        ///
+        /// # Safety
+        /// Loading runs the object's initializers.
+        pub unsafe fn open(path: &std::ffi::CStr) -> Result<Library, String> {
+            const RTLD_NOW: c_int = 2;
+            let handle = dlopen(path.as_ptr(), RTLD_NOW);
+            if handle.is_null() {
+                return Err(last_dl_error());
+            }
+            Ok(Library { handle })
+        }
+
+        pub fn symbol(&self, name: &std::ffi::CStr) -> Option<*mut u8> {
+            // SAFETY: the handle is open and `name` NUL-terminated.
+            let p = unsafe { dlsym(self.handle, name.as_ptr()) };
+            (!p.is_null()).then_some(p as *mut u8)
+        }
+    }
+
+    #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+    impl Drop for Library {
+        fn drop(&mut self) {
+            // SAFETY: the handle came from `dlopen` and is closed once.
+            unsafe {
+                dlclose(self.handle);
+            }
+        }
+    }
+
+    /// Map the start of `file` executable for the life of the process,
+    /// which is how `perf` learns of a jitdump file.
+    #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+    pub fn map_for_profiler(file: &File) -> bool {
+        // SAFETY: a fresh read-only mapping at an address of the kernel's
+        // choosing, never read and never unmapped.
+        let p = unsafe {
+            mmap(
+                std::ptr::null_mut(),
+                4096,
+                PROT_READ | PROT_EXEC,
+                MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        !failed(p)
+    }
+
+    /// Nanoseconds on `CLOCK_MONOTONIC`, the clock `perf record -k mono`
+    /// stamps samples with.
+    #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+    pub fn monotonic_nanos() -> u64 {
+        const CLOCK_MONOTONIC: c_int = 1;
+        let mut ts = [0i64; 2];
+        // SAFETY: `ts` has the layout of a `timespec` on 64-bit Linux.
+        unsafe { clock_gettime(CLOCK_MONOTONIC, &mut ts) };
+        ts[0] as u64 * 1_000_000_000 + ts[1] as u64
+    }
+
+    #[cfg(all(feature = "cranelift", target_arch = "x86_64"))]
+    fn last_dl_error() -> String {
+        // SAFETY: `dlerror` returns null or a NUL-terminated message.
+        let msg = unsafe { dlerror() };
+        if msg.is_null() {
+            return "unknown error".into();
+        }
+        unsafe { std::ffi::CStr::from_ptr(msg) }
+            .to_string_lossy()
+            .into_owned()
+    }
+}
+
+#[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
+pub(crate) use imp::{map_for_profiler, monotonic_nanos, redirect_faults, Library};
+#[cfg(all(
+    target_os = "linux",
+    any(target_arch = "x86_64", target_arch = "aarch64")
+))]
+pub(crate) use imp::{memfd, Mapping};
+
+#[cfg(not(all(target_arch = "wasm32", target_os = "unknown")))]
+mod time {
+    pub use std::time::Instant;
+
+    /// Nanoseconds since the Unix epoch; negative before it.
+    #[cfg(feature = "hostlib")]
+    pub fn wall_clock_nanos() -> i64 {
+        use std::time::{SystemTime, UNIX_EPOCH};
+
+        match SystemTime::now().duration_since(UNIX_EPOCH) {
+            Ok(since) => since.as_nanos() as i64,
+            Err(before) => -(before.duration().as_nanos() as i64),
+        }
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+mod time {
+    use std::sync::{Mutex, PoisonError};
+    use std::time::Duration;
+
+    static SOURCE: Mutex<Option<fn() -> Duration>> = Mutex::new(None);
+
+    pub fn set_time_source(now: fn() -> Duration) {
+        *SOURCE.lock().unwrap_or_else(PoisonError::into_inner) = Some(now);
+    }
+
+    /// Time since the Unix epoch, or zero until a source is installed.
+    fn now() -> Duration {
+        let source = *SOURCE.lock().unwrap_or_else(PoisonError::into_inner);
+        source.map_or(Duration::ZERO, |now| now())
+    }
+
+    /// The subset of `std::time::Instant` the runtime uses, read from the
+    /// installed source.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+    pub struct Instant(Duration);
+
+    impl Instant {
+        pub fn now() -> Instant {
+            Instant(now())
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            now().saturating_sub(self.0)
+        }
+
+        pub fn checked_add(&self, duration: Duration) -> Option<Instant> {
+            self.0.checked_add(duration).map(Instant)
+        }
+    }
+
+    /// Nanoseconds since the Unix epoch.
+    #[cfg(feature = "hostlib")]
+    pub fn wall_clock_nanos() -> i64 {
+        now().as_nanos() as i64
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_os = "unknown"))]
+pub(crate) use time::set_time_source;
+#[cfg(feature = "hostlib")]
+pub(crate) use time::wall_clock_nanos;
+pub(crate) use time::Instant;
@@ -0,0 +1,1015 @@
+//! Text assembly of modules (`.runet` files), for writing modules by hand.
+//!
+//! [`Module::from_text`] assembles a module from text, as `runec compile
+//! plugin.runet -o plugin.rune` does, and [`Module::to_text`] writes one
+//! back, as `runec disasm --text` does. Errors name the line and column at
+//! fault.
+//!
+//! # Syntax
+//!
+//! ```text
+//! ;; Comments run from `;;` to the end of the line.
+//! memory 1 16                       ;; initial pages, then the maximum if any
+//! feature bulk-memory
+//! host-api 1.2
+//! capability fs
+//! import env log (i32) -> ()
+//! global config max_items i32       ;; `mut` after the type if written to
+//! data 0 "hello\0a" "\00\ff"
+//! external 64 4096 9f86d0…
+//!
+//! func add (i32 i32) -> (i32) {
+//!   local i64                       ;; locals beyond the parameters
+//!   local.get 0
+//!   local.get 1
+//!   call_host env.log               ;; by `module.name`, or index
+//!   i32.add
+//! }
+//!
+//! export add                        ;; the function `add`, as `add`
+//! export sum add                    ;; the function `add`, as `sum`
+//! ```
+//!
+//! Items may come in any order, and may refer to items defined after
+//! them. Within a function, ops are their [mnemonics](Op::mnemonic), with
+//! their operands after them, separated by any whitespace:
+//!
+//! | ops                                   | operands                        |
+//! |---------------------------------------|---------------------------------|
+//! | `i32.const`, `i64.const`              | an integer, decimal or `0x` hex |
+//! | `f32.const`, `f64.const`              | a number, `inf`, `nan`, or `0x` hex bits |
+//! | `local.get`, `local.set`, `local.tee` | the local's index               |
+//! | `block`, `loop`, `if`                 | the result type, if any         |
+//! | `br`, `br_if`                         | the depth                       |
+//! | `call`                                | the function's name or index    |
+//! | `call_host`                           | the import's `module.name` or index |
+//! | `global.get`, `global.set`            | the global's `module.name` or index |
+//! | `*.load`, `*.store`                   | `offset=`, `align=`, `memory=`, each optional |
+//! | `ext`                                 | the opcode, then the immediate  |
+//!
+//! A name that is not a plain word, or is a number, is written as a
+//! string. Strings take the escapes `\n`, `\t`, `\r`, `\\`, `\"` and `\`
+//! with two hex digits for any byte. Source maps have no text form; see
+//! [`Module::from_text_with_source_map`].
+
+use std::collections::HashMap;
+use std::fmt::Write as _;
+
+use crate::{
+    compat::ApiVersion,
+    extension::{EXT_OPCODE_FIRST, EXT_OPCODE_LAST},
+    features::Features,
+    hash::to_hex,
+    ir::{BlockType, Function, Op},
+    module::{
+        implied_features, ExternalSegment, GlobalImport, Import, Module, MAX_ALIGN, SIMPLE_OPS,
+    },
+    sourcemap::{SourceLoc, SourceMap},
+    trap::{Result, Trap},
+    types::{FuncType, ValType},
+};
+
+impl Module {
+    /// Assemble a module from the [text form](crate::text). Fails with
+    /// `Trap::InvalidModule` naming the line and column of the first
+    /// error. The module is not verified.
+    pub fn from_text(text: &str) -> Result<Module> {
+        Parser::new(text)?.module(None)
+    }
+
+    /// [`from_text`](Self::from_text), recording in the module's source
+    /// map the line and column each op came from in `file`, so traps and
+    /// verification errors can point back at the text.
+    pub fn from_text_with_source_map(text: &str, file: &str) -> Result<Module> {
+        Parser::new(text)?.module(Some(file))
+    }
+
+    /// Write the module in the [text form](crate::text), without its
+    /// source map.
+    pub fn to_text(&self) -> String {
+        let mut out = String::new();
+        write_module(self, &mut out).expect("writing to a String cannot fail");
+        out
+    }
+}
+
+// ── Writer ───────────────────────────────────────────────────────────────────
+
+fn write_module(m: &Module, out: &mut String) -> std::fmt::Result {
+    write!(out, "memory {}", m.initial_memory_pages)?;
+    if let Some(max) = m.max_memory_pages {
+        write!(out, " {max}")?;
+    }
+    writeln!(out)?;
+    for name in m.required_features.names() {
+        writeln!(out, "feature {name}")?;
+    }
+    if let Some(version) = m.host_requirements.api_version {
+        writeln!(out, "host-api {version}")?;
+    }
+    for capability in &m.host_requirements.capabilities {
+        writeln!(out, "capability {}", quote(capability))?;
+    }
+    for import in &m.imports {
+        write!(
+            out,
+            "import {} {} ",
+            quote(&import.module),
+            quote(&import.name)
+        )?;
+        write_func_type(&import.ty, out)?;
+        writeln!(out)?;
+    }
+    for global in &m.global_imports {
+        write!(
+            out,
+            "global {} {} {}",
+            quote(&global.module),
+            quote(&global.name),
+            type_name(global.ty)
+        )?;
+        writeln!(out, "{}", if global.mutable { " mut" } else { "" })?;
+    }
+    for (offset, bytes) in &m.data_segments {
+        write!(out, "data {offset}")?;
+        for (i, chunk) in bytes.chunks(32).enumerate() {
+            out.push_str(if i == 0 { " " } else { "\n  " });
+            write_bytes(chunk, out);
+        }
+        if bytes.is_empty() {
+            out.push_str(" \"\"");
+        }
+        writeln!(out)?;
+    }
+    for seg in &m.external_segments {
+        writeln!(
+            out,
+            "external {} {} {}",
+            seg.offset,
+            seg.len,
+            to_hex(&seg.hash)
+        )?;
+    }
+
+    let functions = Names::new(m.functions.iter().map(|f| f.name.clone()));
+    let imports = Names::new(m.imports.iter().map(|i| i.to_string()));
+    let globals = Names::new(m.global_imports.iter().map(|g| g.to_string()));
+    for f in &m.functions {
+        write!(out, "\nfunc {} ", quote(&f.name))?;
+        write_func_type(&f.ty, out)?;
+        writeln!(out, " {{")?;
+        if !f.locals.is_empty() {
+            let locals: Vec<_> = f.locals.iter().map(|&t| type_name(t)).collect();
+            writeln!(out, "  local {}", locals.join(" "))?;
+        }
+        let mut depth = 1;
+        for op in f.body.iter() {
+            if matches!(op, Op::Else | Op::End) {
+                depth = usize::max(depth - 1, 1);
+            }
+            write!(out, "{:width$}{}", "", op.mnemonic(), width = 2 * depth)?;
+            write_operands(op, &functions, &imports, &globals, out)?;
+            writeln!(out)?;
+            if matches!(op, Op::Block(_) | Op::Loop(_) | Op::If(_) | Op::Else) {
+                depth += 1;
+            }
+        }
+        writeln!(out, "}}")?;
+    }
+    if !m.exports.is_empty() {
+        writeln!(out)?;
+    }
+    for (name, func) in &m.exports {
+        write!(out, "export {}", quote(name))?;
+        let target = functions.reference(*func);
+        if target != quote(name) {
+            write!(out, " {target}")?;
+        }
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+fn write_func_type(ty: &FuncType, out: &mut String) -> std::fmt::Result {
+    let types = |tys: &[ValType]| tys.iter().map(|&t| type_name(t)).collect::<Vec<_>>();
+    write!(
+        out,
+        "({}) -> ({})",
+        types(&ty.params).join(" "),
+        types(&ty.results).join(" ")
+    )
+}
+
+fn write_operands(
+    op: &Op,
+    functions: &Names,
+    imports: &Names,
+    globals: &Names,
+    out: &mut String,
+) -> std::fmt::Result {
+    match *op {
+        Op::I32Const(v) => write!(out, " {v}"),
+        Op::I64Const(v) => write!(out, " {v}"),
+        Op::F32Const(v) if v.is_nan() => write!(out, " {:#010x}", v.to_bits()),
+        Op::F64Const(v) if v.is_nan() => write!(out, " {:#018x}", v.to_bits()),
+        Op::F32Const(v) => write!(out, " {v:?}"),
+        Op::F64Const(v) => write!(out, " {v:?}"),
+        Op::LocalGet(i) | Op::LocalSet(i) | Op::LocalTee(i) | Op::Br(i) | Op::BrIf(i) => {
+            write!(out, " {i}")
+        }
+        Op::GlobalGet(i) | Op::GlobalSet(i) => write!(out, " {}", globals.reference(i)),
+        Op::Call(i) => write!(out, " {}", functions.reference(i)),
+        Op::CallHost(i) => write!(out, " {}", imports.reference(i)),
+        Op::Block(BlockType::Val(t)) | Op::Loop(BlockType::Val(t)) | Op::If(BlockType::Val(t)) => {
+            write!(out, " {}", type_name(t))
+        }
+        Op::I32Load {
+            align,
+            offset,
+            memory,
+        }
+        | Op::I32Store {
+            align,
+            offset,
+            memory,
+        }
+        | Op::I64Load {
+            align,
+            offset,
+            memory,
+        }
+        | Op::I64Store {
+            align,
+            offset,
+            memory,
+        }
+        | Op::F32Load {
+            align,
+            offset,
+            memory,
+        }
+        | Op::F32Store {
+            align,
+            offset,
+            memory,
+        }
+        | Op::F64Load {
+            align,
+            offset,
+            memory,
+        }
+        | Op::F64Store {
+            align,
+            offset,
+            memory,
+        } => {
+            for (key, value) in [("offset", offset), ("align", align), ("memory", memory)] {
+                if value != 0 {
+                    write!(out, " {key}={value}")?;
+                }
+            }
+            Ok(())
+        }
+        Op::Ext { opcode, imm } => write!(out, " {opcode:#04x} {imm}"),
+        _ => Ok(()),
+    }
+}
+
+/// How the writer refers to functions, imports or globals: by name where
+/// the name is a plain word naming only the one, by index otherwise.
+struct Names(Vec<Option<String>>);
+
+impl Names {
+    fn new(names: impl Iterator<Item = String>) -> Self {
+        let names: Vec<String> = names.collect();
+        let mut count: HashMap<&str, usize> = HashMap::new();
+        for name in &names {
+            *count.entry(name).or_default() += 1;
+        }
+        Names(
+            names
+                .iter()
+                .map(|name| (is_word(name) && count[name.as_str()] == 1).then(|| name.clone()))
+                .collect(),
+        )
+    }
+
+    fn reference(&self, i: u32) -> String {
+        match self.0.get(i as usize) {
+            Some(Some(name)) => name.clone(),
+            _ => i.to_string(),
+        }
+    }
+}
+
+/// Whether `s` reads back as the same word rather than a number, keyword
+/// or other token.
+fn is_word(s: &str) -> bool {
+    !s.is_empty()
+        && !is_keyword(s)
+        && !s.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+')
+        && !s.contains(";;")
+        && s.chars().all(|c| c.is_ascii_graphic() && !is_delimiter(c))
+}
+
+fn quote(s: &str) -> String {
+    if is_word(s) {
+        return s.to_string();
+    }
+    let mut out = String::new();
+    write_bytes(s.as_bytes(), &mut out);
+    out
+}
+
+fn write_bytes(bytes: &[u8], out: &mut String) {
+    out.push('"');
+    for &b in bytes {
+        match b {
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            b' '..=b'~' => out.push(b as char),
+            _ => {
+                let _ = write!(out, "\\{b:02x}");
+            }
+        }
+    }
+    out.push('"');
+}
+
+fn type_name(t: ValType) -> &'static str {
+    match t {
+        ValType::I32 => "i32",
+        ValType::I64 => "i64",
+        ValType::F32 => "f32",
+        ValType::F64 => "f64",
+    }
+}
+
+fn val_type(word: &str) -> Option<ValType> {
+    match word {
+        "i32" => Some(ValType::I32),
+        "i64" => Some(ValType::I64),
+        "f32" => Some(ValType::F32),
+        "f64" => Some(ValType::F64),
+        _ => None,
+    }
+}
+
+// ── Tokens ───────────────────────────────────────────────────────────────────
+
+fn is_delimiter(c: char) -> bool {
+    matches!(c, '(' | ')' | '{' | '}' | '"')
+}
+
+/// A token, and the line and column it starts at, both from 1.
+struct Token<'a> {
+    kind: Kind<'a>,
+    line: usize,
+    column: usize,
+}
+
+#[derive(PartialEq)]
+enum Kind<'a> {
+    Word(&'a str),
+    /// A string, its escapes decoded.
+    Str(Vec<u8>),
+    /// `(`, `)`, `{` or `}`.
+    Punct(char),
+}
+
+fn error(line: usize, column: usize, msg: &str) -> Trap {
+    Trap::InvalidModule(format!("line {line}, column {column}: {msg}"))
+}
+
+fn tokenize(text: &str) -> Result<Vec<Token<'_>>> {
+    let mut tokens = Vec::new();
+    for (l, source) in text.lines().enumerate() {
+        let mut chars = source.char_indices().enumerate().peekable();
+        while let Some((column, (start, c))) = chars.next() {
+            let (line, column) = (l + 1, column + 1);
+            let kind = match c {
+                _ if c.is_whitespace() => continue,
+                ';' if source[start..].starts_with(";;") => break,
+                '(' | ')' | '{' | '}' => Kind::Punct(c),
+                '"' => {
+                    let mut bytes = Vec::new();
+                    loop {
+                        let Some((_, (_, c))) = chars.next() else {
+                            return Err(error(line, column, "unterminated string"));
+                        };
+                        match c {
+                            '"' => break,
+                            '\\' => {
+                                let escape = |c| match c {
+                                    'n' => Some(b'\n'),
+                                    't' => Some(b'\t'),
+                                    'r' => Some(b'\r'),
+                                    '\\' | '"' | '\'' => Some(c as u8),
+                                    _ => None,
+                                };
+                                let (column, (_, first)) = chars
+                                    .next()
+                                    .ok_or_else(|| error(line, column, "unterminated string"))?;
+                                let byte = match escape(first) {
+                                    Some(byte) => Some(byte),
+                                    None => chars.next().and_then(|(_, (_, second))| {
+                                        let hex = [first, second].iter().collect::<String>();
+                                        u8::from_str_radix(&hex, 16).ok()
+                                    }),
+                                };
+                                bytes.push(
+                                    byte.ok_or_else(|| error(line, column, "invalid escape"))?,
+                                );
+                            }
+                            _ => {
+                                let mut buf = [0; 4];
+                                bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+                            }
+                        }
+                    }
+                    Kind::Str(bytes)
+                }
+                _ => {
+                    let mut end = start + c.len_utf8();
+                    while let Some(&(_, (at, c))) = chars.peek() {
+                        if c.is_whitespace() || is_delimiter(c) || source[at..].starts_with(";;") {
+                            break;
+                        }
+                        end = at + c.len_utf8();
+                        chars.next();
+                    }
+                    Kind::Word(&source[start..end])
+                }
+            };
+            tokens.push(Token { kind, line, column });
+        }
+    }
+    Ok(tokens)
+}
+
+// ── Parser ───────────────────────────────────────────────────────────────────
+
+/// A name or index, and the line and column it is at.
+type Reference = (String, usize, usize);
+
+/// What an op names by `name`, to be looked up once the whole
+/// module is read.
+struct Fixup {
+    func: usize,
+    op: usize,
+    name: String,
+    line: usize,
+    column: usize,
+}
+
+struct Parser<'a> {
+    tokens: Vec<Token<'a>>,
+    pos: usize,
+    /// Where the text ends, for errors there.
+    end: (usize, usize),
+}
+
+impl<'a> Parser<'a> {
+    fn new(text: &'a str) -> Result<Self> {
+        let lines = text.lines().count().max(1);
+        let last = text.lines().last().map_or(0, |l| l.chars().count());
+        Ok(Parser {
+            tokens: tokenize(text)?,
+            pos: 0,
+            end: (lines, last + 1),
+        })
+    }
+
+    fn module(mut self, file: Option<&str>) -> Result<Module> {
+        let mut m = Module::new();
+        let mut source_map = file.map(|file| {
+            let mut map = SourceMap::new();
+            map.add_file(file);
+            map
+        });
+        let mut calls = Vec::new();
+        let mut hosts = Vec::new();
+        let mut globals = Vec::new();
+        let mut exports = Vec::new();
+        while self.pos < self.tokens.len() {
+            let (keyword, line, column) = self.word("an item")?;
+            match keyword {
+                "memory" => {
+                    m.initial_memory_pages = self.number::<u32>("a page count")? as usize;
+                    if self.peek_number() {
+                        m.max_memory_pages = Some(self.number::<u32>("a page count")? as usize);
+                    }
+                }
+                "feature" => {
+                    let (name, line, column) = self.word("a feature name")?;
+                    m.required_features |= Features::from_name(name)
+                        .ok_or_else(|| error(line, column, &format!("unknown feature `{name}`")))?;
+                }
+                "host-api" => {
+                    let (version, line, column) = self.word("a version")?;
+                    let version: ApiVersion = version
+                        .parse()
+                        .map_err(|_| error(line, column, "expected `major.minor`"))?;
+                    m.host_requirements.api_version = Some(version);
+                }
+                "capability" => {
+                    let capability = self.name()?;
+                    m.host_requirements.capabilities.push(capability);
+                }
+                "import" => {
+                    let module = self.name()?;
+                    let name = self.name()?;
+                    let ty = self.func_type()?;
+                    m.imports.push(Import { module, name, ty });
+                }
+                "global" => {
+                    let module = self.name()?;
+                    let name = self.name()?;
+                    let ty = self.val_type()?;
+                    let mutable = self.eat_word("mut");
+                    m.global_imports.push(GlobalImport {
+                        module,
+                        name,
+                        ty,
+                        mutable,
+                    });
+                }
+                "data" => {
+                    let offset = self.number("an offset")?;
+                    let mut bytes = self.string()?;
+                    while let Some(Token {
+                        kind: Kind::Str(more),
+                        ..
+                    }) = self.tokens.get(self.pos)
+                    {
+                        bytes.extend_from_slice(more);
+                        self.pos += 1;
+                    }
+                    m.data_segments.push((offset, bytes));
+                }
+                "external" => {
+                    let offset = self.number("an offset")?;
+                    let len = self.number("a length")?;
+                    let (hex, line, column) = self.word("a SHA-256")?;
+                    let hash = parse_hash(hex)
+                        .ok_or_else(|| error(line, column, "expected 64 hex digits"))?;
+                    m.external_segments
+                        .push(ExternalSegment { offset, len, hash });
+                }
+                "export" => {
+                    let (line, column) = self.here();
+                    let name = self.name()?;
+                    let target = match self.tokens.get(self.pos) {
+                        Some(t) if !matches!(t.kind, Kind::Word(w) if is_keyword(w)) => {
+                            self.reference()?
+                        }
+                        _ => (name.clone(), line, column),
+                    };
+                    exports.push((name, target));
+                }
+                "func" => {
+                    let func = m.functions.len();
+                    let name = self.name()?;
+                    let ty = self.func_type()?;
+                    self.punct('{')?;
+                    let mut locals = Vec::new();
+                    while self.eat_word("local") {
+                        while let Some(Token {
+                            kind: Kind::Word(w),
+                            ..
+                        }) = self.tokens.get(self.pos)
+                        {
+                            let Some(ty) = val_type(w) else { break };
+                            locals.push(ty);
+                            self.pos += 1;
+                        }
+                    }
+                    let mut body = Vec::new();
+                    let mut last_line = 0;
+                    while !self.eat_punct('}') {
+                        let (line, column) = self.here();
+                        if let Some(map) = source_map.as_mut().filter(|_| line != last_line) {
+                            let loc = SourceLoc {
+                                file: 0,
+                                line: line as u32,
+                                column: column as u32,
+                            };
+                            map.add_entry(func as u32, body.len() as u32, loc);
+                            last_line = line;
+                        }
+                        let (op, name) = self.op()?;
+                        if let Some(((name, line, column), list)) = name.map(|n| match op {
+                            Op::Call(_) => (n, &mut calls),
+                            Op::CallHost(_) => (n, &mut hosts),
+                            _ => (n, &mut globals),
+                        }) {
+                            list.push(Fixup {
+                                func,
+                                op: body.len(),
+                                name,
+                                line,
+                                column,
+                            });
+                        }
+                        body.push(op);
+                    }
+                    m.functions.push(Function::new(name, ty, locals, body));
+                }
+                _ => return Err(error(line, column, &format!("unknown item `{keyword}`"))),
+            }
+        }
+
+        let functions = index(m.functions.iter().map(|f| f.name.clone()));
+        let imports = index(m.imports.iter().map(|i| i.to_string()));
+        let global_names = index(m.global_imports.iter().map(|g| g.to_string()));
+        for (fixups, names, what) in [
+            (calls, &functions, "function"),
+            (hosts, &imports, "import"),
+            (globals, &global_names, "global"),
+        ] {
+            for fixup in fixups {
+                let i = lookup(names, &fixup.name, fixup.line, fixup.column, what)?;
+                let body = std::sync::Arc::make_mut(&mut m.functions[fixup.func].body);
+                match &mut body[fixup.op] {
+                    Op::Call(target)
+                    | Op::CallHost(target)
+                    | Op::GlobalGet(target)
+                    | Op::GlobalSet(target) => *target = i,
+                    _ => unreachable!("fixups are only taken for references"),
+                }
+            }
+        }
+        for (name, (target, line, column)) in exports {
+            let func = lookup(&functions, &target, line, column, "function")?;
+            m.exports.push((name, func));
+        }
+        m.source_map = source_map;
+        m.required_features |= implied_features(&m.functions);
+        Ok(m)
+    }
+
+    /// An op and the name it refers to, if by name rather than index.
+    fn op(&mut self) -> Result<(Op, Option<Reference>)> {
+        let (mnemonic, line, column) = self.word("an op")?;
+        let mut reference = None;
+        let mut target = |parser: &mut Self| -> Result<u32> {
+            let quoted =
+                matches!(parser.tokens.get(parser.pos), Some(t) if matches!(t.kind, Kind::Str(_)));
+            let (name, line, column) = parser.reference()?;
+            match parse_int(&name) {
+                Some(i) if !quoted => Ok(i),
+                _ => {
+                    reference = Some((name, line, column));
+                    Ok(0)
+                }
+            }
+        };
+        let op = match mnemonic {
+            "i32.const" => Op::I32Const(self.integer::<i32, u32>(|u| u as i32)?),
+            "i64.const" => Op::I64Const(self.integer::<i64, u64>(|u| u as i64)?),
+            "f32.const" => Op::F32Const(self.float(f32::from_bits)?),
+            "f64.const" => Op::F64Const(self.float(f64::from_bits)?),
+            "local.get" => Op::LocalGet(self.number("a local index")?),
+            "local.set" => Op::LocalSet(self.number("a local index")?),
+            "local.tee" => Op::LocalTee(self.number("a local index")?),
+            "br" => Op::Br(self.number("a depth")?),
+            "br_if" => Op::BrIf(self.number("a depth")?),
+            "block" => Op::Block(self.block_type()),
+            "loop" => Op::Loop(self.block_type()),
+            "if" => Op::If(self.block_type()),
+            "call" => Op::Call(target(self)?),
+            "call_host" => Op::CallHost(target(self)?),
+            "global.get" => Op::GlobalGet(target(self)?),
+            "global.set" => Op::GlobalSet(target(self)?),
+            "ext" => {
+                let (word, line, column) = self.word("an opcode")?;
+                let opcode = parse_int::<u8>(word)
+                    .filter(|op| (EXT_OPCODE_FIRST..=EXT_OPCODE_LAST).contains(op))
+                    .ok_or_else(|| {
+                        error(
+                            line,
+                            column,
+                            &format!(
+                                "expected an extension opcode, {EXT_OPCODE_FIRST:#04x} to \
+                                 {EXT_OPCODE_LAST:#04x}"
+                            ),
+                        )
+                    })?;
+                Op::Ext {
+                    opcode,
+                    imm: self.number("an immediate")?,
+                }
+            }
+            "i32.load" | "i32.store" | "i64.load" | "i64.store" | "f32.load" | "f32.store"
+            | "f64.load" | "f64.store" => {
+                let (mut align, mut offset, mut memory) = (0, 0, 0);
+                while let Some(Token {
+                    kind: Kind::Word(w),
+                    line,
+                    column,
+                }) = self.tokens.get(self.pos)
+                {
+                    let Some((key, value)) = w.split_once('=') else {
+                        break;
+                    };
+                    let (field, max) = match key {
+                        "align" => (&mut align, MAX_ALIGN),
+                        "offset" => (&mut offset, u32::MAX),
+                        "memory" => (&mut memory, u32::MAX),
+                        _ => break,
+                    };
+                    *field = parse_int(value).filter(|v| *v <= max).ok_or_else(|| {
+                        error(*line, *column, &format!("invalid {key} `{value}`"))
+                    })?;
+                    self.pos += 1;
+                }
+                match mnemonic {
+                    "i32.load" => Op::I32Load {
+                        align,
+                        offset,
+                        memory,
+                    },
+                    "i32.store" => Op::I32Store {
+                        align,
+                        offset,
+                        memory,
+                    },
+                    "i64.load" => Op::I64Load {
+                        align,
+                        offset,
+                        memory,
+                    },
+                    "i64.store" => Op::I64Store {
+                        align,
+                        offset,
+                        memory,
+                    },
+                    "f32.load" => Op::F32Load {
+                        align,
+                        offset,
+                        memory,
+                    },
+                    "f32.store" => Op::F32Store {
+                        align,
+                        offset,
+                        memory,
+                    },
+                    "f64.load" => Op::F64Load {
+                        align,
+                        offset,
+                        memory,
+                    },
+                    _ => Op::F64Store {
+                        align,
+                        offset,
+                        memory,
+                    },
+                }
+            }
+            _ => SIMPLE_OPS
+                .iter()
+                .find(|op| op.mnemonic() == mnemonic)
+                .cloned()
+                .ok_or_else(|| error(line, column, &format!("unknown op `{mnemonic}`")))?,
+        };
+        Ok((op, reference))
+    }
+
+    fn here(&self) -> (usize, usize) {
+        self.tokens
+            .get(self.pos)
+            .map_or(self.end, |t| (t.line, t.column))
+    }
+
+    fn expected(&self, what: &str) -> Trap {
+        let (line, column) = self.here();
+        match self.tokens.get(self.pos) {
+            Some(_) => error(line, column, &format!("expected {what}")),
+            None => error(line, column, &format!("expected {what}, found the end")),
+        }
+    }
+
+    fn word(&mut self, what: &str) -> Result<(&'a str, usize, usize)> {
+        match self.tokens.get(self.pos) {
+            Some(&Token {
+                kind: Kind::Word(word),
+                line,
+                column,
+            }) => {
+                self.pos += 1;
+                Ok((word, line, column))
+            }
+            _ => Err(self.expected(what)),
+        }
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        let found = matches!(self.tokens.get(self.pos), Some(t) if t.kind == Kind::Word(word));
+        self.pos += usize::from(found);
+        found
+    }
+
+    fn eat_punct(&mut self, c: char) -> bool {
+        let found = matches!(self.tokens.get(self.pos), Some(t) if t.kind == Kind::Punct(c));
+        self.pos += usize::from(found);
+        found
+    }
+
+    fn punct(&mut self, c: char) -> Result<()> {
+        if self.eat_punct(c) {
+            Ok(())
+        } else {
+            Err(self.expected(&format!("`{c}`")))
+        }
+    }
+
+    fn string(&mut self) -> Result<Vec<u8>> {
+        match self.tokens.get(self.pos) {
+            Some(Token {
+                kind: Kind::Str(bytes),
+                ..
+            }) => {
+                let bytes = bytes.clone();
+                self.pos += 1;
+                Ok(bytes)
+            }
+            _ => Err(self.expected("a string")),
+        }
+    }
+
+    /// A name: a word, or a string for any other.
+    fn name(&mut self) -> Result<String> {
+        Ok(self.reference()?.0)
+    }
+
+    /// A name, or an index, and where it is.
+    fn reference(&mut self) -> Result<Reference> {
+        let (line, column) = self.here();
+        match self.tokens.get(self.pos).map(|t| &t.kind) {
+            Some(Kind::Word(word)) => {
+                self.pos += 1;
+                Ok((word.to_string(), line, column))
+            }
+            Some(Kind::Str(bytes)) => {
+                let name = String::from_utf8(bytes.clone())
+                    .map_err(|_| error(line, column, "names must be UTF-8"))?;
+                self.pos += 1;
+                Ok((name, line, column))
+            }
+            _ => Err(self.expected("a name")),
+        }
+    }
+
+    fn peek_number(&self) -> bool {
+        matches!(self.tokens.get(self.pos),
+            Some(Token { kind: Kind::Word(w), .. }) if w.starts_with(|c: char| c.is_ascii_digit()))
+    }
+
+    fn number<T: TryFrom<u64>>(&mut self, what: &str) -> Result<T> {
+        let (word, line, column) = self.word(what)?;
+        parse_int::<u64>(word)
+            .and_then(|n| T::try_from(n).ok())
+            .ok_or_else(|| error(line, column, &format!("expected {what}, found `{word}`")))
+    }
+
+    /// An integer constant of `S`, or of `U` taken as its bits.
+    fn integer<S: std::str::FromStr, U: TryFrom<u64>>(&mut self, bits: fn(U) -> S) -> Result<S> {
+        let (word, line, column) = self.word("an integer")?;
+        let value = match word.strip_prefix('-') {
+            Some(_) if !word.contains("0x") => word.parse().ok(),
+            _ => parse_int::<u64>(word)
+                .and_then(|n| U::try_from(n).ok())
+                .map(bits),
+        };
+        value.ok_or_else(|| error(line, column, &format!("invalid integer `{word}`")))
+    }
+
+    fn float<F: std::str::FromStr, B: TryFrom<u64>>(&mut self, from_bits: fn(B) -> F) -> Result<F> {
+        let (word, line, column) = self.word("a number")?;
+        let value = match word.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16)
+                .ok()
+                .and_then(|n| B::try_from(n).ok())
+                .map(from_bits),
+            None => word.parse().ok(),
+        };
+        value.ok_or_else(|| error(line, column, &format!("invalid number `{word}`")))
+    }
+
+    fn val_type(&mut self) -> Result<ValType> {
+        let (word, line, column) = self.word("a value type")?;
+        val_type(word).ok_or_else(|| {
+            error(
+                line,
+                column,
+                &format!("expected i32, i64, f32 or f64, found `{word}`"),
+            )
+        })
+    }
+
+    fn block_type(&mut self) -> BlockType {
+        match self.tokens.get(self.pos) {
+            Some(Token {
+                kind: Kind::Word(w),
+                ..
+            }) => match val_type(w) {
+                Some(ty) => {
+                    self.pos += 1;
+                    BlockType::Val(ty)
+                }
+                None => BlockType::Empty,
+            },
+            _ => BlockType::Empty,
+        }
+    }
+
+    /// `(params) -> (results)`, the results optional.
+    fn func_type(&mut self) -> Result<FuncType> {
+        let params = self.types()?;
+        let results = if self.eat_word("->") {
+            self.types()?
+        } else {
+            Vec::new()
+        };
+        Ok(FuncType { params, results })
+    }
+
+    fn types(&mut self) -> Result<Vec<ValType>> {
+        self.punct('(')?;
+        let mut types = Vec::new();
+        while !self.eat_punct(')') {
+            types.push(self.val_type()?);
+        }
+        Ok(types)
+    }
+}
+
+/// Words that start an item, and so end an `export` with one name.
+fn is_keyword(word: &str) -> bool {
+    matches!(
+        word,
+        "memory"
+            | "feature"
+            | "host-api"
+            | "capability"
+            | "import"
+            | "global"
+            | "data"
+            | "external"
+            | "export"
+            | "func"
+    )
+}
+
+fn parse_int<T: TryFrom<u64>>(word: &str) -> Option<T> {
+    let n = match word.strip_prefix("0x") {
+        Some(hex) => u64::from_str_radix(hex, 16).ok()?,
+        None if word.starts_with('+') => return None,
+        None => word.parse().ok()?,
+    };
+    T::try_from(n).ok()
+}
+
+fn parse_hash(hex: &str) -> Option<[u8; 32]> {
+    if hex.len() != 64 || !hex.is_ascii() {
+        return None;
+    }
+    let mut hash = [0; 32];
+    for (i, byte) in hash.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&hex[2 * i..2 * i + 2], 16).ok()?;
+    }
+    Some(hash)
+}
+
+/// Index of each name, `None` for names given twice.
+fn index(names: impl Iterator<Item = String>) -> HashMap<String, Option<u32>> {
+    let mut index = HashMap::new();
+    for (i, name) in names.enumerate() {
+        index
+            .entry(name)
+            .and_modify(|e| *e = None)
+            .or_insert(Some(i as u32));
+    }
+    index
+}
+
+fn lookup(
+    names: &HashMap<String, Option<u32>>,
+    name: &str,
+    line: usize,
+    column: usize,
+    what: &str,
+) -> Result<u32> {
+    match names.get(name) {
+        Some(Some(i)) => Ok(*i),
+        Some(None) => Err(error(
+            line,
+            column,
+            &format!("more than one {what} is named `{name}`; refer to it by index"),
+        )),
+        None => match parse_int(name) {
+            Some(i) => Ok(i),
+            None => Err(error(line, column, &format!("no {what} named `{name}`"))),
+        },
+    }
+}
@@ -0,0 +1,103 @@
+//! Timers that call back into a guest, for plugins driven by events rather
+//! than by the host calling them.
+//!
+//! A guest schedules one of its exports with the host library's
+//! `set_timeout` or `yield_now`, or the host does with
+//! [`Instance::set_timeout`]; nothing runs until the host polls, with
+//! [`Instance::poll_timers`] for one instance or
+//! [`Runtime::poll_timers`] for many, typically from its event loop.
+//! Callbacks run one at a time on the polling thread, as ordinary calls
+//! with no arguments, so they are metered and observed like any other.
+//!
+//! Time is read from the runtime's
+//! [`clock`](crate::config::RuntimeConfig::set_clock), so a
+//! [`ManualClock`](crate::clock::ManualClock) makes timers fire exactly
+//! when a test advances it.
+//!
+//! [`Instance::set_timeout`]: crate::instance::Instance::set_timeout
+//! [`Instance::poll_timers`]: crate::instance::Instance::poll_timers
+//! [`Runtime::poll_timers`]: crate::runtime::Runtime::poll_timers
+
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::time::Duration;
+
+use crate::trap::Trap;
+
+/// One instance's pending timers.
+#[derive(Debug, Default)]
+pub(crate) struct Timers {
+    queue: BinaryHeap<Reverse<Timer>>,
+    next_id: u32,
+}
+
+/// Ordered by due time, then by id so equal times fire in schedule order.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+struct Timer {
+    due: Duration,
+    id: u32,
+    export: String,
+}
+
+impl Timers {
+    /// Queue `export` to run once the clock reads `due`; returns its id.
+    pub(crate) fn schedule(&mut self, export: &str, due: Duration) -> u32 {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        self.queue.push(Reverse(Timer {
+            due,
+            id,
+            export: export.into(),
+        }));
+        id
+    }
+
+    /// Drop timer `id`; false if it already fired or never existed.
+    pub(crate) fn cancel(&mut self, id: u32) -> bool {
+        let before = self.queue.len();
+        self.queue.retain(|Reverse(timer)| timer.id != id);
+        self.queue.len() != before
+    }
+
+    /// The id the next timer scheduled will get, marking the end of a poll.
+    pub(crate) fn watermark(&self) -> u32 {
+        self.next_id
+    }
+
+    /// The export of the earliest timer due by `now` and scheduled before
+    /// `watermark`, removed from the queue.
+    pub(crate) fn pop_due(&mut self, now: Duration, watermark: u32) -> Option<String> {
+        let Reverse(next) = self.queue.peek()?;
+        if next.due > now || next.id >= watermark {
+            return None;
+        }
+        self.queue.pop().map(|Reverse(timer)| timer.export)
+    }
+
+    /// When the earliest timer is due.
+    pub(crate) fn next_due(&self) -> Option<Duration> {
+        self.queue.peek().map(|Reverse(timer)| timer.due)
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.queue.clear();
+    }
+}
+
+/// What a [`Runtime::poll_timers`](crate::runtime::Runtime::poll_timers)
+/// pass did.
+#[derive(Debug, Default)]
+pub struct PollReport {
+    /// Callbacks that ran, including any that trapped.
+    pub ran: usize,
+    /// Each instance whose callback trapped, by position in the polled
+    /// sequence, with the trap. Its later timers wait for the next poll.
+    pub traps: Vec<(usize, Trap)>,
+    /// How long until the earliest timer still pending is due, zero if one
+    /// already is; `None` if there are none.
+    pub next_due: Option<Duration>,
+}
@@ -1,5 +1,7 @@
 use std::fmt;
 
+use crate::sourcemap::SourceLoc;
+
 /// All ways execution can fail.
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Trap {
@@ -13,6 +15,27 @@ pub enum Trap {
     UndefinedImport(String),
     InvalidModule(String),
     HostError(String),
+    /// Access to a read-only or no-access page of linear memory.
+    AccessViolation,
+    /// Guest bytes expected to hold a string are not valid UTF-8.
+    InvalidUtf8,
+    /// The module needs optional features this runtime does not provide.
+    UnsupportedFeature(String),
+    /// A load or store address was not a multiple of its `align` hint, with
+    /// strict alignment enabled.
+    UnalignedAccess,
+    /// Read of poisoned memory not written since (address of the first
+    /// such byte), with poison checking enabled.
+    UninitializedRead(usize),
+    /// Call to a host function (named `module.name`) whose capability the
+    /// instance was not granted.
+    PermissionDenied(String),
+    /// The runtime's epoch reached the instance's deadline.
+    Interrupted,
+    /// The instance used up its fuel.
+    OutOfFuel,
+    /// The instance used up its CPU time budget.
+    CpuTimeExceeded,
 }
 
 impl fmt::Display for Trap {
@@ -28,10 +51,85 @@ impl fmt::Display for Trap {
             Trap::UndefinedImport(n) => write!(f, "undefined import: {n}"),
             Trap::InvalidModule(m) => write!(f, "invalid module: {m}"),
             Trap::HostError(e) => write!(f, "host error: {e}"),
+            Trap::AccessViolation => write!(f, "memory access violation"),
+            Trap::InvalidUtf8 => write!(f, "invalid UTF-8 in guest string"),
+            Trap::UnsupportedFeature(m) => write!(f, "runtime lacks feature: {m}"),
+            Trap::UnalignedAccess => write!(f, "unaligned memory access"),
+            Trap::UninitializedRead(a) => write!(f, "read of uninitialized memory at {a:#x}"),
+            Trap::PermissionDenied(n) => write!(f, "permission denied: {n}"),
+            Trap::Interrupted => write!(f, "interrupted at epoch deadline"),
+            Trap::OutOfFuel => write!(f, "fuel exhausted"),
+            Trap::CpuTimeExceeded => write!(f, "CPU time budget exceeded"),
+        }
+    }
+}
+
+impl Trap {
+    /// Name of the variant without its payload, such as `"out_of_bounds"`;
+    /// the key traps are counted under in `RuntimeMetrics::traps`.
+    pub fn kind(&self) -> &'static str {
+        TRAP_KINDS[self.kind_index()]
+    }
+
+    pub(crate) fn kind_index(&self) -> usize {
+        match self {
+            Trap::OutOfBounds => 0,
+            Trap::OutOfMemory => 1,
+            Trap::DivisionByZero => 2,
+            Trap::Unreachable => 3,
+            Trap::StackOverflow => 4,
+            Trap::TypeMismatch => 5,
+            Trap::UndefinedExport(_) => 6,
+            Trap::UndefinedImport(_) => 7,
+            Trap::InvalidModule(_) => 8,
+            Trap::HostError(_) => 9,
+            Trap::AccessViolation => 10,
+            Trap::InvalidUtf8 => 11,
+            Trap::UnsupportedFeature(_) => 12,
+            Trap::UnalignedAccess => 13,
+            Trap::UninitializedRead(_) => 14,
+            Trap::PermissionDenied(_) => 15,
+            Trap::Interrupted => 16,
+            Trap::OutOfFuel => 17,
+            Trap::CpuTimeExceeded => 18,
         }
     }
 }
 
+/// [`Trap::kind`] of each variant, by `kind_index`.
+pub(crate) const TRAP_KINDS: [&str; 19] = [
+    "out_of_bounds",
+    "out_of_memory",
+    "division_by_zero",
+    "unreachable",
+    "stack_overflow",
+    "type_mismatch",
+    "undefined_export",
+    "undefined_import",
+    "invalid_module",
+    "host_error",
+    "access_violation",
+    "invalid_utf8",
+    "unsupported_feature",
+    "unaligned_access",
+    "uninitialized_read",
+    "permission_denied",
+    "interrupted",
+    "out_of_fuel",
+    "cpu_time_exceeded",
+];
+
 impl std::error::Error for Trap {}
 
 pub type Result<T> = std::result::Result<T, Trap>;
+
+/// One guest frame active when a trap was raised.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrapFrame {
+    /// Index into `Module::functions`.
+    pub func_index: u32,
+    /// Index of the op that was executing in that function.
+    pub op_index: usize,
+    /// Source location from the module's source map, if it has one.
+    pub loc: Option<SourceLoc>,
+}
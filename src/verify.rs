@@ -0,0 +1,465 @@
+//! A strict verifier for RuneIR.
+//!
+//! The interpreter checks types as it runs, trapping with
+//! `Trap::TypeMismatch` when an op finds the wrong operands. Native code
+//! cannot: it is generated for the types the ops are meant to see. So
+//! before a function is compiled, the verifier proves statically that it
+//! is well formed:
+//!
+//! - Control flow is structured: every `Block`, `Loop` and `If` is closed
+//!   by an `End`, `Else` only follows an `If`, an `If` with a result has an
+//!   `Else`, and branches name an enclosing block.
+//! - The operand stack is used with discipline: ops only pop what was
+//!   pushed in their own block, and a block ends, a branch leaves and the
+//!   function returns with exactly the values they carry.
+//! - Every op finds operands of its types, and locals, globals, functions,
+//!   imports and extension ops it names exist with the types it uses.
+//!
+//! Code after an unconditional branch, `Return` or `Unreachable` never
+//! runs, but it is checked too, with an operand stack of unknown values
+//! underneath, as in Wasm.
+//!
+//! Compilers only generate code for functions that verify; the others stay
+//! interpreted. The interpreter runs the verifier when a module is
+//! instantiated if [`RuntimeConfig::set_verify`] asks for it, so malformed
+//! modules are refused up front instead of trapping part way through.
+
+use std::fmt;
+
+use crate::{
+    config::RuntimeConfig,
+    ir::{BlockType, Function, Op},
+    module::Module,
+    trap::Trap,
+    types::ValType,
+};
+
+/// Why a function failed verification, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VerifyError {
+    /// Index of the function in its module, if it is one of the module's.
+    pub func: Option<u32>,
+    /// The function's name.
+    pub name: String,
+    /// Index of the offending op, or `None` for the function as a whole.
+    pub op: Option<usize>,
+    pub message: String,
+}
+
+impl fmt::Display for VerifyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "function `{}`", self.name)?;
+        if let Some(func) = self.func {
+            write!(f, " (func[{func}])")?;
+        }
+        if let Some(op) = self.op {
+            write!(f, " op {op}")?;
+        }
+        write!(f, ": {}", self.message)
+    }
+}
+
+impl std::error::Error for VerifyError {}
+
+impl From<VerifyError> for Trap {
+    fn from(err: VerifyError) -> Self {
+        Trap::InvalidModule(err.to_string())
+    }
+}
+
+/// Verify every function of `module`, with extension ops typed by
+/// `config`, stopping at the first error.
+pub fn verify_module(module: &Module, config: &RuntimeConfig) -> Result<(), VerifyError> {
+    for (i, func) in module.functions.iter().enumerate() {
+        verify_function(module, func, config).map_err(|err| VerifyError {
+            func: Some(i as u32),
+            ..err
+        })?;
+    }
+    Ok(())
+}
+
+/// Verify `func` as a function of `module`, which gives the functions,
+/// imports and globals it refers to.
+pub fn verify_function(
+    module: &Module,
+    func: &Function,
+    config: &RuntimeConfig,
+) -> Result<(), VerifyError> {
+    let fail = |op, message: String| VerifyError {
+        func: None,
+        name: func.name.clone(),
+        op,
+        message,
+    };
+    if func.ty.results.len() > 1 {
+        return Err(fail(None, "functions return at most one value".into()));
+    }
+    let mut v = Verifier {
+        module,
+        config,
+        locals: func.ty.params.iter().chain(&func.locals).copied().collect(),
+        result: func.ty.results.first().copied(),
+        stack: Vec::new(),
+        frames: Vec::new(),
+        unreachable: false,
+    };
+    for (i, op) in func.body.iter().enumerate() {
+        match v.op(i, op) {
+            Ok(true) if i + 1 < func.body.len() => {
+                return Err(fail(
+                    Some(i + 1),
+                    "op after the function's final `end`".into(),
+                ))
+            }
+            Ok(true) => return Ok(()),
+            Ok(false) => {}
+            Err(message) => return Err(fail(Some(i), format!("`{}`: {message}", op.mnemonic()))),
+        }
+    }
+    // Falling off the end returns, like the final `End`.
+    if let Some(frame) = v.frames.last() {
+        return Err(fail(
+            Some(frame.start),
+            "block is never closed by an `end`".into(),
+        ));
+    }
+    v.end_of(None, v.result)
+        .map_err(|message| fail(None, format!("at the end of the body: {message}")))
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Block,
+    Loop,
+    If,
+    Else,
+}
+
+/// An open block, loop or if.
+struct Frame {
+    kind: Kind,
+    /// Index of the op opening it.
+    start: usize,
+    /// Stack height at entry.
+    height: usize,
+    result: Option<ValType>,
+    /// Whether the code around it was unreachable, restored at its
+    /// `End`.
+    unreachable: bool,
+}
+
+type Check<T = ()> = Result<T, String>;
+
+struct Verifier<'a> {
+    module: &'a Module,
+    config: &'a RuntimeConfig,
+    locals: Vec<ValType>,
+    result: Option<ValType>,
+    /// Operand types; `None` is a value of unknown type in unreachable
+    /// code.
+    stack: Vec<Option<ValType>>,
+    frames: Vec<Frame>,
+    /// Whether the current point can never run: the stack below the
+    /// innermost block's entry height then holds whatever is needed.
+    unreachable: bool,
+}
+
+impl Verifier<'_> {
+    /// Check op `i`, `op`; true at the function's final `End`.
+    fn op(&mut self, i: usize, op: &Op) -> Check<bool> {
+        use ValType::I32;
+        match op {
+            Op::I32Load { align, .. }
+            | Op::F32Load { align, .. }
+            | Op::I32Store { align, .. }
+            | Op::F32Store { align, .. } => check_align(*align, 4)?,
+            Op::I64Load { align, .. }
+            | Op::F64Load { align, .. }
+            | Op::I64Store { align, .. }
+            | Op::F64Store { align, .. } => check_align(*align, 8)?,
+            _ => {}
+        }
+        if let Some((params, result)) = op.signature() {
+            self.call(params, result.as_slice())?;
+            return Ok(false);
+        }
+        match op {
+            Op::Drop => {
+                self.pop()?;
+            }
+            Op::Select => {
+                self.pop_ty(I32)?;
+                let b = self.pop()?;
+                let a = self.pop()?;
+                let ty = match (a, b) {
+                    (Some(a), Some(b)) if a != b => {
+                        return Err(format!(
+                            "operands differ in type: {} and {}",
+                            name(a),
+                            name(b)
+                        ))
+                    }
+                    (a, b) => a.or(b),
+                };
+                self.stack.push(ty);
+            }
+            Op::LocalGet(l) => {
+                let ty = self.local(*l)?;
+                self.push(ty);
+            }
+            Op::LocalSet(l) => {
+                let ty = self.local(*l)?;
+                self.pop_ty(ty)?;
+            }
+            Op::LocalTee(l) => {
+                let ty = self.local(*l)?;
+                self.pop_ty(ty)?;
+                self.push(ty);
+            }
+            Op::GlobalGet(g) => {
+                let ty = self.global(*g)?.ty;
+                self.push(ty);
+            }
+            Op::GlobalSet(g) => {
+                let global = self.global(*g)?;
+                if !global.mutable {
+                    return Err(format!("global {g} ({global}) is immutable"));
+                }
+                let ty = global.ty;
+                self.pop_ty(ty)?;
+            }
+
+            Op::Unreachable => self.set_unreachable(),
+            Op::Block(bt) => self.open(Kind::Block, bt, i),
+            Op::Loop(bt) => self.open(Kind::Loop, bt, i),
+            Op::If(bt) => {
+                self.pop_ty(I32)?;
+                self.open(Kind::If, bt, i);
+            }
+            Op::Else => {
+                let frame = self.frames.last().ok_or("`else` outside an `if`")?;
+                if frame.kind != Kind::If {
+                    return Err(format!(
+                        "`else` does not follow an `if`; the innermost block opens at op {}",
+                        frame.start
+                    ));
+                }
+                let (height, result) = (frame.height, frame.result);
+                self.end_of(Some(height), result)?;
+                let frame = self.frames.last_mut().expect("checked above");
+                frame.kind = Kind::Else;
+                self.unreachable = false;
+                self.stack.truncate(height);
+            }
+            Op::End => {
+                let Some(frame) = self.frames.last() else {
+                    self.end_of(None, self.result)?;
+                    return Ok(true);
+                };
+                if frame.kind == Kind::If && frame.result.is_some() {
+                    return Err(format!(
+                        "`if` at op {} has a result but no `else`",
+                        frame.start
+                    ));
+                }
+                let (height, result) = (frame.height, frame.result);
+                self.end_of(Some(height), result)?;
+                let frame = self.frames.pop().expect("checked above");
+                self.stack.truncate(frame.height);
+                self.unreachable = frame.unreachable;
+                if let Some(ty) = frame.result {
+                    self.push(ty);
+                }
+            }
+            Op::Br(depth) => {
+                let carried = self.label(*depth)?;
+                self.pop_all(carried)?;
+                self.set_unreachable();
+            }
+            Op::BrIf(depth) => {
+                self.pop_ty(I32)?;
+                let carried = self.label(*depth)?;
+                self.pop_all(carried)?;
+                if let Some(ty) = carried {
+                    self.push(ty);
+                }
+            }
+            Op::Return => {
+                self.pop_all(self.result)?;
+                self.set_unreachable();
+            }
+
+            Op::Call(f) => {
+                let func = self
+                    .module
+                    .functions
+                    .get(*f as usize)
+                    .ok_or_else(|| format!("no function {f}"))?;
+                self.call(&func.ty.params, &func.ty.results)?;
+            }
+            Op::CallHost(i) => {
+                let import = self
+                    .module
+                    .imports
+                    .get(*i as usize)
+                    .ok_or_else(|| format!("no import {i}"))?;
+                self.call(&import.ty.params, &import.ty.results)?;
+            }
+            Op::Ext { opcode, .. } => {
+                let ext = self
+                    .config
+                    .extension(*opcode)
+                    .ok_or_else(|| format!("no extension op {opcode:#04x} is registered"))?;
+                self.call(&ext.ty.params, &ext.ty.results)?;
+            }
+            _ => unreachable!("`{}` has a signature", op.mnemonic()),
+        }
+        Ok(false)
+    }
+
+    fn push(&mut self, ty: ValType) {
+        self.stack.push(Some(ty));
+    }
+
+    /// Pop a value pushed within the innermost block, or an unknown one
+    /// if the code is unreachable.
+    fn pop(&mut self) -> Check<Option<ValType>> {
+        let floor = self.frames.last().map_or(0, |f| f.height);
+        if self.stack.len() > floor {
+            return Ok(self.stack.pop().expect("above the floor"));
+        }
+        if self.unreachable {
+            return Ok(None);
+        }
+        Err(if self.frames.is_empty() {
+            "the stack is empty".into()
+        } else {
+            "nothing on the stack within the block".into()
+        })
+    }
+
+    fn pop_ty(&mut self, ty: ValType) -> Check {
+        match self.pop() {
+            Ok(Some(found)) if found != ty => {
+                Err(format!("expected {}, found {}", name(ty), name(found)))
+            }
+            Ok(_) => Ok(()),
+            Err(err) => Err(format!("expected {}, but {err}", name(ty))),
+        }
+    }
+
+    /// Pop the value `carried`, if any, that a branch or return takes.
+    fn pop_all(&mut self, carried: Option<ValType>) -> Check {
+        match carried {
+            Some(ty) => self.pop_ty(ty),
+            None => Ok(()),
+        }
+    }
+
+    fn call(&mut self, params: &[ValType], results: &[ValType]) -> Check {
+        if results.len() > 1 {
+            return Err("callee returns more than one value".into());
+        }
+        for &ty in params.iter().rev() {
+            self.pop_ty(ty)?;
+        }
+        if let Some(&ty) = results.first() {
+            self.push(ty);
+        }
+        Ok(())
+    }
+
+    fn local(&self, l: u32) -> Check<ValType> {
+        self.locals
+            .get(l as usize)
+            .copied()
+            .ok_or_else(|| format!("no local {l}; the function has {}", self.locals.len()))
+    }
+
+    fn global(&self, g: u32) -> Check<&crate::module::GlobalImport> {
+        self.module.global_imports.get(g as usize).ok_or_else(|| {
+            format!(
+                "no global {g}; the module imports {}",
+                self.module.global_imports.len()
+            )
+        })
+    }
+
+    /// Open a block at op `start`, reachable within until something
+    /// branches away.
+    fn open(&mut self, kind: Kind, bt: &BlockType, start: usize) {
+        self.frames.push(Frame {
+            kind,
+            start,
+            height: self.stack.len(),
+            result: match bt {
+                BlockType::Empty => None,
+                BlockType::Val(ty) => Some(*ty),
+            },
+            unreachable: self.unreachable,
+        });
+        self.unreachable = false;
+    }
+
+    /// What a branch to the block `depth` frames out takes: nothing for a
+    /// loop, which branches back to its start, else the block's result.
+    fn label(&self, depth: u32) -> Check<Option<ValType>> {
+        let index = self
+            .frames
+            .len()
+            .checked_sub(depth as usize + 1)
+            .ok_or_else(|| {
+                format!(
+                    "branch depth {depth}, but only {} blocks enclose it",
+                    self.frames.len()
+                )
+            })?;
+        let frame = &self.frames[index];
+        Ok(if frame.kind == Kind::Loop {
+            None
+        } else {
+            frame.result
+        })
+    }
+
+    /// Check that the stack holds exactly `result` above `height`, or the
+    /// function's whole stack for `None`.
+    fn end_of(&mut self, height: Option<usize>, result: Option<ValType>) -> Check {
+        let height = height.unwrap_or(0);
+        self.pop_all(result)?;
+        let extra = self.stack.len() - height.min(self.stack.len());
+        if extra > 0 {
+            return Err(format!(
+                "{extra} value{} left on the stack",
+                if extra == 1 { "" } else { "s" }
+            ));
+        }
+        Ok(())
+    }
+
+    /// Mark the rest of the block unreachable, dropping its operands.
+    fn set_unreachable(&mut self) {
+        let floor = self.frames.last().map_or(0, |f| f.height);
+        self.stack.truncate(floor);
+        self.unreachable = true;
+    }
+}
+
+fn check_align(align: u32, size: u32) -> Check {
+    if 1u64.checked_shl(align).is_none_or(|a| a > size as u64) {
+        return Err(format!(
+            "alignment 2^{align} exceeds the access's natural {size}"
+        ));
+    }
+    Ok(())
+}
+
+fn name(ty: ValType) -> &'static str {
+    match ty {
+        ValType::I32 => "i32",
+        ValType::I64 => "i64",
+        ValType::F32 => "f32",
+        ValType::F64 => "f64",
+    }
+}
@@ -5,13 +5,20 @@
 //!   Module builder → Module::to_bytes → Module::from_bytes → Instance::call
 
 use rune::{
+    config::RuntimeConfig,
+    extension::ExtensionOp,
+    features::Features,
+    image::MemoryImage,
     ir::{BlockType, Function, Op},
-    module::Module,
+    memory::{
+        Memory, MemoryLimiter, MemoryStats, Poison, Protection, RuntimeMemoryStats, POISON_BYTE,
+    },
+    module::{CollisionPolicy, Module},
     runtime::Runtime,
+    sourcemap::{SourceLoc, SourceMap},
     trap::Trap,
     types::{FuncType, Val, ValType},
 };
-use std::sync::Arc;
 
 // Helper: build a Function using the new Arc-body API from a raw Vec<Op>
 fn func(
@@ -292,6 +299,78 @@ fn test_local_set_get() {
     );
 }
 
+#[test]
+fn test_locals_share_slots() {
+    use rune::config::Strategy;
+
+    // sum(n) = 1 + 2 + ... + (n + 1), through temporaries that never live
+    // at once, an accumulator and an i64 read before they are written.
+    let mut m = Module::new();
+    m.functions.push(Function::new(
+        "sum",
+        FuncType {
+            params: vec![ValType::I32],
+            results: vec![ValType::I32],
+        },
+        vec![
+            ValType::I32, // 1: acc
+            ValType::I32, // 2: i
+            ValType::I32, // 3: t1
+            ValType::I32, // 4: t2
+            ValType::I64, // 5: w
+        ],
+        vec![
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32Add,
+            Op::LocalSet(3),
+            Op::LocalGet(3),
+            Op::LocalSet(2),
+            Op::Loop(BlockType::Empty),
+            Op::LocalGet(1),
+            Op::LocalGet(2),
+            Op::I32Add,
+            Op::LocalSet(1),
+            Op::LocalGet(2),
+            Op::I32Const(1),
+            Op::I32Sub,
+            Op::LocalTee(4),
+            Op::LocalSet(2),
+            Op::LocalGet(2),
+            Op::BrIf(0),
+            Op::End,
+            Op::LocalGet(5),
+            Op::I32WrapI64,
+            Op::LocalGet(1),
+            Op::I32Add,
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("sum".into(), 0));
+
+    #[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
+    let strategies = [
+        Strategy::Interpreter,
+        Strategy::Baseline,
+        Strategy::Cranelift,
+    ];
+    #[cfg(not(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64")))]
+    let strategies = [Strategy::Interpreter];
+    for strategy in strategies {
+        let mut config = RuntimeConfig::new();
+        config.set_strategy(strategy);
+        let mut inst = Runtime::with_config(config).instantiate(&m).unwrap();
+        for _ in 0..2 {
+            assert_eq!(
+                inst.call("sum", &[Val::I32(3)]),
+                Ok(Some(Val::I32(10))),
+                "{strategy:?}"
+            );
+        }
+        assert_eq!(inst.call("sum", &[Val::I32(0)]), Ok(Some(Val::I32(1))));
+    }
+}
+
 // ── Constants ─────────────────────────────────────────────────────────────────
 
 #[test]
@@ -351,11 +430,13 @@ fn test_memory_store_load() {
             Op::I32Store {
                 align: 2,
                 offset: 0,
+                memory: 0,
             },
             Op::I32Const(0),
             Op::I32Load {
                 align: 2,
                 offset: 0,
+                memory: 0,
             },
             Op::Return,
         ],
@@ -376,6 +457,7 @@ fn test_memory_oob() {
             Op::I32Load {
                 align: 2,
                 offset: 0,
+                memory: 0,
             },
             Op::Return,
         ],
@@ -385,304 +467,7188 @@ fn test_memory_oob() {
 }
 
 #[test]
-fn test_memory_grow() {
-    let m = single_func(
-        "grow",
-        &[],
-        Some(ValType::I32),
+fn test_strict_alignment() {
+    let mut m = read_word_module();
+    m.functions.push(func(
+        "read_packed",
+        vec![ValType::I32],
+        vec![ValType::I32],
+        vec![],
         vec![
-            Op::I32Const(2), // grow by 2 pages
-            Op::MemoryGrow,  // returns old page count (1)
+            Op::LocalGet(0),
+            Op::I32Load {
+                align: 0,
+                offset: 1,
+                memory: 0,
+            },
             Op::Return,
         ],
-    );
+    ));
+    m.exports.push(("read_packed".into(), 1));
+
+    // Lenient by default.
     let mut inst = rt().instantiate(&m).unwrap();
-    assert_eq!(inst.call("grow", &[]).unwrap(), Some(Val::I32(1)));
-    assert_eq!(inst.memory.pages(), 3);
+    assert_eq!(
+        inst.call("read", &[Val::I32(2)]).unwrap(),
+        Some(Val::I32(0))
+    );
+
+    let mut config = RuntimeConfig::new();
+    config.set_strict_alignment(true);
+    let rt = Runtime::with_config(config);
+    let mut inst = rt.instantiate(&m).unwrap();
+    assert_eq!(
+        inst.call("read", &[Val::I32(8)]).unwrap(),
+        Some(Val::I32(0))
+    );
+    assert_eq!(
+        inst.call("read", &[Val::I32(2)]).unwrap_err(),
+        Trap::UnalignedAccess
+    );
+    // An `align` of 0 promises nothing, so any address is accepted.
+    assert_eq!(
+        inst.call("read_packed", &[Val::I32(2)]).unwrap(),
+        Some(Val::I32(0))
+    );
 }
 
 #[test]
-fn test_memory_size() {
-    let m = single_func(
-        "msize",
-        &[],
-        Some(ValType::I32),
-        vec![Op::MemorySize, Op::Return],
-    );
+fn test_address_overflow() {
+    use rune::config::AddressOverflow;
+
+    let mut m = read_word_module();
+    m.functions.push(func(
+        "read_high",
+        vec![ValType::I32],
+        vec![ValType::I32],
+        vec![],
+        vec![
+            Op::LocalGet(0),
+            Op::I32Load {
+                align: 2,
+                offset: 16,
+                memory: 0,
+            },
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("read_high".into(), 1));
+    m.data_segments.push((8, vec![42, 0, 0, 0]));
+
+    // -8 + 16 overflows 32 bits: a trap by default...
     let mut inst = rt().instantiate(&m).unwrap();
-    assert_eq!(inst.call("msize", &[]).unwrap(), Some(Val::I32(1)));
-}
+    assert_eq!(
+        inst.call("read_high", &[Val::I32(-8)]).unwrap_err(),
+        Trap::OutOfBounds
+    );
+    assert_eq!(
+        inst.call("read", &[Val::I32(-4)]).unwrap_err(),
+        Trap::OutOfBounds
+    );
 
-// ── Data segments ─────────────────────────────────────────────────────────────
+    // ...or address 8 when wrapping.
+    let mut config = RuntimeConfig::new();
+    config.set_address_overflow(AddressOverflow::Wrap);
+    let rt = Runtime::with_config(config);
+    let mut inst = rt.instantiate(&m).unwrap();
+    assert_eq!(
+        inst.call("read_high", &[Val::I32(-8)]).unwrap(),
+        Some(Val::I32(42))
+    );
+}
 
 #[test]
-fn test_data_segment() {
+fn test_memory_protection() {
     let mut m = single_func(
-        "read_data",
-        &[],
-        Some(ValType::I32),
+        "poke",
+        &[ValType::I32],
+        None,
         vec![
-            Op::I32Const(0),
-            Op::I32Load {
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32Store {
                 align: 2,
                 offset: 0,
+                memory: 0,
             },
             Op::Return,
         ],
     );
-    // Write 0xDEADBEEFu32 at offset 0
-    m.data_segments.push((0, vec![0xEF, 0xBE, 0xAD, 0xDE]));
+    m.initial_memory_pages = 2;
     let mut inst = rt().instantiate(&m).unwrap();
-    let result = inst.call("read_data", &[]).unwrap();
-    assert_eq!(result, Some(Val::I32(0xDEADBEEFu32 as i32)));
+    inst.memory_mut().write_u32(65536, 42).unwrap();
+    inst.memory_mut()
+        .protect(1..2, Protection::ReadOnly)
+        .unwrap();
+
+    inst.call("poke", &[Val::I32(0)]).unwrap();
+    assert_eq!(
+        inst.call("poke", &[Val::I32(65536)]).unwrap_err(),
+        Trap::AccessViolation
+    );
+    assert_eq!(inst.memory().read_u32(65536).unwrap(), 42);
 }
 
-// ── Control flow ──────────────────────────────────────────────────────────────
+#[test]
+fn test_memory_index_plumbing() {
+    let load_from = |memory| {
+        func(
+            "read",
+            vec![ValType::I32],
+            vec![ValType::I32],
+            vec![],
+            vec![
+                Op::LocalGet(0),
+                Op::I32Load {
+                    align: 2,
+                    offset: 0,
+                    memory,
+                },
+                Op::Return,
+            ],
+        )
+    };
+    let mut m = Module::new();
+    m.functions.push(load_from(1));
+    m.exports.push(("read".into(), 0));
+
+    // The index survives the binary and JSON encodings; memory 0 encodes
+    // exactly as before indices existed.
+    let bytes = m.to_bytes();
+    let back = Module::from_bytes(&bytes).unwrap();
+    assert_eq!(back.functions[0].body[1], m.functions[0].body[1]);
+    let back = Module::from_json(&m.to_json()).unwrap();
+    assert_eq!(back.functions[0].body[1], m.functions[0].body[1]);
+    let mut m0 = Module::new();
+    m0.functions.push(load_from(0));
+    m0.exports.push(("read".into(), 0));
+    assert_eq!(m.to_bytes().len(), m0.to_bytes().len() + 4);
+
+    // Only memory 0 exists after instantiation.
+    assert!(matches!(
+        rt().instantiate(&m).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+
+    // A host-supplied second memory can be targeted once it exists.
+    let mut inst = rt().instantiate(&m0).unwrap();
+    assert!(matches!(
+        inst.replace_function("read", load_from(1)).unwrap_err(),
+        Trap::InvalidModule(_)
+    ));
+    let mut extra = Memory::new(1, None);
+    extra.write_u32(8, 99).unwrap();
+    assert_eq!(inst.add_memory(extra), 1);
+    inst.replace_function("read", load_from(1)).unwrap();
+    assert_eq!(
+        inst.call("read", &[Val::I32(8)]).unwrap(),
+        Some(Val::I32(99))
+    );
+    // The replaced function still reads it, so it stays attached.
+    assert!(matches!(inst.take_memory(), Err(Trap::InvalidModule(_))));
+    assert_eq!(inst.memory_count(), 2);
+}
 
 #[test]
-fn test_if_then_else() {
-    // abs(x) = if x < 0 then -x else x
+fn test_external_memory() {
+    // A guest writes straight into a host buffer attached as memory 1.
     let m = single_func(
-        "abs",
-        &[ValType::I32],
-        Some(ValType::I32),
+        "put",
+        &[ValType::I32, ValType::I32],
+        None,
         vec![
             Op::LocalGet(0),
-            Op::I32Const(0),
-            Op::I32LtS,
-            Op::If(BlockType::Val(ValType::I32)),
-            Op::I32Const(0),
-            Op::LocalGet(0),
-            Op::I32Sub,
-            Op::Else,
-            Op::LocalGet(0),
-            Op::End,
-            Op::Return,
+            Op::LocalGet(1),
+            Op::I32Store {
+                align: 2,
+                offset: 0,
+                memory: 1,
+            },
         ],
     );
-    let mut inst = rt().instantiate(&m).unwrap();
-    assert_eq!(
-        inst.call("abs", &[Val::I32(-5)]).unwrap(),
-        Some(Val::I32(5))
-    );
-    assert_eq!(inst.call("abs", &[Val::I32(7)]).unwrap(), Some(Val::I32(7)));
+    let mut frame = vec![0u8; 65536];
+    frame[100] = 7;
+    let external = Memory::from_external(Box::new(frame)).unwrap();
+    assert_eq!(external.pages(), 1);
+    assert_eq!(external.max_pages(), Some(1));
+    let mut inst = rt().instantiate_with_memories(&m, vec![external]).unwrap();
+    assert_eq!(inst.memory_at(1).unwrap().read_u8(100).unwrap(), 7);
+    inst.call("put", &[Val::I32(4), Val::I32(0x0102_0304)])
+        .unwrap();
+    // The buffer cannot grow past its own length.
+    assert!(matches!(
+        inst.memory_at_mut(1).unwrap().grow(1),
+        Err(Trap::OutOfMemory)
+    ));
+    assert!(inst.memory_at(2).is_none());
+
+    // Memory 1 is in use by `put`, so it stays attached while the instance
+    // lives; the buffer comes back with the instance's memories.
+    assert!(matches!(inst.take_memory(), Err(Trap::InvalidModule(_))));
+    let buffer = inst.into_memories().pop().unwrap().into_external().unwrap();
+    assert_eq!(&buffer.as_slice()[4..8], &[4, 3, 2, 1]);
+    assert_eq!(buffer.as_slice()[100], 7);
+    assert!(Memory::new(1, None).into_external().is_none());
+
+    // A memory no code uses can be taken back early; the default one never.
+    let noop = single_func("noop", &[], None, vec![]);
+    let mut inst = rt()
+        .instantiate_with_memories(&noop, vec![Memory::new(1, None)])
+        .unwrap();
+    assert_eq!(inst.take_memory().unwrap().pages(), 1);
+    assert!(matches!(inst.take_memory(), Err(Trap::InvalidModule(_))));
+
+    // Only whole pages can back a memory.
+    assert!(matches!(
+        Memory::from_external(Box::new(vec![0u8; 100])).err(),
+        Some(Trap::InvalidModule(_))
+    ));
 }
 
 #[test]
-fn test_block_br() {
-    // Returns 99 by branching out of a block.
+fn test_memory_grow() {
     let m = single_func(
-        "blk",
+        "grow",
         &[],
         Some(ValType::I32),
         vec![
-            Op::Block(BlockType::Val(ValType::I32)),
-            Op::I32Const(99),
-            Op::Br(0),
-            Op::I32Const(0), // unreachable
-            Op::End,
+            Op::I32Const(2), // grow by 2 pages
+            Op::MemoryGrow,  // returns old page count (1)
             Op::Return,
         ],
     );
     let mut inst = rt().instantiate(&m).unwrap();
-    assert_eq!(inst.call("blk", &[]).unwrap(), Some(Val::I32(99)));
+    assert_eq!(inst.call("grow", &[]).unwrap(), Some(Val::I32(1)));
+    assert_eq!(inst.memory().pages(), 3);
 }
 
 #[test]
-fn test_loop_countdown() {
-    // count down from N to 0 using a loop, return 0.
-    //
-    // Br(0) = continue loop (targets the Loop frame itself)
-    // Br(1) = break out     (targets the wrapping Block, exits past its End)
-    // A bare Loop with no enclosing Block cannot be exited with Br(1) —
-    // the Block wrapper is required so depth-1 has a valid target.
+fn test_memory_stats() {
     let m = single_func(
-        "countdown",
+        "grow",
         &[ValType::I32],
         Some(ValType::I32),
-        vec![
-            Op::Block(BlockType::Empty), // depth 1 — break target
-            Op::Loop(BlockType::Empty),  // depth 0 — continue target
-            Op::LocalGet(0),
-            Op::I32Eqz,
-            Op::BrIf(1), // if i==0: exit Block
-            Op::LocalGet(0),
-            Op::I32Const(1),
-            Op::I32Sub,
-            Op::LocalSet(0),
-            Op::Br(0), // continue Loop
-            Op::End,   // End Loop
-            Op::End,   // End Block
-            Op::LocalGet(0),
-            Op::Return,
-        ],
+        vec![Op::LocalGet(0), Op::MemoryGrow, Op::Return],
     );
-    let mut inst = rt().instantiate(&m).unwrap();
+    let rt = rt();
+    let mut a = rt.instantiate(&m).unwrap();
+    let b = rt.instantiate(&m).unwrap();
+    a.call("grow", &[Val::I32(2)]).unwrap();
+    a.call("grow", &[Val::I32(1)]).unwrap();
     assert_eq!(
-        inst.call("countdown", &[Val::I32(10)]).unwrap(),
-        Some(Val::I32(0))
+        a.memory_stats(),
+        MemoryStats {
+            current_bytes: 4 * 65536,
+            peak_pages: 4,
+            grow_count: 2,
+        }
+    );
+    assert_eq!(b.memory_stats().grow_count, 0);
+    assert_eq!(
+        rt.memory_stats(),
+        RuntimeMemoryStats {
+            instances: 2,
+            current_bytes: 5 * 65536,
+            peak_bytes: 5 * 65536,
+            grow_count: 2,
+        }
     );
-}
 
-// ── Internal function calls ───────────────────────────────────────────────────
+    drop(a);
+    let stats = rt.memory_stats();
+    assert_eq!((stats.instances, stats.current_bytes), (1, 65536));
+    assert_eq!(stats.peak_bytes, 5 * 65536);
+    drop(b);
+    assert_eq!(rt.memory_stats().current_bytes, 0);
+}
 
 #[test]
-fn test_internal_call() {
-    // square(x) calls mul(x, x)
-    let mut m = Module::new();
-    // func[0] = mul(a, b) = a * b
-    m.functions.push(Function::new(
-        "mul",
-        FuncType {
-            params: vec![ValType::I32, ValType::I32],
-            results: vec![ValType::I32],
-        },
-        vec![],
-        vec![Op::LocalGet(0), Op::LocalGet(1), Op::I32Mul, Op::Return],
-    ));
-    // func[1] = square(x) = mul(x, x)
-    m.functions.push(Function::new(
-        "square",
-        FuncType {
-            params: vec![ValType::I32],
-            results: vec![ValType::I32],
-        },
-        vec![],
-        vec![Op::LocalGet(0), Op::LocalGet(0), Op::Call(0), Op::Return],
-    ));
-    m.exports.push(("square".into(), 1));
-    let mut inst = rt().instantiate(&m).unwrap();
+fn test_memory_limiter() {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    // Refuses anything that would take an instance past a host-wide cap,
+    // which can be lowered at runtime.
+    struct Cap(Arc<AtomicUsize>);
+    impl MemoryLimiter for Cap {
+        fn memory_growing(&self, _current: usize, desired: usize, _max: Option<usize>) -> bool {
+            desired <= self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    let m = single_func(
+        "grow",
+        &[ValType::I32],
+        Some(ValType::I32),
+        vec![Op::LocalGet(0), Op::MemoryGrow, Op::Return],
+    );
+    let cap = Arc::new(AtomicUsize::new(3));
+    let mut config = RuntimeConfig::new();
+    config.set_memory_limiter(Cap(cap.clone()));
+    let rt = Runtime::with_config(config);
+
+    let mut inst = rt.instantiate(&m).unwrap();
     assert_eq!(
-        inst.call("square", &[Val::I32(7)]).unwrap(),
-        Some(Val::I32(49))
+        inst.call("grow", &[Val::I32(2)]).unwrap(),
+        Some(Val::I32(1))
     );
-}
+    assert_eq!(
+        inst.call("grow", &[Val::I32(1)]).unwrap(),
+        Some(Val::I32(-1))
+    );
+    assert_eq!(inst.memory_mut().grow(1).unwrap_err(), Trap::OutOfMemory);
 
-// ── Host function calls ───────────────────────────────────────────────────────
+    // A per-instance override replaces the runtime-wide limiter.
+    inst.set_memory_limiter(None);
+    assert_eq!(
+        inst.call("grow", &[Val::I32(1)]).unwrap(),
+        Some(Val::I32(3))
+    );
+
+    cap.store(0, Ordering::Relaxed);
+    assert_eq!(rt.instantiate(&m).err(), Some(Trap::OutOfMemory));
+}
 
 #[test]
-fn test_host_call() {
-    use std::sync::{Arc, Mutex};
+fn test_resource_limiter() {
+    use rune::config::ResourceLimiter;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
 
-    let log: Arc<Mutex<Vec<i32>>> = Arc::new(Mutex::new(Vec::new()));
+    // A tenant's allowance, adjustable while its instances run.
+    #[derive(Default)]
+    struct Tenant {
+        max_instances: AtomicUsize,
+        max_pages: AtomicUsize,
+    }
+    struct Limits(Arc<Tenant>);
+    impl MemoryLimiter for Limits {
+        fn memory_growing(&self, _current: usize, desired: usize, _max: Option<usize>) -> bool {
+            desired <= self.0.max_pages.load(Ordering::Relaxed)
+        }
+    }
+    impl ResourceLimiter for Limits {
+        fn instance_creating(&self, live: usize) -> bool {
+            live < self.0.max_instances.load(Ordering::Relaxed)
+        }
+    }
+
+    let m = single_func(
+        "grow",
+        &[ValType::I32],
+        Some(ValType::I32),
+        vec![Op::LocalGet(0), Op::MemoryGrow, Op::Return],
+    );
+    let tenant = Arc::new(Tenant::default());
+    tenant.max_instances.store(2, Ordering::Relaxed);
+    tenant.max_pages.store(2, Ordering::Relaxed);
+    let mut config = RuntimeConfig::new();
+    config.set_resource_limiter(Limits(tenant.clone()));
+    let rt = Runtime::with_config(config);
+
+    let mut a = rt.instantiate(&m).unwrap();
+    let b = rt.instantiate(&m).unwrap();
+    assert_eq!(rt.instantiate(&m).err(), Some(Trap::OutOfMemory));
+    drop(b);
+    let _b = rt.instantiate(&m).unwrap();
+
+    assert_eq!(a.call("grow", &[Val::I32(1)]).unwrap(), Some(Val::I32(1)));
+    assert_eq!(a.call("grow", &[Val::I32(1)]).unwrap(), Some(Val::I32(-1)));
+    tenant.max_pages.store(3, Ordering::Relaxed);
+    assert_eq!(a.call("grow", &[Val::I32(1)]).unwrap(), Some(Val::I32(2)));
+
+    // The same limiter can police a single instance's memory.
+    let mut solo = Runtime::new().instantiate(&m).unwrap();
+    let limits: Arc<dyn ResourceLimiter> = Arc::new(Limits(tenant));
+    solo.set_memory_limiter(Some(limits));
+    assert_eq!(
+        solo.call("grow", &[Val::I32(3)]).unwrap(),
+        Some(Val::I32(-1))
+    );
+}
+
+#[test]
+fn test_memory_quota() {
+    let m = single_func(
+        "grow",
+        &[ValType::I32],
+        Some(ValType::I32),
+        vec![Op::LocalGet(0), Op::MemoryGrow, Op::Return],
+    );
+    let mut config = RuntimeConfig::new();
+    config.set_memory_quota(3 * 65536);
+    let rt = Runtime::with_config(config);
+
+    // Each instance starts with one page; the quota is shared between them.
+    let mut a = rt.instantiate(&m).unwrap();
+    let b = rt.instantiate(&m).unwrap();
+    assert_eq!(a.call("grow", &[Val::I32(1)]).unwrap(), Some(Val::I32(1)));
+    assert_eq!(a.call("grow", &[Val::I32(1)]).unwrap(), Some(Val::I32(-1)));
+    assert_eq!(rt.instantiate(&m).err(), Some(Trap::OutOfMemory));
+    assert_eq!(rt.memory_stats().current_bytes, 3 * 65536);
+
+    // Shrinking or dropping an instance hands its pages back.
+    a.memory_mut().reset(1).unwrap();
+    assert_eq!(a.call("grow", &[Val::I32(1)]).unwrap(), Some(Val::I32(1)));
+    drop(b);
+    let _c = rt.instantiate(&m).unwrap();
+    assert_eq!(rt.memory_stats().peak_bytes, 3 * 65536);
+}
+
+#[test]
+fn test_runtime_instance_and_code_limits() {
+    use std::sync::Arc;
+
+    let m = single_func(
+        "id",
+        &[ValType::I32],
+        Some(ValType::I32),
+        vec![Op::LocalGet(0), Op::Return],
+    );
+    let code = Runtime::new().prepare(&m).unwrap().code_bytes();
+    assert!(code > 0);
+
+    let mut config = RuntimeConfig::new();
+    config.set_max_instances(2);
+    let rt = Runtime::with_config(config);
+    let a = rt.instantiate(&m).unwrap();
+    let _b = rt.instantiate_owned(Arc::new(m.clone())).unwrap();
+    assert_eq!(rt.instantiate(&m).err(), Some(Trap::OutOfMemory));
+    assert_eq!(rt.instantiate_batch(&m, 1).err(), Some(Trap::OutOfMemory));
+    assert_eq!(rt.metrics().code_bytes, 2 * code);
+    drop(a);
+    let _c = rt.instantiate(&m).unwrap();
+
+    // Instances sharing prepared code still each count it in full.
+    let mut config = RuntimeConfig::new();
+    config.set_code_quota(2 * code + code / 2);
+    let rt = Runtime::with_config(config);
+    let prepared = rt.prepare(&m).unwrap();
+    let a = rt.instantiate_prepared(&m, &prepared).unwrap();
+    let _b = rt.instantiate(&m).unwrap();
+    assert_eq!(
+        rt.instantiate_prepared(&m, &prepared).err(),
+        Some(Trap::OutOfMemory)
+    );
+    // The refused instance released what it held.
+    assert_eq!(rt.memory_stats().instances, 2);
+    assert_eq!(rt.metrics().code_bytes, 2 * code);
+    drop(a);
+    assert_eq!(rt.metrics().code_bytes, code);
+    let _c = rt.instantiate_prepared(&m, &prepared).unwrap();
+}
+
+#[test]
+fn test_memory_size() {
+    let m = single_func(
+        "msize",
+        &[],
+        Some(ValType::I32),
+        vec![Op::MemorySize, Op::Return],
+    );
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(inst.call("msize", &[]).unwrap(), Some(Val::I32(1)));
+}
+
+#[test]
+fn test_guest_alloc_export() {
+    // Guest bump allocator: the heap top lives at address 0; `free` records
+    // the last freed pointer at address 4.
+    let mut m = Module::new();
+    m.functions.push(func(
+        "alloc",
+        vec![ValType::I32],
+        vec![ValType::I32],
+        vec![],
+        vec![
+            Op::I32Const(0),
+            Op::I32Const(0),
+            Op::I32Load {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::LocalGet(0),
+            Op::I32Add,
+            Op::I32Store {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::I32Const(0),
+            Op::I32Load {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::LocalGet(0),
+            Op::I32Sub,
+            Op::Return,
+        ],
+    ));
+    m.functions.push(func(
+        "free",
+        vec![ValType::I32, ValType::I32],
+        vec![],
+        vec![],
+        vec![
+            Op::I32Const(4),
+            Op::LocalGet(0),
+            Op::I32Store {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("alloc".into(), 0));
+    m.exports.push(("free".into(), 1));
+    m.data_segments.push((0, 1024u32.to_le_bytes().to_vec()));
+    let mut inst = rt().instantiate(&m).unwrap();
+
+    assert_eq!(inst.copy_into_guest(b"hello").unwrap(), (1024, 5));
+    assert_eq!(inst.copy_into_guest(b"abc").unwrap(), (1029, 3));
+    assert_eq!(inst.memory().read_bytes(1024, 8).unwrap(), b"helloabc");
+    inst.free_guest(1029, 3).unwrap();
+    assert_eq!(inst.memory().read_u32(4).unwrap(), 1029);
+
+    // A pointer past the end of memory is rejected before the host writes.
+    inst.memory_mut().write_u32(0, 65534).unwrap();
+    assert_eq!(inst.alloc_guest(16).unwrap_err(), Trap::OutOfBounds);
+}
+
+#[test]
+fn test_guest_alloc_fallback() {
+    let mut m = read_word_module();
+    m.max_memory_pages = Some(3);
+    let mut inst = rt().instantiate(&m).unwrap();
+
+    // Without an `alloc` export the host appends pages to memory.
+    let (ptr, len) = inst.copy_into_guest(&7u32.to_le_bytes()).unwrap();
+    assert_eq!((ptr, len), (65536, 4));
+    assert_eq!(inst.memory().pages(), 2);
+    assert_eq!(
+        inst.call("read", &[Val::I32(ptr as i32)]).unwrap(),
+        Some(Val::I32(7))
+    );
+    assert_eq!(inst.alloc_guest(1).unwrap(), 65544);
+    inst.free_guest(ptr, len).unwrap();
+
+    assert_eq!(inst.alloc_guest(2 * 65536).unwrap_err(), Trap::OutOfMemory);
+}
+
+// ── Data segments ─────────────────────────────────────────────────────────────
+
+#[test]
+fn test_data_segment() {
+    let mut m = single_func(
+        "read_data",
+        &[],
+        Some(ValType::I32),
+        vec![
+            Op::I32Const(0),
+            Op::I32Load {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::Return,
+        ],
+    );
+    // Write 0xDEADBEEFu32 at offset 0
+    m.data_segments.push((0, vec![0xEF, 0xBE, 0xAD, 0xDE]));
+    let mut inst = rt().instantiate(&m).unwrap();
+    let result = inst.call("read_data", &[]).unwrap();
+    assert_eq!(result, Some(Val::I32(0xDEADBEEFu32 as i32)));
+}
+
+#[test]
+fn test_reset_memory() {
+    use std::collections::HashMap;
+
+    let mut m = read_word_module();
+    m.max_memory_pages = Some(4);
+    m.data_segments.push((8, vec![42, 0, 0, 0]));
+    let image = MemoryImage::new(&m, &HashMap::new()).unwrap();
+    let rt = rt();
+
+    for mut inst in [
+        rt.instantiate(&m).unwrap(),
+        rt.instantiate_from_image(&m, &image).unwrap(),
+    ] {
+        inst.memory_mut().write_u32(8, 1).unwrap();
+        inst.memory_mut().write_u32(100, 1).unwrap();
+        inst.memory_mut().grow(2).unwrap();
+        inst.reset_memory().unwrap();
+        assert_eq!(inst.memory().pages(), 1);
+        assert_eq!(
+            inst.call("read", &[Val::I32(8)]).unwrap(),
+            Some(Val::I32(42))
+        );
+        assert_eq!(inst.memory().read_u32(100).unwrap(), 0);
+
+        inst.memory_mut().write_u32(8, 1).unwrap();
+        inst.reset_memory_from_image(&image).unwrap();
+        assert_eq!(inst.memory().read_u32(8).unwrap(), 42);
+    }
+    assert_eq!(rt.memory_stats().current_bytes, 0);
+}
+
+#[test]
+fn test_warm_boot() {
+    // init() doubles the word at 8 and grows memory by a page.
+    let mut m = read_word_module();
+    m.data_segments.push((8, vec![21, 0, 0, 0]));
+    m.functions.push(func(
+        "init",
+        vec![],
+        vec![],
+        vec![],
+        vec![
+            Op::I32Const(8),
+            Op::I32Const(8),
+            Op::I32Load {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::I32Const(2),
+            Op::I32Mul,
+            Op::I32Store {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::I32Const(1),
+            Op::MemoryGrow,
+            Op::Drop,
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("init".into(), 1));
+
+    let rt = rt();
+    let image = rt.warm_boot(&m, "init").unwrap();
+    assert_eq!(image.pages(), 2);
+    let mut a = rt.instantiate_warm(&m, &image).unwrap();
+    let mut b = rt.instantiate_warm(&m, &image).unwrap();
+    assert_eq!(a.call("read", &[Val::I32(8)]).unwrap(), Some(Val::I32(42)));
+    assert_eq!(a.memory().pages(), 2);
+    a.memory_mut().write_u32(8, 7).unwrap();
+    assert_eq!(b.call("read", &[Val::I32(8)]).unwrap(), Some(Val::I32(42)));
+
+    // A host allocation made during init is not handed out again.
+    let mut inst = rt.instantiate(&m).unwrap();
+    let ptr = inst.alloc_guest(16).unwrap();
+    let image = inst.snapshot().unwrap();
+    let mut warm = rt.instantiate_warm(&m, &image).unwrap();
+    assert!(warm.alloc_guest(16).unwrap() >= ptr + 16);
+
+    // Only the module and runtime the image came from can use it.
+    assert!(matches!(
+        rt.instantiate_warm(&read_word_module(), &image).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+    assert!(matches!(
+        Runtime::new().instantiate_warm(&m, &image).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+    assert_eq!(
+        rt.warm_boot(&m, "missing").err(),
+        Some(Trap::UndefinedExport("missing".into()))
+    );
+}
+
+#[test]
+fn test_memory_discard() {
+    use std::collections::HashMap;
+
+    let mut m = read_word_module();
+    m.initial_memory_pages = 2;
+    m.functions.push(func(
+        "discard",
+        vec![ValType::I32, ValType::I32],
+        vec![],
+        vec![],
+        vec![
+            Op::LocalGet(0),
+            Op::LocalGet(1),
+            Op::MemoryDiscard,
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("discard".into(), 1));
+    m.data_segments.push((8, vec![42, 0, 0, 0]));
+    m.data_segments.push((65536 + 8, vec![43, 0, 0, 0]));
+    let bytes = m.to_bytes();
+    assert_eq!(
+        Module::from_bytes(&bytes).unwrap().functions[1].body[2],
+        Op::MemoryDiscard
+    );
+
+    let image = MemoryImage::new(&m, &HashMap::new()).unwrap();
+    let rt = rt();
+    for mut inst in [
+        rt.instantiate(&m).unwrap(),
+        rt.instantiate_from_image(&m, &image).unwrap(),
+    ] {
+        // Discarded pages read as zero, even where the image had data.
+        inst.call("discard", &[Val::I32(0), Val::I32(65536)])
+            .unwrap();
+        assert_eq!(
+            inst.call("read", &[Val::I32(8)]).unwrap(),
+            Some(Val::I32(0))
+        );
+        assert_eq!(
+            inst.call("read", &[Val::I32(65536 + 8)]).unwrap(),
+            Some(Val::I32(43))
+        );
+        assert_eq!(
+            inst.call("discard", &[Val::I32(4096), Val::I32(65536)])
+                .unwrap_err(),
+            Trap::OutOfBounds
+        );
+        assert_eq!(
+            inst.call("discard", &[Val::I32(0), Val::I32(100)])
+                .unwrap_err(),
+            Trap::OutOfBounds
+        );
+        assert_eq!(
+            inst.call("discard", &[Val::I32(65536), Val::I32(2 * 65536)])
+                .unwrap_err(),
+            Trap::OutOfBounds
+        );
+    }
+}
+
+#[test]
+fn test_memory_poison() {
+    let mut m = read_word_module();
+    m.initial_memory_pages = 2;
+    m.data_segments.push((8, vec![42, 0, 0, 0]));
+    let mut config = RuntimeConfig::new();
+    config.set_poison(Poison::Check);
+    let rt = Runtime::with_config(config);
+    let mut inst = rt.instantiate(&m).unwrap();
+
+    assert_eq!(
+        inst.call("read", &[Val::I32(8)]).unwrap(),
+        Some(Val::I32(42))
+    );
+    assert_eq!(
+        inst.call("read", &[Val::I32(65536)]).unwrap_err(),
+        Trap::UninitializedRead(65536)
+    );
+    // Inspection sees the pattern without tripping the check.
+    let row = inst.memory().inspect(65536..65540).next().unwrap();
+    assert_eq!(row.bytes, [POISON_BYTE; 4]);
+}
+
+// ── External data segments ────────────────────────────────────────────────────
+
+fn read_word_module() -> Module {
+    single_func(
+        "read",
+        &[ValType::I32],
+        Some(ValType::I32),
+        vec![
+            Op::LocalGet(0),
+            Op::I32Load {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::Return,
+        ],
+    )
+}
+
+#[test]
+fn test_external_segment_roundtrip() {
+    use std::collections::HashMap;
+
+    let mut m = read_word_module();
+    m.data_segments.push((0, vec![1, 0, 0, 0]));
+    m.data_segments.push((64, vec![0xAA; 4096]));
+    let blobs: HashMap<[u8; 32], Vec<u8>> = m.externalize_segments(1024).into_iter().collect();
+    assert_eq!(m.data_segments.len(), 1);
+    assert_eq!(m.external_segments.len(), 1);
+
+    // The blob no longer travels with the module bytes.
+    let bytes = m.to_bytes();
+    assert!(bytes.len() < 1024);
+    let m2 = Module::from_bytes(&bytes).unwrap();
+    assert_eq!(m2.external_segments, m.external_segments);
+
+    let mut inst = rt().instantiate_with_blobs(&m2, &blobs).unwrap();
+    assert_eq!(
+        inst.call("read", &[Val::I32(0)]).unwrap(),
+        Some(Val::I32(1))
+    );
+    assert_eq!(
+        inst.call("read", &[Val::I32(64)]).unwrap(),
+        Some(Val::I32(0xAAAAAAAAu32 as i32))
+    );
+}
+
+#[test]
+fn test_external_segment_missing_or_corrupt() {
+    use std::collections::HashMap;
+
+    let mut m = read_word_module();
+    m.data_segments.push((0, vec![7; 16]));
+    let hash = m.externalize_segments(0)[0].0;
+
+    assert!(matches!(rt().instantiate(&m), Err(Trap::InvalidModule(_))));
+
+    let mut bad: HashMap<[u8; 32], Vec<u8>> = HashMap::new();
+    bad.insert(hash, vec![8; 16]);
+    assert!(matches!(
+        rt().instantiate_with_blobs(&m, &bad),
+        Err(Trap::InvalidModule(_))
+    ));
+
+    // A failed reset applies no segment, not even those checked first.
+    let mut m = read_word_module();
+    m.data_segments.push((8, vec![42, 0, 0, 0]));
+    m.data_segments.push((16, vec![7, 0, 0, 0]));
+    let mut blobs: HashMap<[u8; 32], Vec<u8>> = m.externalize_segments(0).into_iter().collect();
+    let mut inst = rt().instantiate_with_blobs(&m, &blobs).unwrap();
+    inst.memory_mut().write_u32(100, 1).unwrap();
+    blobs.insert(m.external_segments[1].hash, vec![8, 0, 0, 0]);
+    assert!(matches!(
+        inst.reset_memory_with_blobs(&blobs),
+        Err(Trap::InvalidModule(_))
+    ));
+    for addr in [8, 16, 100] {
+        assert_eq!(inst.memory().read_u32(addr).unwrap(), 0);
+    }
+}
+
+#[test]
+fn test_dir_blob_store() {
+    use rune::blob::DirBlobStore;
+
+    let dir = std::env::temp_dir().join(format!("rune-blobs-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+
+    let mut m = read_word_module();
+    m.data_segments.push((8, vec![42, 0, 0, 0]));
+    let store = DirBlobStore::new(&dir);
+    for (hash, bytes) in m.externalize_segments(0) {
+        std::fs::write(store.path_for(&hash), bytes).unwrap();
+    }
+
+    let mut inst = rt().instantiate_with_blobs(&m, &store).unwrap();
+    assert_eq!(
+        inst.call("read", &[Val::I32(8)]).unwrap(),
+        Some(Val::I32(42))
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_data_sources() {
+    use rune::blob::DataSources;
+
+    let path = std::env::temp_dir().join(format!("rune-data-{}", std::process::id()));
+    let mut m = read_word_module();
+    m.data_segments.push((8, vec![42, 0, 0, 0]));
+    m.data_segments.push((16, vec![7, 0, 0, 0]));
+    let blobs = m.externalize_segments(0);
+    std::fs::write(&path, &blobs[0].1).unwrap();
+
+    let mut sources = DataSources::new();
+    sources
+        .add_file(blobs[0].0, &path)
+        .add_reader(blobs[1].0, std::io::Cursor::new(blobs[1].1.clone()));
+    let rt = rt();
+    let mut inst = rt.instantiate_with_data_sources(&m, &sources).unwrap();
+    assert_eq!(
+        inst.call("read", &[Val::I32(8)]).unwrap(),
+        Some(Val::I32(42))
+    );
+    assert_eq!(
+        inst.call("read", &[Val::I32(16)]).unwrap(),
+        Some(Val::I32(7))
+    );
+
+    // The reader was used up by the first instantiation.
+    assert!(matches!(
+        rt.instantiate_with_data_sources(&m, &sources).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+
+    // A source with trailing bytes fails the check.
+    let mut sources = DataSources::new();
+    sources
+        .add_file(blobs[0].0, &path)
+        .add_reader(blobs[1].0, std::io::Cursor::new(vec![7, 0, 0, 0, 0]));
+    assert!(matches!(
+        rt.instantiate_with_data_sources(&m, &sources).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[test]
+fn test_instances_from_memory_image() {
+    use std::collections::HashMap;
+
+    let mut m = read_word_module();
+    m.max_memory_pages = Some(4);
+    m.functions.push(func(
+        "write",
+        vec![ValType::I32, ValType::I32],
+        vec![],
+        vec![],
+        vec![
+            Op::LocalGet(0),
+            Op::LocalGet(1),
+            Op::I32Store {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("write".into(), 1));
+    m.data_segments
+        .push((8, 0x1234_5678u32.to_le_bytes().to_vec()));
+    let image = MemoryImage::new(&m, &HashMap::new()).unwrap();
+    assert_eq!(image.pages(), 1);
+
+    let rt = rt();
+    let mut a = rt.instantiate_from_image(&m, &image).unwrap();
+    let mut b = rt.instantiate_from_image(&m, &image).unwrap();
+    assert_eq!(a.memory().is_copy_on_write(), image.is_shared());
+    assert_eq!(
+        a.call("read", &[Val::I32(8)]).unwrap(),
+        Some(Val::I32(0x1234_5678))
+    );
+
+    // Writes stay private to the instance that made them.
+    a.call("write", &[Val::I32(8), Val::I32(7)]).unwrap();
+    assert_eq!(a.call("read", &[Val::I32(8)]).unwrap(), Some(Val::I32(7)));
+    assert_eq!(
+        b.call("read", &[Val::I32(8)]).unwrap(),
+        Some(Val::I32(0x1234_5678))
+    );
+
+    // Growth keeps contents and yields zeroed pages.
+    assert_eq!(b.memory_mut().grow(2).unwrap(), 1);
+    b.call("write", &[Val::I32(3 * 65536 - 4), Val::I32(9)])
+        .unwrap();
+    assert_eq!(
+        b.call("read", &[Val::I32(2 * 65536)]).unwrap(),
+        Some(Val::I32(0))
+    );
+    assert_eq!(
+        b.call("read", &[Val::I32(8)]).unwrap(),
+        Some(Val::I32(0x1234_5678))
+    );
+    let c = rt.instantiate_from_image(&m, &image).unwrap();
+    assert_eq!(c.memory().pages(), 1);
+
+    let mut other = Module::new();
+    other.initial_memory_pages = 2;
+    assert!(rt.instantiate_from_image(&other, &image).is_err());
+}
+
+// ── Control flow ──────────────────────────────────────────────────────────────
+
+#[test]
+fn test_if_then_else() {
+    // abs(x) = if x < 0 then -x else x
+    let m = single_func(
+        "abs",
+        &[ValType::I32],
+        Some(ValType::I32),
+        vec![
+            Op::LocalGet(0),
+            Op::I32Const(0),
+            Op::I32LtS,
+            Op::If(BlockType::Val(ValType::I32)),
+            Op::I32Const(0),
+            Op::LocalGet(0),
+            Op::I32Sub,
+            Op::Else,
+            Op::LocalGet(0),
+            Op::End,
+            Op::Return,
+        ],
+    );
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(
+        inst.call("abs", &[Val::I32(-5)]).unwrap(),
+        Some(Val::I32(5))
+    );
+    assert_eq!(inst.call("abs", &[Val::I32(7)]).unwrap(), Some(Val::I32(7)));
+}
+
+#[test]
+fn test_block_br() {
+    // Returns 99 by branching out of a block.
+    let m = single_func(
+        "blk",
+        &[],
+        Some(ValType::I32),
+        vec![
+            Op::Block(BlockType::Val(ValType::I32)),
+            Op::I32Const(99),
+            Op::Br(0),
+            Op::I32Const(0), // unreachable
+            Op::End,
+            Op::Return,
+        ],
+    );
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(inst.call("blk", &[]).unwrap(), Some(Val::I32(99)));
+}
+
+#[test]
+fn test_loop_countdown() {
+    // count down from N to 0 using a loop, return 0.
+    //
+    // Br(0) = continue loop (targets the Loop frame itself)
+    // Br(1) = break out     (targets the wrapping Block, exits past its End)
+    // A bare Loop with no enclosing Block cannot be exited with Br(1) —
+    // the Block wrapper is required so depth-1 has a valid target.
+    let m = single_func(
+        "countdown",
+        &[ValType::I32],
+        Some(ValType::I32),
+        vec![
+            Op::Block(BlockType::Empty), // depth 1 — break target
+            Op::Loop(BlockType::Empty),  // depth 0 — continue target
+            Op::LocalGet(0),
+            Op::I32Eqz,
+            Op::BrIf(1), // if i==0: exit Block
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32Sub,
+            Op::LocalSet(0),
+            Op::Br(0), // continue Loop
+            Op::End,   // End Loop
+            Op::End,   // End Block
+            Op::LocalGet(0),
+            Op::Return,
+        ],
+    );
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(
+        inst.call("countdown", &[Val::I32(10)]).unwrap(),
+        Some(Val::I32(0))
+    );
+}
+
+// ── Internal function calls ───────────────────────────────────────────────────
+
+#[test]
+fn test_internal_call() {
+    // square(x) calls mul(x, x)
+    let mut m = Module::new();
+    // func[0] = mul(a, b) = a * b
+    m.functions.push(Function::new(
+        "mul",
+        FuncType {
+            params: vec![ValType::I32, ValType::I32],
+            results: vec![ValType::I32],
+        },
+        vec![],
+        vec![Op::LocalGet(0), Op::LocalGet(1), Op::I32Mul, Op::Return],
+    ));
+    // func[1] = square(x) = mul(x, x)
+    m.functions.push(Function::new(
+        "square",
+        FuncType {
+            params: vec![ValType::I32],
+            results: vec![ValType::I32],
+        },
+        vec![],
+        vec![Op::LocalGet(0), Op::LocalGet(0), Op::Call(0), Op::Return],
+    ));
+    m.exports.push(("square".into(), 1));
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(
+        inst.call("square", &[Val::I32(7)]).unwrap(),
+        Some(Val::I32(49))
+    );
+}
+
+// ── Hot-patching ──────────────────────────────────────────────────────────────
+
+#[test]
+fn test_replace_function_preserves_state() {
+    // func[0] = step() -> i32: mem[0] += 1, return mem[0]
+    // func[1] = run() -> i32: step()
+    let mut m = Module::new();
+    m.functions.push(func(
+        "step",
+        vec![],
+        vec![ValType::I32],
+        vec![],
+        vec![
+            Op::I32Const(0),
+            Op::I32Const(0),
+            Op::I32Load {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::I32Const(1),
+            Op::I32Add,
+            Op::I32Store {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::I32Const(0),
+            Op::I32Load {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::Return,
+        ],
+    ));
+    m.functions.push(func(
+        "run",
+        vec![],
+        vec![ValType::I32],
+        vec![],
+        vec![Op::Call(0), Op::Return],
+    ));
+    m.exports.push(("step".into(), 0));
+    m.exports.push(("run".into(), 1));
+
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(inst.call("run", &[]).unwrap(), Some(Val::I32(1)));
+    assert_eq!(inst.call("run", &[]).unwrap(), Some(Val::I32(2)));
+
+    // New step() adds 10 instead of 1; memory keeps its current value.
+    let mut body: Vec<Op> = m.functions[0].body.to_vec();
+    body[3] = Op::I32Const(10);
+    inst.replace_function(
+        "step",
+        func("step", vec![], vec![ValType::I32], vec![], body),
+    )
+    .unwrap();
+    assert_eq!(inst.call("run", &[]).unwrap(), Some(Val::I32(12)));
+    assert_eq!(inst.call("step", &[]).unwrap(), Some(Val::I32(22)));
+}
+
+#[test]
+fn test_replace_function_checks_signature() {
+    let m = single_func(
+        "f",
+        &[],
+        Some(ValType::I32),
+        vec![Op::I32Const(1), Op::Return],
+    );
+    let mut inst = rt().instantiate(&m).unwrap();
+
+    let wrong = func(
+        "f",
+        vec![],
+        vec![ValType::I64],
+        vec![],
+        vec![Op::I64Const(2), Op::Return],
+    );
+    assert_eq!(
+        inst.replace_function(0u32, wrong).unwrap_err(),
+        Trap::TypeMismatch
+    );
+    let missing = func("g", vec![], vec![], vec![], vec![Op::Return]);
+    assert!(matches!(
+        inst.replace_function("g", missing),
+        Err(Trap::UndefinedExport(_))
+    ));
+
+    let right = func(
+        "f",
+        vec![],
+        vec![ValType::I32],
+        vec![],
+        vec![Op::I32Const(2), Op::Return],
+    );
+    inst.replace_function(0u32, right).unwrap();
+    assert_eq!(inst.call("f", &[]).unwrap(), Some(Val::I32(2)));
+}
+
+#[test]
+fn test_reload_preserves_memory() {
+    use rune::instance::ReloadReport;
+    use std::sync::Arc;
+
+    // step() -> i32: mem[0] += delta, return mem[0]
+    let version = |delta: i32, extra_export: bool| {
+        let mut m = Module::new();
+        m.functions.push(func(
+            "step",
+            vec![],
+            vec![ValType::I32],
+            vec![],
+            vec![
+                Op::I32Const(0),
+                Op::I32Const(0),
+                Op::I32Load {
+                    align: 2,
+                    offset: 0,
+                    memory: 0,
+                },
+                Op::I32Const(delta),
+                Op::I32Add,
+                Op::I32Store {
+                    align: 2,
+                    offset: 0,
+                    memory: 0,
+                },
+                Op::I32Const(0),
+                Op::I32Load {
+                    align: 2,
+                    offset: 0,
+                    memory: 0,
+                },
+                Op::Return,
+            ],
+        ));
+        m.exports.push(("step".into(), 0));
+        if extra_export {
+            m.exports.push(("tick".into(), 0));
+        }
+        m
+    };
+    let v1 = version(1, false);
+    let mut v2 = version(10, true);
+    v2.initial_memory_pages = 3;
+    // The data segment is not re-applied over live state.
+    v2.data_segments.push((0, vec![0xFF; 4]));
+
+    let rt = rt();
+    let mut inst = rt.instantiate(&v1).unwrap();
+    inst.call("step", &[]).unwrap();
+    inst.call("step", &[]).unwrap();
+    let report = rt.reload(&mut inst, &v2).unwrap();
+    assert_eq!(
+        report,
+        ReloadReport {
+            added_exports: vec!["tick".into()],
+            pages_added: 2,
+            ..Default::default()
+        }
+    );
+    assert_eq!(inst.call("step", &[]).unwrap(), Some(Val::I32(12)));
+    assert_eq!(inst.call("tick", &[]).unwrap(), Some(Val::I32(22)));
+    assert_eq!(inst.memory().pages(), 3);
+
+    // A shared export changing signature is refused, leaving v2 in place.
+    let mut v3 = version(100, false);
+    v3.functions[0].ty.params.push(ValType::I32);
+    assert!(matches!(
+        rt.reload(&mut inst, &v3).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+    // So is a maximum the memory has already outgrown.
+    let mut v4 = version(100, false);
+    v4.max_memory_pages = Some(2);
+    assert!(matches!(
+        rt.reload(&mut inst, &v4).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+    assert_eq!(inst.call("tick", &[]).unwrap(), Some(Val::I32(32)));
+
+    // Owned instances take a share of the new version.
+    let v1 = Arc::new(version(1, false));
+    let mut owned = rt.instantiate_owned(v1.clone()).unwrap();
+    owned.call("step", &[]).unwrap();
+    owned
+        .replace_function("step", version(5, false).functions.remove(0))
+        .unwrap();
+    let report = rt
+        .reload_owned(&mut owned, Arc::new(version(2, false)))
+        .unwrap();
+    assert_eq!(report.replaced_patches, 1);
+    assert_eq!(Arc::strong_count(&v1), 1);
+    assert_eq!(owned.call("step", &[]).unwrap(), Some(Val::I32(3)));
+}
+
+// ── Host function calls ───────────────────────────────────────────────────────
+
+#[test]
+fn test_host_call() {
+    use rune::Linker;
+    use std::sync::{Arc, Mutex};
+
+    let log: Arc<Mutex<Vec<i32>>> = Arc::new(Mutex::new(Vec::new()));
     let log2 = log.clone();
 
+    let log_ty = FuncType {
+        params: vec![ValType::I32],
+        results: vec![],
+    };
+    let mut m = Module::new();
+    m.import("env", "log", log_ty.clone());
+    let mut linker = Linker::new();
+    linker
+        .func("env", "log", log_ty, move |args| {
+            log2.lock().unwrap().push(args[0].as_i32().unwrap());
+            Ok(None)
+        })
+        .unwrap();
+    m.functions.push(Function::new(
+        "run",
+        FuncType {
+            params: vec![],
+            results: vec![],
+        },
+        vec![],
+        vec![
+            Op::I32Const(42),
+            Op::CallHost(0),
+            Op::I32Const(7),
+            Op::CallHost(0),
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("run".into(), 0));
+
+    let mut inst = linker.instantiate(&rt(), &m).unwrap();
+    inst.call("run", &[]).unwrap();
+    assert_eq!(*log.lock().unwrap(), vec![42, 7]);
+
+    // The module itself holds no host code, so it needs the linker, and a
+    // copy can be bound to different host functions.
+    assert!(matches!(
+        rt().instantiate(&m).err(),
+        Some(Trap::UndefinedImport(name)) if name == "env.log"
+    ));
+    let mut quiet = Linker::new();
+    quiet
+        .func("env", "log", m.imports[0].ty.clone(), |_| Ok(None))
+        .unwrap();
+    let copy = m.clone();
+    quiet
+        .instantiate(&rt(), &copy)
+        .unwrap()
+        .call("run", &[])
+        .unwrap();
+    assert_eq!(log.lock().unwrap().len(), 2);
+}
+
+#[test]
+fn test_host_memory_view() {
+    use rune::Linker;
+
+    // upper(ptr, len): uppercase a guest buffer in place, return its sum.
+    let upper_ty = FuncType {
+        params: vec![ValType::I32, ValType::I32],
+        results: vec![ValType::I32],
+    };
+    let mut m = Module::new();
+    m.import("env", "upper", upper_ty.clone());
+    let mut linker = Linker::new();
+    linker
+        .func_with_caller("env", "upper", upper_ty, |caller, args| {
+            let ptr = args[0].as_i32().unwrap() as usize;
+            let len = args[1].as_i32().unwrap() as usize;
+            let mut mem = caller.memory();
+            let buf = mem.slice_mut(ptr, len)?;
+            buf.make_ascii_uppercase();
+            let sum: i32 = buf.iter().map(|&b| b as i32).sum();
+            assert_eq!(mem.read_str(ptr, len)?, "HI!");
+            Ok(Some(Val::I32(sum)))
+        })
+        .unwrap();
+    m.functions.push(func(
+        "run",
+        vec![ValType::I32, ValType::I32],
+        vec![ValType::I32],
+        vec![],
+        vec![
+            Op::LocalGet(0),
+            Op::LocalGet(1),
+            Op::CallHost(0),
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("run".into(), 0));
+    m.data_segments.push((16, b"hi!".to_vec()));
+    let mut inst = linker.instantiate(&rt(), &m).unwrap();
+
+    let expected = (b'H' + b'I' + b'!') as i32;
+    assert_eq!(
+        inst.call("run", &[Val::I32(16), Val::I32(3)]).unwrap(),
+        Some(Val::I32(expected))
+    );
+    assert_eq!(inst.memory().read_bytes(16, 3).unwrap(), b"HI!");
+    assert_eq!(
+        inst.call("run", &[Val::I32(65535), Val::I32(3)])
+            .unwrap_err(),
+        Trap::OutOfBounds
+    );
+}
+
+#[test]
+fn test_linker() {
+    use rune::Linker;
+    use std::sync::atomic::{AtomicI32, Ordering};
+    use std::sync::Arc;
+
+    let unary = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
+    // run(x) = env.twice(x) + env.count(x), both imported by name.
+    let plugin = |second: &str| {
+        let mut m = Module::new();
+        let twice = m.import("env", "twice", unary.clone());
+        let other = m.import("env", second, unary.clone());
+        m.functions.push(Function::new(
+            "run",
+            unary.clone(),
+            vec![],
+            vec![
+                Op::LocalGet(0),
+                Op::CallHost(twice),
+                Op::LocalGet(0),
+                Op::CallHost(other),
+                Op::I32Add,
+                Op::Return,
+            ],
+        ));
+        m.exports.push(("run".into(), 0));
+        m
+    };
+
+    // Definitions and their state are shared by every module linked.
+    let calls = Arc::new(AtomicI32::new(0));
+    let counter = calls.clone();
+    let mut linker = Linker::new();
+    linker
+        .func("env", "twice", unary.clone(), |args| {
+            Ok(Some(Val::I32(args[0].as_i32().unwrap() * 2)))
+        })
+        .unwrap()
+        .func("env", "count", unary.clone(), move |_| {
+            Ok(Some(Val::I32(counter.fetch_add(1, Ordering::Relaxed) + 1)))
+        })
+        .unwrap();
+    assert_eq!(linker.get("env", "twice"), Some(&unary));
+    assert!(matches!(
+        linker
+            .func("env", "twice", unary.clone(), |_| Ok(None))
+            .err(),
+        Some(Trap::InvalidModule(_))
+    ));
+
+    let rt = rt();
+    let a = plugin("count");
+    let b = Arc::new(plugin("count"));
+    let mut ia = linker.instantiate(&rt, &a).unwrap();
+    let mut ib = linker.instantiate_owned(&rt, b).unwrap();
+    assert_eq!(ia.call("run", &[Val::I32(5)]).unwrap(), Some(Val::I32(11)));
+    assert_eq!(ib.call("run", &[Val::I32(5)]).unwrap(), Some(Val::I32(12)));
+    assert_eq!(calls.load(Ordering::Relaxed), 2);
+
+    // Every unresolved name is reported; without a linker none resolve.
+    let c = plugin("missing");
+    assert_eq!(
+        linker.instantiate(&rt, &c).err(),
+        Some(Trap::UndefinedImport("env.missing".into()))
+    );
+    assert_eq!(
+        rt.instantiate(&c).err(),
+        Some(Trap::UndefinedImport("env.twice, env.missing".into()))
+    );
+
+    // Signatures must match exactly.
+    let mut d = plugin("count");
+    d.imports[1].ty.results.clear();
+    assert!(matches!(
+        linker.instantiate(&rt, &d).err(),
+        Some(Trap::InvalidModule(msg)) if msg.contains("env.count")
+    ));
+
+    // Imports survive both encodings and show up in the interface.
+    let back = Module::from_bytes(&a.to_bytes()).unwrap();
+    assert_eq!(back.imports, a.imports);
+    assert_eq!(Module::from_json(&a.to_json()).unwrap().imports, a.imports);
+    assert_eq!(Module::interface(&a.to_bytes()).unwrap().imports, a.imports);
+    assert_ne!(a.digest(), c.digest());
+    let mut ib = linker.instantiate(&rt, &back).unwrap();
+    assert_eq!(ib.call("run", &[Val::I32(1)]).unwrap(), Some(Val::I32(5)));
+}
+
+#[test]
+fn test_prepared_module() {
+    let m = single_func(
+        "answer",
+        &[],
+        Some(ValType::I32),
+        vec![Op::I32Const(42), Op::Return],
+    );
+    let rt = rt();
+    let prepared = rt.prepare(&m).unwrap();
+    let mut a = rt.instantiate_prepared(&m, &prepared).unwrap();
+    let mut b = rt.instantiate_prepared(&m, &prepared).unwrap();
+
+    // Patching one instance leaves the shared preparation alone.
+    let seven = Function::new(
+        "answer",
+        m.functions[0].ty.clone(),
+        vec![],
+        vec![Op::I32Const(7), Op::Return],
+    );
+    a.replace_function("answer", seven).unwrap();
+    assert_eq!(a.call("answer", &[]).unwrap(), Some(Val::I32(7)));
+    assert_eq!(b.call("answer", &[]).unwrap(), Some(Val::I32(42)));
+    let mut c = rt.instantiate_prepared(&m, &prepared).unwrap();
+    assert_eq!(c.call("answer", &[]).unwrap(), Some(Val::I32(42)));
+
+    // A clone of the module shares its bodies, so it matches too.
+    let copy = m.clone();
+    assert!(rt.instantiate_prepared(&copy, &prepared).is_ok());
+
+    // Another module, or another runtime's configuration, does not.
+    let other = single_func(
+        "answer",
+        &[],
+        Some(ValType::I32),
+        vec![Op::I32Const(42), Op::Return],
+    );
+    assert!(matches!(
+        rt.instantiate_prepared(&other, &prepared).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+    assert!(matches!(
+        Runtime::new().instantiate_prepared(&m, &prepared).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+    assert_eq!(rt.metrics().instantiations, 4);
+}
+
+#[test]
+fn test_instantiate_batch() {
+    use rune::RuntimeConfig;
+    use std::sync::Arc;
+
+    let mut m = read_word_module();
+    m.data_segments.push((8, vec![42, 0, 0, 0]));
+    let rt = rt();
+    let mut agents = rt.instantiate_batch(&m, 100).unwrap();
+    assert_eq!(agents.len(), 100);
+    for agent in &mut agents {
+        assert_eq!(
+            agent.call("read", &[Val::I32(8)]).unwrap(),
+            Some(Val::I32(42))
+        );
+    }
+    // Each instance has its own copy of the shared initial memory.
+    agents[0].memory_mut().write_u32(8, 7).unwrap();
+    assert_eq!(
+        agents[1].call("read", &[Val::I32(8)]).unwrap(),
+        Some(Val::I32(42))
+    );
+    assert!(rt.instantiate_batch(&m, 0).unwrap().is_empty());
+    assert_eq!(rt.metrics().instantiations, 100);
+
+    let owned = rt.instantiate_batch_owned(Arc::new(m.clone()), 3).unwrap();
+    assert_eq!(owned.len(), 3);
+
+    // One failure fails the batch and releases what the others took.
+    let mut config = RuntimeConfig::new();
+    config.set_memory_quota(3 * 65536);
+    let rt = Runtime::with_config(config);
+    assert_eq!(rt.instantiate_batch(&m, 5).err(), Some(Trap::OutOfMemory));
+    assert_eq!(rt.memory_stats().current_bytes, 0);
+    assert_eq!(rt.instantiate_batch(&m, 3).unwrap().len(), 3);
+}
+
+#[test]
+fn test_compile_background() {
+    use rune::aot::CompileOutcome;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    let mut m = read_word_module();
+    m.data_segments.push((8, vec![42, 0, 0, 0]));
+    let m = Arc::new(m);
+    let rt = rt();
+    let handle = rt.compile_background(&m);
+    // Instances run whether or not compilation is done.
+    let mut inst = rt.instantiate_owned(m.clone()).unwrap();
+    assert_eq!(
+        inst.call("read", &[Val::I32(8)]).unwrap(),
+        Some(Val::I32(42))
+    );
+    let outcome = if cfg!(feature = "cranelift") {
+        CompileOutcome::Native
+    } else {
+        CompileOutcome::Interpreted
+    };
+    assert_eq!(handle.wait(), Ok(outcome));
+    assert!(handle.is_finished());
+    assert_eq!(handle.wait_timeout(Duration::ZERO), Some(Ok(outcome)));
+    assert!(Arc::ptr_eq(handle.module(), &m));
+    assert_eq!(
+        inst.call("read", &[Val::I32(8)]).unwrap(),
+        Some(Val::I32(42))
+    );
+
+    // A module the runtime cannot run fails as instantiating it would.
+    let bad = Arc::new(ext_module(2));
+    assert!(matches!(
+        rt.compile_background(&bad).wait(),
+        Err(Trap::UnsupportedFeature(_))
+    ));
+}
+
+#[test]
+fn test_instance_pre() {
+    use rune::Linker;
+    use std::sync::Arc;
+
+    let unary = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
+    // run(x) = env.twice(x) + mem[0], with mem[0] = 7 from a data segment.
+    let mut m = Module::new();
+    let twice = m.import("env", "twice", unary.clone());
+    m.functions.push(Function::new(
+        "run",
+        unary.clone(),
+        vec![],
+        vec![
+            Op::LocalGet(0),
+            Op::CallHost(twice),
+            Op::I32Const(0),
+            Op::I32Load {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::I32Add,
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("run".into(), 0));
+    m.initial_memory_pages = 1;
+    m.data_segments.push((0, 7u32.to_le_bytes().to_vec()));
+
+    let rt = rt();
+    let mut linker = Linker::new();
+    assert!(matches!(
+        linker.instantiate_pre(&rt, &m).err(),
+        Some(Trap::UndefinedImport(name)) if name == "env.twice"
+    ));
+    linker
+        .func("env", "twice", unary.clone(), |args| {
+            Ok(Some(Val::I32(args[0].as_i32().unwrap() * 2)))
+        })
+        .unwrap();
+    let pre = linker.instantiate_pre(&rt, &m).unwrap();
+
+    // Instances from one template are independent, and each starts fresh.
+    let mut a = pre.instantiate().unwrap();
+    a.memory_mut().write_u32(0, 100).unwrap();
+    let mut b = pre.instantiate().unwrap();
+    assert_eq!(a.call("run", &[Val::I32(5)]).unwrap(), Some(Val::I32(110)));
+    assert_eq!(b.call("run", &[Val::I32(5)]).unwrap(), Some(Val::I32(17)));
+    assert_eq!(rt.memory_stats().instances, 2);
+    assert_eq!(rt.metrics().instantiations, 2);
+
+    // Owned templates are shared across threads.
+    let pre = Arc::new(linker.instantiate_pre_owned(&rt, Arc::new(m)).unwrap());
+    let workers: Vec<_> = (0..2)
+        .map(|_| {
+            let pre = pre.clone();
+            std::thread::spawn(move || {
+                let mut inst = pre.instantiate().unwrap();
+                inst.call("run", &[Val::I32(1)]).unwrap()
+            })
+        })
+        .collect();
+    for worker in workers {
+        assert_eq!(worker.join().unwrap(), Some(Val::I32(9)));
+    }
+}
+
+#[cfg(feature = "hostlib")]
+#[test]
+fn test_linker_capabilities() {
+    use rune::{Capabilities, Linker};
+
+    let unary = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
+    // log(x) and read(x) are host functions; read needs "fs".
+    let mut m = Module::new();
+    let log = m.import("env", "log", unary.clone());
+    let read = m.import("env", "read", unary.clone());
+    for (name, host) in [("log", log), ("read", read)] {
+        let index = m.functions.len() as u32;
+        m.functions.push(func(
+            name,
+            vec![ValType::I32],
+            vec![ValType::I32],
+            vec![],
+            vec![Op::LocalGet(0), Op::CallHost(host), Op::Return],
+        ));
+        m.exports.push((name.into(), index));
+    }
+
+    let mut linker = Linker::new();
+    linker
+        .func("env", "log", unary.clone(), |args| Ok(Some(args[0])))
+        .unwrap()
+        .func("env", "read", unary.clone(), |args| {
+            Ok(Some(Val::I32(args[0].as_i32().unwrap() + 1)))
+        })
+        .unwrap()
+        .set_capability("env", "read", "fs")
+        .unwrap();
+    assert_eq!(linker.capability("env", "read"), Some("fs"));
+    assert_eq!(linker.capability("env", "log"), None);
+    assert!(matches!(
+        linker.set_capability("env", "nope", "fs").err(),
+        Some(Trap::UndefinedImport(name)) if name == "env.nope"
+    ));
+
+    let rt = rt();
+    let mut trusted = linker.instantiate(&rt, &m).unwrap();
+    assert_eq!(
+        trusted.call("read", &[Val::I32(1)]).unwrap(),
+        Some(Val::I32(2))
+    );
+
+    // Linking succeeds; only the call is refused.
+    let mut untrusted = linker
+        .instantiate_with_capabilities(&rt, &m, &Capabilities::none())
+        .unwrap();
+    assert_eq!(
+        untrusted.call("log", &[Val::I32(1)]).unwrap(),
+        Some(Val::I32(1))
+    );
+    assert_eq!(
+        untrusted.call("read", &[Val::I32(1)]),
+        Err(Trap::PermissionDenied("env.read".into()))
+    );
+    assert_eq!(rt.metrics().traps.get("permission_denied"), Some(&1));
+
+    let mut fs = Capabilities::none();
+    fs.grant("fs");
+    assert!(fs.allows("fs") && !fs.allows("net"));
+    let pre = linker
+        .instantiate_pre_with_capabilities(&rt, &m, &fs)
+        .unwrap();
+    let mut granted = pre.instantiate().unwrap();
+    assert_eq!(
+        granted.call("read", &[Val::I32(1)]).unwrap(),
+        Some(Val::I32(2))
+    );
+}
+
+#[test]
+fn test_linker_instance_exports() {
+    use rune::Linker;
+    use std::sync::{Arc, Mutex};
+
+    let unary = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
+    // util: double(x) = 2x, counting calls in memory[0]; boom() traps.
+    let mut util = Module::new();
+    util.functions.push(func(
+        "double",
+        vec![ValType::I32],
+        vec![ValType::I32],
+        vec![],
+        vec![
+            Op::I32Const(0),
+            Op::I32Const(0),
+            Op::I32Load {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::I32Const(1),
+            Op::I32Add,
+            Op::I32Store {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::LocalGet(0),
+            Op::I32Const(2),
+            Op::I32Mul,
+            Op::Return,
+        ],
+    ));
+    util.functions
+        .push(func("boom", vec![], vec![], vec![], vec![Op::Unreachable]));
+    util.exports.push(("double".into(), 0));
+    util.exports.push(("boom".into(), 1));
+    util.initial_memory_pages = 1;
+
+    // app: quad(x) = util.double(util.double(x)); crash() = util.boom().
+    let mut app = Module::new();
+    let double = app.import("util", "double", unary.clone());
+    let void = FuncType {
+        params: vec![],
+        results: vec![],
+    };
+    let boom = app.import("util", "boom", void);
+    app.functions.push(func(
+        "quad",
+        vec![ValType::I32],
+        vec![ValType::I32],
+        vec![],
+        vec![
+            Op::LocalGet(0),
+            Op::CallHost(double),
+            Op::CallHost(double),
+            Op::Return,
+        ],
+    ));
+    app.functions.push(func(
+        "crash",
+        vec![],
+        vec![],
+        vec![],
+        vec![Op::CallHost(boom), Op::Return],
+    ));
+    app.exports.push(("quad".into(), 0));
+    app.exports.push(("crash".into(), 1));
+
+    let rt = rt();
+    let util = Arc::new(Mutex::new(rt.instantiate_owned(Arc::new(util)).unwrap()));
+    let mut linker = Linker::new();
+    linker.instance("util", &util).unwrap();
+    assert_eq!(linker.get("util", "double"), Some(&unary));
+    assert!(matches!(
+        linker.instance("util", &util).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+
+    let mut a = linker.instantiate(&rt, &app).unwrap();
+    let mut b = linker.instantiate(&rt, &app).unwrap();
+    assert_eq!(a.call("quad", &[Val::I32(3)]).unwrap(), Some(Val::I32(12)));
+    assert_eq!(b.call("quad", &[Val::I32(5)]).unwrap(), Some(Val::I32(20)));
+    // Both importers share util's state.
+    assert_eq!(util.lock().unwrap().memory().read_u32(0).unwrap(), 4);
+    // A trap in the exporter surfaces in the importer.
+    assert_eq!(a.call("crash", &[]), Err(Trap::Unreachable));
+}
+
+#[test]
+fn test_linker_globals() {
+    use rune::global::Global;
+    use rune::Linker;
+
+    // limit() reads config.max_items; hit() increments stats.hits.
+    let mut m = Module::new();
+    let max_items = m.import_global("config", "max_items", ValType::I32, false);
+    let hits = m.import_global("stats", "hits", ValType::I64, true);
+    m.functions.push(func(
+        "limit",
+        vec![],
+        vec![ValType::I32],
+        vec![],
+        vec![Op::GlobalGet(max_items), Op::Return],
+    ));
+    m.functions.push(func(
+        "hit",
+        vec![],
+        vec![],
+        vec![],
+        vec![
+            Op::GlobalGet(hits),
+            Op::I64Const(1),
+            Op::I64Add,
+            Op::GlobalSet(hits),
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("limit".into(), 0));
+    m.exports.push(("hit".into(), 1));
+
+    let limit = Global::new(Val::I32(16), false);
+    let counter = Global::new(Val::I64(0), true);
+    let mut linker = Linker::new();
+    linker
+        .global("config", "max_items", limit.clone())
+        .unwrap()
+        .global("stats", "hits", counter.clone())
+        .unwrap();
+    assert!(matches!(
+        linker.global("config", "max_items", limit.clone()).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+
+    // Instances share the host's values in both directions.
+    let rt = rt();
+    let mut a = linker.instantiate(&rt, &m).unwrap();
+    let mut b = linker.instantiate(&rt, &m).unwrap();
+    assert_eq!(a.call("limit", &[]).unwrap(), Some(Val::I32(16)));
+    limit.set(Val::I32(32)).unwrap();
+    assert_eq!(b.call("limit", &[]).unwrap(), Some(Val::I32(32)));
+    a.call("hit", &[]).unwrap();
+    b.call("hit", &[]).unwrap();
+    assert_eq!(counter.get(), Val::I64(2));
+    assert_eq!(limit.set(Val::I64(1)), Err(Trap::TypeMismatch));
+
+    // Unresolved names, type mismatches and writes to immutable globals are
+    // rejected at instantiation.
+    assert_eq!(
+        rt.instantiate(&m).err(),
+        Some(Trap::UndefinedImport("config.max_items, stats.hits".into()))
+    );
+    let mut wrong = Linker::new();
+    wrong
+        .global("config", "max_items", Global::new(Val::I64(16), false))
+        .unwrap()
+        .global("stats", "hits", Global::new(Val::I64(0), false))
+        .unwrap();
+    assert!(matches!(
+        wrong.instantiate(&rt, &m).err(),
+        Some(Trap::InvalidModule(msg)) if msg.contains("config.max_items")
+    ));
+    let mut writes_const = m.clone();
+    writes_const.functions[0] = func(
+        "limit",
+        vec![],
+        vec![ValType::I32],
+        vec![],
+        vec![
+            Op::I32Const(1),
+            Op::GlobalSet(max_items),
+            Op::GlobalGet(max_items),
+            Op::Return,
+        ],
+    );
+    assert!(matches!(
+        linker.instantiate(&rt, &writes_const).err(),
+        Some(Trap::InvalidModule(msg)) if msg.contains("immutable")
+    ));
+
+    // Global imports survive both encodings and show up in the interface.
+    let back = Module::from_bytes(&m.to_bytes()).unwrap();
+    assert_eq!(back.global_imports, m.global_imports);
+    assert_eq!(back.functions[1].body, m.functions[1].body);
+    let json = Module::from_json(&m.to_json()).unwrap();
+    assert_eq!(json.global_imports, m.global_imports);
+    assert_eq!(json.functions[1].body, m.functions[1].body);
+    assert_eq!(
+        Module::interface(&m.to_bytes()).unwrap().global_imports,
+        m.global_imports
+    );
+}
+
+#[cfg(feature = "hostlib")]
+#[test]
+fn test_hostlib() {
+    use rune::hostlib::{HostLib, NAMESPACE};
+    use rune::Linker;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    use ValType::{I32, I64};
+    let mut m = Module::new();
+    let sig = |params: &[ValType], results: &[ValType]| FuncType {
+        params: params.to_vec(),
+        results: results.to_vec(),
+    };
+    let write = m.import(NAMESPACE, "write_stdout", sig(&[I32, I32], &[I32]));
+    let args_get = m.import(NAMESPACE, "args_get", sig(&[I32, I32, I32], &[I32]));
+    let env_get = m.import(NAMESPACE, "env_get", sig(&[I32, I32, I32, I32], &[I32]));
+    let random = m.import(NAMESPACE, "random_get", sig(&[I32, I32], &[]));
+    let mono = m.import(NAMESPACE, "clock_monotonic", sig(&[], &[I64]));
+    let wall = m.import(NAMESPACE, "clock_wall", sig(&[], &[I64]));
+    let call = |ops: &[i32], host: u32| {
+        let mut body: Vec<Op> = ops.iter().map(|&v| Op::I32Const(v)).collect();
+        body.extend([Op::CallHost(host), Op::Return]);
+        body
+    };
+    let exports: [(&str, Vec<ValType>, Vec<Op>); 6] = [
+        ("hello", vec![I32], call(&[0, 6], write)),
+        ("arg", vec![I32], {
+            let mut body = vec![Op::LocalGet(0)];
+            body.extend(call(&[100, 4], args_get));
+            body
+        }),
+        ("env", vec![I32], call(&[6, 4, 200, 16], env_get)),
+        ("unset", vec![I32], call(&[10, 4, 200, 16], env_get)),
+        ("mono", vec![I64], vec![Op::CallHost(mono), Op::Return]),
+        ("wall", vec![I64], vec![Op::CallHost(wall), Op::Return]),
+    ];
+    for (name, results, body) in exports {
+        let params = if name == "arg" { vec![I32] } else { vec![] };
+        let index = m.functions.len() as u32;
+        m.functions.push(func(name, params, results, vec![], body));
+        m.exports.push((name.into(), index));
+    }
+    let index = m.functions.len() as u32;
+    m.functions.push(func(
+        "rand",
+        vec![],
+        vec![],
+        vec![],
+        vec![
+            Op::I32Const(300),
+            Op::I32Const(32),
+            Op::CallHost(random),
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("rand".into(), index));
+    m.initial_memory_pages = 1;
+    m.data_segments.push((0, b"hello\nLANGPATH".to_vec()));
+
+    let out = Captured::default();
+    let mut linker = Linker::new();
+    HostLib::new()
+        .args(["plugin", "--verbose"])
+        .env("LANG", "en_GB.UTF-8")
+        .stdout(out.clone())
+        .add_to_linker(&mut linker)
+        .unwrap();
+    let mut inst = linker.instantiate(&rt(), &m).unwrap();
+
+    assert_eq!(inst.call("hello", &[]).unwrap(), Some(Val::I32(6)));
+    assert_eq!(*out.0.lock().unwrap(), b"hello\n");
+
+    // Truncated to the buffer, but the full length is reported.
+    assert_eq!(inst.call("arg", &[Val::I32(1)]).unwrap(), Some(Val::I32(9)));
+    assert_eq!(inst.memory().read_bytes(100, 4).unwrap(), b"--ve");
+    assert_eq!(
+        inst.call("arg", &[Val::I32(2)]).unwrap(),
+        Some(Val::I32(-1))
+    );
+    assert_eq!(
+        inst.call("arg", &[Val::I32(-1)]).unwrap(),
+        Some(Val::I32(-1))
+    );
+
+    assert_eq!(inst.call("env", &[]).unwrap(), Some(Val::I32(11)));
+    assert_eq!(inst.memory().read_bytes(200, 11).unwrap(), b"en_GB.UTF-8");
+    // The host's own PATH is not visible.
+    assert_eq!(inst.call("unset", &[]).unwrap(), Some(Val::I32(-1)));
+
+    inst.call("rand", &[]).unwrap();
+    let first = inst.memory().read_bytes(300, 32).unwrap().to_vec();
+    inst.call("rand", &[]).unwrap();
+    assert_ne!(inst.memory().read_bytes(300, 32).unwrap(), first);
+
+    let t0 = inst.call("mono", &[]).unwrap().unwrap().as_i64().unwrap();
+    let t1 = inst.call("mono", &[]).unwrap().unwrap().as_i64().unwrap();
+    assert!(0 <= t0 && t0 <= t1);
+    let wall = inst.call("wall", &[]).unwrap().unwrap().as_i64().unwrap();
+    assert!(wall > 1_600_000_000 * 1_000_000_000);
+
+    // Defining the library twice on one linker is refused.
+    assert!(matches!(
+        HostLib::new().add_to_linker(&mut linker),
+        Err(Trap::InvalidModule(_))
+    ));
+}
+#[cfg(feature = "hostlib")]
+#[test]
+fn test_hostlib_log() {
+    use rune::hostlib::{HostLib, LogLevel, NAMESPACE};
+    use rune::Linker;
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+
+    #[derive(Clone, Default)]
+    struct Captured(Arc<Mutex<Vec<u8>>>);
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    use ValType::I32;
+    let mut m = Module::new();
+    let log = m.import(
+        NAMESPACE,
+        "log",
+        FuncType {
+            params: vec![I32, I32, I32],
+            results: vec![],
+        },
+    );
+    m.functions.push(func(
+        "log",
+        vec![I32],
+        vec![],
+        vec![],
+        vec![
+            Op::LocalGet(0),
+            Op::I32Const(0),
+            Op::I32Const(5),
+            Op::CallHost(log),
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("log".into(), 0));
+    m.initial_memory_pages = 1;
+    m.data_segments.push((0, b"ready".to_vec()));
+
+    // Without a logger, messages go to stderr.
+    let err = Captured::default();
+    let mut linker = Linker::new();
+    HostLib::new()
+        .stderr(err.clone())
+        .add_to_linker(&mut linker)
+        .unwrap();
+    let mut inst = linker.instantiate(&rt(), &m).unwrap();
+    inst.call("log", &[Val::I32(1)]).unwrap();
+    assert_eq!(*err.0.lock().unwrap(), b"[warn] ready\n");
+    assert!(matches!(
+        inst.call("log", &[Val::I32(5)]),
+        Err(Trap::HostError(msg)) if msg == "invalid log level 5"
+    ));
+
+    let logged = Arc::new(Mutex::new(Vec::new()));
+    let sink = logged.clone();
+    let mut linker = Linker::new();
+    HostLib::new()
+        .stderr(err.clone())
+        .logger(move |level, message| sink.lock().unwrap().push((level, message.to_owned())))
+        .add_to_linker(&mut linker)
+        .unwrap();
+    let mut inst = linker.instantiate(&rt(), &m).unwrap();
+    inst.call("log", &[Val::I32(2)]).unwrap();
+    assert_eq!(
+        *logged.lock().unwrap(),
+        [(LogLevel::Info, "ready".to_owned())]
+    );
+    assert_eq!(*err.0.lock().unwrap(), b"[warn] ready\n");
+}
+
+#[test]
+fn test_host_data() {
+    use rune::Linker;
+
+    // Per-instance state reached through the caller, no locks needed.
+    #[derive(Default)]
+    struct Tally {
+        sum: i32,
+        seen: Vec<u8>,
+    }
+
+    let void = |params: Vec<ValType>| FuncType {
+        params,
+        results: vec![],
+    };
+    let mut linker = Linker::new();
+    linker
+        .func_with_caller("env", "add", void(vec![ValType::I32]), |caller, args| {
+            let tally = caller.data_mut::<Tally>().ok_or(Trap::TypeMismatch)?;
+            tally.sum += args[0].as_i32().unwrap();
+            Ok(None)
+        })
+        .unwrap()
+        .func_with_caller("env", "note", void(vec![ValType::I32]), |caller, args| {
+            let (tally, view) = caller.data_and_memory::<Tally>().unwrap();
+            let ptr = args[0].as_i32().unwrap() as usize;
+            tally.seen.push(view.slice(ptr, 1)?[0]);
+            Ok(None)
+        })
+        .unwrap();
+
+    let mut m = Module::new();
+    let add = m.import("env", "add", void(vec![ValType::I32]));
+    let note = m.import("env", "note", void(vec![ValType::I32]));
+    m.functions.push(Function::new(
+        "run",
+        void(vec![ValType::I32]),
+        vec![],
+        vec![
+            Op::LocalGet(0),
+            Op::CallHost(add),
+            Op::I32Const(0),
+            Op::CallHost(note),
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("run".into(), 0));
+    m.data_segments.push((0, vec![9]));
+
+    let rt = rt();
+    let mut a = linker.instantiate(&rt, &m).unwrap();
+    let mut b = linker.instantiate(&rt, &m).unwrap();
+    a.set_data(Tally::default());
+    b.set_data(Tally::default());
+    a.call("run", &[Val::I32(3)]).unwrap();
+    a.call("run", &[Val::I32(4)]).unwrap();
+    b.call("run", &[Val::I32(10)]).unwrap();
+    assert_eq!(a.data::<Tally>().unwrap().sum, 7);
+    assert_eq!(b.data::<Tally>().unwrap().sum, 10);
+    assert_eq!(a.data::<Tally>().unwrap().seen, vec![9, 9]);
+
+    // Data of the wrong type is neither seen nor taken.
+    assert!(a.data::<u32>().is_none());
+    assert!(a.take_data::<u32>().is_none());
+    assert_eq!(a.take_data::<Tally>().unwrap().sum, 7);
+    assert_eq!(
+        a.call("run", &[Val::I32(1)]).unwrap_err(),
+        Trap::TypeMismatch
+    );
+}
+
+// ── Module serialization round-trip ──────────────────────────────────────────
+
+#[test]
+fn test_module_roundtrip() {
+    let m = single_func(
+        "add",
+        &[ValType::I32, ValType::I32],
+        Some(ValType::I32),
+        vec![Op::LocalGet(0), Op::LocalGet(1), Op::I32Add, Op::Return],
+    );
+
+    let bytes = m.to_bytes();
+    let m2 = Module::from_bytes(&bytes).expect("failed to deserialize");
+
+    let mut inst = rt().instantiate(&m2).unwrap();
+    assert_eq!(
+        inst.call("add", &[Val::I32(20), Val::I32(22)]).unwrap(),
+        Some(Val::I32(42))
+    );
+}
+
+#[test]
+fn test_module_string_table_and_const_pool() {
+    const BIG: i64 = 0x1122_3344_5566_7788;
+    let mut m = Module::new();
+    for i in 0..3 {
+        m.functions.push(func(
+            "generated_function_with_a_long_name",
+            vec![],
+            vec![ValType::I64],
+            vec![],
+            vec![
+                Op::I64Const(BIG + i),
+                Op::I64Const(BIG),
+                Op::I64Add,
+                Op::Return,
+            ],
+        ));
+    }
+    m.functions.push(func(
+        "pi",
+        vec![],
+        vec![ValType::F64],
+        vec![],
+        vec![
+            Op::F64Const(std::f64::consts::PI),
+            Op::F64Const(std::f64::consts::PI),
+            Op::F64Add,
+            Op::Return,
+        ],
+    ));
+    m.exports
+        .push(("generated_function_with_a_long_name".into(), 2));
+    m.exports.push(("pi".into(), 3));
+
+    let bytes = m.to_bytes();
+    let occurrences = bytes
+        .windows(b"generated_function_with_a_long_name".len())
+        .filter(|w| *w == b"generated_function_with_a_long_name")
+        .count();
+    assert_eq!(occurrences, 1);
+
+    let m2 = Module::from_bytes(&bytes).unwrap();
+    for (a, b) in m.functions.iter().zip(&m2.functions) {
+        assert_eq!(a.name, b.name);
+        assert_eq!(a.body, b.body);
+    }
+    let mut inst = rt().instantiate(&m2).unwrap();
+    assert_eq!(
+        inst.call("generated_function_with_a_long_name", &[])
+            .unwrap(),
+        Some(Val::I64(BIG * 2 + 2))
+    );
+    assert_eq!(
+        inst.call("pi", &[]).unwrap(),
+        Some(Val::F64(std::f64::consts::PI * 2.0))
+    );
+}
+
+#[test]
+fn test_module_reads_version_1() {
+    // Hand-assembled v1 module: k() -> i32 { 5 }, exported as "k".
+    let mut bytes = Vec::new();
+    bytes.extend_from_slice(b"RUNE");
+    for v in [1u32, 1, 0, 1] {
+        bytes.extend_from_slice(&v.to_le_bytes()); // version, pages, max, n_funcs
+    }
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.push(b'k');
+    bytes.extend_from_slice(&0u32.to_le_bytes()); // params
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.push(0x7F); // results: i32
+    bytes.extend_from_slice(&0u32.to_le_bytes()); // locals
+    bytes.extend_from_slice(&6u32.to_le_bytes());
+    bytes.extend_from_slice(&[0x80, 5, 0, 0, 0, 0x03]); // I32Const(5), Return
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.extend_from_slice(&1u32.to_le_bytes());
+    bytes.push(b'k');
+    bytes.extend_from_slice(&0u32.to_le_bytes()); // export -> func 0
+    bytes.extend_from_slice(&0u32.to_le_bytes()); // data segments
+
+    let m = Module::from_bytes(&bytes).unwrap();
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(inst.call("k", &[]).unwrap(), Some(Val::I32(5)));
+}
+
+#[test]
+fn test_module_bad_magic() {
+    let bytes = b"XXXX\x00\x00\x00\x00".to_vec();
+    assert!(Module::from_bytes(&bytes).is_err());
+}
+
+#[test]
+fn test_module_json_roundtrip() {
+    let mut m = Module::new();
+    m.max_memory_pages = Some(4);
+    m.functions.push(func(
+        "consts",
+        vec![ValType::I32],
+        vec![ValType::I64],
+        vec![ValType::F64],
+        vec![
+            Op::LocalGet(0),
+            Op::If(BlockType::Val(ValType::I64)),
+            Op::I64Const(i64::MIN),
+            Op::Else,
+            Op::F32Const(f32::NAN),
+            Op::Drop,
+            Op::F64Const(-0.1),
+            Op::LocalSet(1),
+            Op::I32Const(8),
+            Op::I64Load {
+                align: 3,
+                offset: 16,
+                memory: 0,
+            },
+            Op::End,
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("consts \"quoted\"".into(), 0));
+    m.data_segments.push((16, vec![1, 2, 3, 0xff]));
+    let mut sm = SourceMap::new();
+    let file = sm.add_file("consts.c");
+    sm.add_entry(
+        0,
+        2,
+        SourceLoc {
+            file,
+            line: 7,
+            column: 3,
+        },
+    );
+    m.source_map = Some(sm);
+
+    let json = m.to_json();
+    assert!(json.contains(r#"{ "op": "i64.load", "align": 3, "offset": 16 }"#));
+    let m2 = Module::from_json(&json).unwrap();
+    assert_eq!(m2.to_json(), json);
+    assert_eq!(m2.exports, m.exports);
+    assert_eq!(m2.data_segments, m.data_segments);
+    assert_eq!(m2.max_memory_pages, Some(4));
+    assert_eq!(m2.source_map, m.source_map);
+    let body = &m2.functions[0].body;
+    assert_eq!(body[2], Op::I64Const(i64::MIN));
+    assert!(matches!(body[4], Op::F32Const(v) if v.is_nan()));
+    assert_eq!(body[6], Op::F64Const(-0.1));
+
+    let mut inst = rt().instantiate(&m2).unwrap();
+    assert_eq!(
+        inst.call("consts \"quoted\"", &[Val::I32(1)]).unwrap(),
+        Some(Val::I64(i64::MIN))
+    );
+}
+
+#[test]
+fn test_module_json_errors() {
+    assert!(Module::from_json("{").is_err());
+    assert!(Module::from_json(r#"{"version": 2, "functions": []}"#).is_err());
+    let bad_op = r#"{"version": 1, "functions": [
+        {"name": "f", "params": [], "results": [], "body": [{"op": "i32.frobnicate"}]}
+    ]}"#;
+    match Module::from_json(bad_op) {
+        Err(Trap::InvalidModule(msg)) => assert!(msg.contains("i32.frobnicate"), "{msg}"),
+        other => panic!("expected InvalidModule, got {:?}", other.map(|_| ())),
+    }
+    let minimal = r#"{"version": 1, "functions": [
+        {"name": "f", "params": [], "results": ["i32"], "body": [
+            {"op": "i32.const", "value": 3}, {"op": "return"}
+        ]}
+    ], "exports": [{"name": "f", "function": 0}]}"#;
+    let m = Module::from_json(minimal).unwrap();
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(inst.call("f", &[]).unwrap(), Some(Val::I32(3)));
+}
+
+#[test]
+fn test_module_text_roundtrip() {
+    let mut m = Module::new();
+    m.max_memory_pages = Some(4);
+    m.imports.push(rune::module::Import {
+        module: "env".into(),
+        name: "log".into(),
+        ty: FuncType {
+            params: vec![ValType::I32],
+            results: vec![],
+        },
+    });
+    m.functions.push(func(
+        "consts",
+        vec![ValType::I32],
+        vec![ValType::I64],
+        vec![ValType::F64],
+        vec![
+            Op::LocalGet(0),
+            Op::If(BlockType::Val(ValType::I64)),
+            Op::I64Const(i64::MIN),
+            Op::Else,
+            Op::F32Const(f32::NAN),
+            Op::Drop,
+            Op::F64Const(-0.1),
+            Op::LocalSet(1),
+            Op::I32Const(8),
+            Op::I64Load {
+                align: 3,
+                offset: 16,
+                memory: 0,
+            },
+            Op::End,
+            Op::Return,
+        ],
+    ));
+    m.functions.push(func(
+        "two words",
+        vec![],
+        vec![ValType::I64],
+        vec![],
+        vec![Op::I32Const(1), Op::Call(0), Op::Return],
+    ));
+    m.exports.push(("consts \"quoted\"".into(), 0));
+    m.exports.push(("call".into(), 1));
+    m.data_segments.push((16, vec![1, 2, 3, 0xff, b'"']));
+
+    let text = m.to_text();
+    assert!(text.contains("i64.load offset=16 align=3"), "{text}");
+    assert!(text.contains("call consts"), "{text}");
+    assert!(text.contains(r#"func "two words" () -> (i64) {"#), "{text}");
+    let m2 = Module::from_text(&text).unwrap();
+    assert_eq!(m2.to_text(), text);
+    assert_eq!(m2.exports, m.exports);
+    assert_eq!(m2.imports, m.imports);
+    assert_eq!(m2.data_segments, m.data_segments);
+    assert_eq!(m2.max_memory_pages, Some(4));
+    let body = &m2.functions[0].body;
+    assert_eq!(body[2], Op::I64Const(i64::MIN));
+    assert!(matches!(body[4], Op::F32Const(v) if v.is_nan()));
+    assert_eq!(body[6], Op::F64Const(-0.1));
+}
+
+#[test]
+fn test_module_text_assembly() {
+    // Calls may name functions defined later; the final `end` is optional.
+    let text = "\
+;; Doubles the sum of 1..=n.
+func double (i32) -> (i32) {
+  local.get 0 call sum
+  i32.const 2
+  i32.mul
+}
+
+func sum (i32) -> (i32) {
+  local i32
+  block
+    loop
+      local.get 0
+      i32.eqz
+      br_if 1
+      local.get 1 local.get 0 i32.add local.set 1
+      local.get 0 i32.const 1 i32.sub local.set 0
+      br 0
+    end
+  end
+  local.get 1
+}
+export double
+export total sum
+";
+    let m = Module::from_text_with_source_map(text, "sum.runet").unwrap();
+    assert_eq!(m.functions[0].body[1], Op::Call(1));
+    assert_eq!(m.exports, vec![("double".into(), 0), ("total".into(), 1)]);
+    let sm = m.source_map.as_ref().unwrap();
+    assert_eq!(sm.files, vec!["sum.runet".to_string()]);
+    let loc = sm.lookup(1, 10).unwrap();
+    assert_eq!((loc.line, loc.column), (16, 7));
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(
+        inst.call("double", &[Val::I32(4)]).unwrap(),
+        Some(Val::I32(20))
+    );
+    assert_eq!(
+        inst.call("total", &[Val::I32(4)]).unwrap(),
+        Some(Val::I32(10))
+    );
+
+    let error = |text: &str| match Module::from_text(text) {
+        Err(Trap::InvalidModule(msg)) => msg,
+        other => panic!("expected InvalidModule, got {:?}", other.map(|_| ())),
+    };
+    assert_eq!(
+        error("func f () {\n  i32.frobnicate\n}"),
+        "line 2, column 3: unknown op `i32.frobnicate`"
+    );
+    assert_eq!(
+        error("func f () {\n  call g\n}"),
+        "line 2, column 8: no function named `g`"
+    );
+    assert_eq!(
+        error("memory 1\nexport f"),
+        "line 2, column 8: no function named `f`"
+    );
+    assert_eq!(
+        error("func f (i32 x) {}"),
+        "line 1, column 13: expected i32, i64, f32 or f64, found `x`"
+    );
+    assert_eq!(
+        error("func f () {\n  ext 0x10 0\n}"),
+        "line 2, column 7: expected an extension opcode, 0xe0 to 0xff"
+    );
+    assert_eq!(
+        error("func f () {\n  i32.const 1"),
+        "line 2, column 14: expected an op, found the end"
+    );
+    assert_eq!(
+        error("data 0 \"abc"),
+        "line 1, column 8: unterminated string"
+    );
+}
+
+#[test]
+fn test_memarg_align_out_of_range() {
+    use rune::verify::verify_module;
+
+    // An align of 64 or more would read as a memory index; it is written
+    // as the highest encodable value and the ops after it decode intact.
+    let load = |align| {
+        single_func(
+            "f",
+            &[],
+            Some(ValType::I32),
+            vec![
+                Op::I32Const(0),
+                Op::I32Load {
+                    align,
+                    offset: 4,
+                    memory: 0,
+                },
+                Op::Drop,
+                Op::I32Const(5),
+            ],
+        )
+    };
+    let m = load(64);
+    let body = Module::from_bytes(&m.to_bytes()).unwrap().functions[0]
+        .body
+        .clone();
+    assert_eq!(
+        body[1],
+        Op::I32Load {
+            align: 63,
+            offset: 4,
+            memory: 0,
+        }
+    );
+    assert_eq!(&body[2..], &[Op::Drop, Op::I32Const(5)]);
+    assert!(verify_module(&m, &RuntimeConfig::new()).is_err());
+
+    // The loaders refuse it outright.
+    let json = load(63).to_json().replace("\"align\": 63", "\"align\": 64");
+    match Module::from_json(&json) {
+        Err(Trap::InvalidModule(msg)) => assert!(msg.contains("align 64"), "{msg}"),
+        other => panic!("expected InvalidModule, got {:?}", other.map(|_| ())),
+    }
+    assert!(Module::from_text("func f () { i32.const 0 i32.load align=64 drop }").is_err());
+}
+
+#[test]
+fn test_module_digest() {
+    let build = |exports: &[(&str, u32)], k: i64| {
+        let mut m = Module::new();
+        for name in ["a", "b"] {
+            m.functions.push(func(
+                name,
+                vec![],
+                vec![ValType::I64],
+                vec![],
+                vec![Op::I64Const(k), Op::Return],
+            ));
+        }
+        for (name, idx) in exports {
+            m.exports.push((name.to_string(), *idx));
+        }
+        m.data_segments.push((0, b"data".to_vec()));
+        m
+    };
+    let m = build(&[("a", 0), ("b", 1)], 1 << 40);
+    let digest = m.digest();
+
+    // Stable across encodings and export order.
+    assert_eq!(Module::from_bytes(&m.to_bytes()).unwrap().digest(), digest);
+    assert_eq!(Module::from_json(&m.to_json()).unwrap().digest(), digest);
+    assert_eq!(build(&[("b", 1), ("a", 0)], 1 << 40).digest(), digest);
+
+    // Sensitive to anything that changes behaviour.
+    assert_ne!(build(&[("a", 1), ("b", 0)], 1 << 40).digest(), digest);
+    assert_ne!(build(&[("a", 0), ("b", 1)], 1 << 41).digest(), digest);
+    let mut grown = build(&[("a", 0), ("b", 1)], 1 << 40);
+    grown.initial_memory_pages = 2;
+    assert_ne!(grown.digest(), digest);
+    let mut featured = build(&[("a", 0), ("b", 1)], 1 << 40);
+    featured.required_features = Features::SIMD;
+    assert_ne!(featured.digest(), digest);
+}
+
+#[test]
+fn test_module_digest_pinned() {
+    // `rune-digest-v1` digests must never change: caches and signatures
+    // key on them. This module's digest dates from the first version.
+    let mut m = Module::new();
+    m.max_memory_pages = Some(2);
+    m.functions.push(func(
+        "add",
+        vec![ValType::I32, ValType::I32],
+        vec![ValType::I32],
+        vec![ValType::I64],
+        vec![Op::LocalGet(0), Op::LocalGet(1), Op::I32Add],
+    ));
+    m.exports.push(("add".into(), 0));
+    m.data_segments.push((8, b"rune".to_vec()));
+    assert_eq!(
+        rune::hash::to_hex(&m.digest()),
+        "a71cf81afae69456a990c83fe57a5a2eac3014d8546371c706bfb03910a23449"
+    );
+}
+
+#[test]
+fn test_module_interface_probe() {
+    let mut m = Module::new();
+    m.max_memory_pages = Some(8);
+    m.required_features = Features::SIMD;
+    m.functions.push(func(
+        "helper",
+        vec![],
+        vec![],
+        vec![],
+        vec![Op::Nop, Op::Return],
+    ));
+    m.functions.push(func(
+        "area",
+        vec![ValType::F64, ValType::F64],
+        vec![ValType::F64],
+        vec![],
+        vec![Op::LocalGet(0), Op::LocalGet(1), Op::F64Mul, Op::Return],
+    ));
+    m.exports.push(("area".into(), 1));
+    let bytes = m.to_bytes();
+
+    // Succeeds even though this runtime could not load the module.
+    let iface = Module::interface(&bytes).unwrap();
+    assert_eq!(iface.max_memory_pages, Some(8));
+    assert_eq!(iface.required_features, Features::SIMD);
+    assert_eq!(iface.functions.len(), 2);
+    assert_eq!(iface.functions[0].0, "helper");
+    assert_eq!(iface.exports.len(), 1);
+    assert_eq!(iface.exports[0].name, "area");
+    assert_eq!(iface.exports[0].func_index, 1);
+    assert_eq!(iface.exports[0].ty.params, vec![ValType::F64, ValType::F64]);
+    assert_eq!(iface.exports[0].ty.results, vec![ValType::F64]);
+
+    // Bodies are skipped, not decoded: an invalid opcode goes unnoticed.
+    // Encoding the module again with one op swapped finds that op's byte.
+    m.required_features = Features::NONE;
+    let mut corrupt = m.to_bytes();
+    let mut swapped = m.clone();
+    std::sync::Arc::make_mut(&mut swapped.functions[1].body)[2] = Op::F64Add;
+    let swapped = swapped.to_bytes();
+    assert_eq!(swapped.len(), corrupt.len());
+    let op = corrupt
+        .iter()
+        .zip(&swapped)
+        .position(|(a, b)| a != b)
+        .unwrap();
+    corrupt[op] = 0x7E;
+    assert!(Module::from_bytes(&corrupt).is_err());
+    assert!(Module::interface(&corrupt).is_ok());
+    assert!(Module::interface(&bytes[..bytes.len() - 6]).is_err());
+}
+
+#[test]
+fn test_multi_memory_feature_implied() {
+    let store_to = |memory| {
+        single_func(
+            "f",
+            &[],
+            None,
+            vec![
+                Op::I32Const(0),
+                Op::I32Const(1),
+                Op::I32Store {
+                    align: 2,
+                    offset: 0,
+                    memory,
+                },
+            ],
+        )
+    };
+    let m = store_to(1);
+    assert_eq!(m.required_features, Features::NONE);
+    // Written into the header, so older runtimes reject it up front, and
+    // added by every loader.
+    let bytes = m.to_bytes();
+    assert_eq!(
+        Module::interface(&bytes).unwrap().required_features,
+        Features::MULTI_MEMORY
+    );
+    assert_eq!(
+        Module::from_bytes(&bytes).unwrap().required_features,
+        Features::MULTI_MEMORY
+    );
+    assert_eq!(
+        Module::from_json(&m.to_json()).unwrap().required_features,
+        Features::MULTI_MEMORY
+    );
+    assert!(Features::SUPPORTED.contains(Features::MULTI_MEMORY));
+
+    let m = store_to(0);
+    let bytes = m.to_bytes();
+    assert_eq!(
+        Module::from_bytes(&bytes).unwrap().required_features,
+        Features::NONE
+    );
+}
+
+#[test]
+fn test_module_required_features() {
+    let mut m = single_func(
+        "f",
+        &[],
+        Some(ValType::I32),
+        vec![Op::I32Const(1), Op::Return],
+    );
+    m.required_features = Features::SIMD | Features::from_bits(1 << 40);
+
+    let expected = Trap::UnsupportedFeature("simd, bit 40".into());
+    assert_eq!(
+        Module::from_bytes(&m.to_bytes()).err(),
+        Some(expected.clone())
+    );
+    assert_eq!(rt().instantiate(&m).err(), Some(expected));
+    assert!(Module::from_json(&m.to_json()).is_err());
+
+    m.required_features = Features::NONE;
+    let m2 = Module::from_bytes(&m.to_bytes()).unwrap();
+    let mut inst = rt().instantiate(&m2).unwrap();
+    assert_eq!(inst.call("f", &[]).unwrap(), Some(Val::I32(1)));
+}
+
+#[test]
+fn test_host_api_compat() {
+    use rune::compat::{ApiVersion, HostApi, HostRequirements};
+
+    let mut m = single_func(
+        "answer",
+        &[],
+        Some(ValType::I32),
+        vec![Op::I32Const(42), Op::Return],
+    );
+    m.host_requirements = HostRequirements {
+        api_version: Some(ApiVersion::new(2, 3)),
+        capabilities: vec!["fs".into(), "net".into()],
+    };
+
+    // Carried by the binary and JSON forms, and visible without decoding.
+    let bytes = m.to_bytes();
+    let decoded = Module::from_bytes(&bytes).unwrap();
+    assert_eq!(decoded.host_requirements, m.host_requirements);
+    assert_eq!(decoded.digest(), m.digest());
+    assert_eq!(
+        Module::interface(&bytes).unwrap().host_requirements,
+        m.host_requirements
+    );
+    let json = Module::from_json(&m.to_json()).unwrap();
+    assert_eq!(json.host_requirements, m.host_requirements);
+    assert_ne!(m.digest(), {
+        let mut plain = m.clone();
+        plain.host_requirements = HostRequirements::default();
+        plain.digest()
+    });
+
+    // A host declaring nothing meets no requirement.
+    let report = rt().check_compat(&m);
+    assert!(!report.is_compatible());
+    assert_eq!(
+        report.to_string(),
+        "module requires host API 2.3 (host declares no version) and host capabilities fs, net"
+    );
+    assert!(matches!(
+        rt().instantiate(&m).err(),
+        Some(Trap::UnsupportedFeature(_))
+    ));
+
+    let host = |major, minor, caps: &[&str]| {
+        let mut api = HostApi::new(ApiVersion::new(major, minor));
+        for cap in caps {
+            api.provide(*cap);
+        }
+        let mut config = RuntimeConfig::new();
+        config.set_host_api(api);
+        Runtime::with_config(config)
+    };
+    let older = host(2, 2, &["fs", "net"]).check_compat(&m);
+    assert!(!older.version_compatible());
+    assert!(older.missing_capabilities.is_empty());
+    assert!(!host(3, 5, &["fs", "net"]).check_compat(&m).is_compatible());
+    let partial = host(2, 3, &["fs"]).check_compat(&m);
+    assert!(partial.version_compatible());
+    assert_eq!(partial.missing_capabilities, ["net"]);
+
+    let newer = host(2, 7, &["fs", "net", "gpu"]);
+    assert!(newer.check_compat(&m).is_compatible());
+    let mut inst = newer.instantiate(&m).unwrap();
+    assert_eq!(inst.call("answer", &[]).unwrap(), Some(Val::I32(42)));
+
+    // Modules that declare nothing run anywhere.
+    assert!(rt().check_compat(&read_word_module()).is_compatible());
+
+    // Merging combines requirements, but not across major versions.
+    let mut other = single_func("other", &[], None, vec![Op::Return]);
+    other.host_requirements.api_version = Some(ApiVersion::new(2, 5));
+    other.host_requirements.capabilities = vec!["net".into(), "gpu".into()];
+    let merged = m.clone().merge(other, CollisionPolicy::Error).unwrap();
+    assert_eq!(
+        merged.host_requirements.api_version,
+        Some(ApiVersion::new(2, 5))
+    );
+    assert_eq!(merged.host_requirements.capabilities, ["fs", "net", "gpu"]);
+    let mut v1 = single_func("v1", &[], None, vec![Op::Return]);
+    v1.host_requirements.api_version = Some(ApiVersion::new(1, 0));
+    assert!(matches!(
+        m.merge(v1, CollisionPolicy::Error).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+}
+
+#[test]
+fn test_load_cached() {
+    use std::sync::Arc;
+
+    let a = read_word_module().to_bytes();
+    let b = single_func("f", &[], None, vec![]).to_bytes();
+    let rt = rt();
+    let first = rt.load_cached(&a).unwrap();
+    assert!(Arc::ptr_eq(&first, &rt.load_cached(&a).unwrap()));
+    assert!(!Arc::ptr_eq(&first, &rt.load_cached(&b).unwrap()));
+    assert_eq!(rt.cached_modules(), 2);
+    let mut inst = rt.instantiate_owned(first).unwrap();
+    assert_eq!(
+        inst.call("read", &[Val::I32(0)]).unwrap(),
+        Some(Val::I32(0))
+    );
+    assert!(rt.load_cached(&a[..a.len() - 1]).is_err());
+    rt.clear_module_cache();
+    assert_eq!(rt.cached_modules(), 0);
+
+    // Room for one module only: loading another evicts it.
+    let mut config = RuntimeConfig::new();
+    config.set_module_cache_size(a.len().max(b.len()));
+    let rt = Runtime::with_config(config);
+    let first = rt.load_cached(&a).unwrap();
+    rt.load_cached(&b).unwrap();
+    assert_eq!(rt.cached_modules(), 1);
+    assert!(!Arc::ptr_eq(&first, &rt.load_cached(&a).unwrap()));
+
+    let mut config = RuntimeConfig::new();
+    config.set_module_cache_size(0);
+    let rt = Runtime::with_config(config);
+    rt.load_cached(&a).unwrap();
+    assert_eq!(rt.cached_modules(), 0);
+}
+
+#[test]
+fn test_load_cached_reuses_prepared() {
+    use rune::linker::Linker;
+    use std::sync::Arc;
+
+    let bytes = read_word_module().to_bytes();
+    let rt = rt();
+    let first = rt
+        .instantiate_owned(rt.load_cached(&bytes).unwrap())
+        .unwrap();
+    // A cache hit shares the functions the first instance prepared, through
+    // the runtime or a linker.
+    let module = rt.load_cached(&bytes).unwrap();
+    let second = rt.instantiate(&module).unwrap();
+    let linked = Linker::new().instantiate(&rt, &module).unwrap();
+    assert!(Arc::ptr_eq(first.prepared(), second.prepared()));
+    assert!(Arc::ptr_eq(first.prepared(), linked.prepared()));
+
+    // Modules from outside the cache are prepared per instance.
+    let decoded = Module::from_bytes(&bytes).unwrap();
+    let a = rt.instantiate(&decoded).unwrap();
+    let b = rt.instantiate(&decoded).unwrap();
+    assert!(!Arc::ptr_eq(a.prepared(), b.prepared()));
+
+    // Clearing the cache drops them with the module.
+    rt.clear_module_cache();
+    let reloaded = rt
+        .instantiate_owned(rt.load_cached(&bytes).unwrap())
+        .unwrap();
+    assert!(!Arc::ptr_eq(first.prepared(), reloaded.prepared()));
+}
+
+#[test]
+fn test_load_embedded() {
+    use rune::embed::EmbeddedModule;
+    use std::sync::Arc;
+
+    let bytes: &'static [u8] = read_word_module().to_bytes().leak();
+    static BOGUS: EmbeddedModule = EmbeddedModule::new(b"not a module", None);
+    let embedded = EmbeddedModule::new(bytes, None);
+    assert_eq!(embedded.bytes(), bytes);
+    assert!(embedded.code().is_none());
+    // Decoded once, whichever runtime loads it.
+    let first = rt().load_embedded(&embedded).unwrap();
+    assert!(Arc::ptr_eq(&first, &rt().load_embedded(&embedded).unwrap()));
+    let mut inst = rt().instantiate_owned(first).unwrap();
+    assert_eq!(
+        inst.call("read", &[Val::I32(0)]).unwrap(),
+        Some(Val::I32(0))
+    );
+    assert!(matches!(
+        rt().load_embedded(&BOGUS),
+        Err(Trap::InvalidModule(_))
+    ));
+}
+
+#[test]
+fn test_load_dir() {
+    let dir = std::env::temp_dir().join(format!("rune-plugins-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let answer = single_func(
+        "answer",
+        &[],
+        Some(ValType::I32),
+        vec![Op::I32Const(42), Op::Return],
+    );
+    std::fs::write(dir.join("b.rune"), answer.to_bytes()).unwrap();
+    std::fs::write(dir.join("a.rune"), read_word_module().to_bytes()).unwrap();
+    std::fs::write(dir.join("notes.txt"), b"not a plugin").unwrap();
+
+    let rt = rt();
+    let plugins = rt.load_dir(&dir).unwrap();
+    let names: Vec<_> = plugins.iter().map(|p| p.name()).collect();
+    assert_eq!(names, ["a", "b"]);
+    assert_eq!(plugins[1].interface().exports[0].name, "answer");
+    assert!(!plugins[1].is_loaded());
+    assert_eq!(rt.cached_modules(), 0);
+
+    let mut inst = plugins[1].instantiate().unwrap();
+    assert_eq!(inst.call("answer", &[]).unwrap(), Some(Val::I32(42)));
+    assert!(plugins[1].is_loaded());
+    assert!(!plugins[0].is_loaded());
+    assert_eq!(rt.cached_modules(), 1);
+
+    // A file that is not a module fails the scan, naming it.
+    std::fs::write(dir.join("c.rune"), b"garbage").unwrap();
+    match rt.load_dir(&dir).err() {
+        Some(Trap::InvalidModule(msg)) => assert!(msg.contains("c.rune"), "{msg}"),
+        other => panic!("unexpected {other:?}"),
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(matches!(rt.load_dir(&dir).err(), Some(Trap::HostError(_))));
+}
+
+// ── Module merging ────────────────────────────────────────────────────────────
+
+fn merge_fragment(export: &str, value: i32) -> Module {
+    // func[0] = value(), func[1] = <export>() = value() via an internal call
+    let mut m = Module::new();
+    m.functions.push(func(
+        "value",
+        vec![],
+        vec![ValType::I32],
+        vec![],
+        vec![Op::I32Const(value), Op::Return],
+    ));
+    m.functions.push(func(
+        export,
+        vec![],
+        vec![ValType::I32],
+        vec![],
+        vec![Op::Call(0), Op::Return],
+    ));
+    m.exports.push((export.into(), 1));
+    m
+}
+
+#[test]
+fn test_merge_relocates_calls() {
+    let a = merge_fragment("a", 1);
+    let mut b = merge_fragment("b", 2);
+    b.initial_memory_pages = 3;
+    let m = a.merge(b, CollisionPolicy::Error).unwrap();
+
+    assert_eq!(m.functions.len(), 4);
+    assert_eq!(m.find_export("b"), Some(3));
+    assert_eq!(m.initial_memory_pages, 3);
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(inst.call("a", &[]).unwrap(), Some(Val::I32(1)));
+    assert_eq!(inst.call("b", &[]).unwrap(), Some(Val::I32(2)));
+}
+
+#[test]
+fn test_merge_relocates_host_calls() {
+    use rune::Linker;
+
+    let nullary = FuncType {
+        params: vec![],
+        results: vec![ValType::I32],
+    };
+    let mut a = Module::new();
+    a.import("env", "one", nullary.clone());
+    let mut b = Module::new();
+    b.import("env", "two", nullary.clone());
+    b.functions.push(func(
+        "two",
+        vec![],
+        vec![ValType::I32],
+        vec![],
+        vec![Op::CallHost(0), Op::Return],
+    ));
+    b.exports.push(("two".into(), 0));
+
+    let m = a.merge(b, CollisionPolicy::Error).unwrap();
+    let mut linker = Linker::new();
+    linker
+        .func("env", "one", nullary.clone(), |_| Ok(Some(Val::I32(1))))
+        .unwrap()
+        .func("env", "two", nullary, |_| Ok(Some(Val::I32(2))))
+        .unwrap();
+    let mut inst = linker.instantiate(&rt(), &m).unwrap();
+    assert_eq!(inst.call("two", &[]).unwrap(), Some(Val::I32(2)));
+}
+
+#[test]
+fn test_merge_collision_policies() {
+    let err = merge_fragment("f", 1)
+        .merge(merge_fragment("f", 2), CollisionPolicy::Error)
+        .err()
+        .unwrap();
+    assert!(matches!(err, Trap::InvalidModule(_)));
+
+    let m = merge_fragment("f", 1)
+        .merge(merge_fragment("f", 2), CollisionPolicy::KeepFirst)
+        .unwrap();
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(inst.call("f", &[]).unwrap(), Some(Val::I32(1)));
+
+    let m = merge_fragment("f", 1)
+        .merge(merge_fragment("f", 2), CollisionPolicy::KeepSecond)
+        .unwrap();
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(inst.call("f", &[]).unwrap(), Some(Val::I32(2)));
+
+    let m = merge_fragment("f", 1)
+        .merge(
+            merge_fragment("f", 2),
+            CollisionPolicy::Prefix("other_".into()),
+        )
+        .unwrap();
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(inst.call("f", &[]).unwrap(), Some(Val::I32(1)));
+    assert_eq!(inst.call("other_f", &[]).unwrap(), Some(Val::I32(2)));
+}
+
+// ── Source maps ───────────────────────────────────────────────────────────────
+
+#[test]
+fn test_source_map_roundtrip_and_backtrace() {
+    // func[0] = div(a, b) = a / b;  func[1] = outer(a, b) = div(a, b)
+    let mut m = Module::new();
+    m.functions.push(func(
+        "div",
+        vec![ValType::I32, ValType::I32],
+        vec![ValType::I32],
+        vec![],
+        vec![Op::LocalGet(0), Op::LocalGet(1), Op::I32DivS, Op::Return],
+    ));
+    m.functions.push(func(
+        "outer",
+        vec![ValType::I32, ValType::I32],
+        vec![ValType::I32],
+        vec![],
+        vec![Op::LocalGet(0), Op::LocalGet(1), Op::Call(0), Op::Return],
+    ));
+    m.exports.push(("outer".into(), 1));
+
+    let mut sm = SourceMap::new();
+    let file = sm.add_file("math.c");
+    let at = |line| SourceLoc {
+        file,
+        line,
+        column: 5,
+    };
+    sm.add_entry(0, 0, at(2));
+    sm.add_entry(0, 2, at(3));
+    sm.add_entry(1, 0, at(7));
+    m.source_map = Some(sm);
+
+    let m2 = Module::from_bytes(&m.to_bytes()).unwrap();
+    assert_eq!(m2.source_map, m.source_map);
+
+    let mut inst = rt().instantiate(&m2).unwrap();
+    assert_eq!(
+        inst.call("outer", &[Val::I32(1), Val::I32(0)]).unwrap_err(),
+        Trap::DivisionByZero
+    );
+    let bt = inst.trap_backtrace();
+    assert_eq!(bt.len(), 2);
+    assert_eq!((bt[0].func_index, bt[0].op_index), (0, 2));
+    assert_eq!(bt[0].loc, Some(at(3)));
+    assert_eq!((bt[1].func_index, bt[1].op_index), (1, 2));
+    assert_eq!(bt[1].loc, Some(at(7)));
+
+    // A successful call clears the previous backtrace.
+    inst.call("outer", &[Val::I32(4), Val::I32(2)]).unwrap();
+    assert!(inst.trap_backtrace().is_empty());
+}
+
+#[test]
+fn test_module_without_source_map_skips_section() {
+    let m = single_func("f", &[], None, vec![Op::Return]);
+    let bytes = m.to_bytes();
+    assert!(Module::from_bytes(&bytes).unwrap().source_map.is_none());
+
+    // Unknown trailing sections are ignored by the reader.
+    let mut extended = bytes.clone();
+    extended.push(0x7F);
+    extended.extend_from_slice(&3u32.to_le_bytes());
+    extended.extend_from_slice(b"abc");
+    assert!(Module::from_bytes(&extended).is_ok());
+}
+
+// ── Conversions ───────────────────────────────────────────────────────────────
+
+#[test]
+fn test_i64_extend_i32_s() {
+    let m = single_func(
+        "ext",
+        &[ValType::I32],
+        Some(ValType::I64),
+        vec![Op::LocalGet(0), Op::I64ExtendI32S, Op::Return],
+    );
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(
+        inst.call("ext", &[Val::I32(-1)]).unwrap(),
+        Some(Val::I64(-1))
+    );
+}
+
+#[test]
+fn test_f64_promote_f32() {
+    let m = single_func(
+        "prom",
+        &[ValType::F32],
+        Some(ValType::F64),
+        vec![Op::LocalGet(0), Op::F64PromoteF32, Op::Return],
+    );
+    let mut inst = rt().instantiate(&m).unwrap();
+    if let Some(Val::F64(v)) = inst.call("prom", &[Val::F32(1.5)]).unwrap() {
+        assert!((v - 1.5).abs() < 1e-12);
+    } else {
+        panic!();
+    }
+}
+
+// ── Owned instances and pools ─────────────────────────────────────────────────
+
+#[test]
+fn test_instantiate_owned() {
+    use std::sync::Arc;
+
+    struct Service {
+        inst: rune::OwnedInstance,
+    }
+
+    let module = Arc::new(single_func(
+        "answer",
+        &[],
+        Some(ValType::I32),
+        vec![Op::I32Const(42), Op::Return],
+    ));
+    let rt = rt();
+    let mut service = Service {
+        inst: rt.instantiate_owned(module.clone()).unwrap(),
+    };
+    // Instances of one module share it; the caller's handle can go.
+    let other = rt.instantiate_owned(module.clone()).unwrap();
+    assert_eq!(Arc::strong_count(&module), 3);
+    drop(module);
+
+    let handle = std::thread::spawn(move || {
+        let mut other = other;
+        other.call("answer", &[]).unwrap()
+    });
+    assert_eq!(handle.join().unwrap(), Some(Val::I32(42)));
+    assert_eq!(
+        service.inst.call("answer", &[]).unwrap(),
+        Some(Val::I32(42))
+    );
+}
+
+#[test]
+fn test_thread_safety() {
+    use rune::{image::MemoryImage, pool::InstancePool, InstancePre, Linker, OwnedInstance};
+
+    fn send<T: Send>() {}
+    fn send_sync<T: Send + Sync>() {}
+    send_sync::<Module>();
+    send_sync::<Runtime>();
+    send_sync::<RuntimeConfig>();
+    send_sync::<Linker>();
+    send_sync::<InstancePre<'static>>();
+    send_sync::<rune::instance::PreparedModule>();
+    send_sync::<rune::plugin::Plugin>();
+    send_sync::<rune::cancel::CancellationToken>();
+    send_sync::<InstancePool>();
+    send_sync::<MemoryImage>();
+    send_sync::<Memory>();
+    send::<rune::Instance<'static>>();
+    send::<OwnedInstance>();
+
+    // Each worker borrows the shared module and runs its own instance.
+    let m = read_word_module();
+    let rt = rt();
+    std::thread::scope(|s| {
+        let workers: Vec<_> = (0..4)
+            .map(|i| {
+                let (m, rt) = (&m, &rt);
+                s.spawn(move || {
+                    let mut inst = rt.instantiate(m).unwrap();
+                    inst.memory_mut().write_u32(0, i).unwrap();
+                    inst.call("read", &[Val::I32(0)]).unwrap()
+                })
+            })
+            .collect();
+        for (i, w) in workers.into_iter().enumerate() {
+            assert_eq!(w.join().unwrap(), Some(Val::I32(i as i32)));
+        }
+    });
+
+    // An instance created on one thread can be called on another.
+    let mut inst = rt.instantiate(&m).unwrap();
+    inst.memory_mut().write_u32(0, 5).unwrap();
+    let word = std::thread::scope(|s| s.spawn(|| inst.call("read", &[Val::I32(0)])).join());
+    assert_eq!(word.unwrap().unwrap(), Some(Val::I32(5)));
+}
+
+#[test]
+fn test_instance_pool() {
+    use rune::pool::InstancePool;
+    use std::sync::Arc;
+
+    let mut m = read_word_module();
+    m.data_segments.push((0, vec![1, 0, 0, 0]));
+    let module = Arc::new(m);
+    let pool = InstancePool::new(&rt(), module.clone(), 2).unwrap();
+    assert_eq!(pool.idle(), 2);
+
+    // A returned instance comes back as freshly instantiated.
+    let mut inst = pool.checkout().unwrap();
+    inst.memory_mut().write_u32(0, 77).unwrap();
+    inst.memory_mut().grow(1).unwrap();
+    inst.add_memory(Memory::new(1, None));
+    assert_eq!(
+        inst.call("read", &[Val::I32(0)]).unwrap(),
+        Some(Val::I32(77))
+    );
+    pool.checkin(inst).unwrap();
+    let mut inst = pool.checkout().unwrap();
+    let mut again = pool.checkout().unwrap();
+    assert_eq!(
+        inst.call("read", &[Val::I32(0)]).unwrap(),
+        Some(Val::I32(1))
+    );
+    assert_eq!(inst.memory().pages(), 1);
+    assert_eq!(inst.memory_count(), 1);
+
+    // An empty pool instantiates on demand but keeps at most its capacity.
+    assert_eq!(pool.idle(), 0);
+    let extra = pool.checkout().unwrap();
+    assert_eq!(
+        again.call("read", &[Val::I32(0)]).unwrap(),
+        Some(Val::I32(1))
+    );
+    for inst in [inst, again, extra] {
+        pool.checkin(inst).unwrap();
+    }
+    assert_eq!(pool.idle(), 2);
+
+    // Only the pool's own module is accepted back.
+    let stranger = rt()
+        .instantiate_owned(Arc::new(read_word_module()))
+        .unwrap();
+    assert!(matches!(
+        pool.checkin(stranger).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+
+    // Nor is one whose code was patched: the next borrower gets the
+    // module's own functions, not code reading a memory it lacks.
+    let mut patched = pool.checkout().unwrap();
+    patched.add_memory(Memory::new(1, None));
+    let mut read_second = read_word_module().functions.remove(0);
+    read_second.body = Arc::new(vec![
+        Op::LocalGet(0),
+        Op::I32Load {
+            align: 2,
+            offset: 0,
+            memory: 1,
+        },
+        Op::Return,
+    ]);
+    patched.replace_function("read", read_second).unwrap();
+    let idle = pool.idle();
+    assert!(matches!(
+        pool.checkin(patched).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+    assert_eq!(pool.idle(), idle);
+    let mut inst = pool.checkout().unwrap();
+    assert_eq!(
+        inst.call("read", &[Val::I32(0)]).unwrap(),
+        Some(Val::I32(1))
+    );
+    pool.checkin(inst).unwrap();
+
+    // The pool can be shared between threads.
+    let pool = Arc::new(pool);
+    let workers: Vec<_> = (0..4)
+        .map(|_| {
+            let pool = pool.clone();
+            std::thread::spawn(move || {
+                let mut inst = pool.checkout().unwrap();
+                let word = inst.call("read", &[Val::I32(0)]).unwrap();
+                pool.checkin(inst).unwrap();
+                word
+            })
+        })
+        .collect();
+    for worker in workers {
+        assert_eq!(worker.join().unwrap(), Some(Val::I32(1)));
+    }
+}
+
+#[test]
+fn test_instance_pool_with_linker() {
+    use rune::{pool::InstancePool, Linker};
+    use std::sync::Arc;
+
+    let ty = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
+    let mut m = Module::new();
+    m.import("env", "double", ty.clone());
+    m.functions.push(Function::new(
+        "run",
+        ty.clone(),
+        vec![],
+        vec![Op::LocalGet(0), Op::CallHost(0), Op::Return],
+    ));
+    m.exports.push(("run".into(), 0));
+    let module = Arc::new(m);
+
+    // Without a linker the import cannot be bound.
+    assert!(matches!(
+        InstancePool::new(&rt(), module.clone(), 1).err(),
+        Some(Trap::UndefinedImport(_))
+    ));
+
+    let mut linker = Linker::new();
+    linker
+        .func("env", "double", ty, |args| {
+            Ok(Some(Val::I32(args[0].as_i32().unwrap() * 2)))
+        })
+        .unwrap();
+    let pool = InstancePool::with_linker(&rt(), &linker, module, 1).unwrap();
+    assert_eq!(pool.idle(), 1);
+    let mut inst = pool.checkout().unwrap();
+    assert_eq!(
+        inst.call("run", &[Val::I32(21)]).unwrap(),
+        Some(Val::I32(42))
+    );
+    pool.checkin(inst).unwrap();
+
+    // Instances created on demand are bound the same way.
+    let mut first = pool.checkout().unwrap();
+    let mut second = pool.checkout().unwrap();
+    assert_eq!(
+        first.call("run", &[Val::I32(1)]).unwrap(),
+        Some(Val::I32(2))
+    );
+    assert_eq!(
+        second.call("run", &[Val::I32(5)]).unwrap(),
+        Some(Val::I32(10))
+    );
+}
+
+// ── Metrics ───────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_runtime_metrics() {
+    use std::sync::{Arc, Mutex};
+
+    let mut m = single_func(
+        "div",
+        &[ValType::I32, ValType::I32],
+        Some(ValType::I32),
+        vec![Op::LocalGet(0), Op::LocalGet(1), Op::I32DivS, Op::Return],
+    );
+    m.initial_memory_pages = 1;
+
+    let exported = Arc::new(Mutex::new(Vec::new()));
+    let sink = exported.clone();
+    let mut config = RuntimeConfig::new();
+    config.set_metrics_exporter(2, move |metrics| {
+        sink.lock().unwrap().push(metrics.calls);
+    });
+    let rt = Runtime::with_config(config);
+
+    let mut a = rt.instantiate(&m).unwrap();
+    let b = rt.instantiate(&m).unwrap();
+    assert_eq!(
+        a.call("div", &[Val::I32(6), Val::I32(3)]),
+        Ok(Some(Val::I32(2)))
+    );
+    assert_eq!(
+        a.call("div", &[Val::I32(1), Val::I32(0)]),
+        Err(Trap::DivisionByZero)
+    );
+    a.call("div", &[Val::I32(1), Val::I32(0)]).unwrap_err();
+    // Not a call: there is no such export.
+    a.call("nope", &[]).unwrap_err();
+    drop(b);
+
+    let metrics = rt.metrics();
+    assert_eq!(metrics.instantiations, 2);
+    assert_eq!(metrics.active_instances, 1);
+    assert_eq!(metrics.calls, 3);
+    assert_eq!(metrics.traps.get("division_by_zero"), Some(&2));
+    assert_eq!(metrics.traps.len(), 1);
+    assert_eq!(metrics.total_traps(), 2);
+    assert_eq!(metrics.memory.current_bytes, 65_536);
+    assert_eq!(*exported.lock().unwrap(), vec![2]);
+
+    // Instances created outside a runtime report nowhere.
+    let mut lone = rune::Instance::new(&m).unwrap();
+    lone.call("div", &[Val::I32(4), Val::I32(2)]).unwrap();
+    assert_eq!(rt.metrics().calls, 3);
+    assert_eq!(Trap::OutOfBounds.kind(), "out_of_bounds");
+}
+
+#[test]
+fn test_event_hooks() {
+    use rune::{events::Event, Linker};
+    use std::sync::{Arc, Mutex};
+
+    // inv(x) = 1 / env.check(x), where check fails on negatives.
+    let mut m = Module::new();
+    let check = m.import(
+        "env",
+        "check",
+        FuncType {
+            params: vec![ValType::I32],
+            results: vec![ValType::I32],
+        },
+    );
+    m.functions.push(Function::new(
+        "inv",
+        FuncType {
+            params: vec![ValType::I32],
+            results: vec![ValType::I32],
+        },
+        vec![],
+        vec![
+            Op::I32Const(1),
+            Op::LocalGet(0),
+            Op::CallHost(check),
+            Op::I32DivS,
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("inv".into(), 0));
+
+    let log = Arc::new(Mutex::new(Vec::new()));
+    let sink = log.clone();
+    let mut config = RuntimeConfig::new();
+    config.add_event_hook(move |event| {
+        let line = match event {
+            Event::Instantiated { module, .. } => format!("new {}", module.functions.len()),
+            Event::CallStart { func } => format!("call {func}"),
+            Event::CallEnd { func, trapped, .. } => format!("end {func} {trapped}"),
+            Event::HostCallStart { import } => format!("host {import}"),
+            Event::HostCallEnd {
+                import, trapped, ..
+            } => format!("host end {import} {trapped}"),
+            Event::Trap { func, trap } => format!("trap {func} {}", trap.kind()),
+            Event::TierUp { func, tier } => format!("tier {func} {tier:?}"),
+        };
+        sink.lock().unwrap().push(line);
+    });
+    let rt = Runtime::with_config(config);
+    let mut linker = Linker::new();
+    linker
+        .func(
+            "env",
+            "check",
+            FuncType {
+                params: vec![ValType::I32],
+                results: vec![ValType::I32],
+            },
+            |args| match args[0] {
+                Val::I32(x) if x < 0 => Err(Trap::HostError("negative".into())),
+                v => Ok(Some(v)),
+            },
+        )
+        .unwrap();
+    let mut inst = linker.instantiate(&rt, &m).unwrap();
+    assert_eq!(inst.call("inv", &[Val::I32(1)]), Ok(Some(Val::I32(1))));
+    inst.call("inv", &[Val::I32(0)]).unwrap_err();
+    inst.call("inv", &[Val::I32(-1)]).unwrap_err();
+
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            "new 1",
+            "call inv",
+            "host env.check",
+            "host end env.check false",
+            "end inv false",
+            "call inv",
+            "host env.check",
+            "host end env.check false",
+            "trap inv division_by_zero",
+            "end inv true",
+            "call inv",
+            "host env.check",
+            "host end env.check true",
+            "trap inv host_error",
+            "end inv true",
+        ]
+    );
+}
+
+// ── Epoch interruption ────────────────────────────────────────────────────────
+
+fn spin_module() -> Module {
+    // spin(): loop { br 0 }
+    single_func(
+        "spin",
+        &[],
+        None,
+        vec![Op::Loop(BlockType::Empty), Op::Br(0), Op::End, Op::Return],
+    )
+}
+
+#[test]
+fn test_epoch_deadline() {
+    let m = spin_module();
+    let rt = rt();
+    let mut inst = rt.instantiate(&m).unwrap();
+    inst.set_epoch_deadline(1);
+    rt.increment_epoch();
+    assert_eq!(rt.epoch(), 1);
+    assert_eq!(inst.call("spin", &[]), Err(Trap::Interrupted));
+    // Still past the deadline until it is moved.
+    assert_eq!(inst.call("spin", &[]), Err(Trap::Interrupted));
+
+    let add = single_func(
+        "add1",
+        &[ValType::I32],
+        Some(ValType::I32),
+        vec![Op::LocalGet(0), Op::I32Const(1), Op::I32Add, Op::Return],
+    );
+    let mut straight = rt.instantiate(&add).unwrap();
+    straight.set_epoch_deadline(0);
+    // No loop or call, so nothing checks the deadline.
+    assert_eq!(
+        straight.call("add1", &[Val::I32(1)]).unwrap(),
+        Some(Val::I32(2))
+    );
+}
+
+#[test]
+fn test_epoch_ticker() {
+    use std::time::Duration;
+
+    let m = spin_module();
+    let rt = rt();
+    rt.enable_epoch_ticker(Duration::from_millis(1)).unwrap();
+    let mut inst = rt.instantiate(&m).unwrap();
+    inst.set_epoch_deadline(5);
+    assert_eq!(inst.call("spin", &[]), Err(Trap::Interrupted));
+    assert!(rt.epoch() >= 5);
+
+    rt.disable_epoch_ticker();
+    let stopped = rt.epoch();
+    std::thread::sleep(Duration::from_millis(5));
+    assert_eq!(rt.epoch(), stopped);
+}
+
+// ── Sandbox profiles ──────────────────────────────────────────────────────────
+
+fn countdown_module() -> Module {
+    // down(n) = n == 0 ? 0 : down(n - 1)
+    single_func(
+        "down",
+        &[ValType::I32],
+        Some(ValType::I32),
+        vec![
+            Op::LocalGet(0),
+            Op::I32Eqz,
+            Op::If(BlockType::Empty),
+            Op::I32Const(0),
+            Op::Return,
+            Op::End,
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32Sub,
+            Op::Call(0),
+            Op::Return,
+        ],
+    )
+}
+
+#[test]
+fn test_fuel() {
+    let rt = rt();
+    let m = spin_module();
+    let mut inst = rt.instantiate(&m).unwrap();
+    assert_eq!(inst.fuel(), None);
+    inst.set_fuel(1000);
+    assert_eq!(inst.call("spin", &[]), Err(Trap::OutOfFuel));
+    assert_eq!(inst.fuel(), Some(0));
+
+    // Exactly enough: const + return.
+    let answer = single_func(
+        "answer",
+        &[],
+        Some(ValType::I32),
+        vec![Op::I32Const(42), Op::Return],
+    );
+    let mut config = RuntimeConfig::new();
+    config.set_fuel(2);
+    let rt = Runtime::with_config(config);
+    let mut inst = rt.instantiate(&answer).unwrap();
+    assert_eq!(inst.call("answer", &[]).unwrap(), Some(Val::I32(42)));
+    assert_eq!(inst.fuel(), Some(0));
+    assert_eq!(inst.call("answer", &[]), Err(Trap::OutOfFuel));
+    inst.set_fuel(2);
+    assert_eq!(inst.call("answer", &[]).unwrap(), Some(Val::I32(42)));
+    inst.clear_fuel();
+    assert_eq!(inst.call("answer", &[]).unwrap(), Some(Val::I32(42)));
+}
+
+#[test]
+fn test_call_cancellable() {
+    use rune::cancel::CancellationToken;
+    use std::time::Duration;
+
+    let m = spin_module();
+    let rt = rt();
+    let mut inst = rt.instantiate(&m).unwrap();
+    let token = CancellationToken::new();
+    std::thread::scope(|s| {
+        let remote = token.clone();
+        s.spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            remote.cancel();
+        });
+        assert_eq!(
+            inst.call_cancellable("spin", &[], &token),
+            Err(Trap::Interrupted)
+        );
+    });
+    assert!(token.is_cancelled());
+
+    // An already-cancelled token stops the call on entry; plain calls are
+    // unaffected by it afterwards.
+    let answer = single_func(
+        "answer",
+        &[],
+        Some(ValType::I32),
+        vec![Op::I32Const(42), Op::Return],
+    );
+    let mut inst = rt.instantiate(&answer).unwrap();
+    assert_eq!(
+        inst.call_cancellable("answer", &[], &token),
+        Err(Trap::Interrupted)
+    );
+    assert_eq!(inst.call("answer", &[]).unwrap(), Some(Val::I32(42)));
+
+    // A drop guard cancels when the request holding it goes away.
+    let token = CancellationToken::new();
+    let guard = token.drop_guard();
+    assert_eq!(
+        inst.call_cancellable("answer", &[], &token).unwrap(),
+        Some(Val::I32(42))
+    );
+    drop(guard);
+    assert!(token.is_cancelled());
+    let token = CancellationToken::new();
+    assert!(!token.drop_guard().disarm().is_cancelled());
+}
+
+#[test]
+fn test_call_blocking() {
+    use std::time::Duration;
+
+    let rt = rt();
+    let m = spin_module();
+    let mut inst = rt.instantiate(&m).unwrap();
+    let outcome = rt
+        .call_blocking(&mut inst, "spin", &[], Duration::from_millis(20))
+        .unwrap();
+    assert_eq!(outcome.result, Err(Trap::Interrupted));
+    assert!(outcome.timed_out);
+    assert!(outcome.duration >= Duration::from_millis(20));
+    assert_eq!(outcome.fuel_used, None);
+
+    // A call that returns in time reports its result and the fuel it used.
+    let answer = single_func(
+        "answer",
+        &[],
+        Some(ValType::I32),
+        vec![Op::I32Const(42), Op::Return],
+    );
+    let mut inst = rt.instantiate(&answer).unwrap();
+    inst.set_fuel(10);
+    let outcome = rt
+        .call_blocking(&mut inst, "answer", &[], Duration::from_secs(10))
+        .unwrap();
+    assert_eq!(outcome.result, Ok(Some(Val::I32(42))));
+    assert!(!outcome.timed_out);
+    assert_eq!(outcome.fuel_used, Some(2));
+    assert_eq!(
+        rt.call_blocking(&mut inst, "missing", &[], Duration::from_secs(10))
+            .unwrap()
+            .result,
+        Err(Trap::UndefinedExport("missing".into()))
+    );
+}
+
+#[test]
+fn test_cpu_time() {
+    use std::time::Duration;
+
+    let rt = rt();
+    let m = spin_module();
+    let mut inst = rt.instantiate(&m).unwrap();
+    assert_eq!(inst.cpu_time(), Duration::ZERO);
+    assert_eq!(inst.cpu_budget(), None);
+
+    // The budget spans calls: the spin uses all of it, and the next call
+    // traps on entry.
+    inst.set_cpu_budget(Duration::from_millis(20));
+    assert_eq!(inst.call("spin", &[]), Err(Trap::CpuTimeExceeded));
+    let used = inst.cpu_time();
+    assert!(used >= Duration::from_millis(20));
+    assert_eq!(inst.call("spin", &[]), Err(Trap::CpuTimeExceeded));
+    assert!(inst.cpu_time() >= used);
+
+    let answer = single_func(
+        "answer",
+        &[],
+        Some(ValType::I32),
+        vec![Op::I32Const(42), Op::Return],
+    );
+    let mut inst = rt.instantiate(&answer).unwrap();
+    inst.set_cpu_budget(Duration::ZERO);
+    assert_eq!(inst.call("answer", &[]), Err(Trap::CpuTimeExceeded));
+    inst.clear_cpu_budget();
+    assert_eq!(inst.call("answer", &[]).unwrap(), Some(Val::I32(42)));
+    assert!(inst.cpu_time() > Duration::ZERO);
+    inst.reset_cpu_time();
+    assert_eq!(inst.cpu_time(), Duration::ZERO);
+    assert_eq!(rt.metrics().traps.get("cpu_time_exceeded"), Some(&3));
+}
+
+#[test]
+fn test_max_call_depth() {
+    let m = countdown_module();
+    let mut config = RuntimeConfig::new();
+    config.set_max_call_depth(10);
+    let rt = Runtime::with_config(config);
+    let mut inst = rt.instantiate(&m).unwrap();
+    // down(9) has 10 frames active at its deepest.
+    assert_eq!(
+        inst.call("down", &[Val::I32(9)]).unwrap(),
+        Some(Val::I32(0))
+    );
+    assert_eq!(inst.call("down", &[Val::I32(10)]), Err(Trap::StackOverflow));
+    // The count starts afresh with every call.
+    assert_eq!(
+        inst.call("down", &[Val::I32(9)]).unwrap(),
+        Some(Val::I32(0))
+    );
+}
+
+#[test]
+fn test_sandbox_profiles() {
+    use rune::sandbox::SandboxProfile;
+
+    assert_eq!(SandboxProfile::default(), SandboxProfile::Default);
+    assert!(SandboxProfile::Strict.is_deterministic());
+    assert!(!SandboxProfile::Trusted.is_deterministic());
+
+    let config = RuntimeConfig::with_profile(SandboxProfile::Strict);
+    assert_eq!(config.fuel(), SandboxProfile::Strict.fuel());
+    assert_eq!(config.max_memory_pages(), Some(256));
+    assert!(config.denies_capability("clock"));
+    let strict = Runtime::with_config(config);
+
+    // Fuel runs out instead of spinning forever.
+    let spin = spin_module();
+    let mut inst = strict.instantiate(&spin).unwrap();
+    assert_eq!(inst.call("spin", &[]), Err(Trap::OutOfFuel));
+
+    // Memory is capped below the module's own maximum.
+    let mut big = single_func(
+        "grow",
+        &[ValType::I32],
+        Some(ValType::I32),
+        vec![Op::LocalGet(0), Op::MemoryGrow, Op::Return],
+    );
+    big.initial_memory_pages = 255;
+    let mut inst = strict.instantiate(&big).unwrap();
+    assert_eq!(
+        inst.call("grow", &[Val::I32(2)]).unwrap(),
+        Some(Val::I32(-1))
+    );
+    assert_eq!(
+        inst.call("grow", &[Val::I32(1)]).unwrap(),
+        Some(Val::I32(255))
+    );
+    big.initial_memory_pages = 257;
+    assert_eq!(strict.instantiate(&big).err(), Some(Trap::OutOfMemory));
+
+    // Recursion stops at the default depth. Unoptimized builds use far more
+    // native stack per guest frame, so give the thread room.
+    let depth = SandboxProfile::Default.max_call_depth().unwrap() as i32;
+    let default = Runtime::with_config(RuntimeConfig::with_profile(SandboxProfile::Default));
+    let m = countdown_module();
+    std::thread::scope(|s| {
+        let worker = std::thread::Builder::new().stack_size(64 << 20);
+        worker
+            .spawn_scoped(s, || {
+                let mut inst = default.instantiate(&m).unwrap();
+                assert_eq!(
+                    inst.call("down", &[Val::I32(depth - 1)]).unwrap(),
+                    Some(Val::I32(0))
+                );
+                assert_eq!(
+                    inst.call("down", &[Val::I32(depth)]),
+                    Err(Trap::StackOverflow)
+                );
+            })
+            .unwrap();
+    });
+
+    // Trusted leaves everything to the module.
+    let config = RuntimeConfig::with_profile(SandboxProfile::Trusted);
+    assert_eq!(config.fuel(), None);
+    assert_eq!(config.max_call_depth(), None);
+}
+
+#[cfg(feature = "hostlib")]
+#[test]
+fn test_sandbox_denies_hostlib() {
+    use rune::hostlib::{HostLib, NAMESPACE};
+    use rune::sandbox::SandboxProfile;
+    use rune::Linker;
+
+    let mut m = Module::new();
+    let clock = m.import(
+        NAMESPACE,
+        "clock_wall",
+        FuncType {
+            params: vec![],
+            results: vec![ValType::I64],
+        },
+    );
+    m.functions.push(Function::new(
+        "now",
+        FuncType {
+            params: vec![],
+            results: vec![ValType::I64],
+        },
+        vec![],
+        vec![Op::CallHost(clock), Op::Return],
+    ));
+    m.exports.push(("now".into(), 0));
+
+    let mut linker = Linker::new();
+    HostLib::new().add_to_linker(&mut linker).unwrap();
+    assert_eq!(linker.capability(NAMESPACE, "clock_wall"), Some("clock"));
+
+    let trusted = Runtime::with_config(RuntimeConfig::with_profile(SandboxProfile::Trusted));
+    let mut inst = linker.instantiate(&trusted, &m).unwrap();
+    assert!(inst.call("now", &[]).is_ok());
+
+    // Denied by the profile even though the linker grants everything.
+    let strict = Runtime::with_config(RuntimeConfig::with_profile(SandboxProfile::Strict));
+    let mut inst = linker.instantiate(&strict, &m).unwrap();
+    assert!(matches!(
+        inst.call("now", &[]),
+        Err(Trap::PermissionDenied(_))
+    ));
+}
+
+#[cfg(feature = "hostlib")]
+#[test]
+fn test_hostlib_virtual_clock() {
+    use rune::clock::{ManualClock, SystemClock};
+    use rune::hostlib::{HostLib, NAMESPACE};
+    use rune::sandbox::SandboxProfile;
+    use rune::Linker;
+    use std::time::Duration;
+
+    let mut m = Module::new();
+    let now = m.import(
+        NAMESPACE,
+        "clock_now",
+        FuncType {
+            params: vec![],
+            results: vec![ValType::I64],
+        },
+    );
+    let sleep = m.import(
+        NAMESPACE,
+        "sleep_until",
+        FuncType {
+            params: vec![ValType::I64],
+            results: vec![],
+        },
+    );
+    m.functions.push(Function::new(
+        "now",
+        FuncType {
+            params: vec![],
+            results: vec![ValType::I64],
+        },
+        vec![],
+        vec![Op::CallHost(now), Op::Return],
+    ));
+    m.functions.push(Function::new(
+        "nap",
+        FuncType {
+            params: vec![ValType::I64],
+            results: vec![],
+        },
+        vec![],
+        vec![Op::LocalGet(0), Op::CallHost(sleep), Op::Return],
+    ));
+    m.exports.push(("now".into(), 0));
+    m.exports.push(("nap".into(), 1));
+
+    // A virtual clock is unlabelled, so even a strict sandbox may read it.
+    let clock = ManualClock::new();
+    let mut linker = Linker::new();
+    HostLib::new()
+        .clock(clock.clone())
+        .add_to_linker(&mut linker)
+        .unwrap();
+    assert_eq!(linker.capability(NAMESPACE, "clock_now"), None);
+    let strict = Runtime::with_config(RuntimeConfig::with_profile(SandboxProfile::Strict));
+    let mut inst = linker.instantiate(&strict, &m).unwrap();
+    assert_eq!(inst.call("now", &[]).unwrap(), Some(Val::I64(0)));
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(
+        inst.call("now", &[]).unwrap(),
+        Some(Val::I64(5_000_000_000))
+    );
+
+    // Sleeping jumps straight to the deadline; one already past is a no-op.
+    inst.call("nap", &[Val::I64(7_000_000_000)]).unwrap();
+    assert_eq!(
+        inst.call("now", &[]).unwrap(),
+        Some(Val::I64(7_000_000_000))
+    );
+    inst.call("nap", &[Val::I64(1)]).unwrap();
+    assert_eq!(
+        inst.call("now", &[]).unwrap(),
+        Some(Val::I64(7_000_000_000))
+    );
+
+    // Real time is labelled like the other clocks.
+    let mut linker = Linker::new();
+    HostLib::new()
+        .clock(SystemClock::new())
+        .add_to_linker(&mut linker)
+        .unwrap();
+    assert_eq!(linker.capability(NAMESPACE, "sleep_until"), Some("clock"));
+    let mut inst = linker.instantiate(&rt(), &m).unwrap();
+    let t0 = inst.call("now", &[]).unwrap().unwrap().as_i64().unwrap();
+    inst.call("nap", &[Val::I64(t0 + 1_000_000)]).unwrap();
+    let t1 = inst.call("now", &[]).unwrap().unwrap().as_i64().unwrap();
+    assert!(t1 >= t0 + 1_000_000);
+}
+
+#[cfg(feature = "hostlib")]
+#[test]
+fn test_hostlib_seeded_rng() {
+    use rune::hostlib::{HostLib, NAMESPACE};
+    use rune::sandbox::SandboxProfile;
+    use rune::Linker;
+
+    let mut m = Module::new();
+    let next = m.import(
+        NAMESPACE,
+        "random_u64",
+        FuncType {
+            params: vec![],
+            results: vec![ValType::I64],
+        },
+    );
+    let bytes = m.import(
+        NAMESPACE,
+        "random_bytes",
+        FuncType {
+            params: vec![ValType::I32, ValType::I32],
+            results: vec![],
+        },
+    );
+    m.functions.push(Function::new(
+        "next",
+        FuncType {
+            params: vec![],
+            results: vec![ValType::I64],
+        },
+        vec![],
+        vec![Op::CallHost(next), Op::Return],
+    ));
+    m.functions.push(Function::new(
+        "fill",
+        FuncType {
+            params: vec![],
+            results: vec![],
+        },
+        vec![],
+        vec![
+            Op::I32Const(16),
+            Op::I32Const(13),
+            Op::CallHost(bytes),
+            Op::Return,
+        ],
+    ));
+    m.functions.push(Function::new(
+        "fill_at",
+        FuncType {
+            params: vec![ValType::I32, ValType::I32],
+            results: vec![],
+        },
+        vec![],
+        vec![
+            Op::LocalGet(0),
+            Op::LocalGet(1),
+            Op::CallHost(bytes),
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("next".into(), 0));
+    m.exports.push(("fill".into(), 1));
+    m.exports.push(("fill_at".into(), 2));
+    m.initial_memory_pages = 1;
+
+    let mut linker = Linker::new();
+    HostLib::new().add_to_linker(&mut linker).unwrap();
+    let draws = |inst: &mut rune::Instance<'_>| -> Vec<Option<Val>> {
+        (0..4).map(|_| inst.call("next", &[]).unwrap()).collect()
+    };
+
+    // The same seed draws the same sequence in every instance.
+    let mut config = RuntimeConfig::new();
+    config.set_rng_seed(7);
+    let seeded = Runtime::with_config(config);
+    let mut a = linker.instantiate(&seeded, &m).unwrap();
+    let mut b = linker.instantiate(&seeded, &m).unwrap();
+    let first = draws(&mut a);
+    assert_eq!(draws(&mut b), first);
+    let second = draws(&mut a);
+    assert_ne!(second, first);
+    assert_eq!(draws(&mut b), second);
+    a.call("fill", &[]).unwrap();
+    b.call("fill", &[]).unwrap();
+    assert_eq!(
+        a.memory().read_bytes(16, 13).unwrap(),
+        b.memory().read_bytes(16, 13).unwrap()
+    );
+    assert_ne!(a.memory().read_bytes(16, 13).unwrap(), [0; 13]);
+    assert_eq!(a.memory().read_bytes(29, 1).unwrap(), [0]);
+    // A length past the end of memory traps before anything is written.
+    let before = a.memory().read_bytes(0, 65536).unwrap().to_vec();
+    assert_eq!(
+        a.call("fill_at", &[Val::I32(16), Val::I32(-1)]),
+        Err(Trap::OutOfBounds)
+    );
+    assert_eq!(
+        a.call("fill_at", &[Val::I32(16), Val::I32(65521)]),
+        Err(Trap::OutOfBounds)
+    );
+    assert_eq!(a.memory().read_bytes(0, 65536).unwrap(), before);
+    // Fills longer than the internal buffer draw the same stream.
+    a.call("fill_at", &[Val::I32(0), Val::I32(65536)]).unwrap();
+    b.call("fill_at", &[Val::I32(0), Val::I32(65536)]).unwrap();
+    assert_eq!(
+        a.memory().read_bytes(0, 65536).unwrap(),
+        b.memory().read_bytes(0, 65536).unwrap()
+    );
+    a.set_rng_seed(7);
+    assert_eq!(draws(&mut a), first);
+    a.set_rng_seed(8);
+    assert_ne!(draws(&mut a), first);
+
+    // Unseeded draws need the `random` capability.
+    let strict = Runtime::with_config(RuntimeConfig::with_profile(SandboxProfile::Strict));
+    let mut inst = linker.instantiate(&strict, &m).unwrap();
+    assert!(matches!(
+        inst.call("next", &[]),
+        Err(Trap::PermissionDenied(_))
+    ));
+    let mut config = RuntimeConfig::with_profile(SandboxProfile::Strict);
+    config.set_rng_seed(7);
+    let strict = Runtime::with_config(config);
+    let mut inst = linker.instantiate(&strict, &m).unwrap();
+    assert_eq!(draws(&mut inst), first);
+    let mut inst = linker.instantiate(&rt(), &m).unwrap();
+    assert!(inst.call("next", &[]).is_ok());
+}
+
+/// Ops adding one to the `i32` at `addr`.
+fn bump_counter(addr: i32) -> Vec<Op> {
+    vec![
+        Op::I32Const(addr),
+        Op::I32Const(addr),
+        Op::I32Load {
+            align: 2,
+            offset: 0,
+            memory: 0,
+        },
+        Op::I32Const(1),
+        Op::I32Add,
+        Op::I32Store {
+            align: 2,
+            offset: 0,
+            memory: 0,
+        },
+    ]
+}
+
+#[test]
+fn test_timers() {
+    use rune::clock::ManualClock;
+    use std::time::Duration;
+
+    // tick() counts its calls in memory[0]; boom() traps.
+    let mut m = Module::new();
+    let mut tick = bump_counter(0);
+    tick.push(Op::Return);
+    m.functions.push(func("tick", vec![], vec![], vec![], tick));
+    m.functions
+        .push(func("boom", vec![], vec![], vec![], vec![Op::Unreachable]));
+    m.exports.push(("tick".into(), 0));
+    m.exports.push(("boom".into(), 1));
+    m.initial_memory_pages = 1;
+
+    let clock = ManualClock::new();
+    let mut config = RuntimeConfig::new();
+    config.set_clock(clock.clone());
+    let rt = Runtime::with_config(config);
+    let mut a = rt.instantiate(&m).unwrap();
+    let mut b = rt.instantiate(&m).unwrap();
+
+    let ms = Duration::from_millis;
+    a.set_timeout("tick", ms(100)).unwrap();
+    let cancelled = a.set_timeout("tick", ms(50)).unwrap();
+    assert!(a.clear_timeout(cancelled));
+    assert!(!a.clear_timeout(cancelled));
+    b.set_timeout("boom", ms(10)).unwrap();
+    b.set_timeout("tick", ms(20)).unwrap();
+    assert_eq!(
+        a.set_timeout("missing", ms(1)),
+        Err(Trap::UndefinedExport("missing".into()))
+    );
+    assert_eq!(a.next_timer(), Some(ms(100)));
+
+    let report = rt.poll_timers([&mut a, &mut b]);
+    assert_eq!(report.ran, 0);
+    assert_eq!(report.next_due, Some(ms(10)));
+
+    // b's trap holds back only b's later timer.
+    clock.advance(ms(100));
+    let report = rt.poll_timers([&mut a, &mut b]);
+    assert_eq!(report.ran, 2);
+    assert_eq!(report.traps, vec![(1, Trap::Unreachable)]);
+    assert_eq!(report.next_due, Some(Duration::ZERO));
+    assert_eq!(a.memory().read_u32(0).unwrap(), 1);
+    assert_eq!(b.poll_timers(), Ok(1));
+    assert_eq!(b.memory().read_u32(0).unwrap(), 1);
+    assert_eq!(rt.poll_timers([&mut a, &mut b]).next_due, None);
+}
+
+#[cfg(feature = "hostlib")]
+#[test]
+fn test_hostlib_timers() {
+    use rune::clock::ManualClock;
+    use rune::hostlib::{HostLib, NAMESPACE};
+    use rune::Linker;
+    use std::time::Duration;
+
+    use ValType::I32;
+    let mut m = Module::new();
+    let sig = |params: &[ValType]| FuncType {
+        params: params.to_vec(),
+        results: vec![I32],
+    };
+    let set_timeout = m.import(NAMESPACE, "set_timeout", sig(&[I32, I32, I32]));
+    let clear_timeout = m.import(NAMESPACE, "clear_timeout", sig(&[I32]));
+    let yield_now = m.import(NAMESPACE, "yield_now", sig(&[I32, I32]));
+
+    // start() schedules tick() in 100 ms; step() counts in memory[4] and
+    // yields to itself; cancel(id) clears a timer.
+    let mut step = bump_counter(4);
+    step.extend([
+        Op::I32Const(8),
+        Op::I32Const(4),
+        Op::CallHost(yield_now),
+        Op::Return,
+    ]);
+    let mut tick = bump_counter(0);
+    tick.push(Op::Return);
+    let exports = [
+        (
+            "start",
+            vec![],
+            vec![
+                Op::I32Const(12),
+                Op::I32Const(4),
+                Op::I32Const(100),
+                Op::CallHost(set_timeout),
+                Op::Return,
+            ],
+        ),
+        ("step", vec![], step),
+        ("tick", vec![], tick),
+        (
+            "cancel",
+            vec![I32],
+            vec![Op::LocalGet(0), Op::CallHost(clear_timeout), Op::Return],
+        ),
+    ];
+    for (name, params, body) in exports {
+        let results = if name == "tick" { vec![] } else { vec![I32] };
+        let index = m.functions.len() as u32;
+        m.functions.push(func(name, params, results, vec![], body));
+        m.exports.push((name.into(), index));
+    }
+    m.initial_memory_pages = 1;
+    m.data_segments.push((8, b"steptick".to_vec()));
+
+    let clock = ManualClock::new();
+    let mut config = RuntimeConfig::new();
+    config.set_clock(clock.clone());
+    let rt = Runtime::with_config(config);
+    let mut linker = Linker::new();
+    HostLib::new().add_to_linker(&mut linker).unwrap();
+    let mut inst = linker.instantiate(&rt, &m).unwrap();
+
+    let id = inst.call("start", &[]).unwrap().unwrap();
+    inst.call("start", &[]).unwrap();
+    assert_eq!(inst.call("cancel", &[id]).unwrap(), Some(Val::I32(1)));
+    assert_eq!(inst.call("cancel", &[id]).unwrap(), Some(Val::I32(0)));
+    assert_eq!(inst.poll_timers(), Ok(0));
+    clock.advance(Duration::from_millis(100));
+    assert_eq!(inst.poll_timers(), Ok(1));
+    assert_eq!(inst.memory().read_u32(0).unwrap(), 1);
+
+    // Each poll runs one step, which queues the next.
+    inst.call("step", &[]).unwrap();
+    for _ in 0..3 {
+        assert_eq!(inst.poll_timers(), Ok(1));
+    }
+    assert_eq!(inst.memory().read_u32(4).unwrap(), 4);
+    assert_eq!(inst.pending_timers(), 1);
+}
+
+// ── Undefined export ──────────────────────────────────────────────────────────
+
+#[test]
+fn test_undefined_export() {
+    let m = Module::new();
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert!(matches!(
+        inst.call("nope", &[]),
+        Err(Trap::UndefinedExport(_))
+    ));
+}
+
+// ── Benchmarks / Stress Tests (reviewer-requested) ───────────────────────────
+
+/// Stress-test: recursive fib(30) exercises deep Call stacks.
+/// Passes when it completes and returns the correct value.
+/// (Performance target for AOT: <1ms; interpreter: ~50ms is acceptable.)
+#[test]
+fn bench_fibonacci_30() {
+    let mut m = Module::new();
+    m.functions.push(Function::new(
+        "fib",
+        FuncType {
+            params: vec![ValType::I32],
+            results: vec![ValType::I32],
+        },
+        vec![],
+        vec![
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32LeS,
+            Op::If(BlockType::Val(ValType::I32)),
+            Op::LocalGet(0),
+            Op::Else,
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32Sub,
+            Op::Call(0),
+            Op::LocalGet(0),
+            Op::I32Const(2),
+            Op::I32Sub,
+            Op::Call(0),
+            Op::I32Add,
+            Op::End,
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("fib".into(), 0));
+    let mut inst = rt().instantiate(&m).unwrap();
+    // fib(30) = 832040
+    let result = inst.call("fib", &[Val::I32(30)]).unwrap();
+    assert_eq!(result, Some(Val::I32(832040)));
+}
+
+/// Stress-test: allocate and grow to 100 pages (~6.4MB), write a pattern
+/// across every 4 bytes, then verify all reads. Catches memory bounds bugs.
+#[test]
+fn memory_stress_100_pages() {
+    use rune::memory::PAGE_SIZE;
+
+    let mut m = Module::new();
+    // A no-op function — we test memory directly via the instance API.
+    m.functions.push(Function::new(
+        "nop",
+        FuncType {
+            params: vec![],
+            results: vec![],
+        },
+        vec![],
+        vec![Op::Return],
+    ));
+    m.exports.push(("nop".into(), 0));
+    m.initial_memory_pages = 1;
+    m.max_memory_pages = Some(200);
+
+    let mut inst = rt().instantiate(&m).unwrap();
+
+    // Grow to 100 pages.
+    inst.memory_mut().grow(99).expect("grow failed");
+    assert_eq!(inst.memory().pages(), 100);
+
+    let total_bytes = 100 * PAGE_SIZE;
+
+    // Write a u32 pattern across the full memory space.
+    let mut offset = 0usize;
+    let mut value = 0u32;
+    while offset + 4 <= total_bytes {
+        inst.memory_mut().write_u32(offset, value).unwrap();
+        offset += 4;
+        value = value.wrapping_add(1);
+    }
+
+    // Verify every value.
+    let mut offset = 0usize;
+    let mut value = 0u32;
+    let mut errors = 0u32;
+    while offset + 4 <= total_bytes {
+        let v = inst.memory().read_u32(offset).unwrap();
+        if v != value {
+            errors += 1;
+        }
+        offset += 4;
+        value = value.wrapping_add(1);
+    }
+
+    assert_eq!(errors, 0, "{errors} memory read/write mismatches");
+}
+
+/// Stress-test: call a host function 100,000 times in a Rune loop.
+/// Validates that the host call dispatch path has no memory/stack issues.
+/// (100k instead of 1M to keep CI fast; the overhead scales linearly.)
+#[test]
+fn host_callback_loop_100k() {
+    use rune::Linker;
+    use std::sync::{Arc, Mutex};
+
+    const ITERATIONS: i32 = 100_000;
+
+    let counter: Arc<Mutex<i32>> = Arc::new(Mutex::new(0));
+    let counter2 = counter.clone();
+
+    let void = FuncType {
+        params: vec![],
+        results: vec![],
+    };
+    let mut m = Module::new();
+    // host[0]: increment()
+    m.import("env", "increment", void.clone());
+    let mut linker = Linker::new();
+    linker
+        .func("env", "increment", void, move |_args| {
+            *counter2.lock().unwrap() += 1;
+            Ok(None)
+        })
+        .unwrap();
+
+    // Guest: loop ITERATIONS times, call host each time.
+    //
+    // locals[0] = i (counter, starts at ITERATIONS counts down to 0)
+    m.functions.push(Function::new(
+        "run",
+        FuncType {
+            params: vec![],
+            results: vec![],
+        },
+        vec![ValType::I32],
+        vec![
+            // i = ITERATIONS
+            Op::I32Const(ITERATIONS),
+            Op::LocalSet(0),
+            // Block wraps the Loop so BrIf(1) has a valid exit target.
+            // Br(0) = continue Loop, Br(1) = break out of Block.
+            Op::Block(BlockType::Empty), // depth 1 — break target
+            Op::Loop(BlockType::Empty),  // depth 0 — continue target
+            // if i == 0: exit Block
+            Op::LocalGet(0),
+            Op::I32Eqz,
+            Op::BrIf(1),
+            // call host
+            Op::CallHost(0),
+            // i -= 1
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32Sub,
+            Op::LocalSet(0),
+            // continue Loop
+            Op::Br(0),
+            Op::End, // End Loop
+            Op::End, // End Block
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("run".into(), 0));
+
+    let mut inst = linker.instantiate(&rt(), &m).unwrap();
+    inst.call("run", &[]).expect("run failed");
+
+    assert_eq!(
+        *counter.lock().unwrap(),
+        ITERATIONS,
+        "host was not called the expected number of times"
+    );
+}
+
+// ── Extension opcodes ─────────────────────────────────────────────────────────
+
+/// Test extension: pops an address, returns the byte there plus `imm`.
+struct LoadPlus;
+
+impl ExtensionOp for LoadPlus {
+    fn name(&self) -> &str {
+        "load_plus"
+    }
+    fn signature(&self) -> FuncType {
+        FuncType {
+            params: vec![ValType::I32],
+            results: vec![ValType::I32],
+        }
+    }
+    fn decode(&self, imm: u32) -> Result<(), Trap> {
+        if imm > 100 {
+            return Err(Trap::InvalidModule("load_plus immediate too large".into()));
+        }
+        Ok(())
+    }
+    fn execute(&self, imm: u32, args: &[Val], memory: &mut Memory) -> Result<Option<Val>, Trap> {
+        let addr = args[0].as_i32().expect("checked against the signature") as usize;
+        let byte = memory.read_u8(addr)?;
+        Ok(Some(Val::I32(byte as i32 + imm as i32)))
+    }
+}
+
+fn ext_module(imm: u32) -> Module {
+    let mut m = single_func(
+        "f",
+        &[ValType::I32],
+        Some(ValType::I32),
+        vec![Op::LocalGet(0), Op::Ext { opcode: 0xE3, imm }, Op::Return],
+    );
+    m.data_segments.push((4, vec![40]));
+    m
+}
+
+#[test]
+fn test_extension_opcode() {
+    let mut config = RuntimeConfig::new();
+    config.register_extension(0xE3, LoadPlus).unwrap();
+    assert!(config.register_extension(0x42, LoadPlus).is_err());
+    let runtime = Runtime::with_config(config);
+
+    let m = Module::from_bytes(&ext_module(2).to_bytes()).unwrap();
+    let mut inst = runtime.instantiate(&m).unwrap();
+    assert_eq!(inst.call("f", &[Val::I32(4)]).unwrap(), Some(Val::I32(42)));
+
+    // Rejected up front: no handler, or an immediate the handler refuses.
+    assert!(matches!(
+        rt().instantiate(&m).err(),
+        Some(Trap::UnsupportedFeature(_))
+    ));
+    assert!(matches!(
+        runtime.instantiate(&ext_module(500)).err(),
+        Some(Trap::InvalidModule(_))
+    ));
+
+    // Arguments must match the signature, statically and when run.
+    let wrong = single_func(
+        "f",
+        &[ValType::I64],
+        Some(ValType::I32),
+        vec![
+            Op::LocalGet(0),
+            Op::Ext {
+                opcode: 0xE3,
+                imm: 0,
+            },
+            Op::Return,
+        ],
+    );
+    let err = rune::verify::verify_module(&wrong, runtime.config()).unwrap_err();
+    assert_eq!(err.op, Some(1));
+    let mut inst = runtime.instantiate(&wrong).unwrap();
+    assert_eq!(inst.call("f", &[Val::I64(4)]), Err(Trap::TypeMismatch));
+}
+
+// ── Fibonacci (recursive) ─────────────────────────────────────────────────────
+
+#[test]
+fn test_fibonacci() {
+    // fib(n): recursive fibonacci
+    // fib(0) = 0, fib(1) = 1, fib(n) = fib(n-1) + fib(n-2)
+    let mut m = Module::new();
+    m.functions.push(Function::new(
+        "fib",
+        FuncType {
+            params: vec![ValType::I32],
+            results: vec![ValType::I32],
+        },
+        vec![],
+        vec![
+            // if n <= 1 return n
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32LeS,
+            Op::If(BlockType::Val(ValType::I32)),
+            Op::LocalGet(0),
+            Op::Else,
+            // fib(n-1) + fib(n-2)
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32Sub,
+            Op::Call(0), // fib(n-1)
+            Op::LocalGet(0),
+            Op::I32Const(2),
+            Op::I32Sub,
+            Op::Call(0), // fib(n-2)
+            Op::I32Add,
+            Op::End,
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("fib".into(), 0));
+    let mut inst = rt().instantiate(&m).unwrap();
+    assert_eq!(inst.call("fib", &[Val::I32(0)]).unwrap(), Some(Val::I32(0)));
+    assert_eq!(inst.call("fib", &[Val::I32(1)]).unwrap(), Some(Val::I32(1)));
+    assert_eq!(
+        inst.call("fib", &[Val::I32(10)]).unwrap(),
+        Some(Val::I32(55))
+    );
+}
+
+// ── C API ─────────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_ffi_instance_call() {
+    use rune::ffi::*;
+    use std::ptr;
+
+    let add = single_func(
+        "add",
+        &[ValType::I32, ValType::I32],
+        Some(ValType::I32),
+        vec![Op::LocalGet(0), Op::LocalGet(1), Op::I32Add, Op::Return],
+    );
+    let bytes = add.to_bytes();
+    unsafe {
+        let rt = rune_runtime_new();
+        let module = rune_module_load_bytes(rt, bytes.as_ptr(), bytes.len());
+        assert!(!module.is_null());
+        let inst = rune_instance_new(rt, module);
+        assert!(!inst.is_null());
+        // The instance keeps the module alive.
+        rune_module_free(module);
+
+        let i32 = |v| RuneTypedVal {
+            ty: RuneValType::I32,
+            val: RuneVal { i32: v },
+        };
+        let args = [i32(40), i32(2)];
+        let mut out = RuneTypedVal {
+            ty: RuneValType::Void,
+            val: RuneVal { i64: 0 },
+        };
+        let err = rune_instance_call(inst, c"add".as_ptr(), args.as_ptr(), 2, &mut out);
+        assert!(matches!(err, RuneError::Ok));
+        assert_eq!(out.ty, RuneValType::I32);
+        assert_eq!(out.val.i32, 42);
+
+        // Arguments must match the signature in number and type.
+        assert!(matches!(
+            rune_instance_call(inst, c"add".as_ptr(), args.as_ptr(), 1, &mut out),
+            RuneError::TrapTypeMismatch
+        ));
+        let wrong = [
+            i32(40),
+            RuneTypedVal {
+                ty: RuneValType::F32,
+                val: RuneVal { f32: 2.0 },
+            },
+        ];
+        assert!(matches!(
+            rune_instance_call(inst, c"add".as_ptr(), wrong.as_ptr(), 2, &mut out),
+            RuneError::TrapTypeMismatch
+        ));
+        assert!(matches!(
+            rune_instance_call(inst, c"sub".as_ptr(), ptr::null(), 0, ptr::null_mut()),
+            RuneError::UndefinedExport
+        ));
+        assert!(matches!(
+            rune_instance_call(ptr::null_mut(), c"add".as_ptr(), ptr::null(), 0, &mut out),
+            RuneError::InvalidArgument
+        ));
+        assert!(rune_instance_new(rt, ptr::null_mut()).is_null());
+
+        rune_instance_free(inst);
+        rune_runtime_free(rt);
+    }
+}
+
+#[test]
+// Covers the untyped host function API, kept for existing embedders.
+#[allow(deprecated)]
+fn test_ffi_linker_define() {
+    use rune::ffi::*;
+    use std::ffi::c_void;
+
+    // Adds the i32 behind user_data to its argument, counting calls there.
+    unsafe extern "C-unwind" fn offset(
+        _caller: *mut CCaller,
+        args: *const RuneVal,
+        n_args: usize,
+        result: *mut RuneVal,
+        user_data: *mut c_void,
+    ) -> RuneError {
+        let state = &mut *(user_data as *mut [i32; 2]);
+        state[1] += 1;
+        if n_args != 1 || (*args).i32 < 0 {
+            return RuneError::HostError;
+        }
+        (*result).i32 = (*args).i32 + state[0];
+        RuneError::Ok
+    }
+
+    let unary = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
+    let mut m = Module::new();
+    let host = m.import("env", "offset", unary.clone());
+    m.functions.push(Function::new(
+        "run",
+        unary,
+        vec![],
+        vec![Op::LocalGet(0), Op::CallHost(host), Op::Return],
+    ));
+    m.exports.push(("run".into(), 0));
+    let bytes = m.to_bytes();
+
+    let mut state = [100i32, 0];
+    unsafe {
+        let rt = rune_runtime_new();
+        let module = rune_module_load_bytes(rt, bytes.as_ptr(), bytes.len());
+        // Without a definition the import does not resolve.
+        assert!(rune_instance_new(rt, module).is_null());
+
+        let linker = rune_linker_new();
+        let params = [RuneValType::I32];
+        let sig = RuneSignature {
+            params: params.as_ptr(),
+            n_params: 1,
+            result: RuneValType::I32,
+        };
+        let user_data = &mut state as *mut [i32; 2] as *mut c_void;
+        let define = |linker| {
+            rune_linker_define(
+                linker,
+                c"env".as_ptr(),
+                c"offset".as_ptr(),
+                &sig,
+                offset,
+                user_data,
+            )
+        };
+        assert!(matches!(define(linker), RuneError::Ok));
+        assert!(matches!(define(linker), RuneError::InvalidModule));
+        let inst = rune_linker_instantiate(linker, rt, module);
+        assert!(!inst.is_null());
+        rune_linker_free(linker);
+
+        let arg = |v| RuneTypedVal {
+            ty: RuneValType::I32,
+            val: RuneVal { i32: v },
+        };
+        let mut out = arg(0);
+        let err = rune_instance_call(inst, c"run".as_ptr(), &arg(5), 1, &mut out);
+        assert!(matches!(err, RuneError::Ok));
+        assert_eq!(out.val.i32, 105);
+        // A failing callback traps the guest.
+        let err = rune_instance_call(inst, c"run".as_ptr(), &arg(-1), 1, &mut out);
+        assert!(matches!(err, RuneError::HostError));
+
+        rune_instance_free(inst);
+        rune_module_free(module);
+        rune_runtime_free(rt);
+    }
+    assert_eq!(state[1], 2);
+}
+
+#[test]
+fn test_ffi_typed_values() {
+    use rune::ffi::*;
+    use std::ffi::{c_void, CStr};
+    use std::mem::MaybeUninit;
+
+    // Triples its i64 argument; a negative one comes back tagged f64.
+    unsafe extern "C-unwind" fn triple(
+        _caller: *mut CCaller,
+        args: *const RuneTypedVal,
+        n_args: usize,
+        result: *mut RuneTypedVal,
+        _user_data: *mut c_void,
+    ) -> RuneError {
+        let mut x = 0i64;
+        if n_args != 1 || !rune_val_as_i64(args, &mut x) {
+            return RuneError::TrapTypeMismatch;
+        }
+        // The result arrives tagged with the signature's type.
+        assert!(matches!((*result).ty, RuneValType::I64));
+        *result = if x < 0 {
+            rune_val_f64(x as f64)
+        } else {
+            rune_val_i64(x * 3)
+        };
+        RuneError::Ok
+    }
+
+    let mut x = 0i32;
+    let mut y = 0.0f64;
+    let v = rune_val_i32(5);
+    unsafe {
+        assert!(rune_val_is_valid(&v));
+        assert!(rune_val_as_i32(&v, &mut x));
+        assert_eq!(x, 5);
+        assert!(!rune_val_as_f64(&v, &mut y));
+        assert!(rune_val_as_f64(&rune_val_f64(2.5), &mut y));
+        assert_eq!(y, 2.5);
+        assert!(!rune_val_as_i32(&rune_val_void(), &mut x));
+    }
+    // A tag C code wrote out of range is rejected, not read as a type.
+    let mut bad = MaybeUninit::new(rune_val_i32(1));
+    unsafe { bad.as_mut_ptr().cast::<u32>().write(0x55) };
+    assert!(unsafe { !rune_val_is_valid(bad.as_ptr()) });
+
+    let unary = FuncType {
+        params: vec![ValType::I64],
+        results: vec![ValType::I64],
+    };
+    let mut m = Module::new();
+    let host = m.import("env", "triple", unary.clone());
+    m.functions.push(Function::new(
+        "run",
+        unary,
+        vec![],
+        vec![Op::LocalGet(0), Op::CallHost(host), Op::Return],
+    ));
+    m.exports.push(("run".into(), 0));
+    let bytes = m.to_bytes();
+
+    unsafe {
+        let rt = rune_runtime_new();
+        let module = rune_module_load_bytes(rt, bytes.as_ptr(), bytes.len());
+        let linker = rune_linker_new();
+        let params = [RuneValType::I64];
+        let sig = RuneSignature {
+            params: params.as_ptr(),
+            n_params: 1,
+            result: RuneValType::I64,
+        };
+        let err = rune_linker_define_typed(
+            linker,
+            c"env".as_ptr(),
+            c"triple".as_ptr(),
+            &sig,
+            triple,
+            std::ptr::null_mut(),
+        );
+        assert!(matches!(err, RuneError::Ok));
+        let inst = rune_linker_instantiate(linker, rt, module);
+        rune_linker_free(linker);
+
+        let mut out = rune_val_void();
+        let err = rune_instance_call(inst, c"run".as_ptr(), &rune_val_i64(14), 1, &mut out);
+        assert!(matches!(err, RuneError::Ok));
+        let mut n = 0i64;
+        assert!(rune_val_as_i64(&out, &mut n));
+        assert_eq!(n, 42);
+
+        let err = rune_instance_call(inst, c"run".as_ptr(), &rune_val_i64(-1), 1, &mut out);
+        assert!(matches!(err, RuneError::HostError));
+        assert_eq!(
+            CStr::from_ptr(rune_last_error_message()),
+            c"host error: env.triple: returned f64, expected i64"
+        );
+
+        let err = rune_instance_call(inst, c"run".as_ptr(), bad.as_ptr(), 1, &mut out);
+        assert!(matches!(err, RuneError::TrapTypeMismatch));
+        assert_eq!(
+            CStr::from_ptr(rune_last_error_message()),
+            c"run: invalid value type 0x55"
+        );
+
+        rune_instance_free(inst);
+        rune_module_free(module);
+        rune_runtime_free(rt);
+    }
+}
+
+#[test]
+fn test_ffi_linker_instances() {
+    use rune::ffi::*;
+    use std::ffi::CStr;
+
+    let unary = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
+    let mut lib = Module::new();
+    lib.functions.push(Function::new(
+        "double",
+        unary.clone(),
+        vec![],
+        vec![Op::LocalGet(0), Op::I32Const(2), Op::I32Mul, Op::Return],
+    ));
+    lib.exports.push(("double".into(), 0));
+    let lib = lib.to_bytes();
+
+    let mut app = Module::new();
+    let double = app.import("lib", "double", unary.clone());
+    app.functions.push(Function::new(
+        "run",
+        unary,
+        vec![],
+        vec![Op::LocalGet(0), Op::CallHost(double), Op::Return],
+    ));
+    app.exports.push(("run".into(), 0));
+    let app = app.to_bytes();
+
+    unsafe {
+        let rt = rune_runtime_new();
+        let lib_module = rune_module_load_bytes(rt, lib.as_ptr(), lib.len());
+        let app_module = rune_module_load_bytes(rt, app.as_ptr(), app.len());
+        let linker = rune_linker_new();
+
+        assert_eq!(rune_linker_unresolved_count(linker, app_module), 1);
+        let mut buf = [0 as std::ffi::c_char; 32];
+        let len = rune_linker_unresolved_name(linker, app_module, 0, buf.as_mut_ptr(), 32);
+        assert_eq!(len, "lib.double".len());
+        assert_eq!(CStr::from_ptr(buf.as_ptr()), c"lib.double");
+        // Truncated like snprintf, still reporting the full length.
+        let len = rune_linker_unresolved_name(linker, app_module, 0, buf.as_mut_ptr(), 4);
+        assert_eq!(len, "lib.double".len());
+        assert_eq!(CStr::from_ptr(buf.as_ptr()), c"lib");
+        assert_eq!(
+            rune_linker_unresolved_name(linker, app_module, 1, buf.as_mut_ptr(), 32),
+            0
+        );
+
+        assert!(rune_linker_instantiate(linker, rt, app_module).is_null());
+        assert_eq!(
+            CStr::from_ptr(rune_last_error_message()),
+            c"undefined import: lib.double"
+        );
+
+        let lib_inst = rune_instance_new(rt, lib_module);
+        let err = rune_linker_define_instance(linker, c"lib".as_ptr(), lib_inst);
+        assert!(matches!(err, RuneError::Ok));
+        let err = rune_linker_define_instance(linker, c"lib".as_ptr(), lib_inst);
+        assert!(matches!(err, RuneError::InvalidModule));
+        assert_eq!(rune_linker_unresolved_count(linker, app_module), 0);
+        let app_inst = rune_linker_instantiate(linker, rt, app_module);
+        assert!(!app_inst.is_null());
+        // The linker keeps the library instance alive.
+        rune_instance_free(lib_inst);
+        rune_linker_free(linker);
+
+        let arg = RuneTypedVal {
+            ty: RuneValType::I32,
+            val: RuneVal { i32: 21 },
+        };
+        let mut out = arg;
+        let err = rune_instance_call(app_inst, c"run".as_ptr(), &arg, 1, &mut out);
+        assert!(matches!(err, RuneError::Ok));
+        assert_eq!(out.val.i32, 42);
+
+        rune_instance_free(app_inst);
+        rune_module_free(app_module);
+        rune_module_free(lib_module);
+        rune_runtime_free(rt);
+    }
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_ffi_callback_panics() {
+    use rune::ffi::*;
+    use std::ffi::{c_char, c_void, CStr};
+
+    // Panics on a negative argument, otherwise returns it.
+    unsafe extern "C-unwind" fn check(
+        _caller: *mut CCaller,
+        args: *const RuneVal,
+        _n_args: usize,
+        result: *mut RuneVal,
+        _user_data: *mut c_void,
+    ) -> RuneError {
+        assert!((*args).i32 >= 0, "negative argument");
+        (*result).i32 = (*args).i32;
+        RuneError::Ok
+    }
+
+    unsafe extern "C-unwind" fn on_trap(
+        _code: RuneError,
+        _message: *const c_char,
+        _frames: *const RuneFrame,
+        _n_frames: usize,
+        _user_data: *mut c_void,
+    ) {
+        panic!("trap callback");
+    }
+
+    let unary = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
+    let mut m = Module::new();
+    let host = m.import("env", "check", unary.clone());
+    m.functions.push(Function::new(
+        "run",
+        unary,
+        vec![],
+        vec![Op::LocalGet(0), Op::CallHost(host), Op::Return],
+    ));
+    m.exports.push(("run".into(), 0));
+    let bytes = m.to_bytes();
+
+    unsafe {
+        let rt = rune_runtime_new();
+        let module = rune_module_load_bytes(rt, bytes.as_ptr(), bytes.len());
+        let linker = rune_linker_new();
+        let params = [RuneValType::I32];
+        let sig = RuneSignature {
+            params: params.as_ptr(),
+            n_params: 1,
+            result: RuneValType::I32,
+        };
+        let err = rune_linker_define(
+            linker,
+            c"env".as_ptr(),
+            c"check".as_ptr(),
+            &sig,
+            check,
+            std::ptr::null_mut(),
+        );
+        assert!(matches!(err, RuneError::Ok));
+        let inst = rune_linker_instantiate(linker, rt, module);
+        rune_linker_free(linker);
+
+        let arg = |v| RuneTypedVal {
+            ty: RuneValType::I32,
+            val: RuneVal { i32: v },
+        };
+        let mut out = arg(0);
+        let err = rune_instance_call(inst, c"run".as_ptr(), &arg(-1), 1, &mut out);
+        assert!(matches!(err, RuneError::HostError));
+        assert_eq!(
+            CStr::from_ptr(rune_last_error_message()),
+            c"host error: env.check: panic: negative argument"
+        );
+        // The instance stays usable after the guest trapped.
+        let err = rune_instance_call(inst, c"run".as_ptr(), &arg(3), 1, &mut out);
+        assert!(matches!(err, RuneError::Ok));
+        assert_eq!(out.val.i32, 3);
+
+        // A panicking trap callback does not replace the call's error.
+        rune_runtime_set_trap_callback(rt, Some(on_trap), std::ptr::null_mut());
+        let err = rune_instance_call(inst, c"run".as_ptr(), &arg(-1), 1, &mut out);
+        assert!(matches!(err, RuneError::HostError));
+        assert_eq!(
+            CStr::from_ptr(rune_last_error_message()),
+            c"host error: env.check: panic: negative argument"
+        );
+
+        rune_instance_free(inst);
+        rune_module_free(module);
+        rune_runtime_free(rt);
+    }
+}
+
+#[cfg(feature = "hostlib")]
+#[test]
+fn test_ffi_hostlib_sinks() {
+    use rune::ffi::hostlib::*;
+    use rune::ffi::*;
+    use rune::hostlib::NAMESPACE;
+    use std::ffi::{c_char, c_void, CStr};
+
+    #[derive(Default)]
+    struct Sinks {
+        out: Vec<u8>,
+        log: Vec<(RuneLogLevel, String)>,
+    }
+
+    unsafe extern "C-unwind" fn write_out(
+        data: *const u8,
+        len: usize,
+        user_data: *mut c_void,
+    ) -> bool {
+        let sinks = &mut *(user_data as *mut Sinks);
+        sinks
+            .out
+            .extend_from_slice(std::slice::from_raw_parts(data, len));
+        true
+    }
+
+    unsafe extern "C-unwind" fn refuse(
+        _data: *const u8,
+        _len: usize,
+        _user_data: *mut c_void,
+    ) -> bool {
+        false
+    }
+
+    unsafe extern "C-unwind" fn log(
+        level: RuneLogLevel,
+        message: *const c_char,
+        len: usize,
+        user_data: *mut c_void,
+    ) {
+        let sinks = &mut *(user_data as *mut Sinks);
+        let message = CStr::from_ptr(message).to_str().unwrap();
+        assert_eq!(message.len(), len);
+        sinks.log.push((level, message.to_owned()));
+    }
+
+    use ValType::I32;
+    let sig = |params: &[ValType], results: &[ValType]| FuncType {
+        params: params.to_vec(),
+        results: results.to_vec(),
+    };
+    let mut m = Module::new();
+    let stdout = m.import(NAMESPACE, "write_stdout", sig(&[I32, I32], &[I32]));
+    let stderr = m.import(NAMESPACE, "write_stderr", sig(&[I32, I32], &[I32]));
+    let log_fn = m.import(NAMESPACE, "log", sig(&[I32, I32, I32], &[]));
+    let bodies = [
+        (
+            "out",
+            vec![I32],
+            vec![Op::I32Const(0), Op::I32Const(6), Op::CallHost(stdout)],
+        ),
+        (
+            "err",
+            vec![I32],
+            vec![Op::I32Const(0), Op::I32Const(6), Op::CallHost(stderr)],
+        ),
+        (
+            "log",
+            vec![],
+            vec![
+                Op::I32Const(2),
+                Op::I32Const(0),
+                Op::I32Const(5),
+                Op::CallHost(log_fn),
+            ],
+        ),
+    ];
+    for (name, results, mut body) in bodies {
+        body.push(Op::Return);
+        let index = m.functions.len() as u32;
+        m.functions.push(func(name, vec![], results, vec![], body));
+        m.exports.push((name.into(), index));
+    }
+    m.initial_memory_pages = 1;
+    m.data_segments.push((0, b"hello\n".to_vec()));
+    let bytes = m.to_bytes();
+
+    let mut sinks = Sinks::default();
+    let user_data = &mut sinks as *mut Sinks as *mut c_void;
+    unsafe {
+        let rt = rune_runtime_new();
+        let module = rune_module_load_bytes(rt, bytes.as_ptr(), bytes.len());
+        let hostlib = rune_hostlib_new();
+        assert!(matches!(
+            rune_hostlib_set_stdout(hostlib, Some(write_out), user_data),
+            RuneError::Ok
+        ));
+        assert!(matches!(
+            rune_hostlib_set_log(hostlib, Some(log), user_data),
+            RuneError::Ok
+        ));
+        let linker = rune_linker_new();
+        assert!(matches!(
+            rune_hostlib_add_to_linker(hostlib, linker),
+            RuneError::Ok
+        ));
+        // Later changes do not reach linkers the library was already added to.
+        rune_hostlib_set_stdout(hostlib, Some(refuse), std::ptr::null_mut());
+        let inst = rune_linker_instantiate(linker, rt, module);
+        assert!(!inst.is_null());
+
+        let mut out = RuneTypedVal {
+            ty: RuneValType::Void,
+            val: RuneVal { i64: 0 },
+        };
+        let call = |inst, name: &CStr, out: &mut RuneTypedVal| {
+            rune_instance_call(inst, name.as_ptr(), std::ptr::null(), 0, out)
+        };
+        assert!(matches!(call(inst, c"out", &mut out), RuneError::Ok));
+        assert_eq!(out.val.i32, 6);
+        assert!(matches!(call(inst, c"log", &mut out), RuneError::Ok));
+        rune_instance_free(inst);
+        rune_linker_free(linker);
+
+        // A refusing sink fails the guest's write; a null one discards.
+        rune_hostlib_set_stderr(hostlib, None, std::ptr::null_mut());
+        let linker = rune_linker_new();
+        rune_hostlib_add_to_linker(hostlib, linker);
+        rune_hostlib_free(hostlib);
+        let inst = rune_linker_instantiate(linker, rt, module);
+        assert!(matches!(call(inst, c"out", &mut out), RuneError::Ok));
+        assert_eq!(out.val.i32, -1);
+        assert!(matches!(call(inst, c"err", &mut out), RuneError::Ok));
+        assert_eq!(out.val.i32, 6);
+
+        rune_instance_free(inst);
+        rune_linker_free(linker);
+        rune_module_free(module);
+        rune_runtime_free(rt);
+    }
+    assert_eq!(sinks.out, b"hello\n");
+    assert_eq!(sinks.log, [(RuneLogLevel::Info, "hello".to_owned())]);
+}
+
+#[test]
+fn test_ffi_module_patch_roundtrip() {
+    use rune::ffi::*;
+
+    let mut m = single_func(
+        "add",
+        &[ValType::I32, ValType::I32],
+        Some(ValType::I32),
+        vec![Op::LocalGet(0), Op::LocalGet(1), Op::I32Add, Op::Return],
+    );
+    m.initial_memory_pages = 1;
+    m.data_segments.push((16, b"patched".to_vec()));
+    let bytes = m.to_bytes();
+
+    unsafe {
+        let rt = rune_runtime_new();
+        let module = rune_module_load_bytes(rt, bytes.as_ptr(), bytes.len());
+        let (mut data, mut len) = (std::ptr::null_mut(), 0);
+
+        // A loaded module serializes back to the bytes it came from.
+        assert!(matches!(
+            rune_module_to_bytes(module, &mut data, &mut len),
+            RuneError::Ok
+        ));
+        assert_eq!(std::slice::from_raw_parts(data, len), &bytes[..]);
+        rune_buffer_free(data, len);
+
+        // Patched in place, it keeps everything else.
+        let before = rune_instance_new(rt, module);
+        let err = rune_module_add_export(module, c"sum".as_ptr(), 0);
+        assert!(matches!(err, RuneError::Ok));
+        assert!(matches!(
+            rune_module_to_bytes(module, &mut data, &mut len),
+            RuneError::Ok
+        ));
+        let patched = Module::from_bytes(std::slice::from_raw_parts(data, len)).unwrap();
+        rune_bytes_free(data, len);
+        m.exports.push(("sum".into(), 0));
+        assert_eq!(patched.to_bytes(), m.to_bytes());
+
+        // Instances created before the patch keep the module they had.
+        let mut out = rune_val_void();
+        let args = [rune_val_i32(1), rune_val_i32(2)];
+        let err = rune_instance_call(before, c"sum".as_ptr(), args.as_ptr(), 2, &mut out);
+        assert!(matches!(err, RuneError::UndefinedExport));
+
+        rune_instance_free(before);
+        rune_module_free(module);
+        rune_runtime_free(rt);
+    }
+}
+
+#[test]
+fn test_ffi_memory_access() {
+    use rune::ffi::*;
+    use std::ffi::c_void;
+
+    let mut m = read_word_module();
+    m.max_memory_pages = Some(2);
+    m.data_segments.push((0, b"rune".to_vec()));
+    let bytes = m.to_bytes();
+    unsafe {
+        let rt = rune_runtime_new();
+        let module = rune_module_load_bytes(rt, bytes.as_ptr(), bytes.len());
+        let inst = rune_instance_new(rt, module);
+        assert_eq!(rune_memory_size(inst), 65536);
+
+        let mut buf = [0u8; 4];
+        let dst = buf.as_mut_ptr() as *mut c_void;
+        assert!(matches!(rune_memory_read(inst, 0, dst, 4), RuneError::Ok));
+        assert_eq!(&buf, b"rune");
+        let src = 7u32.to_le_bytes();
+        let src = src.as_ptr() as *const c_void;
+        assert!(matches!(rune_memory_write(inst, 8, src, 4), RuneError::Ok));
+        let arg = RuneTypedVal {
+            ty: RuneValType::I32,
+            val: RuneVal { i32: 8 },
+        };
+        let mut out = arg;
+        rune_instance_call(inst, c"read".as_ptr(), &arg, 1, &mut out);
+        assert_eq!(out.val.i32, 7);
+        assert_eq!(*rune_memory_base(inst).add(8), 7);
+
+        // Out-of-range copies fail whole, without touching either side.
+        buf = [9; 4];
+        assert!(matches!(
+            rune_memory_read(inst, 65534, dst, 4),
+            RuneError::TrapOutOfBounds
+        ));
+        assert_eq!(buf, [9; 4]);
+        assert!(matches!(
+            rune_memory_write(inst, 65534, src, 4),
+            RuneError::TrapOutOfBounds
+        ));
+
+        assert!(matches!(rune_memory_grow(inst, 1), RuneError::Ok));
+        assert_eq!(rune_memory_size(inst), 2 * 65536);
+        assert!(matches!(
+            rune_memory_write(inst, 65534, src, 4),
+            RuneError::Ok
+        ));
+        assert!(matches!(rune_memory_grow(inst, 1), RuneError::OutOfMemory));
+
+        rune_instance_free(inst);
+        rune_module_free(module);
+        rune_runtime_free(rt);
+    }
+}
+
+#[test]
+fn test_ffi_caller_memory() {
+    use rune::ffi::*;
+    use std::ffi::c_void;
+
+    // Increments the word at its argument and returns the memory's size in
+    // pages, reaching memory through the caller while the call holds the
+    // instance.
+    unsafe extern "C-unwind" fn bump(
+        caller: *mut CCaller,
+        args: *const RuneTypedVal,
+        _n_args: usize,
+        result: *mut RuneTypedVal,
+        _user_data: *mut c_void,
+    ) -> RuneError {
+        let mut ptr = 0i32;
+        if !rune_val_as_i32(args, &mut ptr) {
+            return RuneError::TrapTypeMismatch;
+        }
+        let mut word = [0u8; 4];
+        let err = rune_caller_memory_read(caller, ptr as usize, word.as_mut_ptr().cast(), 4);
+        if !matches!(err, RuneError::Ok) {
+            return err;
+        }
+        let word = (u32::from_le_bytes(word) + 1).to_le_bytes();
+        let err = rune_caller_memory_write(caller, ptr as usize, word.as_ptr().cast(), 4);
+        if !matches!(err, RuneError::Ok) {
+            return err;
+        }
+        *result = rune_val_i32((rune_caller_memory_size(caller) / 65536) as i32);
+        RuneError::Ok
+    }
+
+    let unary = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
+    let mut m = Module::new();
+    let host = m.import("env", "bump", unary.clone());
+    m.functions.push(Function::new(
+        "run",
+        unary,
+        vec![],
+        vec![Op::LocalGet(0), Op::CallHost(host), Op::Return],
+    ));
+    m.exports.push(("run".into(), 0));
+    m.data_segments.push((8, 41u32.to_le_bytes().to_vec()));
+    let bytes = m.to_bytes();
+
+    unsafe {
+        assert!(rune_has_feature(c"caller-memory".as_ptr()));
+        assert_eq!(rune_caller_memory_size(std::ptr::null_mut()), 0);
+
+        let rt = rune_runtime_new();
+        let module = rune_module_load_bytes(rt, bytes.as_ptr(), bytes.len());
+        let linker = rune_linker_new();
+        let params = [RuneValType::I32];
+        let sig = RuneSignature {
+            params: params.as_ptr(),
+            n_params: 1,
+            result: RuneValType::I32,
+        };
+        let err = rune_linker_define_typed(
+            linker,
+            c"env".as_ptr(),
+            c"bump".as_ptr(),
+            &sig,
+            bump,
+            std::ptr::null_mut(),
+        );
+        assert!(matches!(err, RuneError::Ok));
+        let inst = rune_linker_instantiate(linker, rt, module);
+        rune_linker_free(linker);
+
+        let mut out = rune_val_void();
+        let err = rune_instance_call(inst, c"run".as_ptr(), &rune_val_i32(8), 1, &mut out);
+        assert!(matches!(err, RuneError::Ok));
+        let mut pages = 0i32;
+        assert!(rune_val_as_i32(&out, &mut pages));
+        assert_eq!(pages, 1);
+        let mut word = [0u8; 4];
+        let dst = word.as_mut_ptr() as *mut c_void;
+        assert!(matches!(rune_memory_read(inst, 8, dst, 4), RuneError::Ok));
+        assert_eq!(u32::from_le_bytes(word), 42);
+
+        // An out-of-range copy fails in the callback, which traps the guest.
+        let err = rune_instance_call(inst, c"run".as_ptr(), &rune_val_i32(65534), 1, &mut out);
+        assert!(matches!(err, RuneError::HostError));
+
+        rune_instance_free(inst);
+        rune_module_free(module);
+        rune_runtime_free(rt);
+    }
+}
+
+#[test]
+fn test_ffi_module_builder() {
+    use rune::ffi::*;
+
+    let op = |opcode: u8, imm: u64| RuneOp {
+        opcode,
+        imm,
+        offset: 0,
+        memory: 0,
+    };
+    unsafe {
+        let module = rune_module_new();
+        let params = [RuneValType::I32, RuneValType::I32];
+        let sig = RuneSignature {
+            params: params.as_ptr(),
+            n_params: 2,
+            result: RuneValType::I32,
+        };
+        // local.get 0; local.get 1; i32.add; i32.const 5; i32.mul
+        let body = [
+            op(0x84, 0),
+            op(0x84, 1),
+            op(0x09, 0),
+            op(0x80, 5),
+            op(0x0B, 0),
+        ];
+        let mut index = u32::MAX;
+        let err = rune_module_add_function(
+            module,
+            c"add_times_5".as_ptr(),
+            &sig,
+            std::ptr::null(),
+            0,
+            body.as_ptr(),
+            body.len(),
+            &mut index,
+        );
+        assert!(matches!(err, RuneError::Ok));
+        assert_eq!(index, 0);
+        assert!(matches!(
+            rune_module_add_export(module, c"f".as_ptr(), 0),
+            RuneError::Ok
+        ));
+        assert!(matches!(
+            rune_module_add_export(module, c"f".as_ptr(), 0),
+            RuneError::InvalidModule
+        ));
+        assert!(matches!(
+            rune_module_add_export(module, c"g".as_ptr(), 1),
+            RuneError::UndefinedExport
+        ));
+        // Pool-indexed constants and unassigned opcodes are rejected.
+        for bad in [op(0x96, 0), op(0xA0, 0), op(0x84, u64::MAX)] {
+            let err = rune_module_add_function(
+                module,
+                c"bad".as_ptr(),
+                &sig,
+                std::ptr::null(),
+                0,
+                &bad,
+                1,
+                std::ptr::null_mut(),
+            );
+            assert!(matches!(err, RuneError::InvalidModule));
+        }
+
+        let (mut data, mut len) = (std::ptr::null_mut(), 0);
+        assert!(matches!(
+            rune_module_to_bytes(module, &mut data, &mut len),
+            RuneError::Ok
+        ));
+        let parsed = Module::from_bytes(std::slice::from_raw_parts(data, len)).unwrap();
+        assert_eq!(parsed.functions.len(), 1);
+        assert_eq!(parsed.find_export("f"), Some(0));
+
+        let rt = rune_runtime_new();
+        let loaded = rune_module_load_bytes(rt, data, len);
+        rune_bytes_free(data, len);
+        let inst = rune_instance_new(rt, loaded);
+        let args = [
+            RuneTypedVal {
+                ty: RuneValType::I32,
+                val: RuneVal { i32: 3 },
+            },
+            RuneTypedVal {
+                ty: RuneValType::I32,
+                val: RuneVal { i32: 4 },
+            },
+        ];
+        let mut out = args[0];
+        let err = rune_instance_call(inst, c"f".as_ptr(), args.as_ptr(), 2, &mut out);
+        assert!(matches!(err, RuneError::Ok));
+        assert_eq!(out.val.i32, 35);
+
+        rune_instance_free(inst);
+        rune_module_free(loaded);
+        rune_module_free(module);
+        rune_runtime_free(rt);
+    }
+}
+
+#[test]
+fn test_ffi_last_error_message() {
+    use rune::ffi::*;
+    use std::ffi::CStr;
+
+    let last = || {
+        unsafe { CStr::from_ptr(rune_last_error_message()) }
+            .to_str()
+            .unwrap()
+    };
+    let mut bytes = read_word_module().to_bytes();
+    bytes.truncate(bytes.len() - 2);
+    unsafe {
+        let rt = rune_runtime_new();
+        assert!(rune_module_load_bytes(rt, bytes.as_ptr(), bytes.len()).is_null());
+        // The data count is the last field; half of it is missing.
+        let at = bytes.len() - 2;
+        assert_eq!(
+            last(),
+            format!("invalid module: truncated data count at offset {at:#x}")
+        );
+
+        let good = read_word_module().to_bytes();
+        let module = rune_module_load_bytes(rt, good.as_ptr(), good.len());
+        let inst = rune_instance_new(rt, module);
+        // A success leaves the last failure in place.
+        assert!(last().contains("truncated data count"));
+
+        let err = rune_instance_call(
+            inst,
+            c"missing".as_ptr(),
+            std::ptr::null(),
+            0,
+            std::ptr::null_mut(),
+        );
+        assert!(matches!(err, RuneError::UndefinedExport));
+        assert_eq!(last(), "undefined export: missing");
+
+        let arg = RuneTypedVal {
+            ty: RuneValType::I32,
+            val: RuneVal { i32: 1 << 20 },
+        };
+        let err = rune_instance_call(inst, c"read".as_ptr(), &arg, 1, std::ptr::null_mut());
+        assert!(matches!(err, RuneError::TrapOutOfBounds));
+        assert_eq!(last(), Trap::OutOfBounds.to_string());
+
+        // Messages are per thread.
+        std::thread::spawn(|| assert!(rune_last_error_message().is_null()))
+            .join()
+            .unwrap();
+
+        rune_instance_free(inst);
+        rune_module_free(module);
+        rune_runtime_free(rt);
+    }
+}
+
+#[test]
+fn test_ffi_abi_version() {
+    use rune::ffi::*;
+
+    let version = rune_abi_version();
+    assert_eq!(version >> 16, ABI_VERSION.major);
+    assert_eq!(version & 0xFFFF, ABI_VERSION.minor);
+    unsafe {
+        assert!(rune_has_feature(c"module-builder".as_ptr()));
+        assert!(rune_has_feature(c"last-error".as_ptr()));
+        // Known op families report whether this build executes them.
+        assert_eq!(
+            rune_has_feature(c"simd".as_ptr()),
+            Features::SUPPORTED.contains(Features::SIMD)
+        );
+        assert!(!rune_has_feature(c"teleportation".as_ptr()));
+        assert!(!rune_has_feature(std::ptr::null()));
+    }
+}
+
+#[test]
+fn test_ffi_fuel_and_deadline() {
+    use rune::ffi::*;
+    use std::ptr;
+
+    let bytes = spin_module().to_bytes();
+    unsafe {
+        let rt = rune_runtime_new();
+        let module = rune_module_load_bytes(rt, bytes.as_ptr(), bytes.len());
+        let inst = rune_instance_new(rt, module);
+
+        let mut fuel = 0;
+        assert!(!rune_instance_remaining_fuel(inst, &mut fuel));
+        assert!(matches!(rune_instance_set_fuel(inst, 1000), RuneError::Ok));
+        let err = rune_instance_call(inst, c"spin".as_ptr(), ptr::null(), 0, ptr::null_mut());
+        assert!(matches!(err, RuneError::OutOfFuel));
+        assert!(rune_instance_remaining_fuel(inst, &mut fuel));
+        assert_eq!(fuel, 0);
+        rune_instance_clear_fuel(inst);
+        assert!(!rune_instance_remaining_fuel(inst, &mut fuel));
+
+        let err =
+            rune_call_with_deadline_ms(inst, c"spin".as_ptr(), ptr::null(), 0, ptr::null_mut(), 20);
+        assert!(matches!(err, RuneError::Interrupted));
+        let err = rune_call_with_deadline_ms(
+            inst,
+            c"missing".as_ptr(),
+            ptr::null(),
+            0,
+            ptr::null_mut(),
+            20,
+        );
+        assert!(matches!(err, RuneError::UndefinedExport));
+
+        rune_instance_free(inst);
+        rune_module_free(module);
+        rune_runtime_free(rt);
+    }
+}
+
+#[test]
+fn test_ffi_export_enumeration() {
+    use rune::ffi::*;
+    use std::ffi::CStr;
+    use std::ptr;
+
+    let mut m = read_word_module();
+    m.functions.push(Function::new(
+        "mix",
+        FuncType {
+            params: vec![ValType::I64, ValType::F64, ValType::I32],
+            results: vec![],
+        },
+        vec![],
+        vec![Op::Return],
+    ));
+    m.exports.push(("mix".into(), 1));
+    let bytes = m.to_bytes();
+    unsafe {
+        let rt = rune_runtime_new();
+        let module = rune_module_load_bytes(rt, bytes.as_ptr(), bytes.len());
+        assert_eq!(rune_module_export_count(module), 2);
+        let name = |i| {
+            let p = rune_module_export_name(module, i);
+            (!p.is_null()).then(|| CStr::from_ptr(p).to_str().unwrap().to_owned())
+        };
+        assert_eq!(name(0).as_deref(), Some("read"));
+        assert_eq!(name(1).as_deref(), Some("mix"));
+        assert_eq!(name(2), None);
+
+        let mut params = [RuneValType::Void; 2];
+        let (mut n, mut result) = (0, RuneValType::Void);
+        let err =
+            rune_module_export_signature(module, 1, params.as_mut_ptr(), 2, &mut n, &mut result);
+        assert!(matches!(err, RuneError::Ok));
+        // The full count is reported even when the buffer is too small.
+        assert_eq!(n, 3);
+        assert_eq!(params, [RuneValType::I64, RuneValType::F64]);
+        assert_eq!(result, RuneValType::Void);
+        let err = rune_module_export_signature(module, 0, ptr::null_mut(), 0, &mut n, &mut result);
+        assert!(matches!(err, RuneError::Ok));
+        assert_eq!((n, result), (1, RuneValType::I32));
+        assert!(matches!(
+            rune_module_export_signature(module, 2, ptr::null_mut(), 0, &mut n, ptr::null_mut()),
+            RuneError::UndefinedExport
+        ));
+
+        // Names are rebuilt after the builder changes the module.
+        assert!(matches!(
+            rune_module_add_export(module, c"again".as_ptr(), 0),
+            RuneError::Ok
+        ));
+        assert_eq!(name(2).as_deref(), Some("again"));
+
+        rune_module_free(module);
+        rune_runtime_free(rt);
+    }
+}
+
+#[test]
+fn test_ffi_trap_callback() {
+    use rune::ffi::*;
+    use std::ffi::{c_char, c_void, CStr};
+
+    #[derive(Default)]
+    struct Seen {
+        code: Option<i32>,
+        message: String,
+        frames: Vec<(u32, String, usize, String, u32)>,
+    }
+    unsafe extern "C-unwind" fn record(
+        code: RuneError,
+        message: *const c_char,
+        frames: *const RuneFrame,
+        n_frames: usize,
+        user_data: *mut c_void,
+    ) {
+        let seen = &mut *(user_data as *mut Seen);
+        let s = |p: *const c_char| {
+            if p.is_null() {
+                String::new()
+            } else {
+                CStr::from_ptr(p).to_string_lossy().into_owned()
+            }
+        };
+        seen.code = Some(code as i32);
+        seen.message = s(message);
+        seen.frames = std::slice::from_raw_parts(frames, n_frames)
+            .iter()
+            .map(|f| (f.func_index, s(f.func_name), f.op_index, s(f.file), f.line))
+            .collect();
+    }
+
+    // div(a, b) = a / b, called through outer(a, b).
+    let mut m = Module::new();
+    m.functions.push(func(
+        "div",
+        vec![ValType::I32, ValType::I32],
+        vec![ValType::I32],
+        vec![],
+        vec![Op::LocalGet(0), Op::LocalGet(1), Op::I32DivS, Op::Return],
+    ));
+    m.functions.push(func(
+        "outer",
+        vec![ValType::I32, ValType::I32],
+        vec![ValType::I32],
+        vec![],
+        vec![Op::LocalGet(0), Op::LocalGet(1), Op::Call(0), Op::Return],
+    ));
+    m.exports.push(("outer".into(), 1));
+    let mut sm = SourceMap::new();
+    let file = sm.add_file("math.c");
+    sm.add_entry(
+        0,
+        2,
+        SourceLoc {
+            file,
+            line: 3,
+            column: 5,
+        },
+    );
+    m.source_map = Some(sm);
+    let bytes = m.to_bytes();
+
+    let mut seen = Seen::default();
+    unsafe {
+        let rt = rune_runtime_new();
+        let module = rune_module_load_bytes(rt, bytes.as_ptr(), bytes.len());
+        let inst = rune_instance_new(rt, module);
+        // Registered after the instance exists, and still applies to it.
+        let user_data = &mut seen as *mut Seen as *mut c_void;
+        rune_runtime_set_trap_callback(rt, Some(record), user_data);
+
+        let arg = |v| RuneTypedVal {
+            ty: RuneValType::I32,
+            val: RuneVal { i32: v },
+        };
+        let args = [arg(1), arg(0)];
+        let err = rune_instance_call(
+            inst,
+            c"outer".as_ptr(),
+            args.as_ptr(),
+            2,
+            std::ptr::null_mut(),
+        );
+        assert!(matches!(err, RuneError::TrapDivZero));
+        assert_eq!(seen.code, Some(RuneError::TrapDivZero as i32));
+        assert_eq!(seen.message, Trap::DivisionByZero.to_string());
+        assert_eq!(
+            seen.frames,
+            [
+                (0, "div".into(), 2, "math.c".into(), 3),
+                (1, "outer".into(), 2, String::new(), 0),
+            ]
+        );
+
+        // Argument errors are not traps; removing the callback stops reports.
+        seen.code = None;
+        let err = rune_instance_call(
+            inst,
+            c"nope".as_ptr(),
+            std::ptr::null(),
+            0,
+            std::ptr::null_mut(),
+        );
+        assert!(matches!(err, RuneError::UndefinedExport));
+        assert_eq!(seen.code, None);
+        rune_runtime_set_trap_callback(rt, None, std::ptr::null_mut());
+        rune_instance_call(
+            inst,
+            c"outer".as_ptr(),
+            args.as_ptr(),
+            2,
+            std::ptr::null_mut(),
+        );
+        assert_eq!(seen.code, None);
+
+        rune_instance_free(inst);
+        rune_module_free(module);
+        rune_runtime_free(rt);
+    }
+}
+
+#[test]
+fn test_ffi_handles() {
+    use rune::ffi::handles::*;
+    use rune::ffi::*;
+    use std::ffi::c_void;
+
+    let mut m = read_word_module();
+    m.data_segments.push((0, 5u32.to_le_bytes().to_vec()));
+    let bytes = m.to_bytes();
+    unsafe {
+        let rt = rune_h_runtime_new();
+        let module = rune_h_module_load_bytes(rt, bytes.as_ptr(), bytes.len());
+        let inst = rune_h_instance_new(rt, module);
+        assert!(rt != 0 && module != 0 && inst != 0);
+        // The instance keeps what it needs alive.
+        assert!(matches!(rune_handle_release(module), RuneError::Ok));
+        assert!(matches!(rune_handle_release(rt), RuneError::Ok));
+
+        let src = 9u32.to_le_bytes();
+        let err = rune_h_memory_write(inst, 4, src.as_ptr() as *const c_void, 4);
+        assert!(matches!(err, RuneError::Ok));
+        let mut dst = [0u8; 4];
+        let err = rune_h_memory_read(inst, 0, dst.as_mut_ptr() as *mut c_void, 4);
+        assert!(matches!(err, RuneError::Ok));
+        assert_eq!(u32::from_le_bytes(dst), 5);
+        let arg = RuneTypedVal {
+            ty: RuneValType::I32,
+            val: RuneVal { i32: 4 },
+        };
+        let mut out = arg;
+        let err = rune_h_instance_call(inst, c"read".as_ptr(), &arg, 1, &mut out);
+        assert!(matches!(err, RuneError::Ok));
+        assert_eq!(out.val.i32, 9);
+
+        // Stale and mistyped handles are rejected, even once the slot is reused.
+        assert_eq!(rune_h_instance_new(rt, module), 0);
+        let rt2 = rune_h_runtime_new();
+        assert_ne!(rt2, rt);
+        assert_eq!(rune_h_module_load_bytes(rt, bytes.as_ptr(), bytes.len()), 0);
+        let err = rune_h_instance_call(rt2, c"read".as_ptr(), &arg, 1, &mut out);
+        assert!(matches!(err, RuneError::InvalidArgument));
+        assert!(matches!(
+            rune_handle_release(rt),
+            RuneError::InvalidArgument
+        ));
+        assert!(matches!(rune_handle_release(0), RuneError::InvalidArgument));
+
+        assert!(matches!(rune_handle_release(inst), RuneError::Ok));
+        let err = rune_h_instance_call(inst, c"read".as_ptr(), &arg, 1, &mut out);
+        assert!(matches!(err, RuneError::InvalidArgument));
+        rune_handle_release(rt2);
+    }
+}
+
+#[test]
+#[allow(deprecated)]
+fn test_ffi_handle_linker() {
+    use rune::ffi::handles::*;
+    use rune::ffi::*;
+    use std::ffi::c_void;
+
+    unsafe extern "C-unwind" fn negate(
+        _caller: *mut CCaller,
+        args: *const RuneVal,
+        _n_args: usize,
+        result: *mut RuneVal,
+        _user_data: *mut c_void,
+    ) -> RuneError {
+        (*result).i32 = -(*args).i32;
+        RuneError::Ok
+    }
+
+    let unary = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
+    let mut m = Module::new();
+    let host = m.import("env", "negate", unary.clone());
+    m.functions.push(Function::new(
+        "run",
+        unary,
+        vec![],
+        vec![Op::LocalGet(0), Op::CallHost(host), Op::Return],
+    ));
+    m.exports.push(("run".into(), 0));
+    let bytes = m.to_bytes();
+
+    unsafe {
+        let rt = rune_h_runtime_new();
+        let module = rune_h_module_load_bytes(rt, bytes.as_ptr(), bytes.len());
+        let linker = rune_h_linker_new();
+        assert_eq!(rune_h_linker_instantiate(linker, rt, module), 0);
+        assert_eq!(rune_h_linker_instantiate(module, rt, module), 0);
+
+        let params = [RuneValType::I32];
+        let sig = RuneSignature {
+            params: params.as_ptr(),
+            n_params: 1,
+            result: RuneValType::I32,
+        };
+        let err = rune_h_linker_define(
+            linker,
+            c"env".as_ptr(),
+            c"negate".as_ptr(),
+            &sig,
+            negate,
+            std::ptr::null_mut(),
+        );
+        assert!(matches!(err, RuneError::Ok));
+        let inst = rune_h_linker_instantiate(linker, rt, module);
+        assert_ne!(inst, 0);
+        // Instances outlive the linker they were created through.
+        rune_handle_release(linker);
+
+        let arg = RuneTypedVal {
+            ty: RuneValType::I32,
+            val: RuneVal { i32: 7 },
+        };
+        let mut out = arg;
+        let err = rune_h_instance_call(inst, c"run".as_ptr(), &arg, 1, &mut out);
+        assert!(matches!(err, RuneError::Ok));
+        assert_eq!(out.val.i32, -7);
+
+        for handle in [inst, module, rt] {
+            assert!(matches!(rune_handle_release(handle), RuneError::Ok));
+        }
+    }
+}
+
+// ── Verifier ──────────────────────────────────────────────────────────────────
+
+#[test]
+fn test_verifier_diagnostics() {
+    use rune::verify::{verify_module, VerifyError};
+
+    let config = RuntimeConfig::new();
+    let check = |result: Option<ValType>, body: Vec<Op>| {
+        verify_module(&single_func("f", &[ValType::I32], result, body), &config)
+    };
+    let at = |op: usize, message: &str| {
+        Err(VerifyError {
+            func: Some(0),
+            name: "f".into(),
+            op: Some(op),
+            message: message.into(),
+        })
+    };
+
+    let ok = [
+        (
+            Some(ValType::I32),
+            vec![Op::LocalGet(0), Op::I32Const(1), Op::I32Add, Op::Return],
+        ),
+        // Falling off the end returns.
+        (Some(ValType::I64), vec![Op::I64Const(1)]),
+        (
+            Some(ValType::I32),
+            vec![
+                Op::Block(BlockType::Val(ValType::I32)),
+                Op::LocalGet(0),
+                Op::LocalGet(0),
+                Op::BrIf(0),
+                Op::Drop,
+                Op::I32Const(2),
+                Op::End,
+                Op::End,
+            ],
+        ),
+        (
+            None,
+            vec![
+                Op::Loop(BlockType::Empty),
+                Op::LocalGet(0),
+                Op::If(BlockType::Empty),
+                Op::Br(1),
+                Op::Else,
+                Op::Nop,
+                Op::End,
+                Op::End,
+            ],
+        ),
+        // Unreachable code is checked against an unknown stack.
+        (
+            Some(ValType::F64),
+            vec![Op::Unreachable, Op::I32Add, Op::Drop, Op::F64Const(1.0)],
+        ),
+    ];
+    for (result, body) in ok {
+        assert_eq!(check(result, body.clone()), Ok(()), "{body:?}");
+    }
+
+    let i32_result = Some(ValType::I32);
+    assert_eq!(
+        check(i32_result, vec![Op::LocalGet(0), Op::I64Eqz]),
+        at(1, "`i64.eqz`: expected i64, found i32")
+    );
+    assert_eq!(
+        check(
+            None,
+            vec![
+                Op::I32Const(1),
+                Op::Block(BlockType::Empty),
+                Op::Drop,
+                Op::End
+            ]
+        ),
+        at(2, "`drop`: nothing on the stack within the block")
+    );
+    assert_eq!(
+        check(None, vec![Op::Block(BlockType::Empty), Op::Nop]),
+        Err(VerifyError {
+            func: Some(0),
+            name: "f".into(),
+            op: Some(0),
+            message: "block is never closed by an `end`".into(),
+        })
+    );
+    assert_eq!(
+        check(None, vec![Op::Block(BlockType::Empty), Op::Br(1), Op::End]),
+        at(1, "`br`: branch depth 1, but only 1 blocks enclose it")
+    );
+    assert_eq!(
+        check(
+            i32_result,
+            vec![
+                Op::LocalGet(0),
+                Op::If(BlockType::Val(ValType::I32)),
+                Op::I32Const(1),
+                Op::End,
+            ]
+        ),
+        at(3, "`end`: `if` at op 1 has a result but no `else`")
+    );
+    assert_eq!(
+        check(None, vec![Op::Else]),
+        at(0, "`else`: `else` outside an `if`")
+    );
+    assert_eq!(
+        check(None, vec![Op::I32Const(1), Op::End]),
+        at(1, "`end`: 1 value left on the stack")
+    );
+    assert_eq!(
+        check(None, vec![Op::End, Op::Nop]),
+        at(1, "op after the function's final `end`")
+    );
+    assert_eq!(
+        check(
+            None,
+            vec![
+                Op::LocalGet(0),
+                Op::LocalGet(0),
+                Op::I32Store {
+                    align: 3,
+                    offset: 0,
+                    memory: 0,
+                },
+            ]
+        ),
+        at(
+            2,
+            "`i32.store`: alignment 2^3 exceeds the access's natural 4"
+        )
+    );
+    assert_eq!(
+        check(None, vec![Op::LocalGet(1), Op::Drop]),
+        at(0, "`local.get`: no local 1; the function has 1")
+    );
+
+    let mut m = single_func("f", &[], None, vec![Op::I32Const(1), Op::GlobalSet(0)]);
+    m.import_global("env", "g", ValType::I32, false);
+    let err = verify_module(&m, &config).unwrap_err();
+    assert_eq!(err.op, Some(1));
+    assert!(err.message.contains("immutable"), "{err}");
+    assert_eq!(
+        err.to_string(),
+        format!("function `f` (func[0]) op 1: {}", err.message)
+    );
+}
+
+#[test]
+fn test_verify_on_instantiation() {
+    let m = single_func(
+        "bad",
+        &[],
+        Some(ValType::I32),
+        vec![Op::I64Const(1), Op::Return],
+    );
+    // Without the verifier the module is not checked up front.
+    assert!(rt().instantiate(&m).is_ok());
+
+    let mut config = RuntimeConfig::new();
+    config.set_verify(true);
+    let rt = Runtime::with_config(config);
+    match rt.instantiate(&m) {
+        Err(Trap::InvalidModule(msg)) => {
+            assert_eq!(
+                msg,
+                "function `bad` (func[0]) op 1: `return`: expected i32, found i64"
+            )
+        }
+        other => panic!("expected InvalidModule, got {:?}", other.map(|_| ())),
+    }
+
+    // Functions swapped in are verified too.
+    let good = single_func("good", &[], Some(ValType::I32), vec![Op::I32Const(1)]);
+    let mut inst = rt.instantiate(&good).unwrap();
+    let bad = m.functions[0].clone();
+    assert!(matches!(
+        inst.replace_function(
+            0,
+            Function {
+                name: "good".into(),
+                ..bad
+            }
+        ),
+        Err(Trap::InvalidModule(_))
+    ));
+    assert_eq!(inst.call("good", &[]), Ok(Some(Val::I32(1))));
+}
+
+#[test]
+fn test_peephole_optimizer() {
+    use rune::opt::{optimize, Pass};
+    use rune::sourcemap::{SourceLoc, SourceMap};
+
+    let i32_to_i32 = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
     let mut m = Module::new();
-    m.register_host(
-        "log",
+    m.functions.push(Function::new(
+        "mix",
+        i32_to_i32.clone(),
+        vec![ValType::I32, ValType::I32],
+        vec![
+            Op::LocalGet(0),
+            Op::I32Const(8),
+            Op::I32Mul,
+            Op::I32Const(0),
+            Op::I32Add,
+            Op::LocalSet(1),
+            Op::LocalGet(0),
+            Op::LocalSet(2), // never read
+            Op::LocalGet(1),
+            Op::I32Const(16),
+            Op::I32RemU,
+            Op::LocalGet(1),
+            Op::I32Const(4),
+            Op::I32DivU,
+            Op::I32Add,
+            Op::F64Const(1.5),
+            Op::F64Neg,
+            Op::F64Neg,
+            Op::Drop,
+            Op::LocalGet(0), // 19: line 2
+            Op::I32Const(10),
+            Op::I32LtS,
+            Op::I32Eqz,
+            Op::If(BlockType::Val(ValType::I32)),
+            Op::I32Const(1),
+            Op::Else,
+            Op::I32Const(2),
+            Op::End,
+            Op::I32Add,
+            Op::Return,
+        ],
+    ));
+    m.functions.push(Function::new(
+        "count",
+        i32_to_i32.clone(),
+        vec![ValType::I32],
+        vec![
+            Op::Loop(BlockType::Empty),
+            Op::LocalGet(1),
+            Op::LocalGet(0),
+            Op::I32Add,
+            Op::LocalSet(1),
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32Sub,
+            Op::LocalTee(0),
+            Op::I32Eqz,
+            Op::I32Eqz,
+            Op::BrIf(0),
+            Op::End,
+            Op::LocalGet(1),
+        ],
+    ));
+    m.functions.push(Function::new(
+        "bad",
+        i32_to_i32,
+        vec![],
+        vec![Op::I32Add, Op::Nop],
+    ));
+    m.exports.push(("mix".into(), 0));
+    m.exports.push(("count".into(), 1));
+    let mut sm = SourceMap::new();
+    let file = sm.add_file("mix.c");
+    for (op, line) in [(0, 1), (19, 2)] {
+        sm.add_entry(
+            0,
+            op,
+            SourceLoc {
+                file,
+                line,
+                column: 1,
+            },
+        );
+    }
+    m.source_map = Some(sm);
+
+    let mut optimized = m.clone();
+    let report = optimize(&mut optimized, &RuntimeConfig::new(), Pass::ALL);
+    assert_eq!(report.unverified, vec![2]);
+    assert_eq!(report.ops_before, 30 + 14 + 2);
+    assert_eq!(report.ops_after, 20 + 12 + 2);
+    let mix = &optimized.functions[0].body;
+    for op in [
+        Op::I32Shl,
+        Op::I32And,
+        Op::I32ShrU,
+        Op::I32GeS,
+        Op::LocalTee(1),
+    ] {
+        assert!(mix.contains(&op), "{op:?} in {mix:?}");
+    }
+    for op in [Op::I32Mul, Op::F64Neg, Op::I32Eqz, Op::LocalSet(2)] {
+        assert!(!mix.contains(&op), "{op:?} in {mix:?}");
+    }
+    assert!(!optimized.functions[1].body.contains(&Op::I32Eqz));
+    assert_eq!(optimized.functions[2].body, m.functions[2].body);
+    // The condition's source line moved with it.
+    let at = mix.iter().position(|op| *op == Op::I32GeS).unwrap() - 2;
+    let sm = optimized.source_map.as_ref().unwrap();
+    assert_eq!(sm.lookup(0, at).map(|loc| loc.line), Some(2));
+    assert_eq!(sm.lookup(0, at - 1).map(|loc| loc.line), Some(1));
+
+    let mut before = rt().instantiate(&m).unwrap();
+    let mut after = rt().instantiate(&optimized).unwrap();
+    for x in [0, 1, 9, 10, 11, 1000, -1, -8, i32::MIN, i32::MAX] {
+        for func in ["mix", "count"] {
+            if func == "count" && !(1..=1000).contains(&x) {
+                continue;
+            }
+            assert_eq!(
+                after.call(func, &[Val::I32(x)]),
+                before.call(func, &[Val::I32(x)]),
+                "{func}({x})"
+            );
+        }
+    }
+}
+
+#[test]
+fn test_profile_guided_optimization() {
+    use rune::opt::{optimize, optimize_with_profile, Pass};
+    use rune::profile::{Branch, Profile};
+
+    let i32_to_i32 = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
+    let mut m = Module::new();
+    m.functions.push(Function::new(
+        "scale",
+        i32_to_i32.clone(),
+        vec![ValType::I32],
+        vec![
+            Op::LocalGet(0),
+            Op::I32Const(5),
+            Op::I32Eq,
+            Op::If(BlockType::Empty),
+            Op::I32Const(7),
+            Op::Return,
+            Op::End,
+            Op::LocalGet(0),
+            Op::I32Const(3),
+            Op::I32Mul,
+            Op::LocalSet(1),
+            Op::LocalGet(1),
+            Op::I32Const(1),
+            Op::I32Add,
+        ],
+    ));
+    m.functions.push(Function::new(
+        "sum",
+        i32_to_i32,
+        vec![ValType::I32],
+        vec![
+            Op::Block(BlockType::Empty),
+            Op::LocalGet(0),
+            Op::I32Eqz,
+            Op::BrIf(0),
+            Op::Loop(BlockType::Empty),
+            Op::LocalGet(0),
+            Op::I32Const(1000),
+            Op::I32GtS,
+            Op::If(BlockType::Val(ValType::I32)), // 8: mostly false
+            Op::I32Const(1),
+            Op::Else,
+            Op::LocalGet(0),
+            Op::Call(0), // 12: hot
+            Op::End,
+            Op::LocalGet(1),
+            Op::I32Add,
+            Op::LocalSet(1),
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32Sub,
+            Op::LocalTee(0),
+            Op::BrIf(0), // 21
+            Op::End,
+            Op::End,
+            Op::LocalGet(1),
+        ],
+    ));
+    m.exports.push(("sum".into(), 1));
+
+    // Record in staging.
+    let mut config = RuntimeConfig::new();
+    config.set_profiling(true);
+    let mut inst = Runtime::with_config(config).instantiate(&m).unwrap();
+    assert_eq!(inst.call("sum", &[Val::I32(20)]), Ok(Some(Val::I32(641))));
+    let profile = inst.profile().unwrap().clone();
+    assert!(profile.is_of(&m));
+    assert_eq!(profile.calls(0), 20);
+    assert_eq!(profile.calls(1), 1);
+    let sum = &profile.functions[1];
+    assert_eq!(sum.call_sites.get(&12), Some(&20));
+    let branch = |taken, not_taken| Some(Branch { taken, not_taken });
+    assert_eq!(sum.branches.get(&8).copied(), branch(0, 20));
+    assert_eq!(sum.branches.get(&21).copied(), branch(19, 1));
+    assert_eq!(profile.to_string().parse::<Profile>(), Ok(profile.clone()));
+    let mut twice = profile.clone();
+    twice.merge(&profile).unwrap();
+    assert_eq!(twice.calls(0), 40);
+    assert!("rune-profile 1\nfunc 0 1 f\n".parse::<Profile>().is_err());
+    assert!(rt().instantiate(&m).unwrap().profile().is_none());
+
+    // Optimize for production.
+    let mut unguided = m.clone();
+    optimize(&mut unguided, &RuntimeConfig::new(), Pass::ALL);
+    assert!(unguided.functions[1].body.contains(&Op::Call(0)));
+    let mut optimized = m.clone();
+    optimize_with_profile(&mut optimized, &RuntimeConfig::new(), Pass::ALL, &profile).unwrap();
+    let body = &optimized.functions[1].body;
+    assert!(!body.contains(&Op::Call(0)), "{body:?}");
+    assert_eq!(optimized.functions[1].locals.len(), 3);
+    // The hot else branch now falls through, under the opposite test.
+    let at = body.iter().position(|op| *op == Op::I32LeS).unwrap();
+    assert_eq!(body[at + 1], Op::If(BlockType::Val(ValType::I32)));
+    assert!(Profile::new(&Module::new()).merge(&profile).is_err());
+    assert!(optimize_with_profile(
+        &mut optimized.clone(),
+        &RuntimeConfig::new(),
+        Pass::ALL,
+        &Profile::new(&Module::new())
+    )
+    .is_err());
+
+    let mut before = rt().instantiate(&m).unwrap();
+    let mut after = rt().instantiate(&optimized).unwrap();
+    for n in [0, 1, 5, 6, 20, 1003] {
+        assert_eq!(
+            after.call("sum", &[Val::I32(n)]),
+            before.call("sum", &[Val::I32(n)]),
+            "sum({n})"
+        );
+    }
+}
+
+// ── Cranelift backend ─────────────────────────────────────────────────────────
+
+/// Run each call on the interpreter and on Cranelift's code, expecting the
+/// same results, backtraces and fuel left.
+#[cfg(feature = "cranelift")]
+fn assert_native_matches(m: &Module, fuel: Option<u64>, calls: &[(&str, Vec<Val>)]) {
+    assert_strategy_matches(rune::config::Strategy::Cranelift, m, fuel, calls);
+}
+
+/// Run each call on the interpreter and under `strategy`, expecting the
+/// same results, backtraces and fuel left.
+#[cfg(feature = "cranelift")]
+fn assert_strategy_matches(
+    strategy: rune::config::Strategy,
+    m: &Module,
+    fuel: Option<u64>,
+    calls: &[(&str, Vec<Val>)],
+) {
+    use rune::config::Strategy;
+
+    let runtime = |strategy| {
+        let mut config = RuntimeConfig::new();
+        config.set_strategy(strategy);
+        if let Some(fuel) = fuel {
+            config.set_fuel(fuel);
+        }
+        Runtime::with_config(config)
+    };
+    let interpreter = runtime(Strategy::Interpreter);
+    let native = runtime(strategy);
+    let mut expected = interpreter.instantiate(m).unwrap();
+    let mut actual = native.instantiate(m).unwrap();
+    for (name, args) in calls {
+        assert_eq!(
+            actual.call(name, args),
+            expected.call(name, args),
+            "{name}{args:?}"
+        );
+        assert_eq!(actual.trap_backtrace(), expected.trap_backtrace());
+        assert_eq!(actual.fuel(), expected.fuel(), "fuel after {name}{args:?}");
+    }
+}
+
+#[cfg(feature = "cranelift")]
+fn fib_module() -> Module {
+    single_func(
+        "fib",
+        &[ValType::I32],
+        Some(ValType::I32),
+        vec![
+            Op::LocalGet(0),
+            Op::I32Const(2),
+            Op::I32LtS,
+            Op::If(BlockType::Val(ValType::I32)),
+            Op::LocalGet(0),
+            Op::Else,
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32Sub,
+            Op::Call(0),
+            Op::LocalGet(0),
+            Op::I32Const(2),
+            Op::I32Sub,
+            Op::Call(0),
+            Op::I32Add,
+            Op::End,
+            Op::Return,
+        ],
+    )
+}
+
+#[cfg(feature = "cranelift")]
+fn sum_to_module() -> Module {
+    // sum = 0; i = n; loop { if i == 0 break; sum += i; i -= 1 }
+    let mut m = single_func(
+        "sum_to",
+        &[ValType::I32],
+        Some(ValType::I64),
+        vec![
+            Op::Block(BlockType::Empty),
+            Op::Loop(BlockType::Empty),
+            Op::LocalGet(0),
+            Op::I32Eqz,
+            Op::BrIf(1),
+            Op::LocalGet(1),
+            Op::LocalGet(0),
+            Op::I64ExtendI32U,
+            Op::I64Add,
+            Op::LocalSet(1),
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32Sub,
+            Op::LocalSet(0),
+            Op::Br(0),
+            Op::End,
+            Op::End,
+            Op::LocalGet(1),
+            Op::Return,
+        ],
+    );
+    m.functions[0].locals = vec![ValType::I64];
+    m
+}
+
+#[cfg(feature = "cranelift")]
+#[test]
+fn test_cranelift_compiles_module() {
+    use rune::compiler::codegen;
+
+    let code = codegen::compile(&fib_module(), &RuntimeConfig::new()).unwrap();
+    assert_eq!(code.functions(), 1);
+    assert!(code.is_compiled(0));
+    assert_eq!(code.compiled_functions(), 1);
+
+    // A block whose two paths leave different stack heights is left to the
+    // interpreter, and still runs.
+    let mut m = sum_to_module();
+    m.functions.push(Function::new(
+        "uneven",
         FuncType {
             params: vec![ValType::I32],
-            results: vec![],
+            results: vec![ValType::I32],
         },
-        move |args| {
-            log2.lock().unwrap().push(args[0].as_i32().unwrap());
-            Ok(None)
+        vec![],
+        vec![
+            Op::I32Const(7),
+            Op::Block(BlockType::Empty),
+            Op::I32Const(1),
+            Op::LocalGet(0),
+            Op::BrIf(0),
+            Op::End,
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("uneven".into(), 1));
+    let code = codegen::compile(&m, &RuntimeConfig::new()).unwrap();
+    assert!(code.is_compiled(0));
+    assert!(!code.is_compiled(1));
+    assert_native_matches(
+        &m,
+        None,
+        &[
+            ("uneven", vec![Val::I32(0)]),
+            ("uneven", vec![Val::I32(1)]),
+            ("sum_to", vec![Val::I32(10)]),
+        ],
+    );
+}
+
+#[cfg(feature = "cranelift")]
+#[test]
+fn test_cranelift_matches_interpreter() {
+    assert_native_matches(
+        &fib_module(),
+        None,
+        &[
+            ("fib", vec![Val::I32(0)]),
+            ("fib", vec![Val::I32(1)]),
+            ("fib", vec![Val::I32(20)]),
+        ],
+    );
+    assert_native_matches(
+        &sum_to_module(),
+        Some(1_000_000),
+        &[
+            ("sum_to", vec![Val::I32(0)]),
+            ("sum_to", vec![Val::I32(1000)]),
+        ],
+    );
+
+    let binary = |name: &str, ty: ValType, op: Op| {
+        single_func(
+            name,
+            &[ty, ty],
+            Some(ty),
+            vec![Op::LocalGet(0), Op::LocalGet(1), op, Op::Return],
+        )
+    };
+    let i32_pairs = [(7, 2), (-7, 2), (5, 0), (i32::MIN, -1), (1, 33)];
+    for op in [
+        Op::I32DivS,
+        Op::I32DivU,
+        Op::I32RemS,
+        Op::I32RemU,
+        Op::I32Shl,
+        Op::I32ShrS,
+        Op::I32ShrU,
+    ] {
+        let calls: Vec<_> = i32_pairs
+            .iter()
+            .map(|&(a, b)| ("f", vec![Val::I32(a), Val::I32(b)]))
+            .collect();
+        assert_native_matches(&binary("f", ValType::I32, op), None, &calls);
+    }
+    let i64_pairs = [(7, -2), (i64::MIN, -1), (9, 0)];
+    for op in [Op::I64DivS, Op::I64RemS, Op::I64DivU, Op::I64Shl] {
+        let calls: Vec<_> = i64_pairs
+            .iter()
+            .map(|&(a, b)| ("f", vec![Val::I64(a), Val::I64(b)]))
+            .collect();
+        assert_native_matches(&binary("f", ValType::I64, op), None, &calls);
+    }
+    // A NaN operand gives the other one, as Rust's `min` and `max` do.
+    for op in [Op::F64Min, Op::F64Max, Op::F64Div] {
+        let calls: Vec<_> = [(1.5, -2.0), (f64::NAN, 3.0), (3.0, f64::NAN), (1.0, 0.0)]
+            .iter()
+            .filter(|(a, b)| !matches!(op, Op::F64Div) || !(a.is_nan() || b.is_nan()))
+            .map(|&(a, b)| ("f", vec![Val::F64(a), Val::F64(b)]))
+            .collect();
+        assert_native_matches(&binary("f", ValType::F64, op), None, &calls);
+    }
+    let trunc = single_func(
+        "trunc",
+        &[ValType::F64],
+        Some(ValType::I32),
+        vec![Op::LocalGet(0), Op::I32TruncF64S, Op::Return],
+    );
+    let calls: Vec<_> = [-1.9, 3e10, f64::NAN, -3e10]
+        .iter()
+        .map(|&x| ("trunc", vec![Val::F64(x)]))
+        .collect();
+    assert_native_matches(&trunc, None, &calls);
+}
+
+#[cfg(feature = "cranelift")]
+#[test]
+fn test_cranelift_fuel_and_limits() {
+    // Fuel runs out at the same op, with the same backtrace, whatever the
+    // budget.
+    for fuel in [0, 1, 5, 17, 100, 1000] {
+        assert_native_matches(
+            &sum_to_module(),
+            Some(fuel),
+            &[("sum_to", vec![Val::I32(50)])],
+        );
+        assert_native_matches(&fib_module(), Some(fuel), &[("fib", vec![Val::I32(6)])]);
+    }
+    assert_native_matches(
+        &single_func("trap", &[], None, vec![Op::Nop, Op::Unreachable]),
+        Some(10),
+        &[("trap", vec![])],
+    );
+
+    let mut config = RuntimeConfig::new();
+    config.set_strategy(rune::config::Strategy::Cranelift);
+    config.set_max_call_depth(8);
+    let m = fib_module();
+    let mut inst = Runtime::with_config(config).instantiate(&m).unwrap();
+    assert_eq!(inst.call("fib", &[Val::I32(6)]), Ok(Some(Val::I32(8))));
+    assert_eq!(inst.call("fib", &[Val::I32(20)]), Err(Trap::StackOverflow));
+    assert_eq!(inst.trap_backtrace().len(), 8);
+    assert_eq!(inst.call("fib", &[Val::I32(7)]), Ok(Some(Val::I32(13))));
+}
+
+#[cfg(feature = "cranelift")]
+#[test]
+fn test_cranelift_memory() {
+    let mut m = single_func(
+        "poke",
+        &[ValType::I32, ValType::I64],
+        Some(ValType::I64),
+        vec![
+            Op::LocalGet(0),
+            Op::LocalGet(1),
+            Op::I64Store {
+                align: 3,
+                offset: 4,
+                memory: 0,
+            },
+            Op::LocalGet(0),
+            Op::I64Load {
+                align: 3,
+                offset: 4,
+                memory: 0,
+            },
+            Op::Return,
+        ],
+    );
+    m.functions.push(Function::new(
+        "grow",
+        FuncType {
+            params: vec![ValType::I32],
+            results: vec![ValType::I32],
         },
+        vec![],
+        vec![
+            Op::LocalGet(0),
+            Op::MemoryGrow,
+            Op::Drop,
+            Op::MemorySize,
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("grow".into(), 1));
+    assert_native_matches(
+        &m,
+        Some(10_000),
+        &[
+            ("poke", vec![Val::I32(0), Val::I64(-5)]),
+            ("poke", vec![Val::I32(65528), Val::I64(1)]),
+            ("poke", vec![Val::I32(65529), Val::I64(1)]),
+            ("poke", vec![Val::I32(-1), Val::I64(1)]),
+            ("grow", vec![Val::I32(1)]),
+            ("poke", vec![Val::I32(65529), Val::I64(3)]),
+        ],
     );
+}
+
+#[cfg(feature = "cranelift")]
+#[test]
+fn test_cranelift_host_calls() {
+    use rune::config::Strategy;
+    use rune::Linker;
+
+    let ty = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
+    let mut m = Module::new();
+    m.import("env", "double", ty.clone());
     m.functions.push(Function::new(
         "run",
-        FuncType {
-            params: vec![],
-            results: vec![],
-        },
+        ty.clone(),
         vec![],
         vec![
-            Op::I32Const(42),
-            Op::CallHost(0),
-            Op::I32Const(7),
+            Op::LocalGet(0),
             Op::CallHost(0),
+            Op::I32Const(1),
+            Op::I32Add,
             Op::Return,
         ],
     ));
     m.exports.push(("run".into(), 0));
+    let mut linker = Linker::new();
+    linker
+        .func("env", "double", ty, |args| {
+            let x = args[0].as_i32().unwrap();
+            if x < 0 {
+                return Err(Trap::HostError("negative".into()));
+            }
+            Ok(Some(Val::I32(x * 2)))
+        })
+        .unwrap();
 
-    let mut inst = rt().instantiate(&m).unwrap();
-    inst.call("run", &[]).unwrap();
-    assert_eq!(*log.lock().unwrap(), vec![42, 7]);
+    let mut config = RuntimeConfig::new();
+    config.set_strategy(Strategy::Cranelift);
+    let mut inst = linker
+        .instantiate(&Runtime::with_config(config), &m)
+        .unwrap();
+    assert_eq!(inst.call("run", &[Val::I32(20)]), Ok(Some(Val::I32(41))));
+    assert_eq!(
+        inst.call("run", &[Val::I32(-1)]),
+        Err(Trap::HostError("negative".into()))
+    );
+    assert_eq!(inst.trap_backtrace()[0].op_index, 1);
 }
 
-// ── Module serialization round-trip ──────────────────────────────────────────
-
+#[cfg(feature = "cranelift")]
 #[test]
-fn test_module_roundtrip() {
-    let m = single_func(
-        "add",
-        &[ValType::I32, ValType::I32],
-        Some(ValType::I32),
-        vec![Op::LocalGet(0), Op::LocalGet(1), Op::I32Add, Op::Return],
-    );
+fn test_native_host_calls_marshal_arguments() {
+    use rune::config::Strategy;
+    use rune::Linker;
+    use ValType::{F32, F64, I32, I64};
 
-    let bytes = m.to_bytes();
-    let m2 = Module::from_bytes(&bytes).expect("failed to deserialize");
+    // Ten parameters of every type, more than the trampoline keeps on its
+    // stack, and two, which it does.
+    let wide = FuncType {
+        params: vec![I32, I64, F32, F64, I32, I64, F32, F64, I32, I64],
+        results: vec![F64],
+    };
+    let narrow = FuncType {
+        params: vec![I32, I32],
+        results: vec![I32],
+    };
+    let mut m = Module::new();
+    m.initial_memory_pages = 1;
+    m.import("env", "wide", wide.clone());
+    m.import("env", "swap", narrow.clone());
+    let mut body = Vec::new();
+    for k in 0..10 {
+        body.push(match wide.params[k] {
+            I32 => Op::I32Const(k as i32 - 3),
+            I64 => Op::I64Const(-(1i64 << (40 + k))),
+            F32 => Op::F32Const(k as f32 + 0.5),
+            _ => Op::F64Const(-(k as f64) * 0.25),
+        });
+    }
+    body.extend([Op::CallHost(0), Op::Return]);
+    m.functions.push(Function::new(
+        "wide",
+        FuncType {
+            params: vec![],
+            results: vec![F64],
+        },
+        vec![],
+        body,
+    ));
+    // Store a word, have the host swap it with another through its
+    // `Caller`, and read the result back.
+    m.functions.push(Function::new(
+        "swap",
+        narrow.clone(),
+        vec![],
+        vec![
+            Op::LocalGet(0),
+            Op::I32Const(0x1234),
+            Op::I32Store {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::LocalGet(0),
+            Op::LocalGet(1),
+            Op::CallHost(1),
+            Op::LocalGet(1),
+            Op::I32Load {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::I32Add,
+            Op::Return,
+        ],
+    ));
+    m.exports = vec![("wide".into(), 0), ("swap".into(), 1)];
 
-    let mut inst = rt().instantiate(&m2).unwrap();
-    assert_eq!(
-        inst.call("add", &[Val::I32(20), Val::I32(22)]).unwrap(),
-        Some(Val::I32(42))
-    );
-}
+    let mut linker = Linker::new();
+    linker
+        .func("env", "wide", wide, |args| {
+            let total = args.iter().enumerate().fold(0.0, |acc, (k, arg)| {
+                let x = match *arg {
+                    Val::I32(x) => x as f64,
+                    Val::I64(x) => x as f64,
+                    Val::F32(x) => x as f64,
+                    Val::F64(x) => x,
+                };
+                acc * 3.0 + x * (k + 1) as f64
+            });
+            Ok(Some(Val::F64(total)))
+        })
+        .unwrap();
+    linker
+        .func_with_caller("env", "swap", narrow, |caller, args| {
+            let (a, b) = (args[0].as_i32().unwrap(), args[1].as_i32().unwrap());
+            let mut memory = caller.memory();
+            let x: u32 = memory.read(a as usize)?;
+            let y: u32 = memory.read(b as usize)?;
+            memory.write(a as usize, y)?;
+            memory.write(b as usize, x)?;
+            Ok(Some(Val::I32(y as i32)))
+        })
+        .unwrap();
 
-#[test]
-fn test_module_bad_magic() {
-    let bytes = b"XXXX\x00\x00\x00\x00".to_vec();
-    assert!(Module::from_bytes(&bytes).is_err());
+    let calls = [
+        ("wide", vec![]),
+        ("swap", vec![Val::I32(8), Val::I32(64)]),
+        ("swap", vec![Val::I32(8), Val::I32(65536)]),
+    ];
+    let run = |strategy| {
+        let mut config = RuntimeConfig::new();
+        config.set_strategy(strategy);
+        let mut inst = linker
+            .instantiate(&Runtime::with_config(config), &m)
+            .unwrap();
+        calls
+            .iter()
+            .map(|(name, args)| (inst.call(name, args), inst.trap_backtrace().to_vec()))
+            .collect::<Vec<_>>()
+    };
+    let expected = run(Strategy::Interpreter);
+    assert_eq!(expected[1].0, Ok(Some(Val::I32(0x1234))));
+    assert_eq!(expected[2].0, Err(Trap::OutOfBounds));
+    assert_eq!(run(Strategy::Cranelift), expected);
+    #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+    assert_eq!(run(Strategy::Baseline), expected);
 }
 
-// ── Conversions ───────────────────────────────────────────────────────────────
+// ── Baseline JIT ──────────────────────────────────────────────────────────────
 
+#[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
 #[test]
-fn test_i64_extend_i32_s() {
-    let m = single_func(
-        "ext",
-        &[ValType::I32],
-        Some(ValType::I64),
-        vec![Op::LocalGet(0), Op::I64ExtendI32S, Op::Return],
-    );
-    let mut inst = rt().instantiate(&m).unwrap();
-    assert_eq!(
-        inst.call("ext", &[Val::I32(-1)]).unwrap(),
-        Some(Val::I64(-1))
+fn test_baseline_compiles_module() {
+    use rune::compiler::baseline;
+
+    let code = baseline::compile(&fib_module(), &RuntimeConfig::new()).unwrap();
+    assert_eq!(code.functions(), 1);
+    assert!(code.is_compiled(0));
+
+    // A function using an op without a template stays interpreted, and
+    // compiled functions still call it.
+    let mut m = fib_module();
+    m.functions.push(Function::new(
+        "min",
+        FuncType {
+            params: vec![ValType::F64, ValType::F64],
+            results: vec![ValType::F64],
+        },
+        vec![],
+        vec![Op::LocalGet(0), Op::LocalGet(1), Op::F64Min, Op::Return],
+    ));
+    m.functions.push(Function::new(
+        "call_min",
+        FuncType {
+            params: vec![],
+            results: vec![ValType::F64],
+        },
+        vec![],
+        vec![
+            Op::F64Const(2.5),
+            Op::F64Const(-1.0),
+            Op::Call(1),
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("call_min".into(), 2));
+    let code = baseline::compile(&m, &RuntimeConfig::new()).unwrap();
+    assert!(code.is_compiled(0));
+    assert!(!code.is_compiled(1));
+    assert!(code.is_compiled(2));
+    assert_eq!(code.compiled_functions(), 2);
+    assert_strategy_matches(
+        rune::config::Strategy::Baseline,
+        &m,
+        Some(1000),
+        &[("call_min", vec![]), ("fib", vec![Val::I32(10)])],
     );
 }
 
+#[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
 #[test]
-fn test_f64_promote_f32() {
-    let m = single_func(
-        "prom",
-        &[ValType::F32],
-        Some(ValType::F64),
-        vec![Op::LocalGet(0), Op::F64PromoteF32, Op::Return],
+fn test_baseline_matches_interpreter() {
+    use rune::config::Strategy::Baseline;
+
+    for fuel in [0, 1, 5, 17, 100, 1000] {
+        assert_strategy_matches(
+            Baseline,
+            &sum_to_module(),
+            Some(fuel),
+            &[("sum_to", vec![Val::I32(50)])],
+        );
+        assert_strategy_matches(
+            Baseline,
+            &fib_module(),
+            Some(fuel),
+            &[("fib", vec![Val::I32(6)])],
+        );
+    }
+    assert_strategy_matches(
+        Baseline,
+        &fib_module(),
+        None,
+        &[("fib", vec![Val::I32(20)])],
     );
-    let mut inst = rt().instantiate(&m).unwrap();
-    if let Some(Val::F64(v)) = inst.call("prom", &[Val::F32(1.5)]).unwrap() {
-        assert!((v - 1.5).abs() < 1e-12);
-    } else {
-        panic!();
+
+    let binary = |ty: ValType, result: ValType, op: Op| {
+        single_func(
+            "f",
+            &[ty, ty],
+            Some(result),
+            vec![Op::LocalGet(0), Op::LocalGet(1), op, Op::Return],
+        )
+    };
+    let i32_pairs = [(7, 2), (-7, 2), (5, 0), (i32::MIN, -1), (1, 33), (-1, 1)];
+    for op in [
+        Op::I32Add,
+        Op::I32Sub,
+        Op::I32Mul,
+        Op::I32And,
+        Op::I32Or,
+        Op::I32Xor,
+        Op::I32Shl,
+        Op::I32ShrS,
+        Op::I32ShrU,
+        Op::I32DivS,
+        Op::I32DivU,
+        Op::I32RemS,
+        Op::I32RemU,
+        Op::I32Eq,
+        Op::I32Ne,
+        Op::I32LtS,
+        Op::I32LtU,
+        Op::I32GtS,
+        Op::I32GtU,
+        Op::I32LeS,
+        Op::I32LeU,
+        Op::I32GeS,
+        Op::I32GeU,
+    ] {
+        let calls: Vec<_> = i32_pairs
+            .iter()
+            .map(|&(a, b)| ("f", vec![Val::I32(a), Val::I32(b)]))
+            .collect();
+        assert_strategy_matches(
+            Baseline,
+            &binary(ValType::I32, ValType::I32, op),
+            None,
+            &calls,
+        );
+    }
+    let i64_pairs = [(7, -2), (i64::MIN, -1), (9, 0), (-1, 65), (1 << 40, 3)];
+    for (op, result) in [
+        (Op::I64Add, ValType::I64),
+        (Op::I64Mul, ValType::I64),
+        (Op::I64Xor, ValType::I64),
+        (Op::I64ShrS, ValType::I64),
+        (Op::I64DivS, ValType::I64),
+        (Op::I64DivU, ValType::I64),
+        (Op::I64RemS, ValType::I64),
+        (Op::I64RemU, ValType::I64),
+        (Op::I64LtS, ValType::I32),
+        (Op::I64GeU, ValType::I32),
+    ] {
+        let calls: Vec<_> = i64_pairs
+            .iter()
+            .map(|&(a, b)| ("f", vec![Val::I64(a), Val::I64(b)]))
+            .collect();
+        assert_strategy_matches(Baseline, &binary(ValType::I64, result, op), None, &calls);
+    }
+    let f64_pairs = [(1.5, -2.0), (f64::NAN, 3.0), (3.0, 3.0), (-0.0, 0.0)];
+    for op in [
+        Op::F64Eq,
+        Op::F64Ne,
+        Op::F64Lt,
+        Op::F64Gt,
+        Op::F64Le,
+        Op::F64Ge,
+    ] {
+        let calls: Vec<_> = f64_pairs
+            .iter()
+            .map(|&(a, b)| ("f", vec![Val::F64(a), Val::F64(b)]))
+            .collect();
+        assert_strategy_matches(
+            Baseline,
+            &binary(ValType::F64, ValType::I32, op),
+            None,
+            &calls,
+        );
+    }
+    for op in [Op::F32Add, Op::F32Sub, Op::F32Mul, Op::F32Div] {
+        let calls = vec![("f", vec![Val::F32(1.5), Val::F32(-0.25)])];
+        assert_strategy_matches(
+            Baseline,
+            &binary(ValType::F32, ValType::F32, op),
+            None,
+            &calls,
+        );
     }
-}
 
-// ── Undefined export ──────────────────────────────────────────────────────────
+    let unary = |from: ValType, to: ValType, op: Op| {
+        single_func(
+            "u",
+            &[from],
+            Some(to),
+            vec![Op::LocalGet(0), op, Op::Return],
+        )
+    };
+    let i32s = [0, 1, -1, i32::MIN, 0x00F0_0000];
+    for (op, to) in [
+        (Op::I32Clz, ValType::I32),
+        (Op::I32Ctz, ValType::I32),
+        (Op::I32Popcnt, ValType::I32),
+        (Op::I32Eqz, ValType::I32),
+        (Op::I64ExtendI32S, ValType::I64),
+        (Op::I64ExtendI32U, ValType::I64),
+        (Op::F32ConvertI32S, ValType::F32),
+        (Op::F32ConvertI32U, ValType::F32),
+        (Op::F64ConvertI32S, ValType::F64),
+        (Op::F64ConvertI32U, ValType::F64),
+    ] {
+        let calls: Vec<_> = i32s.iter().map(|&x| ("u", vec![Val::I32(x)])).collect();
+        assert_strategy_matches(Baseline, &unary(ValType::I32, to, op), None, &calls);
+    }
+    for (op, to) in [
+        (Op::F64Neg, ValType::F64),
+        (Op::F64Abs, ValType::F64),
+        (Op::F64Sqrt, ValType::F64),
+        (Op::F32DemoteF64, ValType::F32),
+        (Op::I64ReinterpretF64, ValType::I64),
+    ] {
+        let calls: Vec<_> = [2.25, -0.0, 1e300]
+            .iter()
+            .filter(|&&x| !matches!(op, Op::F64Sqrt) || x >= 0.0)
+            .map(|&x| ("u", vec![Val::F64(x)]))
+            .collect();
+        assert_strategy_matches(Baseline, &unary(ValType::F64, to, op), None, &calls);
+    }
+    let calls = vec![("u", vec![Val::I64(-3)]), ("u", vec![Val::I64(i64::MAX)])];
+    assert_strategy_matches(
+        Baseline,
+        &unary(ValType::I64, ValType::F64, Op::F64ConvertI64S),
+        None,
+        &calls,
+    );
+    assert_strategy_matches(
+        Baseline,
+        &unary(ValType::I64, ValType::I32, Op::I32WrapI64),
+        None,
+        &[("u", vec![Val::I64(-1 << 33 | 5)])],
+    );
 
-#[test]
-fn test_undefined_export() {
-    let m = Module::new();
-    let mut inst = rt().instantiate(&m).unwrap();
-    assert!(matches!(
-        inst.call("nope", &[]),
-        Err(Trap::UndefinedExport(_))
-    ));
+    let select = single_func(
+        "s",
+        &[ValType::I32],
+        Some(ValType::I64),
+        vec![
+            Op::I64Const(10),
+            Op::I64Const(-20),
+            Op::LocalGet(0),
+            Op::Select,
+            Op::Return,
+        ],
+    );
+    assert_strategy_matches(
+        Baseline,
+        &select,
+        None,
+        &[("s", vec![Val::I32(0)]), ("s", vec![Val::I32(7)])],
+    );
 }
 
-// ── Benchmarks / Stress Tests (reviewer-requested) ───────────────────────────
-
-/// Stress-test: recursive fib(30) exercises deep Call stacks.
-/// Passes when it completes and returns the correct value.
-/// (Performance target for AOT: <1ms; interpreter: ~50ms is acceptable.)
+#[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
 #[test]
-fn bench_fibonacci_30() {
-    let mut m = Module::new();
+fn test_baseline_memory_and_calls() {
+    use rune::config::Strategy;
+    use rune::Linker;
+
+    let mut m = single_func(
+        "poke",
+        &[ValType::I32, ValType::I32],
+        Some(ValType::I32),
+        vec![
+            Op::LocalGet(0),
+            Op::LocalGet(1),
+            Op::I32Store {
+                align: 2,
+                offset: 4,
+                memory: 0,
+            },
+            Op::LocalGet(0),
+            Op::I32Load {
+                align: 2,
+                offset: 4,
+                memory: 0,
+            },
+            Op::Return,
+        ],
+    );
     m.functions.push(Function::new(
-        "fib",
+        "grow",
         FuncType {
             params: vec![ValType::I32],
             results: vec![ValType::I32],
@@ -690,201 +7656,1022 @@ fn bench_fibonacci_30() {
         vec![],
         vec![
             Op::LocalGet(0),
-            Op::I32Const(1),
-            Op::I32LeS,
-            Op::If(BlockType::Val(ValType::I32)),
-            Op::LocalGet(0),
-            Op::Else,
+            Op::MemoryGrow,
+            Op::Drop,
+            Op::MemorySize,
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("grow".into(), 1));
+    let calls = [
+        ("poke", vec![Val::I32(0), Val::I32(-5)]),
+        ("poke", vec![Val::I32(65528), Val::I32(1)]),
+        ("poke", vec![Val::I32(65530), Val::I32(1)]),
+        ("poke", vec![Val::I32(-1), Val::I32(1)]),
+        ("grow", vec![Val::I32(1)]),
+        ("poke", vec![Val::I32(65530), Val::I32(3)]),
+    ];
+    assert_strategy_matches(Strategy::Baseline, &m, Some(10_000), &calls);
+
+    // Misaligned accesses trap at the same op under strict alignment.
+    for strategy in [Strategy::Interpreter, Strategy::Baseline] {
+        let mut config = RuntimeConfig::new();
+        config.set_strategy(strategy);
+        config.set_strict_alignment(true);
+        let mut inst = Runtime::with_config(config).instantiate(&m).unwrap();
+        assert_eq!(
+            inst.call("poke", &[Val::I32(8), Val::I32(9)]),
+            Ok(Some(Val::I32(9)))
+        );
+        assert!(inst.call("poke", &[Val::I32(2), Val::I32(9)]).is_err());
+        assert_eq!(inst.trap_backtrace()[0].op_index, 2);
+    }
+
+    let ty = FuncType {
+        params: vec![ValType::I32],
+        results: vec![ValType::I32],
+    };
+    let mut m = Module::new();
+    m.import("env", "double", ty.clone());
+    m.functions.push(Function::new(
+        "run",
+        ty.clone(),
+        vec![],
+        vec![
             Op::LocalGet(0),
+            Op::CallHost(0),
+            Op::I32Const(1),
+            Op::I32Add,
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("run".into(), 0));
+    let mut linker = Linker::new();
+    linker
+        .func("env", "double", ty, |args| {
+            let x = args[0].as_i32().unwrap();
+            if x < 0 {
+                return Err(Trap::HostError("negative".into()));
+            }
+            Ok(Some(Val::I32(x * 2)))
+        })
+        .unwrap();
+    let mut config = RuntimeConfig::new();
+    config.set_strategy(Strategy::Baseline);
+    let mut inst = linker
+        .instantiate(&Runtime::with_config(config), &m)
+        .unwrap();
+    assert_eq!(inst.call("run", &[Val::I32(20)]), Ok(Some(Val::I32(41))));
+    assert_eq!(
+        inst.call("run", &[Val::I32(-1)]),
+        Err(Trap::HostError("negative".into()))
+    );
+    assert_eq!(inst.trap_backtrace()[0].op_index, 1);
+
+    let mut config = RuntimeConfig::new();
+    config.set_strategy(Strategy::Baseline);
+    config.set_max_call_depth(8);
+    let fib = fib_module();
+    let mut inst = Runtime::with_config(config).instantiate(&fib).unwrap();
+    assert_eq!(inst.call("fib", &[Val::I32(20)]), Err(Trap::StackOverflow));
+    assert_eq!(inst.trap_backtrace().len(), 8);
+    assert_eq!(inst.call("fib", &[Val::I32(7)]), Ok(Some(Val::I32(13))));
+}
+
+#[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn test_backends_agree_on_block_results() {
+    use rune::compiler::{baseline, codegen};
+    use rune::config::Strategy::{Baseline, Cranelift};
+
+    let i32_block = || BlockType::Val(ValType::I32);
+    let mut m = Module::new();
+    let bodies = [
+        // A value carried out of nested blocks by `br` and `br_if`, with
+        // code after the `br` that never runs.
+        vec![
+            Op::Block(i32_block()),
+            Op::I32Const(10),
+            Op::Block(i32_block()),
             Op::I32Const(1),
-            Op::I32Sub,
-            Op::Call(0),
+            Op::LocalGet(0),
+            Op::BrIf(1),
+            Op::Drop,
             Op::LocalGet(0),
             Op::I32Const(2),
-            Op::I32Sub,
-            Op::Call(0),
+            Op::I32Add,
+            Op::Br(0),
+            Op::I32Const(99),
+            Op::End,
             Op::I32Add,
             Op::End,
+            Op::End,
+        ],
+        // An if with results on both arms, feeding a select.
+        vec![
+            Op::LocalGet(0),
+            Op::LocalGet(0),
+            Op::I32Const(3),
+            Op::I32LtS,
+            Op::If(i32_block()),
+            Op::I32Const(7),
+            Op::Else,
+            Op::LocalGet(0),
+            Op::LocalTee(1),
+            Op::LocalGet(1),
+            Op::I32Mul,
+            Op::End,
+            Op::LocalGet(0),
+            Op::Select,
+            Op::End,
+        ],
+        // A loop counting down, returning from inside it.
+        vec![
+            Op::Loop(BlockType::Empty),
+            Op::LocalGet(0),
+            Op::I32Const(0),
+            Op::I32LeS,
+            Op::If(BlockType::Empty),
+            Op::LocalGet(1),
             Op::Return,
+            Op::End,
+            Op::LocalGet(1),
+            Op::LocalGet(0),
+            Op::I32Add,
+            Op::LocalSet(1),
+            Op::LocalGet(0),
+            Op::I32Const(1),
+            Op::I32Sub,
+            Op::LocalSet(0),
+            Op::Br(0),
+            Op::End,
+            Op::Unreachable,
         ],
-    ));
-    m.exports.push(("fib".into(), 0));
-    let mut inst = rt().instantiate(&m).unwrap();
-    // fib(30) = 832040
-    let result = inst.call("fib", &[Val::I32(30)]).unwrap();
-    assert_eq!(result, Some(Val::I32(832040)));
+    ];
+    for (i, body) in bodies.into_iter().enumerate() {
+        let ty = FuncType {
+            params: vec![ValType::I32],
+            results: vec![ValType::I32],
+        };
+        m.functions
+            .push(Function::new(format!("f{i}"), ty, vec![ValType::I32], body));
+        m.exports.push((format!("f{i}"), i as u32));
+    }
+
+    let config = RuntimeConfig::new();
+    assert_eq!(
+        codegen::compile(&m, &config).unwrap().compiled_functions(),
+        3
+    );
+    assert_eq!(
+        baseline::compile(&m, &config).unwrap().compiled_functions(),
+        3
+    );
+    let calls: Vec<_> = (0..3)
+        .flat_map(|f| [-4, 0, 1, 2, 5].map(|x| (["f0", "f1", "f2"][f], vec![Val::I32(x)])))
+        .collect();
+    for strategy in [Cranelift, Baseline] {
+        for fuel in [None, Some(3), Some(12), Some(40)] {
+            assert_strategy_matches(strategy, &m, fuel, &calls);
+        }
+    }
 }
 
-/// Stress-test: allocate and grow to 100 pages (~6.4MB), write a pattern
-/// across every 4 bytes, then verify all reads. Catches memory bounds bugs.
+// ── Tiered execution ──────────────────────────────────────────────────────────
+
+#[cfg(feature = "cranelift")]
 #[test]
-fn memory_stress_100_pages() {
-    use rune::memory::PAGE_SIZE;
+fn test_tiered_promotes_hot_functions() {
+    use rune::config::{Strategy, TierThresholds};
+    use rune::{events::Event, Instance};
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
 
-    let mut m = Module::new();
-    // A no-op function — we test memory directly via the instance API.
+    let mut m = fib_module();
     m.functions.push(Function::new(
-        "nop",
+        "cold",
         FuncType {
             params: vec![],
-            results: vec![],
+            results: vec![ValType::I32],
         },
         vec![],
-        vec![Op::Return],
+        vec![Op::I32Const(1), Op::Return],
     ));
-    m.exports.push(("nop".into(), 0));
-    m.initial_memory_pages = 1;
-    m.max_memory_pages = Some(200);
-
-    let mut inst = rt().instantiate(&m).unwrap();
+    m.exports.push(("cold".into(), 1));
 
-    // Grow to 100 pages.
-    inst.memory.grow(99).expect("grow failed");
-    assert_eq!(inst.memory.pages(), 100);
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink = events.clone();
+    let mut config = RuntimeConfig::new();
+    config.set_strategy(Strategy::Tiered);
+    config.set_tier_thresholds(TierThresholds {
+        baseline: 3,
+        optimized: Some(50),
+    });
+    config.set_fuel(u64::MAX);
+    config.add_event_hook(move |event| {
+        if let Event::TierUp { func, tier } = event {
+            sink.lock().unwrap().push((func.to_string(), *tier));
+        }
+    });
+    let mut inst = Runtime::with_config(config).instantiate(&m).unwrap();
+    let mut reference = {
+        let mut config = RuntimeConfig::new();
+        config.set_fuel(u64::MAX);
+        Runtime::with_config(config).instantiate(&m).unwrap()
+    };
 
-    let total_bytes = 100 * PAGE_SIZE;
+    // Results and fuel stay the interpreter's across every tier change.
+    let check = |inst: &mut Instance, reference: &mut Instance, n| {
+        let args = [Val::I32(n)];
+        assert_eq!(inst.call("fib", &args), reference.call("fib", &args));
+        assert_eq!(inst.fuel(), reference.fuel());
+    };
+    for n in 0..10 {
+        check(&mut inst, &mut reference, n);
+    }
+    assert_eq!(inst.call("cold", &[]), Ok(Some(Val::I32(1))));
+    assert_eq!(reference.call("cold", &[]), Ok(Some(Val::I32(1))));
+    if cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+        assert!(events
+            .lock()
+            .unwrap()
+            .contains(&("fib".to_string(), Strategy::Baseline)));
+    }
 
-    // Write a u32 pattern across the full memory space.
-    let mut offset = 0usize;
-    let mut value = 0u32;
-    while offset + 4 <= total_bytes {
-        inst.memory.write_u32(offset, value).unwrap();
-        offset += 4;
-        value = value.wrapping_add(1);
+    // Cranelift finishes in the background; calls carry on meanwhile.
+    let optimized = ("fib".to_string(), Strategy::Cranelift);
+    let deadline = Instant::now() + Duration::from_secs(30);
+    while !events.lock().unwrap().contains(&optimized) {
+        assert!(Instant::now() < deadline, "fib never reached Cranelift");
+        check(&mut inst, &mut reference, 12);
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    for n in [15, 20] {
+        check(&mut inst, &mut reference, n);
     }
+    assert!(events.lock().unwrap().iter().all(|(func, _)| func == "fib"));
+}
 
-    // Verify every value.
-    let mut offset = 0usize;
-    let mut value = 0u32;
-    let mut errors = 0u32;
-    while offset + 4 <= total_bytes {
-        let v = inst.memory.read_u32(offset).unwrap();
-        if v != value {
-            errors += 1;
+#[cfg(feature = "cranelift")]
+#[test]
+fn test_tier_profile_promotes_on_first_call() {
+    use rune::config::{Strategy, TierThresholds};
+    use rune::events::Event;
+    use std::sync::{Arc, Mutex};
+
+    let m = fib_module();
+    let mut config = RuntimeConfig::new();
+    config.set_profiling(true);
+    config.set_strategy(Strategy::Cranelift);
+    let mut inst = Runtime::with_config(config).instantiate(&m).unwrap();
+    inst.call("fib", &[Val::I32(10)]).unwrap();
+    let profile = inst.profile().unwrap().clone();
+    assert!(profile.calls(0) > 3);
+
+    let events = Arc::new(Mutex::new(Vec::new()));
+    let sink = events.clone();
+    let mut config = RuntimeConfig::new();
+    config.set_strategy(Strategy::Tiered);
+    config.set_tier_thresholds(TierThresholds {
+        baseline: 3,
+        optimized: None,
+    });
+    config.set_tier_profile(profile);
+    config.add_event_hook(move |event| {
+        if let Event::TierUp { func, tier } = event {
+            sink.lock().unwrap().push((func.to_string(), *tier));
         }
-        offset += 4;
-        value = value.wrapping_add(1);
+    });
+    let mut inst = Runtime::with_config(config).instantiate(&m).unwrap();
+    assert_eq!(inst.call("fib", &[Val::I32(1)]), Ok(Some(Val::I32(1))));
+    if cfg!(all(target_os = "linux", target_arch = "x86_64")) {
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![("fib".to_string(), Strategy::Baseline)]
+        );
     }
+}
 
-    assert_eq!(errors, 0, "{errors} memory read/write mismatches");
+#[cfg(feature = "cranelift")]
+#[test]
+fn test_tiered_shares_counts_between_instances() {
+    use rune::config::{Strategy, TierThresholds};
+    use rune::events::Event;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    let count = Arc::new(AtomicUsize::new(0));
+    let seen = count.clone();
+    let mut config = RuntimeConfig::new();
+    config.set_strategy(Strategy::Tiered);
+    config.set_tier_thresholds(TierThresholds {
+        baseline: 4,
+        optimized: None,
+    });
+    config.add_event_hook(move |event| {
+        if matches!(event, Event::TierUp { .. }) {
+            seen.fetch_add(1, Ordering::SeqCst);
+        }
+    });
+    let rt = Runtime::with_config(config);
+    let m = sum_to_module();
+    let mut a = rt.instantiate(&m).unwrap();
+    let mut b = rt.instantiate(&m).unwrap();
+    for inst in [&mut a, &mut b] {
+        assert_eq!(inst.call("sum_to", &[Val::I32(10)]), Ok(Some(Val::I64(55))));
+        assert_eq!(
+            inst.call("sum_to", &[Val::I32(100)]),
+            Ok(Some(Val::I64(5050)))
+        );
+    }
+    let expected = usize::from(cfg!(all(target_os = "linux", target_arch = "x86_64")));
+    assert_eq!(count.load(Ordering::SeqCst), expected);
+    assert_eq!(a.call("sum_to", &[Val::I32(3)]), Ok(Some(Val::I64(6))));
 }
 
-/// Stress-test: call a host function 100,000 times in a Rune loop.
-/// Validates that the host call dispatch path has no memory/stack issues.
-/// (100k instead of 1M to keep CI fast; the overhead scales linearly.)
+#[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
 #[test]
-fn host_callback_loop_100k() {
-    use std::sync::{Arc, Mutex};
+fn test_code_cache_loads_instead_of_compiling() {
+    use rune::config::Strategy;
 
-    const ITERATIONS: i32 = 100_000;
+    let root = std::env::temp_dir().join(format!("rune-code-{}", std::process::id()));
+    let constant = |v| single_func("answer", &[], Some(ValType::I32), vec![Op::I32Const(v)]);
+    let (one, two) = (constant(1), constant(2));
+    for strategy in [Strategy::Baseline, Strategy::Cranelift] {
+        let answer = |dir: &std::path::Path, m: &Module| {
+            let mut config = RuntimeConfig::new();
+            config.set_strategy(strategy);
+            let rt = Runtime::with_config(config);
+            rt.enable_code_cache(dir).unwrap();
+            rt.instantiate(m).unwrap().call("answer", &[]).unwrap()
+        };
+        let only_file = |dir: &std::path::Path| {
+            let files: Vec<_> = std::fs::read_dir(dir).unwrap().collect();
+            assert_eq!(files.len(), 1, "{strategy:?}");
+            files[0].as_ref().unwrap().path()
+        };
+        let (a, b) = (root.join("a"), root.join("b"));
+        assert_eq!(answer(&a, &one), Some(Val::I32(1)));
+        assert_eq!(answer(&b, &two), Some(Val::I32(2)));
+        let (cached, other) = (only_file(&a), only_file(&b));
 
-    let counter: Arc<Mutex<i32>> = Arc::new(Mutex::new(0));
-    let counter2 = counter.clone();
+        // The cache is trusted: whatever code is filed under the module's
+        // key runs, here the other module's.
+        std::fs::copy(&other, &cached).unwrap();
+        assert_eq!(answer(&a, &one), Some(Val::I32(2)));
 
-    let mut m = Module::new();
-    // host[0]: increment()
-    m.register_host(
-        "increment",
-        FuncType {
-            params: vec![],
-            results: vec![],
-        },
-        move |_args| {
-            *counter2.lock().unwrap() += 1;
-            Ok(None)
-        },
-    );
+        // A damaged file is compiled over.
+        let mut bytes = std::fs::read(&cached).unwrap();
+        bytes[20] ^= 0xFF;
+        std::fs::write(&cached, bytes).unwrap();
+        assert_eq!(answer(&a, &one), Some(Val::I32(1)));
+        std::fs::write(&other, b"RUNECODE").unwrap();
+        assert_eq!(answer(&b, &two), Some(Val::I32(2)));
+        assert_eq!(only_file(&a), cached);
+        assert!(std::fs::read(&other).unwrap().len() > 8);
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
 
-    // Guest: loop ITERATIONS times, call host each time.
-    //
-    // locals[0] = i (counter, starts at ITERATIONS counts down to 0)
+#[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn test_shared_library_runs_without_compiling() {
+    use rune::compiler::library;
+
+    let mut m = sum_to_module();
     m.functions.push(Function::new(
-        "run",
+        "grow_and_round",
         FuncType {
-            params: vec![],
-            results: vec![],
+            params: vec![ValType::I32, ValType::F64],
+            results: vec![ValType::F64],
         },
-        vec![ValType::I32],
+        vec![],
         vec![
-            // i = ITERATIONS
-            Op::I32Const(ITERATIONS),
-            Op::LocalSet(0),
-            // Block wraps the Loop so BrIf(1) has a valid exit target.
-            // Br(0) = continue Loop, Br(1) = break out of Block.
-            Op::Block(BlockType::Empty), // depth 1 — break target
-            Op::Loop(BlockType::Empty),  // depth 0 — continue target
-            // if i == 0: exit Block
-            Op::LocalGet(0),
-            Op::I32Eqz,
-            Op::BrIf(1),
-            // call host
-            Op::CallHost(0),
-            // i -= 1
             Op::LocalGet(0),
-            Op::I32Const(1),
-            Op::I32Sub,
-            Op::LocalSet(0),
-            // continue Loop
-            Op::Br(0),
-            Op::End, // End Loop
-            Op::End, // End Block
+            Op::MemoryGrow,
+            Op::F64ConvertI32S,
+            Op::LocalGet(1),
+            Op::F64Floor,
+            Op::F64Add,
             Op::Return,
         ],
     ));
-    m.exports.push(("run".into(), 0));
+    m.exports.push(("grow_and_round".into(), 1));
+    let dir = std::env::temp_dir().join(format!("rune-lib-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("sum.so");
+    std::fs::write(&path, library::build(&m, &RuntimeConfig::new()).unwrap()).unwrap();
 
-    let mut inst = rt().instantiate(&m).unwrap();
-    inst.call("run", &[]).expect("run failed");
+    // The library runs like the interpreter, fuel included, even on a
+    // runtime that would otherwise only interpret.
+    let runtime = || {
+        let mut config = RuntimeConfig::new();
+        config.set_fuel(100_000);
+        Runtime::with_config(config)
+    };
+    let rt = runtime();
+    let loaded = rt.load_library(&path).unwrap();
+    assert_eq!(loaded.digest(), m.digest());
+    let mut actual = rt.instantiate(&loaded).unwrap();
+    let mut expected = runtime().instantiate(&m).unwrap();
+    let calls = [
+        ("sum_to", vec![Val::I32(100)]),
+        ("grow_and_round", vec![Val::I32(1), Val::F64(2.5)]),
+        ("grow_and_round", vec![Val::I32(1), Val::F64(-0.5)]),
+        ("sum_to", vec![Val::I32(100_000)]),
+    ];
+    for (name, args) in &calls {
+        assert_eq!(actual.call(name, args), expected.call(name, args), "{name}");
+        assert_eq!(actual.fuel(), expected.fuel(), "fuel after {name}");
+    }
+
+    // Libraries only load for the settings they were compiled for.
+    let mut config = RuntimeConfig::new();
+    config.set_strict_alignment(true);
+    assert!(Runtime::with_config(config).load_library(&path).is_err());
+    let bogus = dir.join("bogus.so");
+    std::fs::write(&bogus, b"not a library").unwrap();
+    assert!(rt.load_library(&bogus).is_err());
+    assert!(rt.load_library(dir.join("missing.so")).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn test_embedded_code_runs_without_compiling() {
+    use rune::compiler::embedded;
+    use rune::config::Strategy;
+    use rune::embed::EmbeddedModule;
+
+    let m = sum_to_module();
+    let runtime = |strategy| {
+        let mut config = RuntimeConfig::new();
+        config.set_strategy(strategy);
+        config.set_fuel(100_000);
+        Runtime::with_config(config)
+    };
+    let mut expected = runtime(Strategy::Interpreter).instantiate(&m).unwrap();
+    for strategy in [Strategy::Cranelift, Strategy::Baseline] {
+        let code = embedded::build(&m, runtime(strategy).config()).unwrap();
+        let module: &'static [u8] = m.to_bytes().leak();
+        let embedded = EmbeddedModule::new(module, Some(code.leak()));
+
+        // The code runs like the interpreter, fuel included, even on a
+        // runtime that would otherwise only interpret.
+        let rt = runtime(Strategy::Interpreter);
+        let loaded = rt.load_embedded(&embedded).unwrap();
+        assert_eq!(loaded.digest(), m.digest());
+        let mut actual = rt.instantiate(&loaded).unwrap();
+        for n in [100, 100_000] {
+            let args = [Val::I32(n)];
+            assert_eq!(actual.call("sum_to", &args), expected.call("sum_to", &args));
+            assert_eq!(actual.fuel(), expected.fuel(), "{strategy:?}");
+        }
+        expected.set_fuel(100_000);
+
+        // Code only loads for the module and settings it was compiled for.
+        let mut config = RuntimeConfig::new();
+        config.set_strict_alignment(true);
+        assert!(Runtime::with_config(config)
+            .load_embedded(&embedded)
+            .is_err());
+        let other: &'static [u8] = fib_module().to_bytes().leak();
+        let code = embedded.code();
+        assert!(rt.load_embedded(&EmbeddedModule::new(other, code)).is_err());
+        let damaged = &code.unwrap()[..100];
+        assert!(rt
+            .load_embedded(&EmbeddedModule::new(module, Some(damaged)))
+            .is_err());
+    }
+}
+
+#[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn test_shared_library_cross_compiles() {
+    use rune::compiler::library;
+    use rune::config::CompilerConfig;
 
+    let m = sum_to_module();
+    let for_target = |target: &str, cpu_features: Option<Vec<String>>| {
+        let mut config = RuntimeConfig::new();
+        config.set_compiler(CompilerConfig {
+            target: Some(target.into()),
+            cpu_features,
+            ..CompilerConfig::default()
+        });
+        library::build(&m, &config)
+    };
+
+    // Libraries record the target they were built for.
+    let arm = for_target("aarch64-unknown-linux-gnu", Some(vec!["lse".into()])).unwrap();
+    assert_eq!(&arm[18..20], &183u16.to_le_bytes()); // EM_AARCH64
     assert_eq!(
-        *counter.lock().unwrap(),
-        ITERATIONS,
-        "host was not called the expected number of times"
+        library::target_of(&arm).unwrap(),
+        (
+            "aarch64-unknown-linux-gnu".to_string(),
+            vec!["lse".to_string()]
+        )
     );
-}
+    let x86 = for_target("x86_64-unknown-linux-gnu", None).unwrap();
+    assert_eq!(&x86[18..20], &62u16.to_le_bytes()); // EM_X86_64
+    assert_eq!(
+        library::target_of(&x86).unwrap(),
+        ("x86_64-unknown-linux-gnu".to_string(), vec![])
+    );
+    assert!(matches!(
+        library::target_of(b"\x7FELF not a library"),
+        Err(Trap::InvalidModule(_))
+    ));
+    assert!(matches!(
+        for_target("riscv64gc-unknown-linux-gnu", None),
+        Err(Trap::UnsupportedFeature(_))
+    ));
 
-// ── Fibonacci (recursive) ─────────────────────────────────────────────────────
+    // Only a host of the target's architecture loads the library.
+    let dir = std::env::temp_dir().join(format!("rune-cross-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("sum-arm.so");
+    std::fs::write(&path, &arm).unwrap();
+    let err = Runtime::new().load_library(&path).unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("built for aarch64-unknown-linux-gnu"),
+        "{err}"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
 
+#[cfg(feature = "cranelift")]
 #[test]
-fn test_fibonacci() {
-    // fib(n): recursive fibonacci
-    // fib(0) = 0, fib(1) = 1, fib(n) = fib(n-1) + fib(n-2)
-    let mut m = Module::new();
+fn test_compiler_config_keeps_results() {
+    use rune::compiler::codegen;
+    use rune::config::{BoundsChecks, CompilerConfig, OptLevel, Strategy};
+
+    let mut m = sum_to_module();
     m.functions.push(Function::new(
-        "fib",
+        "poke",
         FuncType {
-            params: vec![ValType::I32],
+            params: vec![ValType::I32, ValType::I32],
             results: vec![ValType::I32],
         },
         vec![],
         vec![
-            // if n <= 1 return n
             Op::LocalGet(0),
-            Op::I32Const(1),
-            Op::I32LeS,
-            Op::If(BlockType::Val(ValType::I32)),
+            Op::LocalGet(1),
+            Op::I32Store {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
             Op::LocalGet(0),
-            Op::Else,
-            // fib(n-1) + fib(n-2)
+            Op::I32Load {
+                align: 2,
+                offset: 0,
+                memory: 0,
+            },
+            Op::Return,
+        ],
+    ));
+    m.exports.push(("poke".into(), 1));
+    let calls = [
+        ("sum_to", vec![Val::I32(1000)]),
+        ("poke", vec![Val::I32(16), Val::I32(7)]),
+        ("poke", vec![Val::I32(65534), Val::I32(7)]),
+    ];
+    let runtime = |strategy, compiler: CompilerConfig| {
+        let mut config = RuntimeConfig::new();
+        config.set_strategy(strategy);
+        config.set_fuel(100_000);
+        config.set_compiler(compiler);
+        Runtime::with_config(config)
+    };
+    let compilers = [
+        CompilerConfig {
+            opt_level: OptLevel::None,
+            bounds_checks: BoundsChecks::Helper,
+            ..CompilerConfig::default()
+        },
+        CompilerConfig {
+            opt_level: OptLevel::SpeedAndSize,
+            debug_info: true,
+            cpu_features: Some(vec![]),
+            ..CompilerConfig::default()
+        },
+    ];
+    for strategy in [Strategy::Baseline, Strategy::Cranelift] {
+        for compiler in &compilers {
+            let mut expected = runtime(Strategy::Interpreter, compiler.clone())
+                .instantiate(&m)
+                .unwrap();
+            let mut actual = runtime(strategy, compiler.clone()).instantiate(&m).unwrap();
+            for (name, args) in &calls {
+                assert_eq!(actual.call(name, args), expected.call(name, args), "{name}");
+                assert_eq!(actual.trap_backtrace(), expected.trap_backtrace());
+                assert_eq!(actual.fuel(), expected.fuel(), "{strategy:?} {compiler:?}");
+            }
+        }
+    }
+
+    // Code a runtime runs must be for the host; other targets and unknown
+    // features are refused rather than miscompiled.
+    for compiler in [
+        CompilerConfig {
+            target: Some("aarch64-unknown-linux-gnu".into()),
+            ..CompilerConfig::default()
+        },
+        CompilerConfig {
+            target: Some("not-a-target".into()),
+            ..CompilerConfig::default()
+        },
+        CompilerConfig {
+            cpu_features: Some(vec!["no_such_feature".into()]),
+            ..CompilerConfig::default()
+        },
+    ] {
+        let mut config = RuntimeConfig::new();
+        config.set_compiler(compiler.clone());
+        assert!(
+            matches!(
+                codegen::compile(&m, &config),
+                Err(Trap::UnsupportedFeature(_))
+            ),
+            "{compiler:?}"
+        );
+        #[cfg(all(target_os = "linux", target_arch = "x86_64"))]
+        assert!(
+            rune::compiler::baseline::compile(&m, &config).is_err(),
+            "{compiler:?}"
+        );
+        // The runtime interprets instead.
+        let mut inst = runtime(Strategy::Cranelift, compiler)
+            .instantiate(&m)
+            .unwrap();
+        assert_eq!(inst.call("sum_to", &[Val::I32(10)]), Ok(Some(Val::I64(55))));
+    }
+}
+
+#[cfg(feature = "cranelift")]
+#[test]
+fn test_loop_invariants_hoisted() {
+    use rune::config::{CompilerConfig, OptLevel, Strategy};
+
+    let address = || {
+        [
             Op::LocalGet(0),
+            Op::I32Const(16),
+            Op::I32Add,
+            Op::LocalGet(3),
+            Op::I32Const(2),
+            Op::I32Shl,
+            Op::I32Add,
+        ]
+    };
+    // kernel(base, n, scale) stores i + scale * scale at base + 16 + 4i,
+    // and sums what it loads back times scale * 3, on top of a value
+    // pushed before the loop.
+    let mut body = vec![Op::I32Const(1000), Op::Loop(BlockType::Empty)];
+    body.extend(address());
+    body.extend([
+        Op::LocalGet(3),
+        Op::LocalGet(2),
+        Op::LocalGet(2),
+        Op::I32Mul,
+        Op::I32Add,
+        Op::I32Store {
+            align: 2,
+            offset: 0,
+            memory: 0,
+        },
+    ]);
+    body.extend(address());
+    body.extend([
+        Op::I32Load {
+            align: 2,
+            offset: 0,
+            memory: 0,
+        },
+        Op::LocalGet(2),
+        Op::I64ExtendI32S,
+        Op::I64Const(3),
+        Op::I64Mul,
+        Op::I32WrapI64,
+        Op::I32Mul,
+        Op::LocalGet(4),
+        Op::I32Add,
+        Op::LocalSet(4),
+        Op::LocalGet(3),
+        Op::I32Const(1),
+        Op::I32Add,
+        Op::LocalTee(3),
+        Op::LocalGet(1),
+        Op::I32LtS,
+        Op::BrIf(0),
+        Op::End,
+        Op::LocalGet(4),
+        Op::I32Add,
+        Op::Return,
+    ]);
+    let mut m = Module::new();
+    m.functions.push(Function::new(
+        "kernel",
+        FuncType {
+            params: vec![ValType::I32; 3],
+            results: vec![ValType::I32],
+        },
+        vec![ValType::I32, ValType::I32],
+        body,
+    ));
+    // grid(n) sums 7j + i over an n by n grid: 7j is the same all along
+    // the inner loop, but not the outer.
+    m.functions.push(Function::new(
+        "grid",
+        FuncType {
+            params: vec![ValType::I32],
+            results: vec![ValType::I32],
+        },
+        vec![ValType::I32; 3],
+        vec![
+            Op::Loop(BlockType::Empty),
+            Op::I32Const(0),
+            Op::LocalSet(2),
+            Op::Loop(BlockType::Empty),
+            Op::LocalGet(1),
+            Op::I32Const(7),
+            Op::I32Mul,
+            Op::LocalGet(2),
+            Op::I32Add,
+            Op::LocalGet(3),
+            Op::I32Add,
+            Op::LocalSet(3),
+            Op::LocalGet(2),
             Op::I32Const(1),
-            Op::I32Sub,
-            Op::Call(0), // fib(n-1)
+            Op::I32Add,
+            Op::LocalTee(2),
             Op::LocalGet(0),
-            Op::I32Const(2),
-            Op::I32Sub,
-            Op::Call(0), // fib(n-2)
+            Op::I32LtS,
+            Op::BrIf(0),
+            Op::End,
+            Op::LocalGet(1),
+            Op::I32Const(1),
             Op::I32Add,
+            Op::LocalTee(1),
+            Op::LocalGet(0),
+            Op::I32LtS,
+            Op::BrIf(0),
             Op::End,
+            Op::LocalGet(3),
             Op::Return,
         ],
     ));
-    m.exports.push(("fib".into(), 0));
-    let mut inst = rt().instantiate(&m).unwrap();
-    assert_eq!(inst.call("fib", &[Val::I32(0)]).unwrap(), Some(Val::I32(0)));
-    assert_eq!(inst.call("fib", &[Val::I32(1)]).unwrap(), Some(Val::I32(1)));
+    m.exports.push(("kernel".into(), 0));
+    m.exports.push(("grid".into(), 1));
+
+    let mut expected = Runtime::new().instantiate(&m).unwrap();
     assert_eq!(
-        inst.call("fib", &[Val::I32(10)]).unwrap(),
-        Some(Val::I32(55))
+        expected.call("kernel", &[Val::I32(0), Val::I32(10), Val::I32(2)]),
+        Ok(Some(Val::I32(1510)))
+    );
+    assert_eq!(
+        expected.call("grid", &[Val::I32(4)]),
+        Ok(Some(Val::I32(192)))
     );
+
+    let calls = [
+        ("kernel", vec![Val::I32(0), Val::I32(10), Val::I32(2)]),
+        ("kernel", vec![Val::I32(64), Val::I32(0), Val::I32(-5)]),
+        ("kernel", vec![Val::I32(65500), Val::I32(10), Val::I32(1)]),
+        ("grid", vec![Val::I32(4)]),
+        ("grid", vec![Val::I32(30)]),
+    ];
+    for opt_level in [OptLevel::None, OptLevel::Speed] {
+        for fuel in [100_000, 150] {
+            let runtime = |strategy| {
+                let mut config = RuntimeConfig::new();
+                config.set_strategy(strategy);
+                config.set_fuel(fuel);
+                config.set_compiler(CompilerConfig {
+                    opt_level,
+                    ..CompilerConfig::default()
+                });
+                Runtime::with_config(config)
+            };
+            for strategy in [Strategy::Baseline, Strategy::Cranelift] {
+                let mut expected = runtime(Strategy::Interpreter).instantiate(&m).unwrap();
+                let mut actual = runtime(strategy).instantiate(&m).unwrap();
+                for (name, args) in &calls {
+                    let context = format!("{strategy:?} {opt_level:?} {fuel} {name}{args:?}");
+                    assert_eq!(
+                        actual.call(name, args),
+                        expected.call(name, args),
+                        "{context}"
+                    );
+                    assert_eq!(actual.trap_backtrace(), expected.trap_backtrace());
+                    assert_eq!(actual.fuel(), expected.fuel(), "{context}");
+                }
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn test_debug_info_names_compiled_functions() {
+    use rune::compiler::library;
+    use rune::config::{CompilerConfig, Strategy};
+
+    let mut m = sum_to_module();
+    m.functions[0].name = "traced_sum".into();
+    let mut sm = SourceMap::new();
+    let file = sm.add_file("traced.rn");
+    for (op, line) in [(0, 1), (5, 2), (10, 3), (17, 5)] {
+        let loc = SourceLoc {
+            file,
+            line,
+            column: 1,
+        };
+        sm.add_entry(0, op, loc);
+    }
+    m.source_map = Some(sm);
+    let config = |strategy| {
+        let mut config = RuntimeConfig::new();
+        config.set_strategy(strategy);
+        config.set_compiler(CompilerConfig {
+            debug_info: true,
+            ..CompilerConfig::default()
+        });
+        config
+    };
+
+    // Code the runtime loads is announced in the process's jitdump.
+    for strategy in [Strategy::Baseline, Strategy::Cranelift] {
+        let mut inst = Runtime::with_config(config(strategy))
+            .instantiate(&m)
+            .unwrap();
+        assert_eq!(inst.call("sum_to", &[Val::I32(10)]), Ok(Some(Val::I64(55))));
+    }
+    let dir = std::env::var_os("JITDUMPDIR").map_or_else(std::env::temp_dir, Into::into);
+    let dump = std::fs::read(dir.join(format!("jit-{}.dump", std::process::id()))).unwrap();
+    let u32_at = |at: usize| u32::from_le_bytes(dump[at..at + 4].try_into().unwrap());
+    let c_str = |at: usize| {
+        let len = dump[at..].iter().position(|&b| b == 0).unwrap();
+        std::str::from_utf8(&dump[at..at + len]).unwrap()
+    };
+    assert_eq!(u32_at(0), 0x4A69_5444);
+    let (mut loads, mut lines) = (0, Vec::new());
+    let mut at = u32_at(8) as usize;
+    // Other tests may be appending; a record cut short ends the walk.
+    while at + 16 <= dump.len() && at + u32_at(at + 4) as usize <= dump.len() {
+        match u32_at(at) {
+            0 if c_str(at + 56) == "traced_sum" => loads += 1,
+            2 => {
+                let mut entry = at + 32;
+                for _ in 0..u32_at(at + 24) {
+                    lines.push((u32_at(entry + 8), c_str(entry + 16).to_string()));
+                    entry += 17 + lines.last().unwrap().1.len();
+                }
+            }
+            _ => {}
+        }
+        at += u32_at(at + 4) as usize;
+    }
+    assert!(loads >= 2, "{loads} loads of traced_sum");
+    for line in [1, 2, 3, 5] {
+        assert!(
+            lines.contains(&(line, "traced.rn".to_string())),
+            "line {line}"
+        );
+    }
+
+    // Shared libraries name functions in their symbol table.
+    let plain = library::build(&m, &RuntimeConfig::new()).unwrap();
+    let debug = library::build(&m, &config(Strategy::Cranelift)).unwrap();
+    let has = |bytes: &[u8], needle: &[u8]| bytes.windows(needle.len()).any(|w| w == needle);
+    assert!(!has(&plain, b"traced_sum\0"));
+    assert!(has(&debug, b".symtab\0") && has(&debug, b"traced_sum\0"));
+    let dir = std::env::temp_dir().join(format!("rune-debug-lib-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("traced.so");
+    std::fs::write(&path, debug).unwrap();
+    let rt = Runtime::with_config(config(Strategy::Cranelift));
+    let loaded = rt.load_library(&path).unwrap();
+    let mut inst = rt.instantiate(&loaded).unwrap();
+    assert_eq!(inst.call("sum_to", &[Val::I32(10)]), Ok(Some(Val::I64(55))));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(all(feature = "cranelift", target_os = "linux", target_arch = "x86_64"))]
+#[test]
+fn test_guard_pages_trap_like_checks() {
+    use rune::compiler::baseline;
+    use rune::config::{AddressOverflow, BoundsChecks, CompilerConfig, Strategy};
+
+    let access = |offset| {
+        vec![
+            Op::LocalGet(0),
+            Op::LocalGet(1),
+            Op::I64Store {
+                align: 3,
+                offset,
+                memory: 0,
+            },
+            Op::LocalGet(0),
+            Op::I32Load {
+                align: 2,
+                offset,
+                memory: 0,
+            },
+            Op::Return,
+        ]
+    };
+    let ty = FuncType {
+        params: vec![ValType::I32, ValType::I64],
+        results: vec![ValType::I32],
+    };
+    let mut m = Module::new();
+    m.initial_memory_pages = 1;
+    m.functions
+        .push(Function::new("poke", ty.clone(), vec![], access(0)));
+    m.functions
+        .push(Function::new("poke_far", ty, vec![], access(0xFFFF_FFF0)));
+    m.functions.push(Function::new(
+        "grow",
+        FuncType {
+            params: vec![ValType::I32],
+            results: vec![ValType::I32],
+        },
+        vec![],
+        vec![Op::LocalGet(0), Op::MemoryGrow, Op::Return],
+    ));
+    m.exports = vec![
+        ("poke".into(), 0),
+        ("poke_far".into(), 1),
+        ("grow".into(), 2),
+    ];
+
+    let poke = |addr: u32| ("poke", vec![Val::I32(addr as i32), Val::I64(-7)]);
+    let calls = [
+        poke(16),
+        poke(65528),
+        // Straddling the end, past it, and at the top of the address range.
+        poke(65532),
+        poke(0x8000_0000),
+        poke(0xFFFF_FFFC),
+        ("poke_far", vec![Val::I32(8), Val::I64(1)]),
+        ("poke_far", vec![Val::I32(0x20), Val::I64(1)]),
+        ("grow", vec![Val::I32(1)]),
+        poke(65540),
+        poke(131064),
+        poke(131068),
+    ];
+    for poison in [Poison::Off, Poison::Check] {
+        for overflow in [AddressOverflow::Trap, AddressOverflow::Wrap] {
+            let runtime = |strategy| {
+                let mut config = RuntimeConfig::new();
+                config.set_strategy(strategy);
+                config.set_poison(poison);
+                config.set_address_overflow(overflow);
+                config.set_compiler(CompilerConfig {
+                    bounds_checks: BoundsChecks::Guard,
+                    ..CompilerConfig::default()
+                });
+                Runtime::with_config(config)
+            };
+            let native = runtime(Strategy::Baseline);
+            assert_eq!(
+                baseline::compile(&m, native.config())
+                    .unwrap()
+                    .compiled_functions(),
+                3
+            );
+            let mut expected = runtime(Strategy::Interpreter).instantiate(&m).unwrap();
+            let mut actual = native.instantiate(&m).unwrap();
+            for (name, args) in &calls {
+                let context = format!("{name}{args:?} {poison:?} {overflow:?}");
+                assert_eq!(
+                    actual.call(name, args),
+                    expected.call(name, args),
+                    "{context}"
+                );
+                assert_eq!(actual.trap_backtrace(), expected.trap_backtrace());
+            }
+
+            // Shrinking takes the pages past the end out of reach again,
+            // and protected pages are checked.
+            for inst in [&mut expected, &mut actual] {
+                inst.memory_mut().reset(1).unwrap();
+            }
+            for (name, args) in [poke(16), poke(65540)] {
+                assert_eq!(actual.call(name, &args), expected.call(name, &args));
+            }
+            for inst in [&mut expected, &mut actual] {
+                inst.memory_mut()
+                    .protect(0..1, Protection::ReadOnly)
+                    .unwrap();
+            }
+            let (name, args) = poke(16);
+            assert_eq!(expected.call(name, &args), Err(Trap::AccessViolation));
+            assert_eq!(actual.call(name, &args), Err(Trap::AccessViolation));
+            assert_eq!(actual.trap_backtrace(), expected.trap_backtrace());
+        }
+    }
 }